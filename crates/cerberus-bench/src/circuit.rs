@@ -0,0 +1,167 @@
+//! A simulated Tor circuit walking the gate flow.
+//!
+//! Each iteration: request a challenge, answer it (correctly or not), and
+//! present the passport to `/validate` when one is granted.
+
+use anyhow::Result;
+use cerberus_common::constants::headers;
+use cerberus_common::{CaptchaResult, ChallengeId, redis_keys};
+use rand::Rng;
+use redis::AsyncCommands;
+use serde::Deserialize;
+use std::time::Instant;
+
+use crate::stats::{Operation, Recorder};
+
+/// Answer sent when a circuit is meant to fail
+const WRONG_ANSWER: &str = "BENCH-WRONG";
+
+/// Shared settings for all circuits
+pub struct Scenario {
+    pub client: reqwest::Client,
+    pub target: String,
+    pub iterations: u32,
+    /// Fraction of challenges answered correctly (needs `redis`)
+    pub solve_rate: f64,
+    /// Used to read expected answers; without it every answer is wrong
+    pub redis: Option<redis::aio::ConnectionManager>,
+}
+
+#[derive(Deserialize)]
+struct Challenge {
+    challenge_id: ChallengeId,
+}
+
+#[derive(Deserialize)]
+struct StoredAnswer {
+    answer: String,
+}
+
+/// Run one circuit to completion
+pub async fn run(scenario: &Scenario, circuit_id: String) -> Recorder {
+    let mut recorder = Recorder::default();
+    let mut redis = scenario.redis.clone();
+
+    for _ in 0..scenario.iterations {
+        let Some(challenge_id) = request_challenge(scenario, &circuit_id, &mut recorder).await
+        else {
+            continue;
+        };
+
+        let solve = rand::rng().random_bool(scenario.solve_rate.clamp(0.0, 1.0));
+        let answer = match (solve, redis.as_mut()) {
+            (true, Some(redis)) => lookup_answer(redis, &challenge_id)
+                .await
+                .ok()
+                .flatten()
+                .unwrap_or_else(|| WRONG_ANSWER.to_string()),
+            _ => WRONG_ANSWER.to_string(),
+        };
+
+        let Some(result) =
+            verify(scenario, &circuit_id, &challenge_id, &answer, &mut recorder).await
+        else {
+            continue;
+        };
+
+        match result.passport_token {
+            Some(token) if result.success => {
+                recorder.solved += 1;
+                validate(scenario, &circuit_id, token.as_str(), &mut recorder).await;
+            }
+            _ => recorder.failed += 1,
+        }
+    }
+
+    recorder
+}
+
+async fn request_challenge(
+    scenario: &Scenario,
+    circuit_id: &str,
+    recorder: &mut Recorder,
+) -> Option<ChallengeId> {
+    let started = Instant::now();
+    let response = scenario
+        .client
+        .get(format!("{}/challenge", scenario.target))
+        .query(&[("circuit_id", circuit_id)])
+        .send()
+        .await;
+
+    let (status, challenge) = match response {
+        Ok(response) => {
+            let status = response.status();
+            let challenge = if status.is_success() {
+                response.json::<Challenge>().await.ok()
+            } else {
+                None
+            };
+            (status.as_u16(), challenge)
+        }
+        Err(_) => (0, None),
+    };
+    recorder.record(Operation::Challenge, started.elapsed(), status);
+
+    challenge.map(|c| c.challenge_id)
+}
+
+async fn verify(
+    scenario: &Scenario,
+    circuit_id: &str,
+    challenge_id: &ChallengeId,
+    answer: &str,
+    recorder: &mut Recorder,
+) -> Option<CaptchaResult> {
+    let started = Instant::now();
+    let response = scenario
+        .client
+        .post(format!("{}/verify", scenario.target))
+        .header(headers::X_CIRCUIT_ID, circuit_id)
+        .json(&serde_json::json!({
+            "challenge_id": challenge_id,
+            "answer": answer,
+        }))
+        .send()
+        .await;
+
+    let (status, result) = match response {
+        Ok(response) => {
+            let status = response.status().as_u16();
+            (status, response.json::<CaptchaResult>().await.ok())
+        }
+        Err(_) => (0, None),
+    };
+    recorder.record(Operation::Verify, started.elapsed(), status);
+
+    result
+}
+
+async fn validate(scenario: &Scenario, circuit_id: &str, token: &str, recorder: &mut Recorder) {
+    let started = Instant::now();
+    let status = scenario
+        .client
+        .get(format!("{}/validate", scenario.target))
+        .query(&[("token", token), ("circuit_id", circuit_id)])
+        .send()
+        .await
+        .map(|r| r.status().as_u16())
+        .unwrap_or(0);
+    recorder.record(Operation::Validate, started.elapsed(), status);
+}
+
+/// Peek at the expected answer stored by Fortify
+///
+/// Sealed (offline) challenges are not in Redis and yield `None`.
+async fn lookup_answer(
+    redis: &mut redis::aio::ConnectionManager,
+    challenge_id: &ChallengeId,
+) -> Result<Option<String>> {
+    let key = redis_keys::challenge(challenge_id);
+    let stored: Option<String> = redis.get(&key).await?;
+
+    Ok(match stored {
+        Some(json) => Some(serde_json::from_str::<StoredAnswer>(&json)?.answer),
+        None => None,
+    })
+}
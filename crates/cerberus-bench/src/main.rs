@@ -0,0 +1,192 @@
+//! # Cerberus Bench
+//!
+//! Load generator for Fortify's hot paths. Simulates N concurrent circuits,
+//! each repeatedly requesting a challenge, answering it, and validating the
+//! passport it gets back. Reports throughput and latency percentiles per
+//! endpoint.
+//!
+//! Correct answers are read straight from Redis (`--redis-url`), so point
+//! it at a test deployment, never at production.
+//!
+//! ## Usage
+//! ```bash
+//! # 200 circuits, 80% solve rate
+//! cerberus-bench --circuits 200 --redis-url redis://127.0.0.1:6379
+//!
+//! # Failure path only, machine-readable output for CI comparisons
+//! cerberus-bench --circuits 50 --solve-rate 0 --json
+//! ```
+
+mod circuit;
+mod stats;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use serde::Serialize;
+
+use circuit::Scenario;
+use stats::{Operation, Recorder, Summary};
+
+/// Cerberus load generator
+#[derive(Parser, Debug)]
+#[command(name = "cerberus-bench")]
+#[command(author, version, about = "Load-test Fortify challenge, verify and validate paths", long_about = None)]
+struct Args {
+    /// Fortify base URL
+    #[arg(short, long, default_value = "http://127.0.0.1:8888")]
+    target: String,
+
+    /// Number of concurrent simulated circuits
+    #[arg(short, long, default_value = "50")]
+    circuits: u32,
+
+    /// Challenges attempted per circuit
+    #[arg(short, long, default_value = "20")]
+    iterations: u32,
+
+    /// Fraction of challenges answered correctly (0.0 - 1.0)
+    #[arg(long, default_value = "0.8")]
+    solve_rate: f64,
+
+    /// Redis URL used to look up expected answers (without it, every answer is wrong)
+    #[arg(long)]
+    redis_url: Option<String>,
+
+    /// Per-request timeout in seconds
+    #[arg(long, default_value = "10")]
+    timeout: u64,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Serialize)]
+struct Report {
+    circuits: u32,
+    elapsed_secs: f64,
+    solved: u64,
+    failed: u64,
+    operations: BTreeMap<Operation, Summary>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let redis = match args.redis_url {
+        Some(ref url) => {
+            let client = redis::Client::open(url.as_str()).context("Invalid Redis URL")?;
+            Some(
+                redis::aio::ConnectionManager::new(client)
+                    .await
+                    .context("Failed to connect to Redis")?,
+            )
+        }
+        None => {
+            if args.solve_rate > 0.0 {
+                eprintln!("⚠️  No --redis-url: answers cannot be looked up, all will fail");
+            }
+            None
+        }
+    };
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(args.timeout))
+        .pool_max_idle_per_host(args.circuits as usize)
+        .build()?;
+
+    let scenario = Arc::new(Scenario {
+        client,
+        target: args.target.trim_end_matches('/').to_string(),
+        iterations: args.iterations,
+        solve_rate: args.solve_rate,
+        redis,
+    });
+
+    // Unique per run so circuit state from earlier runs doesn't interfere
+    let run_id: u32 = rand::random();
+
+    if !args.json {
+        println!(
+            "🐺 Benchmarking {} with {} circuits x {} iterations",
+            scenario.target, args.circuits, args.iterations
+        );
+    }
+
+    let started = Instant::now();
+    let handles: Vec<_> = (0..args.circuits)
+        .map(|i| {
+            let scenario = scenario.clone();
+            tokio::spawn(async move {
+                circuit::run(&scenario, format!("bench-{:08x}-{}", run_id, i)).await
+            })
+        })
+        .collect();
+
+    let mut total = Recorder::default();
+    for handle in handles {
+        total.merge(handle.await?);
+    }
+    let elapsed = started.elapsed();
+
+    let report = Report {
+        circuits: args.circuits,
+        elapsed_secs: elapsed.as_secs_f64(),
+        solved: total.solved,
+        failed: total.failed,
+        operations: total
+            .ops
+            .into_iter()
+            .map(|(op, samples)| (op, samples.summarize(elapsed)))
+            .collect(),
+    };
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_report(&report);
+    }
+
+    Ok(())
+}
+
+fn print_report(report: &Report) {
+    println!();
+    println!(
+        "Completed in {:.2}s — {} solved, {} failed",
+        report.elapsed_secs, report.solved, report.failed
+    );
+    println!();
+    println!(
+        "{:<10} {:>8} {:>10} {:>10} {:>10} {:>10} {:>10}  statuses",
+        "endpoint", "requests", "req/s", "p50 ms", "p90 ms", "p99 ms", "max ms"
+    );
+
+    for (op, s) in &report.operations {
+        let statuses: Vec<String> = s
+            .statuses
+            .iter()
+            .map(|(status, count)| match status {
+                0 => format!("error={}", count),
+                _ => format!("{}={}", status, count),
+            })
+            .collect();
+
+        println!(
+            "{:<10} {:>8} {:>10.1} {:>10.2} {:>10.2} {:>10.2} {:>10.2}  {}",
+            op.name(),
+            s.count,
+            s.throughput,
+            s.p50_us as f64 / 1000.0,
+            s.p90_us as f64 / 1000.0,
+            s.p99_us as f64 / 1000.0,
+            s.max_us as f64 / 1000.0,
+            statuses.join(" ")
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! Latency and outcome accounting.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Endpoint exercised by a simulated circuit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    /// `GET /challenge`
+    Challenge,
+    /// `POST /verify`
+    Verify,
+    /// `GET /validate`
+    Validate,
+}
+
+impl Operation {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Challenge => "challenge",
+            Self::Verify => "verify",
+            Self::Validate => "validate",
+        }
+    }
+}
+
+/// Samples for one operation
+#[derive(Debug, Default, Clone)]
+pub struct Samples {
+    /// Latencies in microseconds
+    latencies: Vec<u64>,
+    /// Response status -> count (0 = transport error)
+    statuses: BTreeMap<u16, u64>,
+}
+
+impl Samples {
+    pub fn record(&mut self, latency: Duration, status: u16) {
+        self.latencies.push(latency.as_micros() as u64);
+        *self.statuses.entry(status).or_default() += 1;
+    }
+
+    pub fn merge(&mut self, other: Samples) {
+        self.latencies.extend(other.latencies);
+        for (status, count) in other.statuses {
+            *self.statuses.entry(status).or_default() += count;
+        }
+    }
+
+    pub fn summarize(mut self, elapsed: Duration) -> Summary {
+        self.latencies.sort_unstable();
+        let count = self.latencies.len() as u64;
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+
+        Summary {
+            count,
+            throughput: count as f64 / secs,
+            p50_us: percentile(&self.latencies, 50.0),
+            p90_us: percentile(&self.latencies, 90.0),
+            p99_us: percentile(&self.latencies, 99.0),
+            max_us: self.latencies.last().copied().unwrap_or(0),
+            statuses: self.statuses,
+        }
+    }
+}
+
+/// Per-operation results
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    pub count: u64,
+    /// Requests per second over the whole run
+    pub throughput: f64,
+    pub p50_us: u64,
+    pub p90_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+    pub statuses: BTreeMap<u16, u64>,
+}
+
+/// Nearest-rank percentile of sorted samples
+pub fn percentile(sorted: &[u64], pct: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (pct / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Whole-run accounting, one instance per simulated circuit
+#[derive(Debug, Default, Clone)]
+pub struct Recorder {
+    pub ops: BTreeMap<Operation, Samples>,
+    /// Challenges answered correctly / incorrectly
+    pub solved: u64,
+    pub failed: u64,
+}
+
+impl Recorder {
+    pub fn record(&mut self, op: Operation, latency: Duration, status: u16) {
+        self.ops.entry(op).or_default().record(latency, status);
+    }
+
+    pub fn merge(&mut self, other: Recorder) {
+        for (op, samples) in other.ops {
+            self.ops.entry(op).or_default().merge(samples);
+        }
+        self.solved += other.solved;
+        self.failed += other.failed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile_nearest_rank() {
+        let samples: Vec<u64> = (1..=100).collect();
+        assert_eq!(percentile(&samples, 50.0), 50);
+        assert_eq!(percentile(&samples, 99.0), 99);
+        assert_eq!(percentile(&samples, 100.0), 100);
+        assert_eq!(percentile(&samples, 0.0), 1);
+        assert_eq!(percentile(&[], 50.0), 0);
+    }
+
+    #[test]
+    fn test_merge_and_summarize() {
+        let mut a = Recorder::default();
+        a.record(Operation::Verify, Duration::from_millis(2), 200);
+        let mut b = Recorder::default();
+        b.record(Operation::Verify, Duration::from_millis(4), 429);
+        b.solved = 1;
+        a.merge(b);
+
+        let summary = a
+            .ops
+            .remove(&Operation::Verify)
+            .unwrap()
+            .summarize(Duration::from_secs(1));
+        assert_eq!(summary.count, 2);
+        assert_eq!(summary.max_us, 4000);
+        assert_eq!(summary.statuses.get(&429), Some(&1));
+        assert_eq!(a.solved, 1);
+    }
+}
@@ -0,0 +1,37 @@
+//! Client error type.
+
+use thiserror::Error;
+
+/// Errors returned by `FortifyClient`
+#[derive(Debug, Error)]
+pub enum ClientError {
+    /// Invalid base URL or proxy settings
+    #[error("Client configuration error: {0}")]
+    Config(String),
+
+    /// Connection, timeout or body decoding failure
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+
+    /// Fortify is rate limiting this client or circuit
+    #[error("Rate limited (retry after {retry_after_secs:?}s)")]
+    RateLimited { retry_after_secs: Option<u64> },
+
+    /// Any other non-success status
+    #[error("Fortify returned {status}: {body}")]
+    Status { status: u16, body: String },
+}
+
+impl ClientError {
+    /// HTTP status returned by Fortify, if the request got that far
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Self::RateLimited { .. } => Some(429),
+            Self::Status { status, .. } => Some(*status),
+            Self::Http(e) => e.status().map(|s| s.as_u16()),
+            Self::Config(_) => None,
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, ClientError>;
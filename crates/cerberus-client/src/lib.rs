@@ -0,0 +1,553 @@
+//! # Cerberus Client
+//!
+//! Async client for Fortify's HTTP API: challenges, verification, passport
+//! validation and the admin endpoints.
+//!
+//! ```no_run
+//! # async fn demo() -> cerberus_client::Result<()> {
+//! use cerberus_client::FortifyClient;
+//!
+//! let fortify = FortifyClient::builder("http://fortify.onion")
+//!     .proxy("socks5h://127.0.0.1:9050")
+//!     .build()?;
+//! let challenge = fortify.challenge(None).await?;
+//! let result = fortify.verify(&challenge.challenge_id, "K7XQ2", None).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Idempotent requests are retried on connection errors, timeouts and
+//! 502/503/504; `verify` and `refresh_challenge` consume a challenge, so
+//! they are only retried when the connection could not be made at all.
+
+use cerberus_common::outbound::OutboundClientBuilder;
+use cerberus_common::{CaptchaResult, ChallengeId, CircuitId, PassportToken};
+use reqwest::{StatusCode, Url, header};
+use serde::de::DeserializeOwned;
+use std::time::Duration;
+
+mod error;
+mod types;
+
+pub use cerberus_common::outbound::CircuitIsolation;
+pub use error::{ClientError, Result};
+pub use types::{
+    Challenge, CircuitDetails, CircuitHistoryEntry, NodeStats, RevokeResult, ThreatLevelInfo,
+    Validation,
+};
+use types::{RevokeBody, SetThreatLevelBody, VerifyBody};
+
+/// Default request timeout (onion services are slow to reach)
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Default mount point of the admin routes
+pub const DEFAULT_ADMIN_PATH: &str = "/admin";
+
+/// When and how long to wait before retrying a request
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt (0 = never retry)
+    pub max_retries: u32,
+    /// Delay before the first retry; doubles on each further one
+    pub base_delay: Duration,
+    /// Upper bound for any delay, including a server's `Retry-After`
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            base_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Never retry
+    pub fn none() -> Self {
+        Self {
+            max_retries: 0,
+            ..Default::default()
+        }
+    }
+
+    /// Delay before retry number `retry` (1-based)
+    fn delay(&self, retry: u32) -> Duration {
+        let factor = 1u32 << retry.saturating_sub(1).min(16);
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+/// Builder for `FortifyClient`
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    base_url: String,
+    proxy: Option<String>,
+    isolation: CircuitIsolation,
+    timeout: Duration,
+    admin_path: String,
+    retry: RetryPolicy,
+}
+
+impl ClientBuilder {
+    /// Route every request through a proxy, e.g. `socks5h://127.0.0.1:9050`
+    /// for Tor (`socks5h` resolves `.onion` names on the proxy)
+    pub fn proxy(mut self, url: impl Into<String>) -> Self {
+        self.proxy = Some(url.into());
+        self
+    }
+
+    /// Which requests may share a Tor circuit (default: this client's
+    /// requests share circuits no other client uses)
+    pub fn isolation(mut self, isolation: CircuitIsolation) -> Self {
+        self.isolation = isolation;
+        self
+    }
+
+    /// Per-request timeout (default 30s)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Where the admin routes are mounted (default `/admin`)
+    pub fn admin_path(mut self, path: impl Into<String>) -> Self {
+        self.admin_path = path.into();
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn build(self) -> Result<FortifyClient> {
+        let base = Url::parse(&self.base_url)
+            .map_err(|e| ClientError::Config(format!("invalid base URL: {}", e)))?;
+        if base.cannot_be_a_base() {
+            return Err(ClientError::Config(format!(
+                "invalid base URL: {}",
+                self.base_url
+            )));
+        }
+
+        let mut http = OutboundClientBuilder::new()
+            .timeout(self.timeout)
+            .isolation(self.isolation)
+            .user_agent(concat!("cerberus-client/", env!("CARGO_PKG_VERSION")));
+        if let Some(ref proxy) = self.proxy {
+            http = http.proxy(proxy);
+        }
+
+        Ok(FortifyClient {
+            http: http
+                .build()
+                .map_err(|e| ClientError::Config(e.to_string()))?,
+            base,
+            admin_path: self
+                .admin_path
+                .split('/')
+                .filter(|s| !s.is_empty())
+                .map(str::to_string)
+                .collect(),
+            retry: self.retry,
+        })
+    }
+}
+
+/// Typed client for one Fortify node
+#[derive(Debug, Clone)]
+pub struct FortifyClient {
+    http: reqwest::Client,
+    base: Url,
+    /// Segments of the admin mount point
+    admin_path: Vec<String>,
+    retry: RetryPolicy,
+}
+
+impl FortifyClient {
+    /// Start building a client for the Fortify instance at `base_url`
+    pub fn builder(base_url: impl Into<String>) -> ClientBuilder {
+        ClientBuilder {
+            base_url: base_url.into(),
+            proxy: None,
+            isolation: CircuitIsolation::default(),
+            timeout: DEFAULT_TIMEOUT,
+            admin_path: DEFAULT_ADMIN_PATH.to_string(),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    // === Gate ===
+
+    /// Request a new challenge (`GET /challenge`)
+    pub async fn challenge(&self, circuit_id: Option<&CircuitId>) -> Result<Challenge> {
+        let url = self.url(&["challenge"]);
+        let response = self
+            .send(true, || {
+                with_circuit(self.http.get(url.clone()), circuit_id)
+            })
+            .await?;
+        json(response).await
+    }
+
+    /// Replace a challenge with a fresh one, invalidating the old one
+    pub async fn refresh_challenge(
+        &self,
+        challenge_id: &ChallengeId,
+        circuit_id: Option<&CircuitId>,
+    ) -> Result<Challenge> {
+        let url = self.url(&["challenge", challenge_id.as_str(), "refresh"]);
+        let response = self
+            .send(false, || {
+                with_circuit(
+                    self.http
+                        .post(url.clone())
+                        .header(header::ACCEPT, "application/json"),
+                    circuit_id,
+                )
+            })
+            .await?;
+        json(response).await
+    }
+
+    /// Submit an answer (`POST /verify`)
+    ///
+    /// An incorrect answer is a successful call with `success: false`.
+    pub async fn verify(
+        &self,
+        challenge_id: &ChallengeId,
+        answer: &str,
+        circuit_id: Option<&CircuitId>,
+    ) -> Result<CaptchaResult> {
+        let url = self.url(&["verify"]);
+        let body = VerifyBody {
+            challenge_id,
+            answer,
+        };
+        let response = self
+            .send(false, || {
+                with_circuit(self.http.post(url.clone()).json(&body), circuit_id)
+            })
+            .await?;
+        json(response).await
+    }
+
+    /// Check a passport (`GET /validate`)
+    pub async fn validate(
+        &self,
+        token: &PassportToken,
+        circuit_id: Option<&CircuitId>,
+    ) -> Result<Validation> {
+        let url = self.url(&["validate"]);
+        let response = self
+            .send(true, || {
+                let mut query = vec![("token", token.as_str())];
+                if let Some(id) = circuit_id {
+                    query.push(("circuit_id", id.as_str()));
+                }
+                self.http.get(url.clone()).query(&query)
+            })
+            .await?;
+
+        match response.status() {
+            StatusCode::UNAUTHORIZED => Ok(Validation::Invalid),
+            StatusCode::FORBIDDEN => Ok(Validation::Forbidden),
+            _ => check(response).await.map(|_| Validation::Valid),
+        }
+    }
+
+    // === Admin ===
+
+    pub async fn threat_level(&self) -> Result<ThreatLevelInfo> {
+        let url = self.admin_url(&["threat-level"]);
+        let response = self.send(true, || self.http.get(url.clone())).await?;
+        json(response).await
+    }
+
+    /// Move the threat dial (clamped to 0-10 by Fortify)
+    pub async fn set_threat_level(&self, level: u8) -> Result<ThreatLevelInfo> {
+        let url = self.admin_url(&["threat-level"]);
+        let body = SetThreatLevelBody { level };
+        let response = self
+            .send(true, || self.http.post(url.clone()).json(&body))
+            .await?;
+        json(response).await
+    }
+
+    /// Circuit record and history; `None` if Fortify has no record of it
+    pub async fn circuit(&self, circuit_id: &CircuitId) -> Result<Option<CircuitDetails>> {
+        let url = self.admin_url(&["circuits", circuit_id.as_str()]);
+        let response = self.send(true, || self.http.get(url.clone())).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        json(response).await.map(Some)
+    }
+
+    /// Ban a circuit (queued by Fortify if its Redis is offline)
+    pub async fn ban_circuit(&self, circuit_id: &CircuitId) -> Result<()> {
+        let url = self.admin_url(&["circuits", circuit_id.as_str()]);
+        let response = self.send(true, || self.http.delete(url.clone())).await?;
+        check(response).await.map(drop)
+    }
+
+    /// Lift a ban or soft-lock; `false` if the circuit had neither
+    pub async fn unban_circuit(&self, circuit_id: &CircuitId) -> Result<bool> {
+        let url = self.admin_url(&["circuits", circuit_id.as_str(), "unban"]);
+        let response = self.send(true, || self.http.post(url.clone())).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        check(response).await.map(|_| true)
+    }
+
+    /// Report a honeypot hit, soft-locking the circuit
+    pub async fn honeypot_hit(&self, circuit_id: &CircuitId) -> Result<()> {
+        let url = self.admin_url(&["circuits", circuit_id.as_str(), "honeypot"]);
+        let response = self.send(true, || self.http.post(url.clone())).await?;
+        check(response).await.map(drop)
+    }
+
+    pub async fn stats(&self) -> Result<NodeStats> {
+        let url = self.admin_url(&["stats"]);
+        let response = self.send(true, || self.http.get(url.clone())).await?;
+        json(response).await
+    }
+
+    /// Revoke a passport by token and/or whatever passport a circuit holds
+    pub async fn revoke_passport(
+        &self,
+        token: Option<&PassportToken>,
+        circuit_id: Option<&CircuitId>,
+    ) -> Result<RevokeResult> {
+        let url = self.admin_url(&["passports", "revoke"]);
+        let body = RevokeBody { token, circuit_id };
+        let response = self
+            .send(true, || self.http.post(url.clone()).json(&body))
+            .await?;
+        json(response).await
+    }
+
+    // === Plumbing ===
+
+    /// `base` with `segments` appended (each one percent-encoded)
+    fn url(&self, segments: &[&str]) -> Url {
+        let mut url = self.base.clone();
+        url.path_segments_mut()
+            .expect("base URL checked in build()")
+            .pop_if_empty()
+            .extend(segments);
+        url
+    }
+
+    fn admin_url(&self, segments: &[&str]) -> Url {
+        let path: Vec<&str> = self
+            .admin_path
+            .iter()
+            .map(String::as_str)
+            .chain(segments.iter().copied())
+            .collect();
+        self.url(&path)
+    }
+
+    /// Send a request, retrying per the policy
+    ///
+    /// `idempotent` requests are also retried on timeouts and 502/503/504.
+    async fn send(
+        &self,
+        idempotent: bool,
+        request: impl Fn() -> reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response> {
+        let mut retry = 0;
+        loop {
+            let outcome = request().send().await;
+            if retry >= self.retry.max_retries {
+                return Ok(outcome?);
+            }
+            retry += 1;
+
+            let delay = match outcome {
+                Ok(response) if idempotent && retryable(response.status()) => {
+                    match retry_after(&response) {
+                        Some(secs) => Duration::from_secs(secs).min(self.retry.max_delay),
+                        None => self.retry.delay(retry),
+                    }
+                }
+                Ok(response) => return Ok(response),
+                Err(e) if e.is_connect() || (idempotent && e.is_timeout()) => {
+                    self.retry.delay(retry)
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            tracing::debug!(
+                retry,
+                delay_ms = delay.as_millis() as u64,
+                "Retrying Fortify request"
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+fn with_circuit(
+    request: reqwest::RequestBuilder,
+    circuit_id: Option<&CircuitId>,
+) -> reqwest::RequestBuilder {
+    match circuit_id {
+        Some(id) => request.header(
+            cerberus_common::constants::headers::X_CIRCUIT_ID,
+            id.as_str(),
+        ),
+        None => request,
+    }
+}
+
+/// Worth another attempt: the node is overloaded or a proxy lost it
+fn retryable(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+fn retry_after(response: &reqwest::Response) -> Option<u64> {
+    response
+        .headers()
+        .get(header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// Turn non-success statuses into errors
+async fn check(response: reqwest::Response) -> Result<reqwest::Response> {
+    let status = response.status();
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        return Err(ClientError::RateLimited {
+            retry_after_secs: retry_after(&response),
+        });
+    }
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(ClientError::Status {
+            status: status.as_u16(),
+            body,
+        });
+    }
+    Ok(response)
+}
+
+async fn json<T: DeserializeOwned>(response: reqwest::Response) -> Result<T> {
+    Ok(check(response).await?.json().await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, http::StatusCode as AxumStatus, routing::get};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn test_urls() {
+        let client = FortifyClient::builder("http://fortify.onion/gate/")
+            .admin_path("/k3x9/admin/")
+            .build()
+            .unwrap();
+        assert_eq!(
+            client.url(&["validate"]).as_str(),
+            "http://fortify.onion/gate/validate"
+        );
+
+        // Circuit IDs may contain characters that are special in paths
+        let circuit_id: CircuitId = "fc00::1/#?".parse().unwrap();
+        assert_eq!(
+            client
+                .admin_url(&["circuits", circuit_id.as_str()])
+                .as_str(),
+            "http://fortify.onion/gate/k3x9/admin/circuits/fc00::1%2F%23%3F"
+        );
+
+        assert!(FortifyClient::builder("not a url").build().is_err());
+        assert!(
+            FortifyClient::builder("http://fortify.onion")
+                .proxy("socks5h://127.0.0.1:9050")
+                .build()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let policy = RetryPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(350),
+        };
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(3), Duration::from_millis(350));
+        assert_eq!(policy.delay(40), Duration::from_millis(350));
+    }
+
+    /// Fortify stand-in: `/validate` is unavailable for the first call,
+    /// `/verify` always is
+    async fn flaky_fortify() -> (String, Arc<AtomicU32>, Arc<AtomicU32>) {
+        let validate_calls = Arc::new(AtomicU32::new(0));
+        let verify_calls = Arc::new(AtomicU32::new(0));
+        let (validate, verify) = (validate_calls.clone(), verify_calls.clone());
+
+        let app = Router::new()
+            .route(
+                "/validate",
+                get(move || async move {
+                    match validate.fetch_add(1, Ordering::SeqCst) {
+                        0 => AxumStatus::SERVICE_UNAVAILABLE,
+                        _ => AxumStatus::UNAUTHORIZED,
+                    }
+                }),
+            )
+            .route(
+                "/verify",
+                axum::routing::post(move || async move {
+                    verify.fetch_add(1, Ordering::SeqCst);
+                    AxumStatus::SERVICE_UNAVAILABLE
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        (format!("http://{}", addr), validate_calls, verify_calls)
+    }
+
+    #[tokio::test]
+    async fn test_only_idempotent_requests_retried() {
+        let (base, validate_calls, verify_calls) = flaky_fortify().await;
+        let client = FortifyClient::builder(base)
+            .retry(RetryPolicy {
+                max_retries: 2,
+                base_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(1),
+            })
+            .build()
+            .unwrap();
+
+        let token: PassportToken = "tok".parse().unwrap();
+        assert_eq!(
+            client.validate(&token, None).await.unwrap(),
+            Validation::Invalid
+        );
+        assert_eq!(validate_calls.load(Ordering::SeqCst), 2);
+
+        let challenge_id: ChallengeId = "abc".parse().unwrap();
+        let err = client.verify(&challenge_id, "x", None).await.unwrap_err();
+        assert_eq!(err.status(), Some(503));
+        assert_eq!(verify_calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,98 @@
+//! Request and response bodies of the Fortify API.
+
+use cerberus_common::{ChallengeId, CircuitId, CircuitInfo, PassportToken, ThreatBand};
+use serde::{Deserialize, Serialize};
+
+/// A challenge from `GET /challenge`
+#[derive(Debug, Clone, Deserialize)]
+pub struct Challenge {
+    pub challenge_id: ChallengeId,
+    /// Image as a `data:` URI (absent when the node serves images)
+    #[serde(default)]
+    pub image_data: Option<String>,
+    /// Path of the image, relative to the Fortify base URL
+    #[serde(default)]
+    pub image_url: Option<String>,
+    pub grid_size: (u8, u8),
+    pub instructions: String,
+    pub expires_in_secs: u32,
+}
+
+/// Outcome of `GET /validate`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Validation {
+    /// The passport is good
+    Valid,
+    /// Unknown, expired, revoked or malformed passport
+    Invalid,
+    /// The circuit is banned or soft-locked
+    Forbidden,
+}
+
+/// Threat dial state (`/admin/threat-level`)
+#[derive(Debug, Clone, Deserialize)]
+pub struct ThreatLevelInfo {
+    pub level: u8,
+    /// Absent from older Fortify releases
+    #[serde(default)]
+    pub band: Option<ThreatBand>,
+    pub requires_captcha: bool,
+    pub captcha_count: u8,
+}
+
+/// Circuit record plus its recent history (`/admin/circuits/{id}`)
+#[derive(Debug, Clone, Deserialize)]
+pub struct CircuitDetails {
+    #[serde(flatten)]
+    pub info: CircuitInfo,
+    /// Newest first
+    #[serde(default)]
+    pub events: Vec<CircuitHistoryEntry>,
+}
+
+/// One entry of a circuit's history
+#[derive(Debug, Clone, Deserialize)]
+pub struct CircuitHistoryEntry {
+    /// Event kind, e.g. `failed`, `solved`, `banned`
+    pub kind: String,
+    /// Unix timestamp (seconds)
+    pub at: i64,
+    #[serde(default)]
+    pub detail: Option<String>,
+}
+
+/// Node summary (`/admin/stats`)
+#[derive(Debug, Clone, Deserialize)]
+pub struct NodeStats {
+    pub node_id: String,
+    pub threat_level: u8,
+    pub version: String,
+}
+
+/// Result of `/admin/passports/revoke`
+#[derive(Debug, Clone, Deserialize)]
+pub struct RevokeResult {
+    /// Passports revoked
+    pub revoked: usize,
+    /// Peers the revocation was pushed to
+    pub peers_notified: usize,
+}
+
+#[derive(Serialize)]
+pub(crate) struct VerifyBody<'a> {
+    pub challenge_id: &'a ChallengeId,
+    pub answer: &'a str,
+}
+
+#[derive(Serialize)]
+pub(crate) struct SetThreatLevelBody {
+    pub level: u8,
+}
+
+#[derive(Serialize)]
+pub(crate) struct RevokeBody<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<&'a PassportToken>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub circuit_id: Option<&'a CircuitId>,
+}
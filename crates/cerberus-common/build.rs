@@ -0,0 +1,19 @@
+//! Generates the gRPC control plane code (`grpc` feature).
+
+fn main() {
+    #[cfg(feature = "grpc")]
+    {
+        // Vendored protoc, so building needs no system protobuf install
+        let protoc = protoc_bin_vendored::protoc_bin_path().expect("vendored protoc");
+        // SAFETY: build scripts are single-threaded
+        unsafe { std::env::set_var("PROTOC", protoc) };
+
+        println!("cargo:rerun-if-changed=proto/control.proto");
+        tonic_prost_build::configure()
+            // Transport is up to the caller (Fortify serves, clients pick a
+            // channel), so the stubs only need tonic codegen
+            .build_transport(false)
+            .compile_protos(&["proto/control.proto"], &["proto"])
+            .expect("compile proto/control.proto");
+    }
+}
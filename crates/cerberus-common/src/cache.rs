@@ -0,0 +1,251 @@
+//! Bounded in-process caches.
+//!
+//! `BoundedCache` is a least-recently-used map capped by entry count and,
+//! optionally, by an estimate of the memory its entries hold. Inserting past
+//! either cap evicts the least recently used entries, so a table keyed by
+//! attacker-controlled values (circuits, source addresses, node IDs) can't
+//! grow without limit. Owners publish `stats()` as metrics.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// Occupancy and evictions of a cache
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub entries: usize,
+    /// Estimated bytes held (0 unless a byte cap is set)
+    pub bytes: usize,
+    /// Entries evicted to stay within the caps, since creation
+    pub evictions: u64,
+}
+
+struct Slot<V> {
+    value: V,
+    /// Recency: higher is more recent
+    tick: u64,
+    bytes: usize,
+}
+
+/// Least-recently-used map with an entry cap and an optional byte cap
+pub struct BoundedCache<K, V> {
+    max_entries: usize,
+    /// 0 = no byte cap
+    max_bytes: usize,
+    weigh: fn(&K, &V) -> usize,
+    entries: HashMap<K, Slot<V>>,
+    /// Recency order: tick -> key
+    order: BTreeMap<u64, K>,
+    tick: u64,
+    bytes: usize,
+    evictions: u64,
+}
+
+impl<K: Eq + Hash + Clone, V> BoundedCache<K, V> {
+    /// A cache holding at most `max_entries` (at least one)
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            max_entries: max_entries.max(1),
+            max_bytes: 0,
+            weigh: |_, _| 0,
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            bytes: 0,
+            evictions: 0,
+        }
+    }
+
+    /// Also cap the bytes held, as estimated by `weigh` on insert
+    ///
+    /// An entry is weighed once; changes made through `get_mut` aren't
+    /// reweighed.
+    pub fn with_max_bytes(mut self, max_bytes: usize, weigh: fn(&K, &V) -> usize) -> Self {
+        self.max_bytes = max_bytes;
+        self.weigh = weigh;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Is the entry cap reached (the next new key evicts)?
+    pub fn is_full(&self) -> bool {
+        self.entries.len() >= self.max_entries
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Look up `key`, marking it recently used
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.get_mut(key).map(|value| &*value)
+    }
+
+    /// Look up `key` for changing, marking it recently used
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let tick = self.next_tick();
+        let slot = self.entries.get_mut(key)?;
+        self.order.remove(&slot.tick);
+        self.order.insert(tick, key.clone());
+        slot.tick = tick;
+        Some(&mut slot.value)
+    }
+
+    /// Look up `key` without changing its recency
+    pub fn peek(&self, key: &K) -> Option<&V> {
+        self.entries.get(key).map(|slot| &slot.value)
+    }
+
+    /// The least recently used entry (the next to be evicted)
+    pub fn peek_lru(&self) -> Option<(&K, &V)> {
+        let (_, key) = self.order.first_key_value()?;
+        self.entries.get(key).map(|slot| (key, &slot.value))
+    }
+
+    /// Insert or replace `key` as the most recently used entry, evicting
+    /// the least recently used ones if over a cap; returns the old value
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let bytes = if self.max_bytes > 0 {
+            (self.weigh)(&key, &value)
+        } else {
+            0
+        };
+        let old = self.remove(&key);
+
+        let tick = self.next_tick();
+        self.order.insert(tick, key.clone());
+        self.entries.insert(key, Slot { value, tick, bytes });
+        self.bytes += bytes;
+
+        // Never evicts the entry just inserted, even if it alone is too big
+        while self.entries.len() > 1
+            && (self.entries.len() > self.max_entries
+                || (self.max_bytes > 0 && self.bytes > self.max_bytes))
+        {
+            self.evict_lru();
+        }
+        old
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let slot = self.entries.remove(key)?;
+        self.order.remove(&slot.tick);
+        self.bytes -= slot.bytes;
+        Some(slot.value)
+    }
+
+    /// Keep only the entries `keep` returns true for (not counted as
+    /// evictions)
+    pub fn retain(&mut self, mut keep: impl FnMut(&K, &mut V) -> bool) {
+        let order = &mut self.order;
+        let bytes = &mut self.bytes;
+        self.entries.retain(|key, slot| {
+            let kept = keep(key, &mut slot.value);
+            if !kept {
+                order.remove(&slot.tick);
+                *bytes -= slot.bytes;
+            }
+            kept
+        });
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter().map(|(key, slot)| (key, &slot.value))
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.values().map(|slot| &slot.value)
+    }
+
+    /// Values for changing, without changing recency
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
+        self.entries.values_mut().map(|slot| &mut slot.value)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.entries.len(),
+            bytes: self.bytes,
+            evictions: self.evictions,
+        }
+    }
+
+    fn evict_lru(&mut self) {
+        if let Some((_, key)) = self.order.pop_first()
+            && let Some(slot) = self.entries.remove(&key)
+        {
+            self.bytes -= slot.bytes;
+            self.evictions += 1;
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> BoundedCache<K, V> {
+    /// Copy of the entries, e.g. for a status page
+    pub fn to_map(&self) -> HashMap<K, V> {
+        self.iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = BoundedCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        // Reading "a" makes "b" the oldest
+        assert_eq!(cache.get(&"a"), Some(&1));
+        assert_eq!(cache.peek_lru(), Some((&"b", &2)));
+
+        cache.insert("c", 3);
+        assert!(!cache.contains_key(&"b"));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.stats().evictions, 1);
+
+        // Replacing and removing aren't evictions
+        assert_eq!(cache.insert("a", 10), Some(1));
+        assert_eq!(cache.remove(&"c"), Some(3));
+        cache.retain(|_, value| *value > 100);
+        assert!(cache.is_empty());
+        assert_eq!(cache.stats().evictions, 1);
+    }
+
+    #[test]
+    fn test_byte_cap() {
+        let mut cache = BoundedCache::new(100).with_max_bytes(10, |key: &String, _: &()| key.len());
+        cache.insert("abcd".to_string(), ());
+        cache.insert("efgh".to_string(), ());
+        assert_eq!(cache.stats().bytes, 8);
+
+        cache.insert("ijkl".to_string(), ());
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                entries: 2,
+                bytes: 8,
+                evictions: 1
+            }
+        );
+        assert!(cache.peek(&"abcd".to_string()).is_none());
+
+        // An entry over the cap on its own is still kept
+        cache.insert("x".repeat(20), ());
+        assert_eq!(cache.len(), 1);
+    }
+}
@@ -1,5 +1,7 @@
 //! Shared constants for Cerberus components.
 
+use crate::TtlSecs;
+
 /// Default Redis connection URL
 pub const DEFAULT_REDIS_URL: &str = "redis://127.0.0.1:6379";
 
@@ -7,30 +9,33 @@ pub const DEFAULT_REDIS_URL: &str = "redis://127.0.0.1:6379";
 pub const DEFAULT_LISTEN_ADDR: &str = "127.0.0.1:8888";
 
 /// Default passport token validity (10 minutes)
-pub const DEFAULT_PASSPORT_TTL_SECS: u64 = 600;
+pub const DEFAULT_PASSPORT_TTL_SECS: TtlSecs = TtlSecs::from_mins(10);
 
 /// Maximum failed CAPTCHA attempts before soft-lock
 pub const MAX_FAILED_ATTEMPTS: u32 = 5;
 
-/// Soft-lock duration in seconds (30 minutes)
-pub const SOFT_LOCK_DURATION_SECS: u64 = 1800;
+/// Soft-lock duration (30 minutes)
+pub const SOFT_LOCK_DURATION_SECS: TtlSecs = TtlSecs::from_mins(30);
 
-/// Ban duration in seconds (1 hour)
-pub const BAN_DURATION_SECS: u64 = 3600;
+/// Ban duration (1 hour)
+pub const BAN_DURATION_SECS: TtlSecs = TtlSecs::from_hours(1);
 
 /// Circuit info expiry in Redis (30 minutes)
-pub const CIRCUIT_TTL_SECS: u64 = 1800;
+pub const CIRCUIT_TTL_SECS: TtlSecs = TtlSecs::from_mins(30);
 
 /// CAPTCHA challenge expiry in Redis (5 minutes)
-pub const CAPTCHA_TTL_SECS: u64 = 300;
+pub const CAPTCHA_TTL_SECS: TtlSecs = TtlSecs::from_mins(5);
+
+/// Rate limit and refresh counter window
+pub const RATE_LIMIT_WINDOW_SECS: TtlSecs = TtlSecs::from_mins(1);
 
-/// Cluster heartbeat interval (seconds)
-pub const CLUSTER_HEARTBEAT_INTERVAL_SECS: u64 = 5;
+/// Cluster heartbeat interval
+pub const CLUSTER_HEARTBEAT_INTERVAL_SECS: TtlSecs = TtlSecs::from_secs(5);
 
-/// Cluster node timeout (seconds)
-pub const CLUSTER_NODE_TIMEOUT_SECS: u64 = 15;
+/// Cluster node timeout
+pub const CLUSTER_NODE_TIMEOUT_SECS: TtlSecs = TtlSecs::from_secs(15);
 
-/// Redis key prefixes
+/// Redis key prefixes (build keys with `crate::redis_keys`)
 pub mod redis_keys {
     /// Circuit info: circuit:{circuit_id}
     pub const CIRCUIT_PREFIX: &str = "circuit:";
@@ -38,20 +43,125 @@ pub mod redis_keys {
     /// CAPTCHA challenge: captcha:{challenge_id}
     pub const CAPTCHA_PREFIX: &str = "captcha:";
 
+    /// Rendered challenge image: captchaimage:{challenge_id}
+    pub const CAPTCHA_IMAGE_PREFIX: &str = "captchaimage:";
+
+    /// Shadow trial puzzle (`captcha.shadow`): shadow:{challenge_id}
+    pub const SHADOW_PREFIX: &str = "shadow:";
+
     /// Passport token: passport:{token}
     pub const PASSPORT_PREFIX: &str = "passport:";
 
+    /// Requests validated with a passport: passportuses:{token}
+    pub const PASSPORT_USES_PREFIX: &str = "passportuses:";
+
+    /// Gate-to-first-request timings of a passport (hash): journey:{token}
+    pub const JOURNEY_PREFIX: &str = "journey:";
+
     /// Global threat level
     pub const THREAT_LEVEL: &str = "cerberus:threat_level";
 
+    /// Pub/sub channel announcing threat level changes (payload = level)
+    pub const THREAT_LEVEL_CHANNEL: &str = "cerberus:threat_level_changed";
+
     /// Cluster state: cluster:node:{node_id}
     pub const CLUSTER_NODE_PREFIX: &str = "cluster:node:";
 
     /// Metrics: metrics:{metric_name}
     pub const METRICS_PREFIX: &str = "metrics:";
 
+    /// Challenge experiment tallies (hash, field = {variant}:{outcome}):
+    /// metrics:experiment:{name}
+    pub const EXPERIMENT_PREFIX: &str = "metrics:experiment:";
+
+    /// Journey stage totals per threat level (hash, fields = count and
+    /// {stage}_ms): metrics:journey:{threat_level}
+    pub const JOURNEY_TOTALS_PREFIX: &str = "metrics:journey:";
+
     /// Rate limit counters: ratelimit:{circuit_id}
     pub const RATELIMIT_PREFIX: &str = "ratelimit:";
+
+    /// Outstanding challenges per circuit (sorted set): outstanding:{circuit_id}
+    pub const OUTSTANDING_PREFIX: &str = "outstanding:";
+
+    /// Global issuance counter per second: cerberus:issued:{unix_secs}
+    pub const ISSUANCE_RATE_PREFIX: &str = "cerberus:issued:";
+
+    /// Challenges awaiting an answer (sorted set, score = expires_at,
+    /// member = "{challenge_id}" or "{challenge_id} {circuit_id}")
+    pub const PENDING_CHALLENGES: &str = "cerberus:pending_challenges";
+
+    /// Challenges issued and abandoned per minute (hash):
+    /// cerberus:challenges:{unix_minute}
+    pub const CHALLENGE_TALLY_PREFIX: &str = "cerberus:challenges:";
+
+    /// Challenge outcomes per difficulty per minute (hash, field =
+    /// "{difficulty}:{outcome}"): cerberus:difficulty:{unix_minute}
+    pub const DIFFICULTY_TALLY_PREFIX: &str = "cerberus:difficulty:";
+
+    /// Challenges a circuit left to expire unanswered: abandoned:{circuit_id}
+    pub const ABANDONED_PREFIX: &str = "abandoned:";
+
+    /// Challenge refresh counters: refresh:{circuit_id}
+    pub const REFRESH_PREFIX: &str = "refresh:";
+
+    /// Answer attempt counters: verifyattempts:{circuit_id}
+    pub const VERIFY_ATTEMPTS_PREFIX: &str = "verifyattempts:";
+
+    /// Recent wrong answers: wronganswers:{circuit_id}
+    pub const WRONG_ANSWERS_PREFIX: &str = "wronganswers:";
+
+    /// Circuits locked out of the gate: gatelockout:{circuit_id}
+    pub const GATE_LOCKOUT_PREFIX: &str = "gatelockout:";
+
+    /// Requests of a circuit in flight (sorted set, score = admitted at ms):
+    /// inflight:{circuit_id}
+    pub const IN_FLIGHT_PREFIX: &str = "inflight:";
+
+    /// Recent solve-time samples: solvetimes:{circuit_id}
+    pub const SOLVE_TIMES_PREFIX: &str = "solvetimes:";
+
+    /// Recent circuit events (list, newest first): events:{circuit_id}
+    pub const EVENTS_PREFIX: &str = "events:";
+
+    /// No-JS gate sessions: gatesession:{session_id}
+    pub const GATE_SESSION_PREFIX: &str = "gatesession:";
+
+    /// Gate form nonces, pending or processed: gateform:{nonce}
+    pub const GATE_FORM_PREFIX: &str = "gateform:";
+
+    /// Spent gate proof-of-work puzzles: pow:{seed}
+    pub const POW_PREFIX: &str = "pow:";
+
+    /// Live passports per circuit (sorted set): circuitpassports:{circuit_id}
+    pub const CIRCUIT_PASSPORTS_PREFIX: &str = "circuitpassports:";
+
+    /// Circuits flagged by farm detection (sorted set, score = flagged_at)
+    pub const FARM_SUSPECTS: &str = "cerberus:farm_suspects";
+
+    /// VIP circuits (sorted set, score = last write)
+    pub const VIPS: &str = "cerberus:vips";
+
+    /// Cluster leader lease (value = leader's node ID)
+    pub const LEADER: &str = "cerberus:leader";
+
+    /// Leadership term, incremented on every election
+    pub const LEADER_TERM: &str = "cerberus:leader_term";
+
+    /// Cluster-wide actions proposed to the leader (list)
+    pub const PROPOSALS: &str = "cerberus:proposals";
+
+    /// Passport signing keys of cluster nodes (hash, node ID -> public key)
+    pub const PASSPORT_KEYS: &str = "cerberus:passport_keys";
+
+    /// First-party bot allowlist (hash, secret digest -> JSON entry)
+    pub const ALLOWLIST: &str = "cerberus:allowlist";
+
+    /// Access log entries for the dashboard (stream, field `entry` = JSON)
+    pub const ACCESS_LOG: &str = "cerberus:access_log";
+
+    /// Feature flag overrides (hash, `{flag}` or `{flag}:{node ID}` -> 1/0)
+    pub const FLAGS: &str = "cerberus:flags";
 }
 
 /// HTTP header names
@@ -62,6 +172,12 @@ pub mod headers {
     /// Passport token header
     pub const X_PASSPORT_TOKEN: &str = "X-Passport-Token";
 
+    /// Originally requested path and query (set by Nginx before the gate)
+    pub const X_ORIGINAL_URI: &str = "X-Original-URI";
+
+    /// Originally requested method (set by Nginx on auth subrequests)
+    pub const X_ORIGINAL_METHOD: &str = "X-Original-Method";
+
     /// Threat level header (internal)
     pub const X_THREAT_LEVEL: &str = "X-Threat-Level";
 
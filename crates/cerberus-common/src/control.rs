@@ -0,0 +1,7 @@
+//! gRPC control plane (`grpc` feature).
+//!
+//! Messages and service stubs generated from `proto/control.proto`: Fortify
+//! serves `control_plane_server::ControlPlane`, automation and dashboards
+//! connect with `control_plane_client::ControlPlaneClient`.
+
+tonic::include_proto!("cerberus.control.v1");
@@ -0,0 +1,202 @@
+//! Tunable CAPTCHA difficulty parameters.
+//!
+//! Each `CaptchaDifficulty` has a `DifficultyProfile`: answer length, noise,
+//! distortion, grid and timeout. The built-in profiles apply unless
+//! different ones are installed at startup (`install`, from
+//! `[captcha.difficulty.*]` in fortify.toml), after which
+//! `CaptchaDifficulty::profile` and everything built on it use them.
+
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+use crate::CaptchaDifficulty;
+
+/// Parameters of one difficulty
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DifficultyProfile {
+    /// Characters in a text answer
+    pub answer_length: u8,
+    /// Noise lines drawn over the image
+    pub noise_lines: u16,
+    /// Max glyph rotation either way, in degrees
+    pub rotation: f32,
+    /// Max glyph shear either way (x shift per unit of y)
+    pub skew: f32,
+    /// Max displacement of each glyph point either way, in pixels
+    pub jitter: f32,
+    /// Wave distortion of raster images, in pixels
+    pub wave_amplitude: f32,
+    /// Grid shown to the client (columns, rows)
+    pub grid_size: (u8, u8),
+    /// Time the client is told it has to answer
+    pub timeout_secs: u32,
+}
+
+impl DifficultyProfile {
+    /// The profile compiled in for `difficulty`
+    pub const fn builtin(difficulty: CaptchaDifficulty) -> Self {
+        match difficulty {
+            CaptchaDifficulty::Easy => Self {
+                answer_length: 4,
+                noise_lines: 5,
+                rotation: 10.0,
+                skew: 0.1,
+                jitter: 0.5,
+                wave_amplitude: 1.5,
+                grid_size: (2, 2),
+                timeout_secs: 60,
+            },
+            CaptchaDifficulty::Medium => Self {
+                answer_length: 5,
+                noise_lines: 15,
+                rotation: 15.0,
+                skew: 0.2,
+                jitter: 1.0,
+                wave_amplitude: 2.5,
+                grid_size: (3, 3),
+                timeout_secs: 45,
+            },
+            CaptchaDifficulty::Hard => Self {
+                answer_length: 6,
+                noise_lines: 30,
+                rotation: 20.0,
+                skew: 0.3,
+                jitter: 1.5,
+                wave_amplitude: 3.5,
+                grid_size: (4, 4),
+                timeout_secs: 30,
+            },
+            CaptchaDifficulty::Extreme => Self {
+                answer_length: 8,
+                noise_lines: 50,
+                rotation: 25.0,
+                skew: 0.4,
+                jitter: 2.0,
+                wave_amplitude: 4.5,
+                grid_size: (5, 5),
+                timeout_secs: 20,
+            },
+        }
+    }
+}
+
+/// A profile for every difficulty
+///
+/// Deserialized from per-difficulty tables in which every field is
+/// optional: whatever isn't set keeps its built-in value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "ProfileOverrides")]
+pub struct DifficultyProfiles {
+    pub easy: DifficultyProfile,
+    pub medium: DifficultyProfile,
+    pub hard: DifficultyProfile,
+    pub extreme: DifficultyProfile,
+}
+
+impl DifficultyProfiles {
+    pub const BUILTIN: Self = Self {
+        easy: DifficultyProfile::builtin(CaptchaDifficulty::Easy),
+        medium: DifficultyProfile::builtin(CaptchaDifficulty::Medium),
+        hard: DifficultyProfile::builtin(CaptchaDifficulty::Hard),
+        extreme: DifficultyProfile::builtin(CaptchaDifficulty::Extreme),
+    };
+
+    pub fn get(&self, difficulty: CaptchaDifficulty) -> &DifficultyProfile {
+        match difficulty {
+            CaptchaDifficulty::Easy => &self.easy,
+            CaptchaDifficulty::Medium => &self.medium,
+            CaptchaDifficulty::Hard => &self.hard,
+            CaptchaDifficulty::Extreme => &self.extreme,
+        }
+    }
+}
+
+impl Default for DifficultyProfiles {
+    fn default() -> Self {
+        Self::BUILTIN
+    }
+}
+
+static PROFILES: OnceLock<DifficultyProfiles> = OnceLock::new();
+
+/// Use `profiles` from now on (once, at startup; later calls are ignored)
+pub fn install(profiles: DifficultyProfiles) {
+    let _ = PROFILES.set(profiles);
+}
+
+/// The installed profiles, or the built-in ones
+pub fn profiles() -> &'static DifficultyProfiles {
+    PROFILES.get().unwrap_or(&DifficultyProfiles::BUILTIN)
+}
+
+/// Per-difficulty tables as written in the config
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ProfileOverrides {
+    easy: ProfileOverride,
+    medium: ProfileOverride,
+    hard: ProfileOverride,
+    extreme: ProfileOverride,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ProfileOverride {
+    answer_length: Option<u8>,
+    noise_lines: Option<u16>,
+    rotation: Option<f32>,
+    skew: Option<f32>,
+    jitter: Option<f32>,
+    wave_amplitude: Option<f32>,
+    grid_size: Option<(u8, u8)>,
+    timeout_secs: Option<u32>,
+}
+
+impl ProfileOverride {
+    fn apply(self, difficulty: CaptchaDifficulty) -> DifficultyProfile {
+        let base = DifficultyProfile::builtin(difficulty);
+        DifficultyProfile {
+            answer_length: self.answer_length.unwrap_or(base.answer_length),
+            noise_lines: self.noise_lines.unwrap_or(base.noise_lines),
+            rotation: self.rotation.unwrap_or(base.rotation),
+            skew: self.skew.unwrap_or(base.skew),
+            jitter: self.jitter.unwrap_or(base.jitter),
+            wave_amplitude: self.wave_amplitude.unwrap_or(base.wave_amplitude),
+            grid_size: self.grid_size.unwrap_or(base.grid_size),
+            timeout_secs: self.timeout_secs.unwrap_or(base.timeout_secs),
+        }
+    }
+}
+
+impl From<ProfileOverrides> for DifficultyProfiles {
+    fn from(overrides: ProfileOverrides) -> Self {
+        Self {
+            easy: overrides.easy.apply(CaptchaDifficulty::Easy),
+            medium: overrides.medium.apply(CaptchaDifficulty::Medium),
+            hard: overrides.hard.apply(CaptchaDifficulty::Hard),
+            extreme: overrides.extreme.apply(CaptchaDifficulty::Extreme),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unset_fields_keep_builtin_values() {
+        let profiles: DifficultyProfiles = serde_json::from_str(
+            r#"{"hard": {"answer_length": 7, "noise_lines": 40}, "extreme": {"timeout_secs": 25}}"#,
+        )
+        .unwrap();
+
+        let hard = profiles.get(CaptchaDifficulty::Hard);
+        assert_eq!((hard.answer_length, hard.noise_lines), (7, 40));
+        assert_eq!(hard.grid_size, (4, 4));
+        assert_eq!(profiles.extreme.timeout_secs, 25);
+        assert_eq!(profiles.easy, DifficultyProfiles::BUILTIN.easy);
+
+        // A misspelt field is an error, not silently ignored
+        assert!(serde_json::from_str::<DifficultyProfiles>(r#"{"hard": {"noise": 40}}"#).is_err());
+    }
+}
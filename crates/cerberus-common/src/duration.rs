@@ -0,0 +1,220 @@
+//! Durations in configuration.
+//!
+//! `TtlSecs` is a whole number of seconds that config files can write either
+//! as an integer (`1800`) or as a human-readable span (`"30m"`, `"1h 30m"`,
+//! `"7d"`). Integers keep older config files valid; spans read better for
+//! the longer lifetimes. Units are `s`, `m`, `h`, `d` and `w` (or their
+//! spelled-out names), and a bare number is seconds. Values serialize back
+//! as plain seconds.
+
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::CerberusError;
+
+/// Seconds per unit, longest first (also the `Display` order)
+const UNITS: [(&str, u64); 5] = [
+    ("w", 7 * 86400),
+    ("d", 86400),
+    ("h", 3600),
+    ("m", 60),
+    ("s", 1),
+];
+
+/// A span of whole seconds (TTLs, intervals, timeouts)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(into = "u64")]
+pub struct TtlSecs(u64);
+
+impl TtlSecs {
+    pub const ZERO: Self = Self(0);
+
+    pub const fn from_secs(secs: u64) -> Self {
+        Self(secs)
+    }
+
+    pub const fn from_mins(mins: u64) -> Self {
+        Self(mins * 60)
+    }
+
+    pub const fn from_hours(hours: u64) -> Self {
+        Self(hours * 3600)
+    }
+
+    pub const fn from_days(days: u64) -> Self {
+        Self(days * 86400)
+    }
+
+    pub const fn secs(self) -> u64 {
+        self.0
+    }
+
+    /// Seconds as a signed offset for timestamp arithmetic
+    pub const fn as_i64(self) -> i64 {
+        self.0 as i64
+    }
+
+    pub const fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn duration(self) -> Duration {
+        Duration::from_secs(self.0)
+    }
+
+    /// Is this between `min` and `max`, inclusive?
+    pub fn within(self, min: Self, max: Self) -> bool {
+        (min..=max).contains(&self)
+    }
+}
+
+/// Seconds per unit name (none if unknown)
+fn unit_secs(unit: &str) -> Option<u64> {
+    Some(match unit {
+        "" | "s" | "sec" | "secs" | "second" | "seconds" => 1,
+        "m" | "min" | "mins" | "minute" | "minutes" => 60,
+        "h" | "hr" | "hrs" | "hour" | "hours" => 3600,
+        "d" | "day" | "days" => 86400,
+        "w" | "week" | "weeks" => 7 * 86400,
+        _ => return None,
+    })
+}
+
+impl FromStr for TtlSecs {
+    type Err = CerberusError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |why: &str| CerberusError::InvalidInput(format!("duration {:?} {}", s, why));
+        let mut rest = s.trim();
+        if rest.is_empty() {
+            return Err(invalid("is empty"));
+        }
+
+        let mut total: u64 = 0;
+        while !rest.is_empty() {
+            let digits = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            if digits == 0 {
+                return Err(invalid(
+                    "must be a number of seconds or spans like 30m, 1h 30m",
+                ));
+            }
+            let (number, tail) = rest.split_at(digits);
+            // "2 days" as well as "2d"
+            let tail = tail.trim_start();
+            let unit_len = tail
+                .find(|c: char| !c.is_ascii_alphabetic())
+                .unwrap_or(tail.len());
+            let (unit, tail) = tail.split_at(unit_len);
+            if unit.is_empty() && !tail.is_empty() {
+                return Err(invalid("has a number without a unit"));
+            }
+            let per_unit = unit_secs(unit).ok_or_else(|| invalid("has an unknown unit"))?;
+            total = number
+                .parse::<u64>()
+                .ok()
+                .and_then(|n| n.checked_mul(per_unit))
+                .and_then(|secs| total.checked_add(secs))
+                .ok_or_else(|| invalid("is too long"))?;
+            rest = tail.trim_start();
+        }
+        Ok(Self(total))
+    }
+}
+
+impl fmt::Display for TtlSecs {
+    /// Largest units first: `1h30m`, `45s`, `0s`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 == 0 {
+            return f.write_str("0s");
+        }
+        let mut left = self.0;
+        for (unit, per_unit) in UNITS {
+            if left >= per_unit {
+                write!(f, "{}{}", left / per_unit, unit)?;
+                left %= per_unit;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<TtlSecs> for u64 {
+    fn from(ttl: TtlSecs) -> Self {
+        ttl.0
+    }
+}
+
+impl From<TtlSecs> for Duration {
+    fn from(ttl: TtlSecs) -> Self {
+        ttl.duration()
+    }
+}
+
+impl<'de> Deserialize<'de> for TtlSecs {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Secs(u64),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Secs(secs) => Ok(Self(secs)),
+            Raw::Text(text) => text.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let parse = |s: &str| s.parse::<TtlSecs>().map(TtlSecs::secs).ok();
+        assert_eq!(parse("90"), Some(90));
+        assert_eq!(parse("30m"), Some(1800));
+        assert_eq!(parse("1h 30m"), Some(5400));
+        assert_eq!(parse("1h30m15s"), Some(5415));
+        assert_eq!(parse("2 days"), Some(172_800));
+        assert_eq!(parse("1w"), Some(604_800));
+        assert_eq!(parse(" 0s "), Some(0));
+
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("m"), None);
+        assert_eq!(parse("-5m"), None);
+        assert_eq!(parse("1.5h"), None);
+        assert_eq!(parse("5 fortnights"), None);
+        assert_eq!(parse("99999999999999999999w"), None);
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        for secs in [0, 45, 60, 5400, 86_400 + 61, 604_800 * 2] {
+            let ttl = TtlSecs::from_secs(secs);
+            assert_eq!(ttl.to_string().parse::<TtlSecs>().unwrap(), ttl);
+        }
+        assert_eq!(TtlSecs::from_secs(5415).to_string(), "1h30m15s");
+        assert_eq!(TtlSecs::ZERO.to_string(), "0s");
+    }
+
+    #[test]
+    fn test_serde() {
+        let from = |json: &str| serde_json::from_str::<TtlSecs>(json).ok();
+        assert_eq!(from("1800"), Some(TtlSecs::from_mins(30)));
+        assert_eq!(from("\"30m\""), Some(TtlSecs::from_mins(30)));
+        assert_eq!(from("\"soon\""), None);
+        assert_eq!(from("-1"), None);
+        assert_eq!(
+            serde_json::to_string(&TtlSecs::from_hours(1)).unwrap(),
+            "3600"
+        );
+
+        assert!(TtlSecs::from_mins(5).within(TtlSecs::from_secs(60), TtlSecs::from_hours(1)));
+        assert!(!TtlSecs::from_secs(30).within(TtlSecs::from_secs(60), TtlSecs::from_hours(1)));
+    }
+}
@@ -2,6 +2,9 @@
 
 use thiserror::Error;
 
+use crate::status::StatusEvent;
+use crate::types::CircuitStatus;
+
 /// Common errors across Cerberus components
 #[derive(Debug, Error)]
 pub enum CerberusError {
@@ -21,6 +24,13 @@ pub enum CerberusError {
     #[error("Circuit tracking error: {0}")]
     CircuitTracking(String),
 
+    /// Circuit status change the state machine refuses
+    #[error("Invalid circuit transition: {event:?} from {from:?}")]
+    InvalidTransition {
+        from: CircuitStatus,
+        event: StatusEvent,
+    },
+
     /// Authentication/authorization error
     #[error("Auth error: {0}")]
     Auth(String),
@@ -58,6 +68,7 @@ impl CerberusError {
             Self::Redis(_) => 503,
             Self::Captcha(_) => 500,
             Self::CircuitTracking(_) => 500,
+            Self::InvalidTransition { .. } => 409,
             Self::Auth(_) => 401,
             Self::RateLimited(_) => 429,
             Self::Banned(_) => 403,
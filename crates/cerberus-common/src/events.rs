@@ -0,0 +1,283 @@
+//! Events shared by Cerberus components.
+//!
+//! Anything other components may want to react to (audit log, live
+//! dashboards, cluster peers) is described by a `CerberusEvent`. Producers
+//! hold an `EventPublisher`; consumers implement `EventSubscriber` and
+//! register with an `EventBus`.
+
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+
+use crate::types::{CircuitId, ThreatLevel};
+
+/// Something that happened on a node
+///
+/// Serialized with a `type` tag, e.g. `{"type":"circuit_banned",...}`.
+/// Passport tokens are never included.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CerberusEvent {
+    /// The threat dial moved
+    ThreatLevelChanged { from: ThreatLevel, to: ThreatLevel },
+    /// A circuit was banned (by an admin or automatically)
+    CircuitBanned {
+        circuit_id: CircuitId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    /// A circuit was soft-locked
+    CircuitSoftLocked {
+        circuit_id: CircuitId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    /// A ban or soft-lock was lifted by an admin
+    CircuitUnbanned { circuit_id: CircuitId },
+    /// A circuit became a VIP
+    VipPromoted { circuit_id: CircuitId },
+    /// A VIP lost its status
+    VipDemoted {
+        circuit_id: CircuitId,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+    },
+    /// A passport was granted
+    PassportIssued {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        circuit_id: Option<CircuitId>,
+        /// Unix timestamp (seconds)
+        expires_at: i64,
+        vip: bool,
+    },
+    /// Passports were revoked by an admin
+    PassportsRevoked {
+        count: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        circuit_id: Option<CircuitId>,
+    },
+    /// A cluster peer stopped sending gossip
+    PeerUnhealthy { node_id: String },
+    /// A peer marked unhealthy is heard from again
+    PeerRecovered { node_id: String },
+    /// Too many peers went quiet: the node is cut off from the cluster
+    NodeIsolated { unhealthy: usize, peers: usize },
+    /// An isolated node hears from enough peers again
+    NodeRejoined,
+    /// This node won the cluster leader lease
+    LeaderElected { node_id: String, term: u64 },
+    /// This node gave up or lost the leader lease
+    LeaderSteppedDown { node_id: String },
+    /// The pre-generated CAPTCHA pool is running dry
+    AmmoLow { available: usize, capacity: usize },
+    /// The pool has stayed under the starvation threshold for
+    /// `starved_secs`: challenges are being rendered on demand
+    AmmoStarved {
+        available: usize,
+        capacity: usize,
+        starved_secs: u64,
+    },
+    /// A starving pool is back above the threshold
+    AmmoRecovered {
+        available: usize,
+        capacity: usize,
+        starved_secs: u64,
+    },
+    /// An HAProxy stick table is close to capacity (once full, HAProxy
+    /// evicts the oldest entries, bans included)
+    StickTableNearFull {
+        table: String,
+        used: u64,
+        capacity: u64,
+    },
+    /// An attack signature rule fired and its action was taken
+    RuleTriggered {
+        rule: String,
+        /// `ban`, `escalate` or `challenge`
+        action: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        circuit_id: Option<CircuitId>,
+    },
+    /// Maintenance mode was switched on or off
+    MaintenanceChanged { enabled: bool },
+    /// The node started draining, or the drain was cancelled
+    DrainChanged { draining: bool },
+    /// A feature flag override was set or cleared by an admin
+    FeatureFlagChanged {
+        flag: String,
+        /// `None`: the override was cleared
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        enabled: Option<bool>,
+        /// Node the override applies to (`None`: the whole cluster)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        node_id: Option<String>,
+    },
+    /// Observe-only mode let through a request enforcement would have stopped
+    EnforcementObserved {
+        /// `challenge`, `block`, `rate_limit` or `ban`
+        action: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        circuit_id: Option<CircuitId>,
+    },
+    /// A service level objective's error budget is burning too fast
+    SloBurning {
+        /// `availability` or `latency`
+        objective: String,
+        /// Over the long alert window (1.0 spends the budget exactly)
+        burn_rate: f64,
+        /// Threat level at the time, to tell escalation apart from other causes
+        threat_level: u8,
+    },
+    /// A burning objective is back under the alert burn rate
+    SloRecovered { objective: String },
+    /// A supervised background task panicked and will be restarted
+    TaskPanicked {
+        task: String,
+        message: String,
+        /// Restarts so far, this one included
+        restarts: u32,
+        restart_in_secs: u64,
+    },
+}
+
+impl CerberusEvent {
+    /// The `type` tag, for logs and metrics labels
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::ThreatLevelChanged { .. } => "threat_level_changed",
+            Self::CircuitBanned { .. } => "circuit_banned",
+            Self::CircuitSoftLocked { .. } => "circuit_soft_locked",
+            Self::CircuitUnbanned { .. } => "circuit_unbanned",
+            Self::VipPromoted { .. } => "vip_promoted",
+            Self::VipDemoted { .. } => "vip_demoted",
+            Self::PassportIssued { .. } => "passport_issued",
+            Self::PassportsRevoked { .. } => "passports_revoked",
+            Self::PeerUnhealthy { .. } => "peer_unhealthy",
+            Self::PeerRecovered { .. } => "peer_recovered",
+            Self::NodeIsolated { .. } => "node_isolated",
+            Self::NodeRejoined => "node_rejoined",
+            Self::LeaderElected { .. } => "leader_elected",
+            Self::LeaderSteppedDown { .. } => "leader_stepped_down",
+            Self::AmmoLow { .. } => "ammo_low",
+            Self::AmmoStarved { .. } => "ammo_starved",
+            Self::AmmoRecovered { .. } => "ammo_recovered",
+            Self::StickTableNearFull { .. } => "stick_table_near_full",
+            Self::RuleTriggered { .. } => "rule_triggered",
+            Self::MaintenanceChanged { .. } => "maintenance_changed",
+            Self::DrainChanged { .. } => "drain_changed",
+            Self::FeatureFlagChanged { .. } => "feature_flag_changed",
+            Self::EnforcementObserved { .. } => "enforcement_observed",
+            Self::SloBurning { .. } => "slo_burning",
+            Self::SloRecovered { .. } => "slo_recovered",
+            Self::TaskPanicked { .. } => "task_panicked",
+        }
+    }
+}
+
+/// Sends events to whoever is listening
+///
+/// Publishing never blocks on, or fails because of, a subscriber.
+pub trait EventPublisher: Send + Sync {
+    fn publish(&self, event: CerberusEvent);
+}
+
+/// Receives published events
+///
+/// Called on the publisher's task: subscribers that do I/O should hand the
+/// event to a channel or task of their own.
+pub trait EventSubscriber: Send + Sync {
+    fn on_event(&self, event: &CerberusEvent);
+}
+
+/// In-process fan-out from publishers to subscribers
+///
+/// With no subscribers, publishing is a no-op.
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: RwLock<Vec<Arc<dyn EventSubscriber>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Deliver every event published from now on to `subscriber`
+    pub fn subscribe(&self, subscriber: Arc<dyn EventSubscriber>) {
+        self.subscribers
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(subscriber);
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .len()
+    }
+}
+
+impl EventPublisher for EventBus {
+    fn publish(&self, event: CerberusEvent) {
+        let subscribers = self.subscribers.read().unwrap_or_else(|e| e.into_inner());
+        for subscriber in subscribers.iter() {
+            subscriber.on_event(&event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Collect(Mutex<Vec<CerberusEvent>>);
+
+    impl EventSubscriber for Collect {
+        fn on_event(&self, event: &CerberusEvent) {
+            self.0.lock().unwrap().push(event.clone());
+        }
+    }
+
+    #[test]
+    fn test_bus_fans_out() {
+        let bus = EventBus::new();
+        // No subscribers: nothing to do, nothing to fail
+        bus.publish(CerberusEvent::PeerUnhealthy {
+            node_id: "n1".to_string(),
+        });
+
+        let (a, b) = (Arc::new(Collect::default()), Arc::new(Collect::default()));
+        bus.subscribe(a.clone());
+        bus.subscribe(b.clone());
+        let event = CerberusEvent::AmmoLow {
+            available: 3,
+            capacity: 100,
+        };
+        bus.publish(event.clone());
+
+        assert_eq!(bus.subscriber_count(), 2);
+        assert_eq!(*a.0.lock().unwrap(), vec![event.clone()]);
+        assert_eq!(*b.0.lock().unwrap(), vec![event]);
+    }
+
+    #[test]
+    fn test_event_serialization() {
+        let event = CerberusEvent::CircuitBanned {
+            circuit_id: "c1".parse().unwrap(),
+            reason: None,
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(json, r#"{"type":"circuit_banned","circuit_id":"c1"}"#);
+        assert_eq!(serde_json::from_str::<CerberusEvent>(&json).unwrap(), event);
+
+        let event = CerberusEvent::ThreatLevelChanged {
+            from: ThreatLevel::new(3),
+            to: ThreatLevel::new(7),
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["type"], event.kind());
+        assert_eq!(json["to"], 7);
+    }
+}
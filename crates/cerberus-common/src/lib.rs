@@ -4,12 +4,38 @@
 //!
 //! ## Modules
 //! - `types` - Core data structures (ThreatLevel, CircuitState, etc.)
+//! - `cache` - Bounded LRU caches for in-process tables
 //! - `error` - Common error types
 //! - `constants` - Shared configuration constants
+//! - `difficulty` - Configurable CAPTCHA difficulty profiles
+//! - `duration` - Durations in configuration (`TtlSecs`)
+//! - `control` - gRPC control plane messages and stubs (`grpc` feature)
+//! - `events` - Event vocabulary and publisher/subscriber traits
+//! - `outbound` - Tor-aware outbound HTTP clients (`http` feature)
+//! - `redis_keys` - Redis key builders
+//! - `status` - Circuit status state machine
+//! - `versioned` - Schema versions for records kept in Redis
 
+pub mod cache;
 pub mod constants;
+#[cfg(feature = "grpc")]
+pub mod control;
+pub mod difficulty;
+pub mod duration;
 pub mod error;
+pub mod events;
+#[cfg(feature = "http")]
+pub mod outbound;
+pub mod redis_keys;
+pub mod status;
 pub mod types;
+pub mod versioned;
 
+pub use cache::{BoundedCache, CacheStats};
+pub use difficulty::{DifficultyProfile, DifficultyProfiles};
+pub use duration::TtlSecs;
 pub use error::CerberusError;
+pub use events::{CerberusEvent, EventBus, EventPublisher, EventSubscriber};
+pub use status::StatusEvent;
 pub use types::*;
+pub use versioned::Versioned;
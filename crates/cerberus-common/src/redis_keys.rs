@@ -0,0 +1,453 @@
+//! Redis key builders.
+//!
+//! Every key a component reads or writes is built here from the prefixes in
+//! `constants::redis_keys`, so two modules can't disagree on a key's name.
+//! Each key also says how long it lives (see `Ttl`).
+
+use std::fmt;
+
+use crate::TtlSecs;
+use crate::constants::redis_keys as prefix;
+use crate::constants::{CIRCUIT_TTL_SECS, RATE_LIMIT_WINDOW_SECS};
+use crate::types::{
+    ChallengeId, CircuitId, GateFormNonce, GateSessionId, PassportToken, ThreatLevel,
+};
+
+/// Expiry policy of a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ttl {
+    /// Always written with this lifetime
+    Fixed(TtlSecs),
+    /// Lifetime comes from configuration and is set by the writer
+    Configured,
+    /// Never expires
+    Persistent,
+}
+
+impl Ttl {
+    /// Lifetime in seconds, if fixed
+    pub fn secs(self) -> Option<u64> {
+        match self {
+            Self::Fixed(ttl) => Some(ttl.secs()),
+            _ => None,
+        }
+    }
+}
+
+/// A Redis key and its expiry policy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedisKey {
+    key: String,
+    ttl: Ttl,
+}
+
+impl RedisKey {
+    fn new(prefix: &str, id: impl fmt::Display, ttl: Ttl) -> Self {
+        Self {
+            key: format!("{}{}", prefix, id),
+            ttl,
+        }
+    }
+
+    fn global(key: &str) -> Self {
+        Self {
+            key: key.to_string(),
+            ttl: Ttl::Persistent,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.key
+    }
+
+    pub fn ttl(&self) -> Ttl {
+        self.ttl
+    }
+}
+
+impl From<RedisKey> for String {
+    fn from(key: RedisKey) -> Self {
+        key.key
+    }
+}
+
+impl AsRef<str> for RedisKey {
+    fn as_ref(&self) -> &str {
+        &self.key
+    }
+}
+
+impl fmt::Display for RedisKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.key)
+    }
+}
+
+#[cfg(feature = "redis")]
+impl redis::ToRedisArgs for RedisKey {
+    fn write_redis_args<W>(&self, out: &mut W)
+    where
+        W: ?Sized + redis::RedisWrite,
+    {
+        out.write_arg(self.key.as_bytes())
+    }
+}
+
+/// Circuit record (JSON `CircuitInfo`); lifetime depends on its status
+pub fn circuit(circuit_id: &CircuitId) -> RedisKey {
+    RedisKey::new(prefix::CIRCUIT_PREFIX, circuit_id, Ttl::Configured)
+}
+
+/// Stored CAPTCHA challenge, alive for the challenge TTL
+pub fn challenge(challenge_id: &ChallengeId) -> RedisKey {
+    RedisKey::new(prefix::CAPTCHA_PREFIX, challenge_id, Ttl::Configured)
+}
+
+/// Rendered image of a stored challenge, alive for the challenge TTL
+pub fn challenge_image(challenge_id: &ChallengeId) -> RedisKey {
+    RedisKey::new(prefix::CAPTCHA_IMAGE_PREFIX, challenge_id, Ttl::Configured)
+}
+
+/// Shadow trial puzzle, alive for the challenge TTL
+pub fn shadow_challenge(challenge_id: &ChallengeId) -> RedisKey {
+    RedisKey::new(prefix::SHADOW_PREFIX, challenge_id, Ttl::Configured)
+}
+
+/// Tallies of a challenge experiment, kept until deleted by hand
+pub fn experiment(name: &str) -> RedisKey {
+    RedisKey::new(prefix::EXPERIMENT_PREFIX, name, Ttl::Persistent)
+}
+
+/// Journey stage totals of passports issued at `level`, kept until deleted
+/// by hand
+pub fn journey_totals(level: ThreatLevel) -> RedisKey {
+    RedisKey::new(prefix::JOURNEY_TOTALS_PREFIX, level, Ttl::Persistent)
+}
+
+/// Redis-backed passport, alive for the passport TTL
+pub fn passport(token: &PassportToken) -> RedisKey {
+    RedisKey::new(prefix::PASSPORT_PREFIX, token, Ttl::Configured)
+}
+
+/// Requests validated with a passport, alive for the passport TTL
+pub fn passport_uses(token: &PassportToken) -> RedisKey {
+    RedisKey::new(prefix::PASSPORT_USES_PREFIX, token, Ttl::Configured)
+}
+
+/// Journey of a passport, alive for the passport TTL
+pub fn journey(token: &PassportToken) -> RedisKey {
+    RedisKey::new(prefix::JOURNEY_PREFIX, token, Ttl::Configured)
+}
+
+/// Unanswered challenges of a circuit (sorted set, score = expiry)
+pub fn outstanding(circuit_id: &CircuitId) -> RedisKey {
+    RedisKey::new(prefix::OUTSTANDING_PREFIX, circuit_id, Ttl::Configured)
+}
+
+/// Redis-backed passports of a circuit (sorted set, score = issued_at_ms)
+pub fn circuit_passports(circuit_id: &CircuitId) -> RedisKey {
+    RedisKey::new(
+        prefix::CIRCUIT_PASSPORTS_PREFIX,
+        circuit_id,
+        Ttl::Configured,
+    )
+}
+
+/// Requests of a circuit in the current rate limit window
+pub fn rate_limit(circuit_id: &CircuitId) -> RedisKey {
+    RedisKey::new(
+        prefix::RATELIMIT_PREFIX,
+        circuit_id,
+        Ttl::Fixed(RATE_LIMIT_WINDOW_SECS),
+    )
+}
+
+/// Challenges a circuit left to expire unanswered; kept as long as circuits
+pub fn abandoned(circuit_id: &CircuitId) -> RedisKey {
+    RedisKey::new(
+        prefix::ABANDONED_PREFIX,
+        circuit_id,
+        Ttl::Fixed(CIRCUIT_TTL_SECS),
+    )
+}
+
+/// Challenge refreshes of a circuit in the current rate limit window
+pub fn refresh(circuit_id: &CircuitId) -> RedisKey {
+    RedisKey::new(
+        prefix::REFRESH_PREFIX,
+        circuit_id,
+        Ttl::Fixed(RATE_LIMIT_WINDOW_SECS),
+    )
+}
+
+/// Answers a circuit posted in the current rate limit window
+pub fn verify_attempts(circuit_id: &CircuitId) -> RedisKey {
+    RedisKey::new(
+        prefix::VERIFY_ATTEMPTS_PREFIX,
+        circuit_id,
+        Ttl::Fixed(RATE_LIMIT_WINDOW_SECS),
+    )
+}
+
+/// Wrong answers of a circuit in the current burst window
+pub fn wrong_answers(circuit_id: &CircuitId) -> RedisKey {
+    RedisKey::new(prefix::WRONG_ANSWERS_PREFIX, circuit_id, Ttl::Configured)
+}
+
+/// Marks a circuit locked out of the gate; lives as long as the lockout
+pub fn gate_lockout(circuit_id: &CircuitId) -> RedisKey {
+    RedisKey::new(prefix::GATE_LOCKOUT_PREFIX, circuit_id, Ttl::Configured)
+}
+
+/// Requests of a circuit still counted as in flight; lives one lease
+pub fn in_flight(circuit_id: &CircuitId) -> RedisKey {
+    RedisKey::new(prefix::IN_FLIGHT_PREFIX, circuit_id, Ttl::Configured)
+}
+
+/// Recent solve-time samples of a circuit (list)
+pub fn solve_times(circuit_id: &CircuitId) -> RedisKey {
+    RedisKey::new(
+        prefix::SOLVE_TIMES_PREFIX,
+        circuit_id,
+        Ttl::Fixed(CIRCUIT_TTL_SECS),
+    )
+}
+
+/// Recent events of a circuit (list, newest first)
+pub fn events(circuit_id: &CircuitId) -> RedisKey {
+    RedisKey::new(prefix::EVENTS_PREFIX, circuit_id, Ttl::Configured)
+}
+
+/// No-JS gate session (JSON), alive for the gate session TTL
+pub fn gate_session(session_id: &GateSessionId) -> RedisKey {
+    RedisKey::new(prefix::GATE_SESSION_PREFIX, session_id, Ttl::Configured)
+}
+
+/// One-time nonce of a gate form rendered for a gate session, alive for
+/// the gate session TTL
+pub fn gate_form(session_id: &GateSessionId, nonce: &GateFormNonce) -> RedisKey {
+    RedisKey::new(
+        prefix::GATE_FORM_PREFIX,
+        format!("{}:{}", session_id, nonce),
+        Ttl::Configured,
+    )
+}
+
+/// Challenges issued during one second (global fixed window)
+pub fn issued(unix_secs: i64) -> RedisKey {
+    // One second of counting plus one of grace for late increments
+    RedisKey::new(
+        prefix::ISSUANCE_RATE_PREFIX,
+        unix_secs,
+        Ttl::Fixed(TtlSecs::from_secs(2)),
+    )
+}
+
+/// Challenges issued and abandoned during one minute (hash), kept for the
+/// abandonment window
+pub fn challenge_tally(unix_minute: i64) -> RedisKey {
+    RedisKey::new(prefix::CHALLENGE_TALLY_PREFIX, unix_minute, Ttl::Configured)
+}
+
+/// Challenge outcomes by difficulty during one minute (hash), kept for the
+/// longest challenge stats window
+pub fn difficulty_tally(unix_minute: i64) -> RedisKey {
+    RedisKey::new(prefix::DIFFICULTY_TALLY_PREFIX, unix_minute, Ttl::Configured)
+}
+
+/// Marks a gate proof-of-work puzzle spent; lives until the puzzle expires
+pub fn pow(seed: &str) -> RedisKey {
+    RedisKey::new(prefix::POW_PREFIX, seed, Ttl::Configured)
+}
+
+/// Global threat level
+pub fn threat_level() -> RedisKey {
+    RedisKey::global(prefix::THREAT_LEVEL)
+}
+
+/// Channel a node publishes its new threat level on (not a key)
+pub fn threat_level_channel() -> &'static str {
+    prefix::THREAT_LEVEL_CHANNEL
+}
+
+/// Challenges awaiting an answer (sorted set, score = expiry); entries are
+/// removed when answered or swept once expired
+pub fn pending_challenges() -> RedisKey {
+    RedisKey::global(prefix::PENDING_CHALLENGES)
+}
+
+/// Circuits flagged by farm detection (sorted set, score = flagged_at)
+pub fn farm_suspects() -> RedisKey {
+    RedisKey::global(prefix::FARM_SUSPECTS)
+}
+
+/// VIP circuits (sorted set, score = last write)
+pub fn vips() -> RedisKey {
+    RedisKey::global(prefix::VIPS)
+}
+
+/// Cluster leader lease; its lifetime is the configured lease
+pub fn leader() -> RedisKey {
+    RedisKey {
+        key: prefix::LEADER.to_string(),
+        ttl: Ttl::Configured,
+    }
+}
+
+/// Leadership term counter
+pub fn leader_term() -> RedisKey {
+    RedisKey::global(prefix::LEADER_TERM)
+}
+
+/// Actions followers propose to the leader (list of JSON, drained by it)
+pub fn proposals() -> RedisKey {
+    RedisKey::global(prefix::PROPOSALS)
+}
+
+/// Trust registry: each node's base64 passport verifying key
+pub fn passport_keys() -> RedisKey {
+    RedisKey::global(prefix::PASSPORT_KEYS)
+}
+
+/// First-party bot allowlist; entries carry their own expiry
+pub fn allowlist() -> RedisKey {
+    RedisKey::global(prefix::ALLOWLIST)
+}
+
+/// Access log stream, trimmed by length rather than expiry
+pub fn access_log() -> RedisKey {
+    RedisKey::global(prefix::ACCESS_LOG)
+}
+
+/// Feature flag overrides, cluster-wide and per node
+pub fn flags() -> RedisKey {
+    RedisKey::global(prefix::FLAGS)
+}
+
+/// Keys sharing a prefix, one per ID; all of them expire
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyFamily {
+    /// Label for statistics, e.g. `challenge`
+    pub name: &'static str,
+    pub prefix: &'static str,
+}
+
+impl KeyFamily {
+    const fn new(name: &'static str, prefix: &'static str) -> Self {
+        Self { name, prefix }
+    }
+}
+
+/// Every per-ID key family (global keys aren't included)
+pub const FAMILIES: &[KeyFamily] = &[
+    KeyFamily::new("circuit", prefix::CIRCUIT_PREFIX),
+    KeyFamily::new("challenge", prefix::CAPTCHA_PREFIX),
+    KeyFamily::new("challenge_image", prefix::CAPTCHA_IMAGE_PREFIX),
+    KeyFamily::new("shadow", prefix::SHADOW_PREFIX),
+    KeyFamily::new("passport", prefix::PASSPORT_PREFIX),
+    KeyFamily::new("passport_uses", prefix::PASSPORT_USES_PREFIX),
+    KeyFamily::new("journey", prefix::JOURNEY_PREFIX),
+    KeyFamily::new("outstanding", prefix::OUTSTANDING_PREFIX),
+    KeyFamily::new("circuit_passports", prefix::CIRCUIT_PASSPORTS_PREFIX),
+    KeyFamily::new("rate_limit", prefix::RATELIMIT_PREFIX),
+    KeyFamily::new("issued", prefix::ISSUANCE_RATE_PREFIX),
+    KeyFamily::new("challenge_tally", prefix::CHALLENGE_TALLY_PREFIX),
+    KeyFamily::new("difficulty_tally", prefix::DIFFICULTY_TALLY_PREFIX),
+    KeyFamily::new("abandoned", prefix::ABANDONED_PREFIX),
+    KeyFamily::new("refresh", prefix::REFRESH_PREFIX),
+    KeyFamily::new("verify_attempts", prefix::VERIFY_ATTEMPTS_PREFIX),
+    KeyFamily::new("wrong_answers", prefix::WRONG_ANSWERS_PREFIX),
+    KeyFamily::new("gate_lockout", prefix::GATE_LOCKOUT_PREFIX),
+    KeyFamily::new("in_flight", prefix::IN_FLIGHT_PREFIX),
+    KeyFamily::new("solve_times", prefix::SOLVE_TIMES_PREFIX),
+    KeyFamily::new("events", prefix::EVENTS_PREFIX),
+    KeyFamily::new("gate_session", prefix::GATE_SESSION_PREFIX),
+    KeyFamily::new("gate_form", prefix::GATE_FORM_PREFIX),
+    KeyFamily::new("pow", prefix::POW_PREFIX),
+];
+
+/// The family `key` belongs to, if any
+pub fn family(key: &str) -> Option<&'static KeyFamily> {
+    FAMILIES
+        .iter()
+        .find(|family| key.starts_with(family.prefix))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_and_policies() {
+        let circuit_id: CircuitId = "fc00:dead:beef:4dad::0:2a".parse().unwrap();
+        assert_eq!(
+            circuit(&circuit_id).as_str(),
+            "circuit:fc00:dead:beef:4dad::0:2a"
+        );
+        assert_eq!(
+            rate_limit(&circuit_id).to_string(),
+            "ratelimit:fc00:dead:beef:4dad::0:2a"
+        );
+        assert_eq!(
+            circuit_passports(&circuit_id).as_str(),
+            "circuitpassports:fc00:dead:beef:4dad::0:2a"
+        );
+        assert_eq!(issued(1_700_000_000).as_str(), "cerberus:issued:1700000000");
+        assert_eq!(
+            in_flight(&circuit_id).as_str(),
+            "inflight:fc00:dead:beef:4dad::0:2a"
+        );
+
+        assert_eq!(circuit(&circuit_id).ttl(), Ttl::Configured);
+        assert_eq!(
+            refresh(&circuit_id).ttl().secs(),
+            Some(RATE_LIMIT_WINDOW_SECS.secs())
+        );
+        assert_eq!(
+            verify_attempts(&circuit_id).ttl().secs(),
+            Some(RATE_LIMIT_WINDOW_SECS.secs())
+        );
+        assert_eq!(gate_lockout(&circuit_id).ttl(), Ttl::Configured);
+        assert_eq!(vips().ttl(), Ttl::Persistent);
+        assert_eq!(leader().as_str(), "cerberus:leader");
+        assert_eq!(leader().ttl(), Ttl::Configured);
+        assert_eq!(passport_keys().ttl(), Ttl::Persistent);
+        assert_eq!(flags().as_str(), "cerberus:flags");
+        assert_eq!(vips().ttl().secs(), None);
+        assert_eq!(
+            experiment("grid-vs-text").as_str(),
+            "metrics:experiment:grid-vs-text"
+        );
+        assert_eq!(experiment("grid-vs-text").ttl(), Ttl::Persistent);
+        assert_eq!(
+            journey_totals(ThreatLevel::new(7)).as_str(),
+            "metrics:journey:7"
+        );
+        assert_eq!(journey_totals(ThreatLevel::MAX).ttl(), Ttl::Persistent);
+    }
+
+    #[test]
+    fn test_families() {
+        let circuit_id: CircuitId = "fc00:dead:beef:4dad::0:2a".parse().unwrap();
+        let family_of = |key: RedisKey| family(key.as_str()).map(|f| f.name);
+        assert_eq!(family_of(circuit(&circuit_id)), Some("circuit"));
+        // Not mistaken for `circuit:`
+        assert_eq!(
+            family_of(circuit_passports(&circuit_id)),
+            Some("circuit_passports")
+        );
+        assert_eq!(family_of(issued(1_700_000_000)), Some("issued"));
+        assert_eq!(
+            family_of(challenge_tally(28_333_333)),
+            Some("challenge_tally")
+        );
+        assert_eq!(family_of(abandoned(&circuit_id)), Some("abandoned"));
+        // Global keys belong to no family
+        assert_eq!(family_of(threat_level()), None);
+        assert_eq!(family_of(passport_keys()), None);
+        assert_eq!(family_of(pending_challenges()), None);
+        // Nor do experiment tallies, which never expire
+        assert_eq!(family_of(experiment("grid-vs-text")), None);
+        assert_eq!(family_of(journey_totals(ThreatLevel::MAX)), None);
+    }
+}
@@ -0,0 +1,133 @@
+//! Circuit status state machine.
+//!
+//! Every change to a circuit's `CircuitStatus` goes through
+//! `CircuitStatus::transition`, so which moves are possible is decided in one
+//! place. Moves not in the table are refused: a banned circuit doesn't lift
+//! its own ban by solving a challenge, and only a VIP can be demoted.
+
+use crate::CerberusError;
+use crate::types::CircuitStatus;
+
+/// Something that happened to a circuit that may change its status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusEvent {
+    /// Solved a challenge
+    Solved,
+    /// Solved a challenge and qualifies as a VIP
+    Promoted,
+    /// Lost VIP status (failures, rate limiting, honeypot)
+    Demoted,
+    /// Too many failed attempts, or a honeypot hit
+    Locked,
+    /// Banned by an operator or a rule
+    Banned,
+    /// Ban or soft-lock lifted
+    Unbanned,
+    /// Passport withdrawn; the circuit has to solve again
+    PassportRevoked,
+}
+
+impl StatusEvent {
+    /// Every event, for exhaustive checks
+    pub const ALL: [StatusEvent; 7] = [
+        Self::Solved,
+        Self::Promoted,
+        Self::Demoted,
+        Self::Locked,
+        Self::Banned,
+        Self::Unbanned,
+        Self::PassportRevoked,
+    ];
+}
+
+impl CircuitStatus {
+    /// The status after `event`, or `InvalidTransition` if it is not allowed
+    pub fn transition(self, event: StatusEvent) -> Result<CircuitStatus, CerberusError> {
+        use CircuitStatus::*;
+        use StatusEvent as E;
+
+        match (self, event) {
+            // Solving a challenge
+            (New | Verified | SoftLocked, E::Solved) => Ok(Verified),
+            (Vip, E::Solved) => Ok(Vip),
+            (New | Verified | SoftLocked | Vip, E::Promoted) => Ok(Vip),
+            (Banned, E::Solved | E::Promoted) => Err(self.denied(event)),
+
+            // Losing VIP status
+            (Vip, E::Demoted) => Ok(Verified),
+            (New | Verified | SoftLocked | Banned, E::Demoted) => Err(self.denied(event)),
+
+            // Locks and bans
+            (New | Verified | Vip, E::Locked) => Ok(SoftLocked),
+            (SoftLocked | Banned, E::Locked) => Err(self.denied(event)),
+            (_, E::Banned) => Ok(Banned),
+            (SoftLocked | Banned, E::Unbanned) => Ok(New),
+            (New | Verified | Vip, E::Unbanned) => Err(self.denied(event)),
+
+            // Passport revocation
+            (New | Verified | Vip, E::PassportRevoked) => Ok(New),
+            (SoftLocked | Banned, E::PassportRevoked) => Err(self.denied(event)),
+        }
+    }
+
+    fn denied(self, event: StatusEvent) -> CerberusError {
+        CerberusError::InvalidTransition { from: self, event }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use CircuitStatus::*;
+
+    #[test]
+    fn test_transition_table() {
+        assert_eq!(New.transition(StatusEvent::Solved).unwrap(), Verified);
+        assert_eq!(Verified.transition(StatusEvent::Promoted).unwrap(), Vip);
+        assert_eq!(Vip.transition(StatusEvent::Solved).unwrap(), Vip);
+        assert_eq!(Vip.transition(StatusEvent::Demoted).unwrap(), Verified);
+        assert_eq!(Vip.transition(StatusEvent::Locked).unwrap(), SoftLocked);
+        assert_eq!(
+            SoftLocked.transition(StatusEvent::Solved).unwrap(),
+            Verified
+        );
+        assert_eq!(SoftLocked.transition(StatusEvent::Banned).unwrap(), Banned);
+        assert_eq!(Banned.transition(StatusEvent::Unbanned).unwrap(), New);
+        assert_eq!(Vip.transition(StatusEvent::PassportRevoked).unwrap(), New);
+    }
+
+    #[test]
+    fn test_denied_transitions() {
+        let denied = [
+            (Banned, StatusEvent::Solved),
+            (Banned, StatusEvent::Promoted),
+            (Banned, StatusEvent::Locked),
+            (SoftLocked, StatusEvent::Locked),
+            (Verified, StatusEvent::Demoted),
+            (New, StatusEvent::Unbanned),
+            (Banned, StatusEvent::PassportRevoked),
+        ];
+        for (from, event) in denied {
+            let err = from.transition(event).unwrap_err();
+            assert!(
+                matches!(err, CerberusError::InvalidTransition { from: f, event: e } if f == from && e == event),
+                "{from:?} + {event:?} should be denied"
+            );
+            assert_eq!(err.status_code(), 409);
+        }
+    }
+
+    #[test]
+    fn test_every_status_can_be_banned() {
+        for from in [New, Verified, SoftLocked, Banned, Vip] {
+            assert_eq!(from.transition(StatusEvent::Banned).unwrap(), Banned);
+        }
+
+        // Nothing leads out of a ban but lifting it (or banning again)
+        let exits: Vec<_> = StatusEvent::ALL
+            .into_iter()
+            .filter(|event| Banned.transition(*event).is_ok())
+            .collect();
+        assert_eq!(exits, [StatusEvent::Banned, StatusEvent::Unbanned]);
+    }
+}
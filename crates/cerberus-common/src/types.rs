@@ -1,6 +1,147 @@
 //! Core types shared across Cerberus components.
 
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::CerberusError;
+
+/// A validated string identifier
+///
+/// Parsing (`FromStr`, `TryFrom<String>`, serde) enforces the length limit
+/// and character set, so a value that made it into the type is safe to
+/// splice into a Redis key (see `redis_keys`); `Display` and serde give back the raw string.
+macro_rules! string_id {
+    (
+        $(#[$meta:meta])*
+        $name:ident, $what:literal, max_len = $max:literal, valid = $valid:expr
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+        #[serde(try_from = "String", into = "String")]
+        pub struct $name(String);
+
+        impl $name {
+            /// Longest accepted value, in bytes
+            pub const MAX_LEN: usize = $max;
+
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = CerberusError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                let valid: fn(char) -> bool = $valid;
+                if s.is_empty() || s.len() > Self::MAX_LEN {
+                    return Err(CerberusError::InvalidInput(format!(
+                        "{} must be 1-{} bytes",
+                        $what,
+                        Self::MAX_LEN
+                    )));
+                }
+                if !s.chars().all(valid) {
+                    return Err(CerberusError::InvalidInput(format!(
+                        "{} contains invalid characters",
+                        $what
+                    )));
+                }
+                Ok(Self(s.to_string()))
+            }
+        }
+
+        impl TryFrom<String> for $name {
+            type Error = CerberusError;
+
+            fn try_from(s: String) -> Result<Self, Self::Error> {
+                s.parse()
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(id: $name) -> Self {
+                id.0
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                self.0 == other
+            }
+        }
+
+        impl PartialEq<&str> for $name {
+            fn eq(&self, other: &&str) -> bool {
+                self.0 == *other
+            }
+        }
+
+        #[cfg(feature = "redis")]
+        impl redis::ToRedisArgs for $name {
+            fn write_redis_args<W>(&self, out: &mut W)
+            where
+                W: ?Sized + redis::RedisWrite,
+            {
+                out.write_arg(self.0.as_bytes())
+            }
+        }
+    };
+}
+
+/// URL-safe base64 alphabet (no padding)
+fn is_base64url(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+string_id! {
+    /// Tor circuit identifier, as set by HAProxy in `X-Circuit-Id`
+    ///
+    /// Printable ASCII without spaces (Tor exports circuits as IPv6
+    /// addresses, so `:` is allowed).
+    CircuitId, "circuit ID", max_len = 128, valid = |c| c.is_ascii_graphic()
+}
+
+string_id! {
+    /// CAPTCHA challenge identifier
+    ///
+    /// URL-safe base64; sealed (offline) challenges add `.`-separated fields.
+    ChallengeId, "challenge ID", max_len = 256, valid = |c| is_base64url(c) || c == '.'
+}
+
+string_id! {
+    /// Passport token handed to a client after a solve
+    ///
+    /// URL-safe base64, whether Redis-backed or signed.
+    PassportToken, "passport token", max_len = 512, valid = is_base64url
+}
+
+string_id! {
+    /// Gate session identifier, carried in the `cerberus_gate` cookie
+    ///
+    /// URL-safe base64.
+    GateSessionId, "gate session ID", max_len = 64, valid = is_base64url
+}
+
+string_id! {
+    /// One-time nonce of a rendered gate form
+    ///
+    /// URL-safe base64.
+    GateFormNonce, "gate form nonce", max_len = 64, valid = is_base64url
+}
 
 /// Threat Dial Level (0-10)
 /// Controls the aggressiveness of CAPTCHA challenges.
@@ -28,6 +169,32 @@ impl ThreatLevel {
         self.0
     }
 
+    /// `step` levels higher, up to `MAX`
+    pub fn escalate(self, step: u8) -> Self {
+        Self::new(self.0.saturating_add(step))
+    }
+
+    /// `step` levels lower, down to `MIN`
+    pub fn deescalate(self, step: u8) -> Self {
+        Self(self.0.saturating_sub(step))
+    }
+
+    /// Maximum lockdown (emergency)
+    pub fn is_lockdown(&self) -> bool {
+        *self == Self::MAX
+    }
+
+    /// The protection band this level belongs to
+    pub fn band(&self) -> ThreatBand {
+        match self.0 {
+            0 => ThreatBand::Off,
+            1..=3 => ThreatBand::Light,
+            4..=6 => ThreatBand::Standard,
+            7..=9 => ThreatBand::High,
+            _ => ThreatBand::Lockdown,
+        }
+    }
+
     /// Returns true if this level requires a CAPTCHA challenge
     pub fn requires_captcha(&self) -> bool {
         self.0 > 0
@@ -35,24 +202,22 @@ impl ThreatLevel {
 
     /// Returns the number of CAPTCHAs required at this threat level
     pub fn captcha_count(&self) -> u8 {
-        match self.0 {
-            0 => 0,
-            1..=3 => 1,
-            4..=6 => 2,
-            7..=9 => 3,
-            10 => 5, // Maximum lockdown
-            _ => unreachable!(),
+        match self.band() {
+            ThreatBand::Off => 0,
+            ThreatBand::Light => 1,
+            ThreatBand::Standard => 2,
+            ThreatBand::High => 3,
+            ThreatBand::Lockdown => 5,
         }
     }
 
     /// Returns the CAPTCHA difficulty (grid size) at this level
     pub fn captcha_difficulty(&self) -> CaptchaDifficulty {
-        match self.0 {
-            0..=3 => CaptchaDifficulty::Easy,
-            4..=6 => CaptchaDifficulty::Medium,
-            7..=9 => CaptchaDifficulty::Hard,
-            10 => CaptchaDifficulty::Extreme,
-            _ => unreachable!(),
+        match self.band() {
+            ThreatBand::Off | ThreatBand::Light => CaptchaDifficulty::Easy,
+            ThreatBand::Standard => CaptchaDifficulty::Medium,
+            ThreatBand::High => CaptchaDifficulty::Hard,
+            ThreatBand::Lockdown => CaptchaDifficulty::Extreme,
         }
     }
 }
@@ -69,6 +234,63 @@ impl From<u8> for ThreatLevel {
     }
 }
 
+/// Parses `0`-`10`; anything else is an error rather than clamped
+impl FromStr for ThreatLevel {
+    type Err = CerberusError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().parse::<u8>() {
+            Ok(level) if level <= Self::MAX.0 => Ok(Self(level)),
+            _ => Err(CerberusError::InvalidInput(format!(
+                "threat level must be {}-{}, got {:?}",
+                Self::MIN.0,
+                Self::MAX.0,
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ThreatLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Protection band of a threat level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThreatBand {
+    /// 0: no CAPTCHAs
+    Off,
+    /// 1-3: low traffic
+    Light,
+    /// 4-6: normal operation
+    Standard,
+    /// 7-9: under attack
+    High,
+    /// 10: emergency
+    Lockdown,
+}
+
+impl ThreatBand {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Light => "light",
+            Self::Standard => "standard",
+            Self::High => "high",
+            Self::Lockdown => "lockdown",
+        }
+    }
+}
+
+impl fmt::Display for ThreatBand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 /// CAPTCHA difficulty levels
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -84,24 +306,49 @@ pub enum CaptchaDifficulty {
 }
 
 impl CaptchaDifficulty {
+    /// Every difficulty, easiest first
+    pub const ALL: [Self; 4] = [Self::Easy, Self::Medium, Self::Hard, Self::Extreme];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Easy => "easy",
+            Self::Medium => "medium",
+            Self::Hard => "hard",
+            Self::Extreme => "extreme",
+        }
+    }
+
+    /// Parameters in effect for this difficulty (see `difficulty::install`)
+    pub fn profile(&self) -> &'static crate::difficulty::DifficultyProfile {
+        crate::difficulty::profiles().get(*self)
+    }
+
     pub fn grid_size(&self) -> (u8, u8) {
+        self.profile().grid_size
+    }
+
+    /// One step easier (`Easy` stays `Easy`)
+    pub fn easier(&self) -> Self {
         match self {
-            Self::Easy => (2, 2),
-            Self::Medium => (3, 3),
-            Self::Hard => (4, 4),
-            Self::Extreme => (5, 5),
+            Self::Easy | Self::Medium => Self::Easy,
+            Self::Hard => Self::Medium,
+            Self::Extreme => Self::Hard,
         }
     }
 
-    /// Timeout in seconds for this difficulty
-    pub fn timeout_secs(&self) -> u32 {
+    /// One step harder (`Extreme` stays `Extreme`)
+    pub fn harder(&self) -> Self {
         match self {
-            Self::Easy => 60,
-            Self::Medium => 45,
-            Self::Hard => 30,
-            Self::Extreme => 20,
+            Self::Easy => Self::Medium,
+            Self::Medium => Self::Hard,
+            Self::Hard | Self::Extreme => Self::Extreme,
         }
     }
+
+    /// Timeout in seconds for this difficulty
+    pub fn timeout_secs(&self) -> u32 {
+        self.profile().timeout_secs
+    }
 }
 
 /// Circuit state in the system
@@ -125,7 +372,7 @@ pub enum CircuitStatus {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CircuitInfo {
     /// Unique circuit identifier (from Tor/HAProxy)
-    pub circuit_id: String,
+    pub circuit_id: CircuitId,
 
     /// Current status
     pub status: CircuitStatus,
@@ -144,15 +391,29 @@ pub struct CircuitInfo {
 
     /// Passport token (if verified)
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub passport_token: Option<String>,
+    pub passport_token: Option<PassportToken>,
 
     /// Passport expiry timestamp
     #[serde(skip_serializing_if = "Option::is_none")]
     pub passport_expires: Option<i64>,
+
+    /// Reputation score (negative = suspicious, positive = trusted)
+    #[serde(default)]
+    pub reputation: i32,
+}
+
+/// Stored as `circuit:{id}`
+impl crate::Versioned for CircuitInfo {
+    const VERSION: u32 = 1;
 }
 
 impl CircuitInfo {
-    pub fn new(circuit_id: String) -> Self {
+    /// Lowest possible reputation score
+    pub const REPUTATION_MIN: i32 = -100;
+    /// Highest possible reputation score
+    pub const REPUTATION_MAX: i32 = 100;
+
+    pub fn new(circuit_id: CircuitId) -> Self {
         let now = chrono::Utc::now().timestamp();
         Self {
             circuit_id,
@@ -163,9 +424,19 @@ impl CircuitInfo {
             last_seen: now,
             passport_token: None,
             passport_expires: None,
+            reputation: 0,
         }
     }
 
+    /// Adjust the reputation score, clamping to [REPUTATION_MIN, REPUTATION_MAX]
+    pub fn adjust_reputation(&mut self, delta: i32) -> i32 {
+        self.reputation = self
+            .reputation
+            .saturating_add(delta)
+            .clamp(Self::REPUTATION_MIN, Self::REPUTATION_MAX);
+        self.reputation
+    }
+
     /// Check if the passport is currently valid
     pub fn has_valid_passport(&self) -> bool {
         match (self.passport_token.as_ref(), self.passport_expires) {
@@ -190,9 +461,10 @@ impl CircuitInfo {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CaptchaChallenge {
     /// Unique challenge ID
-    pub challenge_id: String,
+    pub challenge_id: ChallengeId,
 
-    /// Base64-encoded PNG image
+    /// Image as a `data:` URI, or the path it is served from
+    /// (`captcha.serve_images`)
     pub image_data: String,
 
     /// Grid dimensions (cols, rows)
@@ -215,9 +487,13 @@ pub struct CaptchaResult {
     pub success: bool,
     pub remaining_challenges: u8,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub passport_token: Option<String>,
+    pub passport_token: Option<PassportToken>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error_message: Option<String>,
+    /// Milliseconds between challenge issuance and a successful answer
+    /// (server-side only, not sent to client)
+    #[serde(skip)]
+    pub solve_time_ms: Option<u64>,
 }
 
 /// Cluster node state
@@ -266,3 +542,44 @@ pub struct MetricsSnapshot {
     /// Current threat dial level
     pub threat_level: u8,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identifiers_validated() {
+        let circuit: CircuitId = "fc00:dead:beef:4dad::0:2a".parse().unwrap();
+        assert_eq!(circuit, "fc00:dead:beef:4dad::0:2a");
+        assert!("".parse::<CircuitId>().is_err());
+        assert!("two words".parse::<CircuitId>().is_err());
+        assert!("x".repeat(129).parse::<CircuitId>().is_err());
+
+        assert!("s2.0.1700000000.abc.def.ghi".parse::<ChallengeId>().is_ok());
+        assert!("abc:def".parse::<ChallengeId>().is_err());
+        assert!("abc/def".parse::<PassportToken>().is_err());
+
+        // serde round-trips the raw string and rejects bad values
+        let token: PassportToken = serde_json::from_str(r#""aGVsbG8_-w""#).unwrap();
+        assert_eq!(serde_json::to_string(&token).unwrap(), r#""aGVsbG8_-w""#);
+        assert!(serde_json::from_str::<PassportToken>(r#""a b""#).is_err());
+    }
+
+    #[test]
+    fn test_threat_level_steps_and_bands() {
+        let level = ThreatLevel::new(8);
+        assert_eq!(level.escalate(1).value(), 9);
+        assert!(level.escalate(200).is_lockdown());
+        assert_eq!(level.deescalate(3).band(), ThreatBand::Standard);
+        assert_eq!(level.deescalate(20), ThreatLevel::MIN);
+        assert_eq!(ThreatLevel::MIN.band(), ThreatBand::Off);
+        assert_eq!(ThreatLevel::new(3).band(), ThreatBand::Light);
+        assert_eq!(level.band(), ThreatBand::High);
+
+        assert_eq!(" 7".parse::<ThreatLevel>().unwrap(), ThreatLevel::new(7));
+        assert!("11".parse::<ThreatLevel>().is_err());
+        assert!("high".parse::<ThreatLevel>().is_err());
+        assert_eq!(ThreatLevel::MAX.to_string(), "10");
+        assert_eq!(ThreatBand::Lockdown.to_string(), "lockdown");
+    }
+}
@@ -0,0 +1,141 @@
+//! Schema versions for records kept in Redis.
+//!
+//! Records outlive the process that wrote them: during a rolling deploy,
+//! or after an upgrade, a node reads circuits, challenges and passports
+//! written by another build. Each such type implements `Versioned`, and is
+//! written with `encode` as its JSON object plus a `"_v"` field holding the
+//! schema version:
+//!
+//! ```json
+//! {"_v": 1, "circuit_id": "...", "status": "new", ...}
+//! ```
+//!
+//! `decode` reads it back:
+//! - records without `"_v"` (written before versioning) are version 0;
+//! - older records are brought up to date by `Versioned::migrate`, one
+//!   version at a time;
+//! - records from a newer build are read as they are: fields this build
+//!   does not know are ignored, so additive changes need no coordination.
+//!
+//! Keeping the tag inside the object (rather than wrapping the payload)
+//! lets builds from before versioning read new records too, since serde
+//! ignores the unknown `"_v"` field.
+
+use serde::Serialize;
+use serde::de::{DeserializeOwned, Error as _};
+use serde_json::{Map, Value};
+
+/// Field holding the schema version
+pub const VERSION_FIELD: &str = "_v";
+
+/// A record with a schema version
+pub trait Versioned: Serialize + DeserializeOwned {
+    /// Version this build writes
+    const VERSION: u32;
+
+    /// Upgrade a record written at version `from` to `from + 1`
+    ///
+    /// The default leaves the record as it is (enough when the newer
+    /// version only adds fields with serde defaults).
+    fn migrate(from: u32, record: Map<String, Value>) -> serde_json::Result<Map<String, Value>> {
+        let _ = from;
+        Ok(record)
+    }
+}
+
+/// Serialize `record` as JSON, tagged with its version
+pub fn encode<T: Versioned>(record: &T) -> serde_json::Result<String> {
+    let Value::Object(mut object) = serde_json::to_value(record)? else {
+        return Err(serde_json::Error::custom(
+            "versioned record is not a JSON object",
+        ));
+    };
+    object.insert(VERSION_FIELD.to_string(), T::VERSION.into());
+    serde_json::to_string(&object)
+}
+
+/// Parse a record written by `encode` (at any version) or before versioning
+pub fn decode<T: Versioned>(json: &str) -> serde_json::Result<T> {
+    let Value::Object(mut object) = serde_json::from_str(json)? else {
+        return Err(serde_json::Error::custom(
+            "versioned record is not a JSON object",
+        ));
+    };
+    let version = match object.remove(VERSION_FIELD) {
+        None => 0,
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| serde_json::Error::custom(format!("invalid version {}", v)))?,
+    };
+    for from in version..T::VERSION {
+        object = T::migrate(from, object)?;
+    }
+    serde_json::from_value(Value::Object(object))
+}
+
+/// Schema version of an encoded record (0 if untagged)
+pub fn version_of(json: &str) -> Option<u32> {
+    let value: Value = serde_json::from_str(json).ok()?;
+    match value.as_object()?.get(VERSION_FIELD) {
+        None => Some(0),
+        Some(v) => v.as_u64().and_then(|v| u32::try_from(v).ok()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    /// Version 2 renamed `count` to `hits`
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Record {
+        name: String,
+        hits: u32,
+        #[serde(default)]
+        note: Option<String>,
+    }
+
+    impl Versioned for Record {
+        const VERSION: u32 = 2;
+
+        fn migrate(
+            from: u32,
+            mut record: Map<String, Value>,
+        ) -> serde_json::Result<Map<String, Value>> {
+            if from == 1
+                && let Some(count) = record.remove("count")
+            {
+                record.insert("hits".into(), count);
+            }
+            Ok(record)
+        }
+    }
+
+    #[test]
+    fn test_round_trip_and_migration() {
+        let record = Record {
+            name: "a".into(),
+            hits: 3,
+            note: None,
+        };
+        let json = encode(&record).unwrap();
+        assert_eq!(version_of(&json), Some(2));
+        assert_eq!(decode::<Record>(&json).unwrap(), record);
+
+        // Untagged and version 1 records go through the shims
+        let legacy = r#"{"name": "a", "count": 3}"#;
+        assert_eq!(version_of(legacy), Some(0));
+        assert_eq!(decode::<Record>(legacy).unwrap(), record);
+        let v1 = r#"{"_v": 1, "name": "a", "count": 3}"#;
+        assert_eq!(decode::<Record>(v1).unwrap(), record);
+
+        // A newer build's extra fields are ignored
+        let newer = r#"{"_v": 3, "name": "a", "hits": 3, "tags": ["x"]}"#;
+        assert_eq!(decode::<Record>(newer).unwrap(), record);
+
+        assert!(decode::<Record>(r#"{"_v": "two", "name": "a", "hits": 3}"#).is_err());
+        assert!(decode::<Record>("[1, 2]").is_err());
+    }
+}
@@ -0,0 +1,60 @@
+// Gate proof-of-work (progressive challenge flow).
+//
+// Finds a nonce whose SHA-256("{seed}:{nonce}") starts with data-bits zero
+// bits and posts it to /gate/pow, which lets the visitor through or serves
+// an easier challenge. Without JavaScript (or WebCrypto) nothing happens and
+// the visual challenge below is used as usual.
+(function () {
+    "use strict";
+
+    var form = document.getElementById("pow");
+    var status = document.getElementById("pow-status");
+    if (!form || !status || !window.crypto || !window.crypto.subtle || !window.TextEncoder) {
+        return;
+    }
+
+    var seed = form.elements.seed.value;
+    var bits = parseInt(form.getAttribute("data-bits"), 10);
+    var encoder = new TextEncoder();
+
+    function leadingZeroBits(bytes) {
+        var count = 0;
+        for (var i = 0; i < bytes.length; i++) {
+            if (bytes[i] === 0) {
+                count += 8;
+                continue;
+            }
+            return count + Math.clz32(bytes[i]) - 24;
+        }
+        return count;
+    }
+
+    async function solve() {
+        // Hash in batches so the page stays responsive
+        for (var nonce = 0; nonce < Number.MAX_SAFE_INTEGER; nonce++) {
+            var digest = await window.crypto.subtle.digest(
+                "SHA-256",
+                encoder.encode(seed + ":" + nonce)
+            );
+            if (leadingZeroBits(new Uint8Array(digest)) >= bits) {
+                return nonce;
+            }
+            if (nonce % 2000 === 1999) {
+                await new Promise(function (resolve) { setTimeout(resolve, 0); });
+            }
+        }
+        return null;
+    }
+
+    status.hidden = false;
+    solve().then(function (nonce) {
+        if (nonce === null) {
+            status.hidden = true;
+            return;
+        }
+        form.elements.nonce.value = String(nonce);
+        form.submit();
+    }, function () {
+        status.hidden = true;
+    });
+})();
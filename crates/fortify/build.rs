@@ -0,0 +1,33 @@
+//! Stamps the build with its git commit and compiler (`/admin/info`).
+
+use std::process::Command;
+
+/// First line of a command's output ("unknown" if it can't run)
+fn output_of(program: &str, args: &[&str]) -> String {
+    Command::new(program)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .and_then(|out| String::from_utf8(out.stdout).ok())
+        .and_then(|out| out.lines().next().map(|line| line.trim().to_string()))
+        .filter(|line| !line.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn main() {
+    // Source tarballs have no .git; the hash is then "unknown"
+    println!("cargo:rerun-if-changed=../../.git/HEAD");
+    println!("cargo:rerun-if-changed=../../.git/refs/heads");
+    println!("cargo:rerun-if-env-changed=FORTIFY_GIT_HASH");
+
+    let git_hash = std::env::var("FORTIFY_GIT_HASH")
+        .unwrap_or_else(|_| output_of("git", &["rev-parse", "--short=12", "HEAD"]));
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+
+    println!("cargo:rustc-env=FORTIFY_GIT_HASH={}", git_hash);
+    println!(
+        "cargo:rustc-env=FORTIFY_RUSTC_VERSION={}",
+        output_of(&rustc, &["--version"])
+    );
+}
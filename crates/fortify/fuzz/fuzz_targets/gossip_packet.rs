@@ -0,0 +1,10 @@
+//! Fuzz gossip datagram decoding (`GossipPacket::decode`).
+
+#![no_main]
+
+use fortify::cluster::GossipPacket;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = GossipPacket::decode(data);
+});
@@ -0,0 +1,38 @@
+//! Fuzz `PassportService::validate` with arbitrary client-supplied tokens.
+
+#![no_main]
+
+use fortify::cluster::{PassportConfig, PassportService};
+use libfuzzer_sys::fuzz_target;
+use std::sync::OnceLock;
+
+struct Harness {
+    runtime: tokio::runtime::Runtime,
+    service: PassportService,
+}
+
+fn harness() -> &'static Harness {
+    static HARNESS: OnceLock<Harness> = OnceLock::new();
+    HARNESS.get_or_init(|| {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let service = PassportService::new(PassportConfig {
+            node_id: "node-1".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let pubkey = service.public_key_b64().unwrap();
+        runtime
+            .block_on(service.add_peer_key("node-1", &pubkey))
+            .unwrap();
+        Harness { runtime, service }
+    })
+}
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(token) = std::str::from_utf8(data) {
+        let harness = harness();
+        let _ = harness.runtime.block_on(harness.service.validate(token, None));
+    }
+});
@@ -0,0 +1,12 @@
+//! Fuzz HAProxy `show table` line parsing (`StickTableEntry::parse`).
+
+#![no_main]
+
+use fortify::haproxy::StickTableEntry;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(line) = std::str::from_utf8(data) {
+        let _ = StickTableEntry::parse(line);
+    }
+});
@@ -0,0 +1,408 @@
+//! Abandoned challenges: issued, then never answered.
+//!
+//! A visitor who gives up on a challenge leaves no trace: the challenge just
+//! expires. So every stored challenge is also listed in
+//! `cerberus:pending_challenges` (scored by expiry) until it is answered or
+//! refreshed, and `abandonment_worker` sweeps out the entries past their
+//! expiry every `sweep_interval_secs`. Entries are popped atomically, so
+//! each is counted once however many nodes sweep. Each one counts:
+//! - globally, in per-minute tallies kept next to the challenges issued;
+//!   the share abandoned over `window_secs` is
+//!   `fortify_challenge_abandonment_ratio`
+//! - per circuit, in `abandoned:{circuit_id}` (shown by `/circuit/{id}`)
+//!   and the circuit's history
+//!
+//! A high ratio means visitors are being driven away. It can also mean bots
+//! fetching challenges they never meant to solve, so acting on it is opt-in:
+//! with `deescalate`, a ratio of at least `deescalate_ratio` held for
+//! `sustain_secs` lowers the threat level by `deescalate_by`, never below
+//! `min_threat_level`, and again after each further `sustain_secs`. In a
+//! cluster only the leader judges, by proposing the step to itself, so an
+//! escalation proposed in the same round wins.
+
+use anyhow::Result;
+use cerberus_common::{ChallengeId, CircuitId, redis_keys};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+
+use crate::circuits::{CircuitEvent, CircuitEventKind, EventLog};
+use crate::cluster::Proposal;
+use crate::metrics;
+use crate::state::AppState;
+
+/// Width of a tally bucket
+const BUCKET_SECS: i64 = 60;
+
+/// Expired entries popped at a time
+const SWEEP_BATCH: usize = 500;
+
+/// Abandonment tracking (`[abandonment]` in fortify.toml)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AbandonmentConfig {
+    pub enabled: bool,
+    /// How often expired challenges are swept
+    pub sweep_interval_secs: u64,
+    /// Period the abandonment ratio is taken over
+    pub window_secs: u64,
+    /// Challenges issued in the window before the ratio is reported
+    pub min_issued: u64,
+    /// Lower the threat level while abandonment stays high
+    pub deescalate: bool,
+    /// Ratio (0.0-1.0) that counts as high
+    pub deescalate_ratio: f64,
+    /// How long it must stay high before each step down
+    pub sustain_secs: u64,
+    /// Threat levels to step down by
+    pub deescalate_by: u8,
+    /// Lowest threat level reached this way
+    pub min_threat_level: u8,
+}
+
+impl Default for AbandonmentConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            sweep_interval_secs: 15,
+            window_secs: 900,
+            min_issued: 100,
+            deescalate: false,
+            deescalate_ratio: 0.6,
+            sustain_secs: 600,
+            deescalate_by: 1,
+            min_threat_level: 3,
+        }
+    }
+}
+
+/// Lists pending challenges and counts the abandoned ones
+pub struct AbandonmentTracker {
+    config: AbandonmentConfig,
+}
+
+impl AbandonmentTracker {
+    pub fn new(config: AbandonmentConfig) -> Self {
+        Self { config }
+    }
+
+    /// Add a new stored challenge to the pending set and the tallies
+    pub fn queue_issued(
+        &self,
+        pipe: &mut redis::Pipeline,
+        challenge_id: &ChallengeId,
+        circuit_id: Option<&CircuitId>,
+        now: i64,
+        expires_at: i64,
+    ) {
+        if !self.config.enabled {
+            return;
+        }
+        self.queue_pending(pipe, challenge_id, circuit_id, expires_at);
+        self.queue_tally(pipe, now, "issued", 1);
+    }
+
+    /// Add a stored challenge back to the pending set (not counted again)
+    pub fn queue_pending(
+        &self,
+        pipe: &mut redis::Pipeline,
+        challenge_id: &ChallengeId,
+        circuit_id: Option<&CircuitId>,
+        expires_at: i64,
+    ) {
+        if !self.config.enabled {
+            return;
+        }
+        pipe.zadd(
+            redis_keys::pending_challenges(),
+            pending_member(challenge_id, circuit_id),
+            expires_at,
+        )
+        .ignore();
+    }
+
+    fn queue_tally(&self, pipe: &mut redis::Pipeline, now: i64, field: &str, count: u64) {
+        let key = redis_keys::challenge_tally(now / BUCKET_SECS);
+        pipe.hincr(&key, field, count)
+            .ignore()
+            .expire(&key, self.config.window_secs as i64 + BUCKET_SECS)
+            .ignore();
+    }
+
+    /// Pop the challenges that expired unanswered and count them
+    pub async fn sweep(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        events: &EventLog,
+        now: i64,
+    ) -> Result<u64> {
+        let mut swept = 0;
+        loop {
+            let expired: Vec<String> = sweep_script()
+                .key(redis_keys::pending_challenges())
+                .arg(now)
+                .arg(SWEEP_BATCH)
+                .invoke_async(redis)
+                .await?;
+            if expired.is_empty() {
+                break;
+            }
+
+            let mut pipe = redis::pipe();
+            self.queue_tally(&mut pipe, now, "abandoned", expired.len() as u64);
+            for member in &expired {
+                let (challenge_id, Some(circuit_id)) = parse_member(member) else {
+                    continue;
+                };
+                let key = redis_keys::abandoned(&circuit_id);
+                let ttl = key
+                    .ttl()
+                    .secs()
+                    .expect("abandoned counters have a fixed TTL");
+                pipe.incr(&key, 1)
+                    .ignore()
+                    .expire(&key, ttl as i64)
+                    .ignore();
+                let event =
+                    CircuitEvent::new(CircuitEventKind::Abandoned).with_detail(challenge_id);
+                events.queue(&mut pipe, &circuit_id, &event);
+            }
+            pipe.query_async::<()>(redis).await?;
+
+            swept += expired.len() as u64;
+            if expired.len() < SWEEP_BATCH {
+                break;
+            }
+        }
+        metrics::CHALLENGES_ABANDONED.inc_by(swept);
+        Ok(swept)
+    }
+
+    /// Share of the challenges issued over the window that were abandoned
+    ///
+    /// `None` until `min_issued` challenges were issued in the window.
+    pub async fn ratio(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        now: i64,
+    ) -> Result<Option<f64>> {
+        let current = now / BUCKET_SECS;
+        let buckets = (self.config.window_secs as i64 / BUCKET_SECS).max(1);
+        let mut pipe = redis::pipe();
+        for bucket in current - buckets + 1..=current {
+            pipe.hget(
+                redis_keys::challenge_tally(bucket),
+                &["issued", "abandoned"],
+            );
+        }
+        let tallies: Vec<(Option<u64>, Option<u64>)> = pipe.query_async(redis).await?;
+        Ok(abandonment_ratio(&tallies, self.config.min_issued))
+    }
+
+    /// Challenges `circuit_id` has abandoned lately
+    pub async fn abandoned_by(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        circuit_id: &CircuitId,
+    ) -> Result<u64> {
+        let count: Option<u64> = redis.get(redis_keys::abandoned(circuit_id)).await?;
+        Ok(count.unwrap_or(0))
+    }
+}
+
+/// Entry of a challenge in the pending set
+pub(crate) fn pending_member(challenge_id: &ChallengeId, circuit_id: Option<&CircuitId>) -> String {
+    match circuit_id {
+        Some(circuit_id) => format!("{} {}", challenge_id, circuit_id),
+        None => challenge_id.to_string(),
+    }
+}
+
+/// Challenge ID and circuit of a pending set entry
+fn parse_member(member: &str) -> (&str, Option<CircuitId>) {
+    match member.split_once(' ') {
+        Some((challenge_id, circuit_id)) => (challenge_id, circuit_id.parse().ok()),
+        None => (member, None),
+    }
+}
+
+/// Abandoned over issued across `(issued, abandoned)` tallies
+fn abandonment_ratio(tallies: &[(Option<u64>, Option<u64>)], min_issued: u64) -> Option<f64> {
+    let (issued, abandoned) = tallies.iter().fold((0, 0), |(issued, abandoned), (i, a)| {
+        (issued + i.unwrap_or(0), abandoned + a.unwrap_or(0))
+    });
+    // Abandoned challenges were issued up to a challenge TTL before the window
+    (issued > 0 && issued >= min_issued).then(|| (abandoned as f64 / issued as f64).min(1.0))
+}
+
+/// How long abandonment has stayed high
+#[derive(Debug, Default)]
+struct Sustained {
+    high_since: Option<Instant>,
+}
+
+impl Sustained {
+    /// Note a reading; true once it has been high for `sustain`, after
+    /// which the clock starts over
+    fn observe(&mut self, high: bool, sustain: Duration, now: Instant) -> bool {
+        if !high {
+            self.high_since = None;
+            return false;
+        }
+        let since = *self.high_since.get_or_insert(now);
+        if now.duration_since(since) >= sustain {
+            self.high_since = Some(now);
+            return true;
+        }
+        false
+    }
+}
+
+/// Sweep abandoned challenges (and de-escalate, if configured) until shutdown
+pub async fn abandonment_worker(state: AppState, mut shutdown: broadcast::Receiver<()>) {
+    let config = state.config.abandonment.clone();
+    if !config.enabled {
+        return;
+    }
+    let interval = Duration::from_secs(config.sweep_interval_secs);
+    let mut sustained = Sustained::default();
+
+    loop {
+        if let Err(e) = round(&state, &mut sustained).await {
+            tracing::warn!(error = %e, "Abandoned challenge sweep failed");
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = shutdown.recv() => break,
+        }
+    }
+    tracing::info!("Abandonment tracking shutting down");
+}
+
+async fn round(state: &AppState, sustained: &mut Sustained) -> Result<()> {
+    let Some(mut redis) = state.redis() else {
+        return Ok(());
+    };
+    let config = &state.config.abandonment;
+    let now = chrono::Utc::now().timestamp();
+
+    let swept = state
+        .abandonment
+        .sweep(&mut redis, state.circuit_tracker.events(), now)
+        .await?;
+    if swept > 0 {
+        tracing::debug!(swept, "Swept abandoned challenges");
+    }
+
+    let Some(ratio) = state.abandonment.ratio(&mut redis, now).await? else {
+        sustained.observe(false, Duration::ZERO, Instant::now());
+        return Ok(());
+    };
+    metrics::CHALLENGE_ABANDONMENT_RATIO.set(ratio);
+
+    // In a cluster the leader judges for everyone
+    let judging = state.election.as_ref().is_none_or(|e| e.is_leader());
+    let high = config.deescalate && judging && ratio >= config.deescalate_ratio;
+    if sustained.observe(
+        high,
+        Duration::from_secs(config.sustain_secs),
+        Instant::now(),
+    ) {
+        step_down(state, &mut redis, ratio).await?;
+    }
+    Ok(())
+}
+
+/// Lower the threat level a step, as abandonment has stayed high
+async fn step_down(
+    state: &AppState,
+    redis: &mut redis::aio::ConnectionManager,
+    ratio: f64,
+) -> Result<()> {
+    let config = &state.config.abandonment;
+    let current = state.get_threat_level().await;
+    let by = current
+        .value()
+        .saturating_sub(config.min_threat_level)
+        .min(config.deescalate_by);
+    if by == 0 {
+        return Ok(());
+    }
+
+    let reason = format!(
+        "abandonment: {:.0}% of challenges unanswered",
+        ratio * 100.0
+    );
+    tracing::warn!(
+        ratio,
+        from = current.value(),
+        by,
+        "Challenges keep going unanswered, de-escalating"
+    );
+    match state.election {
+        Some(ref election) => {
+            election
+                .propose(redis, &Proposal::Deescalate { by, reason })
+                .await
+        }
+        None => state.set_threat_level(current.deescalate(by)).await,
+    }
+}
+
+/// Pop up to `ARGV[2]` members of a sorted set scored at most `ARGV[1]`
+fn sweep_script() -> redis::Script {
+    redis::Script::new(
+        r"
+        local members = redis.call('ZRANGEBYSCORE', KEYS[1], '-inf', ARGV[1], 'LIMIT', 0, ARGV[2])
+        if #members > 0 then redis.call('ZREM', KEYS[1], unpack(members)) end
+        return members
+        ",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pending_members() {
+        let challenge_id: ChallengeId = "q4vNaTb0Xy7cU2Rk".parse().unwrap();
+        let circuit_id: CircuitId = "fc00::1".parse().unwrap();
+
+        let member = pending_member(&challenge_id, Some(&circuit_id));
+        assert_eq!(member, "q4vNaTb0Xy7cU2Rk fc00::1");
+        assert_eq!(
+            parse_member(&member),
+            ("q4vNaTb0Xy7cU2Rk", Some(circuit_id))
+        );
+
+        let member = pending_member(&challenge_id, None);
+        assert_eq!(parse_member(&member), ("q4vNaTb0Xy7cU2Rk", None));
+    }
+
+    #[test]
+    fn test_abandonment_ratio() {
+        let tallies = [(Some(60), Some(10)), (None, None), (Some(40), Some(15))];
+        assert_eq!(abandonment_ratio(&tallies, 100), Some(0.25));
+        assert_eq!(abandonment_ratio(&tallies, 101), None);
+        assert_eq!(abandonment_ratio(&[(None, Some(3))], 0), None);
+    }
+
+    #[test]
+    fn test_sustained_high_abandonment() {
+        let sustain = Duration::from_secs(600);
+        let start = Instant::now();
+        let mut sustained = Sustained::default();
+
+        assert!(!sustained.observe(true, sustain, start));
+        assert!(!sustained.observe(true, sustain, start + Duration::from_secs(300)));
+        assert!(sustained.observe(true, sustain, start + sustain));
+        // The next step needs another full period
+        assert!(!sustained.observe(true, sustain, start + sustain + Duration::from_secs(60)));
+
+        // A dip starts it over
+        assert!(!sustained.observe(false, sustain, start + 2 * sustain));
+        assert!(!sustained.observe(true, sustain, start + 2 * sustain + Duration::from_secs(1)));
+    }
+}
@@ -0,0 +1,435 @@
+//! Access log: one JSON line per visitor request.
+//!
+//! Kept apart from tracing so it can be shipped and parsed on its own. Each
+//! line has the time, method, path, circuit, status, the decision made
+//! (allowed, challenged, denied or error), latency and the threat level when
+//! the request came in. Admin requests aren't logged. The circuit is
+//! written as the `[privacy]` policy allows.
+//!
+//! Requests only queue their entry; a worker writes them to a file rotated
+//! by size and, optionally, to a capped Redis stream the dashboard can tail.
+//! When the queue is full, entries are dropped (and counted) rather than
+//! slowing requests down.
+
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+use cerberus_common::constants::headers;
+use cerberus_common::redis_keys;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tokio::sync::{Mutex, MutexGuard, broadcast, mpsc};
+
+use crate::enforcement::Action;
+use crate::metrics;
+use crate::redact;
+use crate::sampling;
+use crate::state::AppState;
+
+/// Access log settings (`[access_log]` in fortify.toml)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessLogConfig {
+    pub enabled: bool,
+    /// File written to ("" = no file)
+    pub path: String,
+    /// Size at which the file is rotated, in MiB (0 = never)
+    pub max_size_mb: u64,
+    /// Rotated files kept (`access.log.1` is the newest)
+    pub max_files: usize,
+    /// Also append entries to the `cerberus:access_log` Redis stream
+    pub redis_stream: bool,
+    /// Entries the stream keeps (approximately; older ones are trimmed)
+    pub stream_max_len: usize,
+    /// Entries waiting to be written at most; beyond this they're dropped
+    pub queue_capacity: usize,
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: "/var/log/cerberus/access.log".to_string(),
+            max_size_mb: 100,
+            max_files: 5,
+            redis_stream: false,
+            stream_max_len: 10_000,
+            queue_capacity: 10_000,
+        }
+    }
+}
+
+/// Gate pages and API calls that answer with a challenge
+const CHALLENGE_PATHS: [&str; 6] = [
+    "/",
+    "/captcha.html",
+    "/gate/fragment",
+    "/gate/pow",
+    "/challenge",
+    "/verify",
+];
+
+/// Entries written per batch at most
+const BATCH_SIZE: usize = 256;
+
+/// What was done with a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Decision {
+    /// Let through (a valid passport, a solved challenge, a static asset)
+    Allowed,
+    /// Sent, or served, a challenge
+    Challenged,
+    /// Refused: blocked, banned or rate limited
+    Denied,
+    /// Failed on our side
+    Error,
+}
+
+impl Decision {
+    /// The decision behind a response to `path`
+    ///
+    /// A challenge page is 200; a solved one redirects, so it's allowed.
+    fn classify(path: &str, status: StatusCode) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED => Self::Challenged,
+            StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS => Self::Denied,
+            status if status.is_server_error() => Self::Error,
+            status
+                if status.is_success() && (CHALLENGE_PATHS.contains(&path) || is_refresh(path)) =>
+            {
+                Self::Challenged
+            }
+            _ => Self::Allowed,
+        }
+    }
+}
+
+/// `/challenge/{id}/refresh`
+fn is_refresh(path: &str) -> bool {
+    path.strip_prefix("/challenge/")
+        .is_some_and(|rest| rest.ends_with("/refresh"))
+}
+
+/// One logged request
+#[derive(Debug, Clone, Serialize)]
+pub struct AccessLogEntry {
+    /// RFC 3339, in milliseconds
+    pub timestamp: String,
+    pub method: String,
+    /// Path requested of Fortify, without the query string
+    pub path: String,
+    /// Path of the proxied request (`X-Original-URI`), for `/validate`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub original_path: Option<String>,
+    /// Circuit, redacted per `[privacy]`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub circuit_id: Option<String>,
+    pub status: u16,
+    pub decision: Decision,
+    /// What would have been done, had observe-only mode not let it through
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub observed: Option<&'static str>,
+    /// Time until the response was ready
+    pub latency_ms: f64,
+    /// Threat level when the request came in
+    pub threat_level: u8,
+}
+
+/// Queue of entries waiting for the worker
+pub struct AccessLog {
+    sender: mpsc::Sender<AccessLogEntry>,
+    /// Held by the worker while it runs (and kept if it panics)
+    receiver: Mutex<mpsc::Receiver<AccessLogEntry>>,
+}
+
+impl AccessLog {
+    pub fn new(config: &AccessLogConfig) -> Self {
+        let (sender, receiver) = mpsc::channel(config.queue_capacity.max(1));
+        Self {
+            sender,
+            receiver: Mutex::new(receiver),
+        }
+    }
+
+    /// Queue an entry, dropping it if the queue is full
+    pub fn push(&self, entry: AccessLogEntry) {
+        if self.sender.try_send(entry).is_err() {
+            metrics::ACCESS_LOG_DROPPED.inc();
+        }
+    }
+
+    /// The queue, unless a worker already holds it
+    fn receiver(&self) -> Option<MutexGuard<'_, mpsc::Receiver<AccessLogEntry>>> {
+        self.receiver.try_lock().ok()
+    }
+}
+
+/// Middleware logging every request it sees
+pub async fn log_requests(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let started = Instant::now();
+    let threat_level = state.get_threat_level().await.value();
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    let original_path = request
+        .headers()
+        .get(headers::X_ORIGINAL_URI)
+        .and_then(|v| v.to_str().ok())
+        .map(|uri| uri.split('?').next().unwrap_or_default().to_string());
+    let circuit_id =
+        sampling::circuit_id(&request).map(|id| redact::policy().identifier(id.as_str()));
+
+    let response = next.run(request).await;
+    let status = response.status();
+    state.access_log.push(AccessLogEntry {
+        timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        method,
+        decision: Decision::classify(&path, status),
+        path,
+        original_path,
+        circuit_id,
+        status: status.as_u16(),
+        observed: response.extensions().get::<Action>().map(|a| a.as_str()),
+        latency_ms: started.elapsed().as_secs_f64() * 1000.0,
+        threat_level,
+    });
+    response
+}
+
+/// Write queued entries to the file and stream until shutdown
+pub async fn access_log_worker(state: AppState, mut shutdown: broadcast::Receiver<()>) {
+    let config = state.config.access_log.clone();
+    if !config.enabled {
+        return;
+    }
+    let Some(mut entries) = state.access_log.receiver() else {
+        return;
+    };
+
+    let mut file = if config.path.is_empty() {
+        None
+    } else {
+        match RotatingFile::open(&config.path, config.max_size_mb << 20, config.max_files) {
+            Ok(file) => Some(file),
+            Err(e) => {
+                tracing::error!(error = %e, path = %config.path, "Failed to open access log");
+                None
+            }
+        }
+    };
+    tracing::info!(path = %config.path, redis_stream = config.redis_stream, "Access log started");
+
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    loop {
+        tokio::select! {
+            received = entries.recv_many(&mut batch, BATCH_SIZE) => {
+                if received == 0 {
+                    break;
+                }
+            }
+            _ = shutdown.recv() => {
+                // Write out what's already queued
+                while batch.len() < BATCH_SIZE && let Ok(entry) = entries.try_recv() {
+                    batch.push(entry);
+                }
+                write_batch(&state, &config, file, &mut batch).await;
+                break;
+            }
+        }
+        file = write_batch(&state, &config, file, &mut batch).await;
+    }
+}
+
+/// Write a batch out, handing the file back for the next one
+async fn write_batch(
+    state: &AppState,
+    config: &AccessLogConfig,
+    file: Option<RotatingFile>,
+    batch: &mut Vec<AccessLogEntry>,
+) -> Option<RotatingFile> {
+    let lines: Vec<String> = batch
+        .drain(..)
+        .filter_map(|entry| serde_json::to_string(&entry).ok())
+        .collect();
+    if lines.is_empty() {
+        return file;
+    }
+
+    if config.redis_stream
+        && let Some(mut redis) = state.redis()
+    {
+        let key = redis_keys::access_log();
+        let mut pipe = redis::pipe();
+        for line in &lines {
+            pipe.cmd("XADD")
+                .arg(&key)
+                .arg("MAXLEN")
+                .arg("~")
+                .arg(config.stream_max_len)
+                .arg("*")
+                .arg("entry")
+                .arg(line)
+                .ignore();
+        }
+        if let Err(e) = pipe.query_async::<()>(&mut redis).await {
+            tracing::warn!(error = %e, "Failed to append to the access log stream");
+        }
+    }
+
+    let mut file = file?;
+    // File writes block; keep them off the runtime threads
+    let written = tokio::task::spawn_blocking(move || {
+        let result = file.write_lines(&lines);
+        (file, result)
+    })
+    .await;
+    match written {
+        Ok((file, Ok(()))) => Some(file),
+        Ok((file, Err(e))) => {
+            tracing::warn!(error = %e, "Failed to write the access log");
+            Some(file)
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "Access log writer panicked");
+            None
+        }
+    }
+}
+
+/// Append-only file, rotated by size (`access.log` -> `access.log.1` -> ...)
+struct RotatingFile {
+    path: PathBuf,
+    /// 0 = never rotate
+    max_bytes: u64,
+    /// Rotated files kept
+    max_files: usize,
+    writer: BufWriter<File>,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: impl AsRef<Path>, max_bytes: u64, max_files: usize) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_bytes,
+            max_files,
+            writer: BufWriter::new(file),
+            size,
+        })
+    }
+
+    /// Append `lines`, rotating first whenever one would go over the size
+    fn write_lines(&mut self, lines: &[String]) -> io::Result<()> {
+        for line in lines {
+            let len = line.len() as u64 + 1;
+            if self.max_bytes > 0 && self.size > 0 && self.size + len > self.max_bytes {
+                self.rotate()?;
+            }
+            self.writer.write_all(line.as_bytes())?;
+            self.writer.write_all(b"\n")?;
+            self.size += len;
+        }
+        self.writer.flush()
+    }
+
+    /// Shift the rotated files up one, dropping the oldest, and start afresh
+    fn rotate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        for n in (1..self.max_files).rev() {
+            match std::fs::rename(self.rotated(n), self.rotated(n + 1)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        if self.max_files > 0 {
+            std::fs::rename(&self.path, self.rotated(1))?;
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.writer = BufWriter::new(file);
+        self.size = 0;
+        Ok(())
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decisions() {
+        let cases = [
+            ("/validate", StatusCode::OK, Decision::Allowed),
+            ("/validate", StatusCode::UNAUTHORIZED, Decision::Challenged),
+            ("/validate", StatusCode::FORBIDDEN, Decision::Denied),
+            ("/verify", StatusCode::TOO_MANY_REQUESTS, Decision::Denied),
+            ("/", StatusCode::OK, Decision::Challenged),
+            (
+                "/challenge/abc/refresh",
+                StatusCode::OK,
+                Decision::Challenged,
+            ),
+            // A solved challenge redirects with its passport
+            ("/verify", StatusCode::SEE_OTHER, Decision::Allowed),
+            ("/gate/theme.css", StatusCode::OK, Decision::Allowed),
+            ("/", StatusCode::SERVICE_UNAVAILABLE, Decision::Error),
+        ];
+        for (path, status, expected) in cases {
+            assert_eq!(
+                Decision::classify(path, status),
+                expected,
+                "{} {}",
+                path,
+                status
+            );
+        }
+    }
+
+    #[test]
+    fn test_rotation_keeps_max_files() {
+        let dir = std::env::temp_dir().join(format!("cerberus-access-log-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("access.log");
+
+        // Two 9-byte lines fit in 20 bytes; the third starts a new file
+        let mut file = RotatingFile::open(&path, 20, 2).unwrap();
+        let lines: Vec<String> = (0..7).map(|i| format!("line-{:03}", i)).collect();
+        file.write_lines(&lines).unwrap();
+
+        let read = |p: &Path| std::fs::read_to_string(p).unwrap();
+        assert_eq!(read(&path), "line-006\n");
+        assert_eq!(read(&file.rotated(1)), "line-004\nline-005\n");
+        assert_eq!(read(&file.rotated(2)), "line-002\nline-003\n");
+        assert!(!file.rotated(3).exists());
+
+        // Reopening appends to the current file
+        drop(file);
+        let mut file = RotatingFile::open(&path, 20, 2).unwrap();
+        file.write_lines(&["line-007".to_string()]).unwrap();
+        assert_eq!(read(&path), "line-006\nline-007\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
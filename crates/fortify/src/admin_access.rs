@@ -0,0 +1,235 @@
+//! Where the admin routes are mounted, and who may reach them.
+//!
+//! `/admin` is the first thing anyone probing a service tries. In
+//! production the admin router can instead live under a secret path
+//! segment, either set in `[admin]` or generated on first start and kept
+//! in `secret_file` so it survives restarts. Access can further be limited
+//! to a list of source addresses and/or the unix socket listener; anyone
+//! else gets the same 404 as an unknown path.
+
+use anyhow::{Context, Result, bail};
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::trusted_proxy::{self, Peer};
+
+/// Mount point when nothing else is configured
+pub const DEFAULT_PATH: &str = "/admin";
+
+/// Random bytes in a generated path segment (base64url-encoded)
+const SECRET_BYTES: usize = 18;
+
+/// Admin router placement and access (`[admin]` in fortify.toml)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdminConfig {
+    /// Fixed mount point (e.g. "/k3x9/admin"); overrides `randomize`
+    pub path: Option<String>,
+    /// Mount under a generated secret segment, kept in `secret_file`
+    pub randomize: bool,
+    /// Where the generated segment is stored
+    pub secret_file: String,
+    /// Source IPs or CIDR ranges allowed in (empty = any address)
+    pub allowed_addresses: Vec<String>,
+    /// Allow connections on the unix socket listener
+    pub allow_unix_socket: bool,
+}
+
+impl Default for AdminConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            randomize: false,
+            secret_file: "/var/lib/cerberus/admin_path".to_string(),
+            allowed_addresses: Vec::new(),
+            allow_unix_socket: true,
+        }
+    }
+}
+
+/// Check a configured mount point: absolute, no trailing slash, not "/"
+pub fn check_path(path: &str) -> Result<()> {
+    if !path.starts_with('/') {
+        bail!("must start with '/'");
+    }
+    if path == "/" || path.ends_with('/') {
+        bail!("must not end with '/'");
+    }
+    if path.contains(['{', '}', '*', '?', '#']) {
+        bail!("must be a literal path");
+    }
+    Ok(())
+}
+
+/// Resolve the mount point, generating and storing a secret if needed
+pub fn mount_path(config: &AdminConfig) -> Result<String> {
+    if let Some(ref path) = config.path {
+        check_path(path).with_context(|| format!("Invalid admin.path `{}`", path))?;
+        return Ok(path.clone());
+    }
+    if !config.randomize {
+        return Ok(DEFAULT_PATH.to_string());
+    }
+    let file = Path::new(&config.secret_file);
+    let secret = match std::fs::read_to_string(file) {
+        Ok(stored) => stored.trim().to_string(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let secret = generate_secret();
+            write_secret(file, &secret)?;
+            tracing::info!(
+                file = %file.display(),
+                "🔑 Generated a secret admin path (read it from the file)"
+            );
+            secret
+        }
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read {}", file.display()));
+        }
+    };
+    if secret.is_empty() || !secret.chars().all(is_segment_char) {
+        bail!("{} does not hold a valid path segment", file.display());
+    }
+    Ok(format!("/{}{}", secret, DEFAULT_PATH))
+}
+
+fn is_segment_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+fn generate_secret() -> String {
+    use base64::Engine;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+
+    let mut bytes = [0u8; SECRET_BYTES];
+    rand::Rng::fill(&mut rand::rng(), &mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Store the secret readable by the owner only
+fn write_secret(file: &Path, secret: &str) -> Result<()> {
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut out = options
+        .open(file)
+        .with_context(|| format!("Failed to create {}", file.display()))?;
+    std::io::Write::write_all(&mut out, format!("{}\n", secret).as_bytes())
+        .with_context(|| format!("Failed to write {}", file.display()))
+}
+
+/// Parsed peer restriction
+#[derive(Debug)]
+pub struct AdminAccess {
+    networks: Vec<IpNet>,
+    unix_socket: bool,
+}
+
+impl AdminAccess {
+    /// `None` when any peer may connect (no addresses listed)
+    ///
+    /// Entries that don't parse are skipped (config validation reports them).
+    pub fn new(config: &AdminConfig) -> Option<Self> {
+        if config.allowed_addresses.is_empty() {
+            return None;
+        }
+        Some(Self {
+            networks: config
+                .allowed_addresses
+                .iter()
+                .filter_map(|s| trusted_proxy::parse_address(s))
+                .collect(),
+            unix_socket: config.allow_unix_socket,
+        })
+    }
+
+    /// Whether `peer` may use the admin routes (unknown peers may not)
+    pub fn allows(&self, peer: Option<Peer>) -> bool {
+        match peer {
+            Some(Peer::Tcp(ip)) => self.networks.iter().any(|net| net.contains(&ip)),
+            Some(Peer::Unix) => self.unix_socket,
+            None => false,
+        }
+    }
+}
+
+/// Answer disallowed peers as if there were nothing here
+pub async fn restrict(
+    State(access): State<Arc<AdminAccess>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let peer = trusted_proxy::peer_of(&request);
+    if !access.allows(peer) {
+        tracing::warn!(?peer, "Admin request from a disallowed peer");
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn test_mount_path() {
+        assert_eq!(mount_path(&AdminConfig::default()).unwrap(), DEFAULT_PATH);
+
+        let fixed = AdminConfig {
+            path: Some("/k3x9/admin".to_string()),
+            randomize: true,
+            ..Default::default()
+        };
+        assert_eq!(mount_path(&fixed).unwrap(), "/k3x9/admin");
+        assert!(check_path("admin").is_err());
+        assert!(check_path("/admin/").is_err());
+        assert!(check_path("/{id}").is_err());
+
+        // A generated secret is kept across restarts
+        let dir = std::env::temp_dir().join(format!("cerberus-admin-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = AdminConfig {
+            randomize: true,
+            secret_file: dir.join("admin_path").display().to_string(),
+            ..Default::default()
+        };
+        let first = mount_path(&config).unwrap();
+        assert_ne!(first, DEFAULT_PATH);
+        assert!(first.ends_with(DEFAULT_PATH));
+        assert_eq!(mount_path(&config).unwrap(), first);
+
+        std::fs::write(dir.join("admin_path"), "../etc\n").unwrap();
+        assert!(mount_path(&config).is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_allowed_peers() {
+        assert!(AdminAccess::new(&AdminConfig::default()).is_none());
+
+        let access = AdminAccess::new(&AdminConfig {
+            allowed_addresses: vec!["10.1.0.0/16".into(), "::1".into()],
+            allow_unix_socket: false,
+            ..Default::default()
+        })
+        .unwrap();
+        let tcp = |s: &str| Some(Peer::from(s.parse::<SocketAddr>().unwrap()));
+
+        assert!(access.allows(tcp("10.1.2.3:5000")));
+        assert!(access.allows(tcp("[::1]:5000")));
+        assert!(!access.allows(tcp("10.2.0.1:5000")));
+        assert!(!access.allows(Some(Peer::Unix)));
+        assert!(!access.allows(None));
+    }
+}
@@ -0,0 +1,208 @@
+//! First-party bot allowlist.
+//!
+//! The operator's own uptime monitors and crawlers skip the CAPTCHA by
+//! sending a secret in the `allowlist.header` request header. Secrets are
+//! issued by name through `/admin/allowlist`, shown once, and always
+//! expire. Only their SHA-256 digest is stored, in a Redis hash shared by
+//! the cluster, so a Redis dump can't be replayed as a secret.
+//!
+//! `/validate` answers 200 to a request carrying a live secret, without a
+//! passport and outside the circuit rate limits. Nothing is allowlisted
+//! while Redis is offline.
+
+use anyhow::Result;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use cerberus_common::redis_keys;
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// Allowlist settings (`[allowlist]` in fortify.toml)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AllowlistConfig {
+    /// Request header carrying the secret
+    pub header: String,
+    /// Lifetime of a secret issued without one
+    pub default_ttl_secs: u64,
+    /// Longest lifetime a secret may be issued with
+    pub max_ttl_secs: u64,
+}
+
+impl Default for AllowlistConfig {
+    fn default() -> Self {
+        Self {
+            header: "X-Cerberus-Allowlist".to_string(),
+            default_ttl_secs: 30 * 86400,
+            max_ttl_secs: 365 * 86400,
+        }
+    }
+}
+
+impl AllowlistConfig {
+    /// Lifetime for a secret requested with `ttl_secs`, if allowed
+    pub fn ttl(&self, ttl_secs: Option<u64>) -> Option<u64> {
+        let ttl = ttl_secs.unwrap_or(self.default_ttl_secs);
+        (ttl > 0 && ttl <= self.max_ttl_secs).then_some(ttl)
+    }
+}
+
+/// An allowlisted client
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AllowlistEntry {
+    pub name: String,
+    /// Unix seconds
+    pub created_at: i64,
+    /// Unix seconds
+    pub expires_at: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+impl AllowlistEntry {
+    fn is_expired(&self, now: i64) -> bool {
+        self.expires_at <= now
+    }
+}
+
+/// Names are shown in logs and metrics: short, no spaces
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Issue a new secret for `name`, replacing any it already had
+///
+/// Returns the secret, which is not stored and can't be shown again.
+pub async fn add(
+    redis: &mut ConnectionManager,
+    name: &str,
+    ttl_secs: u64,
+    note: Option<String>,
+) -> Result<(String, AllowlistEntry)> {
+    remove(redis, name).await?;
+
+    let mut bytes = [0u8; 32];
+    rand::Rng::fill(&mut rand::rng(), &mut bytes);
+    let secret = URL_SAFE_NO_PAD.encode(bytes);
+
+    let now = chrono::Utc::now().timestamp();
+    let entry = AllowlistEntry {
+        name: name.to_string(),
+        created_at: now,
+        expires_at: now.saturating_add(ttl_secs as i64),
+        note,
+    };
+    redis
+        .hset::<_, _, _, ()>(
+            redis_keys::allowlist(),
+            digest(&secret),
+            serde_json::to_string(&entry)?,
+        )
+        .await?;
+    Ok((secret, entry))
+}
+
+/// Live entries by name; expired ones are dropped on the way
+pub async fn list(redis: &mut ConnectionManager) -> Result<Vec<AllowlistEntry>> {
+    let now = chrono::Utc::now().timestamp();
+    let mut entries = Vec::new();
+    let mut expired = Vec::new();
+    for (digest, entry) in load(redis).await? {
+        match entry {
+            Some(entry) if !entry.is_expired(now) => entries.push(entry),
+            _ => expired.push(digest),
+        }
+    }
+    if !expired.is_empty() {
+        redis
+            .hdel::<_, _, ()>(redis_keys::allowlist(), expired)
+            .await?;
+    }
+
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+/// Revoke `name`'s secret; returns whether it had one
+pub async fn remove(redis: &mut ConnectionManager, name: &str) -> Result<bool> {
+    let digests: Vec<String> = load(redis)
+        .await?
+        .into_iter()
+        .filter(|(_, entry)| entry.as_ref().is_some_and(|e| e.name == name))
+        .map(|(digest, _)| digest)
+        .collect();
+    if digests.is_empty() {
+        return Ok(false);
+    }
+    redis
+        .hdel::<_, _, ()>(redis_keys::allowlist(), digests)
+        .await?;
+    Ok(true)
+}
+
+/// The live entry `secret` was issued for, if any
+pub async fn check(redis: &mut ConnectionManager, secret: &str) -> Result<Option<AllowlistEntry>> {
+    let now = chrono::Utc::now().timestamp();
+    let entry: Option<String> = redis.hget(redis_keys::allowlist(), digest(secret)).await?;
+    Ok(entry
+        .and_then(|entry| serde_json::from_str::<AllowlistEntry>(&entry).ok())
+        .filter(|entry| !entry.is_expired(now)))
+}
+
+/// Every entry by digest (`None` where unreadable)
+async fn load(redis: &mut ConnectionManager) -> Result<HashMap<String, Option<AllowlistEntry>>> {
+    let raw: HashMap<String, String> = redis.hgetall(redis_keys::allowlist()).await?;
+    Ok(raw
+        .into_iter()
+        .map(|(digest, entry)| (digest, serde_json::from_str(&entry).ok()))
+        .collect())
+}
+
+fn digest(secret: &str) -> String {
+    Sha256::digest(secret.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ttl_and_names() {
+        let config = AllowlistConfig::default();
+        assert_eq!(config.ttl(None), Some(config.default_ttl_secs));
+        assert_eq!(config.ttl(Some(3600)), Some(3600));
+        assert_eq!(config.ttl(Some(0)), None);
+        assert_eq!(config.ttl(Some(config.max_ttl_secs + 1)), None);
+
+        assert!(is_valid_name("uptime-kuma.eu_1"));
+        assert!(!is_valid_name(""));
+        assert!(!is_valid_name("my monitor"));
+        assert!(!is_valid_name(&"a".repeat(65)));
+    }
+
+    #[test]
+    fn test_entry_expiry_and_digest() {
+        let entry = AllowlistEntry {
+            name: "monitor".to_string(),
+            created_at: 100,
+            expires_at: 200,
+            note: None,
+        };
+        assert!(!entry.is_expired(199));
+        assert!(entry.is_expired(200));
+
+        assert_eq!(digest("secret"), digest("secret"));
+        assert_ne!(digest("secret"), digest("secret2"));
+        assert_eq!(digest("secret").len(), 64);
+    }
+}
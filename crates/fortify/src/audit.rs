@@ -0,0 +1,18 @@
+//! Audit log: every node event, as one structured log line.
+//!
+//! Events are logged under the `fortify::audit` target, so they can be
+//! routed or filtered separately (`RUST_LOG=fortify::audit=info`).
+
+use cerberus_common::{CerberusEvent, EventSubscriber};
+
+/// Logs each published event
+pub struct AuditLog;
+
+impl EventSubscriber for AuditLog {
+    fn on_event(&self, event: &CerberusEvent) {
+        let Ok(json) = serde_json::to_string(event) else {
+            return;
+        };
+        tracing::info!(target: "fortify::audit", kind = event.kind(), event = %json, "Audit event");
+    }
+}
@@ -0,0 +1,481 @@
+//! Backups of critical Redis state.
+//!
+//! A Redis wipe in the middle of an attack would forget every ban, VIP and
+//! allowlisted bot, and drop the threat level back to its default. Every
+//! `interval_secs` the backup task snapshots that state into a JSON file in
+//! `dir` (`cerberus-backup-{unix secs}.json`), keeping the newest `keep`:
+//! - the threat level
+//! - banned and VIP circuit records, with the lifetime they had left
+//! - the allowlist (secret digests only, as stored)
+//!
+//! A snapshot holding the same state as the last one written is skipped, so
+//! a quiet node doesn't rotate its useful backups away.
+//!
+//! Files are signed with HMAC-SHA256 under `secret`. `fortify restore <file>`
+//! refuses a file whose signature doesn't check out, then replays it into
+//! Redis without overwriting anything already there; records come back with
+//! the lifetime they had left, less the backup's age, and allowlist entries
+//! expired since are skipped.
+
+use anyhow::{Context, Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use cerberus_common::constants::redis_keys::CIRCUIT_PREFIX;
+use cerberus_common::{CircuitId, CircuitInfo, CircuitStatus, ThreatLevel, redis_keys, versioned};
+use hmac::{Hmac, Mac};
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::allowlist::AllowlistEntry;
+use crate::config::AppConfig;
+use crate::metrics;
+use crate::state::AppState;
+
+/// Version of the snapshot format written
+const FORMAT_VERSION: u32 = 1;
+
+const FILE_PREFIX: &str = "cerberus-backup-";
+const FILE_SUFFIX: &str = ".json";
+
+/// Backup settings (`[backup]` in fortify.toml)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BackupConfig {
+    pub enabled: bool,
+    /// Directory the backup files are written to
+    pub dir: String,
+    pub interval_secs: u64,
+    /// Newest files kept; older ones are deleted
+    pub keep: usize,
+    /// HMAC-SHA256 key signing the files (required when enabled, and to
+    /// restore)
+    pub secret: String,
+    /// SCAN `COUNT` hint while looking for circuit records
+    pub scan_count: usize,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dir: "/var/lib/cerberus/backups".to_string(),
+            interval_secs: 300,
+            keep: 48,
+            secret: String::new(),
+            scan_count: 1000,
+        }
+    }
+}
+
+/// A circuit record worth keeping
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CircuitRecord {
+    pub circuit_id: CircuitId,
+    pub status: CircuitStatus,
+    /// The record as stored (versioned `CircuitInfo`)
+    pub record: String,
+    /// Seconds it had left when the snapshot was taken
+    pub ttl_secs: u64,
+}
+
+/// Critical state at one point in time
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub node_id: String,
+    /// Unix seconds
+    pub taken_at: i64,
+    pub threat_level: Option<ThreatLevel>,
+    /// Banned and VIP circuits
+    pub circuits: Vec<CircuitRecord>,
+    /// Secret digest → entry (JSON `AllowlistEntry`), as stored
+    pub allowlist: BTreeMap<String, String>,
+}
+
+impl Snapshot {
+    /// Same state as `other`, regardless of when each was taken
+    fn same_state(&self, other: &Snapshot) -> bool {
+        let records = |snapshot: &Snapshot| {
+            snapshot
+                .circuits
+                .iter()
+                .map(|c| (c.circuit_id.clone(), c.status, c.record.clone()))
+                .collect::<Vec<_>>()
+        };
+        self.threat_level == other.threat_level
+            && self.allowlist == other.allowlist
+            && records(self) == records(other)
+    }
+}
+
+/// What a restore wrote (state already in Redis is left alone)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Restored {
+    pub threat_level: bool,
+    pub circuits: usize,
+    pub allowlist: usize,
+}
+
+/// A backup file: the snapshot and its signature
+#[derive(Serialize, Deserialize)]
+struct SignedFile {
+    /// Base64 HMAC-SHA256 over the compact JSON of `snapshot`
+    signature: String,
+    snapshot: serde_json::Value,
+}
+
+fn mac(secret: &[u8], body: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes any key length");
+    mac.update(body);
+    mac
+}
+
+/// Serialize and sign a snapshot
+///
+/// The signature covers the snapshot as a JSON value, whose keys serialize
+/// in a fixed order, so it still checks out after a round trip through
+/// another version of this struct.
+pub fn seal(snapshot: &Snapshot, secret: &[u8]) -> Result<Vec<u8>> {
+    let value = serde_json::to_value(snapshot)?;
+    let body = serde_json::to_vec(&value)?;
+    let signature = BASE64.encode(mac(secret, &body).finalize().into_bytes());
+    Ok(serde_json::to_vec_pretty(&SignedFile {
+        signature,
+        snapshot: value,
+    })?)
+}
+
+/// Check a backup file's signature and read its snapshot
+pub fn open(data: &[u8], secret: &[u8]) -> Result<Snapshot> {
+    let file: SignedFile = serde_json::from_slice(data).context("Not a backup file")?;
+    let body = serde_json::to_vec(&file.snapshot)?;
+    let signature = BASE64
+        .decode(&file.signature)
+        .context("Malformed backup signature")?;
+    if mac(secret, &body).verify_slice(&signature).is_err() {
+        bail!("Backup signature doesn't match; wrong secret or a modified file");
+    }
+    let snapshot: Snapshot = serde_json::from_value(file.snapshot)?;
+    if snapshot.version != FORMAT_VERSION {
+        bail!("Unsupported backup format version {}", snapshot.version);
+    }
+    Ok(snapshot)
+}
+
+/// Lifetime left to a record that had `ttl_secs` when backed up `age` ago
+fn remaining_ttl(ttl_secs: u64, age: i64) -> Option<u64> {
+    let left = ttl_secs as i64 - age.max(0);
+    (left > 0).then_some(left as u64)
+}
+
+/// Read the critical state
+pub async fn take(
+    redis: &mut ConnectionManager,
+    config: &BackupConfig,
+    node_id: &str,
+) -> Result<Snapshot> {
+    let taken_at = chrono::Utc::now().timestamp();
+    let threat_level: Option<u8> = redis.get(redis_keys::threat_level()).await?;
+    let allowlist: BTreeMap<String, String> = redis.hgetall(redis_keys::allowlist()).await?;
+
+    let mut circuits = Vec::new();
+    let mut cursor: u64 = 0;
+    loop {
+        let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(format!("{}*", CIRCUIT_PREFIX))
+            .arg("COUNT")
+            .arg(config.scan_count.max(1))
+            .query_async(redis)
+            .await?;
+
+        if !keys.is_empty() {
+            let mut pipe = redis::pipe();
+            for key in &keys {
+                pipe.get(key).ttl(key);
+            }
+            let values: Vec<(Option<String>, i64)> = pipe.query_async(redis).await?;
+            for (record, ttl) in values {
+                // Gone since the SCAN, or never to expire (not ours)
+                let (Some(record), Ok(ttl_secs)) = (record, u64::try_from(ttl)) else {
+                    continue;
+                };
+                let Ok(info) = versioned::decode::<CircuitInfo>(&record) else {
+                    continue;
+                };
+                if matches!(info.status, CircuitStatus::Banned | CircuitStatus::Vip) {
+                    circuits.push(CircuitRecord {
+                        circuit_id: info.circuit_id,
+                        status: info.status,
+                        record,
+                        ttl_secs,
+                    });
+                }
+            }
+        }
+
+        cursor = next;
+        if cursor == 0 {
+            break;
+        }
+    }
+    circuits.sort_by(|a, b| a.circuit_id.cmp(&b.circuit_id));
+
+    Ok(Snapshot {
+        version: FORMAT_VERSION,
+        node_id: node_id.to_string(),
+        taken_at,
+        threat_level: threat_level.map(ThreatLevel::new),
+        circuits,
+        allowlist,
+    })
+}
+
+/// Replay a snapshot into Redis, keeping whatever is already there
+pub async fn restore(redis: &mut ConnectionManager, snapshot: &Snapshot) -> Result<Restored> {
+    let now = chrono::Utc::now().timestamp();
+    let age = now - snapshot.taken_at;
+    let mut restored = Restored::default();
+
+    if let Some(level) = snapshot.threat_level {
+        let set: Option<String> = redis::cmd("SET")
+            .arg(redis_keys::threat_level())
+            .arg(level.value())
+            .arg("NX")
+            .query_async(redis)
+            .await?;
+        restored.threat_level = set.is_some();
+    }
+
+    for circuit in &snapshot.circuits {
+        let Some(ttl) = remaining_ttl(circuit.ttl_secs, age) else {
+            continue;
+        };
+        let set: Option<String> = redis::cmd("SET")
+            .arg(redis_keys::circuit(&circuit.circuit_id))
+            .arg(&circuit.record)
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl)
+            .query_async(redis)
+            .await?;
+        if set.is_none() {
+            continue;
+        }
+        if circuit.status == CircuitStatus::Vip {
+            let _: () = redis
+                .zadd(redis_keys::vips(), &circuit.circuit_id, now)
+                .await?;
+        }
+        restored.circuits += 1;
+    }
+
+    for (digest, entry) in &snapshot.allowlist {
+        let live =
+            serde_json::from_str::<AllowlistEntry>(entry).is_ok_and(|entry| entry.expires_at > now);
+        if live
+            && redis
+                .hset_nx::<_, _, _, bool>(redis_keys::allowlist(), digest, entry)
+                .await?
+        {
+            restored.allowlist += 1;
+        }
+    }
+
+    Ok(restored)
+}
+
+/// `fortify restore <file>`: check and replay a backup file
+pub async fn restore_file(config: &AppConfig, path: &str) -> Result<()> {
+    if config.backup.secret.is_empty() {
+        bail!("backup.secret is not set; it is needed to check the file's signature");
+    }
+    let data = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("Failed to read {}", path))?;
+    let snapshot = open(&data, config.backup.secret.as_bytes())?;
+
+    let client = redis::Client::open(config.redis_url.as_str())?;
+    let mut redis = ConnectionManager::new(client)
+        .await
+        .context("Failed to connect to Redis")?;
+    let restored = restore(&mut redis, &snapshot).await?;
+
+    println!(
+        "{}: backup of {} taken at {}",
+        path,
+        snapshot.node_id,
+        chrono::DateTime::from_timestamp(snapshot.taken_at, 0)
+            .map(|at| at.to_rfc3339())
+            .unwrap_or_else(|| snapshot.taken_at.to_string())
+    );
+    println!(
+        "restored: threat level {}, {} of {} circuits, {} of {} allowlist entries",
+        if restored.threat_level {
+            "yes"
+        } else {
+            "no (already set)"
+        },
+        restored.circuits,
+        snapshot.circuits.len(),
+        restored.allowlist,
+        snapshot.allowlist.len()
+    );
+    Ok(())
+}
+
+/// Write a backup file atomically, returning its path
+async fn write(dir: &Path, snapshot: &Snapshot, secret: &[u8]) -> Result<PathBuf> {
+    tokio::fs::create_dir_all(dir).await?;
+    let path = dir.join(format!(
+        "{}{}{}",
+        FILE_PREFIX, snapshot.taken_at, FILE_SUFFIX
+    ));
+    let tmp = path.with_extension("tmp");
+    tokio::fs::write(&tmp, seal(snapshot, secret)?).await?;
+    tokio::fs::rename(&tmp, &path).await?;
+    Ok(path)
+}
+
+/// Backup file names beyond the newest `keep`, oldest first
+fn surplus(mut names: Vec<String>, keep: usize) -> Vec<String> {
+    names.retain(|name| name.starts_with(FILE_PREFIX) && name.ends_with(FILE_SUFFIX));
+    // Same-width timestamps (until 2286) sort by name
+    names.sort();
+    let excess = names.len().saturating_sub(keep);
+    names.truncate(excess);
+    names
+}
+
+/// Delete backup files beyond the newest `keep`
+async fn prune(dir: &Path, keep: usize) -> Result<()> {
+    let mut names = Vec::new();
+    let mut read_dir = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        if let Some(name) = entry.file_name().to_str() {
+            names.push(name.to_string());
+        }
+    }
+    for name in surplus(names, keep) {
+        tokio::fs::remove_file(dir.join(name)).await?;
+    }
+    Ok(())
+}
+
+/// Snapshot the critical state every `interval_secs` (`backup.enabled`)
+pub async fn backup_worker(state: AppState, mut shutdown: tokio::sync::broadcast::Receiver<()>) {
+    let config = state.config.backup.clone();
+    if !config.enabled {
+        return;
+    }
+    let interval = Duration::from_secs(config.interval_secs.max(1));
+    let dir = PathBuf::from(&config.dir);
+    let mut last: Option<Snapshot> = None;
+
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {
+                let Some(mut redis) = state.redis() else {
+                    continue;
+                };
+                let result = async {
+                    let snapshot = take(&mut redis, &config, &state.config.node_id).await?;
+                    if last.as_ref().is_some_and(|last| last.same_state(&snapshot)) {
+                        return anyhow::Ok(None);
+                    }
+                    let path = write(&dir, &snapshot, config.secret.as_bytes()).await?;
+                    prune(&dir, config.keep).await?;
+                    last = Some(snapshot);
+                    Ok(Some(path))
+                }
+                .await;
+                let outcome = match result {
+                    Ok(Some(path)) => {
+                        tracing::info!(path = %path.display(), "Critical state backed up");
+                        "written"
+                    }
+                    Ok(None) => "unchanged",
+                    Err(e) => {
+                        tracing::warn!(error = %e, "Backup failed");
+                        "failed"
+                    }
+                };
+                metrics::BACKUPS.with_label_values(&[outcome]).inc();
+            }
+            _ = shutdown.recv() => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot() -> Snapshot {
+        Snapshot {
+            version: FORMAT_VERSION,
+            node_id: "node-1".to_string(),
+            taken_at: 1_700_000_000,
+            threat_level: Some(ThreatLevel::new(8)),
+            circuits: vec![CircuitRecord {
+                circuit_id: "fc00::1".parse().unwrap(),
+                status: CircuitStatus::Banned,
+                record: r#"{"v":1}"#.to_string(),
+                ttl_secs: 3600,
+            }],
+            allowlist: BTreeMap::from([("digest".to_string(), "{}".to_string())]),
+        }
+    }
+
+    #[test]
+    fn test_signed_round_trip() {
+        let sealed = seal(&snapshot(), b"secret").unwrap();
+        assert_eq!(open(&sealed, b"secret").unwrap(), snapshot());
+        assert!(open(&sealed, b"other secret").is_err());
+
+        let tampered = String::from_utf8(sealed)
+            .unwrap()
+            .replace(r#""threat_level": 8"#, r#""threat_level": 0"#);
+        assert!(open(tampered.as_bytes(), b"secret").is_err());
+        assert!(open(b"not json", b"secret").is_err());
+    }
+
+    #[test]
+    fn test_same_state_ignores_timing() {
+        let mut later = snapshot();
+        later.taken_at += 300;
+        later.circuits[0].ttl_secs -= 300;
+        assert!(snapshot().same_state(&later));
+
+        later.threat_level = Some(ThreatLevel::new(9));
+        assert!(!snapshot().same_state(&later));
+    }
+
+    #[test]
+    fn test_remaining_ttl_and_rotation() {
+        assert_eq!(remaining_ttl(3600, 600), Some(3000));
+        assert_eq!(remaining_ttl(3600, 3600), None);
+        assert_eq!(remaining_ttl(60, -5), Some(60));
+
+        let names = [
+            "cerberus-backup-1700000600.json",
+            "notes.txt",
+            "cerberus-backup-1700000000.json",
+            "cerberus-backup-1700000300.json",
+            "cerberus-backup-1700000900.tmp",
+        ]
+        .map(str::to_string)
+        .to_vec();
+        assert_eq!(
+            surplus(names.clone(), 2),
+            ["cerberus-backup-1700000000.json"]
+        );
+        assert!(surplus(names, 3).is_empty());
+    }
+}
@@ -8,17 +8,35 @@
 //! - Critical Low (<10%): Emergency load from disk or generate
 //! - Normal Maintenance (<80%): Generate when CPU is available
 //! - Surplus (>95%): Dump to disk for persistence
+//!
+//! Batch files start with a magic number and format version, so a format
+//! change can be migrated instead of failing to decode the whole cache;
+//! files from before versioning are read as version 0. A periodic compaction
+//! pass rewrites small, old-format or stale files into full current-format
+//! batches and trims the cache to `max_disk_cache`.
+//!
+//! At startup the pool is warmed up (disk cache first, then generation) to a
+//! minimum fill; until then the box reports itself cold and `/ready` fails.
+//!
+//! A pool that stays under `starvation.fill_pct` for `starvation.after_secs`
+//! is starving: challenges are being rendered on demand, which an attack
+//! can turn into a CPU exhaustion. `AmmoStarved` is published once per
+//! episode (and `AmmoRecovered` when the pool is back above the threshold).
 
-use anyhow::{Context, Result};
-use cerberus_common::CaptchaDifficulty;
+use anyhow::{Context, Result, bail};
+use cerberus_common::{CaptchaDifficulty, CerberusEvent, EventPublisher, TtlSecs};
 use crossbeam_queue::ArrayQueue;
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 
+use super::text::render_prompt;
+use crate::config::ImageFormat;
+use crate::metrics;
+
 /// A pre-generated CAPTCHA ready for immediate dispatch
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct PregenCaptcha {
@@ -43,8 +61,16 @@ pub struct AmmoBoxConfig {
     pub max_disk_cache: usize,
     /// Minimum free disk space in GB before stopping disk writes
     pub min_disk_free_gb: u64,
-    /// How often to dump RAM to disk (seconds)
-    pub dump_interval_secs: u64,
+    /// How often to dump RAM to disk
+    pub dump_interval_secs: TtlSecs,
+    /// How often to compact the disk cache
+    pub compact_interval_secs: TtlSecs,
+    /// CAPTCHAs per batch file; smaller files are merged on compaction
+    pub compact_batch_size: usize,
+    /// CAPTCHAs older than this are dropped on compaction
+    pub max_disk_age_secs: TtlSecs,
+    /// When a low pool becomes an emergency
+    pub starvation: StarvationConfig,
 }
 
 impl Default for AmmoBoxConfig {
@@ -54,7 +80,30 @@ impl Default for AmmoBoxConfig {
             disk_cache_path: PathBuf::from("/var/lib/cerberus/ammo"),
             max_disk_cache: 100_000,
             min_disk_free_gb: 5,
-            dump_interval_secs: 300,
+            dump_interval_secs: TtlSecs::from_mins(5),
+            compact_interval_secs: TtlSecs::from_hours(1),
+            compact_batch_size: 1000,
+            max_disk_age_secs: TtlSecs::from_days(7),
+            starvation: StarvationConfig::default(),
+        }
+    }
+}
+
+/// Pool starvation alert (`[ammo_starvation]` in fortify.toml)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StarvationConfig {
+    /// Pool fill (percent of capacity) under which the pool is starving
+    pub fill_pct: u8,
+    /// How long it has to stay there before `AmmoStarved` is published
+    pub after_secs: TtlSecs,
+}
+
+impl Default for StarvationConfig {
+    fn default() -> Self {
+        Self {
+            fill_pct: 25,
+            after_secs: TtlSecs::from_secs(30),
         }
     }
 }
@@ -67,8 +116,12 @@ pub struct AmmoBox {
     config: AmmoBoxConfig,
     /// Last dump timestamp
     last_dump: Mutex<Instant>,
+    /// Last compaction timestamp
+    last_compaction: Mutex<Instant>,
     /// Statistics
     stats: AmmoBoxStats,
+    /// Startup warm-up finished (or skipped)
+    warm: AtomicBool,
 }
 
 /// Runtime statistics
@@ -94,10 +147,43 @@ impl AmmoBox {
             pool: ArrayQueue::new(capacity),
             config,
             last_dump: Mutex::new(Instant::now()),
+            last_compaction: Mutex::new(Instant::now()),
             stats: AmmoBoxStats::default(),
+            warm: AtomicBool::new(false),
         }
     }
 
+    /// Has the startup warm-up finished (or been skipped)?
+    pub fn is_warm(&self) -> bool {
+        self.warm.load(Ordering::Acquire)
+    }
+
+    /// Consider the pool warm, however full it is
+    pub fn mark_warm(&self) {
+        self.warm.store(true, Ordering::Release);
+    }
+
+    /// Fill the pool to `min_fill_pct` at startup, then mark it warm
+    ///
+    /// The disk cache is drained first (cheap); whatever is still missing is
+    /// generated in batches, yielding between them so the server keeps
+    /// answering meanwhile.
+    pub async fn warm_up(&self, min_fill_pct: u8) -> Result<()> {
+        let target = self.config.ram_capacity * usize::from(min_fill_pct.min(100)) / 100;
+        if self.len() < target {
+            self.load_from_disk(target - self.len()).await?;
+        }
+        while self.len() < target {
+            let count = (target - self.len()).min(WARMUP_BATCH);
+            if self.push_batch(self.generate_batch(count, CaptchaDifficulty::Medium)) == 0 {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        self.mark_warm();
+        Ok(())
+    }
+
     /// Get pool capacity
     pub fn capacity(&self) -> usize {
         self.config.ram_capacity
@@ -122,9 +208,14 @@ impl AmmoBox {
     ///
     /// Returns None if pool is empty (caller should generate on-demand)
     pub fn pop(&self) -> Option<PregenCaptcha> {
+        let started = Instant::now();
         let captcha = self.pool.pop();
-        if captcha.is_some() {
+        metrics::AMMO_POP_SECONDS.observe(started.elapsed().as_secs_f64());
+
+        if let Some(ref c) = captcha {
             self.stats.served.fetch_add(1, Ordering::Relaxed);
+            let waited = chrono::Utc::now().timestamp() - c.generated_at;
+            metrics::AMMO_POOL_WAIT_SECONDS.observe(waited.max(0) as f64);
         } else {
             self.stats.pool_misses.fetch_add(1, Ordering::Relaxed);
         }
@@ -151,6 +242,76 @@ impl AmmoBox {
         pushed
     }
 
+    /// Generate up to `count` CAPTCHAs into the pool, stopping once it's full
+    ///
+    /// Generated in batches, yielding between them as the warm-up does.
+    pub async fn fill(&self, count: usize, difficulty: CaptchaDifficulty) -> usize {
+        let mut pushed = 0;
+        while pushed < count {
+            let room = self.capacity().saturating_sub(self.len());
+            let batch = (count - pushed).min(room).min(WARMUP_BATCH);
+            if batch == 0 {
+                break;
+            }
+            match self.push_batch(self.generate_batch(batch, difficulty)) {
+                0 => break,
+                n => pushed += n,
+            }
+            tokio::task::yield_now().await;
+        }
+        pushed
+    }
+
+    /// Take up to `count` CAPTCHAs out of the pool, encoded as a batch file
+    /// (for another node); returns how many, and the encoded batch
+    pub fn export(&self, count: usize) -> Result<(usize, Vec<u8>)> {
+        let mut batch = Vec::with_capacity(count.min(self.len()));
+        while batch.len() < count {
+            match self.pool.pop() {
+                Some(captcha) => batch.push(captcha),
+                None => break,
+            }
+        }
+        match encode_batch(&batch) {
+            Ok(data) => Ok((batch.len(), data)),
+            Err(e) => {
+                self.push_batch(batch);
+                Err(e)
+            }
+        }
+    }
+
+    /// Add the CAPTCHAs of an encoded batch (from `export`) to the pool;
+    /// returns how many fitted
+    pub fn import(&self, data: &[u8]) -> Result<usize> {
+        match decode_batch(data)? {
+            BatchFile::Readable { batch, .. } => Ok(self.push_batch(batch)),
+            BatchFile::Unsupported(version) => bail!("Unsupported batch format {}", version),
+        }
+    }
+
+    /// Drop every CAPTCHA in the pool; returns how many there were
+    pub fn flush(&self) -> usize {
+        let mut flushed = 0;
+        while self.pool.pop().is_some() {
+            flushed += 1;
+        }
+        flushed
+    }
+
+    /// Delete every batch file in the disk cache; returns how many
+    pub async fn purge_disk(&self) -> Result<usize> {
+        let cache_dir = &self.config.disk_cache_path;
+        if !cache_dir.exists() {
+            return Ok(0);
+        }
+        let files = batch_files(cache_dir).await?;
+        for path in &files {
+            tokio::fs::remove_file(path).await?;
+        }
+        Ok(files.len())
+    }
+
     /// Generate a batch of CAPTCHAs
     pub fn generate_batch(&self, count: usize, difficulty: CaptchaDifficulty) -> Vec<PregenCaptcha> {
         use rand::Rng;
@@ -161,7 +322,7 @@ impl AmmoBox {
 
         for _ in 0..count {
             let answer = generate_answer(&mut rng, difficulty);
-            let image_data = generate_svg(&answer, difficulty, &mut rng);
+            let image_data = render_prompt(&answer, difficulty, ImageFormat::Svg);
 
             batch.push(PregenCaptcha {
                 answer,
@@ -178,6 +339,7 @@ impl AmmoBox {
 
     /// Load CAPTCHAs from disk cache
     pub async fn load_from_disk(&self, max_count: usize) -> Result<usize> {
+        let started = Instant::now();
         let cache_dir = &self.config.disk_cache_path;
 
         if !cache_dir.exists() {
@@ -199,11 +361,13 @@ impl AmmoBox {
             let path = entry.path();
             if path.extension().is_some_and(|e| e == "bin") {
                 match self.load_batch_file(&path).await {
-                    Ok(count) => {
+                    Ok(Some(count)) => {
                         loaded += count;
                         // Delete after loading
                         let _ = tokio::fs::remove_file(&path).await;
                     }
+                    // Written by a newer release; left for it
+                    Ok(None) => {}
                     Err(e) => {
                         tracing::warn!(path = ?path, error = %e, "Failed to load ammo file");
                     }
@@ -217,20 +381,26 @@ impl AmmoBox {
 
         self.stats.loaded_from_disk.fetch_add(loaded as u64, Ordering::Relaxed);
         tracing::debug!(loaded = loaded, "Loaded CAPTCHAs from disk");
+        metrics::AMMO_LOAD_SECONDS.observe(started.elapsed().as_secs_f64());
 
         Ok(loaded)
     }
 
-    /// Load a single batch file
-    async fn load_batch_file(&self, path: &Path) -> Result<usize> {
+    /// Load a single batch file (`None` if its format is too new to read)
+    async fn load_batch_file(&self, path: &Path) -> Result<Option<usize>> {
         let data = tokio::fs::read(path).await?;
-        let batch: Vec<PregenCaptcha> = bincode::deserialize(&data)?;
-        let count = self.push_batch(batch);
-        Ok(count)
+        match decode_batch(&data)? {
+            BatchFile::Readable { batch, .. } => Ok(Some(self.push_batch(batch))),
+            BatchFile::Unsupported(version) => {
+                tracing::warn!(path = ?path, version, "Skipping ammo file from a newer format");
+                Ok(None)
+            }
+        }
     }
 
     /// Dump current pool to disk
     pub async fn dump_to_disk(&self, batch_size: usize) -> Result<usize> {
+        let started = Instant::now();
         let cache_dir = &self.config.disk_cache_path;
 
         // Ensure directory exists
@@ -253,14 +423,12 @@ impl AmmoBox {
         let count = batch.len();
 
         // Serialize and write
-        let data = bincode::serialize(&batch)?;
-        let filename = format!("ammo_{}.bin", chrono::Utc::now().timestamp_millis());
-        let path = cache_dir.join(filename);
-
-        tokio::fs::write(&path, data).await?;
+        let path = cache_dir.join(batch_file_name(chrono::Utc::now().timestamp_millis(), None));
+        write_batch_file(&path, &batch).await?;
 
         self.stats.dumped_to_disk.fetch_add(count as u64, Ordering::Relaxed);
         tracing::debug!(count = count, path = ?path, "Dumped CAPTCHAs to disk");
+        metrics::AMMO_DUMP_SECONDS.observe(started.elapsed().as_secs_f64());
 
         // Put items back in pool (they're now also on disk)
         self.push_batch(batch);
@@ -268,6 +436,133 @@ impl AmmoBox {
         Ok(count)
     }
 
+    /// Merge small, old-format and stale batch files, and trim the cache
+    ///
+    /// Full current-format files with nothing stale are left alone, as are
+    /// files too new or too damaged to read. Merged files are written before
+    /// their sources are removed, so an interrupted pass loses nothing (at
+    /// worst some CAPTCHAs are on disk twice).
+    pub async fn compact_disk(&self) -> Result<CompactionReport> {
+        let cache_dir = &self.config.disk_cache_path;
+        let mut report = CompactionReport::default();
+        if !cache_dir.exists() {
+            return Ok(report);
+        }
+
+        let stale_before = chrono::Utc::now().timestamp() - self.config.max_disk_age_secs.as_i64();
+        let batch_size = self.config.compact_batch_size.max(1);
+
+        // Oldest first, as loading goes
+        let mut kept: Vec<(PathBuf, usize)> = Vec::new();
+        let mut sources = Vec::new();
+        let mut merged: Vec<PregenCaptcha> = Vec::new();
+        for path in batch_files(cache_dir).await? {
+            let data = tokio::fs::read(&path).await?;
+            let (version, batch) = match decode_batch(&data) {
+                Ok(BatchFile::Readable { version, batch }) => (version, batch),
+                Ok(BatchFile::Unsupported(_)) => continue,
+                Err(e) => {
+                    tracing::warn!(path = ?path, error = %e, "Unreadable ammo file, skipping");
+                    continue;
+                }
+            };
+
+            let fresh = batch.iter().all(|c| c.generated_at >= stale_before);
+            if version == FORMAT_VERSION && batch.len() >= batch_size && fresh {
+                kept.push((path, batch.len()));
+                continue;
+            }
+            let total = batch.len();
+            let before = merged.len();
+            merged.extend(batch.into_iter().filter(|c| c.generated_at >= stale_before));
+            report.dropped_stale += total - (merged.len() - before);
+            sources.push(path);
+        }
+
+        // Rewrite the merged CAPTCHAs, named after the oldest source so they
+        // keep their place in the load order
+        if !sources.is_empty() {
+            let stamp = sources
+                .first()
+                .and_then(|path| batch_timestamp(path))
+                .unwrap_or_else(|| chrono::Utc::now().timestamp_millis());
+            let mut written = Vec::new();
+            for (n, chunk) in merged.chunks(batch_size).enumerate() {
+                let path = cache_dir.join(batch_file_name(stamp, Some(n)));
+                write_batch_file(&path, chunk).await?;
+                kept.push((path.clone(), chunk.len()));
+                written.push(path);
+            }
+            for path in sources.iter().filter(|path| !written.contains(path)) {
+                tokio::fs::remove_file(path).await?;
+            }
+            report.merged_files = sources.len();
+            report.written_files = written.len();
+            kept.sort();
+        }
+
+        // Over the cap: the oldest files go first
+        let mut total: usize = kept.iter().map(|(_, count)| count).sum();
+        for (path, count) in &kept {
+            if total <= self.config.max_disk_cache {
+                break;
+            }
+            tokio::fs::remove_file(path).await?;
+            total -= count;
+            report.dropped_over_cap += count;
+        }
+
+        *self.last_compaction.lock().await = Instant::now();
+        if report.merged_files > 0 || report.dropped_over_cap > 0 {
+            tracing::info!(
+                merged_files = report.merged_files,
+                written_files = report.written_files,
+                dropped_stale = report.dropped_stale,
+                dropped_over_cap = report.dropped_over_cap,
+                "Compacted ammo disk cache"
+            );
+        }
+        Ok(report)
+    }
+
+    /// Batch files in the disk cache and their size
+    pub async fn disk_usage(&self) -> Result<DiskUsage> {
+        let cache_dir = &self.config.disk_cache_path;
+        let mut usage = DiskUsage {
+            max_captchas: self.config.max_disk_cache,
+            ..Default::default()
+        };
+        if !cache_dir.exists() {
+            return Ok(usage);
+        }
+        for path in batch_files(cache_dir).await? {
+            // Gone since listed (a compaction or load): not counted
+            if let Ok(meta) = tokio::fs::metadata(&path).await {
+                usage.files += 1;
+                usage.bytes += meta.len();
+            }
+        }
+        Ok(usage)
+    }
+
+    /// Free space on the filesystem holding the disk cache, in bytes
+    /// (`None` where it can't be read)
+    pub fn disk_free_bytes(&self) -> Option<u64> {
+        // Before the first dump the cache directory may not exist yet
+        let dir = self
+            .config
+            .disk_cache_path
+            .ancestors()
+            .find(|dir| dir.exists())?;
+        free_space(dir)
+    }
+
+    /// Is a disk compaction due?
+    pub async fn should_compact(&self) -> bool {
+        let last = self.last_compaction.lock().await;
+        last.elapsed() > self.config.compact_interval_secs.duration()
+    }
+
     /// Get statistics snapshot
     pub fn get_stats(&self) -> AmmoBoxStatsSnapshot {
         AmmoBoxStatsSnapshot {
@@ -285,7 +580,7 @@ impl AmmoBox {
     /// Check if we should dump to disk
     pub async fn should_dump(&self) -> bool {
         let last = self.last_dump.lock().await;
-        last.elapsed() > Duration::from_secs(self.config.dump_interval_secs)
+        last.elapsed() > self.config.dump_interval_secs.duration()
     }
 
     /// Update last dump time
@@ -295,6 +590,28 @@ impl AmmoBox {
     }
 }
 
+/// What a disk compaction pass did
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CompactionReport {
+    /// Batch files merged and rewritten
+    pub merged_files: usize,
+    /// Batch files written in their place
+    pub written_files: usize,
+    /// CAPTCHAs dropped for being older than `max_disk_age_secs`
+    pub dropped_stale: usize,
+    /// CAPTCHAs dropped to stay under `max_disk_cache`
+    pub dropped_over_cap: usize,
+}
+
+/// Disk cache size
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct DiskUsage {
+    pub files: usize,
+    pub bytes: u64,
+    /// `max_disk_cache`
+    pub max_captchas: usize,
+}
+
 /// Snapshot of Ammo Box statistics
 #[derive(Clone, Debug, Serialize)]
 pub struct AmmoBoxStatsSnapshot {
@@ -309,15 +626,63 @@ pub struct AmmoBoxStatsSnapshot {
 }
 
 /// Background worker that maintains the Ammo Box
+///
+/// Publishes `AmmoLow` each time the pool drops to critical, and
+/// `AmmoStarved` / `AmmoRecovered` as it starts and stops starving.
 pub async fn ammo_box_worker(
     ammo: Arc<AmmoBox>,
+    events: Arc<dyn EventPublisher>,
     mut shutdown: tokio::sync::broadcast::Receiver<()>,
 ) {
     tracing::info!("🎯 Ammo Box worker started (capacity: {})", ammo.capacity());
+    let mut low = false;
+    let mut starvation = Starvation::default();
 
     loop {
         tokio::select! {
             _ = tokio::time::sleep(Duration::from_secs(1)) => {
+                let critical = ammo.fill_percent() < CRITICAL_FILL_PCT;
+                if critical && !low {
+                    events.publish(CerberusEvent::AmmoLow {
+                        available: ammo.len(),
+                        capacity: ammo.capacity(),
+                    });
+                }
+                low = critical;
+
+                let fill_percent = ammo.fill_percent();
+                match starvation.observe(&ammo.config.starvation, fill_percent, Instant::now()) {
+                    Some(StarvationChange::Starved(starved)) => {
+                        tracing::error!(
+                            fill_percent,
+                            starved_secs = starved.as_secs(),
+                            "Ammo Box starving: challenges are being rendered on demand"
+                        );
+                        events.publish(CerberusEvent::AmmoStarved {
+                            available: ammo.len(),
+                            capacity: ammo.capacity(),
+                            starved_secs: starved.as_secs(),
+                        });
+                    }
+                    Some(StarvationChange::Recovered(starved)) => {
+                        tracing::info!(
+                            fill_percent,
+                            starved_secs = starved.as_secs(),
+                            "Ammo Box recovered"
+                        );
+                        events.publish(CerberusEvent::AmmoRecovered {
+                            available: ammo.len(),
+                            capacity: ammo.capacity(),
+                            starved_secs: starved.as_secs(),
+                        });
+                    }
+                    None => {}
+                }
+
+                if let Some(free) = ammo.disk_free_bytes() {
+                    metrics::AMMO_DISK_FREE_BYTES.set(free as i64);
+                }
+
                 if let Err(e) = maintain_ammo_box(&ammo).await {
                     tracing::error!(error = %e, "Ammo Box maintenance error");
                 }
@@ -334,6 +699,72 @@ pub async fn ammo_box_worker(
     }
 }
 
+/// Space available to unprivileged users on the filesystem holding `path`
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)] // field widths vary by platform
+fn free_space(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+    // SAFETY: `statvfs` is plain old data, filled in by the call below
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is NUL-terminated and `stat` outlives the call
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+fn free_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// A starvation episode starting or ending, with how long the pool was
+/// under the threshold
+#[derive(Debug, PartialEq, Eq)]
+enum StarvationChange {
+    Starved(Duration),
+    Recovered(Duration),
+}
+
+/// How long the pool has been under `starvation.fill_pct`
+#[derive(Default)]
+struct Starvation {
+    /// When the pool went under
+    since: Option<Instant>,
+    /// `Starved` was reported for this episode
+    reported: bool,
+}
+
+impl Starvation {
+    fn observe(
+        &mut self,
+        config: &StarvationConfig,
+        fill_pct: u8,
+        now: Instant,
+    ) -> Option<StarvationChange> {
+        if fill_pct >= config.fill_pct {
+            let since = self.since.take()?;
+            let reported = std::mem::take(&mut self.reported);
+            return reported.then(|| StarvationChange::Recovered(now - since));
+        }
+        let since = *self.since.get_or_insert(now);
+        let starved = now - since;
+        if self.reported || starved < config.after_secs.duration() {
+            return None;
+        }
+        self.reported = true;
+        Some(StarvationChange::Starved(starved))
+    }
+}
+
+/// Pool fill percentage below which the worker takes emergency action
+const CRITICAL_FILL_PCT: u8 = 10;
+
+/// CAPTCHAs generated per step of the startup warm-up (and manual fills)
+const WARMUP_BATCH: usize = 100;
+
 /// Maintenance logic for the Ammo Box
 async fn maintain_ammo_box(ammo: &AmmoBox) -> Result<()> {
     let pool_len = ammo.len();
@@ -344,7 +775,7 @@ async fn maintain_ammo_box(ammo: &AmmoBox) -> Result<()> {
     let cpu_load = get_cpu_load().await;
 
     // 1. Critical Low (< 10%): Emergency Action
-    if fill_pct < 10 {
+    if fill_pct < CRITICAL_FILL_PCT {
         if cpu_load > 80 {
             // CPU High: Load from Disk (Cheap I/O)
             tracing::warn!(fill_pct = fill_pct, "Ammo critical - loading from disk");
@@ -374,9 +805,93 @@ async fn maintain_ammo_box(ammo: &AmmoBox) -> Result<()> {
         }
     }
 
+    if ammo.should_compact().await {
+        ammo.compact_disk().await?;
+    }
+
     Ok(())
 }
 
+/// Leads every batch file written since format versioning
+const BATCH_MAGIC: &[u8; 4] = b"CAMO";
+
+/// Current batch file format: magic, little-endian u16 version, bincode
+/// `Vec<PregenCaptcha>`. Version 0 is the headerless bincode of older
+/// releases.
+const FORMAT_VERSION: u16 = 1;
+
+/// A decoded batch file
+enum BatchFile {
+    Readable {
+        version: u16,
+        batch: Vec<PregenCaptcha>,
+    },
+    /// Written by a newer release
+    Unsupported(u16),
+}
+
+fn encode_batch(batch: &[PregenCaptcha]) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity(BATCH_MAGIC.len() + 2);
+    data.extend_from_slice(BATCH_MAGIC);
+    data.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    bincode::serialize_into(&mut data, batch)?;
+    Ok(data)
+}
+
+fn decode_batch(data: &[u8]) -> Result<BatchFile> {
+    let Some(rest) = data.strip_prefix(BATCH_MAGIC.as_slice()) else {
+        // Version 0: no header
+        let batch = bincode::deserialize(data).context("Invalid legacy ammo batch")?;
+        return Ok(BatchFile::Readable { version: 0, batch });
+    };
+    let (version, body) = rest
+        .split_first_chunk::<2>()
+        .context("Truncated ammo batch header")?;
+    match u16::from_le_bytes(*version) {
+        FORMAT_VERSION => Ok(BatchFile::Readable {
+            version: FORMAT_VERSION,
+            batch: bincode::deserialize(body).context("Invalid ammo batch")?,
+        }),
+        version => Ok(BatchFile::Unsupported(version)),
+    }
+}
+
+/// Write a batch in the current format, atomically
+async fn write_batch_file(path: &Path, batch: &[PregenCaptcha]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    tokio::fs::write(&tmp, encode_batch(batch)?).await?;
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+/// `ammo_<ms>.bin`, or `ammo_<ms>_<n>.bin` for compacted files
+fn batch_file_name(timestamp_ms: i64, part: Option<usize>) -> String {
+    match part {
+        Some(n) => format!("ammo_{}_{:04}.bin", timestamp_ms, n),
+        None => format!("ammo_{}.bin", timestamp_ms),
+    }
+}
+
+/// The timestamp a batch file is named after
+fn batch_timestamp(path: &Path) -> Option<i64> {
+    let stem = path.file_stem()?.to_str()?.strip_prefix("ammo_")?;
+    stem.split('_').next()?.parse().ok()
+}
+
+/// Batch files in the cache directory, oldest first
+async fn batch_files(cache_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut read_dir = tokio::fs::read_dir(cache_dir).await?;
+    let mut files = Vec::new();
+    while let Some(entry) = read_dir.next_entry().await? {
+        let path = entry.path();
+        if path.extension().is_some_and(|e| e == "bin") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
 /// Get CPU load (0-100)
 async fn get_cpu_load() -> u8 {
     // Simplified implementation - in production use sysinfo crate
@@ -386,14 +901,7 @@ async fn get_cpu_load() -> u8 {
 
 /// Generate random answer string
 fn generate_answer(rng: &mut impl rand::Rng, difficulty: CaptchaDifficulty) -> String {
-    let length = match difficulty {
-        CaptchaDifficulty::Easy => 4,
-        CaptchaDifficulty::Medium => 5,
-        CaptchaDifficulty::Hard => 6,
-        CaptchaDifficulty::Extreme => 8,
-    };
-
-    (0..length)
+    (0..difficulty.profile().answer_length)
         .map(|_| {
             let idx = rng.random_range(0..36u8);
             if idx < 10 {
@@ -405,65 +913,6 @@ fn generate_answer(rng: &mut impl rand::Rng, difficulty: CaptchaDifficulty) -> S
         .collect()
 }
 
-/// Generate SVG CAPTCHA image
-fn generate_svg(text: &str, difficulty: CaptchaDifficulty, rng: &mut impl rand::Rng) -> String {
-    use base64::{Engine, engine::general_purpose::STANDARD};
-
-    let width = 200;
-    let height = 80;
-
-    let noise_count = match difficulty {
-        CaptchaDifficulty::Easy => 5,
-        CaptchaDifficulty::Medium => 15,
-        CaptchaDifficulty::Hard => 30,
-        CaptchaDifficulty::Extreme => 50,
-    };
-
-    let mut svg = format!(
-        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}">"#,
-        width, height
-    );
-
-    // Background
-    svg.push_str(r##"<rect width="100%" height="100%" fill="#1a1a2e"/>"##);
-
-    // Noise lines
-    for _ in 0..noise_count {
-        let x1 = rng.random_range(0..width);
-        let y1 = rng.random_range(0..height);
-        let x2 = rng.random_range(0..width);
-        let y2 = rng.random_range(0..height);
-        let opacity = rng.random_range(20..50);
-        svg.push_str(&format!(
-            r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="rgba(255,255,255,0.{})" stroke-width="1"/>"#,
-            x1, y1, x2, y2, opacity
-        ));
-    }
-
-    // Text characters
-    let char_width = width as f32 / (text.len() as f32 + 1.0);
-    for (i, c) in text.chars().enumerate() {
-        let x = char_width * (i as f32 + 0.8);
-        let y = 50 + rng.random_range(-10..10);
-        let rotation = rng.random_range(-15..15);
-        let color = format!(
-            "rgb({},{},{})",
-            rng.random_range(150..255),
-            rng.random_range(150..255),
-            rng.random_range(150..255)
-        );
-
-        svg.push_str(&format!(
-            r#"<text x="{}" y="{}" font-family="monospace" font-size="32" font-weight="bold" fill="{}" transform="rotate({} {} {})">{}</text>"#,
-            x, y, color, rotation, x, y, c
-        ));
-    }
-
-    svg.push_str("</svg>");
-
-    format!("data:image/svg+xml;base64,{}", STANDARD.encode(&svg))
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -491,6 +940,158 @@ mod tests {
         assert_eq!(ammo.len(), 49);
     }
 
+    #[tokio::test]
+    async fn test_warm_up_fills_to_minimum() {
+        let ammo = AmmoBox::new(AmmoBoxConfig {
+            ram_capacity: 400,
+            disk_cache_path: std::env::temp_dir().join("cerberus-ammo-warmup-test-missing"),
+            ..Default::default()
+        });
+        assert!(!ammo.is_warm());
+
+        ammo.warm_up(25).await.unwrap();
+        assert!(ammo.is_warm());
+        assert_eq!(ammo.len(), 100);
+        assert_eq!(ammo.fill_percent(), 25);
+    }
+
+    #[test]
+    fn test_batch_format_versions() {
+        let ammo = AmmoBox::new(AmmoBoxConfig::default());
+        let batch = ammo.generate_batch(3, CaptchaDifficulty::Easy);
+
+        let current = encode_batch(&batch).unwrap();
+        assert!(current.starts_with(BATCH_MAGIC));
+        assert!(matches!(
+            decode_batch(&current).unwrap(),
+            BatchFile::Readable { version: FORMAT_VERSION, batch } if batch.len() == 3
+        ));
+
+        let legacy = bincode::serialize(&batch).unwrap();
+        assert!(matches!(
+            decode_batch(&legacy).unwrap(),
+            BatchFile::Readable { version: 0, batch } if batch.len() == 3
+        ));
+
+        let mut future = current.clone();
+        future[BATCH_MAGIC.len()..BATCH_MAGIC.len() + 2].copy_from_slice(&9u16.to_le_bytes());
+        assert!(matches!(
+            decode_batch(&future).unwrap(),
+            BatchFile::Unsupported(9)
+        ));
+        assert!(decode_batch(b"CAMO").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_compaction_merges_and_migrates() {
+        let dir =
+            std::env::temp_dir().join(format!("cerberus-ammo-compact-{}", std::process::id()));
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        tokio::fs::create_dir_all(&dir).await.unwrap();
+        let ammo = AmmoBox::new(AmmoBoxConfig {
+            ram_capacity: 100,
+            disk_cache_path: dir.clone(),
+            compact_batch_size: 10,
+            ..Default::default()
+        });
+
+        // A legacy file, a small current one with stale entries, a full one
+        let legacy = ammo.generate_batch(4, CaptchaDifficulty::Easy);
+        tokio::fs::write(
+            dir.join("ammo_1000.bin"),
+            bincode::serialize(&legacy).unwrap(),
+        )
+        .await
+        .unwrap();
+        let mut small = ammo.generate_batch(5, CaptchaDifficulty::Easy);
+        small[..2].iter_mut().for_each(|c| c.generated_at = 0);
+        write_batch_file(&dir.join("ammo_2000.bin"), &small)
+            .await
+            .unwrap();
+        let full = ammo.generate_batch(10, CaptchaDifficulty::Easy);
+        write_batch_file(&dir.join("ammo_3000.bin"), &full)
+            .await
+            .unwrap();
+
+        let report = ammo.compact_disk().await.unwrap();
+        assert_eq!(
+            report,
+            CompactionReport {
+                merged_files: 2,
+                written_files: 1,
+                dropped_stale: 2,
+                dropped_over_cap: 0,
+            }
+        );
+        let names: Vec<_> = batch_files(&dir)
+            .await
+            .unwrap()
+            .iter()
+            .map(|p| p.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, ["ammo_1000_0000.bin", "ammo_3000.bin"]);
+        let usage = ammo.disk_usage().await.unwrap();
+        assert_eq!(usage.files, 2);
+        assert!(usage.bytes > 0);
+
+        assert_eq!(ammo.load_from_disk(100).await.unwrap(), 17);
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+
+    #[tokio::test]
+    async fn test_manual_fill_and_flush() {
+        let dir = std::env::temp_dir().join(format!("cerberus-ammo-flush-{}", std::process::id()));
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+        let ammo = AmmoBox::new(AmmoBoxConfig {
+            ram_capacity: 150,
+            disk_cache_path: dir.clone(),
+            ..Default::default()
+        });
+
+        assert_eq!(ammo.fill(120, CaptchaDifficulty::Hard).await, 120);
+        // Stops once full
+        assert_eq!(ammo.fill(100, CaptchaDifficulty::Hard).await, 30);
+        assert!(
+            ammo.pop()
+                .is_some_and(|c| c.difficulty == CaptchaDifficulty::Hard)
+        );
+
+        assert_eq!(ammo.dump_to_disk(50).await.unwrap(), 50);
+        assert_eq!(ammo.flush(), 149);
+        assert!(ammo.is_empty());
+        assert_eq!(ammo.purge_disk().await.unwrap(), 1);
+        assert_eq!(ammo.load_from_disk(100).await.unwrap(), 0);
+        let _ = tokio::fs::remove_dir_all(&dir).await;
+    }
+
+    #[test]
+    fn test_starvation_reported_once_per_episode() {
+        let config = StarvationConfig {
+            fill_pct: 25,
+            after_secs: TtlSecs::from_secs(30),
+        };
+        let mut starvation = Starvation::default();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(starvation.observe(&config, 50, at(0)), None);
+        assert_eq!(starvation.observe(&config, 10, at(1)), None);
+        assert_eq!(starvation.observe(&config, 20, at(20)), None);
+        assert_eq!(
+            starvation.observe(&config, 5, at(31)),
+            Some(StarvationChange::Starved(Duration::from_secs(30)))
+        );
+        assert_eq!(starvation.observe(&config, 5, at(60)), None);
+        assert_eq!(
+            starvation.observe(&config, 40, at(61)),
+            Some(StarvationChange::Recovered(Duration::from_secs(60)))
+        );
+
+        // A dip shorter than after_secs says nothing, going in or out
+        assert_eq!(starvation.observe(&config, 10, at(70)), None);
+        assert_eq!(starvation.observe(&config, 30, at(80)), None);
+    }
+
     #[test]
     fn test_generate_answer() {
         let mut rng = rand::rng();
@@ -0,0 +1,164 @@
+//! Micro-batched challenge writes.
+//!
+//! Every issued challenge is stored with a SET (plus its circuit event), one
+//! round trip each. At high issuance rates the writes are instead queued and
+//! sent as one pipeline every `flush_ms` or `max_ops` writes, whichever comes
+//! first. Callers still wait for their own write, so a challenge is never
+//! handed out before it's stored. The queue is bounded: when it's full,
+//! issuance is refused rather than buffered without limit.
+
+use anyhow::Result;
+use cerberus_common::CerberusError;
+use redis::aio::ConnectionManager;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::oneshot;
+
+use crate::config::WriteBatchConfig;
+use crate::metrics;
+
+/// A queued write, and where to report how it went
+struct Write {
+    pipe: redis::Pipeline,
+    redis: ConnectionManager,
+    done: oneshot::Sender<Result<(), String>>,
+}
+
+/// Batches challenge writes into shared pipelines
+pub struct ChallengeWriter {
+    config: WriteBatchConfig,
+    /// Queue to the flush task, started by the first write
+    queue: OnceLock<mpsc::Sender<Write>>,
+}
+
+impl ChallengeWriter {
+    pub fn new(config: WriteBatchConfig) -> Self {
+        Self {
+            config,
+            queue: OnceLock::new(),
+        }
+    }
+
+    /// Run `pipe`, batched with other writes when batching is on
+    ///
+    /// Replies are discarded. Fails with `CerberusError::RateLimited` when
+    /// the queue is full.
+    pub async fn write(&self, redis: &mut ConnectionManager, pipe: redis::Pipeline) -> Result<()> {
+        if !self.config.enabled {
+            pipe.query_async::<()>(redis).await?;
+            return Ok(());
+        }
+
+        let (done, result) = oneshot::channel();
+        let write = Write {
+            pipe,
+            redis: redis.clone(),
+            done,
+        };
+        match self.queue().try_send(write) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                metrics::CHALLENGE_WRITES_REFUSED.inc();
+                tracing::warn!("Challenge write queue full");
+                return Err(CerberusError::RateLimited(
+                    "Challenge issuance is temporarily saturated".to_string(),
+                )
+                .into());
+            }
+            Err(TrySendError::Closed(_)) => {
+                return Err(CerberusError::Redis("challenge writer stopped".to_string()).into());
+            }
+        }
+
+        match result.await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => Err(CerberusError::Redis(e).into()),
+            Err(_) => Err(CerberusError::Redis("challenge write dropped".to_string()).into()),
+        }
+    }
+
+    fn queue(&self) -> &mpsc::Sender<Write> {
+        self.queue.get_or_init(|| {
+            let (tx, rx) = mpsc::channel(self.config.queue_capacity.max(1));
+            tokio::spawn(flush_task(
+                rx,
+                self.config.max_ops.max(1),
+                Duration::from_millis(self.config.flush_ms),
+            ));
+            tx
+        })
+    }
+}
+
+/// Collect writes until `max_ops` or `flush_after` since the first, then
+/// send them together
+async fn flush_task(mut queue: mpsc::Receiver<Write>, max_ops: usize, flush_after: Duration) {
+    let mut batch = Vec::with_capacity(max_ops);
+    while let Some(first) = queue.recv().await {
+        batch.push(first);
+        let deadline = tokio::time::Instant::now() + flush_after;
+        while batch.len() < max_ops {
+            match tokio::time::timeout_at(deadline, queue.recv()).await {
+                Ok(Some(write)) => batch.push(write),
+                Ok(None) | Err(_) => break,
+            }
+        }
+        flush(&mut batch).await;
+    }
+}
+
+/// Send a batch as one pipeline and report the outcome to every writer
+async fn flush(batch: &mut Vec<Write>) {
+    let Some(mut redis) = batch.first().map(|write| write.redis.clone()) else {
+        return;
+    };
+    let mut pipe = redis::pipe();
+    for write in batch.iter() {
+        merge(&mut pipe, &write.pipe);
+    }
+    metrics::CHALLENGE_WRITE_BATCH.observe(batch.len() as f64);
+
+    let result = pipe
+        .query_async::<()>(&mut redis)
+        .await
+        .map_err(|e| e.to_string());
+    if let Err(ref e) = result {
+        tracing::warn!(error = %e, writes = batch.len(), "Batched challenge write failed");
+    }
+    for write in batch.drain(..) {
+        let _ = write.done.send(result.clone());
+    }
+}
+
+/// Append `from`'s commands to `into`, replies ignored
+fn merge(into: &mut redis::Pipeline, from: &redis::Pipeline) {
+    for cmd in from.cmd_iter() {
+        into.add_command(cmd.clone()).ignore();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_keeps_commands_in_order() {
+        let mut first = redis::pipe();
+        first.set_ex("cerberus:challenge:a", "1", 300).ignore();
+        first.lpush("cerberus:events:c", "issued").ignore();
+        let mut second = redis::pipe();
+        second.set_ex("cerberus:challenge:b", "2", 300);
+
+        let mut batch = redis::pipe();
+        merge(&mut batch, &first);
+        merge(&mut batch, &second);
+
+        let packed = String::from_utf8_lossy(&batch.get_packed_pipeline()).into_owned();
+        let a = packed.find("cerberus:challenge:a").unwrap();
+        let events = packed.find("cerberus:events:c").unwrap();
+        let b = packed.find("cerberus:challenge:b").unwrap();
+        assert!(a < events && events < b);
+        assert_eq!(batch.cmd_iter().count(), 3);
+    }
+}
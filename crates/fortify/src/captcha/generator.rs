@@ -1,179 +1,524 @@
-//! CAPTCHA image generation.
+//! CAPTCHA challenge issuance.
 //!
-//! MVP: Generates simple text-based placeholder images.
-//! The text shows random characters that the user must type.
+//! Puzzles come from the provider configured for the difficulty (see
+//! `provider`); this module handles IDs, storage, issuance caps and
+//! sealing for offline mode.
 
 use anyhow::Result;
-use base64::{Engine, engine::general_purpose::STANDARD};
-use cerberus_common::{CaptchaChallenge, CaptchaDifficulty};
+use base64::Engine;
+use cerberus_common::{
+    CaptchaChallenge, CaptchaDifficulty, CerberusError, ChallengeId, CircuitId, redis_keys,
+    versioned,
+};
 use rand::Rng;
-use redis::AsyncCommands;
+use redis::{AsyncCommands, ExistenceCheck, SetExpiry, SetOptions};
+use std::sync::Arc;
 
-use super::StoredChallenge;
+use super::batch::ChallengeWriter;
+use super::provider::ProviderRegistry;
+use super::stateless::ChallengeSealer;
+use super::{StoredChallenge, take_script};
+use crate::abandonment::{AbandonmentConfig, AbandonmentTracker, pending_member};
+use crate::challenge_stats::{ChallengeStats, ChallengeStatsConfig};
+use crate::circuits::{CircuitEvent, CircuitEventKind, EventLog};
+use crate::config::{ImageFormat, WriteBatchConfig};
+use crate::degradation::DegradationState;
+use crate::experiments::{Experiments, Outcome};
+use crate::metrics;
 
 /// CAPTCHA generator service
 pub struct CaptchaGenerator {
     /// Challenge TTL in seconds
     pub challenge_ttl: u64,
+    /// Max unanswered challenges a single circuit may hold (0 = unlimited)
+    pub max_outstanding_per_circuit: u32,
+    /// Max challenges issued per second across all circuits (0 = unlimited)
+    pub max_issued_per_second: u32,
+    /// Redis degradation mode (shorter TTLs, storage refusal)
+    degradation: Arc<DegradationState>,
+    /// Seals offline challenges that need no Redis storage
+    sealer: Arc<ChallengeSealer>,
+    /// Circuit history (records issued challenges)
+    events: EventLog,
+    /// Challenge providers, selected per difficulty
+    providers: Arc<ProviderRegistry>,
+    /// Stores challenges (batched if configured)
+    writer: ChallengeWriter,
+    /// Store images for `/challenge/{id}/image` rather than inlining them
+    serve_images: bool,
+    /// Experiments that may change a circuit's provider and difficulty
+    experiments: Arc<Experiments>,
+    /// Lists challenges until answered, to spot abandoned ones
+    abandonment: Arc<AbandonmentTracker>,
+    /// Tallies challenges issued per difficulty
+    stats: Arc<ChallengeStats>,
 }
 
 impl CaptchaGenerator {
-    pub fn new(challenge_ttl: u64) -> Self {
-        Self { challenge_ttl }
+    pub fn new(
+        challenge_ttl: u64,
+        max_outstanding_per_circuit: u32,
+        max_issued_per_second: u32,
+        degradation: Arc<DegradationState>,
+        sealer: Arc<ChallengeSealer>,
+        events: EventLog,
+        providers: Arc<ProviderRegistry>,
+    ) -> Self {
+        Self {
+            challenge_ttl,
+            max_outstanding_per_circuit,
+            max_issued_per_second,
+            degradation,
+            sealer,
+            events,
+            providers,
+            writer: ChallengeWriter::new(WriteBatchConfig {
+                enabled: false,
+                ..Default::default()
+            }),
+            serve_images: false,
+            experiments: Arc::default(),
+            abandonment: Arc::new(AbandonmentTracker::new(AbandonmentConfig {
+                enabled: false,
+                ..Default::default()
+            })),
+            stats: Arc::new(ChallengeStats::new(ChallengeStatsConfig {
+                enabled: false,
+                ..Default::default()
+            })),
+        }
+    }
+
+    /// Batch challenge writes as `config` says
+    pub fn with_write_batch(mut self, config: WriteBatchConfig) -> Self {
+        self.writer = ChallengeWriter::new(config);
+        self
+    }
+
+    /// Hand out image URLs instead of `data:` URIs (`captcha.serve_images`)
+    pub fn with_served_images(mut self, serve_images: bool) -> Self {
+        self.serve_images = serve_images;
+        self
+    }
+
+    /// Draw challenges for enrolled circuits as their variants say
+    pub fn with_experiments(mut self, experiments: Arc<Experiments>) -> Self {
+        self.experiments = experiments;
+        self
+    }
+
+    /// Track challenges until answered with `abandonment`
+    pub fn with_abandonment(mut self, abandonment: Arc<AbandonmentTracker>) -> Self {
+        self.abandonment = abandonment;
+        self
+    }
+
+    /// Tally challenges issued per difficulty into `stats`
+    pub fn with_challenge_stats(mut self, stats: Arc<ChallengeStats>) -> Self {
+        self.stats = stats;
+        self
     }
 
     /// Generate a new CAPTCHA challenge
+    ///
+    /// Fails with `CerberusError::RateLimited` when the global issuance rate
+    /// or the circuit's outstanding-challenge cap is exceeded, so unanswered
+    /// challenges cannot be used to exhaust Redis memory, and with
+    /// `CerberusError::Redis` while Redis is in critical degradation mode.
+    /// Circuits in an experiment get their variant's provider and difficulty,
+    /// and the challenge lives as long as that difficulty's answer time.
+    #[tracing::instrument(name = "captcha.generate", skip(self, redis))]
     pub async fn generate(
         &self,
         redis: &mut redis::aio::ConnectionManager,
-        circuit_id: Option<String>,
+        circuit_id: Option<CircuitId>,
         difficulty: CaptchaDifficulty,
+        format: ImageFormat,
     ) -> Result<CaptchaChallenge> {
-        let challenge_id = self.generate_challenge_id();
-        let (answer, image_data) = self.create_placeholder_captcha(difficulty);
+        if self.degradation.refuses_new_challenges() {
+            return Err(CerberusError::Redis(
+                "Redis under pressure, not storing new challenges".to_string(),
+            )
+            .into());
+        }
+
+        let started = std::time::Instant::now();
+        let challenge_id = new_challenge_id();
+        let treatment = self.experiments.treatment(circuit_id.as_ref(), difficulty);
+        let difficulty = treatment.difficulty;
+        let ttl = self.ttl_for(difficulty);
 
-        let now = chrono::Utc::now().timestamp();
-        let expires_at = now + self.challenge_ttl as i64;
+        let issued_at = chrono::Utc::now();
+        let now = issued_at.timestamp();
+        let expires_at = now + ttl as i64;
+
+        // Enforce issuance caps before doing any rendering work. The
+        // circuit's cap goes first, so a circuit it turns away doesn't use
+        // up the global budget.
+        if let Some(ref cid) = circuit_id {
+            self.reserve_outstanding(redis, cid, &challenge_id, now, expires_at)
+                .await?;
+        }
+        if let Err(e) = self.check_global_rate(redis, now).await {
+            if let Some(ref cid) = circuit_id {
+                redis
+                    .zrem::<_, _, ()>(redis_keys::outstanding(cid), &challenge_id)
+                    .await?;
+            }
+            return Err(e);
+        }
+
+        let provider = self.providers.select(difficulty, treatment.provider);
+        let puzzle = provider.generate(difficulty);
+        let mut image_data = provider.render(&puzzle, difficulty, format);
 
         // Store challenge in Redis
         let stored = StoredChallenge {
-            answer: answer.clone(),
+            answer: puzzle.answer,
+            provider: provider.name().to_string(),
             circuit_id: circuit_id.clone(),
             difficulty,
             created_at: now,
+            issued_at_ms: issued_at.timestamp_millis(),
             expires_at,
         };
 
-        let key = format!("captcha:{}", challenge_id);
-        let value = serde_json::to_string(&stored)?;
-        redis
-            .set_ex::<_, _, ()>(&key, &value, self.challenge_ttl)
-            .await?;
+        let key = redis_keys::challenge(&challenge_id);
+        let value = versioned::encode(&stored)?;
+        let mut pipe = redis::pipe();
+        pipe.set_ex(&key, &value, ttl).ignore();
+        if self.serve_images {
+            let image_key = redis_keys::challenge_image(&challenge_id);
+            pipe.set_ex(&image_key, &image_data, ttl).ignore();
+            image_data = image_path(&challenge_id);
+        }
+        if let Some(ref cid) = circuit_id {
+            let event = CircuitEvent::new(CircuitEventKind::ChallengeIssued)
+                .with_detail(format!("{:?}", difficulty).to_lowercase());
+            self.events.queue(&mut pipe, cid, &event);
+        }
+        self.experiments
+            .queue(&mut pipe, circuit_id.as_ref(), Outcome::Issued);
+        self.stats
+            .queue(&mut pipe, difficulty, Outcome::Issued, now);
+        self.abandonment.queue_issued(
+            &mut pipe,
+            &challenge_id,
+            circuit_id.as_ref(),
+            now,
+            expires_at,
+        );
+        self.writer.write(redis, pipe).await?;
 
         tracing::debug!(
             challenge_id = %challenge_id,
             circuit_id = ?circuit_id,
             difficulty = ?difficulty,
+            provider = provider.name(),
             "Generated CAPTCHA challenge"
         );
+        metrics::CAPTCHA_GENERATE_SECONDS.observe(started.elapsed().as_secs_f64());
 
         Ok(CaptchaChallenge {
             challenge_id,
             image_data,
             grid_size: difficulty.grid_size(),
-            instructions: self.get_instructions(difficulty),
+            instructions: provider.instructions(difficulty),
             expected_positions: vec![], // Not sent to client
             expires_at,
         })
     }
 
-    /// Generate a cryptographically random challenge ID
-    fn generate_challenge_id(&self) -> String {
-        use base64::engine::general_purpose::URL_SAFE_NO_PAD;
-        let mut bytes = [0u8; 16];
-        rand::rng().fill(&mut bytes);
-        URL_SAFE_NO_PAD.encode(bytes)
+    /// Lifetime of a challenge at `difficulty`: its answer time, capped by
+    /// `challenge_ttl_secs` (and shorter still when Redis is degraded)
+    fn ttl_for(&self, difficulty: CaptchaDifficulty) -> u64 {
+        let ttl = self.challenge_ttl.min(u64::from(difficulty.timeout_secs()));
+        self.degradation.challenge_ttl(ttl)
     }
 
-    /// Create a placeholder CAPTCHA (MVP)
+    /// Global fixed-window (1s) issuance limiter (only challenges issued
+    /// count, not requests turned away)
+    async fn check_global_rate(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        now: i64,
+    ) -> Result<()> {
+        if self.max_issued_per_second == 0 {
+            return Ok(());
+        }
+
+        let key = redis_keys::issued(now);
+        let ttl = key
+            .ttl()
+            .secs()
+            .expect("issuance counters have a fixed TTL");
+        let issued: i32 = issue_script()
+            .key(&key)
+            .arg(self.max_issued_per_second)
+            .arg(ttl)
+            .invoke_async(redis)
+            .await?;
+
+        if issued == 0 {
+            tracing::warn!(
+                cap = self.max_issued_per_second,
+                "Global challenge issuance cap reached"
+            );
+            return Err(CerberusError::RateLimited(
+                "Challenge issuance is temporarily saturated".to_string(),
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Record a new outstanding challenge for a circuit, enforcing the cap
+    async fn reserve_outstanding(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        circuit_id: &CircuitId,
+        challenge_id: &ChallengeId,
+        now: i64,
+        expires_at: i64,
+    ) -> Result<()> {
+        if self.max_outstanding_per_circuit == 0 {
+            return Ok(());
+        }
+
+        let key = redis_keys::outstanding(circuit_id);
+        let reserved: i32 = reserve_script()
+            .key(&key)
+            .arg(now)
+            .arg(self.max_outstanding_per_circuit)
+            .arg(expires_at)
+            .arg(challenge_id)
+            .arg(self.challenge_ttl)
+            .invoke_async(redis)
+            .await?;
+
+        if reserved == 0 {
+            tracing::debug!(circuit_id = %circuit_id, "Outstanding challenge cap reached");
+            return Err(CerberusError::RateLimited(
+                "Too many unanswered challenges. Solve or wait for one to expire.".to_string(),
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+
+    /// Generate a sealed challenge that can be verified without Redis
     ///
-    /// Returns (answer, base64_image_data)
-    fn create_placeholder_captcha(&self, difficulty: CaptchaDifficulty) -> (String, String) {
-        let mut rng = rand::rng();
-
-        // Generate random alphanumeric answer
-        let length = match difficulty {
-            CaptchaDifficulty::Easy => 4,
-            CaptchaDifficulty::Medium => 5,
-            CaptchaDifficulty::Hard => 6,
-            CaptchaDifficulty::Extreme => 8,
-        };
+    /// Used in offline mode; the answer travels (MAC'd) in the challenge ID.
+    pub fn generate_stateless(
+        &self,
+        difficulty: CaptchaDifficulty,
+        format: ImageFormat,
+    ) -> CaptchaChallenge {
+        let provider = self.providers.for_difficulty(difficulty);
+        let puzzle = provider.generate(difficulty);
+        let image_data = provider.render(&puzzle, difficulty, format);
+        let ttl = self.ttl_for(difficulty);
+        let expires_at = chrono::Utc::now().timestamp() + ttl as i64;
 
-        let answer: String = (0..length)
-            .map(|_| {
-                let idx = rng.random_range(0..36);
-                if idx < 10 {
-                    (b'0' + idx) as char
-                } else {
-                    (b'A' + idx - 10) as char
-                }
-            })
-            .collect();
+        let challenge_id = self
+            .sealer
+            .seal(
+                &puzzle.answer,
+                provider.case_insensitive(difficulty),
+                expires_at,
+            )
+            .parse()
+            .expect("sealed IDs are valid challenge IDs");
 
-        // Create a simple SVG placeholder (works without image libraries)
-        let svg = self.create_svg_captcha(&answer, difficulty);
-        let image_data = format!("data:image/svg+xml;base64,{}", STANDARD.encode(&svg));
+        tracing::debug!(difficulty = ?difficulty, "Generated sealed CAPTCHA challenge");
 
-        (answer, image_data)
+        CaptchaChallenge {
+            challenge_id,
+            image_data,
+            grid_size: difficulty.grid_size(),
+            instructions: provider.instructions(difficulty),
+            expected_positions: vec![],
+            expires_at,
+        }
     }
 
-    /// Create an SVG CAPTCHA image
-    fn create_svg_captcha(&self, text: &str, difficulty: CaptchaDifficulty) -> String {
-        let mut rng = rand::rng();
+    /// Atomically invalidate a challenge and issue a replacement
+    ///
+    /// The replacement stays bound to the old challenge's circuit (falling
+    /// back to `circuit_id` if the old one was unbound). Returns `None` if
+    /// the old challenge has already expired or been used. If no
+    /// replacement can be issued, the old challenge is put back and stays
+    /// answerable.
+    pub async fn refresh(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        challenge_id: &ChallengeId,
+        circuit_id: Option<CircuitId>,
+        difficulty: CaptchaDifficulty,
+        format: ImageFormat,
+    ) -> Result<Option<CaptchaChallenge>> {
+        let key = redis_keys::challenge(challenge_id);
 
-        let width = 200;
-        let height = 80;
+        let stored: Option<String> = take_script().key(&key).invoke_async(redis).await?;
+        let Some(stored) = stored else {
+            return Ok(None);
+        };
 
-        // Background noise based on difficulty
-        let noise_count = match difficulty {
-            CaptchaDifficulty::Easy => 5,
-            CaptchaDifficulty::Medium => 15,
-            CaptchaDifficulty::Hard => 30,
-            CaptchaDifficulty::Extreme => 50,
+        let old: StoredChallenge = versioned::decode(&stored)?;
+        // Its slot is freed first, so a circuit at its outstanding cap can
+        // still swap a challenge
+        let replaced = async {
+            release_outstanding(redis, old.circuit_id.as_ref(), challenge_id).await?;
+            self.generate(
+                redis,
+                old.circuit_id.clone().or(circuit_id),
+                difficulty,
+                format,
+            )
+            .await
+        }
+        .await;
+        let challenge = match replaced {
+            Ok(challenge) => challenge,
+            Err(e) => {
+                if let Err(restore) = self.restore(redis, challenge_id, &stored, &old).await {
+                    tracing::warn!(
+                        error = %restore,
+                        challenge_id = %challenge_id,
+                        "Failed to put back a challenge after a failed refresh"
+                    );
+                }
+                return Err(e);
+            }
         };
 
-        let mut svg = format!(
-            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}">"#,
-            width, height
+        tracing::debug!(
+            old_challenge_id = %challenge_id,
+            challenge_id = %challenge.challenge_id,
+            "Refreshed CAPTCHA challenge"
         );
 
-        // Background
-        svg.push_str(r##"<rect width="100%" height="100%" fill="#1a1a2e"/>"##);
-
-        // Noise lines
-        for _ in 0..noise_count {
-            let x1 = rng.random_range(0..width);
-            let y1 = rng.random_range(0..height);
-            let x2 = rng.random_range(0..width);
-            let y2 = rng.random_range(0..height);
-            let opacity = rng.random_range(20..50);
-            svg.push_str(&format!(
-                r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="rgba(255,255,255,0.{})" stroke-width="1"/>"#,
-                x1, y1, x2, y2, opacity
-            ));
-        }
+        Ok(Some(challenge))
+    }
 
-        // Text characters with slight randomization
-        let char_width = width as f32 / (text.len() as f32 + 1.0);
-        for (i, c) in text.chars().enumerate() {
-            let x = char_width * (i as f32 + 0.8);
-            let y = 50 + rng.random_range(-10..10);
-            let rotation = rng.random_range(-15..15);
-            let color = format!(
-                "rgb({},{},{})",
-                rng.random_range(150..255),
-                rng.random_range(150..255),
-                rng.random_range(150..255)
-            );
+    /// Put back a challenge taken by `refresh`, with its outstanding slot
+    /// and pending entry, for the rest of its lifetime
+    async fn restore(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        challenge_id: &ChallengeId,
+        stored: &str,
+        old: &StoredChallenge,
+    ) -> Result<()> {
+        let ttl = old.expires_at - chrono::Utc::now().timestamp();
+        if ttl <= 0 {
+            return Ok(());
+        }
 
-            svg.push_str(&format!(
-                r#"<text x="{}" y="{}" font-family="monospace" font-size="32" font-weight="bold" fill="{}" transform="rotate({} {} {})">{}</text>"#,
-                x, y, color, rotation, x, y, c
-            ));
+        let mut pipe = redis::pipe();
+        let options = SetOptions::default()
+            .conditional_set(ExistenceCheck::NX)
+            .with_expiration(SetExpiry::EX(ttl as u64));
+        pipe.set_options(redis_keys::challenge(challenge_id), stored, options)
+            .ignore();
+        if let Some(ref cid) = old.circuit_id
+            && self.max_outstanding_per_circuit > 0
+        {
+            let key = redis_keys::outstanding(cid);
+            pipe.zadd(&key, challenge_id, old.expires_at)
+                .ignore()
+                .expire(&key, self.challenge_ttl as i64)
+                .ignore();
         }
+        self.abandonment.queue_pending(
+            &mut pipe,
+            challenge_id,
+            old.circuit_id.as_ref(),
+            old.expires_at,
+        );
+        pipe.query_async::<()>(redis).await?;
+        Ok(())
+    }
 
-        svg.push_str("</svg>");
-        svg
+    /// The stored image of a challenge, as a `data:` URI
+    pub async fn image(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        challenge_id: &ChallengeId,
+    ) -> Result<Option<String>> {
+        Ok(redis.get(redis_keys::challenge_image(challenge_id)).await?)
     }
+}
 
-    fn get_instructions(&self, difficulty: CaptchaDifficulty) -> String {
-        match difficulty {
-            CaptchaDifficulty::Easy => "Type the characters shown above".to_string(),
-            CaptchaDifficulty::Medium => {
-                "Type the characters shown above (case insensitive)".to_string()
-            }
-            CaptchaDifficulty::Hard => "Type the characters exactly as shown".to_string(),
-            CaptchaDifficulty::Extreme => "Type the characters within 20 seconds".to_string(),
-        }
+/// Generate a cryptographically random challenge ID
+pub(super) fn new_challenge_id() -> ChallengeId {
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    let mut bytes = [0u8; 16];
+    rand::rng().fill(&mut bytes);
+    URL_SAFE_NO_PAD
+        .encode(bytes)
+        .parse()
+        .expect("base64url is a valid challenge ID")
+}
+
+/// Where a stored challenge's image is served (`captcha.serve_images`)
+pub fn image_path(challenge_id: &ChallengeId) -> String {
+    format!("/challenge/{}/image", challenge_id)
+}
+
+/// Drop a consumed challenge from its circuit's outstanding set and the
+/// pending set (it wasn't abandoned)
+pub(crate) async fn release_outstanding(
+    redis: &mut redis::aio::ConnectionManager,
+    circuit_id: Option<&CircuitId>,
+    challenge_id: &ChallengeId,
+) -> Result<()> {
+    let mut pipe = redis::pipe();
+    pipe.zrem(
+        redis_keys::pending_challenges(),
+        pending_member(challenge_id, circuit_id),
+    )
+    .ignore();
+    if let Some(cid) = circuit_id {
+        let key = redis_keys::outstanding(cid);
+        pipe.zrem(&key, challenge_id).ignore();
     }
+    pipe.query_async::<()>(redis).await?;
+    Ok(())
+}
+
+/// Count an issued challenge if the window is under the cap
+///
+/// ARGV: cap, ttl. Returns 1 if counted.
+fn issue_script() -> redis::Script {
+    redis::Script::new(
+        r"
+        local count = tonumber(redis.call('GET', KEYS[1]) or '0')
+        if count >= tonumber(ARGV[1]) then
+            return 0
+        end
+        redis.call('INCR', KEYS[1])
+        redis.call('EXPIRE', KEYS[1], ARGV[2])
+        return 1
+        ",
+    )
+}
+
+/// Prune expired entries, then add the challenge if under the cap
+///
+/// ARGV: now, max, expires_at, challenge_id, ttl. Returns 1 if reserved.
+fn reserve_script() -> redis::Script {
+    redis::Script::new(
+        r"
+        redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', ARGV[1])
+        if redis.call('ZCARD', KEYS[1]) >= tonumber(ARGV[2]) then
+            return 0
+        end
+        redis.call('ZADD', KEYS[1], ARGV[3], ARGV[4])
+        redis.call('EXPIRE', KEYS[1], ARGV[5])
+        return 1
+        ",
+    )
 }
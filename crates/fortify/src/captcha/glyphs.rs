@@ -0,0 +1,198 @@
+//! Built-in stroke font for CAPTCHA images.
+//!
+//! Each glyph is a few polylines on a 4x6 grid. Rendering bakes scale,
+//! rotation, skew and per-point jitter into absolute path coordinates, so the
+//! SVG carries only `<path>` geometry: no characters, no font, no transforms
+//! that could be undone by reading the markup.
+
+use cerberus_common::CaptchaDifficulty;
+use rand::Rng;
+use std::fmt::Write;
+
+/// Grid width of a glyph
+const GRID_W: f32 = 4.0;
+/// Grid height of a glyph
+const GRID_H: f32 = 6.0;
+
+/// Strokes for `c`: polylines separated by `|`, points as `x,y` (y down)
+fn strokes(c: char) -> Option<&'static str> {
+    Some(match c.to_ascii_uppercase() {
+        'A' => "0,6 2,0 4,6|1,3 3,3",
+        'B' => "0,0 0,6 3,6 4,5 4,4 3,3 0,3|0,0 3,0 4,1 4,2 3,3",
+        'C' => "4,1 3,0 1,0 0,1 0,5 1,6 3,6 4,5",
+        'D' => "0,0 0,6 2,6 4,4 4,2 2,0 0,0",
+        'E' => "4,0 0,0 0,6 4,6|0,3 3,3",
+        'F' => "4,0 0,0 0,6|0,3 3,3",
+        'G' => "4,1 3,0 1,0 0,1 0,5 1,6 3,6 4,5 4,3 2,3",
+        'H' => "0,0 0,6|4,0 4,6|0,3 4,3",
+        'I' => "1,0 3,0|2,0 2,6|1,6 3,6",
+        'J' => "4,0 4,5 3,6 1,6 0,5",
+        'K' => "0,0 0,6|4,0 0,3 4,6",
+        'L' => "0,0 0,6 4,6",
+        'M' => "0,6 0,0 2,3 4,0 4,6",
+        'N' => "0,6 0,0 4,6 4,0",
+        'O' => "1,0 3,0 4,1 4,5 3,6 1,6 0,5 0,1 1,0",
+        'P' => "0,6 0,0 3,0 4,1 4,2 3,3 0,3",
+        'Q' => "1,0 3,0 4,1 4,5 3,6 1,6 0,5 0,1 1,0|2,4 4,6",
+        'R' => "0,6 0,0 3,0 4,1 4,2 3,3 0,3|2,3 4,6",
+        'S' => "4,1 3,0 1,0 0,1 0,2 1,3 3,3 4,4 4,5 3,6 1,6 0,5",
+        'T' => "0,0 4,0|2,0 2,6",
+        'U' => "0,0 0,5 1,6 3,6 4,5 4,0",
+        'V' => "0,0 2,6 4,0",
+        'W' => "0,0 1,6 2,3 3,6 4,0",
+        'X' => "0,0 4,6|4,0 0,6",
+        'Y' => "0,0 2,3 4,0|2,3 2,6",
+        'Z' => "0,0 4,0 0,6 4,6",
+        // Slashed, so it can't be mistaken for O
+        '0' => "1,0 3,0 4,1 4,5 3,6 1,6 0,5 0,1 1,0|0,5 4,1",
+        '1' => "1,1 2,0 2,6|1,6 3,6",
+        '2' => "0,1 1,0 3,0 4,1 4,2 0,6 4,6",
+        '3' => "0,1 1,0 3,0 4,1 4,2 3,3 4,4 4,5 3,6 1,6 0,5|1,3 3,3",
+        '4' => "3,6 3,0 0,4 4,4",
+        '5' => "4,0 0,0 0,3 3,3 4,4 4,5 3,6 1,6 0,5",
+        '6' => "4,1 3,0 1,0 0,1 0,5 1,6 3,6 4,5 4,4 3,3 0,3",
+        '7' => "0,0 4,0 1,6",
+        '8' => "1,3 0,2 0,1 1,0 3,0 4,1 4,2 3,3 1,3 0,4 0,5 1,6 3,6 4,5 4,4 3,3",
+        '9' => "4,3 1,3 0,2 0,1 1,0 3,0 4,1 4,5 3,6 1,6 0,5",
+        '?' => "0,1 1,0 3,0 4,1 4,2 2,3 2,4|2,5.4 2,6",
+        '=' => "0,2 4,2|0,4 4,4",
+        _ => return None,
+    })
+}
+
+/// How far a glyph may be bent out of shape
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Distortion {
+    /// Max rotation either way, in degrees
+    pub rotation: f32,
+    /// Max horizontal shear either way (x shift per unit of y)
+    pub skew: f32,
+    /// Max displacement of each point either way, in pixels
+    pub jitter: f32,
+}
+
+impl Distortion {
+    /// Distortion used at each difficulty
+    pub fn for_difficulty(difficulty: CaptchaDifficulty) -> Self {
+        let profile = difficulty.profile();
+        Self {
+            rotation: profile.rotation,
+            skew: profile.skew,
+            jitter: profile.jitter,
+        }
+    }
+}
+
+/// Polylines (in pixels) for `c`, centred on `(cx, cy)` and `height` pixels tall
+///
+/// Returns `None` for characters without a glyph (including space).
+pub(super) fn outline(
+    c: char,
+    cx: f32,
+    cy: f32,
+    height: f32,
+    distortion: Distortion,
+    rng: &mut impl Rng,
+) -> Option<Vec<Vec<(f32, f32)>>> {
+    let mut lines: Vec<Vec<(f32, f32)>> = Vec::new();
+    trace(c, cx, cy, height, distortion, rng, |starts_line, point| {
+        if starts_line {
+            lines.push(Vec::new());
+        }
+        if let Some(line) = lines.last_mut() {
+            line.push(point);
+        }
+    })?;
+    Some(lines)
+}
+
+/// Write SVG path data for `c` (see `outline`) to `out`
+///
+/// Writes nothing and returns `None` for characters without a glyph.
+pub(super) fn write_path(
+    out: &mut impl Write,
+    c: char,
+    cx: f32,
+    cy: f32,
+    height: f32,
+    distortion: Distortion,
+    rng: &mut impl Rng,
+) -> Option<()> {
+    trace(c, cx, cy, height, distortion, rng, |starts_line, (x, y)| {
+        let cmd = if starts_line { 'M' } else { 'L' };
+        let _ = write!(out, "{}{:.1} {:.1}", cmd, x, y);
+    })
+}
+
+/// Feed the points of `c`'s outline to `point`, flagging the first of
+/// each polyline, without collecting them
+fn trace(
+    c: char,
+    cx: f32,
+    cy: f32,
+    height: f32,
+    distortion: Distortion,
+    rng: &mut impl Rng,
+    mut point: impl FnMut(bool, (f32, f32)),
+) -> Option<()> {
+    let strokes = strokes(c)?;
+
+    let scale = height / GRID_H * rng.random_range(0.85..1.15);
+    let stretch = rng.random_range(0.85..1.15);
+    let skew = rng.random_range(-distortion.skew..=distortion.skew);
+    let angle = rng
+        .random_range(-distortion.rotation..=distortion.rotation)
+        .to_radians();
+    let (sin, cos) = angle.sin_cos();
+
+    for stroke in strokes.split('|') {
+        for (i, grid_point) in stroke.split(' ').enumerate() {
+            let (gx, gy) = grid_point.split_once(',')?;
+            let gx: f32 = gx.parse().ok()?;
+            let gy: f32 = gy.parse().ok()?;
+
+            // Grid -> pixels around the glyph centre, then shear and rotate
+            let mut x = (gx - GRID_W / 2.0) * scale * stretch;
+            let y = (gy - GRID_H / 2.0) * scale;
+            x += skew * y;
+            let (x, y) = (x * cos - y * sin, x * sin + y * cos);
+
+            point(
+                i == 0,
+                (
+                    cx + x + rng.random_range(-distortion.jitter..=distortion.jitter),
+                    cy + y + rng.random_range(-distortion.jitter..=distortion.jitter),
+                ),
+            );
+        }
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glyphs_cover_alphabet() {
+        let mut rng = rand::rng();
+        let distortion = Distortion::for_difficulty(CaptchaDifficulty::Hard);
+        for c in ('A'..='Z').chain('0'..='9').chain("?=".chars()) {
+            let mut d = String::new();
+            write_path(&mut d, c, 50.0, 40.0, 36.0, distortion, &mut rng)
+                .unwrap_or_else(|| panic!("no glyph for {:?}", c));
+            assert!(d.starts_with('M'), "{:?}: {}", c, d);
+            // Path data is commands and numbers only
+            assert!(
+                d.chars()
+                    .all(|ch| "ML.- ".contains(ch) || ch.is_ascii_digit()),
+                "{:?}: {}",
+                c,
+                d
+            );
+        }
+        let mut d = String::new();
+        assert!(write_path(&mut d, ' ', 50.0, 40.0, 36.0, distortion, &mut rng).is_none());
+        assert!(d.is_empty());
+    }
+}
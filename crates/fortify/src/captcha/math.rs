@@ -0,0 +1,201 @@
+//! Math CAPTCHA: a short arithmetic or sequence puzzle, answered with a number.
+//!
+//! The prompt mixes digits and number words ("SEVEN PLUS 3 = ?"), so an
+//! OCR model that reads the characters back still has to understand them.
+//! Drawn with the same noisy SVG as the text provider.
+
+use cerberus_common::CaptchaDifficulty;
+use rand::Rng;
+
+use super::provider::{ChallengeProvider, Puzzle};
+use super::text::render_prompt;
+use crate::config::ImageFormat;
+
+/// Arithmetic ("7 PLUS THREE") and number-sequence ("2 4 6 ?") puzzles
+pub struct MathProvider;
+
+impl MathProvider {
+    pub const NAME: &'static str = "math";
+}
+
+const WORDS: [&str; 21] = [
+    "ZERO",
+    "ONE",
+    "TWO",
+    "THREE",
+    "FOUR",
+    "FIVE",
+    "SIX",
+    "SEVEN",
+    "EIGHT",
+    "NINE",
+    "TEN",
+    "ELEVEN",
+    "TWELVE",
+    "THIRTEEN",
+    "FOURTEEN",
+    "FIFTEEN",
+    "SIXTEEN",
+    "SEVENTEEN",
+    "EIGHTEEN",
+    "NINETEEN",
+    "TWENTY",
+];
+
+/// Spell out `n` as a word (0-20), or leave it as digits
+fn operand(n: u32, spelled: bool) -> String {
+    match WORDS.get(n as usize) {
+        Some(word) if spelled => word.to_string(),
+        _ => n.to_string(),
+    }
+}
+
+/// `a op b` with the result kept non-negative
+fn arithmetic(rng: &mut impl Rng, max: u32, times: bool, words: u32) -> Puzzle {
+    let mut a = rng.random_range(1..=max);
+    let mut b = rng.random_range(1..=max);
+    let op = rng.random_range(0..if times { 3 } else { 2 });
+    let (symbol, answer) = match op {
+        0 => ("PLUS", a + b),
+        1 => {
+            if a < b {
+                std::mem::swap(&mut a, &mut b);
+            }
+            ("MINUS", a - b)
+        }
+        _ => {
+            b = b.min(9);
+            ("TIMES", a * b)
+        }
+    };
+
+    // Spell out `words` of the two operands, picked at random
+    let spell_a = match words {
+        0 => false,
+        1 => rng.random_bool(0.5),
+        _ => true,
+    };
+    let spell_b = words >= 2 || (words == 1 && !spell_a);
+
+    Puzzle {
+        answer: answer.to_string(),
+        prompt: format!(
+            "{} {} {} = ?",
+            operand(a, spell_a),
+            symbol,
+            operand(b, spell_b)
+        ),
+    }
+}
+
+/// Four terms of an arithmetic sequence; the answer is the fifth
+fn sequence(rng: &mut impl Rng) -> Puzzle {
+    let start = rng.random_range(1..=20);
+    let step = rng.random_range(2..=9);
+    let terms: Vec<String> = (0..4).map(|i| (start + i * step).to_string()).collect();
+
+    Puzzle {
+        answer: (start + 4 * step).to_string(),
+        prompt: format!("{} ?", terms.join(" ")),
+    }
+}
+
+impl ChallengeProvider for MathProvider {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn generate(&self, difficulty: CaptchaDifficulty) -> Puzzle {
+        let mut rng = rand::rng();
+
+        match difficulty {
+            CaptchaDifficulty::Easy => arithmetic(&mut rng, 9, false, 0),
+            CaptchaDifficulty::Medium => arithmetic(&mut rng, 20, false, 1),
+            CaptchaDifficulty::Hard => arithmetic(&mut rng, 20, true, 1),
+            CaptchaDifficulty::Extreme => {
+                if rng.random_bool(0.3) {
+                    sequence(&mut rng)
+                } else {
+                    arithmetic(&mut rng, 20, true, 2)
+                }
+            }
+        }
+    }
+
+    fn render(
+        &self,
+        puzzle: &Puzzle,
+        difficulty: CaptchaDifficulty,
+        format: ImageFormat,
+    ) -> String {
+        render_prompt(&puzzle.prompt, difficulty, format)
+    }
+
+    fn instructions(&self, _difficulty: CaptchaDifficulty) -> String {
+        "Solve the puzzle above and type the answer as a number".to_string()
+    }
+
+    fn verify(&self, expected: &str, answer: &str, _difficulty: CaptchaDifficulty) -> bool {
+        match (expected.parse::<i64>(), answer.trim().parse::<i64>()) {
+            (Ok(expected), Ok(answer)) => expected == answer,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Work a prompt out the way a person would
+    fn solve(prompt: &str) -> i64 {
+        let value = |token: &str| {
+            WORDS
+                .iter()
+                .position(|w| *w == token)
+                .map(|n| n as i64)
+                .unwrap_or_else(|| token.parse().unwrap())
+        };
+        let tokens: Vec<&str> = prompt.split_whitespace().collect();
+        match tokens.as_slice() {
+            [a, op, b, "=", "?"] => match *op {
+                "PLUS" => value(a) + value(b),
+                "MINUS" => value(a) - value(b),
+                "TIMES" => value(a) * value(b),
+                other => panic!("unexpected operator {}", other),
+            },
+            [terms @ .., "?"] => {
+                let step = value(terms[1]) - value(terms[0]);
+                value(terms[terms.len() - 1]) + step
+            }
+            _ => panic!("unexpected prompt {}", prompt),
+        }
+    }
+
+    #[test]
+    fn test_answers_match_prompts() {
+        for difficulty in [
+            CaptchaDifficulty::Easy,
+            CaptchaDifficulty::Medium,
+            CaptchaDifficulty::Hard,
+            CaptchaDifficulty::Extreme,
+        ] {
+            for _ in 0..200 {
+                let puzzle = MathProvider.generate(difficulty);
+                let solved = solve(&puzzle.prompt);
+                assert!(solved >= 0, "{}", puzzle.prompt);
+                assert_eq!(puzzle.answer, solved.to_string(), "{}", puzzle.prompt);
+            }
+        }
+    }
+
+    #[test]
+    fn test_verify_is_numeric() {
+        let d = CaptchaDifficulty::Extreme;
+        assert!(MathProvider.verify("10", "10", d));
+        assert!(MathProvider.verify("10", " 010 ", d));
+        assert!(!MathProvider.verify("10", "11", d));
+        assert!(!MathProvider.verify("10", "ten", d));
+        assert!(!MathProvider.verify("10", "", d));
+    }
+}
@@ -1,30 +1,104 @@
 //! CAPTCHA generation and verification.
 //!
-//! MVP Implementation: Simple text-based placeholder CAPTCHA.
-//! Production: Will use image-based grid challenges.
+//! Puzzles are drawn by pluggable challenge providers (`ChallengeProvider`);
+//! the built-in ones are a text CAPTCHA and a math puzzle, both rendered as
+//! SVG. A JavaScript proof-of-work (`pow`) can come first, and a candidate
+//! provider can be tried out on a sample of visitors (`shadow`).
 
 mod ammo_box;
+mod batch;
 mod generator;
+mod glyphs;
+mod math;
+mod pow;
+mod provider;
+mod raster;
+mod shadow;
+mod stateless;
+mod text;
 mod verifier;
 
-pub use ammo_box::{AmmoBox, AmmoBoxConfig, AmmoBoxStatsSnapshot, PregenCaptcha, ammo_box_worker};
-pub use generator::CaptchaGenerator;
-pub use verifier::CaptchaVerifier;
+pub use ammo_box::{
+    AmmoBox, AmmoBoxConfig, AmmoBoxStatsSnapshot, DiskUsage, PregenCaptcha, StarvationConfig,
+    ammo_box_worker,
+};
+pub use generator::{CaptchaGenerator, image_path};
+pub use pow::{PowCheck, PowPuzzle};
+pub use provider::{ProviderRegistry, builtin_names};
+pub use shadow::{ShadowChallenge, ShadowConfig, ShadowTrials};
+pub use stateless::ChallengeSealer;
+pub use verifier::{CaptchaVerifier, ChallengeCheck, PassportGrant, PassportInfo};
 
-use cerberus_common::CaptchaDifficulty;
+use cerberus_common::{CaptchaDifficulty, CircuitId, Versioned};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
 /// Stored challenge data in Redis
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StoredChallenge {
     /// The expected answer (positions or text)
     pub answer: String,
+    /// Provider that issued the challenge (and checks the answer)
+    pub provider: String,
     /// Circuit ID that requested this challenge
-    pub circuit_id: Option<String>,
+    pub circuit_id: Option<CircuitId>,
     /// Difficulty level
     pub difficulty: CaptchaDifficulty,
     /// Creation timestamp
     pub created_at: i64,
+    /// Creation timestamp in milliseconds (for solve-time analysis)
+    #[serde(default)]
+    pub issued_at_ms: i64,
     /// Expiry timestamp
     pub expires_at: i64,
 }
+
+/// Atomic GET + DEL (GETDEL needs Redis 6.2+; Lua works on any version)
+pub(crate) fn take_script() -> redis::Script {
+    redis::Script::new(
+        r"
+        local value = redis.call('GET', KEYS[1])
+        if value then redis.call('DEL', KEYS[1]) end
+        return value
+        ",
+    )
+}
+
+/// Stored as `challenge:{id}`
+impl Versioned for StoredChallenge {
+    const VERSION: u32 = 1;
+
+    fn migrate(
+        from: u32,
+        mut record: Map<String, Value>,
+    ) -> serde_json::Result<Map<String, Value>> {
+        // Challenges stored before providers existed are text challenges
+        if from == 0 {
+            record
+                .entry("provider")
+                .or_insert_with(|| text::TextProvider::NAME.into());
+        }
+        Ok(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cerberus_common::versioned;
+
+    #[test]
+    fn test_stored_challenge_versions() {
+        // Written before providers (and versioning) existed
+        let legacy = r#"{"answer": "AB12", "circuit_id": null, "difficulty": "easy",
+            "created_at": 1700000000, "expires_at": 1700000300}"#;
+        let challenge: StoredChallenge = versioned::decode(legacy).unwrap();
+        assert_eq!(challenge.provider, text::TextProvider::NAME);
+        assert_eq!(challenge.issued_at_ms, 0);
+
+        let json = versioned::encode(&challenge).unwrap();
+        assert_eq!(versioned::version_of(&json), Some(StoredChallenge::VERSION));
+        let again: StoredChallenge = versioned::decode(&json).unwrap();
+        assert_eq!(again.answer, "AB12");
+    }
+}
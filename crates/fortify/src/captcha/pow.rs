@@ -0,0 +1,186 @@
+//! JavaScript proof-of-work for the progressive challenge flow.
+//!
+//! The gate page carries a puzzle seed; `/gate/pow.js` looks for a nonce
+//! whose `SHA-256("{seed}:{nonce}")` starts with `bits` zero bits and posts
+//! it to `/gate/pow`. The seed is signed and bound to the circuit it was
+//! issued to, so puzzles can't be forged or solved once for many circuits:
+//!
+//! `{expires_at}.{nonce}.{mac}`
+//!
+//! Single use is up to the caller (a Redis marker until `expires_at`).
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use cerberus_common::CircuitId;
+use sha2::{Digest, Sha256};
+
+use super::ChallengeSealer;
+
+/// A puzzle handed out with the gate page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PowPuzzle {
+    pub seed: String,
+    /// Leading zero bits a solution's hash needs
+    pub bits: u8,
+}
+
+/// Outcome of checking a solution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowCheck {
+    /// Solved; the seed should be spent until `expires_at`
+    Solved {
+        expires_at: i64,
+    },
+    /// Not enough zero bits
+    Unsolved,
+    Expired,
+    /// Forged, or issued to another circuit
+    Invalid,
+}
+
+impl PowPuzzle {
+    /// A new puzzle for `circuit_id`, valid for `ttl_secs`
+    pub fn issue(
+        sealer: &ChallengeSealer,
+        bits: u8,
+        ttl_secs: u64,
+        circuit_id: Option<&CircuitId>,
+    ) -> Self {
+        let mut nonce = [0u8; 16];
+        rand::Rng::fill(&mut rand::rng(), &mut nonce);
+        let nonce = URL_SAFE_NO_PAD.encode(nonce);
+        let expires_at = chrono::Utc::now().timestamp() + ttl_secs as i64;
+
+        let mac = sealer.sign(&signed_value(expires_at, &nonce, circuit_id));
+        Self {
+            seed: format!("{}.{}.{}", expires_at, nonce, mac),
+            bits,
+        }
+    }
+
+    /// Check `nonce` against `seed`, as posted back by the browser
+    pub fn check(
+        sealer: &ChallengeSealer,
+        seed: &str,
+        nonce: u64,
+        bits: u8,
+        circuit_id: Option<&CircuitId>,
+    ) -> PowCheck {
+        let parts: Vec<&str> = seed.split('.').collect();
+        let [expires_at, puzzle_nonce, mac] = parts[..] else {
+            return PowCheck::Invalid;
+        };
+        let Ok(expires_at) = expires_at.parse::<i64>() else {
+            return PowCheck::Invalid;
+        };
+        if !sealer.verify(&signed_value(expires_at, puzzle_nonce, circuit_id), mac) {
+            return PowCheck::Invalid;
+        }
+        if chrono::Utc::now().timestamp() > expires_at {
+            return PowCheck::Expired;
+        }
+
+        let hash = Sha256::digest(format!("{}:{}", seed, nonce).as_bytes());
+        if leading_zero_bits(&hash) >= u32::from(bits) {
+            PowCheck::Solved { expires_at }
+        } else {
+            PowCheck::Unsolved
+        }
+    }
+}
+
+fn signed_value(expires_at: i64, nonce: &str, circuit_id: Option<&CircuitId>) -> String {
+    format!(
+        "pow|{}|{}|{}",
+        expires_at,
+        nonce,
+        circuit_id.map(CircuitId::as_str).unwrap_or_default()
+    )
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solve(puzzle: &PowPuzzle) -> u64 {
+        (0..)
+            .find(|nonce| {
+                let hash = Sha256::digest(format!("{}:{}", puzzle.seed, nonce).as_bytes());
+                leading_zero_bits(&hash) >= u32::from(puzzle.bits)
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn test_pow_round_trip() {
+        let sealer = ChallengeSealer::new();
+        let circuit: CircuitId = "fc00:dead:beef:4dad::0:2a".parse().unwrap();
+        let puzzle = PowPuzzle::issue(&sealer, 8, 60, Some(&circuit));
+        let nonce = solve(&puzzle);
+
+        assert!(matches!(
+            PowPuzzle::check(&sealer, &puzzle.seed, nonce, 8, Some(&circuit)),
+            PowCheck::Solved { .. }
+        ));
+        // Another circuit, another node's key, a forged expiry
+        assert_eq!(
+            PowPuzzle::check(&sealer, &puzzle.seed, nonce, 8, None),
+            PowCheck::Invalid
+        );
+        assert_eq!(
+            PowPuzzle::check(
+                &ChallengeSealer::new(),
+                &puzzle.seed,
+                nonce,
+                8,
+                Some(&circuit)
+            ),
+            PowCheck::Invalid
+        );
+        let forged = format!("9{}", puzzle.seed);
+        assert_eq!(
+            PowPuzzle::check(&sealer, &forged, nonce, 8, Some(&circuit)),
+            PowCheck::Invalid
+        );
+    }
+
+    #[test]
+    fn test_pow_work_and_expiry() {
+        let sealer = ChallengeSealer::new();
+        let puzzle = PowPuzzle::issue(&sealer, 8, 60, None);
+        // Nonces that don't reach 8 bits are the norm
+        let unsolved = (0..)
+            .find(|nonce| {
+                let hash = Sha256::digest(format!("{}:{}", puzzle.seed, nonce).as_bytes());
+                leading_zero_bits(&hash) < 8
+            })
+            .unwrap();
+        assert_eq!(
+            PowPuzzle::check(&sealer, &puzzle.seed, unsolved, 8, None),
+            PowCheck::Unsolved
+        );
+
+        let past = chrono::Utc::now().timestamp() - 1;
+        let expired = PowPuzzle {
+            seed: format!("{}.n.{}", past, sealer.sign(&signed_value(past, "n", None))),
+            bits: 8,
+        };
+        assert_eq!(
+            PowPuzzle::check(&sealer, &expired.seed, solve(&expired), 8, None),
+            PowCheck::Expired
+        );
+
+        assert_eq!(leading_zero_bits(&[0, 0x10, 0xff]), 11);
+        assert_eq!(leading_zero_bits(&[0x80]), 0);
+    }
+}
@@ -0,0 +1,214 @@
+//! Challenge providers.
+//!
+//! A provider owns one kind of puzzle end to end: drawing it, rendering it
+//! for the client, and checking answers. Providers are registered by name in
+//! a `ProviderRegistry`; `[captcha.providers]` picks which one serves each
+//! difficulty (and so each threat level band). The stored challenge records
+//! the provider's name so answers are checked by whoever issued it.
+
+use cerberus_common::CaptchaDifficulty;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use super::math::MathProvider;
+use super::text::TextProvider;
+use crate::config::{ImageFormat, ProviderSelection};
+
+/// A freshly drawn puzzle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Puzzle {
+    /// Expected answer (stored server-side or sealed, never sent)
+    pub answer: String,
+    /// What the image shows (equal to `answer` for plain text puzzles)
+    pub prompt: String,
+}
+
+/// One kind of challenge (text, math, grid, ...)
+pub trait ChallengeProvider: Send + Sync {
+    /// Name used in `[captcha.providers]` and stored with each challenge
+    fn name(&self) -> &'static str;
+
+    /// Draw a new puzzle
+    fn generate(&self, difficulty: CaptchaDifficulty) -> Puzzle;
+
+    /// Render a puzzle as an image data URI in `format`
+    fn render(&self, puzzle: &Puzzle, difficulty: CaptchaDifficulty, format: ImageFormat)
+    -> String;
+
+    /// Instructions shown under the image
+    fn instructions(&self, difficulty: CaptchaDifficulty) -> String;
+
+    /// Does `answer` solve a puzzle whose expected answer is `expected`?
+    fn verify(&self, expected: &str, answer: &str, difficulty: CaptchaDifficulty) -> bool;
+
+    /// Whether sealed (offline) challenges compare answers ignoring case
+    ///
+    /// Sealed challenges cannot call `verify`: only a MAC of the answer
+    /// travels with them, so the comparison there is plain text.
+    fn case_insensitive(&self, _difficulty: CaptchaDifficulty) -> bool {
+        false
+    }
+}
+
+/// Built-in providers, by name
+fn builtin() -> Vec<Arc<dyn ChallengeProvider>> {
+    vec![Arc::new(TextProvider), Arc::new(MathProvider)]
+}
+
+/// Names of the built-in providers (for config validation)
+pub fn builtin_names() -> Vec<&'static str> {
+    builtin().iter().map(|p| p.name()).collect()
+}
+
+/// Registered providers and the per-difficulty selection
+pub struct ProviderRegistry {
+    providers: HashMap<&'static str, Arc<dyn ChallengeProvider>>,
+    selection: ProviderSelection,
+    /// Provider serving every difficulty for a while, overriding `selection`
+    forced: RwLock<Option<(String, Instant)>>,
+}
+
+impl ProviderRegistry {
+    /// Registry with the built-in providers
+    pub fn new(selection: ProviderSelection) -> Self {
+        let mut registry = Self {
+            providers: HashMap::new(),
+            selection,
+            forced: RwLock::new(None),
+        };
+        for provider in builtin() {
+            registry.register(provider);
+        }
+        registry
+    }
+
+    /// Add (or replace) a provider under its name
+    pub fn register(&mut self, provider: Arc<dyn ChallengeProvider>) {
+        self.providers.insert(provider.name(), provider);
+    }
+
+    /// Provider by name
+    pub fn get(&self, name: &str) -> Option<&dyn ChallengeProvider> {
+        self.providers.get(name).map(|p| p.as_ref())
+    }
+
+    /// Serve `name` at every difficulty for `duration` (e.g. under attack)
+    pub fn force(&self, name: &str, duration: Duration) {
+        *self.forced.write().unwrap_or_else(|e| e.into_inner()) =
+            Some((name.to_string(), Instant::now() + duration));
+    }
+
+    /// Provider configured for `difficulty` (text if the name is unknown),
+    /// unless one is forced
+    pub fn for_difficulty(&self, difficulty: CaptchaDifficulty) -> &dyn ChallengeProvider {
+        self.select(difficulty, None)
+    }
+
+    /// Like `for_difficulty`, but `preferred` (an experiment variant's
+    /// provider) replaces the configured one; a forced provider still wins
+    pub fn select(
+        &self,
+        difficulty: CaptchaDifficulty,
+        preferred: Option<&str>,
+    ) -> &dyn ChallengeProvider {
+        let forced = self
+            .forced
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .filter(|(_, until)| Instant::now() < *until)
+            .and_then(|(name, _)| self.get(name));
+        if let Some(provider) = forced {
+            return provider;
+        }
+
+        let name = preferred.unwrap_or_else(|| self.selection.for_difficulty(difficulty));
+        self.get(name).unwrap_or_else(|| {
+            tracing::warn!(provider = %name, "Unknown challenge provider, using text");
+            self.get(TextProvider::NAME)
+                .expect("text provider is always registered")
+        })
+    }
+}
+
+impl Default for ProviderRegistry {
+    fn default() -> Self {
+        Self::new(ProviderSelection::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fixed;
+
+    impl ChallengeProvider for Fixed {
+        fn name(&self) -> &'static str {
+            "fixed"
+        }
+        fn generate(&self, _: CaptchaDifficulty) -> Puzzle {
+            Puzzle {
+                answer: "42".to_string(),
+                prompt: "6 x 7".to_string(),
+            }
+        }
+        fn render(&self, puzzle: &Puzzle, _: CaptchaDifficulty, _: ImageFormat) -> String {
+            puzzle.prompt.clone()
+        }
+        fn instructions(&self, _: CaptchaDifficulty) -> String {
+            "Answer".to_string()
+        }
+        fn verify(&self, expected: &str, answer: &str, _: CaptchaDifficulty) -> bool {
+            expected == answer.trim()
+        }
+    }
+
+    #[test]
+    fn test_selection_per_difficulty() {
+        let mut registry = ProviderRegistry::new(ProviderSelection {
+            extreme: "fixed".to_string(),
+            ..Default::default()
+        });
+        assert_eq!(
+            registry.for_difficulty(CaptchaDifficulty::Extreme).name(),
+            "text"
+        );
+
+        registry.register(Arc::new(Fixed));
+        let provider = registry.for_difficulty(CaptchaDifficulty::Extreme);
+        assert_eq!(provider.name(), "fixed");
+        assert!(provider.verify("42", " 42 ", CaptchaDifficulty::Extreme));
+        assert_eq!(
+            registry.for_difficulty(CaptchaDifficulty::Easy).name(),
+            "text"
+        );
+
+        // An experiment variant's provider replaces the configured one...
+        assert_eq!(
+            registry
+                .select(CaptchaDifficulty::Easy, Some("fixed"))
+                .name(),
+            "fixed"
+        );
+
+        registry.force("math", Duration::from_secs(60));
+        assert_eq!(
+            registry.for_difficulty(CaptchaDifficulty::Easy).name(),
+            "math"
+        );
+        // ...but not a forced one
+        assert_eq!(
+            registry
+                .select(CaptchaDifficulty::Easy, Some("fixed"))
+                .name(),
+            "math"
+        );
+        registry.force("math", Duration::ZERO);
+        assert_eq!(
+            registry.for_difficulty(CaptchaDifficulty::Easy).name(),
+            "text"
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! Raster CAPTCHA output (PNG, WebP).
+//!
+//! Draws the same glyph outlines as the SVG renderer into a bitmap, bends
+//! the result along a sine wave and sprinkles it with noise. The encoded
+//! image has no markup to pick apart, and some Tor Browser setups display it
+//! more reliably than inline SVG.
+
+use anyhow::Result;
+use cerberus_common::CaptchaDifficulty;
+use image::{Rgb, RgbImage};
+use imageproc::drawing::{draw_filled_circle_mut, draw_line_segment_mut};
+use rand::Rng;
+use std::f32::consts::TAU;
+use std::io::Cursor;
+
+use super::glyphs::{self, Distortion};
+use super::text::{IMAGE_HEIGHT, encode_data_uri, image_width};
+use crate::config::ImageFormat;
+
+const BACKGROUND: Rgb<u8> = Rgb([0x1a, 0x1a, 0x2e]);
+
+/// Render `text` as a PNG or WebP data URI
+pub(super) fn data_uri(
+    text: &str,
+    difficulty: CaptchaDifficulty,
+    format: ImageFormat,
+) -> Result<String> {
+    let (encoding, mime) = match format {
+        ImageFormat::Webp => (image::ImageFormat::WebP, "image/webp"),
+        _ => (image::ImageFormat::Png, "image/png"),
+    };
+
+    let mut bytes = Vec::new();
+    draw(text, difficulty).write_to(&mut Cursor::new(&mut bytes), encoding)?;
+    Ok(encode_data_uri(mime, &bytes))
+}
+
+/// Draw, wave and speckle the image
+fn draw(text: &str, difficulty: CaptchaDifficulty) -> RgbImage {
+    let mut rng = rand::rng();
+    let width = image_width(text);
+    let height = IMAGE_HEIGHT;
+    let mut img = RgbImage::from_pixel(width, height, BACKGROUND);
+
+    let profile = difficulty.profile();
+    let (noise_lines, amplitude) = (u32::from(profile.noise_lines), profile.wave_amplitude);
+
+    // Noise lines
+    for _ in 0..noise_lines {
+        let start = (
+            rng.random_range(0..width) as f32,
+            rng.random_range(0..height) as f32,
+        );
+        let end = (
+            rng.random_range(0..width) as f32,
+            rng.random_range(0..height) as f32,
+        );
+        let shade = rng.random_range(60..110);
+        draw_line_segment_mut(&mut img, start, end, Rgb([shade, shade, shade]));
+    }
+
+    // Glyphs, stroked about 3px wide
+    let distortion = Distortion::for_difficulty(difficulty);
+    let char_width = width as f32 / (text.chars().count() as f32 + 1.0);
+    for (i, c) in text.chars().enumerate() {
+        let x = char_width * (i as f32 + 1.0);
+        let y = 40.0 + rng.random_range(-8.0..8.0);
+        let Some(lines) = glyphs::outline(c, x, y, 32.0, distortion, &mut rng) else {
+            continue;
+        };
+        let color = Rgb([
+            rng.random_range(150..255),
+            rng.random_range(150..255),
+            rng.random_range(150..255),
+        ]);
+        for line in lines {
+            for pair in line.windows(2) {
+                stroke(&mut img, pair[0], pair[1], color);
+            }
+        }
+    }
+
+    let mut img = wave(&img, amplitude, &mut rng);
+
+    // Speckles
+    for _ in 0..noise_lines * 20 {
+        let x = rng.random_range(0..width);
+        let y = rng.random_range(0..height);
+        let shade = rng.random_range(40..200);
+        img.put_pixel(x, y, Rgb([shade, shade, shade]));
+    }
+
+    img
+}
+
+/// Thick line: a run of small discs from `from` to `to`
+fn stroke(img: &mut RgbImage, from: (f32, f32), to: (f32, f32), color: Rgb<u8>) {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let steps = dx.hypot(dy).ceil().max(1.0) as i32;
+    for step in 0..=steps {
+        let t = step as f32 / steps as f32;
+        let x = (from.0 + dx * t).round() as i32;
+        let y = (from.1 + dy * t).round() as i32;
+        draw_filled_circle_mut(img, (x, y), 1, color);
+    }
+}
+
+/// Displace rows and columns along sine waves of random period and phase
+fn wave(img: &RgbImage, amplitude: f32, rng: &mut impl Rng) -> RgbImage {
+    let (width, height) = img.dimensions();
+    let period_x = rng.random_range(40.0..80.0);
+    let period_y = rng.random_range(30.0..60.0);
+    let phase_x = rng.random_range(0.0..TAU);
+    let phase_y = rng.random_range(0.0..TAU);
+
+    RgbImage::from_fn(width, height, |x, y| {
+        let sx = x as f32 + amplitude / 2.0 * (TAU * y as f32 / period_y + phase_y).sin();
+        let sy = y as f32 + amplitude * (TAU * x as f32 / period_x + phase_x).sin();
+        let sx = (sx.round() as i64).clamp(0, width as i64 - 1) as u32;
+        let sy = (sy.round() as i64).clamp(0, height as i64 - 1) as u32;
+        *img.get_pixel(sx, sy)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{Engine, engine::general_purpose::STANDARD};
+
+    #[test]
+    fn test_raster_formats_decode() {
+        for (format, mime, expected) in [
+            (ImageFormat::Png, "image/png", image::ImageFormat::Png),
+            (ImageFormat::Webp, "image/webp", image::ImageFormat::WebP),
+        ] {
+            let uri = data_uri("SEVEN PLUS 3 = ?", CaptchaDifficulty::Hard, format).unwrap();
+            let b64 = uri.strip_prefix(&format!("data:{};base64,", mime)).unwrap();
+            let bytes = STANDARD.decode(b64).unwrap();
+
+            assert_eq!(image::guess_format(&bytes).unwrap(), expected);
+            let img = image::load_from_memory(&bytes).unwrap();
+            assert_eq!(img.height(), IMAGE_HEIGHT);
+            assert_eq!(img.width(), image_width("SEVEN PLUS 3 = ?"));
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! Shadow trials of a candidate challenge provider.
+//!
+//! Before a provider serves real traffic, `[captcha.shadow]` shows its
+//! puzzles to a sample of visitors next to the real challenge, as an
+//! optional extra. Whatever they answer is recorded and never enforced:
+//! the real challenge alone decides whether they get a passport. Outcomes
+//! (solved, failed, skipped; against whether the real challenge was
+//! solved) and solve times are exported per provider, so its solve rate
+//! and the friction it adds can be compared with the provider in use.
+
+use anyhow::Result;
+use cerberus_common::{CaptchaDifficulty, ChallengeId, redis_keys};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use super::generator::new_challenge_id;
+use super::provider::ProviderRegistry;
+use super::take_script;
+use crate::config::ImageFormat;
+use crate::metrics;
+
+/// Shadow trial settings (`[captcha.shadow]` in fortify.toml)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShadowConfig {
+    pub enabled: bool,
+    /// Candidate provider whose puzzles are shown
+    pub provider: String,
+    /// Fraction of challenges that come with a shadow puzzle (0.0-1.0)
+    pub sample_rate: f64,
+}
+
+impl Default for ShadowConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            provider: "math".to_string(),
+            sample_rate: 0.05,
+        }
+    }
+}
+
+/// A shadow puzzle, as shown to the visitor
+#[derive(Debug, Clone, Serialize)]
+pub struct ShadowChallenge {
+    pub shadow_id: ChallengeId,
+    /// Image as a `data:` URI
+    pub image_data: String,
+    pub instructions: String,
+}
+
+/// Stored as `shadow:{id}` until answered or expired
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredShadow {
+    answer: String,
+    provider: String,
+    difficulty: CaptchaDifficulty,
+    issued_at_ms: i64,
+}
+
+/// What the visitor did with a shadow puzzle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShadowOutcome {
+    Solved,
+    Failed,
+    /// Left the optional answer empty
+    Skipped,
+}
+
+impl ShadowOutcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Solved => "solved",
+            Self::Failed => "failed",
+            Self::Skipped => "skipped",
+        }
+    }
+}
+
+/// Issues and scores shadow puzzles
+pub struct ShadowTrials {
+    config: ShadowConfig,
+    providers: Arc<ProviderRegistry>,
+    /// Same lifetime as the real challenge
+    ttl_secs: u64,
+}
+
+impl ShadowTrials {
+    pub fn new(config: ShadowConfig, providers: Arc<ProviderRegistry>, ttl_secs: u64) -> Self {
+        Self {
+            config,
+            providers,
+            ttl_secs,
+        }
+    }
+
+    /// Should the next challenge come with a shadow puzzle?
+    fn should_sample(&self) -> bool {
+        self.config.enabled && rand::random::<f64>() < self.config.sample_rate
+    }
+
+    /// A shadow puzzle for a sample of challenges, `None` for the rest
+    pub async fn issue(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        difficulty: CaptchaDifficulty,
+        format: ImageFormat,
+    ) -> Result<Option<ShadowChallenge>> {
+        if !self.should_sample() {
+            return Ok(None);
+        }
+        let Some(provider) = self.providers.get(&self.config.provider) else {
+            return Ok(None);
+        };
+
+        let puzzle = provider.generate(difficulty);
+        let image_data = provider.render(&puzzle, difficulty, format);
+        let shadow_id = new_challenge_id();
+        let stored = StoredShadow {
+            answer: puzzle.answer,
+            provider: provider.name().to_string(),
+            difficulty,
+            issued_at_ms: chrono::Utc::now().timestamp_millis(),
+        };
+        redis
+            .set_ex::<_, _, ()>(
+                &redis_keys::shadow_challenge(&shadow_id),
+                serde_json::to_string(&stored)?,
+                self.ttl_secs,
+            )
+            .await?;
+
+        metrics::SHADOW_ISSUED
+            .with_label_values(&[provider.name()])
+            .inc();
+        Ok(Some(ShadowChallenge {
+            shadow_id,
+            image_data,
+            instructions: provider.instructions(difficulty),
+        }))
+    }
+
+    /// Score an answer to a shadow puzzle (once; it's consumed)
+    ///
+    /// `real_solved` says how the visitor did on the real challenge. Returns
+    /// `None` when the puzzle has expired or was already answered.
+    pub async fn record(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        shadow_id: &ChallengeId,
+        answer: &str,
+        real_solved: bool,
+    ) -> Result<Option<ShadowOutcome>> {
+        let key = redis_keys::shadow_challenge(shadow_id);
+        let stored: Option<String> = take_script().key(&key).invoke_async(redis).await?;
+        let Some(stored) = stored else {
+            return Ok(None);
+        };
+        let stored: StoredShadow = serde_json::from_str(&stored)?;
+        let Some(provider) = self.providers.get(&stored.provider) else {
+            return Ok(None);
+        };
+
+        let outcome = if answer.trim().is_empty() {
+            ShadowOutcome::Skipped
+        } else if provider.verify(&stored.answer, answer, stored.difficulty) {
+            ShadowOutcome::Solved
+        } else {
+            ShadowOutcome::Failed
+        };
+        let real = if real_solved { "solved" } else { "failed" };
+        metrics::SHADOW_RESULTS
+            .with_label_values(&[provider.name(), outcome.as_str(), real])
+            .inc();
+        if outcome == ShadowOutcome::Solved {
+            let ms = chrono::Utc::now().timestamp_millis() - stored.issued_at_ms;
+            metrics::SHADOW_SOLVE_SECONDS
+                .with_label_values(&[provider.name()])
+                .observe(ms.max(0) as f64 / 1000.0);
+        }
+        tracing::debug!(
+            shadow_id = %shadow_id,
+            provider = provider.name(),
+            outcome = outcome.as_str(),
+            "Recorded shadow trial"
+        );
+        Ok(Some(outcome))
+    }
+}
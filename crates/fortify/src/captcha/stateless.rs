@@ -0,0 +1,257 @@
+//! Stateless (sealed) challenges for offline mode.
+//!
+//! When Redis is unreachable, the expected answer cannot be stored
+//! server-side. Instead the challenge ID carries an HMAC-SHA256 over the
+//! answer, expiry and a nonce, and a second one over the whole ID (so a
+//! forged ID is told apart from a wrong answer), keyed with a per-process
+//! secret:
+//!
+//! `s2.{case_insensitive}.{expires_at}.{nonce}.{answer_mac}.{tag}`
+//!
+//! Sealed challenges are only verifiable by the node that issued them and
+//! are invalidated by a restart. Single use is enforced with an in-memory
+//! spent set, which only ever holds IDs we issued, until they expire.
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use cerberus_common::TtlSecs;
+use cerberus_common::constants::CAPTCHA_TTL_SECS;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+const PREFIX: &str = "s2.";
+
+/// Most spent challenges held at once; past this, sealed challenges fail
+/// until some expire (evicting one would let it be answered again)
+const MAX_SPENT: usize = 100_000;
+
+/// Outcome of opening a sealed challenge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SealedOutcome {
+    /// Answer matches
+    Correct,
+    /// Answer does not match
+    Incorrect,
+    /// Challenge expired
+    Expired,
+    /// Already answered (or never issued by us)
+    Invalid,
+}
+
+/// Issues and verifies sealed challenges
+pub struct ChallengeSealer {
+    key: [u8; 32],
+    /// Longest a sealed challenge may live; later expiries are refused
+    max_ttl: TtlSecs,
+    /// Spent challenge IDs -> expiry (pruned lazily)
+    spent: Mutex<HashMap<String, i64>>,
+}
+
+impl ChallengeSealer {
+    pub fn new() -> Self {
+        let mut key = [0u8; 32];
+        rand::Rng::fill(&mut rand::rng(), &mut key);
+        Self {
+            key,
+            max_ttl: CAPTCHA_TTL_SECS,
+            spent: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Refuse challenges expiring more than `max_ttl` from now
+    /// (`captcha.challenge_ttl_secs`)
+    pub fn with_max_ttl(mut self, max_ttl: TtlSecs) -> Self {
+        self.max_ttl = max_ttl;
+        self
+    }
+
+    /// Is this a sealed challenge ID?
+    pub fn is_sealed(challenge_id: &str) -> bool {
+        challenge_id.starts_with(PREFIX)
+    }
+
+    /// Build a sealed challenge ID for an answer
+    pub fn seal(&self, answer: &str, case_insensitive: bool, expires_at: i64) -> String {
+        let mut nonce = [0u8; 16];
+        rand::Rng::fill(&mut rand::rng(), &mut nonce);
+        let nonce = URL_SAFE_NO_PAD.encode(nonce);
+        let flag = u8::from(case_insensitive);
+
+        let answer_mac = self.answer_mac(
+            flag,
+            expires_at,
+            &nonce,
+            &normalize(answer, case_insensitive),
+        );
+        let unsealed = format!(
+            "{}{}.{}.{}.{}",
+            PREFIX,
+            flag,
+            expires_at,
+            nonce,
+            URL_SAFE_NO_PAD.encode(answer_mac.finalize().into_bytes())
+        );
+        let tag = self.id_mac(&unsealed).finalize().into_bytes();
+        format!("{}.{}", unsealed, URL_SAFE_NO_PAD.encode(tag))
+    }
+
+    /// Verify an answer against a sealed challenge, consuming it
+    ///
+    /// Only IDs we sealed, within `max_ttl` of now, are spent; anything else
+    /// is `Invalid` and leaves no trace.
+    pub fn open(&self, challenge_id: &str, answer: &str) -> SealedOutcome {
+        let Some((unsealed, tag)) = challenge_id.rsplit_once('.') else {
+            return SealedOutcome::Invalid;
+        };
+        let Some(rest) = unsealed.strip_prefix(PREFIX) else {
+            return SealedOutcome::Invalid;
+        };
+        let parts: Vec<&str> = rest.split('.').collect();
+        let [flag, expires_at, nonce, answer_mac] = parts[..] else {
+            return SealedOutcome::Invalid;
+        };
+        let (Ok(flag), Ok(expires_at), Ok(answer_mac), Ok(tag)) = (
+            flag.parse::<u8>(),
+            expires_at.parse::<i64>(),
+            URL_SAFE_NO_PAD.decode(answer_mac),
+            URL_SAFE_NO_PAD.decode(tag),
+        ) else {
+            return SealedOutcome::Invalid;
+        };
+        if self.id_mac(unsealed).verify_slice(&tag).is_err() {
+            return SealedOutcome::Invalid;
+        }
+
+        let now = chrono::Utc::now().timestamp();
+        if now > expires_at {
+            return SealedOutcome::Expired;
+        }
+        if expires_at > now + self.max_ttl.as_i64() {
+            return SealedOutcome::Invalid;
+        }
+
+        // Single use: first attempt consumes the challenge, right or wrong
+        {
+            let mut spent = self.spent.lock().unwrap_or_else(|e| e.into_inner());
+            spent.retain(|_, exp| *exp >= now);
+            if spent.contains_key(challenge_id) {
+                return SealedOutcome::Invalid;
+            }
+            if spent.len() >= MAX_SPENT {
+                tracing::warn!(max = MAX_SPENT, "Spent sealed challenge set full");
+                return SealedOutcome::Invalid;
+            }
+            spent.insert(challenge_id.to_string(), expires_at);
+        }
+
+        let expected = self.answer_mac(flag, expires_at, nonce, &normalize(answer, flag == 1));
+        if expected.verify_slice(&answer_mac).is_ok() {
+            SealedOutcome::Correct
+        } else {
+            SealedOutcome::Incorrect
+        }
+    }
+
+    /// MAC over an arbitrary value (e.g. a form field round-tripped through
+    /// the browser), with the same key as sealed challenges
+    pub fn sign(&self, value: &str) -> String {
+        URL_SAFE_NO_PAD.encode(self.value_mac(value).finalize().into_bytes())
+    }
+
+    /// Was `signature` made by `sign` for `value`?
+    pub fn verify(&self, value: &str, signature: &str) -> bool {
+        URL_SAFE_NO_PAD
+            .decode(signature)
+            .is_ok_and(|mac| self.value_mac(value).verify_slice(&mac).is_ok())
+    }
+
+    // Messages are prefixed so no two kinds of MAC can ever collide
+
+    fn value_mac(&self, value: &str) -> Hmac<Sha256> {
+        self.hmac(format!("v|{}", value))
+    }
+
+    fn id_mac(&self, unsealed: &str) -> Hmac<Sha256> {
+        self.hmac(format!("c|{}", unsealed))
+    }
+
+    fn answer_mac(&self, flag: u8, expires_at: i64, nonce: &str, answer: &str) -> Hmac<Sha256> {
+        self.hmac(format!("{}|{}|{}|{}", flag, expires_at, nonce, answer))
+    }
+
+    fn hmac(&self, message: String) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes any key length");
+        mac.update(message.as_bytes());
+        mac
+    }
+}
+
+impl Default for ChallengeSealer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn normalize(answer: &str, case_insensitive: bool) -> String {
+    if case_insensitive {
+        answer.to_uppercase()
+    } else {
+        answer.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expires() -> i64 {
+        chrono::Utc::now().timestamp() + 60
+    }
+
+    #[test]
+    fn test_seal_and_open() {
+        let sealer = ChallengeSealer::new();
+        let id = sealer.seal("AB12C", true, expires());
+        assert!(ChallengeSealer::is_sealed(&id));
+        assert_eq!(sealer.open(&id, "ab12c"), SealedOutcome::Correct);
+
+        // Single use
+        assert_eq!(sealer.open(&id, "AB12C"), SealedOutcome::Invalid);
+    }
+
+    #[test]
+    fn test_wrong_answer_and_foreign_key() {
+        let sealer = ChallengeSealer::new();
+        let id = sealer.seal("AB12C", false, expires());
+        assert_eq!(sealer.open(&id, "ab12c"), SealedOutcome::Incorrect);
+
+        // Another key can't tell a right answer from a forged ID
+        let other = ChallengeSealer::new();
+        let id = sealer.seal("AB12C", false, expires());
+        assert_eq!(other.open(&id, "AB12C"), SealedOutcome::Invalid);
+    }
+
+    #[test]
+    fn test_forged_ids_not_spent() {
+        let sealer = ChallengeSealer::new().with_max_ttl(TtlSecs::from_secs(60));
+        let id = sealer.seal("AB12C", false, expires());
+
+        // Tampered tag, or an expiry past the TTL: invalid, and not recorded
+        let tampered = format!("{}A", id);
+        assert_eq!(sealer.open(&tampered, "AB12C"), SealedOutcome::Invalid);
+        let far = sealer.seal("AB12C", false, expires() + 3600);
+        assert_eq!(sealer.open(&far, "AB12C"), SealedOutcome::Invalid);
+        assert!(sealer.spent.lock().unwrap().is_empty());
+
+        assert_eq!(sealer.open(&id, "AB12C"), SealedOutcome::Correct);
+        assert_eq!(sealer.spent.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_expired() {
+        let sealer = ChallengeSealer::new();
+        let id = sealer.seal("AB12C", true, chrono::Utc::now().timestamp() - 1);
+        assert_eq!(sealer.open(&id, "AB12C"), SealedOutcome::Expired);
+    }
+}
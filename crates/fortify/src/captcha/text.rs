@@ -0,0 +1,262 @@
+//! Text CAPTCHA: random characters drawn into a noisy SVG.
+//!
+//! Characters are drawn as distorted stroke outlines (see `glyphs`), never
+//! as `<text>`, so decoding the image does not hand over the answer.
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use cerberus_common::CaptchaDifficulty;
+use rand::Rng;
+use rand::seq::SliceRandom;
+use std::cell::RefCell;
+use std::fmt::Write;
+
+use super::glyphs::{self, Distortion};
+use super::provider::{ChallengeProvider, Puzzle};
+use super::raster;
+use crate::config::ImageFormat;
+
+/// Random alphanumeric string, typed back by the user
+pub struct TextProvider;
+
+impl TextProvider {
+    pub const NAME: &'static str = "text";
+}
+
+/// Image height in pixels
+pub(super) const IMAGE_HEIGHT: u32 = 80;
+
+/// Room for an Extreme SVG, so the buffer rarely has to grow
+const SVG_CAPACITY: usize = 16 * 1024;
+
+thread_local! {
+    /// SVG markup, reused by every render on the thread (the Ammo Box
+    /// renders thousands a second); only the data URI is allocated per image
+    static SVG_BUFFER: RefCell<String> = RefCell::new(String::with_capacity(SVG_CAPACITY));
+}
+
+/// A path in the SVG, before drawing: kept small so the draw order can be
+/// shuffled without building the markup first
+#[derive(Clone, Copy)]
+enum Stroke {
+    Noise,
+    /// Character and its position in the prompt
+    Glyph(char, usize),
+}
+
+/// Image width in pixels: wider for longer prompts
+pub(super) fn image_width(text: &str) -> u32 {
+    200.max((text.chars().count() as u32 + 1) * 22)
+}
+
+/// Draw `text` into a noisy image and return it as a data URI
+///
+/// Shared by the providers that show a line of characters. Raster output
+/// falls back to SVG if encoding fails.
+pub(super) fn render_prompt(
+    text: &str,
+    difficulty: CaptchaDifficulty,
+    format: ImageFormat,
+) -> String {
+    if matches!(format, ImageFormat::Png | ImageFormat::Webp) {
+        match raster::data_uri(text, difficulty, format) {
+            Ok(uri) => return uri,
+            Err(e) => tracing::warn!(error = %e, "Raster CAPTCHA encoding failed, using SVG"),
+        }
+    }
+
+    // A simple SVG works without image libraries
+    SVG_BUFFER.with_borrow_mut(|svg| {
+        svg.clear();
+        write_svg_captcha(svg, text, difficulty);
+        encode_data_uri("image/svg+xml", svg.as_bytes())
+    })
+}
+
+/// `data:` URI of `bytes`, base64-encoded straight into a string sized for it
+pub(super) fn encode_data_uri(mime: &str, bytes: &[u8]) -> String {
+    let encoded_len = base64::encoded_len(bytes.len(), true).unwrap_or_default();
+    let mut uri = String::with_capacity("data:;base64,".len() + mime.len() + encoded_len);
+    uri.push_str("data:");
+    uri.push_str(mime);
+    uri.push_str(";base64,");
+    STANDARD.encode_string(bytes, &mut uri);
+    uri
+}
+
+/// Write an SVG CAPTCHA image to `svg`
+fn write_svg_captcha(svg: &mut String, text: &str, difficulty: CaptchaDifficulty) {
+    let mut rng = rand::rng();
+
+    let width = image_width(text);
+    let height = IMAGE_HEIGHT;
+
+    // Background noise based on difficulty
+    let noise_count = difficulty.profile().noise_lines as usize;
+
+    let _ = write!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}">"#,
+        width, height
+    );
+
+    // Background
+    svg.push_str(r##"<rect width="100%" height="100%" fill="#1a1a2e"/>"##);
+
+    // Glyphs and noise are all paths, emitted in random order so neither
+    // the element type nor its position gives the characters away
+    let mut strokes: Vec<Stroke> = std::iter::repeat_n(Stroke::Noise, noise_count)
+        .chain(text.chars().enumerate().map(|(i, c)| Stroke::Glyph(c, i)))
+        .collect();
+    strokes.shuffle(&mut rng);
+
+    let distortion = Distortion::for_difficulty(difficulty);
+    let char_width = width as f32 / (text.chars().count() as f32 + 1.0);
+    for stroke in strokes {
+        match stroke {
+            Stroke::Noise => {
+                let x1 = rng.random_range(0..width);
+                let y1 = rng.random_range(0..height);
+                let x2 = rng.random_range(0..width);
+                let y2 = rng.random_range(0..height);
+                let opacity = rng.random_range(20..50);
+                let _ = write!(
+                    svg,
+                    r#"<path d="M{} {}L{} {}" fill="none" stroke="rgba(255,255,255,0.{})" stroke-width="1"/>"#,
+                    x1, y1, x2, y2, opacity
+                );
+            }
+            // One distorted outline per character
+            Stroke::Glyph(c, i) => {
+                let x = char_width * (i as f32 + 1.0);
+                let y = 40.0 + rng.random_range(-8.0..8.0);
+                let start = svg.len();
+                svg.push_str(r#"<path d=""#);
+                if glyphs::write_path(svg, c, x, y, 32.0, distortion, &mut rng).is_none() {
+                    svg.truncate(start);
+                    continue;
+                }
+                let _ = write!(
+                    svg,
+                    r#"" fill="none" stroke="rgb({},{},{})" stroke-width="3" stroke-linecap="round" stroke-linejoin="round"/>"#,
+                    rng.random_range(150..255),
+                    rng.random_range(150..255),
+                    rng.random_range(150..255)
+                );
+            }
+        }
+    }
+
+    svg.push_str("</svg>");
+}
+
+impl ChallengeProvider for TextProvider {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn generate(&self, difficulty: CaptchaDifficulty) -> Puzzle {
+        let mut rng = rand::rng();
+
+        // Generate random alphanumeric answer
+        let length = difficulty.profile().answer_length;
+
+        let answer: String = (0..length)
+            .map(|_| {
+                let idx = rng.random_range(0..36);
+                if idx < 10 {
+                    (b'0' + idx) as char
+                } else {
+                    (b'A' + idx - 10) as char
+                }
+            })
+            .collect();
+
+        Puzzle {
+            prompt: answer.clone(),
+            answer,
+        }
+    }
+
+    fn render(
+        &self,
+        puzzle: &Puzzle,
+        difficulty: CaptchaDifficulty,
+        format: ImageFormat,
+    ) -> String {
+        render_prompt(&puzzle.prompt, difficulty, format)
+    }
+
+    fn instructions(&self, difficulty: CaptchaDifficulty) -> String {
+        match difficulty {
+            CaptchaDifficulty::Easy => "Type the characters shown above".to_string(),
+            CaptchaDifficulty::Medium => {
+                "Type the characters shown above (case insensitive)".to_string()
+            }
+            CaptchaDifficulty::Hard => "Type the characters exactly as shown".to_string(),
+            CaptchaDifficulty::Extreme => format!(
+                "Type the characters within {} seconds",
+                difficulty.timeout_secs()
+            ),
+        }
+    }
+
+    fn verify(&self, expected: &str, answer: &str, difficulty: CaptchaDifficulty) -> bool {
+        if self.case_insensitive(difficulty) {
+            answer.to_uppercase() == expected.to_uppercase()
+        } else {
+            answer == expected
+        }
+    }
+
+    fn case_insensitive(&self, difficulty: CaptchaDifficulty) -> bool {
+        matches!(
+            difficulty,
+            CaptchaDifficulty::Easy | CaptchaDifficulty::Medium
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_svg_does_not_contain_answer() {
+        for difficulty in [CaptchaDifficulty::Easy, CaptchaDifficulty::Extreme] {
+            let mut svg = String::new();
+            write_svg_captcha(&mut svg, "WXYZ", difficulty);
+            assert!(!svg.contains("<text"));
+            assert!(!svg.contains("WXYZ"));
+            // Background plus the four glyphs, each a stroked outline
+            assert_eq!(svg.matches(r#"stroke-width="3""#).count(), 4);
+        }
+    }
+
+    #[test]
+    fn test_svg_data_uri_reuses_buffer() {
+        let decode = |uri: String| {
+            let b64 = uri.strip_prefix("data:image/svg+xml;base64,").unwrap();
+            String::from_utf8(STANDARD.decode(b64).unwrap()).unwrap()
+        };
+        let long = decode(render_prompt(
+            "ABCDEFGH",
+            CaptchaDifficulty::Extreme,
+            ImageFormat::Svg,
+        ));
+        let short = decode(render_prompt(
+            "A B",
+            CaptchaDifficulty::Easy,
+            ImageFormat::Svg,
+        ));
+
+        // Nothing of the longer image is left over in the shorter one
+        for svg in [&long, &short] {
+            assert!(svg.starts_with("<svg") && svg.ends_with("</svg>"));
+            assert_eq!(svg.matches("<svg").count(), 1);
+        }
+        assert_eq!(long.matches(r#"stroke-width="3""#).count(), 8);
+        // A space has no glyph and leaves no empty path behind
+        assert_eq!(short.matches(r#"stroke-width="3""#).count(), 2);
+        assert!(!short.contains(r#"d="""#));
+    }
+}
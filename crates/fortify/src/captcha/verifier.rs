@@ -1,68 +1,150 @@
 //! CAPTCHA verification logic.
 
 use anyhow::Result;
-use cerberus_common::{CaptchaDifficulty, CaptchaResult};
+use cerberus_common::{
+    CaptchaDifficulty, ChallengeId, CircuitId, PassportToken, ThreatLevel, Versioned, redis_keys,
+    versioned,
+};
 use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
-use super::StoredChallenge;
+use super::generator::release_outstanding;
+use super::provider::ProviderRegistry;
+use super::stateless::{ChallengeSealer, SealedOutcome};
+use super::{StoredChallenge, take_script};
+use crate::circuits::StorageEntry;
+use crate::cluster::{CLUSTER_TARGET, PassportClaims, PassportService, decode_claims};
+use crate::config::{PassportPolicy, PassportRenewal, VipConfig};
+use crate::degradation::DegradationState;
+use crate::flags::{FeatureFlags, Flag};
+
+/// Outcome of checking an answer against a challenge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChallengeCheck {
+    /// Answer matches
+    Correct {
+        /// Milliseconds from issuance to answer, if known
+        solve_time_ms: Option<u64>,
+    },
+    /// Answer does not match (challenge consumed)
+    Incorrect,
+    /// Challenge expired
+    Expired,
+    /// Unknown or already used challenge
+    Missing,
+}
+
+/// A passport ready to hand to the client
+#[derive(Debug, Clone)]
+pub struct PassportGrant {
+    pub token: PassportToken,
+    pub expires_at: i64,
+    /// Redis record to commit (`None` for signed passports)
+    pub record: Option<StorageEntry>,
+}
 
 /// CAPTCHA verifier service
 pub struct CaptchaVerifier {
     /// Passport TTL in seconds
     pub passport_ttl: u64,
+    /// Per-threat-level TTLs, lifetime cap and renewal
+    policy: PassportPolicy,
+    /// Longer-lived passports for VIP circuits
+    vip: VipConfig,
+    /// Redis degradation mode (switches to stateless passports)
+    degradation: Arc<DegradationState>,
+    /// Signs stateless passports (for this node, or the cluster when federated)
+    signer: Arc<PassportService>,
+    /// Opens sealed (offline) challenges
+    sealer: Arc<ChallengeSealer>,
+    /// Checks answers for the provider that issued each challenge
+    providers: Arc<ProviderRegistry>,
+    /// `strict_circuit_binding` and `signed_passports`
+    flags: Arc<FeatureFlags>,
 }
 
 impl CaptchaVerifier {
-    pub fn new(passport_ttl: u64) -> Self {
-        Self { passport_ttl }
+    pub fn new(
+        passport_ttl: u64,
+        policy: PassportPolicy,
+        vip: VipConfig,
+        degradation: Arc<DegradationState>,
+        signer: Arc<PassportService>,
+        sealer: Arc<ChallengeSealer>,
+        providers: Arc<ProviderRegistry>,
+    ) -> Self {
+        Self {
+            passport_ttl,
+            policy,
+            vip,
+            degradation,
+            signer,
+            sealer,
+            providers,
+            flags: Arc::default(),
+        }
+    }
+
+    /// Honour `flags` (otherwise their config defaults apply)
+    pub fn with_flags(mut self, flags: Arc<FeatureFlags>) -> Self {
+        self.flags = flags;
+        self
     }
 
-    /// Verify a CAPTCHA response
+    /// Consume a challenge and compare the answer
     ///
-    /// Returns (success, remaining_challenges, passport_token)
-    pub async fn verify(
+    /// The challenge is removed atomically before comparison, so each
+    /// challenge can be answered at most once. Nothing else is written.
+    /// Also returns the difficulty the challenge was issued at (unknown for
+    /// missing and sealed challenges).
+    #[tracing::instrument(name = "captcha.check", skip(self, redis, user_answer))]
+    pub async fn check(
         &self,
         redis: &mut redis::aio::ConnectionManager,
-        challenge_id: &str,
+        challenge_id: &ChallengeId,
         user_answer: &str,
-        circuit_id: Option<&str>,
-    ) -> Result<CaptchaResult> {
-        let key = format!("captcha:{}", challenge_id);
-
-        // Fetch and delete challenge (single-use)
-        // Use GET + DEL for Redis 3.x compatibility (GETDEL requires Redis 6.2+)
-        let stored: Option<String> = redis.get(&key).await?;
-        let _: () = redis.del(&key).await?;
-
-        let stored = match stored {
-            Some(s) => s,
-            None => {
-                return Ok(CaptchaResult {
-                    success: false,
-                    remaining_challenges: 0,
-                    passport_token: None,
-                    error_message: Some("Challenge expired or invalid".to_string()),
-                });
-            }
+        circuit_id: Option<&CircuitId>,
+    ) -> Result<(ChallengeCheck, Option<CaptchaDifficulty>)> {
+        // Issued while offline; may be answered after Redis is back
+        if ChallengeSealer::is_sealed(challenge_id.as_str()) {
+            return Ok((self.check_sealed(challenge_id, user_answer), None));
+        }
+
+        let key = redis_keys::challenge(challenge_id);
+        let stored: Option<String> = take_script().key(&key).invoke_async(redis).await?;
+        let Some(stored) = stored else {
+            return Ok((ChallengeCheck::Missing, None));
         };
 
-        let challenge: StoredChallenge = serde_json::from_str(&stored)?;
+        let challenge: StoredChallenge = versioned::decode(&stored)?;
+        release_outstanding(redis, challenge.circuit_id.as_ref(), challenge_id).await?;
 
-        // Check expiry
+        // Check expiry, holding challenges stored under a longer TTL to their
+        // difficulty's answer time
         let now = chrono::Utc::now().timestamp();
-        if now > challenge.expires_at {
-            return Ok(CaptchaResult {
-                success: false,
-                remaining_challenges: 0,
-                passport_token: None,
-                error_message: Some("Challenge expired".to_string()),
-            });
+        let difficulty = Some(challenge.difficulty);
+        let deadline = challenge
+            .expires_at
+            .min(challenge.created_at + i64::from(challenge.difficulty.timeout_secs()));
+        if now > deadline {
+            return Ok((ChallengeCheck::Expired, difficulty));
         }
 
-        // Verify circuit ID matches (if provided) - warn on mismatch but don't fail
+        // Verify circuit ID matches (if provided). Circuits can change, so a
+        // mismatch only fails the answer under `strict_circuit_binding`
         if let (Some(stored_cid), Some(request_cid)) = (&challenge.circuit_id, circuit_id)
             && stored_cid != request_cid
         {
+            if self.flags.is_enabled(Flag::StrictCircuitBinding) {
+                tracing::warn!(
+                    challenge_id = %challenge_id,
+                    stored_circuit = %stored_cid,
+                    request_circuit = %request_cid,
+                    "Circuit ID mismatch - strict circuit binding, rejecting"
+                );
+                return Ok((ChallengeCheck::Incorrect, difficulty));
+            }
             tracing::warn!(
                 challenge_id = %challenge_id,
                 stored_circuit = %stored_cid,
@@ -71,86 +153,437 @@ impl CaptchaVerifier {
             );
         }
 
-        // Compare answers (case-insensitive for Easy/Medium)
-        let success = match challenge.difficulty {
-            CaptchaDifficulty::Easy | CaptchaDifficulty::Medium => {
-                user_answer.to_uppercase() == challenge.answer.to_uppercase()
-            }
-            CaptchaDifficulty::Hard | CaptchaDifficulty::Extreme => user_answer == challenge.answer,
+        // The issuing provider decides what counts as a match
+        let Some(provider) = self.providers.get(&challenge.provider) else {
+            tracing::warn!(provider = %challenge.provider, "Challenge from unknown provider");
+            return Ok((ChallengeCheck::Missing, None));
         };
+        let success = provider.verify(&challenge.answer, user_answer, challenge.difficulty);
 
-        if success {
-            // Generate passport token
-            let passport_token = self.generate_passport_token();
+        if !success {
+            tracing::debug!(
+                challenge_id = %challenge_id,
+                circuit_id = ?circuit_id,
+                "CAPTCHA verification failed"
+            );
+            return Ok((ChallengeCheck::Incorrect, difficulty));
+        }
+
+        // Time-to-solve feeds farm detection (older records lack issued_at_ms)
+        let solve_time_ms = (challenge.issued_at_ms > 0).then(|| {
+            (chrono::Utc::now().timestamp_millis() - challenge.issued_at_ms).max(0) as u64
+        });
 
-            // Store passport in Redis
-            let passport_key = format!("passport:{}", passport_token);
-            let passport_data = serde_json::json!({
-                "circuit_id": circuit_id,
-                "issued_at": now,
-                "expires_at": now + self.passport_ttl as i64,
+        Ok((ChallengeCheck::Correct { solve_time_ms }, difficulty))
+    }
+
+    /// Check a sealed challenge without Redis
+    pub fn check_sealed(&self, challenge_id: &ChallengeId, user_answer: &str) -> ChallengeCheck {
+        match self.sealer.open(challenge_id.as_str(), user_answer) {
+            SealedOutcome::Correct => ChallengeCheck::Correct {
+                solve_time_ms: None,
+            },
+            SealedOutcome::Incorrect => ChallengeCheck::Incorrect,
+            SealedOutcome::Expired => ChallengeCheck::Expired,
+            SealedOutcome::Invalid => ChallengeCheck::Missing,
+        }
+    }
+
+    /// Create a passport for a successful solve
+    ///
+    /// Under Redis pressure, with signed passport federation (and the
+    /// `signed_passports` flag on), or when `stateless` is requested, the
+    /// passport is a signed token; otherwise it is a random token whose Redis
+    /// record is returned for the caller to commit. The TTL is the one configured
+    /// for `threat_level`, or the VIP TTL for `vip` passports (signed
+    /// passports always use `passport_ttl`).
+    pub fn grant_passport(
+        &self,
+        circuit_id: Option<&CircuitId>,
+        stateless: bool,
+        vip: bool,
+        threat_level: ThreatLevel,
+    ) -> Result<PassportGrant> {
+        let now = chrono::Utc::now().timestamp();
+
+        let federated =
+            self.signer.is_cluster_wide() && self.flags.is_enabled(Flag::SignedPassports);
+        if stateless || federated || self.degradation.stateless_passports() {
+            let token = self
+                .signer
+                .mint_own(circuit_id.map(CircuitId::to_string))?
+                .parse()?;
+            return Ok(PassportGrant {
+                token,
+                expires_at: now + self.passport_ttl as i64,
+                record: None,
             });
+        }
 
-            redis
-                .set_ex::<_, _, ()>(&passport_key, passport_data.to_string(), self.passport_ttl)
-                .await?;
+        let ttl = if vip {
+            self.vip.passport_ttl_secs.secs()
+        } else {
+            self.policy
+                .ttl_secs(threat_level.captcha_difficulty(), self.passport_ttl)
+        };
+        let ttl = self.policy.capped_ttl(ttl, now, now);
+        let expires_at = now + ttl as i64;
+        let token = self.generate_passport_token();
+        let record = PassportRecord {
+            circuit_id: circuit_id.cloned(),
+            issued_at: now,
+            expires_at,
+            ttl: Some(ttl),
+            vip,
+            issuer: Some(self.signer.node_id().to_string()),
+        };
 
+        Ok(PassportGrant {
+            record: Some(StorageEntry {
+                key: redis_keys::passport(&token).into(),
+                value: versioned::encode(&record)?,
+                ttl,
+            }),
+            token,
+            expires_at,
+        })
+    }
+
+    /// Track a newly committed passport against its circuit's cap
+    ///
+    /// Passports already gone are forgotten; if the circuit still holds more
+    /// than `max_per_circuit`, its oldest are deleted. Returns how many were.
+    pub async fn limit_circuit_passports(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        circuit_id: &CircuitId,
+        token: &PassportToken,
+        ttl: u64,
+    ) -> Result<usize> {
+        if self.policy.max_per_circuit == 0 {
+            return Ok(0);
+        }
+        // Tracked as long as any of its passports may live
+        let ttl = match self.policy.max_lifetime_secs.secs() {
+            0 => ttl,
+            max => max,
+        };
+        let evicted: Vec<String> = passport_cap_script()
+            .key(redis_keys::circuit_passports(circuit_id))
+            .arg(token.as_str())
+            .arg(chrono::Utc::now().timestamp_millis())
+            .arg(self.policy.max_per_circuit)
+            .arg(ttl)
+            .arg(cerberus_common::constants::redis_keys::PASSPORT_PREFIX)
+            .invoke_async(redis)
+            .await?;
+
+        if !evicted.is_empty() {
             tracing::info!(
-                challenge_id = %challenge_id,
-                circuit_id = ?circuit_id,
-                "CAPTCHA verified successfully"
+                circuit_id = %circuit_id,
+                evicted = evicted.len(),
+                "Circuit over its passport cap, oldest passports invalidated"
             );
+        }
+        Ok(evicted.len())
+    }
 
-            Ok(CaptchaResult {
-                success: true,
-                remaining_challenges: 0,
-                passport_token: Some(passport_token),
-                error_message: None,
-            })
-        } else {
-            tracing::debug!(
-                challenge_id = %challenge_id,
-                circuit_id = ?circuit_id,
-                "CAPTCHA verification failed"
-            );
+    /// Check a signed (stateless) passport presented by `circuit_id`; never
+    /// touches Redis
+    pub async fn validate_signed_passport(
+        &self,
+        token: &PassportToken,
+        circuit_id: Option<&CircuitId>,
+    ) -> bool {
+        self.signed_claims(token, circuit_id).await.is_some()
+    }
 
-            Ok(CaptchaResult {
-                success: false,
-                remaining_challenges: 1, // They need to try again
-                passport_token: None,
-                error_message: Some("Incorrect answer".to_string()),
-            })
-        }
+    /// Claims of a signed passport valid for `circuit_id`
+    async fn signed_claims(
+        &self,
+        token: &PassportToken,
+        circuit_id: Option<&CircuitId>,
+    ) -> Option<PassportClaims> {
+        self.signer
+            .validate(token.as_str(), circuit_id.map(CircuitId::as_str))
+            .await
+            .ok()
+    }
+
+    /// Revoke a passport of either kind
+    ///
+    /// The token joins the revocation set (so a signed passport is refused
+    /// even while offline) and its Redis record, if any, is deleted.
+    /// Returns whether a Redis record existed.
+    pub async fn revoke_passport(
+        &self,
+        redis: Option<&mut redis::aio::ConnectionManager>,
+        token: &PassportToken,
+    ) -> Result<bool> {
+        self.signer.revoke(token.as_str()).await;
+
+        let Some(redis) = redis else {
+            return Ok(false);
+        };
+        let deleted: u32 = redis.del(redis_keys::passport(token)).await?;
+        Ok(deleted > 0)
     }
 
     /// Generate a cryptographically secure passport token
-    fn generate_passport_token(&self) -> String {
+    fn generate_passport_token(&self) -> PassportToken {
         use base64::Engine;
         use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 
         let mut bytes = [0u8; 32];
         rand::Rng::fill(&mut rand::rng(), &mut bytes);
-        URL_SAFE_NO_PAD.encode(bytes)
+        URL_SAFE_NO_PAD
+            .encode(bytes)
+            .parse()
+            .expect("base64url is a valid passport token")
     }
 
     /// Validate an existing passport token
+    ///
+    /// Signed (stateless) passports are checked first, against the circuit
+    /// presenting them, and never touch Redis.
+    /// A Redis-backed passport past `max_lifetime_secs` is deleted; one still
+    /// valid is renewed if VIP auto-renewal or sliding renewal applies, and
+    /// has the use counted.
+    #[tracing::instrument(name = "passport.validate", skip_all)]
     pub async fn validate_passport(
         &self,
         redis: &mut redis::aio::ConnectionManager,
-        token: &str,
+        token: &PassportToken,
+        circuit_id: Option<&CircuitId>,
     ) -> Result<bool> {
-        let key = format!("passport:{}", token);
-        let exists: bool = redis.exists(&key).await?;
-
-        if exists {
-            // Update last-seen (touch the key)
-            let ttl: i64 = redis.ttl(&key).await?;
-            if ttl > 0 {
-                // Refresh TTL on valid access
-                redis.expire::<_, ()>(&key, ttl).await?;
+        if self.validate_signed_passport(token, circuit_id).await {
+            return Ok(true);
+        }
+        if self.signer.is_revoked(token.as_str()).await {
+            return Ok(false);
+        }
+
+        let key = redis_keys::passport(token);
+        let record: Option<String> = redis.get(&key).await?;
+        let Some(record) = record else {
+            return Ok(false);
+        };
+        // Unreadable records are still honoured until their key expires
+        let Ok(record) = versioned::decode::<PassportRecord>(&record) else {
+            return Ok(true);
+        };
+
+        let now = chrono::Utc::now().timestamp();
+        if self.policy.outlived(record.issued_at, now) {
+            redis.del::<_, ()>(&key).await?;
+            return Ok(false);
+        }
+
+        let renew_to = match record.vip_circuit() {
+            // VIP passports renew while used, until the circuit is demoted
+            Some(circuit_id) if self.vip.auto_renew => {
+                let score: Option<i64> = redis.zscore(redis_keys::vips(), circuit_id).await?;
+                score.map(|_| self.vip.passport_ttl_secs.secs())
+            }
+            _ if self.policy.renewal == PassportRenewal::Sliding => {
+                Some(record.ttl.unwrap_or(self.passport_ttl))
             }
+            _ => None,
+        };
+        let renew_to = renew_to.map_or(0, |ttl| self.policy.capped_ttl(ttl, record.issued_at, now));
+        count_use_script()
+            .key(&key)
+            .key(redis_keys::passport_uses(token))
+            .arg(renew_to)
+            .invoke_async::<()>(redis)
+            .await?;
+
+        Ok(true)
+    }
+
+    /// What a signed passport says about itself (`None` if `token` isn't
+    /// one), checked for `circuit_id`; never touches Redis
+    pub async fn inspect_signed_passport(
+        &self,
+        token: &PassportToken,
+        circuit_id: Option<&CircuitId>,
+    ) -> Option<PassportInfo> {
+        let claims = decode_claims(token.as_str())?;
+        let valid = self.signed_claims(token, circuit_id).await;
+        let scope = if claims.target == CLUSTER_TARGET {
+            "cluster".to_string()
+        } else {
+            format!("node:{}", claims.target)
+        };
+        Some(PassportInfo {
+            kind: PassportKind::Signed,
+            revoked: self.signer.is_revoked(token.as_str()).await,
+            issued_at: None,
+            expires_at: claims.expiry as i64,
+            issuer: Some(claims.issuer),
+            circuit_id: valid
+                .as_ref()
+                .and_then(|claims| claims.circuit_id.as_deref()?.parse().ok()),
+            valid: valid.is_some(),
+            scopes: vec![scope],
+            validations: None,
+        })
+    }
+
+    /// The record of a Redis-backed passport (`None` if there's none, e.g.
+    /// it expired or was revoked)
+    pub async fn inspect_stored_passport(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        token: &PassportToken,
+    ) -> Result<Option<PassportInfo>> {
+        let key = redis_keys::passport(token);
+        let (record, ttl, uses): (Option<String>, i64, Option<u64>) = redis::pipe()
+            .get(&key)
+            .ttl(&key)
+            .get(redis_keys::passport_uses(token))
+            .query_async(redis)
+            .await?;
+        let Some(record) = record else {
+            return Ok(None);
+        };
+        let record: PassportRecord = versioned::decode(&record)?;
+
+        let now = chrono::Utc::now().timestamp();
+        let revoked = self.signer.is_revoked(token.as_str()).await;
+        let mut scopes = vec!["redis".to_string()];
+        if record.vip {
+            scopes.push("vip".to_string());
         }
+        Ok(Some(PassportInfo {
+            kind: PassportKind::Stored,
+            valid: !revoked && !self.policy.outlived(record.issued_at, now),
+            revoked,
+            issued_at: Some(record.issued_at),
+            // Renewals push the key's expiry past the recorded one
+            expires_at: if ttl > 0 {
+                now + ttl
+            } else {
+                record.expires_at
+            },
+            issuer: record.issuer,
+            circuit_id: record.circuit_id,
+            scopes,
+            validations: Some(uses.unwrap_or(0)),
+        }))
+    }
+}
+
+/// How a passport is kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PassportKind {
+    /// Random token with a Redis record
+    Stored,
+    /// Signed token, checked without Redis
+    Signed,
+}
+
+/// A passport as seen by an admin
+#[derive(Debug, Clone, Serialize)]
+pub struct PassportInfo {
+    pub kind: PassportKind,
+    /// Would this node let it through now?
+    pub valid: bool,
+    pub revoked: bool,
+    /// Unix seconds (unknown for signed passports, which carry only their
+    /// expiry)
+    pub issued_at: Option<i64>,
+    /// Unix seconds, renewals included
+    pub expires_at: i64,
+    /// Node that issued it (unknown for passports stored before issuers
+    /// were recorded)
+    pub issuer: Option<String>,
+    /// Circuit it was issued to (signed passports only carry a hash of it,
+    /// so theirs is known only when checked against that circuit)
+    pub circuit_id: Option<CircuitId>,
+    /// Where it's honoured: `redis` (any node sharing this Redis), `vip`
+    /// (renewed while its circuit is VIP), `cluster` or `node:{id}` for
+    /// signed passports
+    pub scopes: Vec<String>,
+    /// Requests validated with it (not counted for signed passports)
+    pub validations: Option<u64>,
+}
+
+/// Renew a passport if ARGV[1] > 0 and count a use, the count living as
+/// long as the passport
+fn count_use_script() -> redis::Script {
+    redis::Script::new(
+        r"
+        if tonumber(ARGV[1]) > 0 then
+            redis.call('EXPIRE', KEYS[1], ARGV[1])
+        end
+        redis.call('INCR', KEYS[2])
+        local ttl = redis.call('PTTL', KEYS[1])
+        if ttl > 0 then
+            redis.call('PEXPIRE', KEYS[2], ttl)
+        else
+            redis.call('DEL', KEYS[2])
+        end
+        ",
+    )
+}
+
+/// Add a passport to its circuit's set, then drop expired entries and
+/// delete the oldest passports beyond the cap
+///
+/// ARGV: token, issued_at_ms, max, ttl, passport key prefix. Returns the
+/// evicted tokens.
+fn passport_cap_script() -> redis::Script {
+    redis::Script::new(
+        r"
+        redis.call('ZADD', KEYS[1], ARGV[2], ARGV[1])
+        if redis.call('TTL', KEYS[1]) < tonumber(ARGV[4]) then
+            redis.call('EXPIRE', KEYS[1], ARGV[4])
+        end
+        for _, token in ipairs(redis.call('ZRANGE', KEYS[1], 0, -1)) do
+            if redis.call('EXISTS', ARGV[5] .. token) == 0 then
+                redis.call('ZREM', KEYS[1], token)
+            end
+        end
+        local excess = redis.call('ZCARD', KEYS[1]) - tonumber(ARGV[3])
+        if excess <= 0 then
+            return {}
+        end
+        local evicted = redis.call('ZRANGE', KEYS[1], 0, excess - 1)
+        for _, token in ipairs(evicted) do
+            redis.call('DEL', ARGV[5] .. token)
+            redis.call('ZREM', KEYS[1], token)
+        end
+        return evicted
+        ",
+    )
+}
+
+/// The Redis record of a passport
+#[derive(Serialize, Deserialize)]
+struct PassportRecord {
+    circuit_id: Option<CircuitId>,
+    issued_at: i64,
+    expires_at: i64,
+    /// TTL it was issued with (absent on records from older versions)
+    #[serde(default)]
+    ttl: Option<u64>,
+    #[serde(default)]
+    vip: bool,
+    /// Issuing node (absent on records from older versions)
+    #[serde(default)]
+    issuer: Option<String>,
+}
+
+/// Stored as `passport:{token}`
+impl Versioned for PassportRecord {
+    const VERSION: u32 = 1;
+}
 
-        Ok(exists)
+impl PassportRecord {
+    /// Circuit a VIP passport was granted to (`None` if not VIP)
+    fn vip_circuit(&self) -> Option<&CircuitId> {
+        self.circuit_id.as_ref().filter(|_| self.vip)
     }
 }
@@ -0,0 +1,262 @@
+//! Challenge outcomes by difficulty.
+//!
+//! Which difficulty a threat level calls for is a guess until measured.
+//! Every challenge issued, and every answer to one, is tallied under the
+//! difficulty the challenge was issued at: in per-minute buckets in Redis
+//! (`cerberus:difficulty:{minute}`, shared by the cluster and kept as long
+//! as the longest window) and in `fortify_challenge_outcomes_total`.
+//! `/admin/stats/difficulty` sums the buckets over each of `windows_secs`
+//! into pass and abandonment rates per difficulty.
+//!
+//! As in the experiment tallies, abandoned is issued less answered in time,
+//! so it includes challenges still live, and a window's answers include some
+//! to challenges issued before it. Sealed (offline) challenges carry no
+//! difficulty and aren't counted.
+
+use anyhow::Result;
+use cerberus_common::{CaptchaDifficulty, redis_keys};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::captcha::ChallengeCheck;
+use crate::experiments::{Outcome, ratio};
+use crate::metrics;
+
+/// Width of a tally bucket
+const BUCKET_SECS: i64 = 60;
+
+/// Challenge stats settings (`[challenge_stats]` in fortify.toml)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChallengeStatsConfig {
+    pub enabled: bool,
+    /// Periods the report sums over (60 to 86400 seconds each)
+    pub windows_secs: Vec<u64>,
+}
+
+impl Default for ChallengeStatsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            windows_secs: vec![900, 3600, 86400],
+        }
+    }
+}
+
+/// Tallies of one difficulty over a window, cluster-wide
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DifficultyReport {
+    pub difficulty: CaptchaDifficulty,
+    pub issued: u64,
+    pub passed: u64,
+    pub failed: u64,
+    /// Answered after it expired
+    pub expired: u64,
+    /// Issued but not answered in time (includes challenges still live)
+    pub abandoned: u64,
+    /// Passed out of answered in time
+    pub pass_rate: Option<f64>,
+    /// Abandoned out of issued
+    pub abandonment_rate: Option<f64>,
+}
+
+impl DifficultyReport {
+    fn new(difficulty: CaptchaDifficulty, totals: &HashMap<String, u64>) -> Self {
+        let count = |outcome: Outcome| {
+            totals
+                .get(&field(difficulty, outcome))
+                .copied()
+                .unwrap_or(0)
+        };
+        let (issued, passed, failed) = (
+            count(Outcome::Issued),
+            count(Outcome::Passed),
+            count(Outcome::Failed),
+        );
+        let abandoned = issued.saturating_sub(passed + failed);
+        Self {
+            difficulty,
+            issued,
+            passed,
+            failed,
+            expired: count(Outcome::Expired),
+            abandoned,
+            pass_rate: ratio(passed, passed + failed),
+            abandonment_rate: ratio(abandoned, issued),
+        }
+    }
+}
+
+/// Tallies of every difficulty over one window
+#[derive(Debug, Clone, Serialize)]
+pub struct WindowReport {
+    pub window_secs: u64,
+    pub difficulties: Vec<DifficultyReport>,
+}
+
+/// Hash field of a difficulty's tally
+fn field(difficulty: CaptchaDifficulty, outcome: Outcome) -> String {
+    format!("{}:{}", difficulty.as_str(), outcome.as_str())
+}
+
+/// Buckets making up a window of `window_secs`
+fn buckets_in(window_secs: u64) -> usize {
+    (window_secs as i64 / BUCKET_SECS).max(1) as usize
+}
+
+/// Field-wise sum of bucket tallies
+fn sum(buckets: &[HashMap<String, u64>]) -> HashMap<String, u64> {
+    let mut totals = HashMap::new();
+    for (field, count) in buckets.iter().flatten() {
+        *totals.entry(field.clone()).or_default() += count;
+    }
+    totals
+}
+
+/// Tallies challenge outcomes by difficulty
+#[derive(Debug)]
+pub struct ChallengeStats {
+    config: ChallengeStatsConfig,
+}
+
+impl ChallengeStats {
+    pub fn new(config: ChallengeStatsConfig) -> Self {
+        Self { config }
+    }
+
+    /// Tally `outcome` of a challenge issued at `difficulty` into `pipe`
+    pub fn queue(
+        &self,
+        pipe: &mut redis::Pipeline,
+        difficulty: CaptchaDifficulty,
+        outcome: Outcome,
+        now: i64,
+    ) {
+        if !self.config.enabled {
+            return;
+        }
+        let key = redis_keys::difficulty_tally(now / BUCKET_SECS);
+        let longest = self.config.windows_secs.iter().max().copied().unwrap_or(0);
+        pipe.hincr(&key, field(difficulty, outcome), 1)
+            .ignore()
+            .expire(&key, longest as i64 + BUCKET_SECS)
+            .ignore();
+        metrics::CHALLENGE_OUTCOMES
+            .with_label_values(&[difficulty.as_str(), outcome.as_str()])
+            .inc();
+    }
+
+    /// Tally an answer to a challenge issued at `difficulty` (best-effort;
+    /// answers to unknown challenges aren't counted)
+    pub async fn record(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        difficulty: Option<CaptchaDifficulty>,
+        check: ChallengeCheck,
+    ) {
+        let (Some(difficulty), Some(outcome)) = (difficulty, Outcome::of_answer(check)) else {
+            return;
+        };
+        if !self.config.enabled {
+            return;
+        }
+        let mut pipe = redis::pipe();
+        self.queue(
+            &mut pipe,
+            difficulty,
+            outcome,
+            chrono::Utc::now().timestamp(),
+        );
+        if let Err(e) = pipe.query_async::<()>(redis).await {
+            tracing::warn!(error = %e, "Failed to tally challenge outcome");
+        }
+    }
+
+    /// Tallies of every difficulty over each window ending at `now`
+    pub async fn report(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        now: i64,
+    ) -> Result<Vec<WindowReport>> {
+        let Some(longest) = self.config.windows_secs.iter().max().copied() else {
+            return Ok(Vec::new());
+        };
+        // Newest first, so each window is a prefix
+        let current = now / BUCKET_SECS;
+        let mut pipe = redis::pipe();
+        for bucket in (0..buckets_in(longest) as i64).map(|age| current - age) {
+            pipe.hgetall(redis_keys::difficulty_tally(bucket));
+        }
+        let buckets: Vec<HashMap<String, u64>> = pipe.query_async(redis).await?;
+
+        Ok(self
+            .config
+            .windows_secs
+            .iter()
+            .map(|&window_secs| {
+                let totals = sum(&buckets[..buckets_in(window_secs).min(buckets.len())]);
+                WindowReport {
+                    window_secs,
+                    difficulties: CaptchaDifficulty::ALL
+                        .into_iter()
+                        .map(|difficulty| DifficultyReport::new(difficulty, &totals))
+                        .collect(),
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tallies(entries: &[(&str, u64)]) -> HashMap<String, u64> {
+        entries
+            .iter()
+            .map(|&(field, count)| (field.to_string(), count))
+            .collect()
+    }
+
+    #[test]
+    fn test_difficulty_report() {
+        let totals = tallies(&[
+            ("hard:issued", 10),
+            ("hard:passed", 6),
+            ("hard:failed", 2),
+            ("hard:expired", 1),
+            ("easy:issued", 3),
+        ]);
+        let hard = DifficultyReport::new(CaptchaDifficulty::Hard, &totals);
+        assert_eq!(hard.abandoned, 2);
+        assert_eq!(hard.expired, 1);
+        assert_eq!(hard.pass_rate, Some(0.75));
+        assert_eq!(hard.abandonment_rate, Some(0.2));
+
+        let easy = DifficultyReport::new(CaptchaDifficulty::Easy, &totals);
+        assert_eq!(easy.abandoned, 3);
+        assert_eq!(easy.pass_rate, None);
+
+        let medium = DifficultyReport::new(CaptchaDifficulty::Medium, &totals);
+        assert_eq!(medium.issued, 0);
+        assert_eq!(medium.abandonment_rate, None);
+    }
+
+    #[test]
+    fn test_windows_sum_newest_buckets() {
+        let buckets = [
+            tallies(&[("easy:issued", 4), ("easy:passed", 1)]),
+            tallies(&[("easy:issued", 2)]),
+            HashMap::new(),
+            tallies(&[("easy:passed", 5)]),
+        ];
+        assert_eq!(buckets_in(60), 1);
+        assert_eq!(buckets_in(30), 1);
+        assert_eq!(buckets_in(900), 15);
+
+        let totals = sum(&buckets[..2]);
+        assert_eq!(totals, tallies(&[("easy:issued", 6), ("easy:passed", 1)]));
+        let totals = sum(&buckets);
+        assert_eq!(totals["easy:passed"], 6);
+    }
+}
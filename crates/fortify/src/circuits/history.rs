@@ -0,0 +1,157 @@
+//! Per-circuit event history.
+//!
+//! Keeps the last N notable events for each circuit in a Redis list
+//! (`events:{circuit_id}`, newest first) so operators can see why a circuit
+//! ended up soft-locked or banned. The list is capped with `LTRIM` and
+//! expires with the circuit, so it never grows unbounded.
+
+use anyhow::Result;
+use cerberus_common::{CircuitId, redis_keys};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+
+/// What happened to a circuit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitEventKind {
+    /// A challenge was issued
+    ChallengeIssued,
+    /// A challenge was answered incorrectly (or had expired)
+    Failed,
+    /// A challenge expired without an answer
+    Abandoned,
+    /// A challenge was solved and a passport granted
+    Solved,
+    /// Promoted to VIP
+    VipPromoted,
+    /// VIP let through without a challenge
+    VipFastPass,
+    /// Let through on the gate's proof-of-work, without a visual challenge
+    PowPass,
+    /// Lost VIP status
+    VipDemoted,
+    /// Requested a honeypot URL
+    HoneypotHit,
+    /// Too many failures, circuit soft-locked
+    SoftLocked,
+    /// Exceeded its request rate limit
+    RateLimited,
+    /// Too many wrong answers in a short burst, locked out of the gate
+    GateLocked,
+    /// Banned (admin or automatic)
+    Banned,
+    /// Ban or soft-lock lifted by an admin
+    Unbanned,
+    /// Passport revoked by an admin
+    PassportRevoked,
+}
+
+/// A single history entry
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CircuitEvent {
+    pub kind: CircuitEventKind,
+    /// Unix timestamp (seconds)
+    pub at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl CircuitEvent {
+    pub fn new(kind: CircuitEventKind) -> Self {
+        Self {
+            kind,
+            at: chrono::Utc::now().timestamp(),
+            detail: None,
+        }
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+}
+
+/// Bounded event list writer/reader
+#[derive(Debug, Clone)]
+pub struct EventLog {
+    /// Events kept per circuit (0 = disabled)
+    max_events: usize,
+    /// List TTL in seconds (refreshed on every append)
+    ttl: u64,
+}
+
+impl EventLog {
+    pub fn new(max_events: usize, ttl: u64) -> Self {
+        Self { max_events, ttl }
+    }
+
+    /// Add the append commands to a pipeline (so they can share a transaction)
+    pub fn queue(&self, pipe: &mut redis::Pipeline, circuit_id: &CircuitId, event: &CircuitEvent) {
+        if self.max_events == 0 {
+            return;
+        }
+        let Ok(value) = serde_json::to_string(event) else {
+            return;
+        };
+
+        let key = redis_keys::events(circuit_id);
+        pipe.lpush(&key, value)
+            .ignore()
+            .ltrim(&key, 0, self.max_events as isize - 1)
+            .ignore()
+            .expire(&key, self.ttl as i64)
+            .ignore();
+    }
+
+    /// Append an event
+    pub async fn record(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        circuit_id: &CircuitId,
+        event: CircuitEvent,
+    ) -> Result<()> {
+        if self.max_events == 0 {
+            return Ok(());
+        }
+
+        let mut pipe = redis::pipe();
+        self.queue(&mut pipe, circuit_id, &event);
+        pipe.query_async::<()>(redis).await?;
+
+        Ok(())
+    }
+
+    /// Recent events, newest first (unparseable entries are skipped)
+    pub async fn recent(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        circuit_id: &CircuitId,
+    ) -> Result<Vec<CircuitEvent>> {
+        let key = redis_keys::events(circuit_id);
+        let raw: Vec<String> = redis.lrange(&key, 0, -1).await?;
+
+        Ok(raw
+            .iter()
+            .filter_map(|entry| serde_json::from_str(entry).ok())
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_serialization() {
+        let event =
+            CircuitEvent::new(CircuitEventKind::SoftLocked).with_detail("5 failed attempts");
+        let json = serde_json::to_string(&event).unwrap();
+        assert!(json.contains("\"kind\":\"soft_locked\""));
+
+        let parsed: CircuitEvent = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, event);
+
+        let bare = serde_json::to_string(&CircuitEvent::new(CircuitEventKind::Solved)).unwrap();
+        assert!(!bare.contains("detail"));
+    }
+}
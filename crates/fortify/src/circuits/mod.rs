@@ -2,6 +2,15 @@
 //!
 //! Tracks Tor circuit state, rate limits, and reputation.
 
+mod history;
+mod replay;
+mod solve_time;
+mod throttle;
 mod tracker;
+pub mod transfer;
 
-pub use tracker::CircuitTracker;
+pub use history::{CircuitEvent, CircuitEventKind, EventLog};
+pub use replay::{CircuitMutation, MutationQueue};
+pub use solve_time::{FarmOutlier, SolveSample, SolveTimeAnalyzer};
+pub use throttle::VerifyThrottle;
+pub use tracker::{CircuitTracker, RateLimitStatus, StorageEntry};
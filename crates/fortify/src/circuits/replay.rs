@@ -0,0 +1,131 @@
+//! Circuit state mutations queued while Redis is offline.
+//!
+//! Handlers push mutations here instead of failing; the Redis guard replays
+//! them in order once the connection is reattached. The queue is bounded:
+//! when full, the oldest mutation is dropped so memory stays flat during a
+//! long outage.
+
+use cerberus_common::{CircuitId, PassportToken};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use super::CircuitTracker;
+
+/// A deferred circuit state change
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CircuitMutation {
+    /// Failed CAPTCHA attempt
+    Failure { circuit_id: CircuitId },
+    /// Successful solve with its passport
+    Success {
+        circuit_id: CircuitId,
+        passport_token: PassportToken,
+        passport_expires: i64,
+    },
+    /// Admin ban
+    Ban {
+        circuit_id: CircuitId,
+        reason: String,
+    },
+    /// Honeypot URL requested (reported by the proxy)
+    HoneypotHit { circuit_id: CircuitId },
+}
+
+/// Bounded FIFO of pending mutations
+pub struct MutationQueue {
+    pending: Mutex<VecDeque<CircuitMutation>>,
+    capacity: usize,
+}
+
+impl MutationQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            pending: Mutex::new(VecDeque::new()),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Queue a mutation, dropping the oldest if full
+    pub fn push(&self, mutation: CircuitMutation) {
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        if pending.len() >= self.capacity {
+            pending.pop_front();
+            tracing::warn!(
+                capacity = self.capacity,
+                "Offline mutation queue full, dropping oldest"
+            );
+        }
+        pending.push_back(mutation);
+    }
+
+    /// Number of queued mutations
+    pub fn len(&self) -> usize {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// Is the queue empty?
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn pop(&self) -> Option<CircuitMutation> {
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop_front()
+    }
+
+    fn push_front(&self, mutation: CircuitMutation) {
+        self.pending
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push_front(mutation);
+    }
+
+    /// Apply queued mutations in order
+    ///
+    /// Stops at the first Redis error, keeping that mutation at the head of
+    /// the queue for the next attempt. Returns how many were applied.
+    pub async fn replay(
+        &self,
+        tracker: &CircuitTracker,
+        redis: &mut redis::aio::ConnectionManager,
+    ) -> usize {
+        let mut applied = 0;
+
+        while let Some(mutation) = self.pop() {
+            if let Err(e) = tracker.apply(redis, &mutation).await {
+                tracing::warn!(error = %e, "Mutation replay interrupted");
+                self.push_front(mutation);
+                break;
+            }
+            applied += 1;
+        }
+
+        applied
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failure(id: &str) -> CircuitMutation {
+        CircuitMutation::Failure {
+            circuit_id: id.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_queue_drops_oldest_when_full() {
+        let queue = MutationQueue::new(2);
+        queue.push(failure("a"));
+        queue.push(failure("b"));
+        queue.push(failure("c"));
+
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.pop(), Some(failure("b")));
+        assert_eq!(queue.pop(), Some(failure("c")));
+        assert_eq!(queue.pop(), None);
+    }
+}
@@ -0,0 +1,307 @@
+//! CAPTCHA farm detection via solve-time analysis.
+//!
+//! Humans are slow and noisy when solving; solving farms and OCR pipelines
+//! are fast and metronomic. Every successful solve is recorded as a sample in
+//! a bounded per-circuit Redis list, and the recent window is scored for:
+//! - Implausibly fast solves (median below human reaction + typing time)
+//! - Suspiciously uniform solve times (low coefficient of variation)
+//! - Canonical answer entry (always exactly the rendered uppercase glyphs)
+//!
+//! Flagged circuits are recorded in a sorted set for the admin outlier view.
+
+use anyhow::Result;
+use cerberus_common::{CircuitId, redis_keys};
+use redis::AsyncCommands;
+use serde::Serialize;
+
+use crate::config::FarmDetectionConfig;
+
+/// Maximum number of circuits kept in the outlier set
+const MAX_TRACKED_SUSPECTS: isize = 1000;
+
+/// A single successful solve
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SolveSample {
+    /// Milliseconds between challenge issuance and answer
+    pub solve_ms: u64,
+    /// Answer was entered in canonical form (uppercase, no whitespace)
+    pub canonical_entry: bool,
+}
+
+impl SolveSample {
+    /// Build a sample, classifying how the answer was entered
+    pub fn new(solve_ms: u64, answer: &str) -> Self {
+        Self {
+            solve_ms,
+            canonical_entry: is_canonical_entry(answer),
+        }
+    }
+
+    fn encode(&self) -> String {
+        format!("{}:{}", self.solve_ms, u8::from(self.canonical_entry))
+    }
+
+    fn decode(raw: &str) -> Option<Self> {
+        let (ms, canonical) = raw.split_once(':')?;
+        Some(Self {
+            solve_ms: ms.parse().ok()?,
+            canonical_entry: canonical == "1",
+        })
+    }
+}
+
+/// Result of analyzing a circuit's recent solves
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SolveVerdict {
+    /// Circuit looks like a solving farm / bot
+    pub suspicious: bool,
+    /// Reasons contributing to the verdict
+    pub reasons: Vec<&'static str>,
+    /// Number of samples analyzed
+    pub samples: usize,
+    /// Median solve time in milliseconds
+    pub median_ms: u64,
+    /// Coefficient of variation (stddev / mean)
+    pub cv: f64,
+    /// Suspicious because of the newest solve, not just an instant solve
+    /// penalized when it was the newest
+    #[serde(skip)]
+    pub penalize: bool,
+}
+
+/// Admin view of a flagged circuit
+#[derive(Debug, Clone, Serialize)]
+pub struct FarmOutlier {
+    pub circuit_id: CircuitId,
+    pub flagged_at: i64,
+    #[serde(flatten)]
+    pub verdict: SolveVerdict,
+}
+
+/// Solve-time analyzer service
+pub struct SolveTimeAnalyzer {
+    config: FarmDetectionConfig,
+}
+
+impl SolveTimeAnalyzer {
+    pub fn new(config: FarmDetectionConfig) -> Self {
+        Self { config }
+    }
+
+    /// Is farm detection enabled?
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Reputation penalty for a flagged circuit
+    pub fn reputation_penalty(&self) -> i32 {
+        self.config.reputation_penalty
+    }
+
+    /// Record a successful solve and return the updated verdict
+    ///
+    /// Newly flagged circuits are added to the outlier set.
+    pub async fn record(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        circuit_id: &CircuitId,
+        sample: SolveSample,
+    ) -> Result<SolveVerdict> {
+        let key = redis_keys::solve_times(circuit_id);
+        let window = self.config.window.max(1) as isize;
+        let ttl = key.ttl().secs().expect("solve times have a fixed TTL");
+
+        let raw: Vec<String> = redis::pipe()
+            .lpush(&key, sample.encode())
+            .ignore()
+            .ltrim(&key, 0, window - 1)
+            .ignore()
+            .expire(&key, ttl as i64)
+            .ignore()
+            .lrange(&key, 0, window - 1)
+            .query_async(redis)
+            .await?;
+
+        let samples: Vec<SolveSample> = raw.iter().filter_map(|s| SolveSample::decode(s)).collect();
+        let verdict = self.analyze(&samples);
+
+        if verdict.suspicious {
+            let now = chrono::Utc::now().timestamp();
+            let suspects = redis_keys::farm_suspects();
+            let _: () = redis::pipe()
+                .zadd(&suspects, circuit_id, now)
+                .ignore()
+                .zremrangebyrank(&suspects, 0, -(MAX_TRACKED_SUSPECTS + 1))
+                .ignore()
+                .query_async(redis)
+                .await?;
+
+            tracing::warn!(
+                circuit_id = %circuit_id,
+                median_ms = verdict.median_ms,
+                cv = verdict.cv,
+                reasons = ?verdict.reasons,
+                "Possible CAPTCHA farm detected"
+            );
+        }
+
+        Ok(verdict)
+    }
+
+    /// List the most recently flagged circuits with their current verdicts
+    pub async fn outliers(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        limit: usize,
+    ) -> Result<Vec<FarmOutlier>> {
+        let flagged: Vec<(String, i64)> = redis
+            .zrevrange_withscores(redis_keys::farm_suspects(), 0, limit.max(1) as isize - 1)
+            .await?;
+
+        let mut outliers = Vec::with_capacity(flagged.len());
+        for (circuit_id, flagged_at) in flagged {
+            // Only valid IDs are ever flagged
+            let Ok(circuit_id) = circuit_id.parse::<CircuitId>() else {
+                continue;
+            };
+            let key = redis_keys::solve_times(&circuit_id);
+            let raw: Vec<String> = redis.lrange(&key, 0, -1).await?;
+            let samples: Vec<SolveSample> =
+                raw.iter().filter_map(|s| SolveSample::decode(s)).collect();
+
+            outliers.push(FarmOutlier {
+                circuit_id,
+                flagged_at,
+                verdict: self.analyze(&samples),
+            });
+        }
+
+        Ok(outliers)
+    }
+
+    /// Score a window of samples (newest first)
+    pub fn analyze(&self, samples: &[SolveSample]) -> SolveVerdict {
+        if samples.is_empty() {
+            return SolveVerdict::default();
+        }
+
+        let mut times: Vec<u64> = samples.iter().map(|s| s.solve_ms).collect();
+        times.sort_unstable();
+        let median_ms = times[times.len() / 2];
+
+        let mean = times.iter().sum::<u64>() as f64 / times.len() as f64;
+        let variance = times
+            .iter()
+            .map(|&t| (t as f64 - mean).powi(2))
+            .sum::<f64>()
+            / times.len() as f64;
+        let cv = if mean > 0.0 {
+            variance.sqrt() / mean
+        } else {
+            0.0
+        };
+
+        let mut reasons = Vec::new();
+        let mut score = 0;
+
+        // An instant solve is penalized once, when it's recorded, not again
+        // on every solve while it stays in the window
+        let mut penalized_before = 0;
+        if times[0] < self.config.instant_solve_ms {
+            reasons.push("instant_solve");
+            score += 2;
+            if samples[0].solve_ms >= self.config.instant_solve_ms {
+                penalized_before = 2;
+            }
+        }
+        if times.len() >= 2 && median_ms < self.config.min_human_solve_ms {
+            reasons.push("too_fast");
+            score += 2;
+        }
+
+        let enough = samples.len() >= self.config.min_samples;
+        if enough && cv < self.config.max_uniform_cv {
+            reasons.push("uniform_timing");
+            score += 2;
+        }
+        if enough && samples.iter().all(|s| s.canonical_entry) {
+            reasons.push("canonical_entry");
+            score += 1;
+        }
+
+        SolveVerdict {
+            suspicious: score >= 2,
+            reasons,
+            samples: samples.len(),
+            median_ms,
+            cv,
+            penalize: score - penalized_before >= 2,
+        }
+    }
+}
+
+/// Canonical entry = exactly what a renderer/OCR would emit: uppercase
+/// alphanumerics with no surrounding whitespace
+fn is_canonical_entry(answer: &str) -> bool {
+    !answer.is_empty()
+        && answer
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(ms: u64, canonical: bool) -> SolveSample {
+        SolveSample {
+            solve_ms: ms,
+            canonical_entry: canonical,
+        }
+    }
+
+    #[test]
+    fn test_human_solves_not_flagged() {
+        let analyzer = SolveTimeAnalyzer::new(FarmDetectionConfig::default());
+        let samples = [
+            sample(6200, false),
+            sample(9100, true),
+            sample(4800, false),
+            sample(12_400, false),
+        ];
+        let verdict = analyzer.analyze(&samples);
+        assert!(!verdict.suspicious, "{:?}", verdict.reasons);
+    }
+
+    #[test]
+    fn test_fast_uniform_solves_flagged() {
+        let analyzer = SolveTimeAnalyzer::new(FarmDetectionConfig::default());
+        let samples = [sample(1500, true), sample(1520, true), sample(1490, true)];
+        let verdict = analyzer.analyze(&samples);
+        assert!(verdict.suspicious);
+        assert!(verdict.reasons.contains(&"too_fast"));
+        assert!(verdict.reasons.contains(&"uniform_timing"));
+        assert!(verdict.reasons.contains(&"canonical_entry"));
+    }
+
+    #[test]
+    fn test_instant_solve_penalized_once() {
+        let analyzer = SolveTimeAnalyzer::new(FarmDetectionConfig::default());
+        let instant = analyzer.analyze(&[sample(100, false)]);
+        assert!(instant.suspicious && instant.penalize);
+
+        // Still flagged while in the window, but not penalized again
+        let later = analyzer.analyze(&[sample(8000, false), sample(100, false)]);
+        assert!(later.reasons.contains(&"instant_solve"));
+        assert!(later.suspicious);
+        assert!(!later.penalize);
+    }
+
+    #[test]
+    fn test_sample_roundtrip() {
+        let s = SolveSample::new(3400, "AB12C");
+        assert!(s.canonical_entry);
+        assert_eq!(SolveSample::decode(&s.encode()), Some(s));
+        assert!(!SolveSample::new(3400, "ab12c").canonical_entry);
+    }
+}
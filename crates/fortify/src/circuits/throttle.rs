@@ -0,0 +1,167 @@
+//! Answer throttling and gate lockout.
+//!
+//! Counts the answers each circuit posts, apart from its general request
+//! budget, and its recent wrong answers. A burst of wrong answers locks the
+//! circuit out of the gate for `lockout_secs`. Unlike a soft-lock, which
+//! follows from failures piling up since the last solve, a lockout is short
+//! and aimed at rapid guessing across fresh challenges.
+
+use anyhow::Result;
+use cerberus_common::{CircuitId, redis_keys};
+use redis::AsyncCommands;
+use std::time::Duration;
+
+use super::{CircuitEvent, CircuitEventKind, EventLog};
+use crate::config::VerifyThrottleConfig;
+
+/// Answer throttling service
+pub struct VerifyThrottle {
+    config: VerifyThrottleConfig,
+    /// Per-circuit event history (lockouts are recorded)
+    events: EventLog,
+}
+
+impl VerifyThrottle {
+    pub fn new(config: VerifyThrottleConfig, events: EventLog) -> Self {
+        Self { config, events }
+    }
+
+    /// How long a throttled request is held before it's answered
+    pub fn tarpit_delay(&self) -> Duration {
+        Duration::from_millis(self.config.tarpit_delay_ms)
+    }
+
+    /// Count an answer; returns the seconds until the circuit may answer
+    /// again if it's over `max_attempts_per_minute`
+    #[tracing::instrument(name = "circuit.verify_attempt", skip(self, redis))]
+    pub async fn count_attempt(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        circuit_id: &CircuitId,
+    ) -> Result<Option<u64>> {
+        let max = self.config.max_attempts_per_minute;
+        if max == 0 {
+            return Ok(None);
+        }
+        let key = redis_keys::verify_attempts(circuit_id);
+        let window = key
+            .ttl()
+            .secs()
+            .expect("verify attempt counters have a fixed TTL");
+
+        let (count, ttl): (u32, i64) = redis::pipe()
+            .incr(&key, 1)
+            .ttl(&key)
+            .query_async(redis)
+            .await?;
+        let reset_secs = if count == 1 || ttl < 0 {
+            redis.expire::<_, ()>(&key, window as i64).await?;
+            window
+        } else {
+            ttl as u64
+        };
+
+        Ok((count > max).then_some(reset_secs))
+    }
+
+    /// Seconds left on the circuit's gate lockout, if it has one
+    pub async fn locked_for(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        circuit_id: &CircuitId,
+    ) -> Result<Option<u64>> {
+        let ttl: i64 = redis.ttl(redis_keys::gate_lockout(circuit_id)).await?;
+        Ok((ttl > 0).then_some(ttl as u64))
+    }
+
+    /// Count a wrong answer; returns `true` if it locked the gate
+    pub async fn record_wrong(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        circuit_id: &CircuitId,
+    ) -> Result<bool> {
+        if self.config.lockout_after_wrong == 0 {
+            return Ok(false);
+        }
+        let key = redis_keys::wrong_answers(circuit_id);
+        let count: u32 = redis.incr(&key, 1).await?;
+        if count == 1 {
+            redis
+                .expire::<_, ()>(&key, self.config.wrong_window_secs.as_i64())
+                .await?;
+        }
+        if !self.locks_out(count) {
+            return Ok(false);
+        }
+
+        redis::pipe()
+            .atomic()
+            .set_ex(
+                redis_keys::gate_lockout(circuit_id),
+                chrono::Utc::now().timestamp(),
+                self.config.lockout_secs.secs(),
+            )
+            .ignore()
+            .del(&key)
+            .ignore()
+            .query_async::<()>(redis)
+            .await?;
+
+        let event = CircuitEvent::new(CircuitEventKind::GateLocked).with_detail(format!(
+            "{} wrong answers within {}",
+            count, self.config.wrong_window_secs
+        ));
+        if let Err(e) = self.events.record(redis, circuit_id, event).await {
+            tracing::warn!(error = %e, circuit_id = %circuit_id, "Failed to record circuit event");
+        }
+        tracing::warn!(
+            circuit_id = %circuit_id,
+            wrong_answers = count,
+            lockout_secs = self.config.lockout_secs.secs(),
+            "Circuit locked out of the gate"
+        );
+        Ok(true)
+    }
+
+    /// Lift a gate lockout; `false` if there was none
+    pub async fn unlock(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        circuit_id: &CircuitId,
+    ) -> Result<bool> {
+        let (removed,): (u32,) = redis::pipe()
+            .del(redis_keys::gate_lockout(circuit_id))
+            .del(redis_keys::wrong_answers(circuit_id))
+            .ignore()
+            .query_async(redis)
+            .await?;
+        Ok(removed > 0)
+    }
+
+    /// Does the `wrong`th wrong answer in a window lock the gate?
+    fn locks_out(&self, wrong: u32) -> bool {
+        self.config.lockout_after_wrong > 0 && wrong >= self.config.lockout_after_wrong
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lockout_threshold() {
+        let throttle = VerifyThrottle::new(VerifyThrottleConfig::default(), EventLog::new(0, 0));
+        assert!(!throttle.locks_out(4));
+        assert!(throttle.locks_out(5));
+        assert_eq!(throttle.tarpit_delay(), Duration::from_secs(3));
+
+        let never = VerifyThrottle::new(
+            VerifyThrottleConfig {
+                lockout_after_wrong: 0,
+                ..Default::default()
+            },
+            EventLog::new(0, 0),
+        );
+        assert!(!never.locks_out(1_000));
+    }
+}
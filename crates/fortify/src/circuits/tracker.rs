@@ -1,8 +1,23 @@
 //! Circuit state tracking with Redis backend.
 
 use anyhow::Result;
-use cerberus_common::{CircuitInfo, CircuitStatus};
+use cerberus_common::{
+    CerberusEvent, CircuitId, CircuitInfo, CircuitStatus, EventBus, EventPublisher, PassportToken,
+    StatusEvent, ThreatLevel, redis_keys, versioned,
+};
 use redis::AsyncCommands;
+use std::sync::Arc;
+
+use super::{CircuitEvent, CircuitEventKind, CircuitMutation, EventLog};
+use crate::config::VipConfig;
+
+/// A Redis string write (`SET key value EX ttl`) prepared for a transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageEntry {
+    pub key: String,
+    pub value: String,
+    pub ttl: u64,
+}
 
 /// Circuit tracking service
 pub struct CircuitTracker {
@@ -14,6 +29,12 @@ pub struct CircuitTracker {
     soft_lock_duration: u64,
     /// Ban duration in seconds
     ban_duration: u64,
+    /// Per-circuit event history
+    events: EventLog,
+    /// VIP promotion and privileges
+    vip: VipConfig,
+    /// Receives bans and soft-locks once they are stored
+    publisher: Arc<dyn EventPublisher>,
 }
 
 impl CircuitTracker {
@@ -22,28 +43,44 @@ impl CircuitTracker {
         max_failed_attempts: u32,
         soft_lock_duration: u64,
         ban_duration: u64,
+        events: EventLog,
+        vip: VipConfig,
     ) -> Self {
         Self {
             circuit_ttl,
             max_failed_attempts,
             soft_lock_duration,
             ban_duration,
+            events,
+            vip,
+            publisher: Arc::new(EventBus::new()),
         }
     }
 
+    /// Publish bans and soft-locks to `publisher`
+    pub fn with_publisher(mut self, publisher: Arc<dyn EventPublisher>) -> Self {
+        self.publisher = publisher;
+        self
+    }
+
+    /// Per-circuit event history
+    pub fn events(&self) -> &EventLog {
+        &self.events
+    }
+
     /// Get or create circuit info
     pub async fn get_or_create(
         &self,
         redis: &mut redis::aio::ConnectionManager,
-        circuit_id: &str,
+        circuit_id: &CircuitId,
     ) -> Result<CircuitInfo> {
-        let key = format!("circuit:{}", circuit_id);
+        let key = redis_keys::circuit(circuit_id);
 
         // Try to get existing
         let existing: Option<String> = redis.get(&key).await?;
 
         if let Some(data) = existing {
-            let mut info: CircuitInfo = serde_json::from_str(&data)?;
+            let mut info: CircuitInfo = versioned::decode(&data)?;
             info.last_seen = chrono::Utc::now().timestamp();
 
             // Update last_seen
@@ -53,7 +90,7 @@ impl CircuitTracker {
         }
 
         // Create new circuit
-        let info = CircuitInfo::new(circuit_id.to_string());
+        let info = CircuitInfo::new(circuit_id.clone());
         self.save(redis, &info).await?;
 
         tracing::debug!(circuit_id = %circuit_id, "New circuit tracked");
@@ -61,30 +98,173 @@ impl CircuitTracker {
         Ok(info)
     }
 
+    /// Load circuit info for modification without writing it back
+    pub async fn load(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        circuit_id: &CircuitId,
+    ) -> Result<CircuitInfo> {
+        let mut info = self
+            .get(redis, circuit_id)
+            .await?
+            .unwrap_or_else(|| CircuitInfo::new(circuit_id.clone()));
+        info.last_seen = chrono::Utc::now().timestamp();
+        Ok(info)
+    }
+
+    /// Load circuit info for a read-modify-write, along with the stored
+    /// value `save_if_unchanged` checks is still there
+    pub async fn load_for_update(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        circuit_id: &CircuitId,
+    ) -> Result<(CircuitInfo, Option<String>)> {
+        let stored: Option<String> = redis.get(redis_keys::circuit(circuit_id)).await?;
+        let mut info = match stored {
+            Some(ref d) => versioned::decode(d)?,
+            None => CircuitInfo::new(circuit_id.clone()),
+        };
+        info.last_seen = chrono::Utc::now().timestamp();
+        Ok((info, stored))
+    }
+
+    /// Save circuit info (and `with`, e.g. the passport it was granted) if
+    /// the stored record is still `stored`
+    ///
+    /// Returns `false`, writing nothing, if another request changed the
+    /// circuit since `load_for_update`. Its VIP slot and history events are
+    /// left to `queue_related`.
+    #[tracing::instrument(name = "circuit.save_if_unchanged", skip_all, fields(circuit_id = %info.circuit_id))]
+    pub async fn save_if_unchanged(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        info: &CircuitInfo,
+        stored: Option<&str>,
+        with: Option<&StorageEntry>,
+    ) -> Result<bool> {
+        let entry = self.entry(info)?;
+        let script = compare_and_save_script();
+        let mut invocation = script.key(&entry.key);
+        invocation
+            .arg(stored.unwrap_or(""))
+            .arg(&entry.value)
+            .arg(entry.ttl);
+        if let Some(with) = with {
+            invocation.key(&with.key).arg(&with.value).arg(with.ttl);
+        }
+        let saved: bool = invocation.invoke_async(redis).await?;
+        Ok(saved)
+    }
+
     /// Get circuit info (if exists)
+    #[tracing::instrument(name = "circuit.get", skip(self, redis))]
     pub async fn get(
         &self,
         redis: &mut redis::aio::ConnectionManager,
-        circuit_id: &str,
+        circuit_id: &CircuitId,
     ) -> Result<Option<CircuitInfo>> {
-        let key = format!("circuit:{}", circuit_id);
+        let key = redis_keys::circuit(circuit_id);
         let data: Option<String> = redis.get(&key).await?;
 
         match data {
-            Some(d) => Ok(Some(serde_json::from_str(&d)?)),
+            Some(d) => Ok(Some(versioned::decode(&d)?)),
             None => Ok(None),
         }
     }
 
     /// Save circuit info to Redis
+    #[tracing::instrument(name = "circuit.save", skip_all, fields(circuit_id = %info.circuit_id))]
     pub async fn save(
         &self,
         redis: &mut redis::aio::ConnectionManager,
         info: &CircuitInfo,
     ) -> Result<()> {
-        let key = format!("circuit:{}", info.circuit_id);
-        let data = serde_json::to_string(info)?;
+        // VIPs also refresh their slot in the VIP set
+        if info.status == CircuitStatus::Vip {
+            return self.save_with_events(redis, info, &[]).await;
+        }
+
+        let entry = self.entry(info)?;
+        redis
+            .set_ex::<_, _, ()>(&entry.key, &entry.value, entry.ttl)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Save circuit info and append its events in one transaction
+    #[tracing::instrument(name = "circuit.save", skip_all, fields(circuit_id = %info.circuit_id))]
+    pub async fn save_with_events(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        info: &CircuitInfo,
+        events: &[CircuitEvent],
+    ) -> Result<()> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        self.queue_save(&mut pipe, info, events)?;
+        pipe.query_async::<()>(redis).await?;
+        self.announce(&info.circuit_id, events);
 
+        Ok(())
+    }
+
+    /// Publish the history events other components care about
+    ///
+    /// Call once the write queued by `queue_save` has gone through.
+    pub fn announce(&self, circuit_id: &CircuitId, events: &[CircuitEvent]) {
+        for event in events {
+            let circuit_id = circuit_id.clone();
+            let reason = event.detail.clone();
+            self.publisher.publish(match event.kind {
+                CircuitEventKind::Banned => CerberusEvent::CircuitBanned { circuit_id, reason },
+                CircuitEventKind::SoftLocked => {
+                    CerberusEvent::CircuitSoftLocked { circuit_id, reason }
+                }
+                CircuitEventKind::Unbanned => CerberusEvent::CircuitUnbanned { circuit_id },
+                CircuitEventKind::VipPromoted => CerberusEvent::VipPromoted { circuit_id },
+                CircuitEventKind::VipDemoted => CerberusEvent::VipDemoted { circuit_id, reason },
+                _ => continue,
+            });
+        }
+    }
+
+    /// Add the circuit write and its events to a pipeline
+    pub fn queue_save(
+        &self,
+        pipe: &mut redis::Pipeline,
+        info: &CircuitInfo,
+        events: &[CircuitEvent],
+    ) -> Result<()> {
+        let entry = self.entry(info)?;
+        pipe.set_ex(&entry.key, &entry.value, entry.ttl).ignore();
+        self.queue_related(pipe, info, events);
+
+        Ok(())
+    }
+
+    /// Queue what `queue_save` writes besides the circuit record: its VIP
+    /// slot and history events
+    pub fn queue_related(
+        &self,
+        pipe: &mut redis::Pipeline,
+        info: &CircuitInfo,
+        events: &[CircuitEvent],
+    ) {
+        if info.status == CircuitStatus::Vip {
+            let now = chrono::Utc::now().timestamp();
+            pipe.zadd(redis_keys::vips(), &info.circuit_id, now)
+                .ignore();
+        } else {
+            pipe.zrem(redis_keys::vips(), &info.circuit_id).ignore();
+        }
+        for event in events {
+            self.events.queue(pipe, &info.circuit_id, event);
+        }
+    }
+
+    /// Serialize circuit info into the write `save` would perform
+    pub fn entry(&self, info: &CircuitInfo) -> Result<StorageEntry> {
         // Determine TTL based on status
         let ttl = match info.status {
             CircuitStatus::Banned => self.ban_duration,
@@ -92,64 +272,223 @@ impl CircuitTracker {
             _ => self.circuit_ttl,
         };
 
-        redis.set_ex::<_, _, ()>(&key, &data, ttl).await?;
-
-        Ok(())
+        Ok(StorageEntry {
+            key: redis_keys::circuit(&info.circuit_id).into(),
+            value: versioned::encode(info)?,
+            ttl,
+        })
     }
 
-    /// Record a failed CAPTCHA attempt
-    pub async fn record_failure(
-        &self,
-        redis: &mut redis::aio::ConnectionManager,
-        circuit_id: &str,
-    ) -> Result<CircuitInfo> {
-        let mut info = self.get_or_create(redis, circuit_id).await?;
-
+    /// Apply a failed attempt to circuit state (no I/O)
+    ///
+    /// Returns the events to append to the circuit's history.
+    pub fn apply_failure(&self, info: &mut CircuitInfo) -> Vec<CircuitEvent> {
         info.failed_attempts += 1;
         info.last_seen = chrono::Utc::now().timestamp();
 
-        // Check if should be soft-locked
-        if info.failed_attempts >= self.max_failed_attempts {
-            info.status = CircuitStatus::SoftLocked;
+        let mut events = vec![
+            CircuitEvent::new(CircuitEventKind::Failed).with_detail(format!(
+                "attempt {}/{}",
+                info.failed_attempts, self.max_failed_attempts
+            )),
+        ];
+
+        // Failures since the last solve count against VIP status
+        let limit = self.vip.demote_after_failures;
+        if limit > 0 && info.failed_attempts >= limit {
+            events.extend(self.demote(info, CircuitStatus::Verified, "failed challenges"));
+        }
+
+        // Soft-lock, unless already locked or banned
+        if info.failed_attempts >= self.max_failed_attempts
+            && let Ok(status) = info.status.transition(StatusEvent::Locked)
+        {
+            info.status = status;
             tracing::warn!(
-                circuit_id = %circuit_id,
+                circuit_id = %info.circuit_id,
                 failed_attempts = info.failed_attempts,
                 "Circuit soft-locked due to failed attempts"
             );
+            events.push(
+                CircuitEvent::new(CircuitEventKind::SoftLocked)
+                    .with_detail(format!("{} failed attempts", info.failed_attempts)),
+            );
         }
 
-        self.save(redis, &info).await?;
-
-        Ok(info)
+        events
     }
 
-    /// Record a successful CAPTCHA solve
-    pub async fn record_success(
+    /// Apply a successful solve to circuit state (no I/O)
+    ///
+    /// `vip` comes from `qualifies_for_vip`, checked before the passport is
+    /// granted (VIPs get a longer one).
+    pub fn apply_success(
         &self,
-        redis: &mut redis::aio::ConnectionManager,
-        circuit_id: &str,
-        passport_token: &str,
+        info: &mut CircuitInfo,
+        passport_token: &PassportToken,
         passport_expires: i64,
-    ) -> Result<CircuitInfo> {
-        let mut info = self.get_or_create(redis, circuit_id).await?;
+        vip: bool,
+    ) -> Vec<CircuitEvent> {
+        let promoted = vip && info.status != CircuitStatus::Vip;
+        let event = if vip {
+            StatusEvent::Promoted
+        } else {
+            StatusEvent::Solved
+        };
 
         info.successful_solves += 1;
-        info.status = CircuitStatus::Verified;
-        info.passport_token = Some(passport_token.to_string());
+        // A banned circuit keeps its ban, passport or not
+        match info.status.transition(event) {
+            Ok(status) => info.status = status,
+            Err(e) => tracing::debug!(circuit_id = %info.circuit_id, error = %e, "Status kept"),
+        }
+        info.passport_token = Some(passport_token.clone());
         info.passport_expires = Some(passport_expires);
         info.last_seen = chrono::Utc::now().timestamp();
 
         // Reset failed attempts on success
         info.failed_attempts = 0;
 
-        // Check for VIP upgrade (e.g., 5+ successful solves)
-        if info.successful_solves >= 5 && info.status == CircuitStatus::Verified {
-            info.status = CircuitStatus::Vip;
-            tracing::info!(circuit_id = %circuit_id, "Circuit upgraded to VIP");
+        let mut events = vec![CircuitEvent::new(CircuitEventKind::Solved)];
+        if promoted {
+            tracing::info!(circuit_id = %info.circuit_id, "Circuit upgraded to VIP");
+            events.push(
+                CircuitEvent::new(CircuitEventKind::VipPromoted)
+                    .with_detail(format!("{} solves", info.successful_solves)),
+            );
         }
+        events
+    }
+
+    /// Drop a VIP to `status` (no I/O); no-op for other circuits
+    ///
+    /// `status` is `Verified`, or `SoftLocked` to lock the circuit as well.
+    pub fn demote(
+        &self,
+        info: &mut CircuitInfo,
+        status: CircuitStatus,
+        reason: &str,
+    ) -> Option<CircuitEvent> {
+        let mut demoted = info.status.transition(StatusEvent::Demoted).ok()?;
+        if status == CircuitStatus::SoftLocked {
+            demoted = demoted.transition(StatusEvent::Locked).ok()?;
+        }
+
+        info.status = demoted;
+        tracing::warn!(
+            circuit_id = %info.circuit_id,
+            status = ?status,
+            reason = %reason,
+            "VIP demoted"
+        );
+        Some(CircuitEvent::new(CircuitEventKind::VipDemoted).with_detail(reason))
+    }
+
+    /// Will this circuit be a VIP after its next successful solve?
+    ///
+    /// Existing VIPs stay VIP; others need enough solves, a good enough
+    /// reputation and a free slot under `max_vips`.
+    pub async fn qualifies_for_vip(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        info: &CircuitInfo,
+    ) -> Result<bool> {
+        if info.status == CircuitStatus::Vip {
+            return Ok(true);
+        }
+        if info.successful_solves + 1 < self.vip.min_solves
+            || info.reputation < self.vip.min_reputation
+        {
+            return Ok(false);
+        }
+        if self.vip.max_vips == 0 {
+            return Ok(true);
+        }
+
+        // Slots of VIPs whose circuit record has expired are free again
+        let stale_before = chrono::Utc::now().timestamp() - self.circuit_ttl as i64;
+        let (_, count): ((), u32) = redis::pipe()
+            .zrembyscore(redis_keys::vips(), "-inf", stale_before)
+            .zcard(redis_keys::vips())
+            .query_async(redis)
+            .await?;
+
+        Ok(count < self.vip.max_vips)
+    }
+
+    /// May this circuit skip the challenge at `threat_level`?
+    pub fn skips_challenge(&self, info: &CircuitInfo, threat_level: ThreatLevel) -> bool {
+        info.status == CircuitStatus::Vip && threat_level.value() < self.vip.skip_challenge_below
+    }
+
+    /// Hand a circuit a new passport without a visual challenge (no I/O)
+    ///
+    /// `kind` says why (VIP fast path, proof-of-work); unlike a solve it
+    /// doesn't count towards VIP status.
+    pub fn apply_fast_pass(
+        &self,
+        info: &mut CircuitInfo,
+        passport_token: &PassportToken,
+        passport_expires: i64,
+        kind: CircuitEventKind,
+    ) -> Vec<CircuitEvent> {
+        info.passport_token = Some(passport_token.clone());
+        info.passport_expires = Some(passport_expires);
+        info.last_seen = chrono::Utc::now().timestamp();
 
+        vec![CircuitEvent::new(kind)]
+    }
+
+    /// Record a failed CAPTCHA attempt
+    pub async fn record_failure(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        circuit_id: &CircuitId,
+    ) -> Result<CircuitInfo> {
+        let mut info = self.get_or_create(redis, circuit_id).await?;
+        let events = self.apply_failure(&mut info);
+        self.save_with_events(redis, &info, &events).await?;
+
+        Ok(info)
+    }
+
+    /// Record a successful CAPTCHA solve
+    pub async fn record_success(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        circuit_id: &CircuitId,
+        passport_token: &PassportToken,
+        passport_expires: i64,
+    ) -> Result<CircuitInfo> {
+        let mut info = self.get_or_create(redis, circuit_id).await?;
+        let vip = self.qualifies_for_vip(redis, &info).await?;
+        let events = self.apply_success(&mut info, passport_token, passport_expires, vip);
+        self.save_with_events(redis, &info, &events).await?;
+
+        Ok(info)
+    }
+
+    /// Adjust a circuit's reputation score
+    pub async fn adjust_reputation(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        circuit_id: &CircuitId,
+        delta: i32,
+        reason: &str,
+    ) -> Result<CircuitInfo> {
+        let mut info = self.get_or_create(redis, circuit_id).await?;
+
+        let score = info.adjust_reputation(delta);
         self.save(redis, &info).await?;
 
+        tracing::debug!(
+            circuit_id = %circuit_id,
+            delta = delta,
+            reputation = score,
+            reason = %reason,
+            "Circuit reputation adjusted"
+        );
+
         Ok(info)
     }
 
@@ -157,15 +496,16 @@ impl CircuitTracker {
     pub async fn ban(
         &self,
         redis: &mut redis::aio::ConnectionManager,
-        circuit_id: &str,
+        circuit_id: &CircuitId,
         reason: &str,
     ) -> Result<()> {
         let mut info = self.get_or_create(redis, circuit_id).await?;
 
-        info.status = CircuitStatus::Banned;
+        info.status = info.status.transition(StatusEvent::Banned)?;
         info.last_seen = chrono::Utc::now().timestamp();
 
-        self.save(redis, &info).await?;
+        let event = CircuitEvent::new(CircuitEventKind::Banned).with_detail(reason);
+        self.save_with_events(redis, &info, &[event]).await?;
 
         tracing::warn!(
             circuit_id = %circuit_id,
@@ -176,11 +516,129 @@ impl CircuitTracker {
         Ok(())
     }
 
+    /// Lift a ban or soft-lock; `false` if the circuit had neither
+    ///
+    /// The circuit starts over as new, with its failure count reset.
+    pub async fn unban(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        circuit_id: &CircuitId,
+    ) -> Result<bool> {
+        let Some(mut info) = self.get(redis, circuit_id).await? else {
+            return Ok(false);
+        };
+        let Ok(status) = info.status.transition(StatusEvent::Unbanned) else {
+            return Ok(false);
+        };
+
+        info.status = status;
+        info.failed_attempts = 0;
+        info.last_seen = chrono::Utc::now().timestamp();
+
+        let event = CircuitEvent::new(CircuitEventKind::Unbanned);
+        self.save_with_events(redis, &info, &[event]).await?;
+
+        tracing::info!(circuit_id = %circuit_id, "Circuit unbanned");
+
+        Ok(true)
+    }
+
+    /// Drop a circuit's passport, returning the token it held
+    ///
+    /// The circuit has to solve a challenge again; a VIP loses its status.
+    pub async fn revoke_passport(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        circuit_id: &CircuitId,
+        reason: &str,
+    ) -> Result<Option<PassportToken>> {
+        let Some(mut info) = self.get(redis, circuit_id).await? else {
+            return Ok(None);
+        };
+
+        let token = info.passport_token.take();
+        info.passport_expires = None;
+        // Locked and banned circuits stay that way
+        if let Ok(status) = info.status.transition(StatusEvent::PassportRevoked) {
+            info.status = status;
+        }
+
+        let event = CircuitEvent::new(CircuitEventKind::PassportRevoked).with_detail(reason);
+        self.save_with_events(redis, &info, &[event]).await?;
+
+        Ok(token)
+    }
+
+    /// Record a honeypot hit: the circuit is soft-locked (VIP or not)
+    pub async fn record_honeypot_hit(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        circuit_id: &CircuitId,
+    ) -> Result<CircuitInfo> {
+        let mut info = self.get_or_create(redis, circuit_id).await?;
+        info.last_seen = chrono::Utc::now().timestamp();
+
+        let mut events = vec![CircuitEvent::new(CircuitEventKind::HoneypotHit)];
+        events.extend(self.demote(&mut info, CircuitStatus::SoftLocked, "honeypot"));
+        if let Ok(status) = info.status.transition(StatusEvent::Locked) {
+            info.status = status;
+            events.push(CircuitEvent::new(CircuitEventKind::SoftLocked).with_detail("honeypot"));
+        }
+        self.save_with_events(redis, &info, &events).await?;
+
+        tracing::warn!(circuit_id = %circuit_id, "Honeypot hit");
+
+        Ok(info)
+    }
+
+    /// Apply a mutation queued while Redis was offline
+    pub async fn apply(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        mutation: &CircuitMutation,
+    ) -> Result<()> {
+        match mutation {
+            CircuitMutation::Failure { circuit_id } => {
+                self.record_failure(redis, circuit_id).await?;
+            }
+            CircuitMutation::Success {
+                circuit_id,
+                passport_token,
+                passport_expires,
+            } => {
+                self.record_success(redis, circuit_id, passport_token, *passport_expires)
+                    .await?;
+            }
+            CircuitMutation::Ban { circuit_id, reason } => {
+                self.ban(redis, circuit_id, reason).await?;
+            }
+            CircuitMutation::HoneypotHit { circuit_id } => {
+                self.record_honeypot_hit(redis, circuit_id).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// A VIP that hit its rate limit goes back to Verified
+    async fn demote_rate_limited(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        circuit_id: &CircuitId,
+    ) -> Result<()> {
+        let Some(mut info) = self.get(redis, circuit_id).await? else {
+            return Ok(());
+        };
+        if let Some(event) = self.demote(&mut info, CircuitStatus::Verified, "rate limited") {
+            self.save_with_events(redis, &info, &[event]).await?;
+        }
+        Ok(())
+    }
+
     /// Check if circuit is allowed to make requests
     pub async fn is_allowed(
         &self,
         redis: &mut redis::aio::ConnectionManager,
-        circuit_id: &str,
+        circuit_id: &CircuitId,
     ) -> Result<(bool, Option<String>)> {
         let info = self.get(redis, circuit_id).await?;
 
@@ -197,30 +655,151 @@ impl CircuitTracker {
         }
     }
 
-    /// Get rate limit status for a circuit
-    pub async fn check_rate_limit(
+    /// Count a challenge refresh, returning refreshes in the current minute
+    pub async fn record_refresh(
         &self,
         redis: &mut redis::aio::ConnectionManager,
-        circuit_id: &str,
-        max_requests_per_minute: u32,
-    ) -> Result<(bool, u32)> {
-        let key = format!("ratelimit:{}", circuit_id);
+        circuit_id: &CircuitId,
+    ) -> Result<u32> {
+        let key = redis_keys::refresh(circuit_id);
+        let window = key.ttl().secs().expect("refresh counters have a fixed TTL");
 
-        // Increment counter
         let count: u32 = redis.incr(&key, 1).await?;
-
-        // Set expiry on first request
         if count == 1 {
-            redis.expire::<_, ()>(&key, 60).await?;
+            redis.expire::<_, ()>(&key, window as i64).await?;
         }
 
-        let allowed = count <= max_requests_per_minute;
-        let remaining = if allowed {
-            max_requests_per_minute - count
+        Ok(count)
+    }
+
+    /// Take one of a circuit's concurrent request slots
+    ///
+    /// The proxy never reports when a backend request finishes, so a slot is
+    /// held for `lease_ms` and then frees itself. Returns `false` (taking
+    /// nothing) while all `max` slots are held.
+    #[tracing::instrument(name = "circuit.request_slot", skip(self, redis))]
+    pub async fn acquire_request_slot(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        circuit_id: &CircuitId,
+        max: u32,
+        lease_ms: u64,
+    ) -> Result<bool> {
+        let acquired: bool = request_slot_script()
+            .key(redis_keys::in_flight(circuit_id))
+            .arg(chrono::Utc::now().timestamp_millis())
+            .arg(lease_ms)
+            .arg(max)
+            .arg(format!("{:016x}", rand::random::<u64>()))
+            .invoke_async(redis)
+            .await?;
+        Ok(acquired)
+    }
+
+    /// Get rate limit status for a circuit
+    ///
+    /// `elsewhere` is the circuit's recent requests on other nodes, which
+    /// count against the same budget (see `cluster::RateShare`).
+    #[tracing::instrument(name = "circuit.rate_limit", skip(self, redis))]
+    pub async fn check_rate_limit(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        circuit_id: &CircuitId,
+        max_requests_per_minute: u32,
+        elsewhere: u32,
+    ) -> Result<RateLimitStatus> {
+        let key = redis_keys::rate_limit(circuit_id);
+        let window = key
+            .ttl()
+            .secs()
+            .expect("rate limit counters have a fixed TTL");
+
+        // Increment counter and read the remaining window
+        let (count, ttl): (u32, i64) = redis::pipe()
+            .incr(&key, 1)
+            .ttl(&key)
+            .query_async(redis)
+            .await?;
+
+        // Set expiry on first request (or if a previous EXPIRE was lost)
+        let reset_secs = if count == 1 || ttl < 0 {
+            redis.expire::<_, ()>(&key, window as i64).await?;
+            window
         } else {
-            0
+            ttl as u64
         };
 
-        Ok((allowed, remaining))
+        let count = count.saturating_add(elsewhere);
+
+        // Only the first rejection per window goes into the history
+        if count == max_requests_per_minute.saturating_add(1) {
+            let event = CircuitEvent::new(CircuitEventKind::RateLimited)
+                .with_detail(format!("{} requests/min", max_requests_per_minute));
+            if let Err(e) = self.events.record(redis, circuit_id, event).await {
+                tracing::warn!(error = %e, circuit_id = %circuit_id, "Failed to record circuit event");
+            }
+            if self.vip.demote_on_rate_limit {
+                self.demote_rate_limited(redis, circuit_id).await?;
+            }
+        }
+
+        Ok(RateLimitStatus {
+            allowed: count <= max_requests_per_minute,
+            limit: max_requests_per_minute,
+            remaining: max_requests_per_minute.saturating_sub(count),
+            reset_secs,
+        })
     }
 }
+
+/// Set KEYS[1] if it still holds the expected value, along with KEYS[2]
+/// (if given)
+///
+/// ARGV: expected ("" if unset), value, TTL, then KEYS[2]'s value and TTL.
+/// Returns 1 if set.
+fn compare_and_save_script() -> redis::Script {
+    redis::Script::new(
+        r"
+        if (redis.call('GET', KEYS[1]) or '') ~= ARGV[1] then
+            return 0
+        end
+        redis.call('SET', KEYS[1], ARGV[2], 'EX', ARGV[3])
+        if KEYS[2] then
+            redis.call('SET', KEYS[2], ARGV[4], 'EX', ARGV[5])
+        end
+        return 1
+        ",
+    )
+}
+
+/// Drop slots whose lease ran out, then take one if fewer than max are held
+///
+/// ARGV: now_ms, lease_ms, max, slot ID. Returns 1 if a slot was taken.
+fn request_slot_script() -> redis::Script {
+    redis::Script::new(
+        r"
+        local now = tonumber(ARGV[1])
+        local lease = tonumber(ARGV[2])
+        redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now - lease)
+        if redis.call('ZCARD', KEYS[1]) >= tonumber(ARGV[3]) then
+            return 0
+        end
+        redis.call('ZADD', KEYS[1], now, ARGV[4])
+        redis.call('PEXPIRE', KEYS[1], lease)
+        return 1
+        ",
+    )
+}
+
+/// Outcome of a rate limit check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitStatus {
+    /// Request is within the limit
+    pub allowed: bool,
+    /// Requests permitted per window
+    pub limit: u32,
+    /// Requests left in the current window
+    pub remaining: u32,
+    /// Seconds until the window resets
+    pub reset_secs: u64,
+}
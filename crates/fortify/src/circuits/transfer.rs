@@ -0,0 +1,288 @@
+//! Circuit record export and import.
+//!
+//! Reputation takes a while to build. `GET /admin/circuits/export` streams
+//! every circuit record in Redis as JSON lines, one `ExportedCircuit` each:
+//! the decoded record (so it loads into a node with a newer record format)
+//! and the seconds it had left. `POST /admin/circuits/import` and
+//! `fortify import-circuits <file>` read the same lines back, to seed a new
+//! node or move reputation between environments.
+//!
+//! Imported records keep the lifetime they had left, capped at what this
+//! node gives a record of that status (a line without `ttl_secs` gets the
+//! full lifetime). A circuit that already has a record keeps it unless the
+//! import overwrites; VIPs join the VIP set. Lines that don't parse are
+//! counted and skipped, so one bad line doesn't sink a whole file.
+
+use anyhow::{Context, Result};
+use cerberus_common::constants::redis_keys::CIRCUIT_PREFIX;
+use cerberus_common::{CircuitInfo, CircuitStatus, redis_keys, versioned};
+use futures::Stream;
+use redis::aio::ConnectionManager;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncReadExt;
+
+use super::{CircuitTracker, EventLog};
+use crate::config::AppConfig;
+
+/// Keys asked for per SCAN (and records per exported chunk)
+const SCAN_COUNT: usize = 1000;
+
+/// Lines written to Redis per pipeline on import
+const IMPORT_BATCH: usize = 500;
+
+/// One line of an export
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedCircuit {
+    pub circuit: CircuitInfo,
+    /// Lifetime the record had left when exported
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_secs: Option<u64>,
+}
+
+/// Outcome of an import
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct ImportReport {
+    /// Records written
+    pub imported: u64,
+    /// Circuits that already had a record (kept)
+    pub existing: u64,
+    /// Lines that aren't an exported circuit
+    pub invalid: u64,
+}
+
+/// Stream every circuit record as JSON lines, a SCAN batch per chunk
+///
+/// Records that disappear mid-export or don't decode are left out.
+pub fn export(redis: ConnectionManager) -> impl Stream<Item = Result<String>> {
+    futures::stream::try_unfold((redis, Some(0u64)), |(mut redis, cursor)| async move {
+        let Some(cursor) = cursor else {
+            return anyhow::Ok(None);
+        };
+        let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("MATCH")
+            .arg(format!("{}*", CIRCUIT_PREFIX))
+            .arg("COUNT")
+            .arg(SCAN_COUNT)
+            .query_async(&mut redis)
+            .await?;
+
+        let mut chunk = String::new();
+        if !keys.is_empty() {
+            let mut pipe = redis::pipe();
+            for key in &keys {
+                pipe.get(key).ttl(key);
+            }
+            let values: Vec<(Option<String>, i64)> = pipe.query_async(&mut redis).await?;
+            for (record, ttl) in values {
+                let Some(line) = record.and_then(|record| export_line(&record, ttl)) else {
+                    continue;
+                };
+                chunk.push_str(&line);
+                chunk.push('\n');
+            }
+        }
+
+        let cursor = (next != 0).then_some(next);
+        anyhow::Ok(Some((chunk, (redis, cursor))))
+    })
+}
+
+/// The export line of a stored record (none if it doesn't decode)
+fn export_line(record: &str, ttl: i64) -> Option<String> {
+    let circuit = match versioned::decode::<CircuitInfo>(record) {
+        Ok(circuit) => circuit,
+        Err(e) => {
+            tracing::debug!(error = %e, "Skipping undecodable circuit record");
+            return None;
+        }
+    };
+    let exported = ExportedCircuit {
+        circuit,
+        // -1: no expiry, -2: gone since the SCAN
+        ttl_secs: u64::try_from(ttl).ok(),
+    };
+    serde_json::to_string(&exported).ok()
+}
+
+/// Parse one import line (none for a blank line)
+fn parse_line(line: &[u8]) -> Option<Result<ExportedCircuit, serde_json::Error>> {
+    let line = line.trim_ascii();
+    (!line.is_empty()).then(|| serde_json::from_slice(line))
+}
+
+/// Writes exported lines into Redis as they arrive
+pub struct Importer<'a> {
+    tracker: &'a CircuitTracker,
+    overwrite: bool,
+    /// Tail of the input not yet ended by a newline
+    partial: Vec<u8>,
+    report: ImportReport,
+}
+
+impl<'a> Importer<'a> {
+    /// `overwrite` replaces records circuits already have
+    pub fn new(tracker: &'a CircuitTracker, overwrite: bool) -> Self {
+        Self {
+            tracker,
+            overwrite,
+            partial: Vec::new(),
+            report: ImportReport::default(),
+        }
+    }
+
+    /// Import the complete lines in `chunk`, holding back a trailing partial one
+    pub async fn feed(&mut self, redis: &mut ConnectionManager, chunk: &[u8]) -> Result<()> {
+        self.partial.extend_from_slice(chunk);
+        let Some(end) = self.partial.iter().rposition(|&b| b == b'\n') else {
+            return Ok(());
+        };
+        let rest = self.partial.split_off(end + 1);
+        let complete = std::mem::replace(&mut self.partial, rest);
+        self.import(redis, &complete).await
+    }
+
+    /// Import whatever is left and report
+    pub async fn finish(mut self, redis: &mut ConnectionManager) -> Result<ImportReport> {
+        let rest = std::mem::take(&mut self.partial);
+        self.import(redis, &rest).await?;
+        Ok(self.report)
+    }
+
+    async fn import(&mut self, redis: &mut ConnectionManager, lines: &[u8]) -> Result<()> {
+        let mut circuits = Vec::new();
+        for line in lines.split(|&b| b == b'\n') {
+            match parse_line(line) {
+                None => {}
+                Some(Ok(exported)) => circuits.push(exported),
+                Some(Err(e)) => {
+                    tracing::debug!(error = %e, "Skipping invalid circuit import line");
+                    self.report.invalid += 1;
+                }
+            }
+        }
+        for batch in circuits.chunks(IMPORT_BATCH) {
+            self.write(redis, batch).await?;
+        }
+        Ok(())
+    }
+
+    async fn write(
+        &mut self,
+        redis: &mut ConnectionManager,
+        batch: &[ExportedCircuit],
+    ) -> Result<()> {
+        let mut pipe = redis::pipe();
+        for exported in batch {
+            let entry = self.tracker.entry(&exported.circuit)?;
+            let ttl = exported
+                .ttl_secs
+                .map_or(entry.ttl, |ttl| ttl.min(entry.ttl))
+                .max(1);
+            let set = pipe
+                .cmd("SET")
+                .arg(&entry.key)
+                .arg(&entry.value)
+                .arg("EX")
+                .arg(ttl);
+            if !self.overwrite {
+                set.arg("NX");
+            }
+        }
+        let written: Vec<Option<String>> = pipe.query_async(redis).await?;
+
+        let now = chrono::Utc::now().timestamp();
+        let mut vips = redis::pipe();
+        let mut imported = 0;
+        for (exported, written) in batch.iter().zip(written) {
+            if written.is_none() {
+                self.report.existing += 1;
+                continue;
+            }
+            imported += 1;
+            let circuit_id = &exported.circuit.circuit_id;
+            if exported.circuit.status == CircuitStatus::Vip {
+                vips.zadd(redis_keys::vips(), circuit_id, now).ignore();
+            } else {
+                vips.zrem(redis_keys::vips(), circuit_id).ignore();
+            }
+        }
+        if imported > 0 {
+            vips.query_async::<()>(redis).await?;
+        }
+        self.report.imported += imported;
+        Ok(())
+    }
+}
+
+/// `fortify import-circuits <file>`: import an export file
+pub async fn import_file(config: &AppConfig, path: &str, overwrite: bool) -> Result<()> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("Failed to open {}", path))?;
+
+    let client = redis::Client::open(config.redis_url.as_str())?;
+    let mut redis = ConnectionManager::new(client)
+        .await
+        .context("Failed to connect to Redis")?;
+    // Only the record lifetimes are used; imports add no history
+    let tracker = CircuitTracker::new(
+        cerberus_common::constants::CIRCUIT_TTL_SECS.secs(),
+        config.rate_limit.max_failed_attempts,
+        config.rate_limit.soft_lock_duration_secs.secs(),
+        config.rate_limit.ban_duration_secs.secs(),
+        EventLog::new(0, 0),
+        config.vip.clone(),
+    );
+
+    let mut importer = Importer::new(&tracker, overwrite);
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let read = file
+            .read(&mut buf)
+            .await
+            .with_context(|| format!("Failed to read {}", path))?;
+        if read == 0 {
+            break;
+        }
+        importer.feed(&mut redis, &buf[..read]).await?;
+    }
+    let report = importer.finish(&mut redis).await?;
+
+    println!(
+        "{}: imported {} circuits, {} kept their existing record, {} invalid lines",
+        path, report.imported, report.existing, report.invalid
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_line_round_trips() {
+        let mut info = CircuitInfo::new("fc00::1".parse().unwrap());
+        info.status = CircuitStatus::Vip;
+        info.reputation = 42;
+        let record = versioned::encode(&info).unwrap();
+
+        let line = export_line(&record, 600).unwrap();
+        let exported = parse_line(line.as_bytes()).unwrap().unwrap();
+        assert_eq!(exported.circuit.circuit_id, info.circuit_id);
+        assert_eq!(exported.circuit.reputation, 42);
+        assert_eq!(exported.ttl_secs, Some(600));
+
+        // No expiry isn't carried over
+        let line = export_line(&record, -1).unwrap();
+        assert!(!line.contains("ttl_secs"));
+        assert_eq!(export_line("not a record", 600), None);
+    }
+
+    #[test]
+    fn test_parse_line() {
+        assert!(parse_line(b"").is_none());
+        assert!(parse_line(b"  \r").is_none());
+        assert!(parse_line(b"{\"circuit\": 1}").unwrap().is_err());
+    }
+}
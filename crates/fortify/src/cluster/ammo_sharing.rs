@@ -0,0 +1,551 @@
+//! Ammo sharing: pre-generated CAPTCHAs moved from full pools to starving ones.
+//!
+//! Gossip packets already carry every node's Ammo Box fill. A node whose
+//! pool is under `request_below_pct` asks the fullest healthy peer (at or
+//! over `donor_min_fill_pct`) for a batch, over a TCP connection to the
+//! peer's `bind_addr`. Batches are megabytes, far too large for gossip
+//! datagrams.
+//!
+//! Like gossip, the sharing port belongs inside the WireGuard tunnel, which
+//! encrypts the transfer. On top of that, requests are signed with the
+//! requester's passport key, and batches with the donor's: a donor only
+//! gives to nodes whose key it trusts (pinned `peer_keys` or the trust
+//! registry), and a requester only takes batches its donor signed for it.
+//! Requests are refused outside `max_clock_skew_secs` or when not newer
+//! than the last one from that node. A donor never lets its own pool drop
+//! under `donor_keep_pct`.
+
+use anyhow::{Context, Result, bail};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+
+use super::{GossipPacket, PassportService};
+use crate::captcha::AmmoBox;
+use crate::metrics;
+use crate::state::AppState;
+
+/// Largest request frame (requests are ~200 bytes of JSON)
+const MAX_REQUEST_SIZE: usize = 4096;
+
+/// Largest reply frame (a batch of `batch_size` CAPTCHAs)
+const MAX_REPLY_SIZE: usize = 64 * 1024 * 1024;
+
+/// Ammo sharing settings (`[ammo_sharing]` in fortify.toml)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AmmoSharingConfig {
+    pub enabled: bool,
+    /// Address peers fetch batches from (inside the WireGuard tunnel)
+    pub bind_addr: String,
+    /// Sharing address of each peer (node ID -> IP:port)
+    pub peers: HashMap<String, String>,
+    /// How often this node checks whether it needs a batch
+    pub interval_secs: u64,
+    /// Ask peers for a batch while our pool is under this fill (percent)
+    pub request_below_pct: u8,
+    /// Only peers at least this full (percent) are asked, and give
+    pub donor_min_fill_pct: u8,
+    /// A donor's pool never drops under this fill (percent) by giving
+    pub donor_keep_pct: u8,
+    /// CAPTCHAs asked for (and given) at once, at most
+    pub batch_size: usize,
+    /// Requests and batches further than this from our clock are refused
+    pub max_clock_skew_secs: u64,
+    /// Connect, request and transfer timeout
+    pub timeout_secs: u64,
+}
+
+impl Default for AmmoSharingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind_addr: "0.0.0.0:9001".to_string(),
+            peers: HashMap::new(),
+            interval_secs: 10,
+            request_below_pct: 25,
+            donor_min_fill_pct: 80,
+            donor_keep_pct: 60,
+            batch_size: 1000,
+            max_clock_skew_secs: 60,
+            timeout_secs: 10,
+        }
+    }
+}
+
+/// A starving node asking a peer for CAPTCHAs
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct AmmoRequest {
+    node_id: String,
+    donor: String,
+    count: usize,
+    /// Unix seconds
+    timestamp: u64,
+    /// Over `signed_bytes`, base64url
+    signature: String,
+}
+
+impl AmmoRequest {
+    fn signed_bytes(&self) -> Vec<u8> {
+        format!(
+            "cerberus-ammo-request:{}:{}:{}:{}",
+            self.node_id, self.donor, self.count, self.timestamp
+        )
+        .into_bytes()
+    }
+}
+
+/// CAPTCHAs given to a requester
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct AmmoGrant {
+    donor: String,
+    recipient: String,
+    /// Unix seconds
+    timestamp: u64,
+    /// Encoded batch file (`AmmoBox::export`)
+    batch: Vec<u8>,
+    /// Over `signed_bytes`
+    signature: Vec<u8>,
+}
+
+impl AmmoGrant {
+    fn signed_bytes(&self) -> Vec<u8> {
+        let mut bytes = format!(
+            "cerberus-ammo-grant:{}:{}:{}:",
+            self.donor, self.recipient, self.timestamp
+        )
+        .into_bytes();
+        bytes.extend_from_slice(&self.batch);
+        bytes
+    }
+}
+
+/// A donor's answer
+#[derive(Clone, Debug, Serialize, Deserialize)]
+enum AmmoReply {
+    Grant(AmmoGrant),
+    /// Nothing to spare, or a request we won't serve (and why)
+    Refused(String),
+}
+
+/// Both ends of ammo sharing for one node
+pub struct AmmoSharing {
+    config: AmmoSharingConfig,
+    node_id: String,
+    ammo: Arc<AmmoBox>,
+    passports: Arc<PassportService>,
+    /// Timestamp of the last request served per node (replay protection)
+    last_requests: Mutex<HashMap<String, u64>>,
+}
+
+impl AmmoSharing {
+    pub fn new(
+        config: AmmoSharingConfig,
+        node_id: String,
+        ammo: Arc<AmmoBox>,
+        passports: Arc<PassportService>,
+    ) -> Self {
+        Self {
+            config,
+            node_id,
+            ammo,
+            passports,
+            last_requests: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(self.config.timeout_secs)
+    }
+
+    fn within_skew(&self, timestamp: u64, now: u64) -> bool {
+        now.abs_diff(timestamp) <= self.config.max_clock_skew_secs
+    }
+
+    /// The peer to ask for a batch, if our pool is low enough to ask:
+    /// the fullest one over `donor_min_fill_pct` with a sharing address
+    fn pick_donor<'a>(&self, own_fill: u8, peers: &'a [GossipPacket]) -> Option<&'a GossipPacket> {
+        if own_fill >= self.config.request_below_pct {
+            return None;
+        }
+        peers
+            .iter()
+            .filter(|peer| {
+                !peer.draining
+                    && peer.ammo_fill >= self.config.donor_min_fill_pct
+                    && self.config.peers.contains_key(&peer.node_id)
+            })
+            .max_by_key(|peer| peer.ammo_fill)
+    }
+
+    /// Decide on a request (as the donor)
+    async fn answer(&self, request: &AmmoRequest, now: u64) -> AmmoReply {
+        match self.grant(request, now).await {
+            Ok(grant) => grant,
+            Err(e) => AmmoReply::Refused(e.to_string()),
+        }
+    }
+
+    async fn grant(&self, request: &AmmoRequest, now: u64) -> Result<AmmoReply> {
+        if request.donor != self.node_id || request.node_id == self.node_id {
+            bail!("Request not for this node");
+        }
+        if !self.within_skew(request.timestamp, now) {
+            bail!("Request outside the clock skew window");
+        }
+        let signature = URL_SAFE_NO_PAD
+            .decode(&request.signature)
+            .context("Invalid signature encoding")?;
+        self.passports
+            .verify_peer(&request.node_id, &request.signed_bytes(), &signature)
+            .await?;
+        {
+            let mut last_requests = self.last_requests.lock().unwrap_or_else(|e| e.into_inner());
+            let last = last_requests.entry(request.node_id.clone()).or_default();
+            if request.timestamp <= *last {
+                bail!("Replayed request");
+            }
+            *last = request.timestamp;
+        }
+
+        if self.ammo.fill_percent() < self.config.donor_min_fill_pct {
+            return Ok(AmmoReply::Refused("No surplus".to_string()));
+        }
+        let keep = self.ammo.capacity() * usize::from(self.config.donor_keep_pct) / 100;
+        let count = request
+            .count
+            .min(self.config.batch_size)
+            .min(self.ammo.len().saturating_sub(keep));
+        if count == 0 {
+            return Ok(AmmoReply::Refused("No surplus".to_string()));
+        }
+
+        let (count, batch) = self.ammo.export(count)?;
+        let mut grant = AmmoGrant {
+            donor: self.node_id.clone(),
+            recipient: request.node_id.clone(),
+            timestamp: now,
+            batch,
+            signature: Vec::new(),
+        };
+        grant.signature = self.passports.sign(&grant.signed_bytes())?;
+        metrics::AMMO_SHARED
+            .with_label_values(&["sent"])
+            .inc_by(count as u64);
+        tracing::info!(node = %request.node_id, count, "🎯 Shared CAPTCHAs with peer");
+        Ok(AmmoReply::Grant(grant))
+    }
+
+    /// Take in a donor's reply (as the requester); returns the CAPTCHAs added
+    async fn accept(&self, donor: &str, reply: AmmoReply, now: u64) -> Result<usize> {
+        let grant = match reply {
+            AmmoReply::Grant(grant) => grant,
+            AmmoReply::Refused(reason) => bail!("Refused: {}", reason),
+        };
+        if grant.donor != donor || grant.recipient != self.node_id {
+            bail!("Batch not from {} for this node", donor);
+        }
+        if !self.within_skew(grant.timestamp, now) {
+            bail!("Batch outside the clock skew window");
+        }
+        self.passports
+            .verify_peer(donor, &grant.signed_bytes(), &grant.signature)
+            .await?;
+
+        let added = self.ammo.import(&grant.batch)?;
+        metrics::AMMO_SHARED
+            .with_label_values(&["received"])
+            .inc_by(added as u64);
+        Ok(added)
+    }
+
+    /// Ask `donor` for a batch over `stream`
+    async fn exchange<S>(&self, stream: &mut S, donor: &str) -> Result<usize>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let room = self.ammo.capacity().saturating_sub(self.ammo.len());
+        let mut request = AmmoRequest {
+            node_id: self.node_id.clone(),
+            donor: donor.to_string(),
+            count: room.min(self.config.batch_size),
+            timestamp: unix_now(),
+            signature: String::new(),
+        };
+        request.signature = URL_SAFE_NO_PAD.encode(self.passports.sign(&request.signed_bytes())?);
+
+        write_frame(stream, &serde_json::to_vec(&request)?).await?;
+        let data = read_frame(stream, MAX_REPLY_SIZE).await?;
+        let reply: AmmoReply = bincode::deserialize(&data).context("Malformed ammo reply")?;
+        self.accept(donor, reply, unix_now()).await
+    }
+
+    /// Serve one requester connection
+    async fn serve_connection<S>(&self, stream: &mut S) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let data = read_frame(stream, MAX_REQUEST_SIZE).await?;
+        let request: AmmoRequest =
+            serde_json::from_slice(&data).context("Malformed ammo request")?;
+        let reply = self.answer(&request, unix_now()).await;
+        if let AmmoReply::Refused(ref reason) = reply {
+            tracing::debug!(node = %request.node_id, reason = %reason, "Ammo request refused");
+        }
+        write_frame(stream, &bincode::serialize(&reply)?).await
+    }
+
+    /// Accept batch requests from peers until shutdown
+    pub async fn run_server(self: Arc<Self>, mut shutdown: broadcast::Receiver<()>) -> Result<()> {
+        let listener = TcpListener::bind(&self.config.bind_addr)
+            .await
+            .context("Failed to bind ammo sharing listener")?;
+        tracing::info!(addr = %self.config.bind_addr, "🎯 Ammo sharing listening");
+
+        loop {
+            tokio::select! {
+                result = listener.accept() => {
+                    let (mut stream, addr) = match result {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            tracing::warn!(error = %e, "Ammo sharing accept error");
+                            continue;
+                        }
+                    };
+                    let sharing = self.clone();
+                    tokio::spawn(async move {
+                        let served = tokio::time::timeout(
+                            sharing.timeout(),
+                            sharing.serve_connection(&mut stream),
+                        )
+                        .await;
+                        match served {
+                            Ok(Ok(())) => {}
+                            Ok(Err(e)) => {
+                                tracing::warn!(addr = %addr, error = %e, "Ammo sharing request failed");
+                            }
+                            Err(_) => tracing::warn!(addr = %addr, "Ammo sharing request timed out"),
+                        }
+                    });
+                }
+                _ = shutdown.recv() => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Ask the best donor for a batch if our pool is low; returns the
+    /// CAPTCHAs added
+    async fn replenish(&self, peers: &[GossipPacket]) -> Result<usize> {
+        let Some(donor) = self.pick_donor(self.ammo.fill_percent(), peers) else {
+            return Ok(0);
+        };
+        let addr = &self.config.peers[&donor.node_id];
+        let added = tokio::time::timeout(self.timeout(), async {
+            let mut stream = TcpStream::connect(addr)
+                .await
+                .with_context(|| format!("Failed to connect to {}", addr))?;
+            self.exchange(&mut stream, &donor.node_id).await
+        })
+        .await
+        .context("Timed out")??;
+
+        tracing::info!(
+            node = %donor.node_id,
+            added,
+            fill_percent = self.ammo.fill_percent(),
+            "🎯 Took CAPTCHAs from peer"
+        );
+        Ok(added)
+    }
+}
+
+/// Share ammo with peers: serve their requests, and ask them for batches
+/// while our pool is low
+pub async fn ammo_sharing_worker(state: AppState, shutdown: broadcast::Receiver<()>) {
+    let config = state.config.ammo_sharing.clone();
+    let Some(gossip) = state.gossip.clone() else {
+        return;
+    };
+    if !config.enabled {
+        return;
+    }
+    let interval = Duration::from_secs(config.interval_secs);
+    let sharing = Arc::new(AmmoSharing::new(
+        config,
+        state.node_id.clone(),
+        state.ammo_box.clone(),
+        state.passports.clone(),
+    ));
+
+    let server = sharing.clone().run_server(shutdown.resubscribe());
+    let mut shutdown = shutdown;
+    let requester = async {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {
+                    let peers = gossip.get_healthy_peers().await;
+                    if let Err(e) = sharing.replenish(&peers).await {
+                        tracing::warn!(error = %e, "Failed to take CAPTCHAs from peer");
+                    }
+                }
+                _ = shutdown.recv() => break,
+            }
+        }
+    };
+
+    let (served, ()) = tokio::join!(server, requester);
+    if let Err(e) = served {
+        tracing::error!(error = %e, "Ammo sharing server failed");
+    }
+}
+
+/// Length-prefixed (big-endian u32) frame
+async fn write_frame<S: AsyncWrite + Unpin>(stream: &mut S, data: &[u8]) -> Result<()> {
+    let len = u32::try_from(data.len()).context("Frame too large")?;
+    stream.write_u32(len).await?;
+    stream.write_all(data).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn read_frame<S: AsyncRead + Unpin>(stream: &mut S, max: usize) -> Result<Vec<u8>> {
+    let len = stream.read_u32().await? as usize;
+    if len > max {
+        bail!("Frame too large ({} bytes)", len);
+    }
+    let mut data = vec![0; len];
+    stream.read_exact(&mut data).await?;
+    Ok(data)
+}
+
+fn unix_now() -> u64 {
+    chrono::Utc::now().timestamp() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::captcha::AmmoBoxConfig;
+    use crate::cluster::PassportConfig;
+    use cerberus_common::CaptchaDifficulty;
+
+    fn node(node_id: &str) -> AmmoSharing {
+        let passports = PassportService::new(PassportConfig {
+            node_id: node_id.to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let ammo = AmmoBox::new(AmmoBoxConfig {
+            ram_capacity: 100,
+            ..Default::default()
+        });
+        AmmoSharing::new(
+            AmmoSharingConfig {
+                peers: HashMap::from([
+                    ("node-1".to_string(), "10.100.0.1:9001".to_string()),
+                    ("node-2".to_string(), "10.100.0.2:9001".to_string()),
+                ]),
+                ..Default::default()
+            },
+            node_id.to_string(),
+            Arc::new(ammo),
+            Arc::new(passports),
+        )
+    }
+
+    async fn trust(a: &AmmoSharing, b: &AmmoSharing) {
+        let key = b.passports.public_key_b64().unwrap();
+        a.passports.add_peer_key(&b.node_id, &key).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_starving_node_takes_surplus() {
+        let (starving, donor) = (node("node-1"), node("node-2"));
+        trust(&starving, &donor).await;
+        trust(&donor, &starving).await;
+        donor
+            .ammo
+            .push_batch(donor.ammo.generate_batch(90, CaptchaDifficulty::Medium));
+        starving
+            .ammo
+            .push_batch(starving.ammo.generate_batch(10, CaptchaDifficulty::Medium));
+
+        let mut peer = GossipPacket::new("node-2".to_string(), 10, true, 0, 90, 5);
+        let peers = [peer.clone()];
+        assert_eq!(
+            starving.pick_donor(10, &peers).map(|p| p.node_id.as_str()),
+            Some("node-2")
+        );
+        assert!(starving.pick_donor(30, &peers).is_none());
+        peer.ammo_fill = 50;
+        assert!(starving.pick_donor(10, &[peer]).is_none());
+
+        let (mut client, mut server) = tokio::io::duplex(1024 * 1024);
+        let (added, served) = tokio::join!(
+            starving.exchange(&mut client, "node-2"),
+            donor.serve_connection(&mut server)
+        );
+        served.unwrap();
+        // The donor keeps 60%
+        assert_eq!(added.unwrap(), 30);
+        assert_eq!((starving.ammo.len(), donor.ammo.len()), (40, 60));
+    }
+
+    #[tokio::test]
+    async fn test_untrusted_and_replayed_requests_refused() {
+        let (requester, donor, stranger) = (node("node-1"), node("node-2"), node("node-3"));
+        trust(&donor, &requester).await;
+        donor
+            .ammo
+            .push_batch(donor.ammo.generate_batch(100, CaptchaDifficulty::Medium));
+
+        let now = unix_now();
+        let signed = |from: &AmmoSharing, timestamp| {
+            let mut request = AmmoRequest {
+                node_id: "node-1".to_string(),
+                donor: "node-2".to_string(),
+                count: 10,
+                timestamp,
+                signature: String::new(),
+            };
+            request.signature =
+                URL_SAFE_NO_PAD.encode(from.passports.sign(&request.signed_bytes()).unwrap());
+            request
+        };
+
+        // Signed by a key that isn't node-1's
+        let forged = signed(&stranger, now);
+        assert!(matches!(
+            donor.answer(&forged, now).await,
+            AmmoReply::Refused(_)
+        ));
+
+        let request = signed(&requester, now);
+        assert!(matches!(
+            donor.answer(&request, now).await,
+            AmmoReply::Grant(_)
+        ));
+        assert!(matches!(
+            donor.answer(&request, now).await,
+            AmmoReply::Refused(_)
+        ));
+
+        // A grant for someone else isn't taken
+        let AmmoReply::Grant(grant) = donor.answer(&signed(&requester, now + 1), now).await else {
+            panic!("expected a grant");
+        };
+        trust(&stranger, &donor).await;
+        assert!(
+            stranger
+                .accept("node-2", AmmoReply::Grant(grant), now)
+                .await
+                .is_err()
+        );
+    }
+}
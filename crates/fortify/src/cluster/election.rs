@@ -0,0 +1,432 @@
+//! Cluster leader election (a lease in Redis).
+//!
+//! Gossip tells a node whether it is cut off from its peers, but not who
+//! decides for the cluster. Candidate nodes compete for a lease in Redis
+//! (`cerberus:leader`, holding the leader's node ID); the holder renews it
+//! every `renew_interval_secs`, and if it stops (crash, partition) the lease
+//! runs out after `lease_secs` and another candidate takes over. Each new
+//! leader bumps a term counter, so logs and events can tell leaders apart.
+//!
+//! The leader owns cluster-wide decisions: automatic threat-level
+//! escalation and de-escalation, and automatic bans. Every node, the leader
+//! included, proposes these through a Redis list; each round the leader
+//! drains it and arbitrates (escalations proposed in the same round don't
+//! stack and win over de-escalations, and a circuit is banned once however
+//! many nodes asked). All nodes follow the
+//! threat level in Redis. A node that finds itself isolated steps down and
+//! stops campaigning until it rejoins.
+
+use anyhow::Result;
+use cerberus_common::{CerberusEvent, CircuitId, EventPublisher, ThreatLevel, redis_keys};
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use crate::state::AppState;
+
+/// Most proposals kept waiting for a leader (oldest dropped first)
+const MAX_PROPOSALS: isize = 1000;
+
+/// Leader election settings (`[election]` in fortify.toml)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ElectionConfig {
+    /// Campaign for leadership; other nodes only follow and propose
+    pub candidate: bool,
+    /// How long a lease lasts without renewal
+    pub lease_secs: u64,
+    /// How often the leader renews (and candidates try to take over)
+    pub renew_interval_secs: u64,
+}
+
+impl Default for ElectionConfig {
+    fn default() -> Self {
+        Self {
+            candidate: true,
+            lease_secs: 15,
+            renew_interval_secs: 5,
+        }
+    }
+}
+
+/// A cluster-wide action for the leader to decide on
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Proposal {
+    /// Raise the threat level by `by`
+    Escalate { by: u8, reason: String },
+    /// Lower the threat level by `by`
+    Deescalate { by: u8, reason: String },
+    /// Ban a circuit
+    Ban {
+        circuit_id: CircuitId,
+        reason: String,
+    },
+}
+
+/// What the leader does with one round of proposals
+#[derive(Debug, Default, PartialEq)]
+pub struct Decision {
+    /// The largest escalation proposed (escalations don't stack)
+    pub raise_by: u8,
+    /// The largest de-escalation proposed, unless escalating
+    pub lower_by: u8,
+    /// Circuits to ban, each once, with the first reason given
+    pub bans: Vec<(CircuitId, String)>,
+}
+
+/// Merge a round of proposals into one decision
+pub fn arbitrate(proposals: Vec<Proposal>) -> Decision {
+    let mut decision = Decision::default();
+    for proposal in proposals {
+        match proposal {
+            Proposal::Escalate { by, .. } => decision.raise_by = decision.raise_by.max(by),
+            Proposal::Deescalate { by, .. } => decision.lower_by = decision.lower_by.max(by),
+            Proposal::Ban { circuit_id, reason } => {
+                if !decision.bans.iter().any(|(id, _)| *id == circuit_id) {
+                    decision.bans.push((circuit_id, reason));
+                }
+            }
+        }
+    }
+    if decision.raise_by > 0 {
+        decision.lower_by = 0;
+    }
+    decision
+}
+
+/// This node's view of the leader lease
+pub struct LeaderElection {
+    config: ElectionConfig,
+    node_id: String,
+    /// Unix milliseconds until which our lease surely holds (0 = follower)
+    lease_until_ms: AtomicI64,
+    /// Term of our current or last leadership
+    term: AtomicU64,
+}
+
+impl LeaderElection {
+    pub fn new(config: ElectionConfig, node_id: String) -> Self {
+        Self {
+            config,
+            node_id,
+            lease_until_ms: AtomicI64::new(0),
+            term: AtomicU64::new(0),
+        }
+    }
+
+    /// Do we hold the lease?
+    ///
+    /// Turns false by itself once the lease would have run out without a
+    /// renewal, even before Redis is reachable again to say so.
+    pub fn is_leader(&self) -> bool {
+        chrono::Utc::now().timestamp_millis() < self.lease_until_ms.load(Ordering::Acquire)
+    }
+
+    pub fn term(&self) -> u64 {
+        self.term.load(Ordering::Acquire)
+    }
+
+    /// Take the lease if it is free, or renew it if it is ours
+    ///
+    /// Returns whether we hold it.
+    pub async fn campaign(&self, redis: &mut ConnectionManager) -> Result<bool> {
+        let lease_ms = self.config.lease_secs * 1000;
+        // Timed from before the round trip, so our estimate ends first
+        let started = chrono::Utc::now().timestamp_millis();
+        let term: u64 = claim_script()
+            .key(redis_keys::leader())
+            .key(redis_keys::leader_term())
+            .arg(&self.node_id)
+            .arg(lease_ms)
+            .invoke_async(redis)
+            .await?;
+
+        if term == 0 {
+            self.lease_until_ms.store(0, Ordering::Release);
+            return Ok(false);
+        }
+        self.term.store(term, Ordering::Release);
+        self.lease_until_ms
+            .store(started + lease_ms as i64, Ordering::Release);
+        Ok(true)
+    }
+
+    /// Give up the lease (if still ours) so another candidate can take over
+    /// without waiting for it to run out
+    pub async fn step_down(&self, redis: &mut ConnectionManager) -> Result<()> {
+        self.lease_until_ms.store(0, Ordering::Release);
+        let _: i64 = release_script()
+            .key(redis_keys::leader())
+            .arg(&self.node_id)
+            .invoke_async(redis)
+            .await?;
+        Ok(())
+    }
+
+    /// Forget the lease without telling Redis (it is unreachable)
+    fn lose(&self) {
+        self.lease_until_ms.store(0, Ordering::Release);
+    }
+
+    /// Queue a cluster-wide action for the leader
+    pub async fn propose(&self, redis: &mut ConnectionManager, proposal: &Proposal) -> Result<()> {
+        let key = redis_keys::proposals();
+        let _: () = redis::pipe()
+            .rpush(&key, serde_json::to_string(proposal)?)
+            .ltrim(&key, -MAX_PROPOSALS, -1)
+            .query_async(redis)
+            .await?;
+        Ok(())
+    }
+
+    /// Take every waiting proposal (malformed ones are dropped)
+    async fn drain(&self, redis: &mut ConnectionManager) -> Result<Vec<Proposal>> {
+        let items: Vec<String> = drain_script()
+            .key(redis_keys::proposals())
+            .invoke_async(redis)
+            .await?;
+        Ok(items
+            .iter()
+            .filter_map(|item| match serde_json::from_str(item) {
+                Ok(proposal) => Some(proposal),
+                Err(e) => {
+                    tracing::warn!(error = %e, "Dropping malformed proposal");
+                    None
+                }
+            })
+            .collect())
+    }
+}
+
+/// Campaign, lead and follow until shutdown
+pub async fn election_worker(state: AppState, mut shutdown: broadcast::Receiver<()>) {
+    let Some(election) = state.election.clone() else {
+        return;
+    };
+    let interval = Duration::from_secs(state.config.election.renew_interval_secs);
+
+    loop {
+        if let Err(e) = round(&state, &election).await {
+            tracing::warn!(error = %e, "Leader election round failed");
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = shutdown.recv() => {
+                if election.is_leader()
+                    && let Some(mut redis) = state.redis()
+                    && let Err(e) = election.step_down(&mut redis).await
+                {
+                    tracing::warn!(error = %e, "Failed to release leader lease");
+                }
+                tracing::info!("Leader election shutting down");
+                break;
+            }
+        }
+    }
+}
+
+async fn round(state: &AppState, election: &LeaderElection) -> Result<()> {
+    let was_leader = election.is_leader();
+    let stepped_down = || {
+        state.events.publish(CerberusEvent::LeaderSteppedDown {
+            node_id: state.node_id.clone(),
+        })
+    };
+
+    let Some(mut redis) = state.redis() else {
+        if was_leader {
+            tracing::warn!("Redis offline, stepping down as cluster leader");
+            election.lose();
+            stepped_down();
+        }
+        return Ok(());
+    };
+
+    let isolated = match state.gossip {
+        Some(ref gossip) => gossip.is_isolated().await,
+        None => false,
+    };
+    if isolated || !state.config.election.candidate {
+        if was_leader {
+            tracing::warn!(isolated, "Stepping down as cluster leader");
+            election.step_down(&mut redis).await?;
+            stepped_down();
+        }
+    } else {
+        let leader = election.campaign(&mut redis).await?;
+        if leader && !was_leader {
+            tracing::info!(term = election.term(), "👑 Elected cluster leader");
+            state.events.publish(CerberusEvent::LeaderElected {
+                node_id: state.node_id.clone(),
+                term: election.term(),
+            });
+        } else if !leader && was_leader {
+            tracing::warn!("Lost the cluster leader lease");
+            stepped_down();
+        }
+        if leader {
+            decide(state, election, &mut redis).await?;
+        }
+    }
+
+    follow_threat_level(state, &mut redis).await
+}
+
+/// Apply one round of proposals (leader only)
+async fn decide(
+    state: &AppState,
+    election: &LeaderElection,
+    redis: &mut ConnectionManager,
+) -> Result<()> {
+    let decision = arbitrate(election.drain(redis).await?);
+
+    if decision.raise_by > 0 {
+        let current = state.get_threat_level().await;
+        let raised = current.escalate(decision.raise_by);
+        if raised != current {
+            tracing::warn!(
+                from = current.value(),
+                to = raised.value(),
+                "Leader escalating cluster threat level"
+            );
+            state.set_threat_level(raised).await?;
+        }
+    } else if decision.lower_by > 0 {
+        let current = state.get_threat_level().await;
+        let lowered = current.deescalate(decision.lower_by);
+        if lowered != current {
+            tracing::warn!(
+                from = current.value(),
+                to = lowered.value(),
+                "Leader de-escalating cluster threat level"
+            );
+            state.set_threat_level(lowered).await?;
+        }
+    }
+
+    for (circuit_id, reason) in &decision.bans {
+        if let Err(e) = state.circuit_tracker.ban(redis, circuit_id, reason).await {
+            tracing::error!(error = %e, circuit_id = %circuit_id, "Failed to apply proposed ban");
+        }
+    }
+    Ok(())
+}
+
+/// Adopt the cluster threat level from Redis
+async fn follow_threat_level(state: &AppState, redis: &mut ConnectionManager) -> Result<()> {
+    let level: Option<u8> = redis.get(redis_keys::threat_level()).await?;
+    if let Some(level) = level {
+        state.adopt_threat_level(ThreatLevel::new(level)).await;
+    }
+    Ok(())
+}
+
+/// Renew the lease if it is ours, take it if free
+///
+/// KEYS: lease, term counter. ARGV: node ID, lease in milliseconds. Returns
+/// the leader's term, or 0 if another node holds the lease.
+fn claim_script() -> redis::Script {
+    redis::Script::new(
+        r"
+        local holder = redis.call('GET', KEYS[1])
+        if holder == ARGV[1] then
+            redis.call('PEXPIRE', KEYS[1], ARGV[2])
+            return tonumber(redis.call('GET', KEYS[2]) or '1')
+        elseif not holder then
+            redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+            return redis.call('INCR', KEYS[2])
+        end
+        return 0
+        ",
+    )
+}
+
+/// Delete the lease if `ARGV[1]` holds it
+fn release_script() -> redis::Script {
+    redis::Script::new(
+        r"
+        if redis.call('GET', KEYS[1]) == ARGV[1] then
+            return redis.call('DEL', KEYS[1])
+        end
+        return 0
+        ",
+    )
+}
+
+/// Pop every item of a list at once
+fn drain_script() -> redis::Script {
+    redis::Script::new(
+        r"
+        local items = redis.call('LRANGE', KEYS[1], 0, -1)
+        redis.call('DEL', KEYS[1])
+        return items
+        ",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_arbitrate() {
+        let circuit_id: CircuitId = "fc00::1".parse().unwrap();
+        let ban = |reason: &str| Proposal::Ban {
+            circuit_id: circuit_id.clone(),
+            reason: reason.to_string(),
+        };
+        let escalate = |by| Proposal::Escalate {
+            by,
+            reason: "request-surge".to_string(),
+        };
+
+        let decision = arbitrate(vec![
+            escalate(2),
+            ban("rule: a"),
+            escalate(1),
+            ban("rule: b"),
+        ]);
+        assert_eq!(decision.raise_by, 2);
+        assert_eq!(decision.bans, vec![(circuit_id, "rule: a".to_string())]);
+        assert_eq!(arbitrate(vec![]), Decision::default());
+
+        // Escalation wins over de-escalation in the same round
+        let deescalate = |by| Proposal::Deescalate {
+            by,
+            reason: "abandonment".to_string(),
+        };
+        assert_eq!(arbitrate(vec![deescalate(1), deescalate(2)]).lower_by, 2);
+        let decision = arbitrate(vec![deescalate(1), escalate(1)]);
+        assert_eq!((decision.raise_by, decision.lower_by), (1, 0));
+
+        // Wire format
+        let json = serde_json::to_string(&escalate(3)).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"escalate","by":3,"reason":"request-surge"}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<Proposal>(&json).unwrap(),
+            escalate(3)
+        );
+    }
+
+    #[test]
+    fn test_leadership_is_local_until_lease_runs_out() {
+        let election = LeaderElection::new(ElectionConfig::default(), "node-1".to_string());
+        assert!(!election.is_leader());
+
+        let now = chrono::Utc::now().timestamp_millis();
+        election
+            .lease_until_ms
+            .store(now + 10_000, Ordering::Release);
+        assert!(election.is_leader());
+
+        election.lease_until_ms.store(now - 1, Ordering::Release);
+        assert!(!election.is_leader());
+    }
+}
@@ -0,0 +1,159 @@
+//! Passport federation: passports minted on one node honoured on the others.
+//!
+//! Nodes sharing a Redis already share Redis-backed passports, and only
+//! need `local` mode. Nodes with a Redis each (or none reachable) run in
+//! `signed` mode instead: every passport is an ed25519-signed token
+//! targeted at the whole cluster, which `/validate` on any node accepts
+//! once it trusts the issuer's key.
+//!
+//! Keys are trusted when pinned in `peer_keys`, or, with `registry`, when
+//! found in the shared trust registry (`cerberus:passport_keys`), where each
+//! node publishes its own key. Anyone who can write to that Redis can add a
+//! key, just as they could write a passport record; pin keys where Redis is
+//! less trusted than the nodes.
+
+use anyhow::Result;
+use cerberus_common::redis_keys;
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use super::PassportService;
+use crate::state::AppState;
+
+/// Passport federation settings (`[federation]` in fortify.toml)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FederationConfig {
+    pub mode: FederationMode,
+    /// This node's 32-byte ed25519 signing key (ephemeral when unset)
+    pub private_key_path: Option<String>,
+    /// Pinned peer keys (node ID -> base64url public key)
+    pub peer_keys: HashMap<String, String>,
+    /// Publish our key to, and trust keys from, the Redis trust registry
+    pub registry: bool,
+    /// How often the trust registry is read
+    pub registry_refresh_secs: u64,
+    /// Our signing key before a rotation; its passports still validate
+    /// during the overlap
+    pub previous_private_key_path: Option<String>,
+    /// Peer keys before a rotation (node ID -> base64url public key)
+    pub previous_peer_keys: HashMap<String, String>,
+    /// How long a rotated-out key keeps validating, unless the cutover is
+    /// finalized first (`POST /admin/passports/keys/finalize`)
+    pub key_overlap_secs: u64,
+}
+
+impl Default for FederationConfig {
+    fn default() -> Self {
+        Self {
+            mode: FederationMode::Local,
+            private_key_path: None,
+            peer_keys: HashMap::new(),
+            registry: true,
+            registry_refresh_secs: 30,
+            previous_private_key_path: None,
+            previous_peer_keys: HashMap::new(),
+            key_overlap_secs: 86400,
+        }
+    }
+}
+
+/// How passports reach the other nodes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FederationMode {
+    /// Through a shared Redis; signed passports stay on the minting node
+    #[default]
+    Local,
+    /// Every passport is a signed token any trusting node accepts
+    Signed,
+}
+
+impl FederationConfig {
+    /// Is the trust registry in use?
+    pub fn uses_registry(&self) -> bool {
+        self.mode == FederationMode::Signed && self.registry
+    }
+}
+
+/// Keep this node's key in the trust registry and trust its peers' keys
+pub async fn federation_worker(state: AppState, mut shutdown: broadcast::Receiver<()>) {
+    let config = &state.config.federation;
+    if !state.config.cluster_enabled || !config.uses_registry() {
+        return;
+    }
+    let interval = Duration::from_secs(config.registry_refresh_secs);
+
+    loop {
+        if let Some(mut redis) = state.redis()
+            && let Err(e) = sync_registry(&state.passports, &config.peer_keys, &mut redis).await
+        {
+            tracing::warn!(error = %e, "Failed to sync passport trust registry");
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = shutdown.recv() => {
+                tracing::info!("Passport federation shutting down");
+                break;
+            }
+        }
+    }
+}
+
+/// Publish our key and take up new or changed peer keys
+async fn sync_registry(
+    passports: &PassportService,
+    pinned: &HashMap<String, String>,
+    redis: &mut ConnectionManager,
+) -> Result<()> {
+    let key = redis_keys::passport_keys();
+    if let Some(pubkey) = passports.public_key_b64() {
+        redis
+            .hset::<_, _, _, ()>(&key, passports.node_id(), pubkey)
+            .await?;
+    }
+
+    let registry: HashMap<String, String> = redis.hgetall(&key).await?;
+    for (node_id, pubkey) in registry_peers(passports.node_id(), pinned, registry) {
+        if passports.peer_key_b64(&node_id).await.as_deref() == Some(pubkey.as_str()) {
+            continue;
+        }
+        if let Err(e) = passports.add_peer_key(&node_id, &pubkey).await {
+            tracing::warn!(node_id = %node_id, error = %e, "Ignoring invalid registry key");
+        }
+    }
+    Ok(())
+}
+
+/// Registry entries to trust: not our own, and not overriding a pinned key
+fn registry_peers(
+    node_id: &str,
+    pinned: &HashMap<String, String>,
+    registry: HashMap<String, String>,
+) -> impl Iterator<Item = (String, String)> {
+    registry
+        .into_iter()
+        .filter(move |(peer, _)| peer != node_id && !pinned.contains_key(peer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry_never_overrides_pinned_or_own_key() {
+        let pinned = HashMap::from([("node-2".to_string(), "pinned".to_string())]);
+        let registry = HashMap::from([
+            ("node-1".to_string(), "own".to_string()),
+            ("node-2".to_string(), "forged".to_string()),
+            ("node-3".to_string(), "published".to_string()),
+        ]);
+
+        let peers: Vec<_> = registry_peers("node-1", &pinned, registry).collect();
+        assert_eq!(peers, vec![("node-3".to_string(), "published".to_string())]);
+    }
+}
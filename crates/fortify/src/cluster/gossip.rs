@@ -7,30 +7,73 @@
 //! Used for:
 //! - Load-based routing decisions
 //! - Split-brain detection
-//! - Peer health monitoring
+//! - Peer health monitoring (peers that keep dropping out and coming back
+//!   are flagged as flapping and not shed to)
+//! - Backend health: each packet carries the node's Redis reachability and
+//!   latency, free disk space and `/validate` p99, so load isn't shed to a
+//!   node with idle CPUs but a struggling Redis
+//! - Passport revocations (pushed to every peer as soon as they happen,
+//!   signed with the sender's passport key)
+//! - Per-circuit request counts, for the cluster-wide rate limit (see
+//!   `rate_share`), signed the same way
+//!
+//! Anything on the tunnel subnet can reach the gossip port, so datagrams are
+//! rate limited per source address before they are parsed, and health
+//! packets are only believed from allowed node IDs, with a timestamp close
+//! to ours and newer than the last one from that node. A hostile host can't
+//! flood the peer table or bring a dead node back by replaying its packets.
+//! Revocations and request counts are only applied with a valid signature
+//! from a known peer, so one can't revoke visitors' passports or get their
+//! circuits rate limited either.
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use cerberus_common::{BoundedCache, CerberusEvent, EventBus, EventPublisher, TtlSecs};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::net::SocketAddr;
-use std::sync::Arc;
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use tokio::net::UdpSocket;
 use tokio::sync::RwLock;
 
-/// Gossip protocol configuration
-#[derive(Clone, Debug)]
+use super::PassportService;
+use super::rate_share::RateShare;
+use crate::metrics;
+
+/// Gossip protocol configuration (`[gossip]` in fortify.toml)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
 pub struct GossipConfig {
     /// Local bind address (e.g., "10.100.0.1:9000")
     pub bind_addr: String,
     /// Peer addresses to broadcast to
     pub peers: Vec<String>,
-    /// Broadcast interval in seconds
-    pub interval_secs: u64,
-    /// Peer timeout in seconds (mark as unhealthy after this)
-    pub peer_timeout_secs: u64,
+    /// Broadcast interval
+    pub interval_secs: TtlSecs,
+    /// Peer timeout (mark as unhealthy after this)
+    pub peer_timeout_secs: TtlSecs,
     /// Stale threshold (mark as stale after this percentage of cluster is unreachable)
     pub isolation_threshold: f32,
+    /// Window over which a peer's health transitions are counted
+    pub flap_window_secs: TtlSecs,
+    /// Transitions within the window that make a peer flapping (0 = never)
+    pub flap_threshold: usize,
+    /// Datagrams accepted per second from one source address
+    pub max_packets_per_sec: u32,
+    /// Health packets further than this from our clock are dropped
+    pub max_clock_skew_secs: TtlSecs,
+    /// Node IDs accepted from the wire (empty = any)
+    pub allowed_nodes: Vec<String>,
+    /// Peers tracked at most; once full, a new node ID only takes the place
+    /// of the least recently heard peer if that one is unhealthy
+    pub max_peers: usize,
+    /// Peers whose Redis PING is slower than this aren't shed to (0 = no limit)
+    pub shed_max_redis_latency_ms: u32,
+    /// Peers whose `/validate` p99 is slower than this aren't shed to (0 = no limit)
+    pub shed_max_validate_p99_ms: u32,
+    /// Peers with less free disk than this (in MiB) aren't shed to
+    pub shed_min_disk_free_mb: u64,
 }
 
 impl Default for GossipConfig {
@@ -38,9 +81,18 @@ impl Default for GossipConfig {
         Self {
             bind_addr: "0.0.0.0:9000".to_string(),
             peers: vec![],
-            interval_secs: 5,
-            peer_timeout_secs: 30,
+            interval_secs: TtlSecs::from_secs(5),
+            peer_timeout_secs: TtlSecs::from_secs(30),
             isolation_threshold: 0.5,
+            flap_window_secs: TtlSecs::from_mins(10),
+            flap_threshold: 4,
+            max_packets_per_sec: 50,
+            max_clock_skew_secs: TtlSecs::from_mins(1),
+            allowed_nodes: vec![],
+            max_peers: 64,
+            shed_max_redis_latency_ms: 100,
+            shed_max_validate_p99_ms: 250,
+            shed_min_disk_free_mb: 256,
         }
     }
 }
@@ -64,9 +116,33 @@ pub struct GossipPacket {
     pub timestamp: u64,
     /// Software version
     pub version: String,
+    /// Draining for removal: issuing no new challenges
+    #[serde(default)]
+    pub draining: bool,
+    /// Redis answering (false while the node runs offline)
+    #[serde(default = "reachable_by_default")]
+    pub redis_reachable: bool,
+    /// Last Redis PING round-trip in milliseconds
+    #[serde(default)]
+    pub redis_latency_ms: u32,
+    /// Free space where the Ammo Box caches to disk, in MiB (`None` if unknown)
+    #[serde(default)]
+    pub disk_free_mb: Option<u64>,
+    /// `/validate` p99 since the previous packet, in milliseconds (`None`
+    /// without traffic)
+    #[serde(default)]
+    pub validate_p99_ms: Option<u32>,
+}
+
+/// Peers from before backend health was gossiped don't report on Redis
+fn reachable_by_default() -> bool {
+    true
 }
 
 impl GossipPacket {
+    /// Largest datagram we accept (packets are ~200 bytes of JSON)
+    pub const MAX_SIZE: usize = 1024;
+
     /// Create a new gossip packet with current state
     pub fn new(
         node_id: String,
@@ -85,7 +161,218 @@ impl GossipPacket {
             threat_level,
             timestamp: chrono::Utc::now().timestamp() as u64,
             version: env!("CARGO_PKG_VERSION").to_string(),
+            draining: false,
+            redis_reachable: true,
+            redis_latency_ms: 0,
+            disk_free_mb: None,
+            validate_p99_ms: None,
+        }
+    }
+
+    /// Is the node's backend (Redis, disk, `/validate`) healthy enough to
+    /// take shed load?
+    pub fn backend_healthy(&self, config: &GossipConfig) -> bool {
+        let within = |value: u32, limit: u32| limit == 0 || value <= limit;
+        self.redis_reachable
+            && within(self.redis_latency_ms, config.shed_max_redis_latency_ms)
+            && self
+                .validate_p99_ms
+                .is_none_or(|p99| within(p99, config.shed_max_validate_p99_ms))
+            && self
+                .disk_free_mb
+                .is_none_or(|free| free >= config.shed_min_disk_free_mb)
+    }
+
+    /// Decode a packet received from the wire
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() > Self::MAX_SIZE {
+            bail!("Gossip packet too large ({} bytes)", data.len());
+        }
+        let packet: GossipPacket =
+            serde_json::from_slice(data).context("Malformed gossip packet")?;
+        if packet.node_id.is_empty() {
+            bail!("Gossip packet has empty node_id");
+        }
+        Ok(packet)
+    }
+}
+
+/// Passport revocation pushed to peers (one token per datagram)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RevocationNotice {
+    /// Node the revocation was made on
+    pub node_id: String,
+    /// Revoked passport token
+    pub revoked: String,
+    /// Base64 signature of `signed_message` by the node's passport key
+    pub signature: String,
+}
+
+impl RevocationNotice {
+    /// A notice from `node_id`, signed with its passport key
+    pub fn signed(passports: &PassportService, node_id: &str, revoked: &str) -> Result<Self> {
+        let signature = passports.sign(&Self::signed_message(node_id, revoked))?;
+        Ok(Self {
+            node_id: node_id.to_string(),
+            revoked: revoked.to_string(),
+            signature: URL_SAFE_NO_PAD.encode(signature),
+        })
+    }
+
+    /// Check the signature against the sender's known key
+    pub async fn verify(&self, passports: &PassportService) -> Result<()> {
+        let signature = URL_SAFE_NO_PAD
+            .decode(&self.signature)
+            .context("Invalid signature encoding")?;
+        let message = Self::signed_message(&self.node_id, &self.revoked);
+        passports
+            .verify_peer(&self.node_id, &message, &signature)
+            .await
+    }
+
+    fn signed_message(node_id: &str, revoked: &str) -> Vec<u8> {
+        format!("revoke:{}:{}", node_id, revoked).into_bytes()
+    }
+}
+
+/// Requests per circuit a node counted in one rate limit window
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateCountsNotice {
+    pub node_id: String,
+    /// Window (Unix seconds / window length)
+    pub window: u64,
+    /// Circuit ID and its requests so far (a total, not a delta)
+    pub counts: Vec<(String, u32)>,
+    /// Base64 signature of `signed_message` by the node's passport key
+    /// (empty until `sign`)
+    pub signature: String,
+}
+
+impl RateCountsNotice {
+    /// Base64 length of a signature, for sizing datagrams
+    pub const SIGNATURE_LEN: usize = 86;
+
+    /// Sign with the sending node's passport key
+    pub fn sign(&mut self, passports: &PassportService) -> Result<()> {
+        let signature = passports.sign(&self.signed_message())?;
+        self.signature = URL_SAFE_NO_PAD.encode(signature);
+        Ok(())
+    }
+
+    /// Check the signature against the sender's known key
+    pub async fn verify(&self, passports: &PassportService) -> Result<()> {
+        let signature = URL_SAFE_NO_PAD
+            .decode(&self.signature)
+            .context("Invalid signature encoding")?;
+        passports
+            .verify_peer(&self.node_id, &self.signed_message(), &signature)
+            .await
+    }
+
+    fn signed_message(&self) -> Vec<u8> {
+        let counts = serde_json::to_string(&self.counts).expect("counts serialize");
+        format!("rates:{}:{}:{}", self.node_id, self.window, counts).into_bytes()
+    }
+}
+
+/// Anything a peer may send to the gossip port
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum GossipMessage {
+    Health(GossipPacket),
+    Revocation(RevocationNotice),
+    RateCounts(RateCountsNotice),
+}
+
+impl GossipMessage {
+    fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() > GossipPacket::MAX_SIZE {
+            bail!("Gossip packet too large ({} bytes)", data.len());
+        }
+        let message: GossipMessage =
+            serde_json::from_slice(data).context("Malformed gossip packet")?;
+        let node_id = match &message {
+            GossipMessage::Health(packet) => &packet.node_id,
+            GossipMessage::Revocation(notice) => &notice.node_id,
+            GossipMessage::RateCounts(notice) => &notice.node_id,
+        };
+        if node_id.is_empty() {
+            bail!("Gossip packet has empty node_id");
+        }
+        Ok(message)
+    }
+}
+
+/// Why a datagram was dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rejection {
+    /// Over `max_packets_per_sec` from its source address
+    RateLimited,
+    /// Too large, or not a gossip message
+    Malformed,
+    /// Node ID not in `allowed_nodes`
+    UnknownNode,
+    /// Timestamp outside `max_clock_skew_secs`
+    ClockSkew,
+    /// Not newer than the last packet from that node
+    Replayed,
+    /// Revocation without a valid signature from its node
+    BadSignature,
+    /// New node ID with `max_peers` already tracked, all of them healthy
+    PeerTableFull,
+}
+
+impl Rejection {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::RateLimited => "rate_limited",
+            Self::Malformed => "malformed",
+            Self::UnknownNode => "unknown_node",
+            Self::ClockSkew => "clock_skew",
+            Self::Replayed => "replayed",
+            Self::BadSignature => "bad_signature",
+            Self::PeerTableFull => "peer_table_full",
+        }
+    }
+}
+
+/// Source addresses rate limited at once; beyond this the quietest are
+/// forgotten (and start a fresh window)
+const MAX_SOURCES: usize = 4096;
+
+/// Datagrams per source address, in one-second windows
+struct SourceLimiter {
+    windows: BoundedCache<IpAddr, (Instant, u32)>,
+}
+
+impl Default for SourceLimiter {
+    fn default() -> Self {
+        Self {
+            windows: BoundedCache::new(MAX_SOURCES),
+        }
+    }
+}
+
+impl SourceLimiter {
+    /// Count a datagram from `ip`; false once it is over `limit` this second
+    fn allow(&mut self, ip: IpAddr, limit: u32) -> bool {
+        let now = Instant::now();
+        if !self.windows.contains_key(&ip) {
+            self.windows.insert(ip, (now, 0));
+        }
+        let (start, count) = self.windows.get_mut(&ip).expect("window just inserted");
+        if now.duration_since(*start) >= Duration::from_secs(1) {
+            *start = now;
+            *count = 0;
         }
+        *count += 1;
+        *count <= limit
+    }
+
+    /// Forget sources that have gone quiet
+    fn prune(&mut self) {
+        self.windows
+            .retain(|_, (start, _)| start.elapsed() < Duration::from_secs(1));
     }
 }
 
@@ -98,6 +385,43 @@ pub struct NodeHealth {
     pub last_seen: Instant,
     /// Is this node considered healthy?
     pub is_healthy: bool,
+    /// Healthy/unhealthy transitions within the flap window, oldest first
+    pub transitions: VecDeque<Instant>,
+    /// Changing state too often to be trusted with shed load
+    pub flapping: bool,
+}
+
+impl NodeHealth {
+    fn new(packet: GossipPacket) -> Self {
+        Self {
+            last_packet: packet,
+            last_seen: Instant::now(),
+            is_healthy: true,
+            transitions: VecDeque::new(),
+            flapping: false,
+        }
+    }
+
+    /// Transitions within the flap window
+    pub fn flaps(&self) -> usize {
+        self.transitions.len()
+    }
+
+    /// Forget transitions older than `window` and re-evaluate `flapping`;
+    /// returns whether it changed
+    fn update_flapping(&mut self, window: Duration, threshold: usize) -> bool {
+        while self
+            .transitions
+            .front()
+            .is_some_and(|at| at.elapsed() > window)
+        {
+            self.transitions.pop_front();
+        }
+        let flapping = threshold > 0 && self.transitions.len() >= threshold;
+        let changed = flapping != self.flapping;
+        self.flapping = flapping;
+        changed
+    }
 }
 
 /// Gossip service for cluster health monitoring
@@ -106,23 +430,53 @@ pub struct GossipService {
     config: GossipConfig,
     /// Our node ID
     node_id: String,
-    /// Known peer health states
-    peers: Arc<RwLock<HashMap<String, NodeHealth>>>,
+    /// Known peer health states, least recently heard first out
+    peers: Arc<RwLock<BoundedCache<String, NodeHealth>>>,
     /// Are we isolated from the cluster?
     isolated: Arc<RwLock<bool>>,
+    /// Applies revocations received from peers
+    passports: Option<Arc<PassportService>>,
+    /// Takes in request counts received from peers
+    rate_share: Option<Arc<RateShare>>,
+    /// Receives peer health changes
+    publisher: Arc<dyn EventPublisher>,
+    /// Per-source datagram counts
+    limiter: Mutex<SourceLimiter>,
 }
 
 impl GossipService {
     /// Create a new gossip service
     pub fn new(config: GossipConfig, node_id: String) -> Self {
         Self {
+            peers: Arc::new(RwLock::new(BoundedCache::new(config.max_peers))),
             config,
             node_id,
-            peers: Arc::new(RwLock::new(HashMap::new())),
             isolated: Arc::new(RwLock::new(false)),
+            passports: None,
+            rate_share: None,
+            publisher: Arc::new(EventBus::new()),
+            limiter: Mutex::new(SourceLimiter::default()),
         }
     }
 
+    /// Apply passport revocations received from peers to `passports`
+    pub fn with_passports(mut self, passports: Arc<PassportService>) -> Self {
+        self.passports = Some(passports);
+        self
+    }
+
+    /// Merge request counts received from peers into `rate_share`
+    pub fn with_rate_share(mut self, rate_share: Arc<RateShare>) -> Self {
+        self.rate_share = Some(rate_share);
+        self
+    }
+
+    /// Publish peers going unhealthy and recovering to `publisher`
+    pub fn with_publisher(mut self, publisher: Arc<dyn EventPublisher>) -> Self {
+        self.publisher = publisher;
+        self
+    }
+
     /// Get our node ID
     pub fn node_id(&self) -> &str {
         &self.node_id
@@ -135,7 +489,7 @@ impl GossipService {
 
     /// Get all known peer health states
     pub async fn get_peers(&self) -> HashMap<String, NodeHealth> {
-        self.peers.read().await.clone()
+        self.peers.read().await.to_map()
     }
 
     /// Get healthy peers (sorted by load)
@@ -152,16 +506,75 @@ impl GossipService {
         healthy
     }
 
-    /// Get the least loaded healthy peer for load shedding
+    /// Get the least loaded healthy peer for load shedding (never a flapping
+    /// or draining one, nor one whose Redis, disk or `/validate` is struggling)
     pub async fn get_shed_target(&self) -> Option<GossipPacket> {
         let peers = self.peers.read().await;
         peers
             .values()
-            .filter(|p| p.is_healthy && p.last_packet.cpu_load < 80)
+            .filter(|p| {
+                p.is_healthy
+                    && !p.flapping
+                    && !p.last_packet.draining
+                    && p.last_packet.cpu_load < 80
+                    && p.last_packet.backend_healthy(&self.config)
+            })
             .min_by_key(|p| p.last_packet.cpu_load)
             .map(|p| p.last_packet.clone())
     }
 
+    /// Push a passport revocation to every peer, returning how many were sent
+    pub async fn broadcast_revocation(&self, token: &str) -> Result<usize> {
+        let passports = self
+            .passports
+            .as_ref()
+            .context("No passport key to sign revocations with")?;
+        let notice = RevocationNotice::signed(passports, &self.node_id, token)?;
+        let bytes = serde_json::to_vec(&notice)?;
+        if bytes.len() > GossipPacket::MAX_SIZE {
+            bail!("Token too large to gossip ({} bytes)", bytes.len());
+        }
+
+        let socket = UdpSocket::bind("0.0.0.0:0")
+            .await
+            .context("Failed to bind gossip sender socket")?;
+
+        let mut sent = 0;
+        for peer in &self.config.peers {
+            match socket.send_to(&bytes, peer).await {
+                Ok(_) => sent += 1,
+                Err(e) => tracing::warn!(peer = %peer, error = %e, "Failed to send revocation"),
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Sign request counts and push them to every peer, one datagram per
+    /// notice
+    pub async fn broadcast_rate_counts(&self, notices: Vec<RateCountsNotice>) -> Result<()> {
+        let passports = self
+            .passports
+            .as_ref()
+            .context("No passport key to sign rate counts with")?;
+        let socket = UdpSocket::bind("0.0.0.0:0")
+            .await
+            .context("Failed to bind gossip sender socket")?;
+
+        for mut notice in notices {
+            notice.sign(passports)?;
+            let bytes = serde_json::to_vec(&notice)?;
+            if bytes.len() > GossipPacket::MAX_SIZE {
+                bail!("Rate counts too large to gossip ({} bytes)", bytes.len());
+            }
+            for peer in &self.config.peers {
+                if let Err(e) = socket.send_to(&bytes, peer).await {
+                    tracing::debug!(peer = %peer, error = %e, "Failed to send rate counts");
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Run the gossip broadcaster
     pub async fn run_broadcaster(
         &self,
@@ -173,7 +586,7 @@ impl GossipService {
             .context("Failed to bind gossip sender socket")?;
 
         let peers = self.config.peers.clone();
-        let interval = Duration::from_secs(self.config.interval_secs);
+        let interval = self.config.interval_secs.duration();
 
         tracing::info!(
             peers = ?peers,
@@ -218,8 +631,9 @@ impl GossipService {
             .await
             .context("Failed to bind gossip receiver socket")?;
 
-        let mut buf = vec![0u8; 1024];
-        let timeout = Duration::from_secs(self.config.peer_timeout_secs);
+        // One byte over the limit, so oversized datagrams aren't silently cut
+        let mut buf = vec![0u8; GossipPacket::MAX_SIZE + 1];
+        let timeout = self.config.peer_timeout_secs.duration();
 
         tracing::info!(
             addr = %self.config.bind_addr,
@@ -254,10 +668,40 @@ impl GossipService {
 
     /// Handle an incoming gossip packet
     async fn handle_packet(&self, data: &[u8], addr: SocketAddr) {
-        let packet: GossipPacket = match serde_json::from_slice(data) {
-            Ok(p) => p,
+        let allowed = self
+            .limiter
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .allow(addr.ip(), self.config.max_packets_per_sec);
+        if !allowed {
+            // Not logged: this is what a flood looks like
+            self.reject(Rejection::RateLimited);
+            return;
+        }
+
+        let packet = match GossipMessage::decode(data) {
+            Ok(GossipMessage::Health(p)) => p,
+            Ok(GossipMessage::Revocation(notice)) => {
+                if !self.is_allowed(&notice.node_id) {
+                    tracing::warn!(addr = %addr, node = %notice.node_id, "Revocation from unknown node");
+                    self.reject(Rejection::UnknownNode);
+                    return;
+                }
+                self.handle_revocation(notice, addr).await;
+                return;
+            }
+            Ok(GossipMessage::RateCounts(notice)) => {
+                if !self.is_allowed(&notice.node_id) {
+                    tracing::warn!(addr = %addr, node = %notice.node_id, "Rate counts from unknown node");
+                    self.reject(Rejection::UnknownNode);
+                    return;
+                }
+                self.handle_rate_counts(notice, addr).await;
+                return;
+            }
             Err(e) => {
                 tracing::warn!(addr = %addr, error = %e, "Invalid gossip packet");
+                self.reject(Rejection::Malformed);
                 return;
             }
         };
@@ -267,6 +711,25 @@ impl GossipService {
             return;
         }
 
+        if !self.is_allowed(&packet.node_id) {
+            tracing::warn!(addr = %addr, node = %packet.node_id, "Gossip from unknown node");
+            self.reject(Rejection::UnknownNode);
+            return;
+        }
+        let skew = chrono::Utc::now()
+            .timestamp()
+            .abs_diff(packet.timestamp as i64);
+        if skew > self.config.max_clock_skew_secs.secs() {
+            tracing::warn!(
+                addr = %addr,
+                node = %packet.node_id,
+                skew_secs = skew,
+                "Gossip packet outside the clock skew window"
+            );
+            self.reject(Rejection::ClockSkew);
+            return;
+        }
+
         tracing::trace!(
             node = %packet.node_id,
             cpu = packet.cpu_load,
@@ -275,15 +738,110 @@ impl GossipService {
         );
 
         // Update peer state
+        let node_id = packet.node_id.clone();
         let mut peers = self.peers.write().await;
-        peers.insert(
-            packet.node_id.clone(),
-            NodeHealth {
-                last_packet: packet,
-                last_seen: Instant::now(),
-                is_healthy: true,
-            },
-        );
+        // A dead peer gives up its place; live ones can't be pushed out
+        let table_full = peers.is_full() && peers.peek_lru().is_none_or(|(_, lru)| lru.is_healthy);
+        let recovered = match peers.get_mut(&node_id) {
+            Some(health) if packet.timestamp <= health.last_packet.timestamp => {
+                drop(peers);
+                tracing::warn!(addr = %addr, node = %node_id, "Replayed gossip packet");
+                self.reject(Rejection::Replayed);
+                return;
+            }
+            Some(health) => {
+                let recovered = !health.is_healthy;
+                health.last_packet = packet;
+                health.last_seen = Instant::now();
+                health.is_healthy = true;
+                if recovered {
+                    self.record_transition(health);
+                }
+                recovered
+            }
+            None if table_full => {
+                drop(peers);
+                tracing::warn!(addr = %addr, node = %node_id, "Peer table full; ignoring new node");
+                self.reject(Rejection::PeerTableFull);
+                return;
+            }
+            None => {
+                peers.insert(node_id.clone(), NodeHealth::new(packet));
+                false
+            }
+        };
+        drop(peers);
+
+        if recovered {
+            tracing::info!(node = %node_id, "Peer recovered");
+            self.publisher
+                .publish(CerberusEvent::PeerRecovered { node_id });
+        }
+    }
+
+    /// Is `node_id` allowed to gossip with us?
+    fn is_allowed(&self, node_id: &str) -> bool {
+        self.config.allowed_nodes.is_empty()
+            || self.config.allowed_nodes.iter().any(|n| n == node_id)
+    }
+
+    fn reject(&self, reason: Rejection) {
+        metrics::GOSSIP_REJECTED_PACKETS
+            .with_label_values(&[reason.as_str()])
+            .inc();
+    }
+
+    /// Apply a revocation made on a peer, if it signed it
+    async fn handle_revocation(&self, notice: RevocationNotice, addr: SocketAddr) {
+        if notice.node_id == self.node_id {
+            return;
+        }
+        let Some(ref passports) = self.passports else {
+            return;
+        };
+        if let Err(e) = notice.verify(passports).await {
+            tracing::warn!(addr = %addr, node = %notice.node_id, error = %e, "Unsigned revocation");
+            self.reject(Rejection::BadSignature);
+            return;
+        }
+
+        passports.revoke(&notice.revoked).await;
+        tracing::info!(node = %notice.node_id, "Passport revoked by peer");
+    }
+
+    /// Merge request counts from a peer, if it signed them
+    async fn handle_rate_counts(&self, notice: RateCountsNotice, addr: SocketAddr) {
+        let (Some(rate_share), Some(passports)) = (&self.rate_share, &self.passports) else {
+            return;
+        };
+        if notice.node_id == self.node_id {
+            return;
+        }
+        if let Err(e) = notice.verify(passports).await {
+            tracing::warn!(addr = %addr, node = %notice.node_id, error = %e, "Unsigned rate counts");
+            self.reject(Rejection::BadSignature);
+            return;
+        }
+
+        rate_share.apply(&notice, chrono::Utc::now().timestamp() as u64);
+    }
+
+    fn flap_window(&self) -> Duration {
+        self.config.flap_window_secs.duration()
+    }
+
+    /// Note a peer going unhealthy or recovering, flagging it if it flaps
+    fn record_transition(&self, health: &mut NodeHealth) {
+        health.transitions.push_back(Instant::now());
+        if health.update_flapping(self.flap_window(), self.config.flap_threshold) && health.flapping
+        {
+            tracing::warn!(
+                node = %health.last_packet.node_id,
+                transitions = health.flaps(),
+                window_secs = self.config.flap_window_secs.secs(),
+                "Peer is flapping; no longer shedding load to it"
+            );
+        }
     }
 
     /// Check peer health and isolation status
@@ -292,6 +850,8 @@ impl GossipService {
         let total_peers = peers.len();
         let mut unhealthy_count = 0;
 
+        let mut flapping_count = 0;
+
         for health in peers.values_mut() {
             if health.last_seen.elapsed() > timeout {
                 if health.is_healthy {
@@ -299,13 +859,33 @@ impl GossipService {
                         node = %health.last_packet.node_id,
                         "Peer marked unhealthy (timeout)"
                     );
+                    self.publisher.publish(CerberusEvent::PeerUnhealthy {
+                        node_id: health.last_packet.node_id.clone(),
+                    });
+                    health.is_healthy = false;
+                    self.record_transition(health);
                 }
-                health.is_healthy = false;
                 unhealthy_count += 1;
+            } else if health.update_flapping(self.flap_window(), self.config.flap_threshold) {
+                tracing::info!(node = %health.last_packet.node_id, "Peer stopped flapping");
             }
+
+            if health.flapping {
+                flapping_count += 1;
+            }
+            metrics::GOSSIP_PEER_FLAPS
+                .with_label_values(&[&health.last_packet.node_id])
+                .set(health.flaps() as i64);
         }
+        metrics::GOSSIP_FLAPPING_PEERS.set(flapping_count);
+        metrics::record_cache("gossip_peers", peers.stats());
 
         drop(peers);
+        {
+            let mut limiter = self.limiter.lock().unwrap_or_else(|e| e.into_inner());
+            limiter.prune();
+            metrics::record_cache("gossip_sources", limiter.windows.stats());
+        }
 
         // Check isolation
         if total_peers > 0 {
@@ -320,8 +900,13 @@ impl GossipService {
                         total = total_peers,
                         "⚠️ Node is ISOLATED from cluster"
                     );
+                    self.publisher.publish(CerberusEvent::NodeIsolated {
+                        unhealthy: unhealthy_count,
+                        peers: total_peers,
+                    });
                 } else {
                     tracing::info!("✅ Node reconnected to cluster");
+                    self.publisher.publish(CerberusEvent::NodeRejoined);
                 }
                 *is_isolated = isolated;
             }
@@ -335,14 +920,7 @@ mod tests {
 
     #[test]
     fn test_gossip_packet_serialization() {
-        let packet = GossipPacket::new(
-            "node-1".to_string(),
-            45,
-            true,
-            1234,
-            80,
-            2,
-        );
+        let packet = GossipPacket::new("node-1".to_string(), 45, true, 1234, 80, 2);
 
         let json = serde_json::to_string(&packet).unwrap();
         let parsed: GossipPacket = serde_json::from_str(&json).unwrap();
@@ -351,4 +929,283 @@ mod tests {
         assert_eq!(parsed.cpu_load, 45);
         assert!(parsed.tor_health);
     }
+
+    #[tokio::test]
+    async fn test_revocation_applied_from_peer() {
+        let node = |node_id: &str| {
+            Arc::new(
+                PassportService::new(super::super::PassportConfig {
+                    node_id: node_id.to_string(),
+                    ..Default::default()
+                })
+                .unwrap(),
+            )
+        };
+        let peer = node("node-1");
+        let passports = node("node-2");
+        passports
+            .add_peer_key("node-1", &peer.public_key_b64().unwrap())
+            .await
+            .unwrap();
+        let service = GossipService::new(GossipConfig::default(), "node-2".to_string())
+            .with_passports(passports.clone());
+        let addr: SocketAddr = "10.100.0.1:9000".parse().unwrap();
+
+        let notice = RevocationNotice::signed(&peer, "node-1", "token-abc").unwrap();
+        service
+            .handle_packet(&serde_json::to_vec(&notice).unwrap(), addr)
+            .await;
+        assert!(passports.is_revoked("token-abc").await);
+        assert!(service.get_peers().await.is_empty());
+
+        // Unsigned, or signed by anyone but the named node: ignored
+        let mut forged = RevocationNotice::signed(&peer, "node-1", "token-def").unwrap();
+        forged.revoked = "token-xyz".to_string();
+        let impostor = RevocationNotice::signed(&node("node-1"), "node-1", "token-xyz").unwrap();
+        for notice in [forged, impostor] {
+            service
+                .handle_packet(&serde_json::to_vec(&notice).unwrap(), addr)
+                .await;
+        }
+        assert!(!passports.is_revoked("token-xyz").await);
+    }
+
+    #[tokio::test]
+    async fn test_rate_counts_applied_from_peer() {
+        let node = |node_id: &str| {
+            Arc::new(
+                PassportService::new(super::super::PassportConfig {
+                    node_id: node_id.to_string(),
+                    ..Default::default()
+                })
+                .unwrap(),
+            )
+        };
+        let peer = node("node-1");
+        let passports = node("node-2");
+        passports
+            .add_peer_key("node-1", &peer.public_key_b64().unwrap())
+            .await
+            .unwrap();
+        let config = super::super::ClusterRateLimitConfig {
+            enabled: true,
+            ..Default::default()
+        };
+        let rate_share = Arc::new(RateShare::new(config, "node-2".to_string()));
+        let service = GossipService::new(GossipConfig::default(), "node-2".to_string())
+            .with_passports(passports)
+            .with_rate_share(rate_share.clone());
+
+        let now = chrono::Utc::now().timestamp() as u64;
+        let notice = |circuit_id: &str| RateCountsNotice {
+            node_id: "node-1".to_string(),
+            window: now / cerberus_common::constants::RATE_LIMIT_WINDOW_SECS.secs(),
+            counts: vec![(circuit_id.to_string(), 42)],
+            signature: String::new(),
+        };
+        let addr: SocketAddr = "10.100.0.1:9000".parse().unwrap();
+        let mut signed = notice("fc00::1");
+        signed.sign(&peer).unwrap();
+        service
+            .handle_packet(&serde_json::to_vec(&signed).unwrap(), addr)
+            .await;
+
+        let circuit_id = "fc00::1".parse().unwrap();
+        assert_eq!(rate_share.elsewhere(&circuit_id, now), 42);
+        assert!(service.get_peers().await.is_empty());
+
+        // Unsigned, altered, or signed by anyone but the named node: ignored
+        let mut altered = signed.clone();
+        altered.counts[0].0 = "fc00::2".to_string();
+        let mut impostor = notice("fc00::2");
+        impostor.sign(&node("node-1")).unwrap();
+        for notice in [notice("fc00::2"), altered, impostor] {
+            service
+                .handle_packet(&serde_json::to_vec(&notice).unwrap(), addr)
+                .await;
+        }
+        let circuit_id = "fc00::2".parse().unwrap();
+        assert_eq!(rate_share.elsewhere(&circuit_id, now), 0);
+    }
+
+    /// A health packet dated `ahead` seconds from now (each must be newer)
+    fn health(node_id: &str, cpu_load: u8, ahead: u64) -> Vec<u8> {
+        let mut packet = GossipPacket::new(node_id.to_string(), cpu_load, true, 0, 100, 0);
+        packet.timestamp += ahead;
+        serde_json::to_vec(&packet).unwrap()
+    }
+
+    #[derive(Default)]
+    struct Collect(std::sync::Mutex<Vec<CerberusEvent>>);
+
+    impl cerberus_common::EventSubscriber for Collect {
+        fn on_event(&self, event: &CerberusEvent) {
+            self.0.lock().unwrap().push(event.clone());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_peer_health_changes_published() {
+        let bus = Arc::new(EventBus::new());
+        let seen = Arc::new(Collect::default());
+        bus.subscribe(seen.clone());
+        let service =
+            GossipService::new(GossipConfig::default(), "node-2".to_string()).with_publisher(bus);
+
+        let addr: SocketAddr = "10.100.0.1:9000".parse().unwrap();
+        service.handle_packet(&health("node-1", 10, 0), addr).await;
+        service.check_peer_health(Duration::ZERO).await;
+        // Already unhealthy: not published twice
+        service.check_peer_health(Duration::ZERO).await;
+        service.handle_packet(&health("node-1", 10, 1), addr).await;
+
+        let node_id = "node-1".to_string();
+        assert_eq!(
+            *seen.0.lock().unwrap(),
+            vec![
+                CerberusEvent::PeerUnhealthy {
+                    node_id: node_id.clone()
+                },
+                // Its only peer gone quiet, the node is isolated
+                CerberusEvent::NodeIsolated {
+                    unhealthy: 1,
+                    peers: 1
+                },
+                CerberusEvent::PeerRecovered { node_id },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_flapping_peer_not_shed_to() {
+        let config = GossipConfig {
+            flap_threshold: 4,
+            ..Default::default()
+        };
+        let service = GossipService::new(config, "node-3".to_string());
+        let addr: SocketAddr = "10.100.0.1:9000".parse().unwrap();
+
+        service.handle_packet(&health("node-1", 10, 0), addr).await;
+        assert_eq!(service.get_shed_target().await.unwrap().node_id, "node-1");
+
+        // Down and back up twice: four transitions
+        for ahead in 1..=2 {
+            service.check_peer_health(Duration::ZERO).await;
+            service
+                .handle_packet(&health("node-1", 10, ahead), addr)
+                .await;
+        }
+        service.handle_packet(&health("node-2", 50, 0), addr).await;
+
+        let peers = service.get_peers().await;
+        assert_eq!(peers["node-1"].flaps(), 4);
+        assert!(peers["node-1"].flapping);
+        assert!(!peers["node-2"].flapping);
+        assert!(peers["node-1"].is_healthy);
+        assert_eq!(service.get_shed_target().await.unwrap().node_id, "node-2");
+    }
+
+    #[tokio::test]
+    async fn test_draining_peer_not_shed_to() {
+        let service = GossipService::new(GossipConfig::default(), "node-3".to_string());
+        let addr: SocketAddr = "10.100.0.1:9000".parse().unwrap();
+
+        let mut draining = GossipPacket::new("node-1".to_string(), 10, true, 0, 100, 0);
+        draining.draining = true;
+        service
+            .handle_packet(&serde_json::to_vec(&draining).unwrap(), addr)
+            .await;
+        assert!(service.get_shed_target().await.is_none());
+
+        service.handle_packet(&health("node-2", 50, 0), addr).await;
+        assert_eq!(service.get_shed_target().await.unwrap().node_id, "node-2");
+    }
+
+    #[tokio::test]
+    async fn test_backend_health_gates_shedding() {
+        let service = GossipService::new(GossipConfig::default(), "node-4".to_string());
+        let addr: SocketAddr = "10.100.0.1:9000".parse().unwrap();
+
+        // Idle CPUs, but Redis is slow / gone / disk is full
+        let mut slow = GossipPacket::new("node-1".to_string(), 5, true, 0, 100, 0);
+        slow.redis_latency_ms = 400;
+        let mut offline = GossipPacket::new("node-2".to_string(), 10, true, 0, 100, 0);
+        offline.redis_reachable = false;
+        let mut full = GossipPacket::new("node-3".to_string(), 15, true, 0, 100, 0);
+        full.disk_free_mb = Some(10);
+        for packet in [slow, offline, full] {
+            service
+                .handle_packet(&serde_json::to_vec(&packet).unwrap(), addr)
+                .await;
+        }
+        assert!(service.get_shed_target().await.is_none());
+
+        // Busier, but healthy behind the CPU
+        let mut busy = GossipPacket::new("node-5".to_string(), 60, true, 0, 100, 0);
+        busy.redis_latency_ms = 3;
+        busy.validate_p99_ms = Some(40);
+        busy.disk_free_mb = Some(20_000);
+        service
+            .handle_packet(&serde_json::to_vec(&busy).unwrap(), addr)
+            .await;
+        assert_eq!(service.get_shed_target().await.unwrap().node_id, "node-5");
+
+        // Packets from older nodes carry none of it
+        let legacy = r#"{"node_id": "node-6", "cpu_load": 1, "tor_health": true,
+            "active_conns": 0, "ammo_fill": 100, "threat_level": 0, "timestamp": 0,
+            "version": "0.1.0"}"#;
+        let packet: GossipPacket = serde_json::from_str(legacy).unwrap();
+        assert!(packet.backend_healthy(&GossipConfig::default()));
+    }
+
+    #[tokio::test]
+    async fn test_spoofed_packets_dropped() {
+        let config = GossipConfig {
+            allowed_nodes: vec!["node-1".to_string()],
+            max_packets_per_sec: 4,
+            ..Default::default()
+        };
+        let service = GossipService::new(config, "node-2".to_string());
+        let addr: SocketAddr = "10.100.0.1:9000".parse().unwrap();
+
+        service.handle_packet(&health("node-1", 10, 0), addr).await;
+        // Replayed, from the future, and from a node not on the list
+        service.handle_packet(&health("node-1", 20, 0), addr).await;
+        service
+            .handle_packet(&health("node-1", 30, 3600), addr)
+            .await;
+        service.handle_packet(&health("node-9", 40, 0), addr).await;
+
+        let peers = service.get_peers().await;
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers["node-1"].last_packet.cpu_load, 10);
+
+        // The source is over its limit now: even a valid packet is dropped
+        service.handle_packet(&health("node-1", 50, 1), addr).await;
+        assert_eq!(service.get_peers().await["node-1"].last_packet.cpu_load, 10);
+        let other: SocketAddr = "10.100.0.3:9000".parse().unwrap();
+        service.handle_packet(&health("node-1", 50, 1), other).await;
+        assert_eq!(service.get_peers().await["node-1"].last_packet.cpu_load, 50);
+    }
+
+    #[tokio::test]
+    async fn test_full_peer_table_only_gives_up_dead_peers() {
+        let config = GossipConfig {
+            max_peers: 1,
+            ..Default::default()
+        };
+        let service = GossipService::new(config, "node-3".to_string());
+        let addr: SocketAddr = "10.100.0.1:9000".parse().unwrap();
+
+        service.handle_packet(&health("node-1", 10, 0), addr).await;
+        service.handle_packet(&health("node-2", 20, 0), addr).await;
+        assert!(!service.get_peers().await.contains_key("node-2"));
+
+        // Once node-1 has gone quiet, node-2 takes its place
+        service.check_peer_health(Duration::ZERO).await;
+        service.handle_packet(&health("node-2", 20, 1), addr).await;
+        let peers = service.get_peers().await;
+        assert_eq!(peers.len(), 1);
+        assert!(peers["node-2"].is_healthy);
+    }
 }
@@ -2,11 +2,75 @@
 //!
 //! Implements:
 //! - Health Gossip Protocol (UDP broadcast)
+//! - Leader election (lease in Redis) for cluster-wide decisions
 //! - Passport Protocol (cryptographic inter-node trust)
-//! - State synchronization
+//! - Passport federation (cluster-wide passports, shared trust registry)
+//! - Ammo sharing (CAPTCHA batches from full pools to starving ones)
+//! - Cluster-wide rate limiting (request counts over gossip)
+//! - State synchronization (threat level pub/sub)
 
+mod ammo_sharing;
+mod election;
+mod federation;
 mod gossip;
 mod passport;
+mod rate_share;
+mod threat_sync;
 
+pub use ammo_sharing::{AmmoSharingConfig, ammo_sharing_worker};
+pub use election::{ElectionConfig, LeaderElection, Proposal, election_worker};
+pub use federation::{FederationConfig, FederationMode, federation_worker};
 pub use gossip::{GossipConfig, GossipPacket, GossipService, NodeHealth};
-pub use passport::{PassportConfig, PassportService, PassportToken};
+pub use passport::{
+    CLUSTER_TARGET, PassportClaims, PassportConfig, PassportService, RetiredKeyInfo,
+    decode_claims,
+};
+pub use rate_share::{ClusterRateLimitConfig, RateShare, rate_share_worker};
+pub use threat_sync::threat_sync_worker;
+
+use crate::degradation::DegradationLevel;
+use crate::metrics::{self, RecentQuantile};
+use crate::state::AppState;
+
+/// Builds this node's gossip packet from `state`, for `run_broadcaster`
+pub fn health_snapshot(state: AppState) -> impl FnMut() -> GossipPacket + Send + 'static {
+    let mut validate_p99 = RecentQuantile::default();
+    move || {
+        let threat_level = state
+            .threat_level
+            .try_read()
+            .map_or(0, |level| level.value());
+        let mut packet = GossipPacket::new(
+            state.node_id.clone(),
+            cpu_load(),
+            // Neither Tor's health nor connections are tracked yet
+            true,
+            0,
+            state.ammo_box.fill_percent(),
+            threat_level,
+        );
+        packet.draining = state.drain.is_draining();
+        packet.redis_reachable = state.degradation.level() != DegradationLevel::Offline;
+        packet.redis_latency_ms = state.degradation.latency_ms().min(u32::MAX as u64) as u32;
+        packet.disk_free_mb = state.ammo_box.disk_free_bytes().map(|bytes| bytes >> 20);
+        let validate = metrics::HTTP_REQUEST_SECONDS.with_label_values(&["/validate", "GET"]);
+        packet.validate_p99_ms = validate_p99
+            .since_last(&validate, 0.99)
+            .map(|secs| (secs * 1000.0).ceil() as u32);
+        packet
+    }
+}
+
+/// One-minute load average as a percentage of the CPUs
+///
+/// 100 where it can't be read, so peers don't shed load to a node whose
+/// load is unknown.
+fn cpu_load() -> u8 {
+    let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+    std::fs::read_to_string("/proc/loadavg")
+        .ok()
+        .and_then(|loadavg| loadavg.split_whitespace().next()?.parse::<f64>().ok())
+        .map_or(100, |load| {
+            (load / cpus as f64 * 100.0).clamp(0.0, 100.0) as u8
+        })
+}
@@ -4,23 +4,55 @@
 //! When a node is overloaded, it can issue a "passport" token that
 //! allows a client to bypass the CAPTCHA on the target node.
 //!
-//! Token format: base64(target:expiry:signature:sender)
+//! Token format: base64(target:expiry:issuer:nonce:binding:signature)
+//!
+//! `binding` is a keyed hash of the circuit the token was minted for (empty
+//! for a token minted without one), so the circuit ID itself never leaves
+//! the issuer; it's keyed with the token's random `nonce`, so two tokens
+//! for one circuit can't be linked.
 //!
 //! Security properties:
 //! - Tokens are short-lived (30 seconds default)
-//! - Tokens are bound to a specific target node
+//! - Tokens are unique, even two minted for one target in the same second
+//! - Tokens minted for a circuit only validate for that circuit
+//! - Tokens are bound to a specific target node, or to the whole cluster
+//!   (`CLUSTER_TARGET`) when passports are federated
 //! - Only nodes with valid keypairs can issue tokens
 //! - Only nodes with the issuer's public key can validate
+//! - Revoked tokens are refused until they would have expired anyway
+//!
+//! Key rotation is blue/green: when an issuer's key changes, its previous
+//! key is retired rather than dropped, and still validates that issuer's
+//! tokens for `key_overlap_secs` (or until the cutover is finalized), so
+//! passports signed before the rotation keep working.
 
-use anyhow::{Context, Result, bail};
+use anyhow::{Context, Result, anyhow, bail};
 use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
 use ed25519_dalek::{Signer, SigningKey, Verifier, VerifyingKey, Signature};
+use hmac::{Hmac, Mac};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use sha2::Sha256;
 use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::sync::RwLock;
 
+use crate::metrics;
+
+/// Target of passports accepted by every node that trusts the issuer
+pub const CLUSTER_TARGET: &str = "*";
+
+/// Most revoked tokens held at once; beyond this the soonest to expire
+/// make room
+const MAX_REVOKED: usize = 100_000;
+
+/// Random bytes making each token unique
+const NONCE_LEN: usize = 16;
+
+/// Bytes of the circuit binding kept in the token
+const BINDING_LEN: usize = 16;
+
 /// Passport service configuration
 #[derive(Clone, Debug)]
 pub struct PassportConfig {
@@ -32,6 +64,14 @@ pub struct PassportConfig {
     pub private_key_path: Option<String>,
     /// Known peer public keys (node_id -> base64 pubkey)
     pub peer_pubkeys: HashMap<String, String>,
+    /// Mint this node's own passports for the whole cluster
+    pub cluster_wide: bool,
+    /// Our key before a rotation, still honoured during the overlap
+    pub previous_private_key_path: Option<String>,
+    /// Peer keys before a rotation (node_id -> base64 pubkey)
+    pub previous_peer_pubkeys: HashMap<String, String>,
+    /// How long a retired key keeps validating its issuer's tokens
+    pub key_overlap_secs: u64,
 }
 
 impl Default for PassportConfig {
@@ -41,24 +81,29 @@ impl Default for PassportConfig {
             node_id: "unknown".to_string(),
             private_key_path: None,
             peer_pubkeys: HashMap::new(),
+            cluster_wide: false,
+            previous_private_key_path: None,
+            previous_peer_pubkeys: HashMap::new(),
+            key_overlap_secs: 0,
         }
     }
 }
 
-/// A passport token for cross-node authentication
+/// Decoded contents of a signed passport token
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct PassportToken {
+pub struct PassportClaims {
     /// Target node ID this passport is valid for
     pub target: String,
     /// Expiry timestamp (unix seconds)
     pub expiry: u64,
     /// Issuing node ID
     pub issuer: String,
-    /// Circuit ID this passport was issued for
+    /// Circuit ID this passport was issued for, once checked against the
+    /// circuit presenting it (the token only carries a keyed hash of it)
     pub circuit_id: Option<String>,
 }
 
-impl PassportToken {
+impl PassportClaims {
     /// Check if the token has expired
     pub fn is_expired(&self) -> bool {
         let now = SystemTime::now()
@@ -69,6 +114,35 @@ impl PassportToken {
     }
 }
 
+/// An issuer's key from before a rotation
+#[derive(Debug)]
+struct RetiredKey {
+    key: VerifyingKey,
+    /// Unix time at which it stops validating
+    until: u64,
+    /// Tokens that only validated against this key (counted under the
+    /// read lock, so failing signatures never queue behind a writer)
+    validations: AtomicU64,
+}
+
+impl RetiredKey {
+    fn new(key: VerifyingKey, until: u64) -> Self {
+        Self { key, until, validations: AtomicU64::new(0) }
+    }
+}
+
+/// A retired key, as listed for admins
+#[derive(Clone, Debug, Serialize)]
+pub struct RetiredKeyInfo {
+    pub node_id: String,
+    /// Base64 public key
+    pub public_key: String,
+    /// Unix time at which it stops validating
+    pub until: u64,
+    /// Tokens that only validated against this key
+    pub validations: u64,
+}
+
 /// Passport service for issuing and validating tokens
 pub struct PassportService {
     /// Configuration
@@ -79,6 +153,10 @@ pub struct PassportService {
     verifying_key: Option<VerifyingKey>,
     /// Known peer public keys (node_id -> VerifyingKey)
     peer_keys: Arc<RwLock<HashMap<String, VerifyingKey>>>,
+    /// Revoked tokens (token -> unix expiry; dropped once expired)
+    revoked: Arc<RwLock<HashMap<String, u64>>>,
+    /// Keys replaced by a rotation, still valid during the overlap
+    retired_keys: Arc<RwLock<HashMap<String, RetiredKey>>>,
 }
 
 impl PassportService {
@@ -126,11 +204,30 @@ impl PassportService {
             peer_keys.insert(node_id.clone(), verifying);
         }
 
+        // Keys from before a rotation validate until the overlap ends
+        let until = unix_now() + config.key_overlap_secs;
+        let mut retired_keys = HashMap::new();
+        if let Some(ref path) = config.previous_private_key_path {
+            let key_bytes: [u8; 32] = std::fs::read(path)
+                .context("Failed to read previous private key file")?
+                .try_into()
+                .map_err(|_| anyhow!("Invalid previous private key length (expected 32 bytes)"))?;
+            let key = SigningKey::from_bytes(&key_bytes).verifying_key();
+            retired_keys.insert(config.node_id.clone(), RetiredKey::new(key, until));
+        }
+        for (node_id, pubkey_b64) in &config.previous_peer_pubkeys {
+            let key = decode_public_key(pubkey_b64)
+                .with_context(|| format!("Invalid previous public key for node {}", node_id))?;
+            retired_keys.insert(node_id.clone(), RetiredKey::new(key, until));
+        }
+
         Ok(Self {
             config,
             signing_key,
             verifying_key,
             peer_keys: Arc::new(RwLock::new(peer_keys)),
+            revoked: Arc::new(RwLock::new(HashMap::new())),
+            retired_keys: Arc::new(RwLock::new(retired_keys)),
         })
     }
 
@@ -144,6 +241,23 @@ impl PassportService {
         self.verifying_key.as_ref().map(|k| URL_SAFE_NO_PAD.encode(k.as_bytes()))
     }
 
+    /// Are this node's own passports valid cluster-wide?
+    pub fn is_cluster_wide(&self) -> bool {
+        self.config.cluster_wide
+    }
+
+    /// Issue a passport for a client of this node
+    ///
+    /// Targeted at `CLUSTER_TARGET` when `cluster_wide`, otherwise at us.
+    pub fn mint_own(&self, circuit_id: Option<String>) -> Result<String> {
+        let target = if self.config.cluster_wide {
+            CLUSTER_TARGET
+        } else {
+            &self.config.node_id
+        };
+        self.mint(target, circuit_id)
+    }
+
     /// Issue a passport token for a client to present to another node
     pub fn mint(&self, target_node: &str, circuit_id: Option<String>) -> Result<String> {
         let signing_key = self.signing_key.as_ref()
@@ -155,9 +269,20 @@ impl PassportService {
             .as_secs();
 
         let expiry = now + self.config.token_ttl_secs;
-        
+
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::Rng::fill(&mut rand::rng(), &mut nonce);
+        let nonce = URL_SAFE_NO_PAD.encode(nonce);
+        let binding = circuit_id
+            .as_deref()
+            .map(|circuit_id| circuit_binding(&nonce, circuit_id))
+            .unwrap_or_default();
+
         // Create payload to sign
-        let payload = format!("{}:{}:{}", target_node, expiry, self.config.node_id);
+        let payload = format!(
+            "{}:{}:{}:{}:{}",
+            target_node, expiry, self.config.node_id, nonce, binding
+        );
         
         // Sign the payload
         let signature = signing_key.sign(payload.as_bytes());
@@ -176,28 +301,37 @@ impl PassportService {
         Ok(URL_SAFE_NO_PAD.encode(token.as_bytes()))
     }
 
-    /// Validate a passport token presented by a client
-    pub async fn validate(&self, token: &str) -> Result<PassportToken> {
+    /// Validate a passport token presented by a client on `circuit_id`
+    ///
+    /// A token minted for a circuit is refused on any other circuit, or
+    /// when no circuit is presented.
+    pub async fn validate(&self, token: &str, circuit_id: Option<&str>) -> Result<PassportClaims> {
+        if self.is_revoked(token).await {
+            bail!("Token revoked");
+        }
+
         // Decode outer base64
         let decoded = URL_SAFE_NO_PAD.decode(token)
             .context("Invalid token encoding")?;
         let token_str = String::from_utf8(decoded)
             .context("Invalid token UTF-8")?;
 
-        // Parse: target:expiry:issuer:signature
+        // Parse: target:expiry:issuer:nonce:binding:signature
         let parts: Vec<&str> = token_str.split(':').collect();
-        if parts.len() != 4 {
-            bail!("Invalid token format (expected 4 parts, got {})", parts.len());
+        if parts.len() != 6 {
+            bail!("Invalid token format (expected 6 parts, got {})", parts.len());
         }
 
         let target = parts[0];
         let expiry: u64 = parts[1].parse()
             .context("Invalid expiry timestamp")?;
         let issuer = parts[2];
-        let sig_b64 = parts[3];
+        let nonce = parts[3];
+        let binding = parts[4];
+        let sig_b64 = parts[5];
 
-        // 1. Check if token is for us
-        if target != self.config.node_id {
+        // 1. Check if token is for us (or for any node)
+        if target != self.config.node_id && target != CLUSTER_TARGET {
             bail!("Token not for this node (target: {}, we are: {})", target, self.config.node_id);
         }
 
@@ -228,9 +362,25 @@ impl PassportService {
         sig_array.copy_from_slice(&sig_bytes);
         let signature = Signature::from_bytes(&sig_array);
 
-        let payload = format!("{}:{}:{}", target, expiry, issuer);
-        issuer_key.verify(payload.as_bytes(), &signature)
-            .context("Invalid signature")?;
+        // 5. Failing that, the issuer's key from before a rotation
+        let (payload, _) = token_str.rsplit_once(':')
+            .context("Invalid token format")?;
+        if issuer_key.verify(payload.as_bytes(), &signature).is_err()
+            && !self.verify_retired(issuer, payload.as_bytes(), &signature).await
+        {
+            bail!("Invalid signature");
+        }
+
+        // 6. Check the token was minted for the presenting circuit
+        let circuit_id = if binding.is_empty() {
+            None
+        } else {
+            let circuit_id = circuit_id.context("Token is bound to a circuit, none presented")?;
+            if !binding_matches(nonce, binding, circuit_id) {
+                bail!("Token not for this circuit");
+            }
+            Some(circuit_id.to_string())
+        };
 
         tracing::debug!(
             issuer = issuer,
@@ -239,15 +389,152 @@ impl PassportService {
             "Validated passport token"
         );
 
-        Ok(PassportToken {
+        Ok(PassportClaims {
             target: target.to_string(),
             expiry,
             issuer: issuer.to_string(),
-            circuit_id: None, // Not stored in token for privacy
+            circuit_id,
         })
     }
 
+    /// Refuse a token from now on
+    ///
+    /// The entry lives until the token's own expiry, if its signature checks
+    /// out, but never longer than one token TTL (tokens that carry no expiry,
+    /// e.g. Redis-backed passports, get the full TTL). At most `MAX_REVOKED`
+    /// tokens are held.
+    pub async fn revoke(&self, token: &str) {
+        let now = unix_now();
+        let longest = now + self.config.token_ttl_secs;
+        let expiry = match self.verified_claims(token).await {
+            Some(claims) => claims.expiry.min(longest),
+            None => longest,
+        };
+        if expiry < now {
+            return;
+        }
+
+        let mut revoked = self.revoked.write().await;
+        revoked.retain(|_, expires| *expires >= now);
+        if revoked.len() >= MAX_REVOKED && !revoked.contains_key(token) {
+            let soonest = revoked
+                .iter()
+                .min_by_key(|(_, expires)| **expires)
+                .map(|(token, _)| token.clone());
+            if let Some(soonest) = soonest {
+                revoked.remove(&soonest);
+            }
+            tracing::warn!(
+                max = MAX_REVOKED,
+                "Revoked passport set full, dropped the soonest to expire"
+            );
+        }
+        revoked.insert(token.to_string(), expiry);
+    }
+
+    /// Claims of a token signed by its issuer's current or retired key (or
+    /// our own), with target, expiry and revocation left unchecked
+    async fn verified_claims(&self, token: &str) -> Option<PassportClaims> {
+        let claims = decode_claims(token)?;
+        let (payload, signature) = token_signature(token)?;
+
+        let mut keys = Vec::new();
+        if claims.issuer == self.config.node_id {
+            keys.extend(self.verifying_key);
+        }
+        keys.extend(self.peer_keys.read().await.get(&claims.issuer).copied());
+        if let Some(retired) = self.retired_keys.read().await.get(&claims.issuer)
+            && retired.until >= unix_now()
+        {
+            keys.push(retired.key);
+        }
+
+        keys.iter()
+            .any(|key| key.verify(payload.as_bytes(), &signature).is_ok())
+            .then_some(claims)
+    }
+
+    /// Has this token been revoked?
+    pub async fn is_revoked(&self, token: &str) -> bool {
+        self.revoked.read().await.contains_key(token)
+    }
+
+    /// Sign a message for another node with our key
+    pub fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+        let signing_key = self.signing_key.as_ref()
+            .context("No signing key available")?;
+        Ok(signing_key.sign(message).to_bytes().to_vec())
+    }
+
+    /// Check a message signed by a peer, against its current key
+    pub async fn verify_peer(&self, node_id: &str, message: &[u8], signature: &[u8]) -> Result<()> {
+        let signature = Signature::from_slice(signature)
+            .context("Invalid signature encoding")?;
+        let key = self.peer_keys.read().await.get(node_id).copied()
+            .with_context(|| format!("Unknown issuer: {}", node_id))?;
+        key.verify(message, &signature)
+            .map_err(|_| anyhow!("Invalid signature from {}", node_id))
+    }
+
+    /// A peer's public key as base64, if known
+    pub async fn peer_key_b64(&self, node_id: &str) -> Option<String> {
+        self.peer_keys
+            .read()
+            .await
+            .get(node_id)
+            .map(|k| URL_SAFE_NO_PAD.encode(k.as_bytes()))
+    }
+
+    /// Check a signature against `issuer`'s retired key, if still in overlap
+    async fn verify_retired(&self, issuer: &str, payload: &[u8], signature: &Signature) -> bool {
+        let retired_keys = self.retired_keys.read().await;
+        let Some(retired) = retired_keys.get(issuer) else {
+            return false;
+        };
+        if retired.until < unix_now() || retired.key.verify(payload, signature).is_err() {
+            return false;
+        }
+
+        retired.validations.fetch_add(1, Ordering::Relaxed);
+        metrics::PASSPORT_RETIRED_KEY_VALIDATIONS
+            .with_label_values(&[issuer])
+            .inc();
+        tracing::debug!(issuer = issuer, "Validated passport token with a retired key");
+        true
+    }
+
+    /// Keys retired by a rotation and still validating
+    pub async fn retired_keys(&self) -> Vec<RetiredKeyInfo> {
+        let now = unix_now();
+        let mut retired_keys = self.retired_keys.write().await;
+        retired_keys.retain(|_, retired| retired.until >= now);
+
+        let mut keys: Vec<_> = retired_keys
+            .iter()
+            .map(|(node_id, retired)| RetiredKeyInfo {
+                node_id: node_id.clone(),
+                public_key: URL_SAFE_NO_PAD.encode(retired.key.as_bytes()),
+                until: retired.until,
+                validations: retired.validations.load(Ordering::Relaxed),
+            })
+            .collect();
+        keys.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+        keys
+    }
+
+    /// Finalize a rotation: retired keys stop validating now
+    ///
+    /// Returns the keys dropped.
+    pub async fn finalize_rotation(&self) -> Vec<RetiredKeyInfo> {
+        let keys = self.retired_keys().await;
+        self.retired_keys.write().await.clear();
+        tracing::info!(retired = keys.len(), "Passport key rotation finalized");
+        keys
+    }
+
     /// Add a peer's public key at runtime
+    ///
+    /// A different key already held for `node_id` is retired, not dropped.
     pub async fn add_peer_key(&self, node_id: &str, pubkey_b64: &str) -> Result<()> {
         let pubkey_bytes = URL_SAFE_NO_PAD.decode(pubkey_b64)
             .context("Failed to decode public key")?;
@@ -262,14 +549,95 @@ impl PassportService {
             .context("Invalid public key")?;
 
         let mut peer_keys = self.peer_keys.write().await;
-        peer_keys.insert(node_id.to_string(), verifying);
-        
-        tracing::info!(node_id = node_id, "Added peer public key");
+        let previous = peer_keys.insert(node_id.to_string(), verifying);
+
+        match previous {
+            Some(key) if key != verifying && self.config.key_overlap_secs > 0 => {
+                let until = unix_now() + self.config.key_overlap_secs;
+                self.retired_keys
+                    .write()
+                    .await
+                    .insert(node_id.to_string(), RetiredKey::new(key, until));
+                tracing::info!(
+                    node_id = node_id,
+                    overlap_secs = self.config.key_overlap_secs,
+                    "Peer public key rotated; previous key retired"
+                );
+            }
+            _ => tracing::info!(node_id = node_id, "Added peer public key"),
+        }
 
         Ok(())
     }
 }
 
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Parse a base64 ed25519 public key
+fn decode_public_key(pubkey_b64: &str) -> Result<VerifyingKey> {
+    let pubkey_bytes: [u8; 32] = URL_SAFE_NO_PAD
+        .decode(pubkey_b64)
+        .context("Failed to decode public key")?
+        .try_into()
+        .map_err(|_| anyhow!("Invalid public key length"))?;
+    VerifyingKey::from_bytes(&pubkey_bytes).context("Invalid public key")
+}
+
+/// Signed payload and signature of a signed token, if it parses as one
+fn token_signature(token: &str) -> Option<(String, Signature)> {
+    let decoded = String::from_utf8(URL_SAFE_NO_PAD.decode(token).ok()?).ok()?;
+    let (payload, sig_b64) = decoded.rsplit_once(':')?;
+    let signature = Signature::from_slice(&URL_SAFE_NO_PAD.decode(sig_b64).ok()?).ok()?;
+    Some((payload.to_string(), signature))
+}
+
+/// HMAC of `circuit_id`, keyed with a token's nonce
+fn binding_mac(nonce: &str, circuit_id: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(nonce.as_bytes())
+        .expect("HMAC takes any key length");
+    mac.update(circuit_id.as_bytes());
+    mac
+}
+
+/// A token's binding to `circuit_id`
+fn circuit_binding(nonce: &str, circuit_id: &str) -> String {
+    let mac = binding_mac(nonce, circuit_id).finalize().into_bytes();
+    URL_SAFE_NO_PAD.encode(&mac[..BINDING_LEN])
+}
+
+/// Does a token's `binding` hash `circuit_id`?
+fn binding_matches(nonce: &str, binding: &str, circuit_id: &str) -> bool {
+    let Ok(binding) = URL_SAFE_NO_PAD.decode(binding) else {
+        return false;
+    };
+    if binding.len() != BINDING_LEN {
+        return false;
+    }
+    binding_mac(nonce, circuit_id)
+        .verify_truncated_left(&binding)
+        .is_ok()
+}
+
+/// Claims of a signed token, read without checking its signature, target
+/// or expiry (`None` if it doesn't parse as one)
+pub fn decode_claims(token: &str) -> Option<PassportClaims> {
+    let decoded = URL_SAFE_NO_PAD.decode(token).ok()?;
+    let token_str = String::from_utf8(decoded).ok()?;
+    let [target, expiry, issuer, _nonce, _binding, _signature]: [&str; 6] =
+        token_str.split(':').collect::<Vec<_>>().try_into().ok()?;
+    Some(PassportClaims {
+        target: target.to_string(),
+        expiry: expiry.parse().ok()?,
+        issuer: issuer.to_string(),
+        circuit_id: None,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -299,10 +667,57 @@ mod tests {
         let token = service1.mint("node-2", Some("circuit-123".to_string())).unwrap();
 
         // Node 2 validates the token
-        let passport = service2.validate(&token).await.unwrap();
+        let passport = service2.validate(&token, Some("circuit-123")).await.unwrap();
         assert_eq!(passport.target, "node-2");
         assert_eq!(passport.issuer, "node-1");
+        assert_eq!(passport.circuit_id.as_deref(), Some("circuit-123"));
         assert!(!passport.is_expired());
+
+        // The circuit ID itself isn't in the token
+        let decoded = String::from_utf8(URL_SAFE_NO_PAD.decode(&token).unwrap()).unwrap();
+        assert!(!decoded.contains("circuit-123"));
+    }
+
+    #[tokio::test]
+    async fn test_passport_bound_to_its_circuit() {
+        let service = PassportService::new(PassportConfig {
+            node_id: "node-1".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let pubkey = service.public_key_b64().unwrap();
+        service.add_peer_key("node-1", &pubkey).await.unwrap();
+
+        let token = service.mint("node-1", Some("circuit-a".to_string())).unwrap();
+        assert!(service.validate(&token, Some("circuit-b")).await.is_err());
+        assert!(service.validate(&token, None).await.is_err());
+        assert!(service.validate(&token, Some("circuit-a")).await.is_ok());
+
+        // Unbound tokens validate on any circuit
+        let token = service.mint("node-1", None).unwrap();
+        let passport = service.validate(&token, Some("circuit-b")).await.unwrap();
+        assert_eq!(passport.circuit_id, None);
+    }
+
+    #[tokio::test]
+    async fn test_passports_unique_within_a_second() {
+        let service = PassportService::new(PassportConfig {
+            node_id: "node-1".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+        let pubkey = service.public_key_b64().unwrap();
+        service.add_peer_key("node-1", &pubkey).await.unwrap();
+
+        let circuit = Some("circuit-a".to_string());
+        let first = service.mint("node-1", circuit.clone()).unwrap();
+        let second = service.mint("node-1", circuit).unwrap();
+        assert_ne!(first, second);
+
+        // Revoking one leaves the other valid
+        service.revoke(&first).await;
+        assert!(service.validate(&first, Some("circuit-a")).await.is_err());
+        assert!(service.validate(&second, Some("circuit-a")).await.is_ok());
     }
 
     #[tokio::test]
@@ -316,7 +731,114 @@ mod tests {
 
         // Mint for node-2, but try to validate on node-1
         let token = service.mint("node-2", None).unwrap();
-        let result = service.validate(&token).await;
+        let result = service.validate(&token, None).await;
         assert!(result.is_err());
+
+        // Its claims can still be read
+        let claims = decode_claims(&token).unwrap();
+        assert_eq!(claims.target, "node-2");
+        assert_eq!(claims.issuer, "node-1");
+        assert!(decode_claims("bm90LWEtdG9rZW4").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_cluster_passport_needs_trusted_issuer() {
+        let service1 = PassportService::new(PassportConfig {
+            node_id: "node-1".to_string(),
+            cluster_wide: true,
+            ..Default::default()
+        })
+        .unwrap();
+        let service2 = PassportService::new(PassportConfig {
+            node_id: "node-2".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let token = service1.mint_own(None).unwrap();
+        assert!(service2.validate(&token, None).await.is_err());
+
+        let pubkey1 = service1.public_key_b64().unwrap();
+        service2.add_peer_key("node-1", &pubkey1).await.unwrap();
+        assert_eq!(service2.peer_key_b64("node-1").await, Some(pubkey1));
+        let passport = service2.validate(&token, None).await.unwrap();
+        assert_eq!(passport.target, CLUSTER_TARGET);
+        assert_eq!(passport.issuer, "node-1");
+
+        // Node 2's own passports stay local
+        let pubkey2 = service2.public_key_b64().unwrap();
+        service1.add_peer_key("node-2", &pubkey2).await.unwrap();
+        let token = service2.mint_own(None).unwrap();
+        assert!(service1.validate(&token, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_revoked_passport_rejected() {
+        let config = PassportConfig {
+            node_id: "node-1".to_string(),
+            token_ttl_secs: 30,
+            ..Default::default()
+        };
+        let service = PassportService::new(config).unwrap();
+        let pubkey = service.public_key_b64().unwrap();
+        service.add_peer_key("node-1", &pubkey).await.unwrap();
+
+        let token = service.mint("node-1", None).unwrap();
+        assert!(service.validate(&token, None).await.is_ok());
+
+        service.revoke(&token).await;
+        assert!(service.is_revoked(&token).await);
+        assert!(service.validate(&token, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_revocation_kept_at_most_one_ttl() {
+        let service = PassportService::new(PassportConfig {
+            node_id: "node-1".to_string(),
+            token_ttl_secs: 30,
+            ..Default::default()
+        })
+        .unwrap();
+        let longest = unix_now() + 30;
+
+        // A forged far-future expiry isn't believed
+        let forged = URL_SAFE_NO_PAD.encode(format!("*:{}:node-1:AAAA::AAAA", u64::MAX));
+        service.revoke(&forged).await;
+        assert!(service.revoked.read().await[&forged] <= longest);
+
+        let token = service.mint("node-1", None).unwrap();
+        service.revoke(&token).await;
+        assert!(service.revoked.read().await[&token] <= longest);
+        assert!(service.verified_claims(&token).await.is_some());
+        assert!(service.verified_claims(&forged).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rotated_key_validates_until_finalized() {
+        let node = |node_id: &str| PassportConfig {
+            node_id: node_id.to_string(),
+            key_overlap_secs: 3600,
+            ..Default::default()
+        };
+        let old = PassportService::new(node("node-1")).unwrap();
+        let new = PassportService::new(node("node-1")).unwrap();
+        let service = PassportService::new(node("node-2")).unwrap();
+
+        service.add_peer_key("node-1", &old.public_key_b64().unwrap()).await.unwrap();
+        let old_token = old.mint("node-2", None).unwrap();
+        assert!(service.retired_keys().await.is_empty());
+
+        // Rotated: tokens from both keys validate, the old ones counted
+        service.add_peer_key("node-1", &new.public_key_b64().unwrap()).await.unwrap();
+        assert!(service.validate(&new.mint("node-2", None).unwrap(), None).await.is_ok());
+        assert!(service.validate(&old_token, None).await.is_ok());
+        let retired = service.retired_keys().await;
+        assert_eq!(retired.len(), 1);
+        assert_eq!(retired[0].public_key, old.public_key_b64().unwrap());
+        assert_eq!(retired[0].validations, 1);
+
+        assert_eq!(service.finalize_rotation().await.len(), 1);
+        assert!(service.validate(&old_token, None).await.is_err());
+        assert!(service.validate(&new.mint("node-2", None).unwrap(), None).await.is_ok());
     }
 }
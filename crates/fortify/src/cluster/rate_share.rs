@@ -0,0 +1,402 @@
+//! Cluster-wide rate limiting: request counts shared over gossip.
+//!
+//! Each node counts a circuit's requests in its own Redis, so nodes with a
+//! Redis each would give a circuit spreading its requests over them the
+//! budget once per node. With `rate_limit.cluster.enabled` every node also
+//! counts requests per circuit in windows aligned on the minute and gossips
+//! the counts of its busiest circuits every `sync_interval_ms`. A request is
+//! then refused once its Redis count plus an estimate of the circuit's
+//! requests on the peers passes `max_requests_per_minute`.
+//!
+//! The estimate is a sliding window: a peer's count for this window, plus
+//! its count for the previous one weighted by how much of that window is
+//! still within the last minute. Counts are gossiped as totals, not deltas,
+//! so a lost datagram only delays the estimate. It is approximate by design:
+//! up to a sync interval behind, and a circuit under `min_requests` on a
+//! node isn't reported by that node.
+//!
+//! Counts are signed with the sending node's passport key, and only taken
+//! in from peers with a known key (see `gossip`).
+//!
+//! Nodes sharing one Redis already share counts; don't turn this on there,
+//! or requests are counted twice.
+
+use cerberus_common::{BoundedCache, CircuitId, constants::RATE_LIMIT_WINDOW_SECS};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use super::gossip::{GossipPacket, RateCountsNotice};
+use crate::state::AppState;
+
+/// Peers whose counts are kept per circuit
+///
+/// Only peers with a known key get their counts in; this bounds the map
+/// however many node IDs that lets through.
+const MAX_PEERS_PER_CIRCUIT: usize = 64;
+
+/// Cluster rate limit settings (`[rate_limit.cluster]` in fortify.toml)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClusterRateLimitConfig {
+    /// Count requests made on peers too (needs `cluster_enabled`)
+    pub enabled: bool,
+    /// How often counts are gossiped
+    pub sync_interval_ms: u64,
+    /// Busiest circuits reported per sync
+    pub max_circuits_per_sync: usize,
+    /// Requests in the window before a circuit is reported
+    pub min_requests: u32,
+    /// Circuits tracked at most, locally and from peers each
+    pub max_circuits: usize,
+}
+
+impl Default for ClusterRateLimitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sync_interval_ms: 1000,
+            max_circuits_per_sync: 256,
+            min_requests: 2,
+            max_circuits: 100_000,
+        }
+    }
+}
+
+/// Aligned window `now` (Unix seconds) falls in, and how far into it
+fn window_at(now: u64) -> (u64, f64) {
+    let window = RATE_LIMIT_WINDOW_SECS.secs();
+    (now / window, (now % window) as f64 / window as f64)
+}
+
+/// A circuit's requests in the current and previous aligned windows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct WindowCounts {
+    window: u64,
+    current: u32,
+    previous: u32,
+}
+
+impl WindowCounts {
+    /// Move on to `window`, if it's later
+    fn advance(&mut self, window: u64) {
+        if window <= self.window {
+            return;
+        }
+        self.previous = if window == self.window + 1 {
+            self.current
+        } else {
+            0
+        };
+        self.current = 0;
+        self.window = window;
+    }
+
+    /// Requests over the last minute, `elapsed` of the way into `window`
+    fn estimate(&self, window: u64, elapsed: f64) -> u32 {
+        let (current, previous) = match window.checked_sub(self.window) {
+            Some(0) => (self.current, self.previous),
+            Some(1) => (0, self.current),
+            _ => (0, 0),
+        };
+        current + (previous as f64 * (1.0 - elapsed)).round() as u32
+    }
+}
+
+/// Request counts of this node and its peers
+pub struct RateShare {
+    config: ClusterRateLimitConfig,
+    node_id: String,
+    local: Mutex<BoundedCache<CircuitId, WindowCounts>>,
+    /// Circuit -> peer node ID -> its counts
+    peers: Mutex<BoundedCache<CircuitId, HashMap<String, WindowCounts>>>,
+}
+
+impl RateShare {
+    pub fn new(config: ClusterRateLimitConfig, node_id: String) -> Self {
+        Self {
+            local: Mutex::new(BoundedCache::new(config.max_circuits)),
+            peers: Mutex::new(BoundedCache::new(config.max_circuits)),
+            config,
+            node_id,
+        }
+    }
+
+    /// Count a request from `circuit_id` made here at `now` (Unix seconds)
+    pub fn record_local(&self, circuit_id: &CircuitId, now: u64) {
+        let (window, _) = window_at(now);
+        let mut local = self.local.lock().unwrap_or_else(|e| e.into_inner());
+        match local.get_mut(circuit_id) {
+            Some(counts) => {
+                counts.advance(window);
+                counts.current = counts.current.saturating_add(1);
+            }
+            None => {
+                local.insert(
+                    circuit_id.clone(),
+                    WindowCounts {
+                        window,
+                        current: 1,
+                        previous: 0,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Requests `circuit_id` made on peers over the last minute, as of `now`
+    pub fn elsewhere(&self, circuit_id: &CircuitId, now: u64) -> u32 {
+        let (window, elapsed) = window_at(now);
+        let mut peers = self.peers.lock().unwrap_or_else(|e| e.into_inner());
+        peers.get(circuit_id).map_or(0, |by_node| {
+            by_node
+                .values()
+                .map(|counts| counts.estimate(window, elapsed))
+                .fold(0u32, u32::saturating_add)
+        })
+    }
+
+    /// Take in counts gossiped by a peer
+    ///
+    /// Only this window's and the previous one's are kept; a count lower
+    /// than one already heard for the same window (a reordered datagram)
+    /// is ignored.
+    pub fn apply(&self, notice: &RateCountsNotice, now: u64) {
+        if notice.node_id == self.node_id {
+            return;
+        }
+        let (window, _) = window_at(now);
+        if notice.window > window || notice.window + 1 < window {
+            return;
+        }
+        let mut peers = self.peers.lock().unwrap_or_else(|e| e.into_inner());
+        for (circuit_id, count) in &notice.counts {
+            let Ok(circuit_id) = circuit_id.parse::<CircuitId>() else {
+                continue;
+            };
+            if peers.get_mut(&circuit_id).is_none() {
+                peers.insert(circuit_id.clone(), HashMap::new());
+            }
+            let Some(by_node) = peers.get_mut(&circuit_id) else {
+                continue;
+            };
+            if !by_node.contains_key(&notice.node_id) && by_node.len() >= MAX_PEERS_PER_CIRCUIT {
+                continue;
+            }
+            let counts = by_node.entry(notice.node_id.clone()).or_default();
+            counts.advance(notice.window);
+            if counts.window == notice.window {
+                counts.current = counts.current.max(*count);
+            } else if counts.window == notice.window + 1 {
+                counts.previous = counts.previous.max(*count);
+            }
+        }
+    }
+
+    /// This window's counts of the busiest circuits, packed into notices
+    /// that each fit a gossip datagram
+    pub fn reports(&self, now: u64) -> Vec<RateCountsNotice> {
+        let (window, _) = window_at(now);
+        let mut counts: Vec<(String, u32)> = {
+            let local = self.local.lock().unwrap_or_else(|e| e.into_inner());
+            local
+                .iter()
+                .filter(|(_, counts)| {
+                    counts.window == window && counts.current >= self.config.min_requests
+                })
+                .map(|(circuit_id, counts)| (circuit_id.to_string(), counts.current))
+                .collect()
+        };
+        counts.sort_unstable_by_key(|&(_, count)| std::cmp::Reverse(count));
+        counts.truncate(self.config.max_circuits_per_sync);
+        pack(&self.node_id, window, counts)
+    }
+}
+
+/// Split `counts` into notices of at most `GossipPacket::MAX_SIZE` bytes
+fn pack(node_id: &str, window: u64, counts: Vec<(String, u32)>) -> Vec<RateCountsNotice> {
+    // `{"node_id":"..","window":..,"counts":[],"signature":".."}`, and
+    // `["..",4294967295],`
+    let overhead = node_id.len() + 80 + RateCountsNotice::SIGNATURE_LEN;
+    let entry_len = |circuit_id: &str| circuit_id.len() + 16;
+
+    let mut notices = Vec::new();
+    let mut batch = Vec::new();
+    let mut size = overhead;
+    for (circuit_id, count) in counts {
+        let len = entry_len(&circuit_id);
+        if overhead + len > GossipPacket::MAX_SIZE {
+            continue;
+        }
+        if size + len > GossipPacket::MAX_SIZE {
+            notices.push(RateCountsNotice {
+                node_id: node_id.to_string(),
+                window,
+                counts: std::mem::take(&mut batch),
+                signature: String::new(),
+            });
+            size = overhead;
+        }
+        size += len;
+        batch.push((circuit_id, count));
+    }
+    if !batch.is_empty() {
+        notices.push(RateCountsNotice {
+            node_id: node_id.to_string(),
+            window,
+            counts: batch,
+            signature: String::new(),
+        });
+    }
+    notices
+}
+
+/// Gossip this node's counts every `sync_interval_ms` (needs `rate_share`
+/// and gossip, i.e. `cluster_enabled`)
+pub async fn rate_share_worker(state: AppState, mut shutdown: broadcast::Receiver<()>) {
+    let (Some(share), Some(gossip)) = (state.rate_share.clone(), state.gossip.clone()) else {
+        return;
+    };
+    let interval = Duration::from_millis(state.config.rate_limit.cluster.sync_interval_ms);
+
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {
+                let now = chrono::Utc::now().timestamp() as u64;
+                let notices = share.reports(now);
+                if notices.is_empty() {
+                    continue;
+                }
+                if let Err(e) = gossip.broadcast_rate_counts(notices).await {
+                    tracing::warn!(error = %e, "Failed to gossip rate limit counts");
+                }
+            }
+            _ = shutdown.recv() => break,
+        }
+    }
+    tracing::info!("Rate limit count sharing shutting down");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn share(node_id: &str) -> RateShare {
+        RateShare::new(
+            ClusterRateLimitConfig {
+                enabled: true,
+                ..Default::default()
+            },
+            node_id.to_string(),
+        )
+    }
+
+    #[test]
+    fn test_counts_merge_into_estimate() {
+        let circuit: CircuitId = "fc00::1".parse().unwrap();
+        let node_a = share("node-a");
+        let node_b = share("node-b");
+        // Ten seconds into a window
+        let now = 1_700_000_050;
+        assert_eq!(window_at(now).0, 28_333_334);
+
+        for _ in 0..30 {
+            node_a.record_local(&circuit, now);
+        }
+        node_a.record_local(&"fc00::2".parse().unwrap(), now);
+        let reports = node_a.reports(now);
+        // Only circuits with `min_requests` are reported
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].counts, [("fc00::1".to_string(), 30)]);
+
+        for notice in &reports {
+            node_b.apply(notice, now);
+            // A node ignores its own counts
+            node_a.apply(notice, now);
+        }
+        assert_eq!(node_b.elsewhere(&circuit, now), 30);
+        assert_eq!(node_a.elsewhere(&circuit, now), 0);
+
+        // A reordered, older total doesn't lower the count
+        let stale = RateCountsNotice {
+            counts: vec![("fc00::1".to_string(), 12)],
+            ..reports[0].clone()
+        };
+        node_b.apply(&stale, now);
+        assert_eq!(node_b.elsewhere(&circuit, now), 30);
+
+        // Ten seconds into the next window, five sixths of this one count
+        assert_eq!(node_b.elsewhere(&circuit, now + 60), 25);
+        // Two windows on, it's forgotten
+        assert_eq!(node_b.elsewhere(&circuit, now + 120), 0);
+    }
+
+    #[test]
+    fn test_stale_windows_ignored() {
+        let circuit: CircuitId = "fc00::1".parse().unwrap();
+        let node_b = share("node-b");
+        let now = 1_700_000_050;
+        let (window, _) = window_at(now);
+
+        let notice = |window| RateCountsNotice {
+            node_id: "node-a".to_string(),
+            window,
+            counts: vec![("fc00::1".to_string(), 40)],
+            signature: String::new(),
+        };
+        node_b.apply(&notice(window - 2), now);
+        node_b.apply(&notice(window + 1), now);
+        assert_eq!(node_b.elsewhere(&circuit, now), 0);
+
+        // The previous window's total is weighted
+        node_b.apply(&notice(window - 1), now);
+        assert_eq!(node_b.elsewhere(&circuit, now), 33);
+    }
+
+    #[test]
+    fn test_pack_fits_datagrams() {
+        let counts: Vec<(String, u32)> = (0..200)
+            .map(|i| (format!("fc00:dead:beef:4dad::{:x}", i), u32::MAX))
+            .collect();
+        let notices = pack("node-with-a-long-name", 7, counts);
+        assert!(notices.len() > 1);
+        assert_eq!(notices.iter().map(|n| n.counts.len()).sum::<usize>(), 200);
+        for notice in &notices {
+            let signed = RateCountsNotice {
+                signature: "x".repeat(RateCountsNotice::SIGNATURE_LEN),
+                ..notice.clone()
+            };
+            let bytes = serde_json::to_vec(&signed).unwrap();
+            assert!(
+                bytes.len() <= GossipPacket::MAX_SIZE,
+                "{} bytes",
+                bytes.len()
+            );
+        }
+        assert!(pack("node-a", 7, Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn test_peers_per_circuit_bounded() {
+        let circuit: CircuitId = "fc00::1".parse().unwrap();
+        let node_b = share("node-b");
+        let now = 1_700_000_050;
+        let (window, _) = window_at(now);
+
+        for i in 0..MAX_PEERS_PER_CIRCUIT + 10 {
+            let notice = RateCountsNotice {
+                node_id: format!("node-{}", i),
+                window,
+                counts: vec![("fc00::1".to_string(), 1)],
+                signature: String::new(),
+            };
+            node_b.apply(&notice, now);
+        }
+        assert_eq!(
+            node_b.elsewhere(&circuit, now),
+            MAX_PEERS_PER_CIRCUIT as u32
+        );
+    }
+}
@@ -0,0 +1,125 @@
+//! Threat level sync: follow changes made by other nodes as they happen.
+//!
+//! `set_threat_level` writes the shared key and publishes the new level on
+//! `cerberus:threat_level_changed`. Every node subscribes to that channel
+//! and adopts what it hears. Scripts that just `SET cerberus:threat_level`
+//! are picked up too when Redis keyspace notifications are on
+//! (`notify-keyspace-events K$`): the key is re-read on each notification.
+//!
+//! The leader election round still re-reads the key periodically, so a
+//! message missed while the subscription was down is caught up on there
+//! as well as on resubscribing.
+
+use anyhow::{Result, bail};
+use cerberus_common::{ThreatLevel, redis_keys};
+use futures::StreamExt;
+use redis::AsyncCommands;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use crate::state::AppState;
+
+/// Wait before resubscribing after the connection drops
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(5);
+
+/// What a pub/sub message tells us
+#[derive(Debug, PartialEq, Eq)]
+enum Update {
+    /// A node announced its new level
+    Level(ThreatLevel),
+    /// The key changed (keyspace notification); read it back
+    Reload,
+}
+
+impl Update {
+    fn from_message(channel: &str, payload: &[u8]) -> Self {
+        if channel == redis_keys::threat_level_channel()
+            && let Some(level) = std::str::from_utf8(payload)
+                .ok()
+                .and_then(|s| s.trim().parse::<u8>().ok())
+        {
+            return Self::Level(ThreatLevel::new(level));
+        }
+        Self::Reload
+    }
+}
+
+/// Keyspace notifications for the threat level key, in any database
+fn keyspace_pattern() -> String {
+    format!("__keyspace@*__:{}", redis_keys::threat_level().as_str())
+}
+
+/// Keep the local threat level in step with the shared one until shutdown
+pub async fn threat_sync_worker(state: AppState, mut shutdown: broadcast::Receiver<()>) {
+    loop {
+        tokio::select! {
+            result = follow(&state) => {
+                if let Err(e) = result {
+                    tracing::warn!(error = %e, "Threat level subscription lost");
+                }
+            }
+            _ = shutdown.recv() => break,
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(RESUBSCRIBE_DELAY) => {}
+            _ = shutdown.recv() => break,
+        }
+    }
+    tracing::info!("Threat level sync shutting down");
+}
+
+/// Subscribe and apply updates until the connection drops
+async fn follow(state: &AppState) -> Result<()> {
+    let mut pubsub = state.redis_client.get_async_pubsub().await?;
+    pubsub.subscribe(redis_keys::threat_level_channel()).await?;
+    pubsub.psubscribe(keyspace_pattern()).await?;
+    tracing::debug!("Subscribed to threat level changes");
+
+    // Anything changed while we weren't listening
+    reload(state).await?;
+
+    let mut messages = pubsub.on_message();
+    while let Some(message) = messages.next().await {
+        match Update::from_message(message.get_channel_name(), message.get_payload_bytes()) {
+            Update::Level(level) => state.adopt_threat_level(level).await,
+            Update::Reload => reload(state).await?,
+        }
+    }
+    bail!("subscription closed")
+}
+
+/// Adopt the level stored in Redis
+async fn reload(state: &AppState) -> Result<()> {
+    let Some(mut redis) = state.redis() else {
+        return Ok(());
+    };
+    let level: Option<u8> = redis.get(redis_keys::threat_level()).await?;
+    if let Some(level) = level {
+        state.adopt_threat_level(ThreatLevel::new(level)).await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_updates_from_messages() {
+        let channel = redis_keys::threat_level_channel();
+        assert_eq!(
+            Update::from_message(channel, b"7"),
+            Update::Level(ThreatLevel::new(7))
+        );
+        // Clamped like any other level
+        assert_eq!(
+            Update::from_message(channel, b"42"),
+            Update::Level(ThreatLevel::MAX)
+        );
+        assert_eq!(Update::from_message(channel, b"high"), Update::Reload);
+        assert_eq!(
+            Update::from_message("__keyspace@0__:cerberus:threat_level", b"set"),
+            Update::Reload
+        );
+    }
+}
@@ -0,0 +1,1511 @@
+//! Configuration management for Fortify.
+
+use anyhow::{Context, Result, bail};
+use config::Source;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::abandonment::AbandonmentConfig;
+use crate::access_log::AccessLogConfig;
+use crate::admin_access::AdminConfig;
+use crate::allowlist::AllowlistConfig;
+use crate::backup::BackupConfig;
+use crate::captcha::{ShadowConfig, StarvationConfig};
+use crate::challenge_stats::ChallengeStatsConfig;
+use crate::cluster::{
+    AmmoSharingConfig, ClusterRateLimitConfig, ElectionConfig, FederationConfig, GossipConfig,
+};
+use crate::experiments::ExperimentsConfig;
+use crate::flags::FlagsConfig;
+use crate::grpc::GrpcConfig;
+use crate::haproxy::HaproxyConfig;
+use crate::journey::JourneyConfig;
+use crate::keyspace::KeyspaceConfig;
+use crate::maintenance::MaintenanceConfig;
+use crate::redact::PrivacyConfig;
+use crate::rules::RulesConfig;
+use crate::sampling::SamplingConfig;
+use crate::slo::SloConfig;
+use crate::trusted_proxy::TrustedProxyConfig;
+use crate::verify_queue::VerifyQueueConfig;
+use crate::webhook::WebhookConfig;
+use cerberus_common::constants::{
+    BAN_DURATION_SECS, CAPTCHA_TTL_SECS, DEFAULT_LISTEN_ADDR, DEFAULT_PASSPORT_TTL_SECS,
+    DEFAULT_REDIS_URL, SOFT_LOCK_DURATION_SECS,
+};
+use cerberus_common::{CaptchaDifficulty, DifficultyProfiles, TtlSecs};
+
+mod validate;
+
+pub use validate::{ConfigIssue, report};
+
+/// Application configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppConfig {
+    /// Redis connection URL
+    #[serde(default = "default_redis_url")]
+    pub redis_url: String,
+
+    /// HTTP listen address (`IP:port`, or `unix:/path` for a unix socket)
+    #[serde(default = "default_listen_addr")]
+    pub listen_addr: String,
+
+    /// Permissions for a unix socket listener
+    #[serde(default = "default_listen_socket_mode")]
+    pub listen_socket_mode: u32,
+
+    /// Initial threat level (0-10)
+    #[serde(default = "default_threat_level")]
+    pub initial_threat_level: u8,
+
+    /// Enable cluster mode (gossip with the `[gossip]` peers)
+    #[serde(default)]
+    pub cluster_enabled: bool,
+
+    /// This node's unique ID (auto-generated if not set)
+    #[serde(default = "generate_node_id")]
+    pub node_id: String,
+
+    /// Gossip between cluster nodes (used when `cluster_enabled`)
+    #[serde(default)]
+    pub gossip: GossipConfig,
+
+    /// Leader election among cluster nodes
+    #[serde(default)]
+    pub election: ElectionConfig,
+
+    /// Passports honoured across cluster nodes
+    #[serde(default)]
+    pub federation: FederationConfig,
+
+    /// CAPTCHA batches from peers with full pools when ours runs low
+    #[serde(default)]
+    pub ammo_sharing: AmmoSharingConfig,
+
+    /// HAProxy runtime API (stick tables, draining unhealthy peers)
+    #[serde(default)]
+    pub haproxy: HaproxyConfig,
+
+    /// CAPTCHA configuration
+    #[serde(default)]
+    pub captcha: CaptchaConfig,
+
+    /// Rate limiting configuration
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+
+    /// CAPTCHA farm detection configuration
+    #[serde(default)]
+    pub farm_detection: FarmDetectionConfig,
+
+    /// Attack signature rules (auto-mitigation)
+    #[serde(default)]
+    pub rules: RulesConfig,
+
+    /// Signed webhook alerts for critical events
+    #[serde(default)]
+    pub webhooks: WebhookConfig,
+
+    /// VIP circuits (promotion, long-lived passports, fast path)
+    #[serde(default)]
+    pub vip: VipConfig,
+
+    /// Security response headers and CORS, per route group
+    #[serde(default)]
+    pub security_headers: SecurityHeadersConfig,
+
+    /// Redis memory/latency guard and degradation mode
+    #[serde(default)]
+    pub degradation: DegradationConfig,
+
+    /// gzip responses for clients that accept it
+    #[serde(default)]
+    pub compression: CompressionConfig,
+
+    /// Ammo Box fill before `/ready` reports ready
+    #[serde(default)]
+    pub warmup: WarmupConfig,
+
+    /// Alert when the Ammo Box stays low
+    #[serde(default)]
+    pub ammo_starvation: StarvationConfig,
+
+    /// Observe-only mode (record decisions without enforcing them)
+    #[serde(default)]
+    pub enforcement: EnforcementConfig,
+
+    /// Capture of a fraction of `/validate` requests for analysis
+    #[serde(default)]
+    pub sampling: SamplingConfig,
+
+    /// Availability and latency objectives for `/validate`
+    #[serde(default)]
+    pub slo: SloConfig,
+
+    /// Periodic count of Redis keys by family (and keys that never expire)
+    #[serde(default)]
+    pub keyspace: KeyspaceConfig,
+
+    /// JSON line per visitor request, to a file and/or a Redis stream
+    #[serde(default)]
+    pub access_log: AccessLogConfig,
+
+    /// How identifiers and secrets are written to logs
+    #[serde(default)]
+    pub privacy: PrivacyConfig,
+
+    /// Page served while maintenance mode is on
+    #[serde(default)]
+    pub maintenance: MaintenanceConfig,
+
+    /// First-party bots that skip the CAPTCHA with a secret header
+    #[serde(default)]
+    pub allowlist: AllowlistConfig,
+
+    /// gRPC control plane (requires the `grpc` feature)
+    #[serde(default)]
+    pub grpc: GrpcConfig,
+
+    /// OpenTelemetry trace export (requires the `otel` feature)
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+
+    /// TLS on the listener, with client certificates for control routes
+    #[serde(default)]
+    pub tls: TlsConfig,
+
+    /// Hops allowed to set proxy-only headers such as `X-Circuit-Id`
+    #[serde(default)]
+    pub trusted_proxies: TrustedProxyConfig,
+
+    /// Admin router mount point and allowed peers
+    #[serde(default)]
+    pub admin: AdminConfig,
+
+    /// Fair queueing of answers to `/verify`
+    #[serde(default)]
+    pub verify_queue: VerifyQueueConfig,
+
+    /// A/B experiments on challenge providers and difficulty
+    #[serde(default)]
+    pub experiments: ExperimentsConfig,
+
+    /// Challenges left unanswered, and de-escalating when they pile up
+    #[serde(default)]
+    pub abandonment: AbandonmentConfig,
+
+    /// Gate-to-first-request timings of passports
+    #[serde(default)]
+    pub journeys: JourneyConfig,
+
+    /// Challenge outcomes per difficulty
+    #[serde(default)]
+    pub challenge_stats: ChallengeStatsConfig,
+
+    /// Signed on-disk snapshots of bans, VIPs, threat level and allowlist
+    #[serde(default)]
+    pub backup: BackupConfig,
+
+    /// Runtime switches for risky behaviors (overridable from the admin API)
+    #[serde(default)]
+    pub flags: FlagsConfig,
+}
+
+/// CAPTCHA-specific configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptchaConfig {
+    /// Path to font file for CAPTCHA text (reserved for image-based CAPTCHA)
+    #[serde(default = "default_font_path")]
+    #[allow(dead_code)]
+    pub font_path: String,
+
+    /// Passport token validity
+    #[serde(default = "default_passport_ttl")]
+    pub passport_ttl_secs: TtlSecs,
+
+    /// Per-threat-level passport TTLs, lifetime cap and renewal
+    #[serde(default)]
+    pub passport: PassportPolicy,
+
+    /// Per-threat-level JavaScript proof-of-work ahead of the CAPTCHA
+    #[serde(default)]
+    pub flow: ChallengeFlow,
+
+    /// Batching of challenge writes to Redis
+    #[serde(default)]
+    pub write_batch: WriteBatchConfig,
+
+    /// Challenge validity
+    #[serde(default = "default_challenge_ttl")]
+    pub challenge_ttl_secs: TtlSecs,
+
+    /// Challenge refreshes per minute before they count against reputation
+    #[serde(default = "default_max_refreshes")]
+    pub max_refreshes_per_minute: u32,
+
+    /// Reputation penalty per refresh beyond the limit
+    #[serde(default = "default_refresh_penalty")]
+    pub refresh_penalty: i32,
+
+    /// Max unanswered challenges per circuit (0 = unlimited)
+    #[serde(default = "default_max_outstanding")]
+    pub max_outstanding_per_circuit: u32,
+
+    /// Max challenges issued per second across all circuits (0 = unlimited)
+    #[serde(default = "default_max_issued_per_second")]
+    pub max_issued_per_second: u32,
+
+    /// Lifetime of a no-JS gate session (attempts, page to return to)
+    #[serde(default = "default_gate_session_ttl")]
+    pub gate_session_ttl_secs: TtlSecs,
+
+    /// Challenge image encoding
+    #[serde(default)]
+    pub image_format: ImageFormat,
+
+    /// Serve images from `/challenge/{id}/image` instead of inlining them
+    #[serde(default)]
+    pub serve_images: bool,
+
+    /// Challenge provider per difficulty
+    #[serde(default)]
+    pub providers: ProviderSelection,
+
+    /// Candidate provider tried out on a sample of visitors
+    #[serde(default)]
+    pub shadow: ShadowConfig,
+
+    /// Answer length, noise, distortion, grid and timeout per difficulty
+    #[serde(default)]
+    pub difficulty: DifficultyProfiles,
+}
+
+impl Default for CaptchaConfig {
+    fn default() -> Self {
+        Self {
+            font_path: default_font_path(),
+            passport_ttl_secs: default_passport_ttl(),
+            passport: PassportPolicy::default(),
+            flow: ChallengeFlow::default(),
+            write_batch: WriteBatchConfig::default(),
+            challenge_ttl_secs: default_challenge_ttl(),
+            max_refreshes_per_minute: default_max_refreshes(),
+            refresh_penalty: default_refresh_penalty(),
+            max_outstanding_per_circuit: default_max_outstanding(),
+            max_issued_per_second: default_max_issued_per_second(),
+            gate_session_ttl_secs: default_gate_session_ttl(),
+            image_format: ImageFormat::default(),
+            serve_images: false,
+            providers: ProviderSelection::default(),
+            shadow: ShadowConfig::default(),
+            difficulty: DifficultyProfiles::default(),
+        }
+    }
+}
+
+/// Challenge image encoding (`captcha.image_format`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    /// Inline SVG of stroked paths
+    #[default]
+    Svg,
+    Png,
+    Webp,
+    /// Per request: WebP or PNG if the `Accept` header lists it, else SVG
+    Auto,
+}
+
+impl ImageFormat {
+    /// Concrete format for a request carrying this `Accept` header
+    pub fn negotiate(self, accept: Option<&str>) -> Self {
+        if self != Self::Auto {
+            return self;
+        }
+        let accepts = |mime: &str| {
+            accept.is_some_and(|accept| {
+                accept
+                    .split(',')
+                    .filter_map(|range| range.split(';').next())
+                    .any(|range| range.trim().eq_ignore_ascii_case(mime))
+            })
+        };
+        if accepts("image/webp") {
+            Self::Webp
+        } else if accepts("image/png") {
+            Self::Png
+        } else {
+            Self::Svg
+        }
+    }
+}
+
+/// Challenge provider used at each difficulty (`[captcha.providers]`)
+///
+/// Difficulty follows the threat level: easy 0-3, medium 4-6, hard 7-9,
+/// extreme 10.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderSelection {
+    #[serde(default = "default_provider")]
+    pub easy: String,
+    #[serde(default = "default_provider")]
+    pub medium: String,
+    #[serde(default = "default_provider")]
+    pub hard: String,
+    #[serde(default = "default_provider")]
+    pub extreme: String,
+}
+
+impl ProviderSelection {
+    /// Provider name for a difficulty
+    pub fn for_difficulty(&self, difficulty: CaptchaDifficulty) -> &str {
+        match difficulty {
+            CaptchaDifficulty::Easy => &self.easy,
+            CaptchaDifficulty::Medium => &self.medium,
+            CaptchaDifficulty::Hard => &self.hard,
+            CaptchaDifficulty::Extreme => &self.extreme,
+        }
+    }
+}
+
+impl Default for ProviderSelection {
+    fn default() -> Self {
+        Self {
+            easy: default_provider(),
+            medium: default_provider(),
+            hard: default_provider(),
+            extreme: default_provider(),
+        }
+    }
+}
+
+/// Passport lifetime (`[captcha.passport]`)
+///
+/// Applies to Redis-backed passports; signed passports issued while Redis
+/// is offline always last `captcha.passport_ttl_secs`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PassportPolicy {
+    /// TTL per difficulty (threat levels as for `[captcha.providers]`);
+    /// unset ones use `captcha.passport_ttl_secs`
+    #[serde(default)]
+    pub easy_ttl_secs: Option<TtlSecs>,
+    #[serde(default)]
+    pub medium_ttl_secs: Option<TtlSecs>,
+    #[serde(default)]
+    pub hard_ttl_secs: Option<TtlSecs>,
+    #[serde(default)]
+    pub extreme_ttl_secs: Option<TtlSecs>,
+
+    /// Age at which a passport dies however often it was renewed (0 = never)
+    #[serde(default = "default_passport_max_lifetime")]
+    pub max_lifetime_secs: TtlSecs,
+
+    /// Whether validation extends a passport
+    #[serde(default)]
+    pub renewal: PassportRenewal,
+
+    /// Valid passports one circuit may hold; solving again beyond this
+    /// invalidates its oldest (0 = unlimited)
+    #[serde(default = "default_max_passports_per_circuit")]
+    pub max_per_circuit: u32,
+}
+
+/// Passport renewal on validation (`captcha.passport.renewal`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PassportRenewal {
+    /// Expires its TTL after issuance, however much it is used
+    #[default]
+    Fixed,
+    /// Each validation restarts the TTL (up to `max_lifetime_secs`)
+    Sliding,
+}
+
+impl PassportPolicy {
+    /// TTL of a passport issued at `difficulty`
+    pub fn ttl_secs(&self, difficulty: CaptchaDifficulty, default: u64) -> u64 {
+        match difficulty {
+            CaptchaDifficulty::Easy => self.easy_ttl_secs,
+            CaptchaDifficulty::Medium => self.medium_ttl_secs,
+            CaptchaDifficulty::Hard => self.hard_ttl_secs,
+            CaptchaDifficulty::Extreme => self.extreme_ttl_secs,
+        }
+        .map_or(default, TtlSecs::secs)
+    }
+
+    /// Has a passport issued at `issued_at` reached the lifetime cap?
+    pub fn outlived(&self, issued_at: i64, now: i64) -> bool {
+        !self.max_lifetime_secs.is_zero() && now >= issued_at + self.max_lifetime_secs.as_i64()
+    }
+
+    /// `ttl` cut short so it doesn't outlive the lifetime cap of a passport
+    /// issued at `issued_at` (0 once the cap is reached)
+    pub fn capped_ttl(&self, ttl: u64, issued_at: i64, now: i64) -> u64 {
+        if self.max_lifetime_secs.is_zero() {
+            return ttl;
+        }
+        let left = (issued_at + self.max_lifetime_secs.as_i64() - now).max(0) as u64;
+        ttl.min(left)
+    }
+}
+
+impl Default for PassportPolicy {
+    fn default() -> Self {
+        Self {
+            easy_ttl_secs: None,
+            medium_ttl_secs: None,
+            hard_ttl_secs: None,
+            extreme_ttl_secs: None,
+            max_lifetime_secs: default_passport_max_lifetime(),
+            renewal: PassportRenewal::default(),
+            max_per_circuit: default_max_passports_per_circuit(),
+        }
+    }
+}
+
+/// Progressive challenge flow (`[captcha.flow]`)
+///
+/// The gate page can carry a small JavaScript proof-of-work; what solving
+/// it earns is set per difficulty (threat levels as for
+/// `[captcha.providers]`). Clients without JavaScript never see it and go
+/// straight to the visual challenge.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChallengeFlow {
+    pub easy: PowReward,
+    pub medium: PowReward,
+    pub hard: PowReward,
+    pub extreme: PowReward,
+    /// Leading zero bits the proof-of-work hash needs (each doubles the work)
+    pub pow_bits: u8,
+    /// How long a proof-of-work puzzle stays valid
+    pub pow_ttl_secs: TtlSecs,
+}
+
+/// What solving the proof-of-work earns (`captcha.flow`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PowReward {
+    /// No proof-of-work; everyone gets the visual challenge
+    #[default]
+    Off,
+    /// A visual challenge one difficulty easier
+    Easier,
+    /// A passport without a visual challenge
+    Skip,
+}
+
+impl ChallengeFlow {
+    /// Reward for a proof-of-work solved at `difficulty`
+    pub fn reward(&self, difficulty: CaptchaDifficulty) -> PowReward {
+        match difficulty {
+            CaptchaDifficulty::Easy => self.easy,
+            CaptchaDifficulty::Medium => self.medium,
+            CaptchaDifficulty::Hard => self.hard,
+            CaptchaDifficulty::Extreme => self.extreme,
+        }
+    }
+
+    /// Is a proof-of-work offered at any difficulty?
+    pub fn is_enabled(&self) -> bool {
+        [self.easy, self.medium, self.hard, self.extreme]
+            .iter()
+            .any(|reward| *reward != PowReward::Off)
+    }
+}
+
+impl Default for ChallengeFlow {
+    fn default() -> Self {
+        Self {
+            easy: PowReward::Off,
+            medium: PowReward::Off,
+            hard: PowReward::Off,
+            extreme: PowReward::Off,
+            pow_bits: 18,
+            pow_ttl_secs: TtlSecs::from_mins(2),
+        }
+    }
+}
+
+/// Challenge write batching (`captcha.write_batch`)
+///
+/// Challenge writes are queued and sent to Redis as one pipeline every
+/// `flush_ms` or `max_ops` writes, whichever comes first.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WriteBatchConfig {
+    /// Batch writes (off: one round trip per challenge)
+    pub enabled: bool,
+    /// Writes sent in one pipeline at most
+    pub max_ops: usize,
+    /// Longest a write waits for others to join its batch
+    pub flush_ms: u64,
+    /// Writes queued at most; beyond this, issuance is refused
+    pub queue_capacity: usize,
+}
+
+impl Default for WriteBatchConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_ops: 64,
+            flush_ms: 2,
+            queue_capacity: 4096,
+        }
+    }
+}
+
+/// Rate limiting configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// Maximum requests per minute per circuit
+    #[serde(default = "default_max_requests")]
+    pub max_requests_per_minute: u32,
+
+    /// Maximum failed CAPTCHAs before soft-lock
+    #[serde(default = "default_max_failures")]
+    pub max_failed_attempts: u32,
+
+    /// Soft-lock duration
+    #[serde(default = "default_soft_lock")]
+    pub soft_lock_duration_secs: TtlSecs,
+
+    /// Ban duration
+    #[serde(default = "default_ban_duration")]
+    pub ban_duration_secs: TtlSecs,
+
+    /// Recent events kept per circuit for `GET /circuit/{id}` (0 = disabled)
+    #[serde(default = "default_event_history_len")]
+    pub event_history_len: usize,
+
+    /// Requests a circuit may have in flight behind `/validate` (0 = unlimited)
+    #[serde(default = "default_max_concurrent")]
+    pub max_concurrent_per_circuit: u32,
+
+    /// How long a validated request counts as in flight (the proxy never
+    /// reports when it finishes)
+    #[serde(default = "default_concurrency_lease_ms")]
+    pub concurrency_lease_ms: u64,
+
+    /// Answer throttling and gate lockout
+    #[serde(default)]
+    pub verify: VerifyThrottleConfig,
+
+    /// Budgets shared across the cluster over gossip
+    #[serde(default)]
+    pub cluster: ClusterRateLimitConfig,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            max_requests_per_minute: default_max_requests(),
+            max_failed_attempts: default_max_failures(),
+            soft_lock_duration_secs: default_soft_lock(),
+            ban_duration_secs: default_ban_duration(),
+            event_history_len: default_event_history_len(),
+            max_concurrent_per_circuit: default_max_concurrent(),
+            concurrency_lease_ms: default_concurrency_lease_ms(),
+            verify: VerifyThrottleConfig::default(),
+            cluster: ClusterRateLimitConfig::default(),
+        }
+    }
+}
+
+/// Answer throttling (`[rate_limit.verify]`)
+///
+/// Separate from `max_requests_per_minute`, so a circuit can't spend its
+/// whole request budget guessing answers. A burst of wrong answers locks
+/// the circuit out of the gate for a while; locked-out requests are held
+/// for `tarpit_delay_ms` and then get a "slow down" page.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VerifyThrottleConfig {
+    /// Answers a circuit may post per minute (0 = unlimited)
+    pub max_attempts_per_minute: u32,
+    /// Wrong answers within `wrong_window_secs` that lock the gate (0 = never)
+    pub lockout_after_wrong: u32,
+    pub wrong_window_secs: TtlSecs,
+    /// How long the gate stays locked
+    pub lockout_secs: TtlSecs,
+    /// How long a throttled or locked-out request is held before answering
+    pub tarpit_delay_ms: u64,
+}
+
+impl Default for VerifyThrottleConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts_per_minute: 20,
+            lockout_after_wrong: 5,
+            wrong_window_secs: TtlSecs::from_mins(1),
+            lockout_secs: TtlSecs::from_mins(5),
+            tarpit_delay_ms: 3000,
+        }
+    }
+}
+
+/// CAPTCHA farm detection (solve-time analysis) configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FarmDetectionConfig {
+    /// Enable solve-time analysis
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Median solve time (ms) below which solving is implausible for a human
+    #[serde(default = "default_min_human_solve_ms")]
+    pub min_human_solve_ms: u64,
+
+    /// Any single solve faster than this (ms) is flagged outright
+    #[serde(default = "default_instant_solve_ms")]
+    pub instant_solve_ms: u64,
+
+    /// Minimum samples before uniformity/entry-pattern checks apply
+    #[serde(default = "default_farm_min_samples")]
+    pub min_samples: usize,
+
+    /// Coefficient of variation below which solve times are "too uniform"
+    #[serde(default = "default_max_uniform_cv")]
+    pub max_uniform_cv: f64,
+
+    /// Number of recent solves kept per circuit
+    #[serde(default = "default_farm_window")]
+    pub window: usize,
+
+    /// Reputation penalty applied when a circuit is flagged
+    #[serde(default = "default_farm_penalty")]
+    pub reputation_penalty: i32,
+}
+
+impl Default for FarmDetectionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_human_solve_ms: default_min_human_solve_ms(),
+            instant_solve_ms: default_instant_solve_ms(),
+            min_samples: default_farm_min_samples(),
+            max_uniform_cv: default_max_uniform_cv(),
+            window: default_farm_window(),
+            reputation_penalty: default_farm_penalty(),
+        }
+    }
+}
+
+/// VIP circuit policy
+///
+/// Circuits that keep solving in good standing are promoted to VIP: their
+/// passports live longer, renew as they are used, and while the threat
+/// level is low they are let through the gate without a challenge. Failed
+/// challenges, rate limiting or a honeypot hit take the status away again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VipConfig {
+    /// Successful solves before a circuit is promoted
+    #[serde(default = "default_vip_min_solves")]
+    pub min_solves: u32,
+
+    /// Lowest reputation still eligible for promotion
+    #[serde(default)]
+    pub min_reputation: i32,
+
+    /// Max VIP circuits at once (0 = unlimited)
+    #[serde(default = "default_max_vips")]
+    pub max_vips: u32,
+
+    /// Passport validity for VIP circuits (Redis-backed passports)
+    #[serde(default = "default_vip_passport_ttl")]
+    pub passport_ttl_secs: TtlSecs,
+
+    /// Extend a VIP passport to the full TTL each time it validates
+    #[serde(default = "default_true")]
+    pub auto_renew: bool,
+
+    /// VIPs skip the challenge while the threat level is below this (0 = never)
+    #[serde(default)]
+    pub skip_challenge_below: u8,
+
+    /// Failed challenges (since the last solve) that cost VIP status (0 = never)
+    #[serde(default = "default_vip_demote_after_failures")]
+    pub demote_after_failures: u32,
+
+    /// Hitting the rate limit costs VIP status
+    #[serde(default = "default_true")]
+    pub demote_on_rate_limit: bool,
+}
+
+impl Default for VipConfig {
+    fn default() -> Self {
+        Self {
+            min_solves: default_vip_min_solves(),
+            min_reputation: 0,
+            max_vips: default_max_vips(),
+            passport_ttl_secs: default_vip_passport_ttl(),
+            auto_renew: true,
+            skip_challenge_below: 0,
+            demote_after_failures: default_vip_demote_after_failures(),
+            demote_on_rate_limit: true,
+        }
+    }
+}
+
+/// Enforcement mode
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EnforcementConfig {
+    /// Let every request through `/validate` and hold back rule bans,
+    /// recording what would have been done instead
+    #[serde(default)]
+    pub observe: bool,
+}
+
+/// Ammo Box warm-up at startup (skipped with `--skip-warmup`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WarmupConfig {
+    /// Pool fill (percent of capacity) to reach before `/ready` succeeds
+    #[serde(default = "default_warmup_min_fill")]
+    pub min_fill_pct: u8,
+
+    /// Give up and report ready anyway after this long (0 = never)
+    #[serde(default = "default_warmup_timeout")]
+    pub timeout_secs: TtlSecs,
+}
+
+impl Default for WarmupConfig {
+    fn default() -> Self {
+        Self {
+            min_fill_pct: default_warmup_min_fill(),
+            timeout_secs: default_warmup_timeout(),
+        }
+    }
+}
+
+/// Response compression (gzip)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompressionConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Smaller responses are sent uncompressed
+    #[serde(default = "default_compression_min_size")]
+    pub min_size_bytes: usize,
+
+    /// gzip level, 1 (fastest) to 9 (smallest)
+    #[serde(default = "default_compression_level")]
+    pub level: u32,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_size_bytes: default_compression_min_size(),
+            level: default_compression_level(),
+        }
+    }
+}
+
+/// Redis memory guard and offline mode configuration
+///
+/// Memory ratios are `used_memory / maxmemory`; when Redis runs without
+/// `maxmemory`, `max_memory_bytes` is used as the denominator instead.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DegradationConfig {
+    /// Enable memory/latency thresholds (offline detection always runs)
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+
+    /// Time between Redis INFO samples
+    #[serde(default = "default_guard_interval")]
+    pub check_interval_secs: TtlSecs,
+
+    /// Memory budget when Redis has no maxmemory set (0 = ignore memory)
+    #[serde(default)]
+    pub max_memory_bytes: u64,
+
+    /// Memory ratio that enters degraded mode
+    #[serde(default = "default_degraded_memory_ratio")]
+    pub degraded_memory_ratio: f64,
+
+    /// Memory ratio that stops new challenge storage
+    #[serde(default = "default_critical_memory_ratio")]
+    pub critical_memory_ratio: f64,
+
+    /// Round-trip latency (ms) that enters degraded mode
+    #[serde(default = "default_degraded_latency_ms")]
+    pub degraded_latency_ms: u64,
+
+    /// Round-trip latency (ms) that stops new challenge storage
+    #[serde(default = "default_critical_latency_ms")]
+    pub critical_latency_ms: u64,
+
+    /// Consecutive healthy samples required before stepping down a level
+    #[serde(default = "default_recovery_samples")]
+    pub recovery_samples: u32,
+
+    /// Challenge TTL while degraded
+    #[serde(default = "default_degraded_challenge_ttl")]
+    pub degraded_challenge_ttl_secs: TtlSecs,
+
+    /// Retry-After advertised when new challenges are refused
+    #[serde(default = "default_degraded_retry_after")]
+    pub retry_after_secs: TtlSecs,
+
+    /// Circuit mutations kept for replay while Redis is offline
+    #[serde(default = "default_offline_queue_capacity")]
+    pub offline_queue_capacity: usize,
+}
+
+impl Default for DegradationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            check_interval_secs: default_guard_interval(),
+            max_memory_bytes: 0,
+            degraded_memory_ratio: default_degraded_memory_ratio(),
+            critical_memory_ratio: default_critical_memory_ratio(),
+            degraded_latency_ms: default_degraded_latency_ms(),
+            critical_latency_ms: default_critical_latency_ms(),
+            recovery_samples: default_recovery_samples(),
+            degraded_challenge_ttl_secs: default_degraded_challenge_ttl(),
+            retry_after_secs: default_degraded_retry_after(),
+            offline_queue_capacity: default_offline_queue_capacity(),
+        }
+    }
+}
+
+/// OpenTelemetry (OTLP) trace export
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(not(feature = "otel"), allow(dead_code))]
+pub struct TelemetryConfig {
+    /// Export spans to an OTLP collector
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// OTLP/HTTP traces endpoint
+    #[serde(default = "default_otlp_endpoint")]
+    pub otlp_endpoint: String,
+
+    /// `service.name` resource attribute
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+
+    /// Fraction of new traces to sample (upstream sampling decisions win)
+    #[serde(default = "default_sample_ratio")]
+    pub sample_ratio: f64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            otlp_endpoint: default_otlp_endpoint(),
+            service_name: default_service_name(),
+            sample_ratio: default_sample_ratio(),
+        }
+    }
+}
+
+/// TLS termination on the Fortify listener
+///
+/// With `client_ca_path` set, `/validate` and `/admin` require a client
+/// certificate issued by that CA (mTLS); other routes do not.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// Serve HTTPS instead of plain HTTP
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// PEM certificate chain (leaf first)
+    #[serde(default = "default_tls_cert_path")]
+    pub cert_path: String,
+
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1)
+    #[serde(default = "default_tls_key_path")]
+    pub key_path: String,
+
+    /// PEM CA bundle for client certificates (unset = no mTLS)
+    #[serde(default)]
+    pub client_ca_path: Option<String>,
+}
+
+impl TlsConfig {
+    /// Are control routes restricted to clients with a certificate?
+    pub fn client_auth(&self) -> bool {
+        self.enabled && self.client_ca_path.is_some()
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cert_path: default_tls_cert_path(),
+            key_path: default_tls_key_path(),
+            client_ca_path: None,
+        }
+    }
+}
+
+/// Security headers per route group
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecurityHeadersConfig {
+    /// HTML gate pages (CAPTCHA page, form verify, protected app)
+    #[serde(default = "default_gate_headers")]
+    pub gate: HeaderPolicy,
+
+    /// JSON API (challenge, validate, circuit info, health)
+    #[serde(default = "default_api_headers")]
+    pub api: HeaderPolicy,
+
+    /// Admin API
+    #[serde(default = "default_api_headers")]
+    pub admin: HeaderPolicy,
+}
+
+impl Default for SecurityHeadersConfig {
+    fn default() -> Self {
+        Self {
+            gate: default_gate_headers(),
+            api: default_api_headers(),
+            admin: default_api_headers(),
+        }
+    }
+}
+
+/// Headers applied to every response of a route group
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeaderPolicy {
+    /// Content-Security-Policy value (default if unset, omitted if empty)
+    #[serde(default = "default_csp")]
+    pub content_security_policy: Option<String>,
+
+    /// X-Frame-Options value (default if unset, omitted if empty)
+    #[serde(default = "default_frame_options")]
+    pub frame_options: Option<String>,
+
+    /// Referrer-Policy value (default if unset, omitted if empty)
+    #[serde(default = "default_referrer_policy")]
+    pub referrer_policy: Option<String>,
+
+    /// Send X-Content-Type-Options: nosniff
+    #[serde(default = "default_true")]
+    pub nosniff: bool,
+
+    /// Optional CORS rules (no CORS headers if unset)
+    #[serde(default)]
+    pub cors: Option<CorsPolicy>,
+}
+
+/// Cross-origin rules for a route group
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CorsPolicy {
+    /// Allowed origins ("*" allows any)
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+
+    /// Allowed request methods
+    #[serde(default = "default_cors_methods")]
+    pub allowed_methods: Vec<String>,
+
+    /// Allowed request headers
+    #[serde(default)]
+    pub allowed_headers: Vec<String>,
+
+    /// Preflight cache lifetime
+    #[serde(default = "default_cors_max_age")]
+    pub max_age_secs: TtlSecs,
+}
+
+// Default value functions
+fn default_redis_url() -> String {
+    DEFAULT_REDIS_URL.to_string()
+}
+fn default_listen_addr() -> String {
+    DEFAULT_LISTEN_ADDR.to_string()
+}
+fn default_listen_socket_mode() -> u32 {
+    0o660
+}
+fn default_threat_level() -> u8 {
+    5
+}
+fn default_font_path() -> String {
+    "assets/fonts/DejaVuSans.ttf".to_string()
+}
+fn default_passport_ttl() -> TtlSecs {
+    DEFAULT_PASSPORT_TTL_SECS
+}
+fn default_passport_max_lifetime() -> TtlSecs {
+    TtlSecs::from_days(1)
+}
+fn default_max_passports_per_circuit() -> u32 {
+    3
+}
+fn default_challenge_ttl() -> TtlSecs {
+    CAPTCHA_TTL_SECS
+}
+fn default_max_refreshes() -> u32 {
+    10
+}
+fn default_refresh_penalty() -> i32 {
+    5
+}
+fn default_max_outstanding() -> u32 {
+    5
+}
+fn default_max_issued_per_second() -> u32 {
+    500
+}
+fn default_gate_session_ttl() -> TtlSecs {
+    TtlSecs::from_mins(30)
+}
+fn default_provider() -> String {
+    "text".to_string()
+}
+fn default_max_requests() -> u32 {
+    60
+}
+fn default_max_concurrent() -> u32 {
+    16
+}
+fn default_concurrency_lease_ms() -> u64 {
+    5000
+}
+fn default_max_failures() -> u32 {
+    5
+}
+fn default_soft_lock() -> TtlSecs {
+    SOFT_LOCK_DURATION_SECS
+}
+fn default_ban_duration() -> TtlSecs {
+    BAN_DURATION_SECS
+}
+fn default_event_history_len() -> usize {
+    20
+}
+fn default_true() -> bool {
+    true
+}
+fn default_min_human_solve_ms() -> u64 {
+    2000
+}
+fn default_instant_solve_ms() -> u64 {
+    500
+}
+fn default_farm_min_samples() -> usize {
+    3
+}
+fn default_max_uniform_cv() -> f64 {
+    0.1
+}
+fn default_farm_window() -> usize {
+    10
+}
+fn default_farm_penalty() -> i32 {
+    20
+}
+fn default_vip_min_solves() -> u32 {
+    5
+}
+fn default_max_vips() -> u32 {
+    1000
+}
+fn default_vip_passport_ttl() -> TtlSecs {
+    TtlSecs::from_hours(1)
+}
+fn default_vip_demote_after_failures() -> u32 {
+    1
+}
+fn default_guard_interval() -> TtlSecs {
+    TtlSecs::from_secs(5)
+}
+fn default_degraded_memory_ratio() -> f64 {
+    0.75
+}
+fn default_critical_memory_ratio() -> f64 {
+    0.9
+}
+fn default_degraded_latency_ms() -> u64 {
+    100
+}
+fn default_critical_latency_ms() -> u64 {
+    500
+}
+fn default_recovery_samples() -> u32 {
+    3
+}
+fn default_degraded_challenge_ttl() -> TtlSecs {
+    TtlSecs::from_mins(1)
+}
+fn default_degraded_retry_after() -> TtlSecs {
+    TtlSecs::from_secs(30)
+}
+fn default_offline_queue_capacity() -> usize {
+    10_000
+}
+fn default_otlp_endpoint() -> String {
+    "http://127.0.0.1:4318/v1/traces".to_string()
+}
+fn default_service_name() -> String {
+    "fortify".to_string()
+}
+fn default_sample_ratio() -> f64 {
+    1.0
+}
+fn default_tls_cert_path() -> String {
+    "/etc/cerberus/tls/fortify.crt".to_string()
+}
+fn default_tls_key_path() -> String {
+    "/etc/cerberus/tls/fortify.key".to_string()
+}
+
+fn default_warmup_min_fill() -> u8 {
+    10
+}
+fn default_warmup_timeout() -> TtlSecs {
+    TtlSecs::from_mins(2)
+}
+fn default_compression_min_size() -> usize {
+    1024
+}
+fn default_compression_level() -> u32 {
+    6
+}
+
+fn default_gate_headers() -> HeaderPolicy {
+    HeaderPolicy {
+        // Our stylesheet and proof-of-work script, inline styles and SVG
+        // only; forms may only post back to us
+        content_security_policy: Some(
+            "default-src 'none'; script-src 'self'; style-src 'self' 'unsafe-inline'; \
+             img-src 'self' data:; form-action 'self'; frame-ancestors 'none'; base-uri 'none'"
+                .to_string(),
+        ),
+        frame_options: default_frame_options(),
+        referrer_policy: default_referrer_policy(),
+        nosniff: true,
+        cors: None,
+    }
+}
+fn default_api_headers() -> HeaderPolicy {
+    HeaderPolicy {
+        content_security_policy: default_csp(),
+        frame_options: default_frame_options(),
+        referrer_policy: default_referrer_policy(),
+        nosniff: true,
+        cors: None,
+    }
+}
+fn default_csp() -> Option<String> {
+    Some("default-src 'none'; frame-ancestors 'none'".to_string())
+}
+fn default_frame_options() -> Option<String> {
+    Some("DENY".to_string())
+}
+fn default_referrer_policy() -> Option<String> {
+    Some("no-referrer".to_string())
+}
+fn default_cors_methods() -> Vec<String> {
+    vec!["GET".to_string(), "POST".to_string()]
+}
+fn default_cors_max_age() -> TtlSecs {
+    TtlSecs::from_mins(10)
+}
+
+fn generate_node_id() -> String {
+    use rand::Rng;
+    let mut rng = rand::rng();
+    format!("node-{:08x}", rng.random::<u32>())
+}
+
+impl AppConfig {
+    /// Load configuration from file, with CLI overrides
+    ///
+    /// Fails if the file has unknown keys or out-of-range values.
+    pub fn load(config_path: &str, args: &super::Args) -> Result<Self> {
+        let (config, issues) = Self::check(config_path, args)?;
+        if !issues.is_empty() {
+            bail!(
+                "Invalid configuration ({} problem(s)):\n{}",
+                issues.len(),
+                validate::report(config_path, &issues)
+            );
+        }
+        Ok(config)
+    }
+
+    /// Load configuration and collect every problem instead of stopping at
+    /// the first (used by `--validate-config`)
+    pub fn check(config_path: &str, args: &super::Args) -> Result<(Self, Vec<ConfigIssue>)> {
+        Self::check_with_env(config_path, args, environment())
+    }
+
+    fn check_with_env(
+        config_path: &str,
+        args: &super::Args,
+        env: config::Environment,
+    ) -> Result<(Self, Vec<ConfigIssue>)> {
+        let text = if Path::new(config_path).exists() {
+            Some(std::fs::read_to_string(config_path).context("Failed to read config file")?)
+        } else {
+            // Use defaults if config file doesn't exist (logged by the caller,
+            // since logging is configured from this file)
+            None
+        };
+
+        let mut builder = config::Config::builder();
+        let mut issues = Vec::new();
+        if let Some(text) = &text {
+            let (parsed, file_issues) = validate::check_file(text);
+            issues = file_issues;
+            if parsed.is_none() {
+                // Syntax or type errors; nothing further to check
+                return Ok((Self::default(), issues));
+            }
+            builder = builder.add_source(config::File::with_name(config_path));
+        }
+
+        // Environment overrides (`FORTIFY__SECTION__KEY`) layered on the file.
+        // Remember where overridden keys came from so problems with them are
+        // not blamed on a line in the file.
+        let mut overrides: Vec<(String, String)> = env
+            .collect()
+            .map(|vars| vars.into_keys().map(|key| (env_var(&key), key)).collect())
+            .unwrap_or_default();
+        let settings = builder
+            .add_source(env)
+            .build()
+            .context("Failed to load configuration")?;
+
+        let mut unknown = Vec::new();
+        let mut config: Self =
+            match serde_ignored::deserialize(settings, |path| unknown.push(path.to_string())) {
+                Ok(config) => config,
+                Err(e) => {
+                    issues.push(ConfigIssue::new("", e.to_string()));
+                    return Ok((Self::default(), issues));
+                }
+            };
+
+        // Apply CLI overrides
+        if let Some(ref redis_url) = args.redis_url {
+            config.redis_url = redis_url.clone();
+            overrides.push(("--redis-url".to_string(), "redis_url".to_string()));
+        }
+        if let Some(ref listen) = args.listen {
+            config.listen_addr = listen.clone();
+            overrides.push(("--listen".to_string(), "listen_addr".to_string()));
+        }
+
+        // Unknown file keys were reported above; the rest came from the environment
+        let mut later_issues: Vec<_> = unknown
+            .into_iter()
+            .filter(|key| !issues.iter().any(|issue| &issue.key == key))
+            .map(|key| ConfigIssue::new(key, "unknown key"))
+            .collect();
+        later_issues.extend(validate::check_values(&config));
+
+        let mut in_file = Vec::new();
+        for mut issue in later_issues {
+            let nested = format!("{}.", issue.key);
+            match overrides
+                .iter()
+                .find(|(_, key)| *key == issue.key || key.starts_with(&nested))
+            {
+                Some((source, _)) => {
+                    issue.message = format!("{} (set by {})", issue.message, source);
+                    issues.push(issue);
+                }
+                None => in_file.push(issue),
+            }
+        }
+        if let Some(text) = &text {
+            validate::locate_all(text, &mut in_file);
+        }
+        issues.extend(in_file);
+        issues.sort_by_key(|issue| issue.line.unwrap_or(usize::MAX));
+
+        Ok((config, issues))
+    }
+}
+
+/// Environment variable prefix for config overrides
+const ENV_PREFIX: &str = "FORTIFY";
+
+/// Keys parsed as comma-separated lists when set from the environment
+const ENV_LIST_KEYS: &[&str] = &[
+    "gossip.peers",
+    "gossip.allowed_nodes",
+    "webhooks.urls",
+    "security_headers.gate.cors.allowed_origins",
+    "security_headers.api.cors.allowed_origins",
+    "security_headers.admin.cors.allowed_origins",
+];
+
+/// Variable name that sets a dotted config key
+fn env_var(key: &str) -> String {
+    format!("{}__{}", ENV_PREFIX, key.replace('.', "__")).to_uppercase()
+}
+
+/// Environment source: `FORTIFY__RATE_LIMIT__BAN_DURATION_SECS=600` sets
+/// `rate_limit.ban_duration_secs` (double underscores separate levels)
+fn environment() -> config::Environment {
+    ENV_LIST_KEYS.iter().fold(
+        config::Environment::with_prefix(ENV_PREFIX)
+            .prefix_separator("__")
+            .separator("__")
+            .list_separator(",")
+            .try_parsing(true)
+            .ignore_empty(true),
+        |env, key| env.with_list_parse_key(key),
+    )
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            redis_url: default_redis_url(),
+            listen_addr: default_listen_addr(),
+            listen_socket_mode: default_listen_socket_mode(),
+            initial_threat_level: default_threat_level(),
+            cluster_enabled: false,
+            node_id: generate_node_id(),
+            gossip: GossipConfig::default(),
+            election: ElectionConfig::default(),
+            federation: FederationConfig::default(),
+            ammo_sharing: AmmoSharingConfig::default(),
+            haproxy: HaproxyConfig::default(),
+            captcha: CaptchaConfig::default(),
+            rate_limit: RateLimitConfig::default(),
+            farm_detection: FarmDetectionConfig::default(),
+            rules: RulesConfig::default(),
+            webhooks: WebhookConfig::default(),
+            vip: VipConfig::default(),
+            security_headers: SecurityHeadersConfig::default(),
+            degradation: DegradationConfig::default(),
+            compression: CompressionConfig::default(),
+            warmup: WarmupConfig::default(),
+            ammo_starvation: StarvationConfig::default(),
+            enforcement: EnforcementConfig::default(),
+            sampling: SamplingConfig::default(),
+            slo: SloConfig::default(),
+            keyspace: KeyspaceConfig::default(),
+            access_log: AccessLogConfig::default(),
+            privacy: PrivacyConfig::default(),
+            maintenance: MaintenanceConfig::default(),
+            allowlist: AllowlistConfig::default(),
+            grpc: GrpcConfig::default(),
+            telemetry: TelemetryConfig::default(),
+            tls: TlsConfig::default(),
+            trusted_proxies: TrustedProxyConfig::default(),
+            admin: AdminConfig::default(),
+            verify_queue: VerifyQueueConfig::default(),
+            experiments: ExperimentsConfig::default(),
+            abandonment: AbandonmentConfig::default(),
+            journeys: JourneyConfig::default(),
+            challenge_stats: ChallengeStatsConfig::default(),
+            backup: BackupConfig::default(),
+            flags: FlagsConfig::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn load_with_env(vars: &[(&str, &str)]) -> (AppConfig, Vec<ConfigIssue>) {
+        let vars = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let args = super::super::Args::parse_from(["fortify"]);
+        AppConfig::check_with_env(
+            "/nonexistent/fortify.toml",
+            &args,
+            environment().source(Some(vars)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_env_overrides_nested_fields() {
+        let (config, issues) = load_with_env(&[
+            ("FORTIFY__INITIAL_THREAT_LEVEL", "8"),
+            ("FORTIFY__RATE_LIMIT__BAN_DURATION_SECS", "7200"),
+            ("FORTIFY__CAPTCHA__CHALLENGE_TTL_SECS", "2m"),
+            ("FORTIFY__CLUSTER_ENABLED", "true"),
+            ("FORTIFY__GOSSIP__PEERS", "10.100.0.2:9000,10.100.0.3:9000"),
+            ("FORTIFY__TELEMETRY__SAMPLE_RATIO", "0.25"),
+            ("UNRELATED__RATE_LIMIT__BAN_DURATION_SECS", "1"),
+        ]);
+
+        assert_eq!(issues, []);
+        assert_eq!(config.initial_threat_level, 8);
+        assert_eq!(config.rate_limit.ban_duration_secs, TtlSecs::from_hours(2));
+        assert_eq!(config.rate_limit.max_failed_attempts, 5);
+        assert_eq!(config.captcha.challenge_ttl_secs, TtlSecs::from_mins(2));
+        assert!(config.cluster_enabled);
+        assert_eq!(config.gossip.peers, ["10.100.0.2:9000", "10.100.0.3:9000"]);
+        assert_eq!(config.telemetry.sample_ratio, 0.25);
+    }
+
+    #[test]
+    fn test_env_problems_reported() {
+        let (_, issues) = load_with_env(&[("FORTIFY__RATE_LIMIT__BAN_DURATON_SECS", "60")]);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].key, "rate_limit.ban_duraton_secs");
+        assert!(
+            issues[0]
+                .message
+                .contains("FORTIFY__RATE_LIMIT__BAN_DURATON_SECS")
+        );
+
+        let (_, issues) = load_with_env(&[("FORTIFY__CAPTCHA__PASSPORT_TTL_SECS", "soon")]);
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].message.contains("passport_ttl_secs"));
+    }
+
+    #[test]
+    fn test_image_format_negotiation() {
+        let tor_browser =
+            "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8";
+        assert_eq!(
+            ImageFormat::Auto.negotiate(Some(tor_browser)),
+            ImageFormat::Webp
+        );
+        assert_eq!(
+            ImageFormat::Auto.negotiate(Some("image/png;q=0.8, */*")),
+            ImageFormat::Png
+        );
+        assert_eq!(ImageFormat::Auto.negotiate(Some("*/*")), ImageFormat::Svg);
+        assert_eq!(ImageFormat::Auto.negotiate(None), ImageFormat::Svg);
+        assert_eq!(
+            ImageFormat::Png.negotiate(Some(tor_browser)),
+            ImageFormat::Png
+        );
+    }
+
+    #[test]
+    fn test_passport_policy() {
+        let policy = PassportPolicy {
+            extreme_ttl_secs: Some(TtlSecs::from_mins(2)),
+            max_lifetime_secs: TtlSecs::from_secs(1000),
+            ..Default::default()
+        };
+        assert_eq!(policy.ttl_secs(CaptchaDifficulty::Extreme, 600), 120);
+        assert_eq!(policy.ttl_secs(CaptchaDifficulty::Easy, 600), 600);
+
+        // Renewals stop at the lifetime cap
+        assert_eq!(policy.capped_ttl(600, 0, 100), 600);
+        assert_eq!(policy.capped_ttl(600, 0, 700), 300);
+        assert!(!policy.outlived(0, 999));
+        assert!(policy.outlived(0, 1000));
+        assert_eq!(policy.capped_ttl(600, 0, 1200), 0);
+
+        let uncapped = PassportPolicy {
+            max_lifetime_secs: TtlSecs::ZERO,
+            ..Default::default()
+        };
+        assert_eq!(uncapped.capped_ttl(600, 0, i64::MAX / 2), 600);
+        assert!(!uncapped.outlived(0, i64::MAX / 2));
+    }
+}
@@ -0,0 +1,1121 @@
+//! Strict configuration checks.
+//!
+//! Serde defaults make a misspelled key indistinguishable from a missing
+//! one, so the file is also walked for keys `AppConfig` does not know, and
+//! the loaded values are range-checked. Every problem is collected (not just
+//! the first) and mapped back to a line in the file where possible.
+
+use serde_ignored::Path as KeyPath;
+use std::fmt;
+use std::net::SocketAddr;
+use toml::Spanned;
+use toml::de::{DeTable, DeValue};
+
+use super::AppConfig;
+use crate::listener::ListenAddr;
+use cerberus_common::{ThreatLevel, TtlSecs};
+
+/// A single configuration problem
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigIssue {
+    /// Dotted key path (empty for syntax errors)
+    pub key: String,
+    /// 1-based line in the config file, if the key appears there
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    pub fn new(key: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            line: None,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.key.is_empty() {
+            f.write_str(&self.message)
+        } else {
+            write!(f, "{}: {}", self.key, self.message)
+        }
+    }
+}
+
+/// Format issues as `file:line: key: message`, one per line
+pub fn report(file: &str, issues: &[ConfigIssue]) -> String {
+    issues
+        .iter()
+        .map(|issue| match issue.line {
+            Some(line) => format!("{}:{}: {}", file, line, issue),
+            None => format!("{}: {}", file, issue),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Parse the file strictly: syntax, value types and unknown keys
+///
+/// Returns the parsed config when the file could be deserialized at all
+/// (unknown keys alone do not prevent that).
+pub fn check_file(text: &str) -> (Option<AppConfig>, Vec<ConfigIssue>) {
+    let table = match DeTable::parse(text) {
+        Ok(table) => table,
+        Err(e) => {
+            let mut issue = ConfigIssue::new("", e.message());
+            issue.line = e.span().map(|span| line_of(text, span.start));
+            return (None, vec![issue]);
+        }
+    };
+
+    let mut issues = Vec::new();
+    let deserializer = toml::de::Deserializer::from(table.clone());
+    let parsed = serde_ignored::deserialize(deserializer, |path| {
+        let key = key_segments(&path).join(".");
+        issues.push(ConfigIssue::new(key, "unknown key"));
+    });
+
+    for issue in &mut issues {
+        issue.line = locate(text, &table, &issue.key);
+    }
+    issues.sort_by_key(|issue| issue.line);
+
+    match parsed {
+        Ok(config) => (Some(config), issues),
+        Err(e) => {
+            let mut issue = ConfigIssue::new("", e.message());
+            issue.line = e.span().map(|span| line_of(text, span.start));
+            issues.push(issue);
+            (None, issues)
+        }
+    }
+}
+
+/// Range and format checks on loaded values
+pub fn check_values(config: &AppConfig) -> Vec<ConfigIssue> {
+    let mut issues = Vec::new();
+    let mut check = |ok: bool, key: &str, message: String| {
+        if !ok {
+            issues.push(ConfigIssue::new(key, message));
+        }
+    };
+
+    if let Err(e) = redis::IntoConnectionInfo::into_connection_info(config.redis_url.as_str()) {
+        check(false, "redis_url", format!("invalid Redis URL: {}", e));
+    }
+    if let Err(e) = config.listen_addr.parse::<ListenAddr>() {
+        check(
+            false,
+            "listen_addr",
+            format!("`{}`: {}", config.listen_addr, e),
+        );
+    }
+    check(
+        config.listen_socket_mode <= 0o777,
+        "listen_socket_mode",
+        format!("{:o} is not a permission mode", config.listen_socket_mode),
+    );
+    check(
+        config.initial_threat_level <= ThreatLevel::MAX.value(),
+        "initial_threat_level",
+        format!("must be 0-{}", ThreatLevel::MAX.value()),
+    );
+
+    let gossip = &config.gossip;
+    for (key, addr) in std::iter::once(("gossip.bind_addr", &gossip.bind_addr))
+        .chain(gossip.peers.iter().map(|peer| ("gossip.peers", peer)))
+    {
+        check(
+            addr.parse::<SocketAddr>().is_ok(),
+            key,
+            format!("`{}` is not an IP:port address", addr),
+        );
+    }
+    check(
+        !gossip.interval_secs.is_zero(),
+        "gossip.interval_secs",
+        "must be greater than 0".into(),
+    );
+    check(
+        gossip.peer_timeout_secs > gossip.interval_secs,
+        "gossip.peer_timeout_secs",
+        format!("must exceed interval_secs ({})", gossip.interval_secs),
+    );
+    check(
+        (0.0..=1.0).contains(&gossip.isolation_threshold),
+        "gossip.isolation_threshold",
+        format!("{} is outside [0, 1]", gossip.isolation_threshold),
+    );
+    check(
+        gossip.flap_window_secs > gossip.peer_timeout_secs,
+        "gossip.flap_window_secs",
+        format!(
+            "must exceed peer_timeout_secs ({})",
+            gossip.peer_timeout_secs
+        ),
+    );
+    check(
+        gossip.max_packets_per_sec > 0,
+        "gossip.max_packets_per_sec",
+        "must be greater than 0".into(),
+    );
+    check(
+        gossip.max_peers >= gossip.peers.len(),
+        "gossip.max_peers",
+        format!(
+            "must be at least the number of peers ({})",
+            gossip.peers.len()
+        ),
+    );
+
+    let election = &config.election;
+    check(
+        election.renew_interval_secs > 0,
+        "election.renew_interval_secs",
+        "must be greater than 0".into(),
+    );
+    check(
+        election.lease_secs > election.renew_interval_secs,
+        "election.lease_secs",
+        format!(
+            "must exceed renew_interval_secs ({})",
+            election.renew_interval_secs
+        ),
+    );
+
+    let federation = &config.federation;
+    if federation.uses_registry() {
+        check(
+            federation.registry_refresh_secs > 0,
+            "federation.registry_refresh_secs",
+            "must be greater than 0".into(),
+        );
+    }
+    if federation.previous_private_key_path.is_some() || !federation.previous_peer_keys.is_empty() {
+        check(
+            federation.key_overlap_secs > 0,
+            "federation.key_overlap_secs",
+            "must be greater than 0 while previous keys are set".into(),
+        );
+    }
+
+    let sharing = &config.ammo_sharing;
+    if sharing.enabled {
+        for (key, addr) in std::iter::once(("ammo_sharing.bind_addr", &sharing.bind_addr)).chain(
+            sharing
+                .peers
+                .values()
+                .map(|peer| ("ammo_sharing.peers", peer)),
+        ) {
+            check(
+                addr.parse::<SocketAddr>().is_ok(),
+                key,
+                format!("`{}` is not an IP:port address", addr),
+            );
+        }
+        check(
+            sharing.interval_secs > 0,
+            "ammo_sharing.interval_secs",
+            "must be greater than 0".into(),
+        );
+        check(
+            sharing.donor_min_fill_pct <= 100,
+            "ammo_sharing.donor_min_fill_pct",
+            format!("{} is over 100", sharing.donor_min_fill_pct),
+        );
+        check(
+            sharing.request_below_pct < sharing.donor_min_fill_pct,
+            "ammo_sharing.request_below_pct",
+            format!(
+                "must be under donor_min_fill_pct ({})",
+                sharing.donor_min_fill_pct
+            ),
+        );
+        check(
+            sharing.donor_keep_pct < sharing.donor_min_fill_pct,
+            "ammo_sharing.donor_keep_pct",
+            format!(
+                "must be under donor_min_fill_pct ({})",
+                sharing.donor_min_fill_pct
+            ),
+        );
+        check(
+            sharing.batch_size > 0,
+            "ammo_sharing.batch_size",
+            "must be greater than 0".into(),
+        );
+    }
+
+    let haproxy = &config.haproxy;
+    if haproxy.enabled {
+        for (key, name) in std::iter::once(("haproxy.backend", &haproxy.backend))
+            .chain(haproxy.servers.values().map(|s| ("haproxy.servers", s)))
+        {
+            check(
+                crate::haproxy::is_valid_name(name),
+                key,
+                format!("`{}` is not a valid HAProxy name", name),
+            );
+        }
+        check(
+            haproxy.stats_interval_secs > 0,
+            "haproxy.stats_interval_secs",
+            "must be greater than 0".into(),
+        );
+        check(
+            haproxy.table_full_ratio > 0.0 && haproxy.table_full_ratio <= 1.0,
+            "haproxy.table_full_ratio",
+            format!("{} is outside (0, 1]", haproxy.table_full_ratio),
+        );
+        if let Some(ref map) = haproxy.flags_map {
+            check(
+                !map.is_empty() && !map.contains(char::is_whitespace),
+                "haproxy.flags_map",
+                format!("`{}` is not a usable map path", map),
+            );
+        }
+        let pressure = &haproxy.pressure;
+        if pressure.enabled {
+            check(
+                crate::pressure::is_valid_tag(&pressure.tag),
+                "haproxy.pressure.tag",
+                format!("`{}` is not gpt0 or gpt(N)", pressure.tag),
+            );
+            check(
+                pressure.interval_secs > 0,
+                "haproxy.pressure.interval_secs",
+                "must be greater than 0".into(),
+            );
+            check(
+                pressure.max_circuits > 0,
+                "haproxy.pressure.max_circuits",
+                "must be greater than 0".into(),
+            );
+        }
+    }
+
+    let captcha = &config.captcha;
+    check(
+        !captcha.passport_ttl_secs.is_zero(),
+        "captcha.passport_ttl_secs",
+        "must be greater than 0".into(),
+    );
+    let passport = &captcha.passport;
+    for (key, ttl) in [
+        ("captcha.passport.easy_ttl_secs", passport.easy_ttl_secs),
+        ("captcha.passport.medium_ttl_secs", passport.medium_ttl_secs),
+        ("captcha.passport.hard_ttl_secs", passport.hard_ttl_secs),
+        (
+            "captcha.passport.extreme_ttl_secs",
+            passport.extreme_ttl_secs,
+        ),
+    ] {
+        check(
+            ttl != Some(TtlSecs::ZERO),
+            key,
+            "must be greater than 0".into(),
+        );
+    }
+    check(
+        passport.max_lifetime_secs.is_zero()
+            || passport.max_lifetime_secs >= captcha.passport_ttl_secs,
+        "captcha.passport.max_lifetime_secs",
+        format!(
+            "must be 0 or at least passport_ttl_secs ({})",
+            captcha.passport_ttl_secs
+        ),
+    );
+    check(
+        !captcha.challenge_ttl_secs.is_zero(),
+        "captcha.challenge_ttl_secs",
+        "must be greater than 0".into(),
+    );
+    check(
+        !captcha.gate_session_ttl_secs.is_zero(),
+        "captcha.gate_session_ttl_secs",
+        "must be greater than 0".into(),
+    );
+    let flow = &captcha.flow;
+    if flow.is_enabled() {
+        check(
+            (8..=28).contains(&flow.pow_bits),
+            "captcha.flow.pow_bits",
+            format!("{} is outside 8-28", flow.pow_bits),
+        );
+        check(
+            !flow.pow_ttl_secs.is_zero(),
+            "captcha.flow.pow_ttl_secs",
+            "must be greater than 0".into(),
+        );
+    }
+
+    let batch = &captcha.write_batch;
+    if batch.enabled {
+        check(
+            batch.max_ops > 0,
+            "captcha.write_batch.max_ops",
+            "must be greater than 0".into(),
+        );
+        check(
+            batch.flush_ms <= 100,
+            "captcha.write_batch.flush_ms",
+            format!(
+                "{} is over 100 (every challenge waits up to this long)",
+                batch.flush_ms
+            ),
+        );
+        check(
+            batch.queue_capacity >= batch.max_ops,
+            "captcha.write_batch.queue_capacity",
+            format!(
+                "{} is less than max_ops ({})",
+                batch.queue_capacity, batch.max_ops
+            ),
+        );
+    }
+
+    let providers = crate::captcha::builtin_names();
+    for (key, name) in [
+        ("captcha.providers.easy", &captcha.providers.easy),
+        ("captcha.providers.medium", &captcha.providers.medium),
+        ("captcha.providers.hard", &captcha.providers.hard),
+        ("captcha.providers.extreme", &captcha.providers.extreme),
+    ] {
+        check(
+            providers.contains(&name.as_str()),
+            key,
+            format!(
+                "unknown provider `{}` (expected one of: {})",
+                name,
+                providers.join(", ")
+            ),
+        );
+    }
+
+    if captcha.shadow.enabled {
+        check(
+            providers.contains(&captcha.shadow.provider.as_str()),
+            "captcha.shadow.provider",
+            format!(
+                "unknown provider `{}` (expected one of: {})",
+                captcha.shadow.provider,
+                providers.join(", ")
+            ),
+        );
+    }
+    check(
+        (0.0..=1.0).contains(&captcha.shadow.sample_rate),
+        "captcha.shadow.sample_rate",
+        format!("{} is outside 0.0-1.0", captcha.shadow.sample_rate),
+    );
+
+    let profiles = &captcha.difficulty;
+    for (name, profile) in [
+        ("easy", &profiles.easy),
+        ("medium", &profiles.medium),
+        ("hard", &profiles.hard),
+        ("extreme", &profiles.extreme),
+    ] {
+        let key = |field: &str| format!("captcha.difficulty.{}.{}", name, field);
+        check(
+            (1..=32).contains(&profile.answer_length),
+            &key("answer_length"),
+            format!("{} is outside 1-32", profile.answer_length),
+        );
+        check(
+            profile.timeout_secs > 0,
+            &key("timeout_secs"),
+            "must be greater than 0".into(),
+        );
+        check(
+            profile.grid_size.0 > 0 && profile.grid_size.1 > 0,
+            &key("grid_size"),
+            format!("{:?} has an empty side", profile.grid_size),
+        );
+        for (field, value) in [
+            ("rotation", profile.rotation),
+            ("skew", profile.skew),
+            ("jitter", profile.jitter),
+            ("wave_amplitude", profile.wave_amplitude),
+        ] {
+            check(
+                value.is_finite() && value >= 0.0,
+                &key(field),
+                format!("{} is not a non-negative number", value),
+            );
+        }
+    }
+
+    let rate = &config.rate_limit;
+    check(
+        rate.max_requests_per_minute > 0,
+        "rate_limit.max_requests_per_minute",
+        "must be greater than 0".into(),
+    );
+    check(
+        rate.max_failed_attempts > 0,
+        "rate_limit.max_failed_attempts",
+        "must be greater than 0".into(),
+    );
+    check(
+        !rate.soft_lock_duration_secs.is_zero(),
+        "rate_limit.soft_lock_duration_secs",
+        "must be greater than 0".into(),
+    );
+    check(
+        !rate.ban_duration_secs.is_zero(),
+        "rate_limit.ban_duration_secs",
+        "must be greater than 0".into(),
+    );
+    if rate.max_concurrent_per_circuit > 0 {
+        check(
+            rate.concurrency_lease_ms > 0,
+            "rate_limit.concurrency_lease_ms",
+            "must be greater than 0".into(),
+        );
+    }
+    if rate.verify.lockout_after_wrong > 0 {
+        check(
+            !rate.verify.wrong_window_secs.is_zero(),
+            "rate_limit.verify.wrong_window_secs",
+            "must be greater than 0".into(),
+        );
+        check(
+            !rate.verify.lockout_secs.is_zero(),
+            "rate_limit.verify.lockout_secs",
+            "must be greater than 0".into(),
+        );
+    }
+    check(
+        rate.verify.tarpit_delay_ms <= 30_000,
+        "rate_limit.verify.tarpit_delay_ms",
+        format!(
+            "{} holds connections too long (at most 30000)",
+            rate.verify.tarpit_delay_ms
+        ),
+    );
+    if rate.cluster.enabled {
+        check(
+            config.cluster_enabled,
+            "rate_limit.cluster.enabled",
+            "needs cluster_enabled".into(),
+        );
+        check(
+            rate.cluster.sync_interval_ms > 0,
+            "rate_limit.cluster.sync_interval_ms",
+            "must be greater than 0".into(),
+        );
+        check(
+            rate.cluster.max_circuits_per_sync > 0,
+            "rate_limit.cluster.max_circuits_per_sync",
+            "must be greater than 0".into(),
+        );
+    }
+
+    let vip = &config.vip;
+    check(
+        vip.min_solves > 0,
+        "vip.min_solves",
+        "must be greater than 0".into(),
+    );
+    check(
+        !vip.passport_ttl_secs.is_zero(),
+        "vip.passport_ttl_secs",
+        "must be greater than 0".into(),
+    );
+    check(
+        vip.skip_challenge_below <= ThreatLevel::MAX.value(),
+        "vip.skip_challenge_below",
+        format!("must be 0-{}", ThreatLevel::MAX.value()),
+    );
+
+    let farm = &config.farm_detection;
+    check(
+        farm.window >= farm.min_samples,
+        "farm_detection.window",
+        format!("must be at least min_samples ({})", farm.min_samples),
+    );
+    check(
+        farm.max_uniform_cv >= 0.0,
+        "farm_detection.max_uniform_cv",
+        "must not be negative".into(),
+    );
+
+    let rules = &config.rules;
+    check(
+        rules.interval_secs > 0,
+        "rules.interval_secs",
+        "must be greater than 0".into(),
+    );
+    check(
+        rules.window_secs > 0,
+        "rules.window_secs",
+        "must be greater than 0".into(),
+    );
+    for (i, rule) in rules.rules.iter().enumerate() {
+        if let Err(e) = crate::rules::check_rule(rule) {
+            check(false, "rules.rule", format!("{:#}", e));
+        }
+        check(
+            !rules.rules[..i].iter().any(|other| other.name == rule.name),
+            "rules.rule",
+            format!("duplicate rule name `{}`", rule.name),
+        );
+    }
+
+    let hooks = &config.webhooks;
+    check(
+        !hooks.enabled || !hooks.urls.is_empty(),
+        "webhooks.urls",
+        "must not be empty when webhooks are enabled".into(),
+    );
+    for url in &hooks.urls {
+        check(
+            is_http_url(url),
+            "webhooks.urls",
+            format!("`{}` is not an http(s) URL", url),
+        );
+    }
+    if let Some(ref proxy) = hooks.socks_proxy {
+        check(
+            is_socks_url(proxy),
+            "webhooks.socks_proxy",
+            format!("`{}` is not a socks5:// or socks5h:// URL", proxy),
+        );
+    }
+    check(
+        hooks.timeout_secs > 0,
+        "webhooks.timeout_secs",
+        "must be greater than 0".into(),
+    );
+    check(
+        hooks.min_threat_level <= ThreatLevel::MAX.value(),
+        "webhooks.min_threat_level",
+        format!("must be 0-{}", ThreatLevel::MAX.value()),
+    );
+    check(
+        hooks.mass_ban_count > 0,
+        "webhooks.mass_ban_count",
+        "must be greater than 0".into(),
+    );
+    check(
+        hooks.mass_ban_window_secs > 0,
+        "webhooks.mass_ban_window_secs",
+        "must be greater than 0".into(),
+    );
+
+    check(
+        config.warmup.min_fill_pct <= 100,
+        "warmup.min_fill_pct",
+        format!("{} is over 100", config.warmup.min_fill_pct),
+    );
+    check(
+        config.ammo_starvation.fill_pct <= 100,
+        "ammo_starvation.fill_pct",
+        format!("{} is over 100", config.ammo_starvation.fill_pct),
+    );
+    let sampling = &config.sampling;
+    check(
+        (0.0..=1.0).contains(&sampling.rate),
+        "sampling.rate",
+        format!("{} is outside 0.0-1.0", sampling.rate),
+    );
+    check(
+        !sampling.enabled || sampling.capacity > 0,
+        "sampling.capacity",
+        "must be greater than 0".into(),
+    );
+    let slo = &config.slo;
+    for (key, target) in [
+        ("slo.availability_target", slo.availability_target),
+        ("slo.latency_target", slo.latency_target),
+    ] {
+        check(
+            target > 0.0 && target < 1.0,
+            key,
+            format!(
+                "{} is outside 0.0-1.0 (exclusive), leaving no error budget",
+                target
+            ),
+        );
+    }
+    check(
+        slo.short_window_secs <= slo.long_window_secs && slo.long_window_secs <= slo.window_secs,
+        "slo.long_window_secs",
+        format!(
+            "windows must grow: short {}s <= long {}s <= window {}s",
+            slo.short_window_secs, slo.long_window_secs, slo.window_secs
+        ),
+    );
+    check(
+        slo.burn_rate_alert > 0.0,
+        "slo.burn_rate_alert",
+        "must be greater than 0".into(),
+    );
+    check(
+        config.keyspace.scan_count > 0,
+        "keyspace.scan_count",
+        "must be greater than 0".into(),
+    );
+    let access_log = &config.access_log;
+    if access_log.enabled {
+        check(
+            !access_log.path.is_empty() || access_log.redis_stream,
+            "access_log.path",
+            "is empty and redis_stream is off, so nothing would be logged".into(),
+        );
+        check(
+            access_log.queue_capacity > 0,
+            "access_log.queue_capacity",
+            "must be greater than 0".into(),
+        );
+        check(
+            !access_log.redis_stream || access_log.stream_max_len > 0,
+            "access_log.stream_max_len",
+            "must be greater than 0".into(),
+        );
+    }
+    let hash_key = &config.privacy.hash_key;
+    check(
+        hash_key.is_empty() || hash_key.len() >= 16,
+        "privacy.hash_key",
+        "is too short to keep hashed identifiers from being guessed; use at least 16 characters"
+            .into(),
+    );
+    let allowlist = &config.allowlist;
+    check(
+        axum::http::HeaderName::try_from(allowlist.header.as_str()).is_ok(),
+        "allowlist.header",
+        format!("`{}` is not a valid header name", allowlist.header),
+    );
+    check(
+        allowlist.max_ttl_secs > 0,
+        "allowlist.max_ttl_secs",
+        "must be greater than 0".into(),
+    );
+    check(
+        (1..=allowlist.max_ttl_secs).contains(&allowlist.default_ttl_secs),
+        "allowlist.default_ttl_secs",
+        format!("must be 1-{} (max_ttl_secs)", allowlist.max_ttl_secs),
+    );
+    check(
+        (1..=9).contains(&config.compression.level),
+        "compression.level",
+        format!("{} is outside 1-9", config.compression.level),
+    );
+    let proxies = &config.trusted_proxies;
+    for address in &proxies.addresses {
+        check(
+            crate::trusted_proxy::parse_address(address).is_some(),
+            "trusted_proxies.addresses",
+            format!("`{}` is not an IP address or CIDR range", address),
+        );
+    }
+    for name in &proxies.headers {
+        check(
+            axum::http::HeaderName::try_from(name.as_str()).is_ok(),
+            "trusted_proxies.headers",
+            format!("`{}` is not a valid header name", name),
+        );
+    }
+    if let Some(ref path) = config.admin.path
+        && let Err(e) = crate::admin_access::check_path(path)
+    {
+        check(false, "admin.path", format!("`{}` {}", path, e));
+    }
+    for address in &config.admin.allowed_addresses {
+        check(
+            crate::trusted_proxy::parse_address(address).is_some(),
+            "admin.allowed_addresses",
+            format!("`{}` is not an IP address or CIDR range", address),
+        );
+    }
+
+    let queue = &config.verify_queue;
+    check(
+        (0.0..=1.0).contains(&queue.excess_share),
+        "verify_queue.excess_share",
+        format!("{} is outside 0.0-1.0", queue.excess_share),
+    );
+    check(
+        queue.per_circuit_rate > 0.0,
+        "verify_queue.per_circuit_rate",
+        "must be greater than 0".into(),
+    );
+
+    let experiments = &config.experiments.experiments;
+    for (i, experiment) in experiments.iter().enumerate() {
+        check(
+            !experiments[..i]
+                .iter()
+                .any(|other| other.name == experiment.name),
+            "experiments.experiment",
+            format!("duplicate experiment name `{}`", experiment.name),
+        );
+        check(
+            experiment.variants.iter().any(|v| v.weight > 0),
+            "experiments.experiment.variant",
+            format!(
+                "`{}` needs a variant with a weight above 0",
+                experiment.name
+            ),
+        );
+        for (j, variant) in experiment.variants.iter().enumerate() {
+            check(
+                !experiment.variants[..j]
+                    .iter()
+                    .any(|other| other.name == variant.name),
+                "experiments.experiment.variant",
+                format!(
+                    "duplicate variant name `{}` in `{}`",
+                    variant.name, experiment.name
+                ),
+            );
+            if let Some(ref provider) = variant.provider {
+                check(
+                    providers.contains(&provider.as_str()),
+                    "experiments.experiment.variant.provider",
+                    format!(
+                        "unknown provider `{}` (expected one of: {})",
+                        provider,
+                        providers.join(", ")
+                    ),
+                );
+            }
+            check(
+                (-3..=3).contains(&variant.difficulty_shift),
+                "experiments.experiment.variant.difficulty_shift",
+                format!("{} is outside -3 to 3", variant.difficulty_shift),
+            );
+        }
+    }
+
+    let abandonment = &config.abandonment;
+    check(
+        abandonment.sweep_interval_secs > 0,
+        "abandonment.sweep_interval_secs",
+        "must be greater than 0".into(),
+    );
+    check(
+        abandonment.window_secs >= 60,
+        "abandonment.window_secs",
+        "must be at least 60".into(),
+    );
+    check(
+        (0.0..=1.0).contains(&abandonment.deescalate_ratio),
+        "abandonment.deescalate_ratio",
+        format!("{} is outside 0.0-1.0", abandonment.deescalate_ratio),
+    );
+    check(
+        abandonment.min_threat_level <= ThreatLevel::MAX.value(),
+        "abandonment.min_threat_level",
+        format!("must be 0-{}", ThreatLevel::MAX.value()),
+    );
+    for &window in &config.challenge_stats.windows_secs {
+        check(
+            (60..=86_400).contains(&window),
+            "challenge_stats.windows_secs",
+            format!("{} is outside 60-86400", window),
+        );
+    }
+    let backup = &config.backup;
+    if backup.enabled {
+        check(
+            !backup.dir.is_empty(),
+            "backup.dir",
+            "must be set when backups are enabled".into(),
+        );
+        check(
+            backup.secret.len() >= 16,
+            "backup.secret",
+            "must be at least 16 characters when backups are enabled".into(),
+        );
+        check(
+            backup.interval_secs > 0,
+            "backup.interval_secs",
+            "must be greater than 0".into(),
+        );
+        check(
+            backup.keep > 0,
+            "backup.keep",
+            "must be greater than 0".into(),
+        );
+    }
+    check(
+        config
+            .flags
+            .refresh_secs
+            .within(TtlSecs::from_secs(1), TtlSecs::from_hours(1)),
+        "flags.refresh_secs",
+        format!("{} is outside 1s-1h", config.flags.refresh_secs),
+    );
+
+    let deg = &config.degradation;
+    check(
+        !deg.check_interval_secs.is_zero(),
+        "degradation.check_interval_secs",
+        "must be greater than 0".into(),
+    );
+    for (key, ratio) in [
+        (
+            "degradation.degraded_memory_ratio",
+            deg.degraded_memory_ratio,
+        ),
+        (
+            "degradation.critical_memory_ratio",
+            deg.critical_memory_ratio,
+        ),
+    ] {
+        check(
+            ratio > 0.0 && ratio <= 1.0,
+            key,
+            format!("{} is outside (0, 1]", ratio),
+        );
+    }
+    check(
+        deg.degraded_memory_ratio <= deg.critical_memory_ratio,
+        "degradation.degraded_memory_ratio",
+        "must not exceed critical_memory_ratio".into(),
+    );
+    check(
+        deg.degraded_latency_ms <= deg.critical_latency_ms,
+        "degradation.degraded_latency_ms",
+        "must not exceed critical_latency_ms".into(),
+    );
+    check(
+        !deg.degraded_challenge_ttl_secs.is_zero(),
+        "degradation.degraded_challenge_ttl_secs",
+        "must be greater than 0".into(),
+    );
+    check(
+        deg.retry_after_secs
+            .within(TtlSecs::from_secs(1), TtlSecs::from_hours(1)),
+        "degradation.retry_after_secs",
+        format!("{} is outside 1s-1h", deg.retry_after_secs),
+    );
+
+    for (group, policy) in [
+        ("gate", &config.security_headers.gate),
+        ("api", &config.security_headers.api),
+        ("admin", &config.security_headers.admin),
+    ] {
+        let Some(cors) = &policy.cors else { continue };
+        check(
+            cors.max_age_secs
+                .within(TtlSecs::ZERO, TtlSecs::from_days(1)),
+            &format!("security_headers.{}.cors.max_age_secs", group),
+            format!("{} is over 1d (browsers cap it lower)", cors.max_age_secs),
+        );
+        for origin in &cors.allowed_origins {
+            check(
+                origin == "*" || is_http_url(origin),
+                &format!("security_headers.{}.cors.allowed_origins", group),
+                format!("`{}` is not \"*\" or an http(s) origin", origin),
+            );
+        }
+    }
+
+    let tls = &config.tls;
+    if tls.enabled {
+        for (key, path) in [
+            ("tls.cert_path", &tls.cert_path),
+            ("tls.key_path", &tls.key_path),
+        ]
+        .into_iter()
+        .chain(tls.client_ca_path.iter().map(|p| ("tls.client_ca_path", p)))
+        {
+            check(
+                std::path::Path::new(path).is_file(),
+                key,
+                format!("`{}` does not exist", path),
+            );
+        }
+    } else {
+        check(
+            tls.client_ca_path.is_none(),
+            "tls.client_ca_path",
+            "has no effect unless tls.enabled = true".into(),
+        );
+    }
+
+    let grpc = &config.grpc;
+    check(
+        grpc.listen_addr.parse::<std::net::SocketAddr>().is_ok(),
+        "grpc.listen_addr",
+        format!("`{}` is not an IP:port address", grpc.listen_addr),
+    );
+    check(
+        !grpc.enabled || !grpc.exposed_without_token(),
+        "grpc.token",
+        "must be set when listen_addr is not a loopback address".into(),
+    );
+    check(
+        grpc.stats_interval_secs > 0,
+        "grpc.stats_interval_secs",
+        "must be greater than 0".into(),
+    );
+
+    let telemetry = &config.telemetry;
+    check(
+        (0.0..=1.0).contains(&telemetry.sample_ratio),
+        "telemetry.sample_ratio",
+        format!("{} is outside [0, 1]", telemetry.sample_ratio),
+    );
+    check(
+        is_http_url(&telemetry.otlp_endpoint),
+        "telemetry.otlp_endpoint",
+        format!("`{}` is not an http(s) URL", telemetry.otlp_endpoint),
+    );
+
+    issues
+}
+
+/// Attach file lines to issues for keys that appear in the file
+pub fn locate_all(text: &str, issues: &mut [ConfigIssue]) {
+    let Ok(table) = DeTable::parse(text) else {
+        return;
+    };
+    for issue in issues.iter_mut().filter(|i| i.line.is_none()) {
+        issue.line = locate(text, &table, &issue.key);
+    }
+}
+
+fn is_http_url(value: &str) -> bool {
+    url::Url::parse(value)
+        .map(|url| matches!(url.scheme(), "http" | "https") && url.host().is_some())
+        .unwrap_or(false)
+}
+
+fn is_socks_url(value: &str) -> bool {
+    url::Url::parse(value)
+        .map(|url| matches!(url.scheme(), "socks5" | "socks5h") && url.host().is_some())
+        .unwrap_or(false)
+}
+
+/// Key names along an ignored path (skipping `Option`/newtype wrappers)
+fn key_segments(path: &KeyPath<'_>) -> Vec<String> {
+    let (parent, segment) = match path {
+        KeyPath::Root => return Vec::new(),
+        KeyPath::Seq { parent, index } => (*parent, Some(index.to_string())),
+        KeyPath::Map { parent, key } => (*parent, Some(key.clone())),
+        KeyPath::Some { parent }
+        | KeyPath::NewtypeStruct { parent }
+        | KeyPath::NewtypeVariant { parent } => (*parent, None),
+    };
+    let mut segments = key_segments(parent);
+    segments.extend(segment);
+    segments
+}
+
+/// Line of the deepest part of a dotted key that exists in the file
+fn locate(text: &str, table: &Spanned<DeTable<'_>>, key: &str) -> Option<usize> {
+    let mut current = table.get_ref();
+    let mut line = None;
+
+    for segment in key.split('.') {
+        let (name, value) = current.get_key_value(segment)?;
+        line = Some(line_of(text, name.span().start));
+        match value.get_ref() {
+            DeValue::Table(table) => current = table,
+            _ => break,
+        }
+    }
+
+    line
+}
+
+fn line_of(text: &str, offset: usize) -> usize {
+    text[..offset.min(text.len())].matches('\n').count() + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unknown_keys_reported_with_lines() {
+        let text = "redis_url = \"redis://127.0.0.1:6379\"\n\
+                    treat_level = 3\n\
+                    \n\
+                    [rate_limit]\n\
+                    max_requests_per_minute = 60\n\
+                    ban_duraton_secs = 10\n";
+
+        let (config, issues) = check_file(text);
+        assert!(config.is_some());
+        assert_eq!(issues.len(), 2);
+        assert_eq!(issues[0].key, "treat_level");
+        assert_eq!(issues[0].line, Some(2));
+        assert_eq!(issues[1].key, "rate_limit.ban_duraton_secs");
+        assert_eq!(issues[1].line, Some(6));
+
+        let (config, issues) = check_file("[captcha]\npassport_ttl_secs = \"ten\"\n");
+        assert!(config.is_none());
+        assert_eq!(issues[0].line, Some(2));
+    }
+
+    #[test]
+    fn test_value_checks() {
+        let mut config = AppConfig::default();
+        assert!(check_values(&config).is_empty());
+
+        config.initial_threat_level = 11;
+        config.captcha.challenge_ttl_secs = TtlSecs::ZERO;
+        config.captcha.write_batch.queue_capacity = 10;
+        config.captcha.difficulty.hard.answer_length = 0;
+        config.rate_limit.concurrency_lease_ms = 0;
+        config.slo.latency_target = 1.0;
+        config.privacy.hash_key = "cerberus".to_string();
+        config
+            .trusted_proxies
+            .addresses
+            .push("10.0.0.0/33".to_string());
+        config.admin.path = Some("admin/".to_string());
+        config
+            .experiments
+            .experiments
+            .push(crate::experiments::ExperimentConfig {
+                name: "grid".to_string(),
+                variants: vec![crate::experiments::VariantConfig {
+                    name: "grid".to_string(),
+                    weight: 1,
+                    provider: Some("grid".to_string()),
+                    difficulty_shift: 0,
+                }],
+            });
+        config.degradation.degraded_memory_ratio = 0.95;
+        config.telemetry.otlp_endpoint = "collector:4318".to_string();
+
+        let keys: Vec<_> = check_values(&config).into_iter().map(|i| i.key).collect();
+        assert_eq!(
+            keys,
+            [
+                "initial_threat_level",
+                "captcha.challenge_ttl_secs",
+                "captcha.write_batch.queue_capacity",
+                "captcha.difficulty.hard.answer_length",
+                "rate_limit.concurrency_lease_ms",
+                "slo.latency_target",
+                "privacy.hash_key",
+                "trusted_proxies.addresses",
+                "admin.path",
+                "experiments.experiment.variant.provider",
+                "degradation.degraded_memory_ratio",
+                "telemetry.otlp_endpoint",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_shipped_config_is_valid() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/../../config/fortify.toml");
+        let text = std::fs::read_to_string(path).unwrap();
+
+        let (config, issues) = check_file(&text);
+        assert_eq!(issues, []);
+        assert_eq!(check_values(&config.unwrap()), []);
+    }
+}
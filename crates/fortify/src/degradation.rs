@@ -0,0 +1,318 @@
+//! Redis memory guard, degradation and offline mode.
+//!
+//! A watchdog samples Redis `INFO memory` and PING latency. When either
+//! crosses its threshold, Fortify sheds Redis load instead of failing with
+//! cascading 500s:
+//! - **Degraded**: shorter challenge TTLs, signed stateless passports
+//! - **Critical**: additionally refuses new challenge storage and serves a
+//!   retry page
+//! - **Offline**: Redis unreachable; sealed challenges and signed passports
+//!   are verified statelessly and circuit mutations are queued. When Redis
+//!   returns the guard reattaches and replays the queue.
+//!
+//! Stepping down a level requires several consecutive healthy samples so a
+//! borderline instance does not flap between modes.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU8, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::config::DegradationConfig;
+use crate::metrics;
+use crate::state::AppState;
+
+/// Redis health level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DegradationLevel {
+    /// Redis healthy, full functionality
+    Normal = 0,
+    /// Redis under pressure, shed non-essential state
+    Degraded = 1,
+    /// Redis near exhaustion, refuse new challenges
+    Critical = 2,
+    /// Redis unreachable, stateless operation only
+    Offline = 3,
+}
+
+impl DegradationLevel {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Self::Normal,
+            1 => Self::Degraded,
+            2 => Self::Critical,
+            _ => Self::Offline,
+        }
+    }
+}
+
+/// One observation of Redis health
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RedisSample {
+    /// `used_memory` from INFO
+    pub used_memory: u64,
+    /// `maxmemory` from INFO (0 = unlimited)
+    pub maxmemory: u64,
+    /// PING round-trip in milliseconds
+    pub latency_ms: u64,
+}
+
+impl RedisSample {
+    /// Parse the relevant fields out of an `INFO memory` reply
+    pub fn from_info(info: &str, latency_ms: u64) -> Self {
+        let mut sample = Self {
+            latency_ms,
+            ..Default::default()
+        };
+        for line in info.lines() {
+            let Some((key, value)) = line.trim().split_once(':') else {
+                continue;
+            };
+            match key {
+                "used_memory" => sample.used_memory = value.parse().unwrap_or(0),
+                "maxmemory" => sample.maxmemory = value.parse().unwrap_or(0),
+                _ => {}
+            }
+        }
+        sample
+    }
+}
+
+/// Shared degradation state, read on the request path
+pub struct DegradationState {
+    level: AtomicU8,
+    /// PING round-trip of the last sample, in milliseconds
+    latency_ms: AtomicU64,
+    config: DegradationConfig,
+}
+
+impl DegradationState {
+    pub fn new(config: DegradationConfig) -> Self {
+        Self {
+            level: AtomicU8::new(DegradationLevel::Normal as u8),
+            latency_ms: AtomicU64::new(0),
+            config,
+        }
+    }
+
+    /// Current level
+    pub fn level(&self) -> DegradationLevel {
+        DegradationLevel::from_u8(self.level.load(Ordering::Relaxed))
+    }
+
+    /// Force a level (e.g. offline at startup)
+    pub fn set_level(&self, level: DegradationLevel) {
+        self.level.store(level as u8, Ordering::Relaxed);
+    }
+
+    /// Redis PING round-trip of the last sample, in milliseconds
+    pub fn latency_ms(&self) -> u64 {
+        self.latency_ms.load(Ordering::Relaxed)
+    }
+
+    /// Should passports be issued as signed tokens instead of Redis keys?
+    pub fn stateless_passports(&self) -> bool {
+        self.level() >= DegradationLevel::Degraded
+    }
+
+    /// Should new challenge storage be refused?
+    ///
+    /// Offline mode issues sealed challenges instead, so only Critical refuses.
+    pub fn refuses_new_challenges(&self) -> bool {
+        self.level() == DegradationLevel::Critical
+    }
+
+    /// Challenge TTL to use given the configured normal TTL
+    pub fn challenge_ttl(&self, normal_ttl: u64) -> u64 {
+        if self.stateless_passports() {
+            normal_ttl.min(self.config.degraded_challenge_ttl_secs.secs())
+        } else {
+            normal_ttl
+        }
+    }
+
+    /// Retry-After (seconds) for refused requests
+    pub fn retry_after_secs(&self) -> u64 {
+        self.config.retry_after_secs.secs()
+    }
+
+    /// Classify a sample against the configured thresholds
+    pub fn evaluate(&self, sample: &RedisSample) -> DegradationLevel {
+        if !self.config.enabled {
+            return DegradationLevel::Normal;
+        }
+
+        let budget = if sample.maxmemory > 0 {
+            sample.maxmemory
+        } else {
+            self.config.max_memory_bytes
+        };
+        let ratio = if budget > 0 {
+            sample.used_memory as f64 / budget as f64
+        } else {
+            0.0
+        };
+
+        if ratio >= self.config.critical_memory_ratio
+            || sample.latency_ms >= self.config.critical_latency_ms
+        {
+            DegradationLevel::Critical
+        } else if ratio >= self.config.degraded_memory_ratio
+            || sample.latency_ms >= self.config.degraded_latency_ms
+        {
+            DegradationLevel::Degraded
+        } else {
+            DegradationLevel::Normal
+        }
+    }
+}
+
+/// Sample Redis memory usage and round-trip latency
+async fn sample_redis(redis: &mut redis::aio::ConnectionManager) -> Result<RedisSample> {
+    let started = Instant::now();
+    let _: String = redis::cmd("PING")
+        .query_async(redis)
+        .await
+        .context("Redis PING failed")?;
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    let info: String = redis::cmd("INFO")
+        .arg("memory")
+        .query_async(redis)
+        .await
+        .context("Redis INFO failed")?;
+
+    Ok(RedisSample::from_info(&info, latency_ms))
+}
+
+/// Background worker: watch Redis, move between levels, reattach and replay
+pub async fn redis_guard_worker(
+    state: AppState,
+    mut shutdown: tokio::sync::broadcast::Receiver<()>,
+) {
+    let guard = state.degradation.clone();
+    let interval = Duration::from_secs(guard.config.check_interval_secs.secs().max(1));
+    let mut healthy_streak = 0u32;
+
+    tracing::info!("🛡️ Redis guard started (interval: {:?})", interval);
+
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {
+                let observed = match observe(&state).await {
+                    Ok(sample) => {
+                        guard.latency_ms.store(sample.latency_ms, Ordering::Relaxed);
+                        metrics::REDIS_UP.set(1);
+                        metrics::REDIS_PING_SECONDS.set(sample.latency_ms as f64 / 1000.0);
+                        guard.evaluate(&sample)
+                    }
+                    Err(e) => {
+                        tracing::error!(error = %e, "Redis guard sample failed");
+                        metrics::REDIS_UP.set(0);
+                        DegradationLevel::Offline
+                    }
+                };
+
+                let current = guard.level();
+                if observed > current {
+                    healthy_streak = 0;
+                    guard.set_level(observed);
+                    tracing::warn!(from = ?current, to = ?observed, "Entering Redis degradation mode");
+                } else if observed < current {
+                    healthy_streak += 1;
+                    if healthy_streak >= guard.config.recovery_samples {
+                        healthy_streak = 0;
+                        guard.set_level(observed);
+                        tracing::info!(from = ?current, to = ?observed, "Redis pressure eased");
+                    }
+                } else {
+                    healthy_streak = 0;
+                }
+
+                if guard.level() != DegradationLevel::Offline {
+                    replay_pending(&state).await;
+                }
+            }
+            _ = shutdown.recv() => {
+                tracing::info!("🛡️ Redis guard shutting down...");
+                break;
+            }
+        }
+    }
+}
+
+/// Sample Redis, establishing the connection first if we started offline
+async fn observe(state: &AppState) -> Result<RedisSample> {
+    let mut redis = match state.redis_conn() {
+        Some(conn) => conn,
+        None => {
+            let conn = redis::aio::ConnectionManager::new(state.redis_client.clone())
+                .await
+                .context("Redis still unreachable")?;
+            state.attach_redis(conn.clone());
+            tracing::info!("✅ Redis connection established");
+            conn
+        }
+    };
+
+    sample_redis(&mut redis).await
+}
+
+/// Replay circuit mutations queued while offline
+async fn replay_pending(state: &AppState) {
+    if state.mutation_queue.is_empty() {
+        return;
+    }
+    let Some(mut redis) = state.redis() else {
+        return;
+    };
+
+    let applied = state
+        .mutation_queue
+        .replay(&state.circuit_tracker, &mut redis)
+        .await;
+    tracing::info!(
+        applied = applied,
+        remaining = state.mutation_queue.len(),
+        "Replayed offline circuit mutations"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_info_memory() {
+        let info =
+            "# Memory\r\nused_memory:1048576\r\nused_memory_human:1.00M\r\nmaxmemory:4194304\r\n";
+        let sample = RedisSample::from_info(info, 3);
+        assert_eq!(sample.used_memory, 1_048_576);
+        assert_eq!(sample.maxmemory, 4_194_304);
+        assert_eq!(sample.latency_ms, 3);
+    }
+
+    #[test]
+    fn test_evaluate_thresholds() {
+        let state = DegradationState::new(DegradationConfig::default());
+        let sample = |used, latency_ms| RedisSample {
+            used_memory: used,
+            maxmemory: 100,
+            latency_ms,
+        };
+
+        assert_eq!(state.evaluate(&sample(50, 1)), DegradationLevel::Normal);
+        assert_eq!(state.evaluate(&sample(80, 1)), DegradationLevel::Degraded);
+        assert_eq!(state.evaluate(&sample(95, 1)), DegradationLevel::Critical);
+        assert_eq!(state.evaluate(&sample(10, 600)), DegradationLevel::Critical);
+
+        // No maxmemory and no configured budget: only latency counts
+        let unbounded = RedisSample {
+            used_memory: u64::MAX,
+            maxmemory: 0,
+            latency_ms: 1,
+        };
+        assert_eq!(state.evaluate(&unbounded), DegradationLevel::Normal);
+    }
+}
@@ -0,0 +1,159 @@
+//! Node drain, for taking a node out of a cluster without disruption.
+//!
+//! Started with `POST /admin/drain`. A draining node issues no new
+//! challenges (the gate and `/challenge` answer 503 with `Retry-After`, and
+//! `/ready` fails so the load balancer stops sending visitors), but still
+//! checks answers to challenges it already handed out and keeps validating
+//! passports. Peers hear `draining` in gossip and stop shedding load to it.
+//!
+//! Once every challenge issued before the drain has expired
+//! (`captcha.challenge_ttl_secs`), the drain is complete and the node either
+//! shuts down gracefully or stays up in standby, as requested. Like
+//! maintenance mode, it doesn't survive a restart.
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU8, AtomicU64, Ordering};
+use tokio::sync::Notify;
+
+/// What the node does once drained
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AfterDrain {
+    /// Shut down gracefully
+    Exit,
+    /// Keep running, still drained, until the drain is cancelled
+    #[default]
+    Standby,
+}
+
+impl AfterDrain {
+    fn to_u8(self) -> u8 {
+        match self {
+            Self::Exit => 0,
+            Self::Standby => 1,
+        }
+    }
+
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Self::Exit,
+            _ => Self::Standby,
+        }
+    }
+}
+
+/// The drain switch, read on every challenge request
+#[derive(Default)]
+pub struct Drain {
+    draining: AtomicBool,
+    /// When the drain started (Unix seconds)
+    since: AtomicI64,
+    after: AtomicU8,
+    /// Outstanding challenges have expired
+    complete: AtomicBool,
+    /// Bumped on every start and cancel, so a stale timer does nothing
+    generation: AtomicU64,
+    /// Woken when a drain completes with `AfterDrain::Exit`
+    exit: Notify,
+}
+
+impl Drain {
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// When the drain started, if one is under way
+    pub fn since(&self) -> Option<i64> {
+        self.is_draining()
+            .then(|| self.since.load(Ordering::Relaxed))
+    }
+
+    pub fn after(&self) -> AfterDrain {
+        AfterDrain::from_u8(self.after.load(Ordering::Relaxed))
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.is_draining() && self.complete.load(Ordering::Relaxed)
+    }
+
+    /// Start draining; returns the generation to complete, or `None` if a
+    /// drain was already under way (its `after` is updated)
+    pub fn start(&self, after: AfterDrain) -> Option<u64> {
+        self.after.store(after.to_u8(), Ordering::Relaxed);
+        if self.draining.swap(true, Ordering::Relaxed) {
+            return None;
+        }
+        self.since
+            .store(chrono::Utc::now().timestamp(), Ordering::Relaxed);
+        self.complete.store(false, Ordering::Relaxed);
+        Some(self.generation.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+
+    /// Stop draining; returns whether a drain was under way
+    pub fn cancel(&self) -> bool {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+        self.complete.store(false, Ordering::Relaxed);
+        self.draining.swap(false, Ordering::Relaxed)
+    }
+
+    /// Mark drain `generation` complete, unless it was cancelled since;
+    /// wakes `exited` if the node should now shut down
+    pub fn complete(&self, generation: u64) -> bool {
+        if !self.is_draining() || self.generation.load(Ordering::Relaxed) != generation {
+            return false;
+        }
+        self.complete.store(true, Ordering::Relaxed);
+        if self.after() == AfterDrain::Exit {
+            self.exit.notify_one();
+        }
+        true
+    }
+
+    /// Wait for a drain to complete with `AfterDrain::Exit`
+    pub async fn exited(&self) {
+        self.exit.notified().await
+    }
+}
+
+/// Complete drain `generation` once challenges issued before it have expired
+pub async fn drain_worker(state: crate::state::AppState, generation: u64) {
+    let ttl = state.config.captcha.challenge_ttl_secs.duration();
+    tokio::time::sleep(ttl).await;
+
+    if state.drain.complete(generation) {
+        match state.drain.after() {
+            AfterDrain::Exit => tracing::warn!("🚰 Drain complete; shutting down"),
+            AfterDrain::Standby => tracing::warn!("🚰 Drain complete; standing by"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_drain_lifecycle() {
+        let drain = Drain::default();
+        assert!(!drain.is_draining());
+
+        let generation = drain.start(AfterDrain::Exit).unwrap();
+        assert!(drain.start(AfterDrain::Exit).is_none());
+        assert!(drain.since().is_some());
+        assert!(!drain.is_complete());
+
+        // A cancelled drain's timer is ignored
+        assert!(drain.cancel());
+        assert!(!drain.complete(generation));
+        assert!(!drain.is_draining());
+
+        let generation = drain.start(AfterDrain::Exit).unwrap();
+        assert!(drain.complete(generation));
+        assert!(drain.is_complete());
+        // The exit was signalled even though nobody was waiting yet
+        tokio::time::timeout(Duration::from_secs(1), drain.exited())
+            .await
+            .unwrap();
+    }
+}
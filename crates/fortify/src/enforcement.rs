@@ -0,0 +1,85 @@
+//! Observe-only mode.
+//!
+//! With `enforcement.observe`, `/validate` answers 200 to every request and
+//! the rules engine doesn't ban. Whatever enforcement would have done is
+//! counted in `fortify_observed_decisions_total` and published as an
+//! `EnforcementObserved` event (so it reaches the audit log), letting
+//! operators run Cerberus in front of production traffic and tune its
+//! thresholds before turning enforcement on.
+//!
+//! Everything else runs as usual: circuits are still tracked, rate limit
+//! counters still count, and threat-level escalation still happens.
+
+use axum::http::StatusCode;
+use cerberus_common::{CerberusEvent, CircuitId, EventPublisher};
+
+use crate::metrics;
+use crate::state::AppState;
+
+/// What enforcement would have done
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Sent to solve a challenge (no valid passport)
+    Challenge,
+    /// Refused a banned or soft-locked circuit
+    Block,
+    /// Refused a circuit over its rate or concurrency limit
+    RateLimit,
+    /// Banned a circuit (rules engine)
+    Ban,
+}
+
+impl Action {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Challenge => "challenge",
+            Self::Block => "block",
+            Self::RateLimit => "rate_limit",
+            Self::Ban => "ban",
+        }
+    }
+
+    /// The action behind a `/validate` refusal (`None` for errors)
+    pub fn for_status(status: StatusCode) -> Option<Self> {
+        match status {
+            StatusCode::UNAUTHORIZED => Some(Self::Challenge),
+            StatusCode::FORBIDDEN => Some(Self::Block),
+            StatusCode::TOO_MANY_REQUESTS => Some(Self::RateLimit),
+            _ => None,
+        }
+    }
+}
+
+/// Record an action observe-only mode held back
+pub fn record(state: &AppState, action: Action, circuit_id: Option<&CircuitId>) {
+    metrics::OBSERVED_DECISIONS
+        .with_label_values(&[action.as_str()])
+        .inc();
+    tracing::debug!(action = action.as_str(), circuit_id = ?circuit_id, "Observed, not enforced");
+    state.events.publish(CerberusEvent::EnforcementObserved {
+        action: action.as_str().to_string(),
+        circuit_id: circuit_id.cloned(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_action_for_status() {
+        assert_eq!(
+            Action::for_status(StatusCode::UNAUTHORIZED),
+            Some(Action::Challenge)
+        );
+        assert_eq!(
+            Action::for_status(StatusCode::FORBIDDEN),
+            Some(Action::Block)
+        );
+        assert_eq!(
+            Action::for_status(StatusCode::TOO_MANY_REQUESTS),
+            Some(Action::RateLimit)
+        );
+        assert_eq!(Action::for_status(StatusCode::INTERNAL_SERVER_ERROR), None);
+    }
+}
@@ -0,0 +1,458 @@
+//! A/B experiments on the challenge flow.
+//!
+//! Each `[[experiments.experiment]]` splits circuits between weighted
+//! variants. A variant may serve another provider (text against math, say)
+//! or shift the difficulty the threat level picks a step or two. A circuit's
+//! variant is a hash of the experiment name and its circuit ID, so it gets
+//! the same one on every node and every visit; requests without a circuit ID
+//! are left out. A circuit is in every experiment at once: where two name a
+//! provider the later one wins, and difficulty shifts add up.
+//!
+//! Challenges issued to enrolled circuits, and the answers to them, are
+//! tallied per variant in the metrics store (`metrics:experiment:{name}`
+//! in Redis, shared by the cluster) and in Prometheus.
+//! `/admin/experiments` reports pass and abandonment rates from the Redis
+//! tallies. They are kept until the key is deleted, so delete it to start
+//! an experiment over. A forced provider (see `rules`) overrides every
+//! variant while it lasts.
+
+use anyhow::Result;
+use cerberus_common::{CaptchaDifficulty, CircuitId, redis_keys};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+use crate::captcha::ChallengeCheck;
+use crate::metrics;
+
+/// Experiment settings (`[experiments]` in fortify.toml)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExperimentsConfig {
+    pub enabled: bool,
+    /// The experiments (`[[experiments.experiment]]`)
+    #[serde(rename = "experiment")]
+    pub experiments: Vec<ExperimentConfig>,
+}
+
+/// One experiment and its variants
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExperimentConfig {
+    pub name: String,
+    /// The variants (`[[experiments.experiment.variant]]`)
+    #[serde(default, rename = "variant")]
+    pub variants: Vec<VariantConfig>,
+}
+
+/// How challenges differ for circuits in one variant
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VariantConfig {
+    pub name: String,
+    /// Share of circuits, relative to the other variants
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    /// Provider serving this variant instead of `[captcha.providers]`
+    pub provider: Option<String>,
+    /// Steps harder (positive) or easier (negative) than the threat level
+    /// calls for
+    #[serde(default)]
+    pub difficulty_shift: i8,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// What happened to a challenge, as tallied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Issued,
+    Passed,
+    Failed,
+    /// Answered after it expired
+    Expired,
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Issued => "issued",
+            Self::Passed => "passed",
+            Self::Failed => "failed",
+            Self::Expired => "expired",
+        }
+    }
+
+    /// Outcome of an answer (`None` for unknown or reused challenges)
+    pub(crate) fn of_answer(check: ChallengeCheck) -> Option<Self> {
+        match check {
+            ChallengeCheck::Correct { .. } => Some(Self::Passed),
+            ChallengeCheck::Incorrect => Some(Self::Failed),
+            ChallengeCheck::Expired => Some(Self::Expired),
+            ChallengeCheck::Missing => None,
+        }
+    }
+}
+
+/// How a circuit's challenges are drawn, after its experiments
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Treatment<'a> {
+    /// Provider replacing the configured one
+    pub provider: Option<&'a str>,
+    pub difficulty: CaptchaDifficulty,
+}
+
+/// Results of one experiment
+#[derive(Debug, Clone, Serialize)]
+pub struct ExperimentReport {
+    pub name: String,
+    pub variants: Vec<VariantReport>,
+}
+
+/// Tallies of one variant, cluster-wide
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VariantReport {
+    pub name: String,
+    pub weight: u32,
+    pub provider: Option<String>,
+    pub difficulty_shift: i8,
+    pub issued: u64,
+    pub passed: u64,
+    pub failed: u64,
+    pub expired: u64,
+    /// Issued but not answered in time (includes challenges still live)
+    pub abandoned: u64,
+    /// Passed out of answered in time
+    pub pass_rate: Option<f64>,
+    /// Abandoned out of issued
+    pub abandonment_rate: Option<f64>,
+}
+
+impl VariantReport {
+    fn new(variant: &VariantConfig, tallies: &HashMap<String, u64>) -> Self {
+        let count = |outcome: Outcome| {
+            tallies
+                .get(&field(&variant.name, outcome))
+                .copied()
+                .unwrap_or(0)
+        };
+        let (issued, passed, failed) = (
+            count(Outcome::Issued),
+            count(Outcome::Passed),
+            count(Outcome::Failed),
+        );
+        let abandoned = issued.saturating_sub(passed + failed);
+        Self {
+            name: variant.name.clone(),
+            weight: variant.weight,
+            provider: variant.provider.clone(),
+            difficulty_shift: variant.difficulty_shift,
+            issued,
+            passed,
+            failed,
+            expired: count(Outcome::Expired),
+            abandoned,
+            pass_rate: ratio(passed, passed + failed),
+            abandonment_rate: ratio(abandoned, issued),
+        }
+    }
+}
+
+pub(crate) fn ratio(part: u64, whole: u64) -> Option<f64> {
+    (whole > 0).then(|| part as f64 / whole as f64)
+}
+
+/// Hash field of a variant's tally
+fn field(variant: &str, outcome: Outcome) -> String {
+    format!("{}:{}", variant, outcome.as_str())
+}
+
+/// Running experiments
+#[derive(Debug, Default)]
+pub struct Experiments {
+    experiments: Vec<ExperimentConfig>,
+}
+
+impl Experiments {
+    /// Experiments from the config (none unless enabled)
+    pub fn new(config: &ExperimentsConfig) -> Self {
+        let experiments = if config.enabled {
+            config
+                .experiments
+                .iter()
+                .filter(|e| e.variants.iter().any(|v| v.weight > 0))
+                .cloned()
+                .collect()
+        } else {
+            Vec::new()
+        };
+        Self { experiments }
+    }
+
+    /// The variant of `circuit_id` in each experiment
+    fn assign<'a>(
+        &'a self,
+        circuit_id: &CircuitId,
+    ) -> impl Iterator<Item = (&'a ExperimentConfig, &'a VariantConfig)> {
+        self.experiments
+            .iter()
+            .filter_map(move |experiment| Some((experiment, variant_of(experiment, circuit_id)?)))
+    }
+
+    /// How challenges for `circuit_id` are drawn at `difficulty`
+    pub fn treatment(
+        &self,
+        circuit_id: Option<&CircuitId>,
+        difficulty: CaptchaDifficulty,
+    ) -> Treatment<'_> {
+        let mut treatment = Treatment {
+            provider: None,
+            difficulty,
+        };
+        let Some(circuit_id) = circuit_id else {
+            return treatment;
+        };
+        for (_, variant) in self.assign(circuit_id) {
+            if let Some(ref provider) = variant.provider {
+                treatment.provider = Some(provider);
+            }
+            treatment.difficulty = shift(treatment.difficulty, variant.difficulty_shift);
+        }
+        treatment
+    }
+
+    /// Tally `outcome` for the variants of `circuit_id` into `pipe`
+    pub fn queue(
+        &self,
+        pipe: &mut redis::Pipeline,
+        circuit_id: Option<&CircuitId>,
+        outcome: Outcome,
+    ) {
+        let Some(circuit_id) = circuit_id else {
+            return;
+        };
+        for (experiment, variant) in self.assign(circuit_id) {
+            pipe.hincr(
+                redis_keys::experiment(&experiment.name),
+                field(&variant.name, outcome),
+                1,
+            )
+            .ignore();
+            metrics::EXPERIMENT_CHALLENGES
+                .with_label_values(&[
+                    experiment.name.as_str(),
+                    variant.name.as_str(),
+                    outcome.as_str(),
+                ])
+                .inc();
+        }
+    }
+
+    /// Tally an answer from `circuit_id` (best-effort)
+    pub async fn record(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        circuit_id: Option<&CircuitId>,
+        check: ChallengeCheck,
+    ) {
+        let Some(outcome) = Outcome::of_answer(check) else {
+            return;
+        };
+        if self.experiments.is_empty() || circuit_id.is_none() {
+            return;
+        }
+        let mut pipe = redis::pipe();
+        self.queue(&mut pipe, circuit_id, outcome);
+        if let Err(e) = pipe.query_async::<()>(redis).await {
+            tracing::warn!(error = %e, "Failed to tally experiment outcome");
+        }
+    }
+
+    /// Tallies of every experiment, cluster-wide
+    pub async fn report(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+    ) -> Result<Vec<ExperimentReport>> {
+        if self.experiments.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut pipe = redis::pipe();
+        for experiment in &self.experiments {
+            pipe.hgetall(redis_keys::experiment(&experiment.name));
+        }
+        let tallies: Vec<HashMap<String, u64>> = pipe.query_async(redis).await?;
+
+        Ok(self
+            .experiments
+            .iter()
+            .zip(tallies)
+            .map(|(experiment, tallies)| ExperimentReport {
+                name: experiment.name.clone(),
+                variants: experiment
+                    .variants
+                    .iter()
+                    .map(|variant| VariantReport::new(variant, &tallies))
+                    .collect(),
+            })
+            .collect())
+    }
+}
+
+/// The variant `circuit_id` falls in, by weight
+fn variant_of<'a>(
+    experiment: &'a ExperimentConfig,
+    circuit_id: &CircuitId,
+) -> Option<&'a VariantConfig> {
+    let total: u64 = experiment.variants.iter().map(|v| v.weight as u64).sum();
+    if total == 0 {
+        return None;
+    }
+    let digest = Sha256::new()
+        .chain_update(experiment.name.as_bytes())
+        .chain_update([0])
+        .chain_update(circuit_id.as_str().as_bytes())
+        .finalize();
+    let mut point = u64::from_be_bytes(digest[..8].try_into().expect("8 bytes")) % total;
+    for variant in &experiment.variants {
+        if point < variant.weight as u64 {
+            return Some(variant);
+        }
+        point -= variant.weight as u64;
+    }
+    None
+}
+
+/// `difficulty` moved `steps` harder (negative: easier), within bounds
+fn shift(mut difficulty: CaptchaDifficulty, steps: i8) -> CaptchaDifficulty {
+    for _ in 0..steps.unsigned_abs() {
+        difficulty = if steps > 0 {
+            difficulty.harder()
+        } else {
+            difficulty.easier()
+        };
+    }
+    difficulty
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variant(name: &str, weight: u32, provider: Option<&str>, shift: i8) -> VariantConfig {
+        VariantConfig {
+            name: name.to_string(),
+            weight,
+            provider: provider.map(str::to_string),
+            difficulty_shift: shift,
+        }
+    }
+
+    fn experiments(variants: Vec<VariantConfig>) -> Experiments {
+        Experiments::new(&ExperimentsConfig {
+            enabled: true,
+            experiments: vec![ExperimentConfig {
+                name: "provider".to_string(),
+                variants,
+            }],
+        })
+    }
+
+    #[test]
+    fn test_assignment_is_deterministic_and_weighted() {
+        let experiments = experiments(vec![
+            variant("text", 3, None, 0),
+            variant("math", 1, Some("math"), 0),
+        ]);
+        let circuit: CircuitId = "fc00::1".parse().unwrap();
+        let first = experiments.treatment(Some(&circuit), CaptchaDifficulty::Medium);
+        for _ in 0..10 {
+            assert_eq!(
+                experiments.treatment(Some(&circuit), CaptchaDifficulty::Medium),
+                first
+            );
+        }
+
+        let math = (0..4000)
+            .map(|i| format!("fc00::{:x}", i).parse::<CircuitId>().unwrap())
+            .filter(|c| {
+                experiments
+                    .treatment(Some(c), CaptchaDifficulty::Medium)
+                    .provider
+                    == Some("math")
+            })
+            .count();
+        assert!((800..1200).contains(&math), "{} of 4000 in math", math);
+
+        // No circuit ID, no experiment
+        assert_eq!(
+            experiments.treatment(None, CaptchaDifficulty::Medium),
+            Treatment {
+                provider: None,
+                difficulty: CaptchaDifficulty::Medium,
+            }
+        );
+    }
+
+    #[test]
+    fn test_difficulty_shift() {
+        let circuit: CircuitId = "fc00::2".parse().unwrap();
+        let harder = experiments(vec![variant("harder", 1, None, 2)]);
+        assert_eq!(
+            harder
+                .treatment(Some(&circuit), CaptchaDifficulty::Easy)
+                .difficulty,
+            CaptchaDifficulty::Hard
+        );
+        assert_eq!(
+            harder
+                .treatment(Some(&circuit), CaptchaDifficulty::Hard)
+                .difficulty,
+            CaptchaDifficulty::Extreme
+        );
+        let easier = experiments(vec![variant("easier", 1, None, -1)]);
+        assert_eq!(
+            easier
+                .treatment(Some(&circuit), CaptchaDifficulty::Easy)
+                .difficulty,
+            CaptchaDifficulty::Easy
+        );
+    }
+
+    #[test]
+    fn test_disabled_enrolls_nobody() {
+        let circuit: CircuitId = "fc00::3".parse().unwrap();
+        let experiments = Experiments::new(&ExperimentsConfig {
+            enabled: false,
+            experiments: vec![ExperimentConfig {
+                name: "math".to_string(),
+                variants: vec![variant("math", 1, Some("math"), 0)],
+            }],
+        });
+        assert_eq!(
+            experiments
+                .treatment(Some(&circuit), CaptchaDifficulty::Easy)
+                .provider,
+            None
+        );
+    }
+
+    #[test]
+    fn test_variant_report() {
+        let tallies = HashMap::from([
+            ("grid:issued".to_string(), 10),
+            ("grid:passed".to_string(), 6),
+            ("grid:failed".to_string(), 2),
+            ("grid:expired".to_string(), 1),
+            ("text:issued".to_string(), 5),
+        ]);
+        let report = VariantReport::new(&variant("grid", 1, None, 0), &tallies);
+        assert_eq!(report.abandoned, 2);
+        assert_eq!(report.pass_rate, Some(0.75));
+        assert_eq!(report.abandonment_rate, Some(0.2));
+
+        let report = VariantReport::new(&variant("unseen", 1, None, 0), &tallies);
+        assert_eq!(report.issued, 0);
+        assert_eq!(report.pass_rate, None);
+        assert_eq!(report.abandonment_rate, None);
+    }
+}
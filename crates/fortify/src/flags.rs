@@ -0,0 +1,353 @@
+//! Feature flags for risky behaviors.
+//!
+//! Behaviors that hurt visitors if they misfire sit behind a flag, so they
+//! can be tried on one node, then the whole cluster, and switched off again
+//! without a redeploy:
+//!
+//! - `strict_circuit_binding`: an answer posted from another circuit than
+//!   the one its challenge was issued to fails (otherwise it's logged and
+//!   accepted, since Tor circuits change)
+//! - `signed_passports`: with `federation.mode = "signed"`, passports are
+//!   signed tokens honoured cluster-wide (off: Redis-backed passports)
+//! - `auto_escalation`: `escalate` rules raise the threat level (off: the
+//!   rule fires but leaves the level alone)
+//!
+//! `[flags]` in fortify.toml sets each flag's default. Overrides set through
+//! `/admin/flags` are kept in Redis (`cerberus:flags`), for the cluster or
+//! for one node, and every node reads them every `refresh_secs`; a node's
+//! own override beats the cluster's, which beats the config. The node that
+//! takes a toggle applies it at once. While Redis is unreachable, the
+//! overrides last read stay in force.
+
+use anyhow::Result;
+use cerberus_common::{TtlSecs, redis_keys};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::metrics;
+use crate::state::AppState;
+
+/// A gated behavior
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Flag {
+    StrictCircuitBinding,
+    SignedPassports,
+    AutoEscalation,
+}
+
+impl Flag {
+    pub const ALL: [Flag; 3] = [
+        Flag::StrictCircuitBinding,
+        Flag::SignedPassports,
+        Flag::AutoEscalation,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::StrictCircuitBinding => "strict_circuit_binding",
+            Self::SignedPassports => "signed_passports",
+            Self::AutoEscalation => "auto_escalation",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|flag| flag.as_str() == name)
+    }
+}
+
+/// Feature flag defaults (`[flags]` in fortify.toml)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FlagsConfig {
+    pub strict_circuit_binding: bool,
+    /// Only matters with `federation.mode = "signed"`
+    pub signed_passports: bool,
+    pub auto_escalation: bool,
+    /// How often overrides are read from Redis
+    pub refresh_secs: TtlSecs,
+}
+
+impl Default for FlagsConfig {
+    fn default() -> Self {
+        Self {
+            strict_circuit_binding: false,
+            signed_passports: true,
+            auto_escalation: true,
+            refresh_secs: TtlSecs::from_secs(5),
+        }
+    }
+}
+
+impl FlagsConfig {
+    fn default_of(&self, flag: Flag) -> bool {
+        match flag {
+            Flag::StrictCircuitBinding => self.strict_circuit_binding,
+            Flag::SignedPassports => self.signed_passports,
+            Flag::AutoEscalation => self.auto_escalation,
+        }
+    }
+}
+
+/// Hash field of an override: `{flag}` for the cluster, `{flag}:{node ID}`
+/// for one node
+fn field(flag: Flag, node_id: Option<&str>) -> String {
+    match node_id {
+        Some(node_id) => format!("{}:{}", flag.as_str(), node_id),
+        None => flag.as_str().to_string(),
+    }
+}
+
+/// Overrides that apply to one node
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Overrides {
+    cluster: HashMap<Flag, bool>,
+    node: HashMap<Flag, bool>,
+}
+
+impl Overrides {
+    /// Pick this node's overrides out of the hash (other nodes' fields,
+    /// unknown flags and values other than 1/0 are ignored)
+    fn parse(fields: &HashMap<String, String>, node_id: &str) -> Self {
+        let mut overrides = Self::default();
+        for (field, value) in fields {
+            let enabled = match value.as_str() {
+                "1" => true,
+                "0" => false,
+                _ => continue,
+            };
+            let (name, node) = match field.split_once(':') {
+                Some((name, node)) => (name, Some(node)),
+                None => (field.as_str(), None),
+            };
+            let Some(flag) = Flag::parse(name) else {
+                continue;
+            };
+            match node {
+                None => {
+                    overrides.cluster.insert(flag, enabled);
+                }
+                Some(node) if node == node_id => {
+                    overrides.node.insert(flag, enabled);
+                }
+                Some(_) => {}
+            }
+        }
+        overrides
+    }
+}
+
+/// A flag as seen by an admin
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FlagStatus {
+    pub flag: Flag,
+    /// In force on this node
+    pub enabled: bool,
+    /// From `[flags]`
+    pub default: bool,
+    /// Cluster-wide override
+    pub cluster: Option<bool>,
+    /// This node's override
+    pub node: Option<bool>,
+}
+
+/// The flags in force on this node, read on the request path
+pub struct FeatureFlags {
+    config: FlagsConfig,
+    node_id: String,
+    overrides: RwLock<Overrides>,
+    /// Resolved value of each flag, by `Flag::ALL` index
+    enabled: [AtomicBool; Flag::ALL.len()],
+}
+
+/// Config defaults, without publishing them (for components built before
+/// the node's flags are handed to them)
+impl Default for FeatureFlags {
+    fn default() -> Self {
+        Self::unpublished(FlagsConfig::default(), String::new())
+    }
+}
+
+impl FeatureFlags {
+    pub fn new(config: FlagsConfig, node_id: String) -> Self {
+        let flags = Self::unpublished(config, node_id);
+        flags.publish_metrics();
+        flags
+    }
+
+    fn unpublished(config: FlagsConfig, node_id: String) -> Self {
+        let enabled = Flag::ALL.map(|flag| AtomicBool::new(config.default_of(flag)));
+        Self {
+            config,
+            node_id,
+            overrides: RwLock::new(Overrides::default()),
+            enabled,
+        }
+    }
+
+    pub fn is_enabled(&self, flag: Flag) -> bool {
+        self.slot(flag).load(Ordering::Relaxed)
+    }
+
+    fn slot(&self, flag: Flag) -> &AtomicBool {
+        let index = Flag::ALL.iter().position(|f| *f == flag).expect("listed");
+        &self.enabled[index]
+    }
+
+    /// This node's override, else the cluster's, else the config default
+    fn resolve(&self, overrides: &Overrides, flag: Flag) -> bool {
+        overrides
+            .node
+            .get(&flag)
+            .or_else(|| overrides.cluster.get(&flag))
+            .copied()
+            .unwrap_or_else(|| self.config.default_of(flag))
+    }
+
+    /// Put a fresh read of the overrides in force
+    fn apply(&self, overrides: Overrides) {
+        for flag in Flag::ALL {
+            let enabled = self.resolve(&overrides, flag);
+            if self.slot(flag).swap(enabled, Ordering::Relaxed) != enabled {
+                tracing::warn!(flag = flag.as_str(), enabled, "Feature flag changed");
+            }
+        }
+        *self.overrides.write().unwrap_or_else(|e| e.into_inner()) = overrides;
+        self.publish_metrics();
+    }
+
+    fn publish_metrics(&self) {
+        for flag in Flag::ALL {
+            metrics::FEATURE_FLAGS
+                .with_label_values(&[flag.as_str()])
+                .set(i64::from(self.is_enabled(flag)));
+        }
+    }
+
+    /// Read the overrides from Redis and put them in force
+    pub async fn refresh(&self, redis: &mut redis::aio::ConnectionManager) -> Result<()> {
+        let fields: HashMap<String, String> = redis.hgetall(redis_keys::flags()).await?;
+        self.apply(Overrides::parse(&fields, &self.node_id));
+        Ok(())
+    }
+
+    /// Set (`Some`) or clear (`None`) an override for `node_id`, or for the
+    /// cluster; in force here at once, on other nodes by their next refresh
+    pub async fn set(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        flag: Flag,
+        node_id: Option<&str>,
+        enabled: Option<bool>,
+    ) -> Result<()> {
+        let field = field(flag, node_id);
+        match enabled {
+            Some(enabled) => {
+                redis
+                    .hset::<_, _, _, ()>(redis_keys::flags(), field, u8::from(enabled))
+                    .await?
+            }
+            None => redis.hdel::<_, _, ()>(redis_keys::flags(), field).await?,
+        }
+        self.refresh(redis).await
+    }
+
+    /// Every flag, as in force on this node
+    pub fn report(&self) -> Vec<FlagStatus> {
+        let overrides = self.overrides.read().unwrap_or_else(|e| e.into_inner());
+        Flag::ALL
+            .into_iter()
+            .map(|flag| FlagStatus {
+                flag,
+                enabled: self.is_enabled(flag),
+                default: self.config.default_of(flag),
+                cluster: overrides.cluster.get(&flag).copied(),
+                node: overrides.node.get(&flag).copied(),
+            })
+            .collect()
+    }
+}
+
+/// Read the overrides every `flags.refresh_secs` until shutdown
+pub async fn flags_worker(state: AppState, mut shutdown: tokio::sync::broadcast::Receiver<()>) {
+    let interval = state.config.flags.refresh_secs.duration();
+
+    loop {
+        if let Some(mut redis) = state.redis()
+            && let Err(e) = state.flags.refresh(&mut redis).await
+        {
+            tracing::warn!(error = %e, "Failed to read feature flag overrides");
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = shutdown.recv() => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|&(field, value)| (field.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_overrides() {
+        let fields = hash(&[
+            ("strict_circuit_binding", "1"),
+            ("strict_circuit_binding:node-b", "0"),
+            ("auto_escalation:node-a", "0"),
+            ("signed_passports:node-b", "0"),
+            ("no_such_flag", "1"),
+            ("signed_passports", "yes"),
+        ]);
+        let overrides = Overrides::parse(&fields, "node-a");
+        assert_eq!(
+            overrides.cluster,
+            HashMap::from([(Flag::StrictCircuitBinding, true)])
+        );
+        assert_eq!(
+            overrides.node,
+            HashMap::from([(Flag::AutoEscalation, false)])
+        );
+        assert_eq!(field(Flag::SignedPassports, None), "signed_passports");
+        assert_eq!(
+            field(Flag::SignedPassports, Some("node-b")),
+            "signed_passports:node-b"
+        );
+    }
+
+    #[test]
+    fn test_node_override_beats_cluster_and_config() {
+        let flags = FeatureFlags::new(FlagsConfig::default(), "node-a".to_string());
+        assert!(!flags.is_enabled(Flag::StrictCircuitBinding));
+        assert!(flags.is_enabled(Flag::AutoEscalation));
+
+        flags.apply(Overrides::parse(
+            &hash(&[
+                ("strict_circuit_binding", "1"),
+                ("auto_escalation", "0"),
+                ("auto_escalation:node-a", "1"),
+            ]),
+            "node-a",
+        ));
+        assert!(flags.is_enabled(Flag::StrictCircuitBinding));
+        assert!(flags.is_enabled(Flag::AutoEscalation));
+        let report = flags.report();
+        assert_eq!(report[2].cluster, Some(false));
+        assert_eq!(report[2].node, Some(true));
+
+        // Cleared overrides fall back to the config
+        flags.apply(Overrides::default());
+        assert!(!flags.is_enabled(Flag::StrictCircuitBinding));
+        assert_eq!(report.len(), Flag::ALL.len());
+    }
+}
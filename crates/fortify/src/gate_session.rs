@@ -0,0 +1,347 @@
+//! No-JS gate sessions.
+//!
+//! The form flow is a series of full page loads, so without a session a
+//! visitor who fails a challenge loses all context. A gate session is an
+//! opaque ID in the `cerberus_gate` cookie pointing at a small Redis record:
+//! how many attempts were made, and the page the visitor was sent from, to
+//! return to once they solve. Sessions need Redis; while it is offline the
+//! gate works as before, without one.
+//!
+//! The page to return to also travels in the gate form itself, as a hidden
+//! field signed with the challenge sealer's key, so it survives a Redis
+//! outage and can't be swapped for another page by editing the form.
+//!
+//! Each rendered gate form carries a one-time nonce, kept in Redis with the
+//! gate session for its lifetime. Submitting the form spends it, so a form
+//! posted again with the browser's back button and resubmit is recognised
+//! and gets an "already processed" page instead of a confusing wrong-answer
+//! error. A form without a nonce, or with one not issued to the session in
+//! the cookie, isn't checked at all. Nonces outlive the session record,
+//! which a solve ends; the cookie stays, so a resubmitted form is still
+//! recognised.
+
+use anyhow::Result;
+use axum::http::{HeaderMap, HeaderValue, header};
+use cerberus_common::redis_keys::{self, RedisKey};
+use cerberus_common::{GateFormNonce, GateSessionId};
+use redis::{AsyncCommands, ExistenceCheck, SetExpiry, SetOptions};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::captcha::ChallengeSealer;
+
+/// Cookie carrying the session ID
+pub const COOKIE_NAME: &str = "cerberus_gate";
+
+/// Longest `return_to` path kept
+const MAX_RETURN_TO_LEN: usize = 1024;
+
+/// Gate form nonce values, before and after the form is processed
+const FORM_PENDING: &str = "pending";
+const FORM_PROCESSED: &str = "processed";
+
+/// State kept between gate page loads
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GateSession {
+    /// Local path to send the visitor to after solving
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub return_to: Option<String>,
+    /// Wrong answers so far
+    pub attempts: u32,
+    /// Unix timestamp (seconds)
+    pub created_at: i64,
+}
+
+/// A submitted gate form, as its nonce tells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormSubmission {
+    /// First submission of a form rendered for this session
+    New,
+    /// Already processed (posted again, e.g. with back and resubmit)
+    Resubmitted,
+    /// No nonce, or one not issued to this session (or expired)
+    Unknown,
+}
+
+/// `return_to` with its signature, as carried in the gate form
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedReturnTo {
+    pub path: String,
+    pub signature: String,
+}
+
+/// Creates, loads and ends gate sessions
+pub struct GateSessions {
+    ttl_secs: u64,
+    sealer: Arc<ChallengeSealer>,
+}
+
+impl GateSessions {
+    pub fn new(ttl_secs: u64, sealer: Arc<ChallengeSealer>) -> Self {
+        Self { ttl_secs, sealer }
+    }
+
+    /// Sign `path` for the gate form
+    pub fn sign_return_to(&self, path: &str) -> SignedReturnTo {
+        SignedReturnTo {
+            path: path.to_string(),
+            signature: self.sealer.sign(path),
+        }
+    }
+
+    /// `path` from a submitted gate form, if its signature checks out
+    pub fn open_return_to(&self, path: &str, signature: &str) -> Option<String> {
+        if !self.sealer.verify(path, signature) {
+            tracing::debug!("Ignoring return_to with a bad signature");
+            return None;
+        }
+        sanitize_return_to(path)
+    }
+
+    /// Start a session, returning its ID
+    pub async fn start(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        return_to: Option<String>,
+    ) -> Result<(GateSessionId, GateSession)> {
+        let id = generate_id();
+        let session = GateSession {
+            return_to,
+            attempts: 0,
+            created_at: chrono::Utc::now().timestamp(),
+        };
+        self.save(redis, &id, &session).await?;
+        Ok((id, session))
+    }
+
+    /// Session `id`, if it exists and hasn't expired
+    pub async fn load(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        id: &GateSessionId,
+    ) -> Result<Option<GateSession>> {
+        let json: Option<String> = redis.get(redis_keys::gate_session(id)).await?;
+        Ok(json.and_then(|json| serde_json::from_str(&json).ok()))
+    }
+
+    /// Store `session`, restarting its lifetime
+    pub async fn save(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        id: &GateSessionId,
+        session: &GateSession,
+    ) -> Result<()> {
+        let _: () = redis
+            .set_ex(
+                redis_keys::gate_session(id),
+                serde_json::to_string(session)?,
+                self.ttl_secs,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Drop a session once the visitor is through
+    pub async fn end(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        id: &GateSessionId,
+    ) -> Result<()> {
+        let _: () = redis.del(redis_keys::gate_session(id)).await?;
+        Ok(())
+    }
+
+    /// A nonce for a gate form about to be rendered for session `id`
+    pub async fn issue_form_nonce(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        id: &GateSessionId,
+    ) -> Result<GateFormNonce> {
+        let nonce: GateFormNonce = random_base64url()
+            .parse()
+            .expect("base64url is a valid gate form nonce");
+        let _: () = redis
+            .set_ex(
+                redis_keys::gate_form(id, &nonce),
+                FORM_PENDING,
+                self.ttl_secs,
+            )
+            .await?;
+        Ok(nonce)
+    }
+
+    /// Mark the form carrying `nonce`, posted with session `id`, processed
+    pub async fn spend_form_nonce(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        id: Option<&GateSessionId>,
+        nonce: Option<&GateFormNonce>,
+    ) -> Result<FormSubmission> {
+        let Some(key) = form_nonce_key(id, nonce) else {
+            return Ok(FormSubmission::Unknown);
+        };
+        let options = SetOptions::default()
+            .conditional_set(ExistenceCheck::XX)
+            .get(true)
+            .with_expiration(SetExpiry::KEEPTTL);
+        let previous: Option<String> = redis.set_options(key, FORM_PROCESSED, options).await?;
+        Ok(match previous.as_deref() {
+            Some(FORM_PROCESSED) => FormSubmission::Resubmitted,
+            Some(_) => FormSubmission::New,
+            None => FormSubmission::Unknown,
+        })
+    }
+
+    /// `Set-Cookie` value handing `id` to the browser
+    pub fn cookie(&self, id: &GateSessionId) -> HeaderValue {
+        cookie_header(id.as_str(), self.ttl_secs)
+    }
+
+    /// `Set-Cookie` value removing the session cookie
+    pub fn expired_cookie(&self) -> HeaderValue {
+        cookie_header("", 0)
+    }
+}
+
+/// Where a submitted form's nonce is kept (`None` without a nonce, or
+/// without a session to have issued it)
+fn form_nonce_key(id: Option<&GateSessionId>, nonce: Option<&GateFormNonce>) -> Option<RedisKey> {
+    Some(redis_keys::gate_form(id?, nonce?))
+}
+
+/// Session ID from the request's cookies (ignored if malformed)
+pub fn from_headers(headers: &HeaderMap) -> Option<GateSessionId> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == COOKIE_NAME)
+        .and_then(|(_, value)| value.parse().ok())
+}
+
+/// Page the proxy was asked for when it sent the visitor to the gate
+/// (`X-Original-URI`, set by Nginx on the auth_request failure path)
+pub fn original_uri(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(cerberus_common::constants::headers::X_ORIGINAL_URI)?
+        .to_str()
+        .ok()
+        .and_then(sanitize_return_to)
+}
+
+/// Accept `return_to` only as a local path, so the gate can't be used as an
+/// open redirect (`//host` and `/\host` are other hosts to browsers)
+pub fn sanitize_return_to(return_to: &str) -> Option<String> {
+    let local = return_to.starts_with('/')
+        && !return_to.starts_with("//")
+        && !return_to.starts_with("/\\")
+        && return_to.len() <= MAX_RETURN_TO_LEN
+        && !return_to.chars().any(|c| c.is_control());
+    local.then(|| return_to.to_string())
+}
+
+fn cookie_header(value: &str, max_age: u64) -> HeaderValue {
+    // Session IDs are base64url, so the value is always a valid header
+    HeaderValue::from_str(&format!(
+        "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+        COOKIE_NAME, value, max_age
+    ))
+    .expect("valid cookie header")
+}
+
+fn generate_id() -> GateSessionId {
+    random_base64url()
+        .parse()
+        .expect("base64url is a valid gate session ID")
+}
+
+/// 24 random bytes, base64url
+fn random_base64url() -> String {
+    use base64::Engine;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+
+    let mut bytes = [0u8; 24];
+    rand::Rng::fill(&mut rand::rng(), &mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cookie_round_trip() {
+        let sessions = GateSessions::new(600, Arc::new(ChallengeSealer::new()));
+        let id = generate_id();
+        let set_cookie = sessions.cookie(&id);
+        assert!(
+            set_cookie
+                .to_str()
+                .unwrap()
+                .contains("Max-Age=600; HttpOnly")
+        );
+
+        let mut headers = HeaderMap::new();
+        let cookie = set_cookie.to_str().unwrap().split(';').next().unwrap();
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_str(&format!("theme=dark; {}", cookie)).unwrap(),
+        );
+        assert_eq!(from_headers(&headers), Some(id));
+
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_static("cerberus_gate=not valid!"),
+        );
+        assert_eq!(from_headers(&headers), None);
+    }
+
+    #[test]
+    fn test_signed_return_to() {
+        let sessions = GateSessions::new(600, Arc::new(ChallengeSealer::new()));
+        let signed = sessions.sign_return_to("/app/forum?page=2");
+        assert_eq!(
+            sessions
+                .open_return_to(&signed.path, &signed.signature)
+                .as_deref(),
+            Some("/app/forum?page=2")
+        );
+
+        // Another page, or a signature from another node's key
+        assert_eq!(
+            sessions.open_return_to("/app/admin", &signed.signature),
+            None
+        );
+        let other = GateSessions::new(600, Arc::new(ChallengeSealer::new()));
+        assert_eq!(other.open_return_to(&signed.path, &signed.signature), None);
+        assert_eq!(sessions.open_return_to(&signed.path, "not base64!"), None);
+    }
+
+    #[test]
+    fn test_return_to_must_be_local() {
+        assert_eq!(
+            sanitize_return_to("/app/forum?page=2").as_deref(),
+            Some("/app/forum?page=2")
+        );
+        assert_eq!(sanitize_return_to("https://evil.example/"), None);
+        assert_eq!(sanitize_return_to("//evil.example/"), None);
+        assert_eq!(sanitize_return_to("/\\evil.example/"), None);
+        assert_eq!(sanitize_return_to("/app/\r\nSet-Cookie: x"), None);
+    }
+
+    #[test]
+    fn test_form_nonce_needs_nonce_and_session() {
+        let id = generate_id();
+        let other = generate_id();
+        let nonce: GateFormNonce = random_base64url().parse().unwrap();
+
+        assert!(form_nonce_key(Some(&id), None).is_none());
+        assert!(form_nonce_key(None, Some(&nonce)).is_none());
+
+        // Kept per session: another session's cookie doesn't find it
+        let key = form_nonce_key(Some(&id), Some(&nonce)).unwrap();
+        assert_ne!(form_nonce_key(Some(&other), Some(&nonce)), Some(key));
+    }
+}
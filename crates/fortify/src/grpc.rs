@@ -0,0 +1,398 @@
+//! gRPC control plane (`grpc` feature).
+//!
+//! The admin API as a typed service (`cerberus.control.v1.ControlPlane`,
+//! defined in cerberus-common's `proto/control.proto`): threat dial, circuit
+//! management, passport revocation, and a stats stream for dashboards in
+//! place of polling `/admin/stats`. It listens on its own address, loopback
+//! by default; with a `token`, every call must carry
+//! `authorization: Bearer <token>`.
+
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use tokio::sync::broadcast;
+
+use crate::state::AppState;
+
+/// Control plane settings (`[grpc]` in fortify.toml)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+#[cfg_attr(not(feature = "grpc"), allow(dead_code))]
+pub struct GrpcConfig {
+    pub enabled: bool,
+    pub listen_addr: String,
+    /// Bearer token required on every call (empty = none)
+    pub token: String,
+    /// `StreamStats` interval when the client asks for none
+    pub stats_interval_secs: u64,
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen_addr: "127.0.0.1:50051".to_string(),
+            token: String::new(),
+            stats_interval_secs: 5,
+        }
+    }
+}
+
+impl GrpcConfig {
+    /// Listening beyond loopback without a token would hand the admin API
+    /// to anyone who can reach the port
+    pub fn exposed_without_token(&self) -> bool {
+        self.token.is_empty()
+            && self
+                .listen_addr
+                .parse::<SocketAddr>()
+                .is_ok_and(|addr| !addr.ip().is_loopback())
+    }
+}
+
+/// Serve the control plane until shutdown
+pub async fn grpc_worker(state: AppState, shutdown: broadcast::Receiver<()>) {
+    if !state.config.grpc.enabled {
+        return;
+    }
+
+    #[cfg(feature = "grpc")]
+    if let Err(e) = server::serve(state, shutdown).await {
+        tracing::error!(error = %e, "gRPC control plane failed");
+    }
+    #[cfg(not(feature = "grpc"))]
+    {
+        let _ = shutdown;
+        tracing::warn!("gRPC enabled in config, but Fortify was built without the `grpc` feature");
+    }
+}
+
+#[cfg(feature = "grpc")]
+mod server {
+    use anyhow::{Context, Result};
+    use cerberus_common::control::control_plane_server::{ControlPlane, ControlPlaneServer};
+    use cerberus_common::control::{
+        CircuitActionResult, CircuitInfo, CircuitRequest, CircuitStatus, GetThreatLevelRequest,
+        RevokePassportsRequest, RevokePassportsResult, SetThreatLevelRequest, Stats,
+        StreamStatsRequest, ThreatLevelInfo,
+    };
+    use cerberus_common::{CircuitId, PassportToken, ThreatLevel};
+    use futures::Stream;
+    use std::net::SocketAddr;
+    use std::pin::Pin;
+    use std::time::Duration;
+    use tokio::sync::broadcast;
+    use tonic::{Request, Response, Status};
+
+    use crate::circuits::CircuitMutation;
+    use crate::state::AppState;
+
+    /// Shortest `StreamStats` interval a client may ask for
+    const MIN_STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+    pub async fn serve(state: AppState, mut shutdown: broadcast::Receiver<()>) -> Result<()> {
+        let config = &state.config.grpc;
+        let addr: SocketAddr = config
+            .listen_addr
+            .parse()
+            .with_context(|| format!("Invalid grpc.listen_addr `{}`", config.listen_addr))?;
+        let token = (!config.token.is_empty()).then(|| format!("Bearer {}", config.token));
+
+        let service = ControlPlaneServer::with_interceptor(
+            ControlService { state },
+            move |request: Request<()>| authorize(token.as_deref(), request),
+        );
+
+        tracing::info!(%addr, "🛰️ gRPC control plane listening");
+        tonic::transport::Server::builder()
+            .add_service(service)
+            .serve_with_shutdown(addr, async move {
+                let _ = shutdown.recv().await;
+            })
+            .await
+            .context("gRPC server error")
+    }
+
+    /// Check the bearer token, if one is configured
+    fn authorize(token: Option<&str>, request: Request<()>) -> Result<Request<()>, Status> {
+        let Some(expected) = token else {
+            return Ok(request);
+        };
+        let given = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        if constant_time_eq(given.as_bytes(), expected.as_bytes()) {
+            Ok(request)
+        } else {
+            Err(Status::unauthenticated("invalid or missing token"))
+        }
+    }
+
+    fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+    }
+
+    struct ControlService {
+        state: AppState,
+    }
+
+    impl ControlService {
+        fn redis(&self) -> Result<redis::aio::ConnectionManager, Status> {
+            self.state
+                .redis()
+                .ok_or_else(|| Status::unavailable("Redis is offline"))
+        }
+    }
+
+    #[tonic::async_trait]
+    impl ControlPlane for ControlService {
+        async fn get_threat_level(
+            &self,
+            _request: Request<GetThreatLevelRequest>,
+        ) -> Result<Response<ThreatLevelInfo>, Status> {
+            Ok(Response::new(threat_level_info(
+                self.state.get_threat_level().await,
+            )))
+        }
+
+        async fn set_threat_level(
+            &self,
+            request: Request<SetThreatLevelRequest>,
+        ) -> Result<Response<ThreatLevelInfo>, Status> {
+            let level = request.into_inner().level.min(u8::MAX as u32) as u8;
+            let level = ThreatLevel::new(level);
+            self.state.set_threat_level(level).await.map_err(|e| {
+                tracing::error!(error = %e, "Failed to set threat level");
+                Status::internal("failed to set threat level")
+            })?;
+            Ok(Response::new(threat_level_info(level)))
+        }
+
+        async fn get_circuit(
+            &self,
+            request: Request<CircuitRequest>,
+        ) -> Result<Response<CircuitInfo>, Status> {
+            let circuit_id = circuit_id(request.into_inner())?;
+            let mut redis = self.redis()?;
+            match self
+                .state
+                .circuit_tracker
+                .get(&mut redis, &circuit_id)
+                .await
+            {
+                Ok(Some(info)) => Ok(Response::new(circuit_info(info))),
+                Ok(None) => Err(Status::not_found("unknown circuit")),
+                Err(e) => {
+                    tracing::error!(error = %e, circuit_id = %circuit_id, "Failed to get circuit");
+                    Err(Status::internal("failed to get circuit"))
+                }
+            }
+        }
+
+        async fn ban_circuit(
+            &self,
+            request: Request<CircuitRequest>,
+        ) -> Result<Response<CircuitActionResult>, Status> {
+            let circuit_id = circuit_id(request.into_inner())?;
+            let Some(mut redis) = self.state.redis() else {
+                // Applied when Redis is back
+                self.state.mutation_queue.push(CircuitMutation::Ban {
+                    circuit_id,
+                    reason: "Admin ban".to_string(),
+                });
+                return Ok(Response::new(CircuitActionResult { queued: true }));
+            };
+
+            match self
+                .state
+                .circuit_tracker
+                .ban(&mut redis, &circuit_id, "Admin ban")
+                .await
+            {
+                Ok(()) => {
+                    tracing::info!(circuit_id = %circuit_id, "Circuit banned by admin (gRPC)");
+                    Ok(Response::new(CircuitActionResult { queued: false }))
+                }
+                Err(e) => {
+                    tracing::error!(error = %e, circuit_id = %circuit_id, "Failed to ban circuit");
+                    Err(Status::internal("failed to ban circuit"))
+                }
+            }
+        }
+
+        async fn unban_circuit(
+            &self,
+            request: Request<CircuitRequest>,
+        ) -> Result<Response<CircuitActionResult>, Status> {
+            let circuit_id = circuit_id(request.into_inner())?;
+            let mut redis = self.redis()?;
+            match self
+                .state
+                .circuit_tracker
+                .unban(&mut redis, &circuit_id)
+                .await
+            {
+                Ok(true) => {
+                    tracing::info!(circuit_id = %circuit_id, "Circuit unbanned by admin (gRPC)");
+                    Ok(Response::new(CircuitActionResult { queued: false }))
+                }
+                Ok(false) => Err(Status::not_found(
+                    "circuit is neither banned nor soft-locked",
+                )),
+                Err(e) => {
+                    tracing::error!(error = %e, circuit_id = %circuit_id, "Failed to unban circuit");
+                    Err(Status::internal("failed to unban circuit"))
+                }
+            }
+        }
+
+        async fn revoke_passports(
+            &self,
+            request: Request<RevokePassportsRequest>,
+        ) -> Result<Response<RevokePassportsResult>, Status> {
+            let request = request.into_inner();
+            let token = request
+                .token
+                .map(|token| token.parse::<PassportToken>())
+                .transpose()
+                .map_err(|e| Status::invalid_argument(format!("token: {}", e)))?;
+            let circuit_id = request
+                .circuit_id
+                .map(|id| id.parse::<CircuitId>())
+                .transpose()
+                .map_err(|e| Status::invalid_argument(format!("circuit_id: {}", e)))?;
+
+            let result = crate::routes::revoke_passports(&self.state, token, circuit_id)
+                .await
+                .map_err(|status| match status.as_u16() {
+                    400 => Status::invalid_argument("token or circuit_id is required"),
+                    503 => Status::unavailable("Redis is offline"),
+                    _ => Status::internal("failed to revoke passports"),
+                })?;
+            Ok(Response::new(RevokePassportsResult {
+                revoked: result.revoked as u64,
+                peers_notified: result.peers_notified as u64,
+            }))
+        }
+
+        type StreamStatsStream = Pin<Box<dyn Stream<Item = Result<Stats, Status>> + Send>>;
+
+        async fn stream_stats(
+            &self,
+            request: Request<StreamStatsRequest>,
+        ) -> Result<Response<Self::StreamStatsStream>, Status> {
+            let interval = match request.into_inner().interval_secs {
+                0 => Duration::from_secs(self.state.config.grpc.stats_interval_secs),
+                secs => Duration::from_secs(secs as u64),
+            }
+            .max(MIN_STATS_INTERVAL);
+
+            let mut ticker = tokio::time::interval(interval);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            let stream = futures::stream::unfold(
+                (self.state.clone(), ticker),
+                |(state, mut ticker)| async move {
+                    ticker.tick().await;
+                    let stats = stats(&state).await;
+                    Some((Ok(stats), (state, ticker)))
+                },
+            );
+            Ok(Response::new(Box::pin(stream)))
+        }
+    }
+
+    fn circuit_id(request: CircuitRequest) -> Result<CircuitId, Status> {
+        request
+            .circuit_id
+            .parse()
+            .map_err(|e| Status::invalid_argument(format!("circuit_id: {}", e)))
+    }
+
+    fn threat_level_info(level: ThreatLevel) -> ThreatLevelInfo {
+        ThreatLevelInfo {
+            level: level.value() as u32,
+            requires_captcha: level.requires_captcha(),
+            captcha_count: level.captcha_count() as u32,
+        }
+    }
+
+    fn circuit_info(info: cerberus_common::CircuitInfo) -> CircuitInfo {
+        use cerberus_common::CircuitStatus as Status;
+
+        let status = match info.status {
+            Status::New => CircuitStatus::New,
+            Status::Verified => CircuitStatus::Verified,
+            Status::SoftLocked => CircuitStatus::SoftLocked,
+            Status::Banned => CircuitStatus::Banned,
+            Status::Vip => CircuitStatus::Vip,
+        };
+        CircuitInfo {
+            circuit_id: info.circuit_id.to_string(),
+            status: status as i32,
+            failed_attempts: info.failed_attempts,
+            successful_solves: info.successful_solves,
+            first_seen: info.first_seen,
+            last_seen: info.last_seen,
+            passport_expires: info.passport_expires,
+            reputation: info.reputation,
+        }
+    }
+
+    async fn stats(state: &AppState) -> Stats {
+        let ammo = state.ammo_box.get_stats();
+        Stats {
+            node_id: state.node_id.clone(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            at: chrono::Utc::now().timestamp(),
+            threat_level: state.get_threat_level().await.value() as u32,
+            degradation: format!("{:?}", state.degradation.level()).to_lowercase(),
+            ammo_available: ammo.pool_size as u64,
+            ammo_capacity: ammo.pool_capacity as u64,
+            ammo_served: ammo.served,
+            ammo_misses: ammo.pool_misses,
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_authorize() {
+            let request = |auth: Option<&str>| {
+                let mut request = Request::new(());
+                if let Some(auth) = auth {
+                    request
+                        .metadata_mut()
+                        .insert("authorization", auth.parse().unwrap());
+                }
+                request
+            };
+
+            assert!(authorize(None, request(None)).is_ok());
+            assert!(authorize(Some("Bearer s3cret"), request(Some("Bearer s3cret"))).is_ok());
+            for auth in [None, Some("Bearer wrong"), Some("s3cret")] {
+                let status = authorize(Some("Bearer s3cret"), request(auth)).unwrap_err();
+                assert_eq!(status.code(), tonic::Code::Unauthenticated);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exposed_without_token() {
+        let mut config = GrpcConfig::default();
+        assert!(!config.exposed_without_token());
+
+        config.listen_addr = "0.0.0.0:50051".to_string();
+        assert!(config.exposed_without_token());
+
+        config.token = "s3cret".to_string();
+        assert!(!config.exposed_without_token());
+    }
+}
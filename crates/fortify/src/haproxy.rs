@@ -4,13 +4,64 @@
 //! - Update circuit status in stick tables (VIP/Ban)
 //! - Query current connection statistics
 //! - Read stick table entries
+//! - Drain backend servers of unhealthy cluster peers
+//! - Raise flags (maintenance) in a map the HAProxy config reads
+//! - Tag circuits with their challenge pressure (see `pressure`)
 //!
 //! Reference: https://www.haproxy.com/blog/dynamic-configuration-haproxy-runtime-api/
 //!
 //! NOTE: Unix sockets are only available on Unix systems. On Windows,
 //! this module provides stub implementations that log warnings.
 
-use anyhow::Result;
+use anyhow::{Result, bail};
+use cerberus_common::{CerberusEvent, CircuitId, EventPublisher, EventSubscriber};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::metrics;
+use crate::pressure::PressureConfig;
+
+/// HAProxy runtime API settings (`[haproxy]` in fortify.toml)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HaproxyConfig {
+    /// Use the runtime API at all
+    pub enabled: bool,
+    /// Runtime API socket (`stats socket ... level admin`)
+    pub socket_path: String,
+    /// Stick table tracking circuits
+    pub stick_table: String,
+    /// Backend holding the cluster's servers
+    pub backend: String,
+    /// Peer node ID -> its server in `backend`, drained while the peer is unhealthy
+    pub servers: HashMap<String, String>,
+    /// How often to poll `show stat` / `show table` into the metrics
+    pub stats_interval_secs: u64,
+    /// Stick table fill ratio at which `StickTableNearFull` is raised
+    pub table_full_ratio: f64,
+    /// Map file (as named in haproxy.cfg) holding flags such as `maintenance`
+    pub flags_map: Option<String>,
+    /// Per-circuit challenge pressure tags (`[haproxy.pressure]`)
+    pub pressure: PressureConfig,
+}
+
+impl Default for HaproxyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            socket_path: "/var/run/haproxy.sock".to_string(),
+            stick_table: "be_stick_tables".to_string(),
+            backend: "be_fortify".to_string(),
+            servers: HashMap::new(),
+            stats_interval_secs: 10,
+            table_full_ratio: 0.9,
+            flags_map: None,
+            pressure: PressureConfig::default(),
+        }
+    }
+}
 
 /// HAProxy Runtime API client
 #[allow(dead_code)]
@@ -19,10 +70,13 @@ pub struct HaproxyApi {
     socket_path: String,
     /// Stick table name for circuit tracking
     stick_table: String,
+    /// Map of flags read by the HAProxy config
+    flags_map: Option<String>,
 }
 
 /// Circuit status values in HAProxy stick table gpc0
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
 #[repr(u8)]
 #[allow(dead_code)]
 pub enum HaproxyCircuitStatus {
@@ -34,6 +88,69 @@ pub enum HaproxyCircuitStatus {
     Banned = 2,
 }
 
+/// `data.gpc0` filter for table-wide commands (empty for the whole table)
+fn status_filter(status: Option<HaproxyCircuitStatus>) -> String {
+    status
+        .map(|status| format!(" data.gpc0 eq {}", status as u8))
+        .unwrap_or_default()
+}
+
+/// Administrative state of a backend server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(dead_code)]
+pub enum ServerState {
+    /// In rotation
+    Ready,
+    /// Keeps its sessions but receives no new ones
+    Drain,
+    /// Out of rotation (maintenance)
+    Maint,
+}
+
+impl ServerState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Ready => "ready",
+            Self::Drain => "drain",
+            Self::Maint => "maint",
+        }
+    }
+}
+
+/// Valid HAProxy proxy/server name (also keeps it to one runtime API word)
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'))
+}
+
+/// Key of the maintenance flag in `flags_map` (`1` while on)
+const MAINTENANCE_FLAG: &str = "maintenance";
+
+/// `set server` command for a server's new state
+fn server_state_command(backend: &str, server: &str, state: ServerState) -> Result<String> {
+    for name in [backend, server] {
+        if !is_valid_name(name) {
+            bail!("Invalid HAProxy proxy or server name `{}`", name);
+        }
+    }
+    Ok(format!(
+        "set server {}/{} state {}",
+        backend,
+        server,
+        state.as_str()
+    ))
+}
+
+/// Commands setting `key` to `value` in `map`: update, else add
+fn flag_commands(map: &str, key: &str, value: &str) -> [String; 2] {
+    [
+        format!("set map {} {} {}", map, key, value),
+        format!("add map {} {} {}", map, key, value),
+    ]
+}
+
 #[allow(dead_code)]
 impl HaproxyApi {
     /// Create a new HAProxy API client
@@ -41,17 +158,26 @@ impl HaproxyApi {
         Self {
             socket_path,
             stick_table,
+            flags_map: None,
         }
     }
 
-    /// Create with default paths
-    pub fn default_paths() -> Self {
+    /// Create from the `[haproxy]` settings
+    pub fn from_config(config: &HaproxyConfig) -> Self {
         Self {
-            socket_path: "/var/run/haproxy.sock".to_string(),
-            stick_table: "be_stick_tables".to_string(),
+            flags_map: config.flags_map.clone(),
+            ..Self::new(config.socket_path.clone(), config.stick_table.clone())
         }
     }
 
+    /// Create with default paths
+    pub fn default_paths() -> Self {
+        Self::new(
+            "/var/run/haproxy.sock".to_string(),
+            "be_stick_tables".to_string(),
+        )
+    }
+
     /// Check if socket is accessible
     pub async fn is_available(&self) -> bool {
         #[cfg(unix)]
@@ -66,6 +192,7 @@ impl HaproxyApi {
 
     /// Execute a command and return the response (Unix only)
     #[cfg(unix)]
+    #[tracing::instrument(name = "haproxy.command", skip(self))]
     async fn execute(&self, command: &str) -> Result<String> {
         use anyhow::Context;
         use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
@@ -134,6 +261,29 @@ impl HaproxyApi {
         Ok(())
     }
 
+    /// Set general purpose tag `tag` (`gpt0`, `gpt(N)`) of a circuit's
+    /// stick table entry
+    pub async fn set_circuit_tag(&self, circuit_id: &str, tag: &str, value: u32) -> Result<()> {
+        if !crate::pressure::is_valid_tag(tag) {
+            bail!("Invalid stick table tag `{}`", tag);
+        }
+        if !self.is_available().await {
+            tracing::debug!("HAProxy socket not available, skipping stick table tag");
+            return Ok(());
+        }
+
+        let command = format!(
+            "set table {} key {} data.{} {}",
+            self.stick_table, circuit_id, tag, value
+        );
+        let response = self.execute(&command).await?;
+        if !response.is_empty() && !response.starts_with("Entry") {
+            bail!("HAProxy rejected `{}`: {}", command, response);
+        }
+
+        Ok(())
+    }
+
     /// Promote a circuit to VIP status
     pub async fn promote_to_vip(&self, circuit_id: &str) -> Result<()> {
         self.set_circuit_status(circuit_id, HaproxyCircuitStatus::Vip)
@@ -160,6 +310,86 @@ impl HaproxyApi {
         Ok(())
     }
 
+    /// All stick table entries, optionally only those with `status`
+    ///
+    /// HAProxy dumps the table in one go; entries are parsed as the
+    /// iterator advances.
+    pub async fn iter_table(&self, status: Option<HaproxyCircuitStatus>) -> Result<TableEntries> {
+        if !self.is_available().await {
+            return Ok(TableEntries::new(String::new()));
+        }
+
+        let command = format!("show table {}{}", self.stick_table, status_filter(status));
+        Ok(TableEntries::new(self.execute(&command).await?))
+    }
+
+    /// Remove every stick table entry, or only those with `status`
+    pub async fn clear_table(&self, status: Option<HaproxyCircuitStatus>) -> Result<()> {
+        if !self.is_available().await {
+            return Ok(());
+        }
+
+        let command = format!("clear table {}{}", self.stick_table, status_filter(status));
+        let response = self.execute(&command).await?;
+        if !response.is_empty() {
+            bail!("HAProxy rejected `{}`: {}", command, response);
+        }
+
+        tracing::info!(status = ?status, "Cleared HAProxy stick table entries");
+
+        Ok(())
+    }
+
+    /// Set the state of `server` in `backend`
+    pub async fn set_server_state(
+        &self,
+        backend: &str,
+        server: &str,
+        state: ServerState,
+    ) -> Result<()> {
+        let command = server_state_command(backend, server, state)?;
+        if !self.is_available().await {
+            tracing::debug!("HAProxy socket not available, skipping server state change");
+            return Ok(());
+        }
+
+        // Success is an empty answer; anything else is an error message
+        let response = self.execute(&command).await?;
+        if !response.is_empty() {
+            bail!("HAProxy rejected `{}`: {}", command, response);
+        }
+
+        tracing::info!(
+            backend = backend,
+            server = server,
+            state = state.as_str(),
+            "Changed HAProxy server state"
+        );
+
+        Ok(())
+    }
+
+    /// Raise or lower the maintenance flag (no-op without `flags_map`)
+    pub async fn set_maintenance(&self, active: bool) -> Result<()> {
+        let Some(ref map) = self.flags_map else {
+            return Ok(());
+        };
+        if !self.is_available().await {
+            tracing::debug!("HAProxy socket not available, skipping maintenance flag");
+            return Ok(());
+        }
+
+        let value = if active { "1" } else { "0" };
+        for command in flag_commands(map, MAINTENANCE_FLAG, value) {
+            // `set map` fails on a missing key; `add map` then creates it
+            if self.execute(&command).await?.is_empty() {
+                tracing::info!(active = active, "Set HAProxy maintenance flag");
+                return Ok(());
+            }
+        }
+        bail!("HAProxy rejected the maintenance flag in {}", map)
+    }
+
     /// Get circuit info from stick table
     pub async fn get_circuit_info(&self, circuit_id: &str) -> Result<Option<StickTableEntry>> {
         if !self.is_available().await {
@@ -189,28 +419,7 @@ impl HaproxyApi {
         }
 
         let response = self.execute("show stat").await?;
-        let mut stats = HaproxyStats::default();
-
-        for line in response.lines() {
-            if line.starts_with('#') || line.is_empty() {
-                continue;
-            }
-
-            let fields: Vec<&str> = line.split(',').collect();
-            if fields.len() < 10 {
-                continue;
-            }
-
-            if let Ok(scur) = fields.get(4).unwrap_or(&"0").parse::<u64>() {
-                stats.current_sessions += scur;
-            }
-
-            if let Ok(stot) = fields.get(7).unwrap_or(&"0").parse::<u64>() {
-                stats.total_sessions += stot;
-            }
-        }
-
-        Ok(stats)
+        Ok(HaproxyStats::parse(&response))
     }
 
     /// Get stick table statistics
@@ -221,44 +430,179 @@ impl HaproxyApi {
 
         let command = format!("show table {}", self.stick_table);
         let response = self.execute(&command).await?;
-        let mut stats = TableStats::default();
+        Ok(response
+            .lines()
+            .next()
+            .map(TableStats::parse)
+            .unwrap_or_default())
+    }
 
-        if let Some(header) = response.lines().next() {
-            if let Some(used_part) = header.split("used:").nth(1) {
-                if let Ok(used) = used_part.trim().parse::<u64>() {
-                    stats.entries_used = used;
-                }
+    /// Name of the circuit stick table
+    pub fn stick_table(&self) -> &str {
+        &self.stick_table
+    }
+}
+
+/// Drains the HAProxy server of a peer that gossip reports unhealthy, and
+/// puts it back in rotation when the peer recovers
+pub struct PeerDrain {
+    api: Arc<HaproxyApi>,
+    backend: String,
+    servers: HashMap<String, String>,
+}
+
+impl PeerDrain {
+    pub fn new(api: Arc<HaproxyApi>, config: &HaproxyConfig) -> Self {
+        Self {
+            api,
+            backend: config.backend.clone(),
+            servers: config.servers.clone(),
+        }
+    }
+
+    /// Server to change for `event`, and its new state
+    fn target(&self, event: &CerberusEvent) -> Option<(&str, ServerState)> {
+        let (node_id, state) = match event {
+            CerberusEvent::PeerUnhealthy { node_id } => (node_id, ServerState::Drain),
+            CerberusEvent::PeerRecovered { node_id } => (node_id, ServerState::Ready),
+            _ => return None,
+        };
+        match self.servers.get(node_id) {
+            Some(server) => Some((server.as_str(), state)),
+            None => {
+                tracing::debug!(node_id = %node_id, "No HAProxy server mapped for peer");
+                None
             }
-            if let Some(size_part) = header.split("size:").nth(1) {
-                if let Some(size_str) = size_part.split(',').next() {
-                    if let Ok(size) = size_str.trim().parse::<u64>() {
-                        stats.entries_max = size;
-                    }
-                }
+        }
+    }
+}
+
+impl EventSubscriber for PeerDrain {
+    fn on_event(&self, event: &CerberusEvent) {
+        let Some((server, state)) = self.target(event) else {
+            return;
+        };
+        let api = self.api.clone();
+        let backend = self.backend.clone();
+        let server = server.to_string();
+        tokio::spawn(async move {
+            if let Err(e) = api.set_server_state(&backend, &server, state).await {
+                tracing::warn!(
+                    error = %e,
+                    backend = %backend,
+                    server = %server,
+                    "Failed to change HAProxy server state"
+                );
+            }
+        });
+    }
+}
+
+/// Attempts at each stick table update made by `StickTableSync`
+const SYNC_ATTEMPTS: u32 = 3;
+
+/// Delay before the first retry (doubles after each one)
+const SYNC_RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// Change a circuit's stick table entry needs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StickUpdate {
+    Set(HaproxyCircuitStatus),
+    Clear,
+}
+
+/// Mirrors bans, unbans and VIP changes into the HAProxy stick table, so
+/// they take effect at HAProxy without a separate call
+///
+/// Updates are retried a few times on socket errors (e.g. HAProxy reloading).
+pub struct StickTableSync {
+    api: Arc<HaproxyApi>,
+}
+
+impl StickTableSync {
+    pub fn new(api: Arc<HaproxyApi>) -> Self {
+        Self { api }
+    }
+
+    /// Circuit to update for `event`, and how
+    fn update(event: &CerberusEvent) -> Option<(&CircuitId, StickUpdate)> {
+        let (circuit_id, update) = match event {
+            CerberusEvent::CircuitBanned { circuit_id, .. } => {
+                (circuit_id, StickUpdate::Set(HaproxyCircuitStatus::Banned))
+            }
+            CerberusEvent::VipPromoted { circuit_id } => {
+                (circuit_id, StickUpdate::Set(HaproxyCircuitStatus::Vip))
+            }
+            CerberusEvent::VipDemoted { circuit_id, .. } => {
+                (circuit_id, StickUpdate::Set(HaproxyCircuitStatus::Normal))
             }
+            CerberusEvent::CircuitUnbanned { circuit_id } => (circuit_id, StickUpdate::Clear),
+            _ => return None,
+        };
+        Some((circuit_id, update))
+    }
+
+    async fn apply(api: &HaproxyApi, circuit_id: &CircuitId, update: StickUpdate) -> Result<()> {
+        match update {
+            StickUpdate::Set(status) => api.set_circuit_status(circuit_id.as_str(), status).await,
+            StickUpdate::Clear => api.clear_circuit(circuit_id.as_str()).await,
         }
+    }
+}
 
-        Ok(stats)
+impl EventSubscriber for StickTableSync {
+    fn on_event(&self, event: &CerberusEvent) {
+        let Some((circuit_id, update)) = Self::update(event) else {
+            return;
+        };
+        let api = self.api.clone();
+        let circuit_id = circuit_id.clone();
+        tokio::spawn(async move {
+            let mut delay = SYNC_RETRY_DELAY;
+            for attempt in 1..=SYNC_ATTEMPTS {
+                match Self::apply(&api, &circuit_id, update).await {
+                    Ok(()) => return,
+                    Err(e) if attempt < SYNC_ATTEMPTS => {
+                        tracing::debug!(error = %e, attempt, "Retrying HAProxy stick table update");
+                        tokio::time::sleep(delay).await;
+                        delay *= 2;
+                    }
+                    Err(e) => tracing::warn!(
+                        error = %e,
+                        circuit_id = %circuit_id,
+                        update = ?update,
+                        "Failed to update HAProxy stick table"
+                    ),
+                }
+            }
+        });
     }
 }
 
 /// Parsed stick table entry
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, Serialize)]
 #[allow(dead_code)]
 pub struct StickTableEntry {
+    /// Table key (the circuit ID)
+    pub key: String,
     pub conn_cur: u32,
     pub conn_rate: u32,
     pub http_req_rate: u32,
     pub gpc0: u8,
+    /// General purpose tags stored, by data type (`gpt0`, `gpt(1)`, ...)
+    pub tags: BTreeMap<String, u32>,
     pub expire_secs: u64,
 }
 
 impl StickTableEntry {
-    fn parse(line: &str) -> Result<Self> {
+    /// Parse one `show table` entry line (unknown fields are ignored)
+    pub fn parse(line: &str) -> Result<Self> {
         let mut entry = StickTableEntry::default();
 
         for part in line.split_whitespace() {
-            if let Some(val) = part.strip_prefix("conn_cur=") {
+            if let Some(val) = part.strip_prefix("key=") {
+                entry.key = val.to_string();
+            } else if let Some(val) = part.strip_prefix("conn_cur=") {
                 entry.conn_cur = val.parse().unwrap_or(0);
             } else if part.starts_with("conn_rate") {
                 if let Some(eq_pos) = part.find('=') {
@@ -272,6 +616,10 @@ impl StickTableEntry {
                 entry.gpc0 = val.parse().unwrap_or(0);
             } else if let Some(val) = part.strip_prefix("exp=") {
                 entry.expire_secs = val.parse().unwrap_or(0);
+            } else if part.starts_with("gpt")
+                && let Some((tag, val)) = part.split_once('=')
+            {
+                entry.tags.insert(tag.to_string(), val.parse().unwrap_or(0));
             }
         }
 
@@ -279,22 +627,174 @@ impl StickTableEntry {
     }
 }
 
-/// HAProxy runtime statistics
-#[derive(Debug, Clone, Default)]
+/// Entries of a `show table` dump, parsed lazily
+pub struct TableEntries {
+    dump: String,
+    pos: usize,
+}
+
+impl TableEntries {
+    fn new(dump: String) -> Self {
+        Self { dump, pos: 0 }
+    }
+}
+
+impl Iterator for TableEntries {
+    type Item = StickTableEntry;
+
+    fn next(&mut self) -> Option<StickTableEntry> {
+        while self.pos < self.dump.len() {
+            let rest = &self.dump[self.pos..];
+            let line = rest.split('\n').next().unwrap_or(rest);
+            self.pos += line.len() + 1;
+
+            // Skip the `# table: ...` header and anything else that isn't an entry
+            if line.starts_with('#') || !line.contains("key=") {
+                continue;
+            }
+            if let Ok(entry) = StickTableEntry::parse(line) {
+                return Some(entry);
+            }
+        }
+        None
+    }
+}
+
+/// HAProxy runtime statistics (totals over all frontends)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 #[allow(dead_code)]
 pub struct HaproxyStats {
     pub current_sessions: u64,
     pub total_sessions: u64,
+    /// Requests denied by `http-request deny` and friends
+    pub denied_requests: u64,
+}
+
+impl HaproxyStats {
+    /// Parse `show stat` CSV output
+    ///
+    /// Only `FRONTEND` rows are summed: backend and server rows count the
+    /// same sessions again.
+    pub(crate) fn parse(csv: &str) -> Self {
+        let mut stats = HaproxyStats::default();
+
+        for line in csv.lines() {
+            if line.starts_with('#') || line.is_empty() {
+                continue;
+            }
+
+            // pxname,svname,qcur,qmax,scur,smax,slim,stot,bin,bout,dreq,...
+            let fields: Vec<&str> = line.split(',').collect();
+            if fields.len() < 11 || fields[1] != "FRONTEND" {
+                continue;
+            }
+
+            let field = |i: usize| fields[i].parse::<u64>().unwrap_or(0);
+            stats.current_sessions += field(4);
+            stats.total_sessions += field(7);
+            stats.denied_requests += field(10);
+        }
+
+        stats
+    }
 }
 
 /// Stick table statistics
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 #[allow(dead_code)]
 pub struct TableStats {
     pub entries_used: u64,
     pub entries_max: u64,
 }
 
+impl TableStats {
+    /// Parse the `show table` header, e.g.
+    /// `# table: be_stick_tables, type: string, size:1048576, used:42`
+    pub(crate) fn parse(header: &str) -> Self {
+        let mut stats = TableStats::default();
+
+        if let Some(used_part) = header.split("used:").nth(1)
+            && let Ok(used) = used_part.trim().parse::<u64>()
+        {
+            stats.entries_used = used;
+        }
+        if let Some(size_part) = header.split("size:").nth(1)
+            && let Some(size_str) = size_part.split(',').next()
+            && let Ok(size) = size_str.trim().parse::<u64>()
+        {
+            stats.entries_max = size;
+        }
+
+        stats
+    }
+
+    /// Fraction of the table in use (0 if the size is unknown)
+    pub fn utilization(&self) -> f64 {
+        if self.entries_max == 0 {
+            return 0.0;
+        }
+        self.entries_used as f64 / self.entries_max as f64
+    }
+}
+
+/// Poll HAProxy for statistics into the metrics, every `stats_interval_secs`
+///
+/// Publishes `StickTableNearFull` when the circuit table crosses
+/// `table_full_ratio` (once per crossing).
+pub async fn haproxy_stats_worker(
+    api: Arc<HaproxyApi>,
+    config: HaproxyConfig,
+    events: Arc<dyn EventPublisher>,
+    mut shutdown: tokio::sync::broadcast::Receiver<()>,
+) {
+    let interval = Duration::from_secs(config.stats_interval_secs.max(1));
+    let mut near_full = false;
+
+    tracing::info!("📊 HAProxy stats poller started (interval: {:?})", interval);
+
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {
+                match api.get_stats().await {
+                    Ok(stats) => {
+                        metrics::HAPROXY_CURRENT_SESSIONS.set(stats.current_sessions as i64);
+                        metrics::HAPROXY_SESSIONS.set(stats.total_sessions as i64);
+                        metrics::HAPROXY_DENIED_REQUESTS.set(stats.denied_requests as i64);
+                    }
+                    Err(e) => tracing::warn!(error = %e, "Failed to poll HAProxy stats"),
+                }
+
+                match api.get_table_stats().await {
+                    Ok(table) => {
+                        metrics::HAPROXY_TABLE_ENTRIES.set(table.entries_used as i64);
+                        metrics::HAPROXY_TABLE_SIZE.set(table.entries_max as i64);
+
+                        let full = table.utilization() >= config.table_full_ratio;
+                        if full && !near_full {
+                            tracing::warn!(
+                                used = table.entries_used,
+                                size = table.entries_max,
+                                "HAProxy stick table nearly full"
+                            );
+                            events.publish(CerberusEvent::StickTableNearFull {
+                                table: api.stick_table().to_string(),
+                                used: table.entries_used,
+                                capacity: table.entries_max,
+                            });
+                        }
+                        near_full = full;
+                    }
+                    Err(e) => tracing::warn!(error = %e, "Failed to poll HAProxy stick table"),
+                }
+            }
+            _ = shutdown.recv() => {
+                tracing::info!("📊 HAProxy stats poller shutting down");
+                break;
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -304,10 +804,157 @@ mod tests {
         let line = "0x12345678: key=abc123 use=1 exp=1800 conn_cur=3 conn_rate(10000)=5 http_req_rate(10000)=10 gpc0=1";
         let entry = StickTableEntry::parse(line).unwrap();
 
+        assert_eq!(entry.key, "abc123");
         assert_eq!(entry.conn_cur, 3);
         assert_eq!(entry.conn_rate, 5);
         assert_eq!(entry.http_req_rate, 10);
         assert_eq!(entry.gpc0, 1);
         assert_eq!(entry.expire_secs, 1800);
     }
+
+    #[test]
+    fn test_stick_table_entry_parse_tags() {
+        let line = "0x12345678: key=abc123 use=1 exp=1800 gpc0=1 gpt0=2 gpt(1)=7 gpt(2)=x";
+        let entry = StickTableEntry::parse(line).unwrap();
+
+        assert_eq!(entry.gpc0, 1);
+        assert_eq!(entry.tags.get("gpt0"), Some(&2));
+        assert_eq!(entry.tags.get("gpt(1)"), Some(&7));
+        assert_eq!(entry.tags.get("gpt(2)"), Some(&0));
+        assert_eq!(entry.expire_secs, 1800);
+    }
+
+    #[test]
+    fn test_table_entries() {
+        let dump = "\
+# table: be_stick_tables, type: string, size:1000, used:2
+0x1: key=fc00::1 use=0 exp=1000 gpc0=2
+0x2: key=fc00::2 use=0 exp=2000 gpc0=0
+";
+        let keys: Vec<_> = TableEntries::new(dump.to_string())
+            .map(|entry| (entry.key, entry.gpc0))
+            .collect();
+        assert_eq!(
+            keys,
+            [("fc00::1".to_string(), 2), ("fc00::2".to_string(), 0)]
+        );
+        assert_eq!(TableEntries::new(String::new()).count(), 0);
+
+        assert_eq!(
+            status_filter(Some(HaproxyCircuitStatus::Banned)),
+            " data.gpc0 eq 2"
+        );
+        assert_eq!(status_filter(None), "");
+    }
+
+    #[test]
+    fn test_stats_parse() {
+        let csv = "\
+# pxname,svname,qcur,qmax,scur,smax,slim,stot,bin,bout,dreq,dresp,ereq
+ft_tor_public,FRONTEND,,,12,40,5000,900,0,0,7,0,1
+be_nginx_public,nginx_local,0,0,12,40,5000,900,0,0,,0,
+be_nginx_public,BACKEND,0,0,12,40,500,900,0,0,0,0,
+ft_admin,FRONTEND,,,1,2,100,3,0,0,0,0,0";
+        assert_eq!(
+            HaproxyStats::parse(csv),
+            HaproxyStats {
+                current_sessions: 13,
+                total_sessions: 903,
+                denied_requests: 7,
+            }
+        );
+
+        let table =
+            TableStats::parse("# table: be_stick_tables, type: string, size:1000, used:950");
+        assert_eq!(table.entries_used, 950);
+        assert_eq!(table.entries_max, 1000);
+        assert!(table.utilization() >= 0.9);
+        assert_eq!(TableStats::default().utilization(), 0.0);
+    }
+
+    #[test]
+    fn test_server_state_command() {
+        assert_eq!(
+            server_state_command("be_fortify", "node-2", ServerState::Drain).unwrap(),
+            "set server be_fortify/node-2 state drain"
+        );
+        // One command per connection: no smuggling a second one in
+        assert!(
+            server_state_command("be_fortify", "x;shutdown sessions", ServerState::Maint).is_err()
+        );
+        assert!(server_state_command("", "node-2", ServerState::Ready).is_err());
+    }
+
+    #[test]
+    fn test_flag_commands() {
+        assert_eq!(
+            flag_commands("/etc/haproxy/flags.map", MAINTENANCE_FLAG, "1"),
+            [
+                "set map /etc/haproxy/flags.map maintenance 1",
+                "add map /etc/haproxy/flags.map maintenance 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stick_table_sync_updates() {
+        let circuit_id: CircuitId = "fc00::1".parse().unwrap();
+        let banned = CerberusEvent::CircuitBanned {
+            circuit_id: circuit_id.clone(),
+            reason: None,
+        };
+        let unbanned = CerberusEvent::CircuitUnbanned {
+            circuit_id: circuit_id.clone(),
+        };
+        let demoted = CerberusEvent::VipDemoted {
+            circuit_id: circuit_id.clone(),
+            reason: Some("rate limited".to_string()),
+        };
+        let soft_locked = CerberusEvent::CircuitSoftLocked {
+            circuit_id: circuit_id.clone(),
+            reason: None,
+        };
+
+        assert_eq!(
+            StickTableSync::update(&banned),
+            Some((&circuit_id, StickUpdate::Set(HaproxyCircuitStatus::Banned)))
+        );
+        assert_eq!(
+            StickTableSync::update(&unbanned),
+            Some((&circuit_id, StickUpdate::Clear))
+        );
+        assert_eq!(
+            StickTableSync::update(&demoted),
+            Some((&circuit_id, StickUpdate::Set(HaproxyCircuitStatus::Normal)))
+        );
+        assert_eq!(StickTableSync::update(&soft_locked), None);
+    }
+
+    #[test]
+    fn test_peer_drain_targets() {
+        let mut config = HaproxyConfig::default();
+        config
+            .servers
+            .insert("node-2".to_string(), "fortify2".to_string());
+        let drain = PeerDrain::new(Arc::new(HaproxyApi::from_config(&config)), &config);
+
+        let unhealthy = CerberusEvent::PeerUnhealthy {
+            node_id: "node-2".to_string(),
+        };
+        let recovered = CerberusEvent::PeerRecovered {
+            node_id: "node-2".to_string(),
+        };
+        let unmapped = CerberusEvent::PeerUnhealthy {
+            node_id: "node-3".to_string(),
+        };
+        assert_eq!(
+            drain.target(&unhealthy),
+            Some(("fortify2", ServerState::Drain))
+        );
+        assert_eq!(
+            drain.target(&recovered),
+            Some(("fortify2", ServerState::Ready))
+        );
+        assert_eq!(drain.target(&unmapped), None);
+    }
 }
@@ -0,0 +1,370 @@
+//! Whole-flow latency of a visitor's way in.
+//!
+//! Getting through the gate takes four steps: the gate page renders a
+//! challenge, the visitor answers it, a passport is issued, and the first
+//! request carrying the passport is validated. The time that takes is the
+//! friction Cerberus adds for a real user, and it grows with the threat
+//! level. For every passport issued on a solved challenge the timestamps are
+//! kept in Redis (`journey:{token}`, for the passport's lifetime). The first
+//! validated request completes the journey, adding its stages to the totals
+//! of the threat level the passport was issued at (`metrics:journey:{level}`,
+//! shared by the cluster) and to the `fortify_journey_seconds` histogram.
+//! `/admin/journeys` reports the mean of each stage per threat level.
+//!
+//! VIP and proof-of-work passes skip the challenge and aren't tracked, nor
+//! are passports issued while Redis is offline. Completing a journey costs
+//! every validated request a Redis round trip; set `journeys.enabled = false`
+//! to save it.
+
+use anyhow::Result;
+use cerberus_common::{PassportToken, ThreatLevel, redis_keys};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::metrics;
+
+/// Journey settings (`[journeys]` in fortify.toml)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JourneyConfig {
+    pub enabled: bool,
+}
+
+impl Default for JourneyConfig {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+/// A leg of the journey
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Gate render to answer received
+    Solve,
+    /// Answer received to passport committed
+    Issue,
+    /// Passport committed to first validated request
+    FirstRequest,
+    /// Gate render to first validated request
+    Total,
+}
+
+impl Stage {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Solve => "solve",
+            Self::Issue => "issue",
+            Self::FirstRequest => "first_request",
+            Self::Total => "total",
+        }
+    }
+
+    /// Hash field of the stage's total in `metrics:journey:{level}`
+    fn total_field(self) -> String {
+        format!("{}_ms", self.as_str())
+    }
+}
+
+/// Timestamps of one passport's journey (Unix ms)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Journey {
+    /// Threat level the passport was issued at
+    pub threat_level: ThreatLevel,
+    pub rendered_at_ms: i64,
+    pub solved_at_ms: i64,
+    pub issued_at_ms: i64,
+    /// `None` until a request with the passport is validated
+    pub first_request_at_ms: Option<i64>,
+}
+
+impl Journey {
+    /// How long each stage took, once the journey is complete
+    pub fn stages(&self) -> Option<[(Stage, u64); 4]> {
+        let first_request_at_ms = self.first_request_at_ms?;
+        let span = |from: i64, to: i64| (to - from).max(0) as u64;
+        Some([
+            (Stage::Solve, span(self.rendered_at_ms, self.solved_at_ms)),
+            (Stage::Issue, span(self.solved_at_ms, self.issued_at_ms)),
+            (
+                Stage::FirstRequest,
+                span(self.issued_at_ms, first_request_at_ms),
+            ),
+            (Stage::Total, span(self.rendered_at_ms, first_request_at_ms)),
+        ])
+    }
+
+    fn fields(&self) -> [(&'static str, i64); 4] {
+        [
+            ("threat_level", self.threat_level.value() as i64),
+            ("rendered_at_ms", self.rendered_at_ms),
+            ("solved_at_ms", self.solved_at_ms),
+            ("issued_at_ms", self.issued_at_ms),
+        ]
+    }
+
+    /// A journey read back from its hash (`None` if fields are missing)
+    fn from_fields(fields: &HashMap<String, i64>) -> Option<Self> {
+        let threat_level = u8::try_from(*fields.get("threat_level")?).ok()?;
+        Some(Self {
+            threat_level: ThreatLevel::new(threat_level),
+            rendered_at_ms: *fields.get("rendered_at_ms")?,
+            solved_at_ms: *fields.get("solved_at_ms")?,
+            issued_at_ms: *fields.get("issued_at_ms")?,
+            first_request_at_ms: fields.get("first_request_at_ms").copied(),
+        })
+    }
+}
+
+/// Mean stage times of the journeys completed at one threat level,
+/// cluster-wide
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LevelReport {
+    pub threat_level: ThreatLevel,
+    pub journeys: u64,
+    pub solve_ms: f64,
+    pub issue_ms: f64,
+    pub first_request_ms: f64,
+    pub total_ms: f64,
+}
+
+impl LevelReport {
+    /// `None` if no journey was completed at `threat_level`
+    fn new(threat_level: ThreatLevel, totals: &HashMap<String, u64>) -> Option<Self> {
+        let journeys = totals.get("count").copied().filter(|&n| n > 0)?;
+        let mean = |stage: Stage| {
+            totals.get(&stage.total_field()).copied().unwrap_or(0) as f64 / journeys as f64
+        };
+        Some(Self {
+            threat_level,
+            journeys,
+            solve_ms: mean(Stage::Solve),
+            issue_ms: mean(Stage::Issue),
+            first_request_ms: mean(Stage::FirstRequest),
+            total_ms: mean(Stage::Total),
+        })
+    }
+}
+
+/// Records journeys and their totals
+#[derive(Debug)]
+pub struct Journeys {
+    enabled: bool,
+}
+
+impl Journeys {
+    pub fn new(config: &JourneyConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+        }
+    }
+
+    /// The journey of a passport being issued now, for a challenge answered
+    /// at `solved_at_ms` after `solve_ms` on the gate page (`None` when
+    /// disabled)
+    pub fn start(
+        &self,
+        solved_at_ms: i64,
+        solve_ms: u64,
+        threat_level: ThreatLevel,
+    ) -> Option<Journey> {
+        self.enabled.then(|| Journey {
+            threat_level,
+            rendered_at_ms: solved_at_ms - solve_ms as i64,
+            solved_at_ms,
+            issued_at_ms: chrono::Utc::now().timestamp_millis(),
+            first_request_at_ms: None,
+        })
+    }
+
+    /// Store `journey` for `token` into `pipe`, to be committed with the
+    /// passport and to expire with it at `expires_at` (Unix seconds)
+    pub fn queue_start(
+        &self,
+        pipe: &mut redis::Pipeline,
+        token: &PassportToken,
+        expires_at: i64,
+        journey: &Journey,
+    ) {
+        let key = redis_keys::journey(token);
+        pipe.hset_multiple(&key, &journey.fields())
+            .ignore()
+            .expire_at(&key, expires_at)
+            .ignore();
+    }
+
+    /// Complete the journey of `token` on a validated request (best-effort)
+    ///
+    /// Only the first validated request counts; later ones, and passports
+    /// without a journey, are a no-op.
+    pub async fn complete(&self, redis: &mut redis::aio::ConnectionManager, token: &PassportToken) {
+        if !self.enabled {
+            return;
+        }
+        if let Err(e) = self.try_complete(redis, token).await {
+            tracing::warn!(error = %e, "Failed to complete passport journey");
+        }
+    }
+
+    async fn try_complete(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        token: &PassportToken,
+    ) -> Result<()> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let fields: Option<HashMap<String, i64>> = complete_script()
+            .key(redis_keys::journey(token))
+            .arg(now)
+            .invoke_async(redis)
+            .await?;
+        let Some(journey) = fields.as_ref().and_then(Journey::from_fields) else {
+            return Ok(());
+        };
+        let Some(stages) = journey.stages() else {
+            return Ok(());
+        };
+
+        let level = journey.threat_level.to_string();
+        let key = redis_keys::journey_totals(journey.threat_level);
+        let mut pipe = redis::pipe();
+        pipe.hincr(&key, "count", 1).ignore();
+        for (stage, ms) in stages {
+            pipe.hincr(&key, stage.total_field(), ms).ignore();
+            metrics::JOURNEY_SECONDS
+                .with_label_values(&[stage.as_str(), level.as_str()])
+                .observe(ms as f64 / 1000.0);
+        }
+        pipe.query_async::<()>(redis).await?;
+        Ok(())
+    }
+
+    /// The journey of `token`, while its passport lives
+    pub async fn get(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+        token: &PassportToken,
+    ) -> Result<Option<Journey>> {
+        let fields: HashMap<String, i64> = redis.hgetall(redis_keys::journey(token)).await?;
+        Ok(Journey::from_fields(&fields))
+    }
+
+    /// Mean stage times per threat level (levels without a completed
+    /// journey are left out)
+    pub async fn report(
+        &self,
+        redis: &mut redis::aio::ConnectionManager,
+    ) -> Result<Vec<LevelReport>> {
+        let levels: Vec<ThreatLevel> = (ThreatLevel::MIN.value()..=ThreatLevel::MAX.value())
+            .map(ThreatLevel::new)
+            .collect();
+        let mut pipe = redis::pipe();
+        for &level in &levels {
+            pipe.hgetall(redis_keys::journey_totals(level));
+        }
+        let totals: Vec<HashMap<String, u64>> = pipe.query_async(redis).await?;
+
+        Ok(levels
+            .into_iter()
+            .zip(totals)
+            .filter_map(|(level, totals)| LevelReport::new(level, &totals))
+            .collect())
+    }
+}
+
+/// Stamp the first validated request on a journey, returning its fields
+/// (nil if there's no journey or it was already complete)
+fn complete_script() -> redis::Script {
+    redis::Script::new(
+        r"
+        if redis.call('EXISTS', KEYS[1]) == 0 then return false end
+        if redis.call('HSETNX', KEYS[1], 'first_request_at_ms', ARGV[1]) == 0 then
+            return false
+        end
+        return redis.call('HGETALL', KEYS[1])
+        ",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn journey() -> Journey {
+        Journey {
+            threat_level: ThreatLevel::new(6),
+            rendered_at_ms: 1_000,
+            solved_at_ms: 9_000,
+            issued_at_ms: 9_040,
+            first_request_at_ms: Some(9_540),
+        }
+    }
+
+    #[test]
+    fn test_stages() {
+        assert_eq!(
+            journey().stages(),
+            Some([
+                (Stage::Solve, 8_000),
+                (Stage::Issue, 40),
+                (Stage::FirstRequest, 500),
+                (Stage::Total, 8_540),
+            ])
+        );
+
+        let pending = Journey {
+            first_request_at_ms: None,
+            ..journey()
+        };
+        assert_eq!(pending.stages(), None);
+
+        // Clock skew between nodes never yields a negative stage
+        let skewed = Journey {
+            first_request_at_ms: Some(9_000),
+            ..journey()
+        };
+        assert_eq!(skewed.stages().unwrap()[2], (Stage::FirstRequest, 0));
+    }
+
+    #[test]
+    fn test_fields_round_trip() {
+        let pending = Journey {
+            first_request_at_ms: None,
+            ..journey()
+        };
+        let mut fields: HashMap<String, i64> = pending
+            .fields()
+            .iter()
+            .map(|&(name, value)| (name.to_string(), value))
+            .collect();
+        assert_eq!(Journey::from_fields(&fields), Some(pending));
+
+        fields.insert("first_request_at_ms".to_string(), 9_540);
+        assert_eq!(Journey::from_fields(&fields), Some(journey()));
+
+        fields.remove("solved_at_ms");
+        assert_eq!(Journey::from_fields(&fields), None);
+        assert_eq!(Journey::from_fields(&HashMap::new()), None);
+    }
+
+    #[test]
+    fn test_level_report() {
+        let totals: HashMap<String, u64> = [
+            ("count", 4),
+            ("solve_ms", 40_000),
+            ("issue_ms", 200),
+            ("first_request_ms", 2_000),
+            ("total_ms", 42_200),
+        ]
+        .into_iter()
+        .map(|(field, value)| (field.to_string(), value))
+        .collect();
+        let report = LevelReport::new(ThreatLevel::new(3), &totals).unwrap();
+        assert_eq!(report.journeys, 4);
+        assert_eq!(report.solve_ms, 10_000.0);
+        assert_eq!(report.issue_ms, 50.0);
+        assert_eq!(report.first_request_ms, 500.0);
+        assert_eq!(report.total_ms, 10_550.0);
+
+        assert_eq!(LevelReport::new(ThreatLevel::new(3), &HashMap::new()), None);
+    }
+}
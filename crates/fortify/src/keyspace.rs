@@ -0,0 +1,223 @@
+//! Redis keyspace statistics.
+//!
+//! Every `interval_secs`, a sweep walks the keyspace with SCAN (`scan_count`
+//! keys a step) and counts keys by family (see `redis_keys::FAMILIES`):
+//! challenges outstanding, passports active, circuits tracked and so on.
+//! Every key of a family should expire; one without a TTL is counted as
+//! orphaned, since nothing will ever remove it.
+//!
+//! A sweep stops after `max_keys` keys, so a huge keyspace costs a bounded
+//! amount of work; the counts are then scaled up by `DBSIZE` (estimates,
+//! assuming the part seen is typical). Results go to the
+//! `fortify_redis_keys` and `fortify_redis_orphaned_keys` gauges.
+
+use anyhow::Result;
+use cerberus_common::redis_keys::{self, FAMILIES};
+use redis::aio::ConnectionManager;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::metrics;
+use crate::state::AppState;
+
+/// Keyspace sweep settings (`[keyspace]` in fortify.toml)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyspaceConfig {
+    pub enabled: bool,
+    pub interval_secs: u64,
+    /// SCAN `COUNT` hint: keys looked at per step
+    pub scan_count: usize,
+    /// Keys looked at per sweep, at most
+    pub max_keys: usize,
+}
+
+impl Default for KeyspaceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_secs: 300,
+            scan_count: 1000,
+            max_keys: 200_000,
+        }
+    }
+}
+
+/// Keys of one family
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FamilyCount {
+    pub keys: u64,
+    /// Keys without a TTL
+    pub orphaned: u64,
+}
+
+/// What a sweep saw
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Tally {
+    /// Keys looked at
+    pub scanned: u64,
+    /// The whole keyspace was seen
+    pub complete: bool,
+    pub families: BTreeMap<&'static str, FamilyCount>,
+}
+
+impl Tally {
+    /// Counts scaled up to a keyspace of `total` keys, if the sweep stopped
+    /// early
+    pub fn estimate(&self, total: u64) -> BTreeMap<&'static str, FamilyCount> {
+        if self.complete || self.scanned == 0 || total <= self.scanned {
+            return self.families.clone();
+        }
+        let scale = total as f64 / self.scanned as f64;
+        let scaled = |n: u64| (n as f64 * scale).round() as u64;
+        self.families
+            .iter()
+            .map(|(&name, count)| {
+                (
+                    name,
+                    FamilyCount {
+                        keys: scaled(count.keys),
+                        orphaned: scaled(count.orphaned),
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+/// Walk the keyspace, counting keys (and keys without a TTL) by family
+async fn sweep(redis: &mut ConnectionManager, config: &KeyspaceConfig) -> Result<Tally> {
+    let mut tally = Tally::default();
+    for family in FAMILIES {
+        tally.families.insert(family.name, FamilyCount::default());
+    }
+
+    let mut cursor: u64 = 0;
+    loop {
+        let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+            .arg(cursor)
+            .arg("COUNT")
+            .arg(config.scan_count.max(1))
+            .query_async(redis)
+            .await?;
+
+        let mut pipe = redis::pipe();
+        let mut counted = Vec::new();
+        for key in &keys {
+            if let Some(family) = redis_keys::family(key) {
+                pipe.ttl(key);
+                counted.push(family.name);
+            }
+        }
+        // TTL is -1 for a key without expiry, -2 if it's gone since
+        let ttls: Vec<i64> = if counted.is_empty() {
+            Vec::new()
+        } else {
+            pipe.query_async(redis).await?
+        };
+        for (name, ttl) in counted.into_iter().zip(ttls) {
+            if ttl == -2 {
+                continue;
+            }
+            let count = tally.families.entry(name).or_default();
+            count.keys += 1;
+            if ttl == -1 {
+                count.orphaned += 1;
+            }
+        }
+
+        tally.scanned += keys.len() as u64;
+        cursor = next;
+        if cursor == 0 {
+            tally.complete = true;
+            break;
+        }
+        if tally.scanned >= config.max_keys as u64 {
+            break;
+        }
+    }
+    Ok(tally)
+}
+
+/// Sweep the keyspace every `interval_secs` into the metrics
+pub async fn keyspace_worker(state: AppState, mut shutdown: tokio::sync::broadcast::Receiver<()>) {
+    let config = state.config.keyspace.clone();
+    if !config.enabled {
+        return;
+    }
+    let interval = Duration::from_secs(config.interval_secs.max(1));
+
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {
+                let Some(mut redis) = state.redis() else {
+                    continue;
+                };
+                let started = Instant::now();
+                let result = async {
+                    let tally = sweep(&mut redis, &config).await?;
+                    let total: u64 = redis::cmd("DBSIZE").query_async(&mut redis).await?;
+                    anyhow::Ok((tally, total))
+                }
+                .await;
+                match result {
+                    Ok((tally, total)) => {
+                        for (family, count) in tally.estimate(total) {
+                            metrics::REDIS_KEYS
+                                .with_label_values(&[family])
+                                .set(count.keys as i64);
+                            metrics::REDIS_ORPHANED_KEYS
+                                .with_label_values(&[family])
+                                .set(count.orphaned as i64);
+                        }
+                        metrics::REDIS_KEYS_SCANNED.set(tally.scanned as i64);
+                        tracing::debug!(
+                            scanned = tally.scanned,
+                            total,
+                            complete = tally.complete,
+                            elapsed_ms = started.elapsed().as_millis() as u64,
+                            "Keyspace swept"
+                        );
+                    }
+                    Err(e) => tracing::warn!(error = %e, "Keyspace sweep failed"),
+                }
+            }
+            _ = shutdown.recv() => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partial_sweep_is_scaled() {
+        let mut tally = Tally {
+            scanned: 1000,
+            complete: false,
+            ..Default::default()
+        };
+        tally.families.insert(
+            "challenge",
+            FamilyCount {
+                keys: 300,
+                orphaned: 2,
+            },
+        );
+
+        let estimate = tally.estimate(4000);
+        assert_eq!(
+            estimate["challenge"],
+            FamilyCount {
+                keys: 1200,
+                orphaned: 8,
+            }
+        );
+
+        // A full sweep is exact
+        tally.complete = true;
+        assert_eq!(tally.estimate(4000)["challenge"].keys, 300);
+    }
+}
@@ -0,0 +1,107 @@
+//! # Fortify - Cerberus L7+ Logic Engine
+//!
+//! Library half of the `fortify` binary: every subsystem lives here, and
+//! `main.rs` wires them together. Fuzz targets (`fuzz/`) link against it to
+//! reach the parsers that see untrusted input.
+
+use clap::Parser;
+
+pub mod abandonment;
+pub mod access_log;
+pub mod admin_access;
+pub mod allowlist;
+pub mod audit;
+pub mod backup;
+pub mod captcha;
+pub mod challenge_stats;
+pub mod circuits;
+pub mod cluster;
+pub mod config;
+pub mod degradation;
+pub mod drain;
+pub mod enforcement;
+pub mod experiments;
+pub mod flags;
+#[cfg(test)]
+pub mod fuzz_harness;
+pub mod gate_session;
+pub mod grpc;
+pub mod haproxy;
+pub mod journey;
+pub mod keyspace;
+pub mod listener;
+pub mod maintenance;
+pub mod metrics;
+pub mod node_info;
+pub mod pressure;
+pub mod redact;
+pub mod routes;
+pub mod rules;
+pub mod sampling;
+pub mod slo;
+pub mod state;
+pub mod supervisor;
+pub mod systemd;
+pub mod telemetry;
+pub mod tls;
+pub mod trusted_proxy;
+pub mod verification;
+pub mod verify_queue;
+pub mod webhook;
+
+/// Cerberus Fortify - L7+ Logic Engine
+#[derive(Parser, Debug)]
+#[command(name = "fortify")]
+#[command(author, version, about, long_about = None)]
+pub struct Args {
+    /// Configuration file path
+    #[arg(short, long, default_value = "config/fortify.toml")]
+    pub config: String,
+
+    /// Redis URL (overrides config)
+    #[arg(long, env = "REDIS_URL")]
+    pub redis_url: Option<String>,
+
+    /// Listen address (overrides config)
+    #[arg(short, long, env = "LISTEN_ADDR")]
+    pub listen: Option<String>,
+
+    /// Log level (trace, debug, info, warn, error)
+    #[arg(long, default_value = "info", env = "LOG_LEVEL")]
+    pub log_level: String,
+
+    /// Enable JSON logging output
+    #[arg(long, default_value = "false")]
+    pub json_logs: bool,
+
+    /// Check the configuration, print every problem, and exit
+    #[arg(long)]
+    pub validate_config: bool,
+
+    /// Report ready without waiting for the Ammo Box to fill
+    #[arg(long)]
+    pub skip_warmup: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Maintenance tasks run instead of the server
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+    /// Replay a backup file (see `[backup]`) into Redis, keeping what's
+    /// already there
+    Restore {
+        /// Backup file written by the backup task
+        file: String,
+    },
+    /// Load circuit records exported from `/admin/circuits/export` into
+    /// Redis
+    ImportCircuits {
+        /// JSON lines export file
+        file: String,
+        /// Replace records circuits already have
+        #[arg(long)]
+        overwrite: bool,
+    },
+}
@@ -0,0 +1,212 @@
+//! HTTP listener: TCP or unix domain socket, optionally TLS.
+//!
+//! `listen_addr = "unix:/var/run/fortify.sock"` serves over a unix socket
+//! only, so a proxy on the same host reaches Fortify without any TCP port
+//! being open. Anything else is parsed as an `IP:port`.
+
+use anyhow::{Context, Result, bail};
+use axum::Router;
+use axum::extract::connect_info::Connected;
+use axum::serve::IncomingStream;
+use rustls::ServerConfig;
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::tls::{ClientCert, TlsListener};
+use crate::trusted_proxy::Peer;
+
+/// Prefix marking a unix socket path in `listen_addr`
+const UNIX_PREFIX: &str = "unix:";
+
+/// Parsed `listen_addr`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(path) = s.strip_prefix(UNIX_PREFIX) {
+            if !path.starts_with('/') {
+                bail!("unix socket path must be absolute");
+            }
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+        s.parse()
+            .map(Self::Tcp)
+            .map_err(|_| anyhow::anyhow!("expected IP:port or unix:/path"))
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{}", addr),
+            Self::Unix(path) => write!(f, "{}{}", UNIX_PREFIX, path.display()),
+        }
+    }
+}
+
+/// A bound listener, ready to serve
+pub enum Listener {
+    Tcp(tokio::net::TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+impl Listener {
+    /// Bind `addr`; unix sockets get `socket_mode` permissions
+    ///
+    /// A stale socket file left by a previous run is replaced.
+    pub async fn bind(addr: &ListenAddr, socket_mode: u32) -> Result<Self> {
+        match addr {
+            ListenAddr::Tcp(addr) => Ok(Self::Tcp(
+                tokio::net::TcpListener::bind(addr)
+                    .await
+                    .with_context(|| format!("Failed to bind {}", addr))?,
+            )),
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => {
+                use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+                if let Ok(meta) = std::fs::symlink_metadata(path) {
+                    if !meta.file_type().is_socket() {
+                        bail!("{} exists and is not a socket", path.display());
+                    }
+                    std::fs::remove_file(path)
+                        .with_context(|| format!("Failed to remove stale {}", path.display()))?;
+                }
+
+                let listener = tokio::net::UnixListener::bind(path)
+                    .with_context(|| format!("Failed to bind {}", path.display()))?;
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(socket_mode))
+                    .with_context(|| format!("Failed to set mode on {}", path.display()))?;
+
+                Ok(Self::Unix(listener))
+            }
+            #[cfg(not(unix))]
+            ListenAddr::Unix(_) => {
+                let _ = socket_mode;
+                bail!("Unix socket listeners are not supported on this platform")
+            }
+        }
+    }
+
+    /// Address actually listened on (for logs)
+    pub fn local_addr(&self) -> String {
+        match self {
+            Self::Tcp(listener) => listener
+                .local_addr()
+                .map(|addr| addr.to_string())
+                .unwrap_or_else(|_| "tcp".to_string()),
+            #[cfg(unix)]
+            Self::Unix(listener) => listener
+                .local_addr()
+                .ok()
+                .and_then(|addr| addr.as_pathname().map(|p| p.display().to_string()))
+                .map(|path| format!("{}{}", UNIX_PREFIX, path))
+                .unwrap_or_else(|| "unix socket".to_string()),
+        }
+    }
+
+    /// Serve `app` until `shutdown` resolves, over TLS if configured
+    pub async fn serve<F>(
+        self,
+        app: Router,
+        tls: Option<Arc<ServerConfig>>,
+        shutdown: F,
+    ) -> Result<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        match self {
+            Self::Tcp(listener) => serve_on(listener, app, tls, shutdown).await,
+            #[cfg(unix)]
+            Self::Unix(listener) => {
+                let path = listener
+                    .local_addr()
+                    .ok()
+                    .and_then(|addr| addr.as_pathname().map(PathBuf::from));
+                let result = serve_on(listener, app, tls, shutdown).await;
+                if let Some(path) = path {
+                    let _ = std::fs::remove_file(path);
+                }
+                result
+            }
+        }
+    }
+}
+
+async fn serve_on<L, F>(
+    listener: L,
+    app: Router,
+    tls: Option<Arc<ServerConfig>>,
+    shutdown: F,
+) -> Result<()>
+where
+    L: axum::serve::Listener,
+    L::Addr: Clone + Sync + fmt::Debug + Into<Peer> + 'static,
+    Peer: for<'a> Connected<IncomingStream<'a, L>>,
+    F: Future<Output = ()> + Send + 'static,
+{
+    match tls {
+        Some(config) => axum::serve(
+            TlsListener::new(listener, config)?,
+            app.into_make_service_with_connect_info::<ClientCert>(),
+        )
+        .with_graceful_shutdown(shutdown)
+        .await
+        .context("Server error"),
+        None => axum::serve(listener, app.into_make_service_with_connect_info::<Peer>())
+            .with_graceful_shutdown(shutdown)
+            .await
+            .context("Server error"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_listen_addr() {
+        assert_eq!(
+            "127.0.0.1:8888".parse::<ListenAddr>().unwrap(),
+            ListenAddr::Tcp("127.0.0.1:8888".parse().unwrap())
+        );
+        let unix: ListenAddr = "unix:/run/fortify.sock".parse().unwrap();
+        assert_eq!(unix, ListenAddr::Unix(PathBuf::from("/run/fortify.sock")));
+        assert_eq!(unix.to_string(), "unix:/run/fortify.sock");
+
+        assert!("unix:fortify.sock".parse::<ListenAddr>().is_err());
+        assert!("/run/fortify.sock".parse::<ListenAddr>().is_err());
+        assert!("localhost".parse::<ListenAddr>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_socket_bind_replaces_stale_socket() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("fortify-test-{}.sock", std::process::id()));
+        let addr = ListenAddr::Unix(path.clone());
+
+        let first = Listener::bind(&addr, 0o660).await.unwrap();
+        drop(first); // leaves the socket file behind
+        let second = Listener::bind(&addr, 0o600).await.unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode, 0o600);
+        assert_eq!(second.local_addr(), format!("unix:{}", path.display()));
+
+        drop(second);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -10,128 +10,391 @@
 //!                   Redis (State)
 //! ```
 
-use anyhow::{Context, Result};
+use anyhow::Result;
 use clap::Parser;
 use std::sync::Arc;
+use std::time::Duration;
 use tracing::info;
-use tracing_subscriber::{EnvFilter, fmt, prelude::*};
-
-mod captcha;
-mod circuits;
-mod cluster;
-mod config;
-mod haproxy;
-mod routes;
-mod state;
-
-use captcha::{AmmoBox, AmmoBoxConfig, ammo_box_worker};
-use config::AppConfig;
-use state::AppState;
-
-/// Cerberus Fortify - L7+ Logic Engine
-#[derive(Parser, Debug)]
-#[command(name = "fortify")]
-#[command(author, version, about, long_about = None)]
-struct Args {
-    /// Configuration file path
-    #[arg(short, long, default_value = "config/fortify.toml")]
-    config: String,
-
-    /// Redis URL (overrides config)
-    #[arg(long, env = "REDIS_URL")]
-    redis_url: Option<String>,
-
-    /// Listen address (overrides config)
-    #[arg(short, long, env = "LISTEN_ADDR")]
-    listen: Option<String>,
-
-    /// Log level (trace, debug, info, warn, error)
-    #[arg(long, default_value = "info", env = "LOG_LEVEL")]
-    log_level: String,
-
-    /// Enable JSON logging output
-    #[arg(long, default_value = "false")]
-    json_logs: bool,
-}
+
+use cerberus_common::{EventBus, TtlSecs};
+use fortify::captcha::{AmmoBox, AmmoBoxConfig, ammo_box_worker};
+use fortify::config::AppConfig;
+use fortify::listener::{ListenAddr, Listener};
+use fortify::state::AppState;
+use fortify::supervisor::{self, Supervisor};
+use fortify::{
+    Args, Command, abandonment, access_log, audit, backup, circuits, cluster, config, degradation,
+    flags, grpc, haproxy, keyspace, node_info, pressure, routes, rules, slo, systemd, telemetry,
+    tls, webhook,
+};
+
+/// How long shutdown waits for background tasks to finish
+const TASK_STOP_TIMEOUT: Duration = Duration::from_secs(10);
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    node_info::mark_started();
+
     // Parse CLI arguments
     let args = Args::parse();
 
-    // Initialize logging
-    init_logging(&args.log_level, args.json_logs)?;
+    if args.validate_config {
+        let (_, issues) = AppConfig::check(&args.config, &args)?;
+        if issues.is_empty() {
+            println!("{}: OK", args.config);
+            return Ok(());
+        }
+        eprintln!("{}", config::report(&args.config, &issues));
+        eprintln!("{} problem(s) found", issues.len());
+        std::process::exit(1);
+    }
+
+    // Load configuration
+    let config = AppConfig::load(&args.config, &args)?;
+
+    match &args.command {
+        Some(Command::Restore { file }) => return backup::restore_file(&config, file).await,
+        Some(Command::ImportCircuits { file, overwrite }) => {
+            return circuits::transfer::import_file(&config, file, *overwrite).await;
+        }
+        None => {}
+    }
+
+    // Initialize logging (and trace export); flushed when the guard drops
+    let _telemetry = telemetry::init(
+        &args.log_level,
+        args.json_logs,
+        &config.telemetry,
+        &config.privacy,
+    )?;
+    supervisor::install_panic_hook();
 
+    let build = node_info::BuildInfo::current();
     info!(
+        version = build.version,
+        git_hash = build.git_hash,
+        rustc = build.rustc,
+        features = ?build.features,
+        node_id = %config.node_id,
+        cluster = config.cluster_enabled,
+        subsystems = ?node_info::subsystems(&config),
         "🔥 Starting Cerberus Fortify v{}",
-        env!("CARGO_PKG_VERSION")
+        build.version
     );
-
-    // Load configuration
-    let config = AppConfig::load(&args.config, &args)?;
-    info!("📋 Configuration loaded from {}", args.config);
+    if std::path::Path::new(&args.config).exists() {
+        info!("📋 Configuration loaded from {}", args.config);
+    } else {
+        tracing::warn!("Config file not found, using defaults");
+    }
+    if config.enforcement.observe {
+        tracing::warn!("👀 Observe-only mode: requests are let through, decisions only recorded");
+    }
 
     // Create shutdown broadcast channel
     let (shutdown_tx, _) = tokio::sync::broadcast::channel::<()>(1);
 
+    // Node events, recorded in the audit log
+    let events = Arc::new(EventBus::new());
+    events.subscribe(Arc::new(audit::AuditLog));
+    if config.webhooks.enabled {
+        events.subscribe(Arc::new(webhook::Webhooks::new(
+            config.webhooks.clone(),
+            config.node_id.clone(),
+        )?));
+    }
+
+    // Background workers are restarted if they panic
+    let supervisor = Arc::new(Supervisor::new(events.clone(), shutdown_tx.clone()));
+
+    // CAPTCHA parameters per difficulty, before anything is generated
+    cerberus_common::difficulty::install(config.captcha.difficulty.clone());
+
     // Initialize Ammo Box (pre-generated CAPTCHA pool)
     let ammo_config = AmmoBoxConfig {
         ram_capacity: 10_000,
+        starvation: config.ammo_starvation.clone(),
         ..Default::default()
     };
     let ammo_box = Arc::new(AmmoBox::new(ammo_config));
 
     // Spawn Ammo Box background worker
     let ammo_clone = ammo_box.clone();
-    let ammo_shutdown = shutdown_tx.subscribe();
-    tokio::spawn(async move {
-        ammo_box_worker(ammo_clone, ammo_shutdown).await;
+    let ammo_events = events.clone();
+    supervisor.spawn("ammo_box", move |shutdown| {
+        ammo_box_worker(ammo_clone.clone(), ammo_events.clone(), shutdown)
     });
 
     // Initialize application state
-    let state = AppState::new(config.clone(), ammo_box).await?;
-    info!("✅ Redis connected: {}", config.redis_url);
+    let state = AppState::new(config.clone(), ammo_box.clone(), events, supervisor.clone()).await?;
+    if state.redis_conn().is_some() {
+        let redis_url = node_info::strip_userinfo(&config.redis_url);
+        info!(
+            "✅ Redis connected: {}",
+            redis_url.as_deref().unwrap_or(&config.redis_url)
+        );
+    }
+
+    if let Some(haproxy) = state.haproxy.clone() {
+        // Bans, unbans and VIP changes take effect at HAProxy too
+        state
+            .events
+            .subscribe(Arc::new(haproxy::StickTableSync::new(haproxy.clone())));
+
+        // Take unhealthy peers out of HAProxy rotation until they recover
+        if config.cluster_enabled {
+            state.events.subscribe(Arc::new(haproxy::PeerDrain::new(
+                haproxy.clone(),
+                &config.haproxy,
+            )));
+        }
+
+        // Poll HAProxy statistics into the metrics
+        let stats_config = config.haproxy.clone();
+        let stats_events = state.events.clone();
+        supervisor.spawn("haproxy_stats", move |shutdown| {
+            haproxy::haproxy_stats_worker(
+                haproxy.clone(),
+                stats_config.clone(),
+                stats_events.clone(),
+                shutdown,
+            )
+        });
+
+        // Tag circuits with how hard HAProxy should throttle them
+        if config.haproxy.pressure.enabled {
+            let pressure_state = state.clone();
+            supervisor.spawn("haproxy_pressure", move |shutdown| {
+                pressure::pressure_worker(pressure_state.clone(), shutdown)
+            });
+        }
+    }
+
+    // Spawn Redis guard (memory pressure, offline detection, reattach)
+    let guard_state = state.clone();
+    supervisor.spawn("redis_guard", move |shutdown| {
+        degradation::redis_guard_worker(guard_state.clone(), shutdown)
+    });
+
+    // Access log file and stream
+    let access_log_state = state.clone();
+    supervisor.spawn("access_log", move |shutdown| {
+        access_log::access_log_worker(access_log_state.clone(), shutdown)
+    });
+
+    // Error budget burn rates (metrics and alerts)
+    let slo_state = state.clone();
+    supervisor.spawn("slo", move |shutdown| {
+        slo::slo_worker(slo_state.clone(), shutdown)
+    });
+
+    // Redis key counts by family
+    let keyspace_state = state.clone();
+    supervisor.spawn("keyspace", move |shutdown| {
+        keyspace::keyspace_worker(keyspace_state.clone(), shutdown)
+    });
+
+    // Feature flag overrides set from any node
+    let flags_state = state.clone();
+    supervisor.spawn("flags", move |shutdown| {
+        flags::flags_worker(flags_state.clone(), shutdown)
+    });
+
+    // Attack signature rules (auto-mitigation)
+    let rules_state = state.clone();
+    supervisor.spawn("rules", move |shutdown| {
+        rules::rules_worker(rules_state.clone(), shutdown)
+    });
+
+    // Signed snapshots of bans, VIPs, threat level and allowlist
+    let backup_state = state.clone();
+    supervisor.spawn("backup", move |shutdown| {
+        backup::backup_worker(backup_state.clone(), shutdown)
+    });
+
+    // Challenges left unanswered (and de-escalation, if configured)
+    let abandonment_state = state.clone();
+    supervisor.spawn("abandonment", move |shutdown| {
+        abandonment::abandonment_worker(abandonment_state.clone(), shutdown)
+    });
+
+    // gRPC control plane (with the `grpc` feature)
+    let grpc_state = state.clone();
+    supervisor.spawn("grpc", move |shutdown| {
+        grpc::grpc_worker(grpc_state.clone(), shutdown)
+    });
+
+    // Leader election (cluster-wide escalation and bans)
+    let election_state = state.clone();
+    supervisor.spawn("election", move |shutdown| {
+        cluster::election_worker(election_state.clone(), shutdown)
+    });
+
+    // Threat level changes made by other nodes or scripts
+    let threat_sync_state = state.clone();
+    supervisor.spawn("threat_sync", move |shutdown| {
+        cluster::threat_sync_worker(threat_sync_state.clone(), shutdown)
+    });
+
+    // Passport trust registry (signed federation)
+    let federation_state = state.clone();
+    supervisor.spawn("federation", move |shutdown| {
+        cluster::federation_worker(federation_state.clone(), shutdown)
+    });
+
+    // CAPTCHA batches from full peers when our pool runs low
+    let sharing_state = state.clone();
+    supervisor.spawn("ammo_sharing", move |shutdown| {
+        cluster::ammo_sharing_worker(sharing_state.clone(), shutdown)
+    });
+
+    // Cluster gossip receiver (peer health, passport revocations, request
+    // counts)
+    if let Some(gossip) = state.gossip.clone() {
+        let receiver = gossip.clone();
+        supervisor.spawn("gossip_receiver", move |shutdown| {
+            let receiver = receiver.clone();
+            async move {
+                if let Err(e) = receiver.run_receiver(shutdown).await {
+                    tracing::error!(error = %e, "Gossip receiver failed");
+                }
+            }
+        });
+
+        // ...and our own health, so peers know where to shed load
+        let broadcast_state = state.clone();
+        supervisor.spawn("gossip_broadcaster", move |shutdown| {
+            let gossip = gossip.clone();
+            let get_state = cluster::health_snapshot(broadcast_state.clone());
+            async move {
+                if let Err(e) = gossip.run_broadcaster(get_state, shutdown).await {
+                    tracing::error!(error = %e, "Gossip broadcaster failed");
+                }
+            }
+        });
+
+        // ...and per-circuit request counts, for the cluster rate limit
+        if state.rate_share.is_some() {
+            let rate_share_state = state.clone();
+            supervisor.spawn("rate_share", move |shutdown| {
+                cluster::rate_share_worker(rate_share_state.clone(), shutdown)
+            });
+        }
+    }
 
     // Build router
-    let app = routes::create_router(state);
+    let redis_status = if state.redis_conn().is_some() {
+        "Redis connected"
+    } else {
+        "Redis offline"
+    };
+    let drain = state.drain.clone();
+    let app = routes::create_router(state)?;
 
-    // Start server
-    let listener = tokio::net::TcpListener::bind(&config.listen_addr).await?;
-    info!("🚀 Fortify listening on {}", config.listen_addr);
+    // Fill the Ammo Box; /ready fails until it is warm
+    if args.skip_warmup {
+        ammo_box.mark_warm();
+    } else {
+        tokio::spawn(warm_up_ammo(ammo_box.clone(), config.warmup.clone()));
+    }
 
-    // Handle graceful shutdown
+    // Start server (on the socket systemd passed us, if any)
+    let listener = match systemd::inherited_listener()? {
+        Some(listener) => {
+            info!(
+                "🚀 Fortify listening on {} (systemd socket)",
+                listener.local_addr()
+            );
+            listener
+        }
+        None => {
+            let addr: ListenAddr = config.listen_addr.parse()?;
+            let listener = Listener::bind(&addr, config.listen_socket_mode).await?;
+            info!("🚀 Fortify listening on {}", addr);
+            listener
+        }
+    };
+
+    systemd::notify_ready(&format!(
+        "Serving; {}, {} CAPTCHAs pooled",
+        redis_status,
+        ammo_box.len()
+    ));
+    supervisor.spawn("watchdog", systemd::watchdog_worker);
+
+    // Handle graceful shutdown (on a signal, or once an `exit` drain completes)
     let shutdown_signal = async move {
-        tokio::signal::ctrl_c()
-            .await
-            .expect("Failed to install Ctrl+C handler");
-        info!("🛑 Shutdown signal received");
+        tokio::select! {
+            _ = shutdown_requested() => info!("🛑 Shutdown signal received"),
+            _ = drain.exited() => info!("🛑 Drained; shutting down"),
+        }
+        systemd::notify_stopping();
         let _ = shutdown_tx.send(());
     };
 
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal)
-        .await
-        .context("Server error")?;
+    let tls = if config.tls.enabled {
+        info!(
+            client_auth = config.tls.client_auth(),
+            "🔐 TLS enabled on the listener"
+        );
+        Some(tls::server_config(&config.tls)?)
+    } else {
+        None
+    };
+
+    listener.serve(app, tls, shutdown_signal).await?;
+
+    // Let background tasks finish (the access log flushes its last entries)
+    let running = supervisor.wait(TASK_STOP_TIMEOUT).await;
+    if !running.is_empty() {
+        tracing::warn!(tasks = ?running, "Background tasks still running at exit");
+    }
 
     info!("👋 Fortify shutdown complete");
     Ok(())
 }
 
-/// Initialize structured logging with tracing
-fn init_logging(level: &str, json: bool) -> Result<()> {
-    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level));
-
-    if json {
-        tracing_subscriber::registry()
-            .with(filter)
-            .with(fmt::layer().json())
-            .init();
-    } else {
-        tracing_subscriber::registry()
-            .with(filter)
-            .with(fmt::layer().with_target(true).with_thread_ids(true))
-            .init();
+/// Warm the Ammo Box up, marking it warm anyway after `timeout_secs`
+async fn warm_up_ammo(ammo_box: Arc<AmmoBox>, warmup: config::WarmupConfig) {
+    info!(
+        min_fill_pct = warmup.min_fill_pct,
+        "🎯 Warming up the Ammo Box"
+    );
+    let warm_up = ammo_box.warm_up(warmup.min_fill_pct);
+    let result = match warmup.timeout_secs {
+        TtlSecs::ZERO => Ok(warm_up.await),
+        timeout => tokio::time::timeout(timeout.duration(), warm_up).await,
+    };
+    match result {
+        Ok(Ok(())) => info!(pooled = ammo_box.len(), "🎯 Ammo Box warm"),
+        Ok(Err(e)) => tracing::warn!(error = %e, "Ammo Box warm-up failed, reporting ready"),
+        Err(_) => tracing::warn!(
+            pooled = ammo_box.len(),
+            "Ammo Box still warming up, reporting ready anyway"
+        ),
     }
+    ammo_box.mark_warm();
+}
 
-    Ok(())
+/// Ctrl+C, or SIGTERM (what systemd sends on stop)
+async fn shutdown_requested() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
 }
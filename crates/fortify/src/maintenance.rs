@@ -0,0 +1,117 @@
+//! Maintenance mode.
+//!
+//! Switched on and off at runtime through `/admin/maintenance`. While on,
+//! every visitor-facing route answers 503 with `Retry-After` and a static
+//! maintenance page, so the protected service can be taken down cleanly in
+//! the middle of an attack; admin, health and metrics routes keep working.
+//! HAProxy is told through a flag in its runtime map (`haproxy.flags_map`)
+//! and can turn visitors away before they reach Nginx.
+//!
+//! The switch is per node and doesn't survive a restart.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+
+/// Maintenance page settings (`[maintenance]` in fortify.toml)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MaintenanceConfig {
+    /// `Retry-After` sent with the page, unless the toggle sets another
+    pub retry_after_secs: u64,
+    /// Shown on the built-in page
+    pub message: String,
+    /// Serve this HTML file instead of the built-in page
+    pub page_path: Option<String>,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            retry_after_secs: 300,
+            message: "The service is down for maintenance. Please come back later.".to_string(),
+            page_path: None,
+        }
+    }
+}
+
+/// The maintenance switch, read on every request
+pub struct Maintenance {
+    active: AtomicBool,
+    /// When it was switched on (Unix seconds)
+    since: AtomicI64,
+    retry_after_secs: AtomicU64,
+    /// Operator-supplied page (`page_path`)
+    custom_page: Option<String>,
+}
+
+impl Maintenance {
+    /// Load the custom page, if one is configured
+    pub fn new(config: &MaintenanceConfig) -> Result<Self> {
+        let custom_page = config
+            .page_path
+            .as_ref()
+            .map(|path| {
+                std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read maintenance page {}", path))
+            })
+            .transpose()?;
+        Ok(Self {
+            active: AtomicBool::new(false),
+            since: AtomicI64::new(0),
+            retry_after_secs: AtomicU64::new(config.retry_after_secs),
+            custom_page,
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// When maintenance started, if it is on
+    pub fn since(&self) -> Option<i64> {
+        self.is_active().then(|| self.since.load(Ordering::Relaxed))
+    }
+
+    pub fn retry_after_secs(&self) -> u64 {
+        self.retry_after_secs.load(Ordering::Relaxed)
+    }
+
+    pub fn custom_page(&self) -> Option<&str> {
+        self.custom_page.as_deref()
+    }
+
+    /// Switch maintenance on or off; returns whether it changed
+    pub fn set(&self, active: bool, retry_after_secs: Option<u64>) -> bool {
+        if let Some(secs) = retry_after_secs {
+            self.retry_after_secs.store(secs, Ordering::Relaxed);
+        }
+        let changed = self.active.swap(active, Ordering::Relaxed) != active;
+        if changed && active {
+            self.since
+                .store(chrono::Utc::now().timestamp(), Ordering::Relaxed);
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_toggle() {
+        let maintenance = Maintenance::new(&MaintenanceConfig::default()).unwrap();
+        assert!(!maintenance.is_active());
+        assert_eq!(maintenance.since(), None);
+
+        assert!(maintenance.set(true, Some(60)));
+        assert!(!maintenance.set(true, None));
+        assert!(maintenance.since().is_some());
+        assert_eq!(maintenance.retry_after_secs(), 60);
+
+        assert!(maintenance.set(false, None));
+        assert_eq!(maintenance.since(), None);
+        assert!(maintenance.custom_page().is_none());
+    }
+}
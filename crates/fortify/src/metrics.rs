@@ -0,0 +1,717 @@
+//! Prometheus metrics.
+//!
+//! Per-route request latency, status and in-flight counts, latency
+//! histograms for the CAPTCHA hot paths and the Ammo Box, and
+//! HAProxy figures polled from its runtime API, served in the Prometheus
+//! text format at `/metrics/prometheus`. Metrics live in a
+//! process-wide registry so instrumented code needs no extra state.
+
+use cerberus_common::CacheStats;
+use prometheus::core::Metric;
+use prometheus::{
+    Encoder, Gauge, GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec,
+    IntGauge, IntGaugeVec, Opts, Registry, TextEncoder, exponential_buckets,
+};
+use std::sync::LazyLock;
+
+static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+/// Time to generate and store a challenge
+pub static CAPTCHA_GENERATE_SECONDS: LazyLock<Histogram> = LazyLock::new(|| {
+    histogram(
+        "fortify_captcha_generate_seconds",
+        "Time to generate and store a CAPTCHA challenge",
+        buckets(0.0005, 2.0, 14),
+    )
+});
+
+/// Time to verify an answer, by outcome
+pub static CAPTCHA_VERIFY_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    let opts = HistogramOpts::new(
+        "fortify_captcha_verify_seconds",
+        "Time to verify a CAPTCHA answer and record the outcome",
+    )
+    .buckets(buckets(0.0005, 2.0, 14));
+    register(HistogramVec::new(opts, &["outcome"]).expect("valid histogram"))
+});
+
+/// Request handling time, by route template and method
+pub static HTTP_REQUEST_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    let opts = HistogramOpts::new(
+        "fortify_http_request_duration_seconds",
+        "Time to handle an HTTP request, by route template and method",
+    )
+    .buckets(buckets(0.0005, 2.0, 16));
+    register(HistogramVec::new(opts, &["route", "method"]).expect("valid histogram"))
+});
+
+/// Responses sent, by route template, method and status class
+pub static HTTP_RESPONSES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_http_responses_total",
+        "HTTP responses sent, by route template, method and status class",
+    );
+    register(IntCounterVec::new(opts, &["route", "method", "status_class"]).expect("valid counter"))
+});
+
+/// Requests being handled, by route template
+pub static HTTP_REQUESTS_IN_FLIGHT: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_http_requests_in_flight",
+        "HTTP requests being handled, by route template",
+    );
+    register(IntGaugeVec::new(opts, &["route"]).expect("valid gauge"))
+});
+
+/// Challenge writes sent to Redis per batched pipeline
+pub static CHALLENGE_WRITE_BATCH: LazyLock<Histogram> = LazyLock::new(|| {
+    histogram(
+        "fortify_challenge_write_batch_size",
+        "Challenge writes sent to Redis in one batched pipeline",
+        buckets(1.0, 2.0, 10),
+    )
+});
+
+/// Challenges refused because the write queue was full
+pub static CHALLENGE_WRITES_REFUSED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(
+        IntCounter::new(
+            "fortify_challenge_writes_refused_total",
+            "Challenges refused because the batched write queue was full",
+        )
+        .expect("valid counter"),
+    )
+});
+
+/// Access log entries dropped because the write queue was full
+pub static ACCESS_LOG_DROPPED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(
+        IntCounter::new(
+            "fortify_access_log_dropped_total",
+            "Access log entries dropped because the write queue was full",
+        )
+        .expect("valid counter"),
+    )
+});
+
+/// Time from challenge issuance to a correct answer
+pub static CAPTCHA_SOLVE_SECONDS: LazyLock<Histogram> = LazyLock::new(|| {
+    histogram(
+        "fortify_captcha_solve_seconds",
+        "Time between challenge issuance and a correct answer",
+        vec![
+            1.0, 2.0, 3.0, 5.0, 8.0, 12.0, 20.0, 30.0, 45.0, 60.0, 120.0, 300.0,
+        ],
+    )
+});
+
+/// Ammo Box pop latency
+pub static AMMO_POP_SECONDS: LazyLock<Histogram> = LazyLock::new(|| {
+    histogram(
+        "fortify_ammo_pop_seconds",
+        "Time to pop a pre-generated CAPTCHA from the pool",
+        buckets(0.000_001, 4.0, 10),
+    )
+});
+
+/// Ammo Box disk load latency
+pub static AMMO_LOAD_SECONDS: LazyLock<Histogram> = LazyLock::new(|| {
+    histogram(
+        "fortify_ammo_load_seconds",
+        "Time to load CAPTCHA batches from the disk cache",
+        buckets(0.001, 2.0, 14),
+    )
+});
+
+/// Ammo Box disk dump latency
+pub static AMMO_DUMP_SECONDS: LazyLock<Histogram> = LazyLock::new(|| {
+    histogram(
+        "fortify_ammo_dump_seconds",
+        "Time to dump a CAPTCHA batch to the disk cache",
+        buckets(0.001, 2.0, 14),
+    )
+});
+
+/// How long a served CAPTCHA sat in the pool
+pub static AMMO_POOL_WAIT_SECONDS: LazyLock<Histogram> = LazyLock::new(|| {
+    histogram(
+        "fortify_ammo_pool_wait_seconds",
+        "Time a served CAPTCHA spent waiting in the pool",
+        buckets(1.0, 2.0, 16),
+    )
+});
+
+/// HAProxy sessions open right now
+pub static HAPROXY_CURRENT_SESSIONS: LazyLock<IntGauge> = LazyLock::new(|| {
+    gauge(
+        "fortify_haproxy_current_sessions",
+        "Sessions currently open on HAProxy frontends",
+    )
+});
+
+/// HAProxy sessions since it started (a counter kept by HAProxy)
+pub static HAPROXY_SESSIONS: LazyLock<IntGauge> = LazyLock::new(|| {
+    gauge(
+        "fortify_haproxy_sessions",
+        "Sessions handled by HAProxy frontends since HAProxy started",
+    )
+});
+
+/// HAProxy requests denied since it started
+pub static HAPROXY_DENIED_REQUESTS: LazyLock<IntGauge> = LazyLock::new(|| {
+    gauge(
+        "fortify_haproxy_denied_requests",
+        "Requests denied by HAProxy frontends since HAProxy started",
+    )
+});
+
+/// Circuits in the HAProxy stick table
+pub static HAPROXY_TABLE_ENTRIES: LazyLock<IntGauge> = LazyLock::new(|| {
+    gauge(
+        "fortify_haproxy_table_entries",
+        "Entries in the HAProxy circuit stick table",
+    )
+});
+
+/// Capacity of the HAProxy stick table
+pub static HAPROXY_TABLE_SIZE: LazyLock<IntGauge> = LazyLock::new(|| {
+    gauge(
+        "fortify_haproxy_table_size",
+        "Capacity of the HAProxy circuit stick table",
+    )
+});
+
+/// Circuits in the HAProxy stick table at each challenge pressure (0-3)
+pub static HAPROXY_CIRCUIT_PRESSURE: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_haproxy_circuit_pressure",
+        "Circuits in the HAProxy stick table by challenge pressure",
+    );
+    register(IntGaugeVec::new(opts, &["pressure"]).expect("valid gauge"))
+});
+
+/// Decisions observe-only mode recorded instead of enforcing, by action
+pub static OBSERVED_DECISIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_observed_decisions_total",
+        "Requests observe-only mode let through that enforcement would have stopped",
+    );
+    register(IntCounterVec::new(opts, &["action"]).expect("valid counter"))
+});
+
+/// Health transitions per gossip peer within the flap window
+pub static GOSSIP_PEER_FLAPS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_gossip_peer_flaps",
+        "Healthy/unhealthy transitions of a gossip peer within the flap window",
+    );
+    register(IntGaugeVec::new(opts, &["node_id"]).expect("valid gauge"))
+});
+
+/// Gossip peers currently flagged as flapping
+pub static GOSSIP_FLAPPING_PEERS: LazyLock<IntGauge> = LazyLock::new(|| {
+    gauge(
+        "fortify_gossip_flapping_peers",
+        "Gossip peers flagged as flapping (excluded from load shedding)",
+    )
+});
+
+/// Gossip datagrams dropped, by reason
+pub static GOSSIP_REJECTED_PACKETS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_gossip_rejected_packets_total",
+        "Gossip datagrams dropped (rate limited, malformed, unknown node, clock skew, replayed, peer table full)",
+    );
+    register(IntCounterVec::new(opts, &["reason"]).expect("valid counter"))
+});
+
+/// CAPTCHAs shared with peers (`sent`) and taken from them (`received`)
+pub static AMMO_SHARED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_ammo_shared_total",
+        "Pre-generated CAPTCHAs sent to or received from cluster peers",
+    );
+    register(IntCounterVec::new(opts, &["direction"]).expect("valid counter"))
+});
+
+/// Passports validated against an issuer's retired key during a key rotation
+pub static PASSPORT_RETIRED_KEY_VALIDATIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_passport_retired_key_validations_total",
+        "Signed passports that only validated against the issuer's retired key",
+    );
+    register(IntCounterVec::new(opts, &["issuer"]).expect("valid counter"))
+});
+
+/// `/validate` requests let through by an allowlist secret, by entry
+pub static ALLOWLIST_HITS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_allowlist_hits_total",
+        "Requests let through without a passport by a first-party allowlist secret",
+    );
+    register(IntCounterVec::new(opts, &["name"]).expect("valid counter"))
+});
+
+/// Internal headers removed from requests that didn't come through a
+/// trusted proxy, by header
+pub static UNTRUSTED_HEADERS_STRIPPED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_untrusted_headers_stripped_total",
+        "Proxy-only headers stripped from requests sent by untrusted peers",
+    );
+    register(IntCounterVec::new(opts, &["header"]).expect("valid counter"))
+});
+
+/// Gate proof-of-work answers, by outcome (solved, unsolved, expired,
+/// invalid, replayed)
+pub static POW_SOLUTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_pow_solutions_total",
+        "Proof-of-work answers posted from the gate page",
+    );
+    register(IntCounterVec::new(opts, &["outcome"]).expect("valid counter"))
+});
+
+/// Gate requests held and turned away by answer throttling, by reason
+/// (`attempts` over the per-minute budget, `locked` out of the gate)
+pub static VERIFY_THROTTLED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_verify_throttled_total",
+        "Gate requests tarpitted by answer throttling",
+    );
+    register(IntCounterVec::new(opts, &["reason"]).expect("valid counter"))
+});
+
+/// Gate forms posted again after they were processed (back and resubmit)
+pub static GATE_FORM_RESUBMISSIONS: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(
+        IntCounter::new(
+            "fortify_gate_form_resubmissions_total",
+            "Gate forms submitted again after they were processed",
+        )
+        .expect("valid counter"),
+    )
+});
+
+/// Backup passes, by outcome (written, unchanged, failed)
+pub static BACKUPS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_backups_total",
+        "Backups of critical Redis state to disk",
+    );
+    register(IntCounterVec::new(opts, &["outcome"]).expect("valid counter"))
+});
+
+/// Shadow trial puzzles shown, by candidate provider
+pub static SHADOW_ISSUED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_shadow_issued_total",
+        "Shadow trial puzzles shown next to real challenges",
+    );
+    register(IntCounterVec::new(opts, &["provider"]).expect("valid counter"))
+});
+
+/// Shadow trial answers, by candidate provider, outcome (solved, failed,
+/// skipped) and how the real challenge went (solved, failed)
+pub static SHADOW_RESULTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_shadow_results_total",
+        "Shadow trial answers, recorded but never enforced",
+    );
+    register(IntCounterVec::new(opts, &["provider", "outcome", "real"]).expect("valid counter"))
+});
+
+/// Time from showing to solving a shadow puzzle, by candidate provider
+pub static SHADOW_SOLVE_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    let opts = HistogramOpts::new(
+        "fortify_shadow_solve_seconds",
+        "Time visitors took to solve a shadow trial puzzle",
+    )
+    .buckets(buckets(1.0, 1.5, 12));
+    register(HistogramVec::new(opts, &["provider"]).expect("valid histogram"))
+});
+
+/// Gate-to-first-request journeys, by stage (solve, issue, first_request,
+/// total) and the threat level the passport was issued at
+pub static JOURNEY_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    let opts = HistogramOpts::new(
+        "fortify_journey_seconds",
+        "Time visitors spent getting from the gate page to their first validated request",
+    )
+    .buckets(buckets(0.01, 2.0, 16));
+    register(HistogramVec::new(opts, &["stage", "threat_level"]).expect("valid histogram"))
+});
+
+/// Challenges issued to and answered by circuits in an experiment, by
+/// experiment, variant and outcome (issued, passed, failed, expired)
+pub static EXPERIMENT_CHALLENGES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_experiment_challenges_total",
+        "Challenges issued and answered per experiment variant",
+    );
+    register(
+        IntCounterVec::new(opts, &["experiment", "variant", "outcome"]).expect("valid counter"),
+    )
+});
+
+/// Challenges issued and answered, by the difficulty they were issued at
+/// and outcome (issued, passed, failed, expired)
+pub static CHALLENGE_OUTCOMES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_challenge_outcomes_total",
+        "Challenges issued and answered per difficulty",
+    );
+    register(IntCounterVec::new(opts, &["difficulty", "outcome"]).expect("valid counter"))
+});
+
+/// Challenges that expired unanswered (counted by the node sweeping them)
+pub static CHALLENGES_ABANDONED: LazyLock<IntCounter> = LazyLock::new(|| {
+    register(
+        IntCounter::new(
+            "fortify_challenges_abandoned_total",
+            "Challenges that expired without an answer",
+        )
+        .expect("valid counter"),
+    )
+});
+
+/// Share of challenges issued over the abandonment window left unanswered
+pub static CHALLENGE_ABANDONMENT_RATIO: LazyLock<Gauge> = LazyLock::new(|| {
+    register(
+        Gauge::new(
+            "fortify_challenge_abandonment_ratio",
+            "Share of recently issued challenges that expired unanswered, cluster-wide",
+        )
+        .expect("valid gauge"),
+    )
+});
+
+/// Answers waiting for a verification slot
+pub static VERIFY_QUEUE_DEPTH: LazyLock<IntGauge> = LazyLock::new(|| {
+    gauge(
+        "fortify_verify_queue_depth",
+        "Answers waiting in the verify queue",
+    )
+});
+
+/// Time answers waited for a verification slot
+pub static VERIFY_QUEUE_WAIT_SECONDS: LazyLock<Histogram> = LazyLock::new(|| {
+    histogram(
+        "fortify_verify_queue_wait_seconds",
+        "Time an answer waited in the verify queue",
+        buckets(0.001, 2.0, 12),
+    )
+});
+
+/// Answers shed by the verify queue, by reason (`over_rate`, `queue_full`,
+/// `timeout`)
+pub static VERIFY_QUEUE_SHED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_verify_queue_shed_total",
+        "Answers turned away by the verify queue",
+    );
+    register(IntCounterVec::new(opts, &["reason"]).expect("valid counter"))
+});
+
+/// Entries held by bounded in-process caches, by cache
+pub static CACHE_ENTRIES: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_cache_entries",
+        "Entries held by a bounded in-process cache",
+    );
+    register(IntGaugeVec::new(opts, &["cache"]).expect("valid gauge"))
+});
+
+/// Estimated bytes held by bounded in-process caches with a byte cap
+pub static CACHE_BYTES: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_cache_bytes",
+        "Estimated bytes held by a bounded in-process cache",
+    );
+    register(IntGaugeVec::new(opts, &["cache"]).expect("valid gauge"))
+});
+
+/// Entries evicted from bounded in-process caches, by cache
+pub static CACHE_EVICTIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_cache_evictions_total",
+        "Entries evicted from a bounded in-process cache to stay within its caps",
+    );
+    register(IntCounterVec::new(opts, &["cache"]).expect("valid counter"))
+});
+
+/// Redis keys per family, from the last keyspace sweep (estimated when the
+/// sweep stopped early)
+pub static REDIS_KEYS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_redis_keys",
+        "Redis keys per family (challenge, passport, circuit, ...), from the last keyspace sweep",
+    );
+    register(IntGaugeVec::new(opts, &["family"]).expect("valid gauge"))
+});
+
+/// Redis keys without a TTL per family (they should all expire)
+pub static REDIS_ORPHANED_KEYS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_redis_orphaned_keys",
+        "Redis keys without a TTL in a family whose keys should all expire",
+    );
+    register(IntGaugeVec::new(opts, &["family"]).expect("valid gauge"))
+});
+
+/// Keys looked at by the last keyspace sweep
+pub static REDIS_KEYS_SCANNED: LazyLock<IntGauge> = LazyLock::new(|| {
+    gauge(
+        "fortify_redis_keys_scanned",
+        "Redis keys looked at by the last keyspace sweep",
+    )
+});
+
+/// Whether Redis answered the guard's last sample
+pub static REDIS_UP: LazyLock<IntGauge> = LazyLock::new(|| {
+    gauge(
+        "fortify_redis_up",
+        "Whether Redis answered the last health sample (1) or not (0)",
+    )
+});
+
+/// Round-trip of the guard's last Redis PING
+pub static REDIS_PING_SECONDS: LazyLock<Gauge> = LazyLock::new(|| {
+    register(
+        Gauge::new(
+            "fortify_redis_ping_seconds",
+            "Round-trip time of the last Redis PING",
+        )
+        .expect("valid gauge"),
+    )
+});
+
+/// Free space on the filesystem holding the Ammo Box disk cache
+pub static AMMO_DISK_FREE_BYTES: LazyLock<IntGauge> = LazyLock::new(|| {
+    gauge(
+        "fortify_ammo_disk_free_bytes",
+        "Free space on the filesystem holding the Ammo Box disk cache",
+    )
+});
+
+/// Panics, by supervised task (`unsupervised` for any other)
+pub static PANICS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    let opts = Opts::new("fortify_panics_total", "Panics, by supervised task");
+    register(IntCounterVec::new(opts, &["task"]).expect("valid counter"))
+});
+
+/// Restarts of supervised tasks after a panic, by task
+pub static TASK_RESTARTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_task_restarts_total",
+        "Restarts of a supervised background task after it panicked",
+    );
+    register(IntCounterVec::new(opts, &["task"]).expect("valid counter"))
+});
+
+/// `/validate` error budget burn rate, by objective and window
+pub static SLO_BURN_RATE: LazyLock<GaugeVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_slo_burn_rate",
+        "Rate the /validate error budget is spent at (1 = exactly over the SLO window)",
+    );
+    register(GaugeVec::new(opts, &["objective", "window_secs"]).expect("valid gauge"))
+});
+
+/// Share of the `/validate` error budget left, by objective
+pub static SLO_BUDGET_REMAINING: LazyLock<GaugeVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_slo_budget_remaining",
+        "Share of the /validate error budget left over the SLO window",
+    );
+    register(GaugeVec::new(opts, &["objective"]).expect("valid gauge"))
+});
+
+/// Feature flags in force on this node (1 = enabled)
+pub static FEATURE_FLAGS: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    let opts = Opts::new(
+        "fortify_feature_flag_enabled",
+        "Feature flags in force on this node (1 = enabled)",
+    );
+    register(IntGaugeVec::new(opts, &["flag"]).expect("valid gauge"))
+});
+
+/// Quantiles of the observations a histogram got since the last look
+///
+/// Prometheus histograms only ever count up; this remembers the bucket
+/// counts from the previous call so each call covers what came since.
+#[derive(Debug, Default)]
+pub struct RecentQuantile {
+    counts: Vec<u64>,
+    total: u64,
+}
+
+impl RecentQuantile {
+    /// Upper bound (in seconds) of the bucket holding quantile `q` of the
+    /// observations since the last call, or `None` if there were none
+    ///
+    /// Observations beyond the last bucket report its bound.
+    pub fn since_last(&mut self, histogram: &Histogram, q: f64) -> Option<f64> {
+        let metric = histogram.metric();
+        let snapshot = metric.get_histogram();
+        let buckets = snapshot.get_bucket();
+        let counts: Vec<u64> = buckets.iter().map(|b| b.cumulative_count()).collect();
+        let total = snapshot.get_sample_count();
+
+        let previous = std::mem::replace(&mut self.counts, counts);
+        let new = total.saturating_sub(std::mem::replace(&mut self.total, total));
+        if new == 0 {
+            return None;
+        }
+        let rank = (q * new as f64).ceil().max(1.0) as u64;
+        let since = |i: usize| self.counts[i].saturating_sub(previous.get(i).copied().unwrap_or(0));
+        buckets
+            .iter()
+            .enumerate()
+            .find(|&(i, _)| since(i) >= rank)
+            .or(buckets.iter().enumerate().next_back())
+            .map(|(_, bucket)| bucket.upper_bound())
+    }
+}
+
+/// Publish a cache's occupancy, and the evictions since it was last published
+pub fn record_cache(name: &str, stats: CacheStats) {
+    CACHE_ENTRIES
+        .with_label_values(&[name])
+        .set(stats.entries as i64);
+    CACHE_BYTES
+        .with_label_values(&[name])
+        .set(stats.bytes as i64);
+    let evictions = CACHE_EVICTIONS.with_label_values(&[name]);
+    if stats.evictions > evictions.get() {
+        evictions.inc_by(stats.evictions - evictions.get());
+    }
+}
+
+fn histogram(name: &str, help: &str, buckets: Vec<f64>) -> Histogram {
+    let opts = HistogramOpts::new(name, help).buckets(buckets);
+    register(Histogram::with_opts(opts).expect("valid histogram"))
+}
+
+fn gauge(name: &str, help: &str) -> IntGauge {
+    register(IntGauge::new(name, help).expect("valid gauge"))
+}
+
+fn buckets(start: f64, factor: f64, count: usize) -> Vec<f64> {
+    exponential_buckets(start, factor, count).expect("valid buckets")
+}
+
+fn register<M: prometheus::core::Collector + Clone + 'static>(metric: M) -> M {
+    REGISTRY
+        .register(Box::new(metric.clone()))
+        .expect("metric registered once");
+    metric
+}
+
+/// Render all metrics in the Prometheus text format
+pub fn render() -> String {
+    // Register every metric up front so unused ones still show (as zero)
+    LazyLock::force(&CAPTCHA_GENERATE_SECONDS);
+    LazyLock::force(&CAPTCHA_VERIFY_SECONDS);
+    LazyLock::force(&CAPTCHA_SOLVE_SECONDS);
+    LazyLock::force(&HTTP_REQUEST_SECONDS);
+    LazyLock::force(&HTTP_RESPONSES);
+    LazyLock::force(&HTTP_REQUESTS_IN_FLIGHT);
+    LazyLock::force(&CHALLENGE_WRITE_BATCH);
+    LazyLock::force(&CHALLENGE_WRITES_REFUSED);
+    LazyLock::force(&ACCESS_LOG_DROPPED);
+    LazyLock::force(&AMMO_POP_SECONDS);
+    LazyLock::force(&AMMO_LOAD_SECONDS);
+    LazyLock::force(&AMMO_DUMP_SECONDS);
+    LazyLock::force(&AMMO_POOL_WAIT_SECONDS);
+    LazyLock::force(&HAPROXY_CURRENT_SESSIONS);
+    LazyLock::force(&HAPROXY_SESSIONS);
+    LazyLock::force(&HAPROXY_DENIED_REQUESTS);
+    LazyLock::force(&HAPROXY_TABLE_ENTRIES);
+    LazyLock::force(&HAPROXY_TABLE_SIZE);
+    LazyLock::force(&HAPROXY_CIRCUIT_PRESSURE);
+    LazyLock::force(&OBSERVED_DECISIONS);
+    LazyLock::force(&GOSSIP_PEER_FLAPS);
+    LazyLock::force(&GOSSIP_FLAPPING_PEERS);
+    LazyLock::force(&GOSSIP_REJECTED_PACKETS);
+    LazyLock::force(&AMMO_SHARED);
+    LazyLock::force(&PASSPORT_RETIRED_KEY_VALIDATIONS);
+    LazyLock::force(&ALLOWLIST_HITS);
+    LazyLock::force(&UNTRUSTED_HEADERS_STRIPPED);
+    LazyLock::force(&POW_SOLUTIONS);
+    LazyLock::force(&VERIFY_THROTTLED);
+    LazyLock::force(&GATE_FORM_RESUBMISSIONS);
+    LazyLock::force(&BACKUPS);
+    LazyLock::force(&SHADOW_ISSUED);
+    LazyLock::force(&SHADOW_RESULTS);
+    LazyLock::force(&SHADOW_SOLVE_SECONDS);
+    LazyLock::force(&JOURNEY_SECONDS);
+    LazyLock::force(&EXPERIMENT_CHALLENGES);
+    LazyLock::force(&CHALLENGE_OUTCOMES);
+    LazyLock::force(&CHALLENGES_ABANDONED);
+    LazyLock::force(&CHALLENGE_ABANDONMENT_RATIO);
+    LazyLock::force(&VERIFY_QUEUE_DEPTH);
+    LazyLock::force(&VERIFY_QUEUE_WAIT_SECONDS);
+    LazyLock::force(&VERIFY_QUEUE_SHED);
+    LazyLock::force(&CACHE_ENTRIES);
+    LazyLock::force(&CACHE_BYTES);
+    LazyLock::force(&CACHE_EVICTIONS);
+    LazyLock::force(&REDIS_KEYS);
+    LazyLock::force(&REDIS_ORPHANED_KEYS);
+    LazyLock::force(&REDIS_KEYS_SCANNED);
+    LazyLock::force(&REDIS_UP);
+    LazyLock::force(&REDIS_PING_SECONDS);
+    LazyLock::force(&AMMO_DISK_FREE_BYTES);
+    LazyLock::force(&PANICS);
+    LazyLock::force(&TASK_RESTARTS);
+    LazyLock::force(&SLO_BURN_RATE);
+    LazyLock::force(&SLO_BUDGET_REMAINING);
+    LazyLock::force(&FEATURE_FLAGS);
+
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer) {
+        tracing::error!(error = %e, "Failed to encode metrics");
+    }
+    String::from_utf8(buffer).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_includes_observations() {
+        CAPTCHA_SOLVE_SECONDS.observe(4.2);
+        CAPTCHA_VERIFY_SECONDS
+            .with_label_values(&["correct"])
+            .observe(0.003);
+
+        let text = render();
+        assert!(text.contains("fortify_captcha_solve_seconds_count"));
+        assert!(text.contains("fortify_captcha_verify_seconds_bucket{outcome=\"correct\""));
+        assert!(text.contains("# TYPE fortify_ammo_pool_wait_seconds histogram"));
+        assert!(text.contains("# TYPE fortify_haproxy_table_entries gauge"));
+    }
+
+    #[test]
+    fn test_recent_quantile_covers_new_observations() {
+        let histogram = Histogram::with_opts(
+            HistogramOpts::new("test_recent_seconds", "test").buckets(vec![0.01, 0.1, 1.0]),
+        )
+        .unwrap();
+        let mut p99 = RecentQuantile::default();
+        assert_eq!(p99.since_last(&histogram, 0.99), None);
+
+        for _ in 0..99 {
+            histogram.observe(0.005);
+        }
+        histogram.observe(0.5);
+        assert_eq!(p99.since_last(&histogram, 0.99), Some(0.01));
+        assert_eq!(p99.since_last(&histogram, 0.99), None);
+
+        // Only the slow ones since: the old fast ones don't dilute them
+        histogram.observe(0.05);
+        histogram.observe(5.0);
+        assert_eq!(p99.since_last(&histogram, 0.99), Some(1.0));
+    }
+}
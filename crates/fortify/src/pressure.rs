@@ -0,0 +1,258 @@
+//! Challenge pressure: how hard HAProxy should throttle each circuit.
+//!
+//! Every `interval_secs` the circuits in the HAProxy stick table are given a
+//! pressure from 0 (leave alone) to `MAX_PRESSURE` (throttle hardest),
+//! written to a general purpose tag of their entry (`gpt0` by default). The
+//! HAProxy config reads it back with `sc_get_gpt0` and applies tighter
+//! `tcp-request` rates to circuits under more pressure, before their
+//! traffic reaches Fortify (see config/haproxy.cfg).
+//!
+//! A circuit's pressure starts from the threat band, then:
+//! - VIPs get none and soft-locked circuits the most
+//! - a low reputation, repeated wrong answers and challenges left to expire
+//!   (see `abandonment`) each add a step
+//! - a verified circuit in good standing takes a step off
+//!
+//! Banned entries are skipped (HAProxy denies them on `gpc0` already), as
+//! are entries whose tag already holds the right value. Without Redis only
+//! the threat band counts.
+
+use anyhow::Result;
+use cerberus_common::{
+    CircuitId, CircuitInfo, CircuitStatus, ThreatBand, ThreatLevel, redis_keys, versioned,
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use crate::haproxy::{HaproxyApi, HaproxyCircuitStatus, StickTableEntry};
+use crate::metrics;
+use crate::state::AppState;
+
+/// Highest pressure written
+pub const MAX_PRESSURE: u32 = 3;
+
+/// Challenge pressure settings (`[haproxy.pressure]` in fortify.toml)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PressureConfig {
+    pub enabled: bool,
+    /// How often the stick table is walked
+    pub interval_secs: u64,
+    /// Stick table data type holding the pressure (`gpt0`, or `gpt(N)`
+    /// for a table storing a `gpt` array)
+    pub tag: String,
+    /// Most entries updated per walk
+    pub max_circuits: usize,
+    /// Reputation at or under which a circuit gets a step more
+    pub suspicious_reputation: i32,
+    /// Wrong answers from which a circuit gets a step more
+    pub failed_attempts: u32,
+    /// Abandoned challenges from which a circuit gets a step more
+    pub abandoned: u64,
+}
+
+impl Default for PressureConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: 15,
+            tag: "gpt0".to_string(),
+            max_circuits: 10_000,
+            suspicious_reputation: -20,
+            failed_attempts: 3,
+            abandoned: 5,
+        }
+    }
+}
+
+impl PressureConfig {
+    /// Pressure on a circuit at `threat`, given its record (if any) and the
+    /// challenges it abandoned
+    pub fn pressure(&self, threat: ThreatLevel, info: Option<&CircuitInfo>, abandoned: u64) -> u32 {
+        let base = match threat.band() {
+            ThreatBand::Off | ThreatBand::Light => 0,
+            ThreatBand::Standard => 1,
+            ThreatBand::High => 2,
+            ThreatBand::Lockdown => MAX_PRESSURE,
+        };
+        let Some(info) = info else {
+            let steps = u32::from(abandoned >= self.abandoned);
+            return (base + steps).min(MAX_PRESSURE);
+        };
+
+        match info.status {
+            CircuitStatus::Vip => return 0,
+            CircuitStatus::SoftLocked => return MAX_PRESSURE,
+            _ => {}
+        }
+        let steps = [
+            info.reputation <= self.suspicious_reputation,
+            info.failed_attempts >= self.failed_attempts,
+            abandoned >= self.abandoned,
+        ]
+        .into_iter()
+        .filter(|&step| step)
+        .count() as u32;
+        let relief = u32::from(info.status == CircuitStatus::Verified && info.reputation > 0);
+        (base + steps).saturating_sub(relief).min(MAX_PRESSURE)
+    }
+}
+
+/// Valid stick table tag name: `gpt0` or `gpt(N)`
+pub fn is_valid_tag(tag: &str) -> bool {
+    tag == "gpt0"
+        || tag
+            .strip_prefix("gpt(")
+            .and_then(|rest| rest.strip_suffix(')'))
+            .is_some_and(|index| !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Walk the stick table every `interval_secs`, tagging circuits with their
+/// pressure (needs `haproxy.enabled` and `haproxy.pressure.enabled`)
+pub async fn pressure_worker(state: AppState, mut shutdown: broadcast::Receiver<()>) {
+    let config = state.config.haproxy.pressure.clone();
+    let Some(api) = state.haproxy.clone() else {
+        return;
+    };
+    if !config.enabled {
+        return;
+    }
+    let interval = Duration::from_secs(config.interval_secs);
+
+    loop {
+        match round(&state, &api, &config).await {
+            Ok(0) => {}
+            Ok(updated) => tracing::debug!(updated, "Updated HAProxy challenge pressure"),
+            Err(e) => tracing::warn!(error = %e, "Failed to update HAProxy challenge pressure"),
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = shutdown.recv() => break,
+        }
+    }
+    tracing::info!("HAProxy challenge pressure shutting down");
+}
+
+/// One walk of the table, returning how many entries were updated
+async fn round(state: &AppState, api: &HaproxyApi, config: &PressureConfig) -> Result<usize> {
+    let threat = state.get_threat_level().await;
+    let entries: Vec<StickTableEntry> = api
+        .iter_table(None)
+        .await?
+        .filter(|entry| entry.gpc0 != HaproxyCircuitStatus::Banned as u8)
+        .take(config.max_circuits)
+        .collect();
+    let circuit_ids: Vec<Option<CircuitId>> =
+        entries.iter().map(|entry| entry.key.parse().ok()).collect();
+    let signals = match state.redis() {
+        Some(mut redis) => load_signals(&mut redis, &circuit_ids).await?,
+        None => vec![(None, 0); entries.len()],
+    };
+
+    let mut at_level = [0i64; MAX_PRESSURE as usize + 1];
+    let mut updated = 0;
+    for (entry, (info, abandoned)) in entries.iter().zip(signals) {
+        let pressure = config.pressure(threat, info.as_ref(), abandoned);
+        at_level[pressure as usize] += 1;
+        if entry.tags.get(&config.tag) == Some(&pressure) {
+            continue;
+        }
+        api.set_circuit_tag(&entry.key, &config.tag, pressure)
+            .await?;
+        updated += 1;
+    }
+
+    for (pressure, count) in at_level.iter().enumerate() {
+        metrics::HAPROXY_CIRCUIT_PRESSURE
+            .with_label_values(&[pressure.to_string().as_str()])
+            .set(*count);
+    }
+    Ok(updated)
+}
+
+/// Record and abandoned challenge count of each circuit (entries whose key
+/// isn't a circuit ID get neither)
+async fn load_signals(
+    redis: &mut redis::aio::ConnectionManager,
+    circuit_ids: &[Option<CircuitId>],
+) -> Result<Vec<(Option<CircuitInfo>, u64)>> {
+    let mut pipe = redis::pipe();
+    for circuit_id in circuit_ids.iter().flatten() {
+        pipe.get(redis_keys::circuit(circuit_id))
+            .get(redis_keys::abandoned(circuit_id));
+    }
+    let values: Vec<(Option<String>, Option<u64>)> = pipe.query_async(redis).await?;
+
+    let mut values = values.into_iter();
+    Ok(circuit_ids
+        .iter()
+        .map(|circuit_id| {
+            if circuit_id.is_none() {
+                return (None, 0);
+            }
+            let (record, abandoned) = values.next().unwrap_or_default();
+            let info = record.and_then(|data| match versioned::decode(&data) {
+                Ok(info) => Some(info),
+                Err(e) => {
+                    tracing::debug!(error = %e, "Skipping undecodable circuit record");
+                    None
+                }
+            });
+            (info, abandoned.unwrap_or(0))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn circuit(status: CircuitStatus, reputation: i32, failed_attempts: u32) -> CircuitInfo {
+        let mut info = CircuitInfo::new("fc00::1".parse().unwrap());
+        info.status = status;
+        info.reputation = reputation;
+        info.failed_attempts = failed_attempts;
+        info
+    }
+
+    #[test]
+    fn test_pressure() {
+        let config = PressureConfig::default();
+        let standard = ThreatLevel::new(5);
+
+        // The threat band sets the base
+        assert_eq!(config.pressure(ThreatLevel::new(2), None, 0), 0);
+        assert_eq!(config.pressure(standard, None, 0), 1);
+        assert_eq!(config.pressure(ThreatLevel::new(8), None, 0), 2);
+        assert_eq!(config.pressure(ThreatLevel::MAX, None, 0), MAX_PRESSURE);
+
+        let new = circuit(CircuitStatus::New, 0, 0);
+        assert_eq!(config.pressure(standard, Some(&new), 0), 1);
+        // Each signal adds a step, up to the maximum
+        let failing = circuit(CircuitStatus::New, -20, 3);
+        assert_eq!(config.pressure(standard, Some(&failing), 0), 3);
+        assert_eq!(config.pressure(standard, Some(&failing), 5), MAX_PRESSURE);
+        assert_eq!(config.pressure(standard, None, 5), 2);
+        // Good standing takes one off
+        let verified = circuit(CircuitStatus::Verified, 10, 0);
+        assert_eq!(config.pressure(standard, Some(&verified), 0), 0);
+
+        let vip = circuit(CircuitStatus::Vip, 50, 0);
+        assert_eq!(config.pressure(ThreatLevel::MAX, Some(&vip), 10), 0);
+        let locked = circuit(CircuitStatus::SoftLocked, 0, 0);
+        assert_eq!(
+            config.pressure(ThreatLevel::new(1), Some(&locked), 0),
+            MAX_PRESSURE
+        );
+    }
+
+    #[test]
+    fn test_valid_tag() {
+        assert!(is_valid_tag("gpt0"));
+        assert!(is_valid_tag("gpt(2)"));
+        assert!(!is_valid_tag("gpt()"));
+        assert!(!is_valid_tag("gpc0"));
+        assert!(!is_valid_tag("gpt0 1;shutdown"));
+    }
+}
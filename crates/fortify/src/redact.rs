@@ -0,0 +1,409 @@
+//! Redaction of sensitive values in logs.
+//!
+//! Circuit IDs, onion addresses and challenge IDs identify visitors;
+//! passport tokens and session IDs are credentials. Neither belongs in a log
+//! verbatim. The policy (`[privacy]` in fortify.toml) names the fields of
+//! each kind and how they're written:
+//!
+//! - `off`: verbatim (debugging only)
+//! - `truncate`: identifiers cut to a short prefix; secrets hashed
+//! - `hash`: both replaced by a keyed hash, the same for the same value, so
+//!   lines about one circuit can still be followed
+//! - `redact`: both replaced by `<redacted>`
+//!
+//! It's applied by name to every tracing field, in text and JSON logs alike
+//! (`RedactFields`, `JsonEvents`), and to the access log. Values written into
+//! a message itself aren't seen; log them as fields.
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::fmt;
+use std::sync::OnceLock;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::field::{MakeVisitor, RecordFields, VisitFmt, VisitOutput};
+use tracing_subscriber::fmt::format::{FormatEvent, FormatFields, JsonVisitor, Writer};
+use tracing_subscriber::fmt::{FmtContext, FormattedFields};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Log privacy settings (`[privacy]` in fortify.toml)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PrivacyConfig {
+    pub level: PrivacyLevel,
+    /// Fields naming a visitor (circuits, onion addresses, challenges)
+    pub identifiers: Vec<String>,
+    /// Fields holding credentials (passports, sessions)
+    pub secrets: Vec<String>,
+    /// Key for `hash` ("" = random per process, so hashes differ across
+    /// restarts and nodes; set the same key everywhere to correlate them)
+    pub hash_key: String,
+}
+
+impl Default for PrivacyConfig {
+    fn default() -> Self {
+        Self {
+            level: PrivacyLevel::default(),
+            identifiers: [
+                "circuit_id",
+                "challenge_id",
+                "old_challenge_id",
+                "onion",
+                "onion_address",
+            ]
+            .map(String::from)
+            .to_vec(),
+            secrets: ["token", "passport", "secret", "session_id"]
+                .map(String::from)
+                .to_vec(),
+            hash_key: String::new(),
+        }
+    }
+}
+
+/// How sensitive fields are written (`privacy.level`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PrivacyLevel {
+    Off,
+    Truncate,
+    #[default]
+    Hash,
+    Redact,
+}
+
+/// Characters of an identifier kept by `truncate`
+const TRUNCATE_CHARS: usize = 6;
+
+/// Hex digits of a `hash`
+const HASH_DIGITS: usize = 12;
+
+const REDACTED: &str = "<redacted>";
+
+/// The policy in force
+pub struct Redactor {
+    level: PrivacyLevel,
+    identifiers: Vec<String>,
+    secrets: Vec<String>,
+    key: Vec<u8>,
+}
+
+static POLICY: OnceLock<Redactor> = OnceLock::new();
+
+/// Put `config` in force for the life of the process (first call wins)
+pub fn install(config: &PrivacyConfig) {
+    let _ = POLICY.set(Redactor::new(config));
+}
+
+/// The policy in force (verbatim until one is installed)
+pub fn policy() -> &'static Redactor {
+    POLICY.get_or_init(|| {
+        Redactor::new(&PrivacyConfig {
+            level: PrivacyLevel::Off,
+            ..Default::default()
+        })
+    })
+}
+
+impl Redactor {
+    pub fn new(config: &PrivacyConfig) -> Self {
+        let key = if config.hash_key.is_empty() {
+            rand::random::<[u8; 32]>().to_vec()
+        } else {
+            config.hash_key.as_bytes().to_vec()
+        };
+        Self {
+            level: config.level,
+            identifiers: config.identifiers.clone(),
+            secrets: config.secrets.clone(),
+            key,
+        }
+    }
+
+    /// An identifier as it may be logged
+    pub fn identifier(&self, value: &str) -> String {
+        match self.level {
+            PrivacyLevel::Off => value.to_string(),
+            PrivacyLevel::Truncate => {
+                let kept: String = value.chars().take(TRUNCATE_CHARS).collect();
+                if kept.len() < value.len() {
+                    format!("{}…", kept)
+                } else {
+                    kept
+                }
+            }
+            PrivacyLevel::Hash => self.hash(value),
+            PrivacyLevel::Redact => REDACTED.to_string(),
+        }
+    }
+
+    /// A secret as it may be logged (never even partly)
+    pub fn secret(&self, value: &str) -> String {
+        match self.level {
+            PrivacyLevel::Off => value.to_string(),
+            PrivacyLevel::Truncate | PrivacyLevel::Hash => self.hash(value),
+            PrivacyLevel::Redact => REDACTED.to_string(),
+        }
+    }
+
+    /// `field`'s value as it may be logged, or `None` if it isn't sensitive
+    fn field(&self, field: &str, value: &str) -> Option<String> {
+        if self.level == PrivacyLevel::Off {
+            None
+        } else if self.identifiers.iter().any(|name| name == field) {
+            Some(self.identifier(value))
+        } else if self.secrets.iter().any(|name| name == field) {
+            Some(self.secret(value))
+        } else {
+            None
+        }
+    }
+
+    fn hash(&self, value: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes any key length");
+        mac.update(value.as_bytes());
+        let digest = mac.finalize().into_bytes();
+        let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("h:{}", &hex[..HASH_DIGITS])
+    }
+}
+
+/// The value inside a field's `Debug` output
+///
+/// `%id`, `?id` and `?Some(id)` all log the same ID; hashing the bare value
+/// keeps them the same hash. `None` is left alone.
+fn bare(debug: &str) -> Option<&str> {
+    if debug == "None" {
+        return None;
+    }
+    let mut value = debug;
+    while let Some(inner) = unwrap_call(value) {
+        value = inner;
+    }
+    Some(
+        value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value),
+    )
+}
+
+/// `inner` of `Name(inner)` (`Some`, newtypes)
+fn unwrap_call(value: &str) -> Option<&str> {
+    let (name, rest) = value.split_once('(')?;
+    let is_name = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if is_name {
+        rest.strip_suffix(')')
+    } else {
+        None
+    }
+}
+
+/// Visitor writing redacted values to the one it wraps
+pub struct Redact<V> {
+    inner: V,
+}
+
+impl<V: Visit> Visit for Redact<V> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        let debug = format!("{:?}", value);
+        match bare(&debug).and_then(|bare| policy().field(field.name(), bare)) {
+            Some(redacted) => self.inner.record_str(field, &redacted),
+            None => self.inner.record_debug(field, value),
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        match policy().field(field.name(), value) {
+            Some(redacted) => self.inner.record_str(field, &redacted),
+            None => self.inner.record_str(field, value),
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.inner.record_i64(field, value);
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.inner.record_u64(field, value);
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.inner.record_f64(field, value);
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.inner.record_bool(field, value);
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.inner.record_error(field, value);
+    }
+}
+
+impl<V: VisitOutput<fmt::Result>> VisitOutput<fmt::Result> for Redact<V> {
+    fn finish(self) -> fmt::Result {
+        self.inner.finish()
+    }
+}
+
+impl<V: VisitFmt> VisitFmt for Redact<V> {
+    fn writer(&mut self) -> &mut dyn fmt::Write {
+        self.inner.writer()
+    }
+}
+
+/// Field formatter for text logs: `inner`'s output, redacted
+pub struct RedactFields<M> {
+    inner: M,
+}
+
+impl<M> RedactFields<M> {
+    pub fn new(inner: M) -> Self {
+        Self { inner }
+    }
+}
+
+impl<T, M: MakeVisitor<T>> MakeVisitor<T> for RedactFields<M> {
+    type Visitor = Redact<M::Visitor>;
+
+    fn make_visitor(&self, target: T) -> Self::Visitor {
+        Redact {
+            inner: self.inner.make_visitor(target),
+        }
+    }
+}
+
+/// Span field formatter for JSON logs, redacted
+pub struct RedactJsonFields;
+
+impl<'writer> FormatFields<'writer> for RedactJsonFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        mut writer: Writer<'writer>,
+        fields: R,
+    ) -> fmt::Result {
+        let mut visitor = Redact {
+            inner: JsonVisitor::new(&mut writer),
+        };
+        fields.record(&mut visitor);
+        visitor.finish()
+    }
+}
+
+/// JSON event formatter, redacted
+///
+/// The stock JSON format writes event fields itself, past any field
+/// formatter; this one writes the same shape (`timestamp`, `level`,
+/// `fields`, `target`, `span`, `spans`) through `Redact`.
+pub struct JsonEvents;
+
+impl<S, N> FormatEvent<S, N> for JsonEvents
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'writer> FormatFields<'writer> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut fields = String::new();
+        let mut visitor = Redact {
+            inner: JsonVisitor::new(&mut fields),
+        };
+        event.record(&mut visitor);
+        visitor.finish()?;
+
+        let meta = event.metadata();
+        let mut line = serde_json::Map::new();
+        line.insert(
+            "timestamp".into(),
+            chrono::Utc::now()
+                .to_rfc3339_opts(chrono::SecondsFormat::Micros, true)
+                .into(),
+        );
+        line.insert("level".into(), meta.level().as_str().into());
+        line.insert("fields".into(), parse_object(&fields));
+        line.insert("target".into(), meta.target().into());
+
+        if let Some(scope) = ctx.event_scope() {
+            let spans: Vec<serde_json::Value> = scope
+                .from_root()
+                .map(|span| {
+                    let mut object = match span
+                        .extensions()
+                        .get::<FormattedFields<N>>()
+                        .map(|recorded| parse_object(recorded))
+                    {
+                        Some(serde_json::Value::Object(object)) => object,
+                        _ => serde_json::Map::new(),
+                    };
+                    object.insert("name".into(), span.name().into());
+                    serde_json::Value::Object(object)
+                })
+                .collect();
+            if let Some(current) = spans.last() {
+                line.insert("span".into(), current.clone());
+            }
+            line.insert("spans".into(), spans.into());
+        }
+
+        writeln!(writer, "{}", serde_json::Value::Object(line))
+    }
+}
+
+/// Fields recorded as a JSON object (empty if there were none)
+fn parse_object(json: &str) -> serde_json::Value {
+    serde_json::from_str(json).unwrap_or_else(|_| serde_json::Value::Object(Default::default()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redactor(level: PrivacyLevel) -> Redactor {
+        Redactor::new(&PrivacyConfig {
+            level,
+            hash_key: "test".to_string(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_levels() {
+        let id = "fc00:dead:beef:4dad::0:2a";
+        let token = "c2VjcmV0LXBhc3Nwb3J0";
+
+        let off = redactor(PrivacyLevel::Off);
+        assert_eq!(off.field("circuit_id", id), None);
+        assert_eq!(off.field("token", token), None);
+
+        let truncate = redactor(PrivacyLevel::Truncate);
+        assert_eq!(truncate.field("circuit_id", id).unwrap(), "fc00:d…");
+        // Secrets are never shown, even in part
+        assert!(truncate.field("token", token).unwrap().starts_with("h:"));
+        assert_eq!(truncate.field("path", "/validate"), None);
+
+        let hash = redactor(PrivacyLevel::Hash);
+        let hashed = hash.field("circuit_id", id).unwrap();
+        assert_eq!(hashed.len(), 2 + HASH_DIGITS);
+        assert_eq!(hash.field("circuit_id", id).unwrap(), hashed);
+        assert_ne!(hash.field("circuit_id", "fc00::1").unwrap(), hashed);
+
+        let redact = redactor(PrivacyLevel::Redact);
+        assert_eq!(redact.field("session_id", "abc").unwrap(), REDACTED);
+    }
+
+    #[test]
+    fn test_bare_debug_values() {
+        assert_eq!(bare("fc00::1"), Some("fc00::1"));
+        assert_eq!(bare(r#""fc00::1""#), Some("fc00::1"));
+        assert_eq!(bare(r#"Some(CircuitId("fc00::1"))"#), Some("fc00::1"));
+        assert_eq!(bare("None"), None);
+        // Quotes inside a displayed ID are part of it
+        assert_eq!(bare(r#"a"b"c"#), Some(r#"a"b"c"#));
+    }
+}
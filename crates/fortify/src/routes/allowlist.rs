@@ -0,0 +1,128 @@
+//! Admin management of the first-party bot allowlist, and the bypass
+//! `/validate` grants its secrets.
+
+use axum::{
+    Json,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::allowlist::{self, AllowlistEntry};
+use crate::metrics;
+use crate::state::AppState;
+
+/// The entry a request's allowlist secret was issued for, if it is live
+///
+/// Errors are logged and treated as no secret, so the request goes on to
+/// be checked as usual.
+pub async fn allowlisted(state: &AppState, headers: &HeaderMap) -> Option<AllowlistEntry> {
+    let secret = headers
+        .get(state.config.allowlist.header.as_str())?
+        .to_str()
+        .ok()?;
+    let mut redis = state.redis()?;
+
+    match allowlist::check(&mut redis, secret).await {
+        Ok(Some(entry)) => {
+            metrics::ALLOWLIST_HITS
+                .with_label_values(&[entry.name.as_str()])
+                .inc();
+            Some(entry)
+        }
+        Ok(None) => {
+            tracing::debug!("Unknown or expired allowlist secret");
+            None
+        }
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to check allowlist");
+            None
+        }
+    }
+}
+
+/// Live allowlist entries (503 while Redis is offline)
+pub async fn list_allowlist(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<AllowlistEntry>>, StatusCode> {
+    let mut redis = state.redis().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    allowlist::list(&mut redis).await.map(Json).map_err(|e| {
+        tracing::error!(error = %e, "Failed to list allowlist");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+#[derive(Deserialize)]
+pub struct AllowlistRequest {
+    pub name: String,
+    /// Defaults to `allowlist.default_ttl_secs`
+    pub ttl_secs: Option<u64>,
+    pub note: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct AllowlistResponse {
+    /// Send in `allowlist.header`; shown only this once
+    pub secret: String,
+    /// Header to send it in
+    pub header: String,
+    #[serde(flatten)]
+    pub entry: AllowlistEntry,
+}
+
+/// Issue a secret for a first-party client, replacing any it had
+///
+/// Returns:
+/// - 200: Issued
+/// - 400: Invalid name, or a TTL of 0 or over `allowlist.max_ttl_secs`
+/// - 503: Redis is offline
+pub async fn add_to_allowlist(
+    State(state): State<AppState>,
+    Json(request): Json<AllowlistRequest>,
+) -> Result<Json<AllowlistResponse>, StatusCode> {
+    let config = &state.config.allowlist;
+    let ttl = config
+        .ttl(request.ttl_secs)
+        .filter(|_| allowlist::is_valid_name(&request.name))
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let mut redis = state.redis().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+
+    let (secret, entry) = allowlist::add(&mut redis, &request.name, ttl, request.note)
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to add to allowlist");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    tracing::info!(
+        name = %entry.name,
+        expires_at = entry.expires_at,
+        "Allowlist secret issued by admin"
+    );
+
+    Ok(Json(AllowlistResponse {
+        secret,
+        header: config.header.clone(),
+        entry,
+    }))
+}
+
+/// Revoke a client's secret (404 if it had none)
+pub async fn remove_from_allowlist(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> StatusCode {
+    let Some(mut redis) = state.redis() else {
+        return StatusCode::SERVICE_UNAVAILABLE;
+    };
+    match allowlist::remove(&mut redis, &name).await {
+        Ok(true) => {
+            tracing::info!(name = %name, "Allowlist secret revoked by admin");
+            StatusCode::NO_CONTENT
+        }
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to remove from allowlist");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
@@ -0,0 +1,160 @@
+//! Ammo Box status and manual controls.
+//!
+//! Operators can pre-fill the pool before an announced attack window, move
+//! CAPTCHAs between the pool and the disk cache, or purge CAPTCHAs rendered
+//! by a buggy release.
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::StatusCode,
+};
+use cerberus_common::CaptchaDifficulty;
+use serde::{Deserialize, Serialize};
+
+use crate::captcha::{AmmoBoxStatsSnapshot, DiskUsage};
+use crate::state::AppState;
+
+#[derive(Serialize)]
+pub struct AmmoStatus {
+    #[serde(flatten)]
+    pub stats: AmmoBoxStatsSnapshot,
+    pub warm: bool,
+    /// `None` if the cache directory couldn't be read
+    pub disk: Option<DiskUsage>,
+}
+
+/// Ammo Box pool, counters and disk cache
+pub async fn get_ammo(State(state): State<AppState>) -> Json<AmmoStatus> {
+    let disk = match state.ammo_box.disk_usage().await {
+        Ok(usage) => Some(usage),
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to read ammo disk cache");
+            None
+        }
+    };
+    Json(AmmoStatus {
+        stats: state.ammo_box.get_stats(),
+        warm: state.ammo_box.is_warm(),
+        disk,
+    })
+}
+
+/// What a manual action did
+#[derive(Serialize)]
+pub struct AmmoAction {
+    /// CAPTCHAs generated, dumped, loaded or flushed
+    pub count: usize,
+    /// Batch files deleted (flush with `disk=true`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disk_files: Option<usize>,
+    pub pool_size: usize,
+}
+
+impl AmmoAction {
+    fn new(state: &AppState, count: usize) -> Self {
+        Self {
+            count,
+            disk_files: None,
+            pool_size: state.ammo_box.len(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct GenerateQuery {
+    /// Defaults to filling the pool
+    pub count: Option<usize>,
+    #[serde(default = "default_difficulty")]
+    pub difficulty: CaptchaDifficulty,
+}
+
+fn default_difficulty() -> CaptchaDifficulty {
+    CaptchaDifficulty::Medium
+}
+
+/// Free space in the pool
+fn room(state: &AppState) -> usize {
+    state
+        .ammo_box
+        .capacity()
+        .saturating_sub(state.ammo_box.len())
+}
+
+/// Generate CAPTCHAs into the pool (up to its capacity)
+pub async fn generate(
+    State(state): State<AppState>,
+    Query(query): Query<GenerateQuery>,
+) -> Json<AmmoAction> {
+    let count = query.count.unwrap_or_else(|| room(&state));
+    let generated = state.ammo_box.fill(count, query.difficulty).await;
+    tracing::info!(
+        generated,
+        difficulty = ?query.difficulty,
+        "🎯 Ammo Box filled by admin"
+    );
+    Json(AmmoAction::new(&state, generated))
+}
+
+#[derive(Deserialize)]
+pub struct CountQuery {
+    pub count: Option<usize>,
+}
+
+/// Write pool CAPTCHAs to the disk cache (they stay in the pool too)
+pub async fn dump(
+    State(state): State<AppState>,
+    Query(query): Query<CountQuery>,
+) -> Result<Json<AmmoAction>, StatusCode> {
+    let count = query.count.unwrap_or_else(|| state.ammo_box.len());
+    let dumped = state.ammo_box.dump_to_disk(count).await.map_err(|e| {
+        tracing::error!(error = %e, "Failed to dump Ammo Box");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    state.ammo_box.mark_dumped().await;
+    tracing::info!(dumped, "🎯 Ammo Box dumped to disk by admin");
+    Ok(Json(AmmoAction::new(&state, dumped)))
+}
+
+/// Move CAPTCHAs from the disk cache into the pool (up to its capacity)
+pub async fn load(
+    State(state): State<AppState>,
+    Query(query): Query<CountQuery>,
+) -> Result<Json<AmmoAction>, StatusCode> {
+    let count = query.count.unwrap_or_else(|| room(&state));
+    let loaded = state.ammo_box.load_from_disk(count).await.map_err(|e| {
+        tracing::error!(error = %e, "Failed to load Ammo Box from disk");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    tracing::info!(loaded, "🎯 Ammo Box loaded from disk by admin");
+    Ok(Json(AmmoAction::new(&state, loaded)))
+}
+
+#[derive(Deserialize)]
+pub struct FlushQuery {
+    /// Delete the disk cache as well
+    #[serde(default)]
+    pub disk: bool,
+}
+
+/// Empty the pool (and with `disk=true`, the disk cache)
+pub async fn flush(
+    State(state): State<AppState>,
+    Query(query): Query<FlushQuery>,
+) -> Result<Json<AmmoAction>, StatusCode> {
+    let flushed = state.ammo_box.flush();
+    let disk_files = if query.disk {
+        let purged = state.ammo_box.purge_disk().await.map_err(|e| {
+            tracing::error!(error = %e, "Failed to purge Ammo Box disk cache");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        Some(purged)
+    } else {
+        None
+    };
+    tracing::warn!(flushed, disk_files, "🎯 Ammo Box flushed by admin");
+    Ok(Json(AmmoAction {
+        disk_files,
+        ..AmmoAction::new(&state, flushed)
+    }))
+}
@@ -2,131 +2,487 @@
 
 use axum::{
     Json,
-    extract::{Query, State},
-    http::StatusCode,
+    extract::{Path, State},
+    http::{HeaderMap, HeaderName, StatusCode, header},
+    response::{IntoResponse, Response},
 };
+use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
 use serde::{Deserialize, Serialize};
 
+use super::rate_limit::{self, RateLimitHeaders};
+use crate::captcha::ShadowChallenge;
+use crate::gate_session;
 use crate::state::AppState;
-use cerberus_common::CaptchaResult;
-
-#[derive(Deserialize)]
-pub struct ChallengeQuery {
-    /// Circuit ID (from X-Circuit-Id header or query param)
-    pub circuit_id: Option<String>,
-}
+use crate::verification::VerificationRequest;
+use cerberus_common::{CaptchaChallenge, CaptchaResult, ChallengeId, CircuitId};
 
 #[derive(Serialize)]
 pub struct ChallengeResponse {
-    pub challenge_id: String,
-    pub image_data: String,
+    pub challenge_id: ChallengeId,
+    /// Image as a `data:` URI (unless served separately)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_data: Option<String>,
+    /// Where to fetch the image (`captcha.serve_images`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_url: Option<String>,
     pub grid_size: (u8, u8),
     pub instructions: String,
     pub expires_in_secs: u32,
+    /// Optional extra puzzle from a candidate provider (`captcha.shadow`);
+    /// its answer is recorded, never enforced
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shadow: Option<ShadowChallenge>,
+}
+
+impl ChallengeResponse {
+    fn new(challenge: CaptchaChallenge) -> Self {
+        let (image_data, image_url) = if challenge.image_data.starts_with("data:") {
+            (Some(challenge.image_data), None)
+        } else {
+            (None, Some(challenge.image_data))
+        };
+        Self {
+            challenge_id: challenge.challenge_id,
+            image_data,
+            image_url,
+            grid_size: challenge.grid_size,
+            instructions: challenge.instructions,
+            expires_in_secs: super::expires_in_secs(challenge.expires_at),
+            shadow: None,
+        }
+    }
 }
 
 /// Generate a new CAPTCHA challenge
+///
+/// The circuit comes from `X-Circuit-Id` only, which `trusted_proxy` strips
+/// unless a trusted hop set it.
 pub async fn get_challenge(
     State(state): State<AppState>,
-    Query(params): Query<ChallengeQuery>,
-) -> Result<Json<ChallengeResponse>, (StatusCode, String)> {
-    let mut redis = state.redis.clone();
+    headers: HeaderMap,
+) -> Result<(RateLimitHeaders, Json<ChallengeResponse>), Response> {
+    let circuit_id = super::circuit_id_from_headers(&headers);
+    let format = super::image_format(&state, &headers);
+    let Some(mut redis) = state.redis() else {
+        // Offline: sealed challenge, no circuit checks or rate limits
+        let difficulty = state.get_threat_level().await.captcha_difficulty();
+        let challenge = state
+            .captcha_generator
+            .generate_stateless(difficulty, format);
+        return Ok((
+            RateLimitHeaders::default(),
+            Json(ChallengeResponse::new(challenge)),
+        ));
+    };
 
     // Check if circuit is allowed
-    if let Some(ref circuit_id) = params.circuit_id {
+    if let Some(ref circuit_id) = circuit_id {
         let (allowed, reason) = state
             .circuit_tracker
             .is_allowed(&mut redis, circuit_id)
             .await
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response())?;
 
         if !allowed {
             return Err((
                 StatusCode::FORBIDDEN,
                 reason.unwrap_or_else(|| "Access denied".to_string()),
-            ));
+            )
+                .into_response());
         }
     }
 
+    let limits = rate_limit::check(&state, &mut redis, circuit_id.as_ref())
+        .await
+        .map_err(IntoResponse::into_response)?;
+    if !limits.allowed() {
+        return Err(limits.too_many_requests());
+    }
+
     let threat_level = state.get_threat_level().await;
     let difficulty = threat_level.captcha_difficulty();
 
     let challenge = state
         .captcha_generator
-        .generate(&mut redis, params.circuit_id, difficulty)
+        .generate(&mut redis, circuit_id, difficulty, format)
+        .await
+        .map_err(|e| {
+            (
+                limits,
+                super::generation_error(&state, e, "Failed to generate challenge", false),
+            )
+                .into_response()
+        })?;
+
+    let mut response = ChallengeResponse::new(challenge);
+    response.shadow = super::issue_shadow(&state, &mut redis, difficulty, format).await;
+    Ok((limits, Json(response)))
+}
+
+/// Replace a challenge with a fresh one, invalidating the old one
+///
+/// JSON clients (`Accept: application/json`) get a `ChallengeResponse`;
+/// the no-JS form button gets the gate page re-rendered.
+pub async fn refresh_challenge(
+    State(state): State<AppState>,
+    Path(challenge_id): Path<ChallengeId>,
+    headers: HeaderMap,
+) -> Response {
+    let circuit_id = super::circuit_id_from_headers(&headers);
+    let json = super::wants_json(&headers);
+    let format = super::image_format(&state, &headers);
+
+    let Some(mut redis) = state.redis() else {
+        // Offline: sealed challenges can't be revoked, they just expire
+        if !json {
+            return super::serve_captcha_page_inner(state, circuit_id, format, None, None, None)
+                .await;
+        }
+        let difficulty = state.get_threat_level().await.captcha_difficulty();
+        let challenge = state
+            .captcha_generator
+            .generate_stateless(difficulty, format);
+        return Json(ChallengeResponse::new(challenge)).into_response();
+    };
+
+    if let Some(ref circuit_id) = circuit_id {
+        match state
+            .circuit_tracker
+            .is_allowed(&mut redis, circuit_id)
+            .await
+        {
+            Ok((true, _)) => {}
+            Ok((false, reason)) => {
+                return (
+                    StatusCode::FORBIDDEN,
+                    reason.unwrap_or_else(|| "Access denied".to_string()),
+                )
+                    .into_response();
+            }
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        }
+    }
+
+    let limits = match rate_limit::check(&state, &mut redis, circuit_id.as_ref()).await {
+        Ok(limits) => limits,
+        Err(status) => return status.into_response(),
+    };
+    if !limits.allowed() {
+        return limits.too_many_requests();
+    }
+
+    // Excessive refreshing (fishing for an easy challenge) costs reputation
+    if let Some(ref circuit_id) = circuit_id {
+        let max = state.config.captcha.max_refreshes_per_minute;
+        match state
+            .circuit_tracker
+            .record_refresh(&mut redis, circuit_id)
+            .await
+        {
+            Ok(count) if count > max => {
+                let penalty = state.config.captcha.refresh_penalty;
+                if let Err(e) = state
+                    .circuit_tracker
+                    .adjust_reputation(&mut redis, circuit_id, -penalty, "excessive_refresh")
+                    .await
+                {
+                    tracing::warn!(error = %e, circuit_id = %circuit_id, "Failed to penalize circuit");
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                tracing::warn!(error = %e, circuit_id = %circuit_id, "Failed to count refresh");
+            }
+        }
+    }
+
+    // The form re-rendered stays with the visitor's gate session
+    let session_id = match gate_session::from_headers(&headers) {
+        Some(id) if !json => super::load_gate_session(&state, &mut redis, &id)
+            .await
+            .map(|_| id),
+        _ => None,
+    };
+
+    let threat_level = state.get_threat_level().await;
+    let difficulty = threat_level.captcha_difficulty();
+    let challenge = match state
+        .captcha_generator
+        .refresh(
+            &mut redis,
+            &challenge_id,
+            circuit_id.clone(),
+            difficulty,
+            format,
+        )
+        .await
+    {
+        Ok(Some(challenge)) => challenge,
+        Ok(None) if json => {
+            return (
+                StatusCode::NOT_FOUND,
+                limits,
+                "Challenge expired or invalid",
+            )
+                .into_response();
+        }
+        Ok(None) => {
+            // Stale page: just hand out a fresh challenge
+            return (
+                limits,
+                super::serve_captcha_page_inner(
+                    state,
+                    circuit_id,
+                    format,
+                    session_id.as_ref(),
+                    None,
+                    None,
+                )
+                .await,
+            )
+                .into_response();
+        }
+        Err(e) => {
+            return (
+                limits,
+                super::generation_error(&state, e, "Failed to refresh challenge", !json),
+            )
+                .into_response();
+        }
+    };
+
+    if json {
+        (limits, Json(ChallengeResponse::new(challenge))).into_response()
+    } else {
+        let (nonce, cookie) =
+            super::issue_form_nonce(&state, &mut redis, session_id.as_ref(), None).await;
+        let form = super::FormFields {
+            return_to: None,
+            nonce: nonce.as_ref(),
+        };
+        let mut page = super::render_captcha_page(&challenge, threat_level, form, None, None, None);
+        if let Some(cookie) = cookie {
+            page.headers_mut().insert(header::SET_COOKIE, cookie);
+        }
+        (limits, page).into_response()
+    }
+}
+
+/// Serve a stored challenge's image on its own (`captcha.serve_images`)
+///
+/// Browsers are told not to keep it; `X-Accel-Expires` lets Nginx cache it
+/// for the challenge's lifetime instead, so refetches never reach Fortify.
+pub async fn get_image(
+    State(state): State<AppState>,
+    Path(challenge_id): Path<ChallengeId>,
+) -> Response {
+    let Some(mut redis) = state.redis() else {
+        // Offline challenges are always inlined
+        return (StatusCode::NOT_FOUND, "Challenge expired or invalid").into_response();
+    };
+    let data_uri = match state
+        .captcha_generator
+        .image(&mut redis, &challenge_id)
         .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-
-    Ok(Json(ChallengeResponse {
-        challenge_id: challenge.challenge_id,
-        image_data: challenge.image_data,
-        grid_size: challenge.grid_size,
-        instructions: challenge.instructions,
-        expires_in_secs: difficulty.timeout_secs(),
-    }))
+    {
+        Ok(Some(data_uri)) => data_uri,
+        Ok(None) => return (StatusCode::NOT_FOUND, "Challenge expired or invalid").into_response(),
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    };
+    let Some((mime, bytes)) = decode_data_uri(&data_uri) else {
+        tracing::warn!(challenge_id = %challenge_id, "Stored challenge image is not a data URI");
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+    let headers = [
+        (header::CONTENT_TYPE, mime.to_string()),
+        (header::CACHE_CONTROL, "no-store, private".to_string()),
+        (
+            HeaderName::from_static("x-accel-expires"),
+            state.config.captcha.challenge_ttl_secs.secs().to_string(),
+        ),
+    ];
+    (headers, bytes).into_response()
+}
+
+/// Split a base64 `data:` URI into its media type and bytes
+fn decode_data_uri(uri: &str) -> Option<(&str, Vec<u8>)> {
+    let (mime, data) = uri.strip_prefix("data:")?.split_once(";base64,")?;
+    Some((mime, BASE64.decode(data).ok()?))
 }
 
 #[derive(Deserialize)]
 pub struct VerifyRequest {
-    pub challenge_id: String,
+    pub challenge_id: ChallengeId,
     /// User's answer (text input for MVP)
     pub answer: String,
-    /// Circuit ID for tracking
-    pub circuit_id: Option<String>,
+    /// Older clients send their circuit here; it must match `X-Circuit-Id`
+    #[serde(default)]
+    pub circuit_id: Option<CircuitId>,
+    /// Shadow trial puzzle that came with the challenge, and its answer
+    #[serde(default)]
+    pub shadow_id: Option<ChallengeId>,
+    #[serde(default)]
+    pub shadow_answer: Option<String>,
 }
 
-/// Verify a CAPTCHA response
+/// Verify a CAPTCHA response (JSON API)
+///
+/// The circuit is the one in the `X-Circuit-Id` header, which only the
+/// trusted proxy can set; a payload naming another circuit is refused.
 pub async fn verify_challenge(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(payload): Json<VerifyRequest>,
-) -> Result<Json<CaptchaResult>, (StatusCode, String)> {
-    let mut redis = state.redis.clone();
+) -> Result<(RateLimitHeaders, Json<CaptchaResult>), Response> {
+    let circuit_id = super::circuit_id_from_headers(&headers);
+    if payload
+        .circuit_id
+        .as_ref()
+        .is_some_and(|claimed| Some(claimed) != circuit_id.as_ref())
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "circuit_id does not match the request's circuit",
+        )
+            .into_response());
+    }
+
+    let request = VerificationRequest {
+        challenge_id: &payload.challenge_id,
+        answer: &payload.answer,
+        circuit_id: circuit_id.as_ref(),
+        threat_level: state.get_threat_level().await,
+    };
+
+    let Some(mut redis) = state.redis() else {
+        let result = state
+            .verification
+            .verify(None, request)
+            .await
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response())?;
+        return Ok((RateLimitHeaders::default(), Json(result)));
+    };
 
     // Check if circuit is allowed
-    if let Some(ref circuit_id) = payload.circuit_id {
+    if let Some(ref circuit_id) = circuit_id {
         let (allowed, reason) = state
             .circuit_tracker
             .is_allowed(&mut redis, circuit_id)
             .await
-            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response())?;
 
         if !allowed {
             return Err((
                 StatusCode::FORBIDDEN,
                 reason.unwrap_or_else(|| "Access denied".to_string()),
-            ));
+            )
+                .into_response());
         }
     }
 
+    let limits = rate_limit::check(&state, &mut redis, circuit_id.as_ref())
+        .await
+        .map_err(IntoResponse::into_response)?;
+    if !limits.allowed() {
+        return Err(limits.too_many_requests());
+    }
+
     let result = state
-        .captcha_verifier
-        .verify(
-            &mut redis,
-            &payload.challenge_id,
-            &payload.answer,
-            payload.circuit_id.as_deref(),
-        )
+        .verification
+        .verify(Some(&mut redis), request)
         .await
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-
-    // Update circuit state
-    if let Some(ref circuit_id) = payload.circuit_id {
-        if result.success {
-            if let Some(ref token) = result.passport_token {
-                let expires =
-                    chrono::Utc::now().timestamp() + state.config.captcha.passport_ttl_secs as i64;
-                let _ = state
-                    .circuit_tracker
-                    .record_success(&mut redis, circuit_id, token, expires)
-                    .await;
-            }
-        } else {
-            let _ = state
-                .circuit_tracker
-                .record_failure(&mut redis, circuit_id)
-                .await;
-        }
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response())?;
+    super::record_shadow(
+        &state,
+        payload.shadow_id.as_ref(),
+        payload.shadow_answer.as_deref(),
+        result.success,
+    )
+    .await;
+
+    Ok((limits, Json(result)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::captcha::{AmmoBox, AmmoBoxConfig};
+    use crate::config::AppConfig;
+    use crate::supervisor::Supervisor;
+    use axum::body::Body;
+    use axum::http::Request;
+    use cerberus_common::EventBus;
+    use cerberus_common::constants::headers::X_CIRCUIT_ID;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    /// Fortify with no Redis to reach (offline mode, sealed challenges)
+    ///
+    /// Run with paused time: the connection manager backs off for minutes
+    /// before giving up.
+    async fn offline_state() -> AppState {
+        let config = AppConfig {
+            redis_url: "redis+unix:///nonexistent/redis.sock".to_string(),
+            ..Default::default()
+        };
+        let events = Arc::new(EventBus::new());
+        let (shutdown, _) = tokio::sync::broadcast::channel(1);
+        let supervisor = Arc::new(Supervisor::new(events.clone(), shutdown));
+        let ammo_box = Arc::new(AmmoBox::new(AmmoBoxConfig::default()));
+        AppState::new(config, ammo_box, events, supervisor)
+            .await
+            .unwrap()
     }
 
-    Ok(Json(result))
+    fn verify_request(challenge_id: &str, circuit_id: &str) -> Request<Body> {
+        let body = serde_json::json!({
+            "challenge_id": challenge_id,
+            "answer": "AB12C",
+            "circuit_id": circuit_id,
+        });
+        Request::post("/verify")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(X_CIRCUIT_ID, "circuit-a")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_verify_circuit_comes_from_header() {
+        let state = offline_state().await;
+        let expires_at = chrono::Utc::now().timestamp() + 60;
+        let challenge_id = state.sealer.seal("AB12C", false, expires_at);
+        let app = axum::Router::new()
+            .route("/verify", axum::routing::post(super::super::verify))
+            .with_state(state);
+
+        // A payload naming another circuit can't claim the solve for it
+        let response = app
+            .clone()
+            .oneshot(verify_request(&challenge_id, "circuit-b"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = app
+            .oneshot(verify_request(&challenge_id, "circuit-a"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let result: CaptchaResult = serde_json::from_slice(&body).unwrap();
+        assert!(result.success);
+    }
+
+    #[test]
+    fn test_decode_data_uri() {
+        let (mime, bytes) = decode_data_uri("data:image/svg+xml;base64,PHN2Zy8+").unwrap();
+        assert_eq!(mime, "image/svg+xml");
+        assert_eq!(bytes, b"<svg/>");
+
+        assert!(decode_data_uri("/challenge/abc/image").is_none());
+        assert!(decode_data_uri("data:image/png;base64,!!").is_none());
+    }
 }
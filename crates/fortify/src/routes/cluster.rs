@@ -0,0 +1,75 @@
+//! Admin view of the cluster as seen from this node.
+
+use axum::{Json, extract::State, http::StatusCode};
+use serde::Serialize;
+
+use crate::state::AppState;
+
+#[derive(Serialize)]
+pub struct ClusterStatus {
+    pub node_id: String,
+    /// Holds the leader lease
+    pub leader: bool,
+    /// Cut off from too many peers (`gossip.isolation_threshold`)
+    pub isolated: bool,
+    pub peers: Vec<PeerStatus>,
+}
+
+#[derive(Serialize)]
+pub struct PeerStatus {
+    pub node_id: String,
+    pub healthy: bool,
+    /// Seconds since its last gossip packet
+    pub last_seen_secs: u64,
+    pub cpu_load: u8,
+    pub threat_level: u8,
+    pub version: String,
+    /// Healthy/unhealthy transitions within `gossip.flap_window_secs`
+    pub flaps: usize,
+    /// Excluded from load shedding
+    pub flapping: bool,
+    /// Being drained for removal (also excluded from load shedding)
+    pub draining: bool,
+    pub redis_reachable: bool,
+    pub redis_latency_ms: u32,
+    pub disk_free_mb: Option<u64>,
+    /// `/validate` p99 over its last gossip interval
+    pub validate_p99_ms: Option<u32>,
+}
+
+/// Peers known through gossip, by node ID (503 unless `cluster_enabled`)
+pub async fn get_cluster(State(state): State<AppState>) -> Result<Json<ClusterStatus>, StatusCode> {
+    let gossip = state
+        .gossip
+        .as_ref()
+        .ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+
+    let mut peers: Vec<_> = gossip
+        .get_peers()
+        .await
+        .into_iter()
+        .map(|(node_id, health)| PeerStatus {
+            node_id,
+            healthy: health.is_healthy,
+            last_seen_secs: health.last_seen.elapsed().as_secs(),
+            cpu_load: health.last_packet.cpu_load,
+            threat_level: health.last_packet.threat_level,
+            version: health.last_packet.version.clone(),
+            flaps: health.flaps(),
+            flapping: health.flapping,
+            draining: health.last_packet.draining,
+            redis_reachable: health.last_packet.redis_reachable,
+            redis_latency_ms: health.last_packet.redis_latency_ms,
+            disk_free_mb: health.last_packet.disk_free_mb,
+            validate_p99_ms: health.last_packet.validate_p99_ms,
+        })
+        .collect();
+    peers.sort_by(|a, b| a.node_id.cmp(&b.node_id));
+
+    Ok(Json(ClusterStatus {
+        node_id: state.node_id.clone(),
+        leader: state.election.as_ref().is_some_and(|e| e.is_leader()),
+        isolated: gossip.is_isolated().await,
+        peers,
+    }))
+}
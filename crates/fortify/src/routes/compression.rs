@@ -0,0 +1,170 @@
+//! gzip response compression.
+//!
+//! The gate page (with its inline SVG challenge) is several KB, and Tor
+//! circuits are slow, so text responses are gzipped for clients that accept
+//! it. Bodies under `min_size_bytes`, streamed bodies, and responses already
+//! encoded are sent as they are. Brotli would squeeze a little more, but
+//! there is no Brotli encoder in our dependency tree; gzip is accepted by
+//! every browser, Tor Browser included.
+//!
+//! Compressing a page that mixes secrets with attacker-chosen text can leak
+//! the secrets through the compressed size (BREACH). Gate pages hold no
+//! long-lived secret (challenge IDs are single-use), but operators can turn
+//! compression off with `compression.enabled = false`.
+
+use axum::{
+    body::{Body, HttpBody},
+    extract::{Request, State},
+    http::{HeaderMap, HeaderValue, header},
+    middleware::Next,
+    response::Response,
+};
+use flate2::{Compression, write::GzEncoder};
+use std::io::Write;
+
+use crate::config::CompressionConfig;
+
+/// Compress the response if the client and the content allow it
+pub async fn compress(
+    State(config): State<CompressionConfig>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let accepts_gzip = accepts_gzip(request.headers());
+    let response = next.run(request).await;
+    if !accepts_gzip || !compressible(&response, config.min_size_bytes) {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to read response body for compression");
+            return Response::from_parts(parts, Body::empty());
+        }
+    };
+    let compressed = match gzip(&bytes, config.level) {
+        Ok(compressed) if compressed.len() < bytes.len() => compressed,
+        _ => return Response::from_parts(parts, Body::from(bytes)),
+    };
+
+    let headers = &mut parts.headers;
+    headers.insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+    headers.remove(header::CONTENT_LENGTH);
+    headers.append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    // Same content, different bytes: the validator is only weakly equal now
+    if let Some(etag) = headers.get(header::ETAG)
+        && !etag.as_bytes().starts_with(b"W/")
+        && let Ok(weak) = HeaderValue::from_bytes(&[b"W/", etag.as_bytes()].concat())
+    {
+        headers.insert(header::ETAG, weak);
+    }
+    Response::from_parts(parts, Body::from(compressed))
+}
+
+/// Does `Accept-Encoding` allow gzip (with a non-zero q-value)?
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|coding| {
+            let mut params = coding.split(';').map(str::trim);
+            let name = params.next().unwrap_or_default();
+            let refused = params.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q <= 0.0)
+            });
+            (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+        })
+}
+
+/// Text content of a known size, at least `min_size` bytes, not yet encoded
+fn compressible(response: &Response, min_size: usize) -> bool {
+    let headers = response.headers();
+    if headers.contains_key(header::CONTENT_ENCODING) {
+        return false;
+    }
+    let textual = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| {
+            let mime = content_type.split(';').next().unwrap_or_default().trim();
+            (mime.starts_with("text/") && mime != "text/event-stream")
+                || mime == "application/json"
+                || mime == "image/svg+xml"
+        });
+    let size = response.body().size_hint().exact();
+    textual && size.is_some_and(|size| size >= min_size as u64)
+}
+
+fn gzip(bytes: &[u8], level: u32) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::new(level));
+    encoder.write_all(bytes)?;
+    encoder.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, routing::get};
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_accept_encoding() {
+        let accepts = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::ACCEPT_ENCODING, value.parse().unwrap());
+            accepts_gzip(&headers)
+        };
+        assert!(accepts("gzip, deflate, br"));
+        assert!(accepts("br;q=1.0, GZIP;q=0.5"));
+        assert!(accepts("*"));
+        assert!(!accepts("br, deflate"));
+        assert!(!accepts("gzip;q=0"));
+        assert!(!accepts("identity"));
+    }
+
+    #[tokio::test]
+    async fn test_compresses_large_text_only() {
+        let page = "<p>Verification required</p>".repeat(100);
+        let app = Router::new()
+            .route(
+                "/page",
+                get(move || async move { axum::response::Html(page) }),
+            )
+            .route(
+                "/small",
+                get(|| async { axum::response::Html("<p>ok</p>") }),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                CompressionConfig::default(),
+                compress,
+            ));
+        let get = |uri: &str| {
+            Request::get(uri)
+                .header(header::ACCEPT_ENCODING, "gzip")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(get("/page")).await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+        assert_eq!(response.headers()[header::VARY], "accept-encoding");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let mut html = String::new();
+        GzDecoder::new(&body[..]).read_to_string(&mut html).unwrap();
+        assert!(html.starts_with("<p>Verification required</p>"));
+
+        let response = app.oneshot(get("/small")).await.unwrap();
+        assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+    }
+}
@@ -0,0 +1,105 @@
+//! Admin drain switch, and the guard that stops new challenges.
+
+use axum::{
+    Json,
+    extract::{Request, State},
+    http::{Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use cerberus_common::{CerberusEvent, EventPublisher};
+use serde::{Deserialize, Serialize};
+
+use super::render_retry_page;
+use crate::drain::{AfterDrain, drain_worker};
+use crate::state::AppState;
+
+/// Routes that hand out new challenges
+const ISSUING_PATHS: [&str; 4] = ["/", "/captcha.html", "/gate/fragment", "/challenge"];
+
+/// `Retry-After` while draining; a retry goes through the load balancer,
+/// which will have picked another node by then
+const RETRY_AFTER_SECS: u64 = 5;
+
+/// Refuse to issue challenges while the node drains
+pub async fn guard(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let path = request.uri().path();
+    if !state.drain.is_draining()
+        || request.method() != Method::GET
+        || !ISSUING_PATHS.contains(&path)
+    {
+        return next.run(request).await;
+    }
+
+    if path == "/challenge" {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, RETRY_AFTER_SECS)],
+            "Node is draining",
+        )
+            .into_response()
+    } else {
+        render_retry_page(RETRY_AFTER_SECS)
+    }
+}
+
+#[derive(Serialize)]
+pub struct DrainStatus {
+    pub draining: bool,
+    /// When the drain started (Unix seconds)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since: Option<i64>,
+    /// Challenges issued before the drain have expired
+    pub complete: bool,
+    pub after: AfterDrain,
+}
+
+fn status(state: &AppState) -> DrainStatus {
+    DrainStatus {
+        draining: state.drain.is_draining(),
+        since: state.drain.since(),
+        complete: state.drain.is_complete(),
+        after: state.drain.after(),
+    }
+}
+
+pub async fn get_drain(State(state): State<AppState>) -> Json<DrainStatus> {
+    Json(status(&state))
+}
+
+#[derive(Deserialize)]
+pub struct DrainRequest {
+    /// `exit` or `standby` (the default) once drained
+    #[serde(default)]
+    pub after: AfterDrain,
+}
+
+/// Start draining this node
+pub async fn start_drain(
+    State(state): State<AppState>,
+    Json(request): Json<DrainRequest>,
+) -> Json<DrainStatus> {
+    if let Some(generation) = state.drain.start(request.after) {
+        tracing::warn!(
+            after = ?request.after,
+            wait_secs = state.config.captcha.challenge_ttl_secs.secs(),
+            "🚰 Draining: no new challenges"
+        );
+        state
+            .events
+            .publish(CerberusEvent::DrainChanged { draining: true });
+        tokio::spawn(drain_worker(state.clone(), generation));
+    }
+    Json(status(&state))
+}
+
+/// Cancel a drain (before an `exit` drain completes)
+pub async fn cancel_drain(State(state): State<AppState>) -> Json<DrainStatus> {
+    if state.drain.cancel() {
+        tracing::warn!("🚰 Drain cancelled; issuing challenges again");
+        state
+            .events
+            .publish(CerberusEvent::DrainChanged { draining: false });
+    }
+    Json(status(&state))
+}
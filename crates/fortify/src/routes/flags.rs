@@ -0,0 +1,86 @@
+//! Admin toggles for feature flags.
+
+use axum::{
+    Json,
+    extract::{Path, Query, State},
+    http::StatusCode,
+};
+use cerberus_common::{CerberusEvent, EventPublisher};
+use serde::Deserialize;
+
+use crate::flags::{Flag, FlagStatus};
+use crate::state::AppState;
+
+/// Every flag as in force on this node, with the overrides behind it
+pub async fn list_flags(State(state): State<AppState>) -> Json<Vec<FlagStatus>> {
+    Json(state.flags.report())
+}
+
+#[derive(Deserialize)]
+pub struct FlagRequest {
+    pub enabled: bool,
+    /// Node to override it for (default: the whole cluster)
+    pub node_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct FlagScope {
+    /// Node whose override to clear (default: the cluster's)
+    pub node_id: Option<String>,
+}
+
+/// Set a flag for the cluster, or for one node
+///
+/// Returns this node's flags (in force at once here, on other nodes within
+/// `flags.refresh_secs`). 400 for an empty node ID, 503 while Redis is
+/// offline.
+pub async fn set_flag(
+    State(state): State<AppState>,
+    Path(flag): Path<Flag>,
+    Json(request): Json<FlagRequest>,
+) -> Result<Json<Vec<FlagStatus>>, StatusCode> {
+    change(&state, flag, request.node_id, Some(request.enabled)).await
+}
+
+/// Clear the cluster's or a node's override of a flag
+pub async fn clear_flag(
+    State(state): State<AppState>,
+    Path(flag): Path<Flag>,
+    Query(scope): Query<FlagScope>,
+) -> Result<Json<Vec<FlagStatus>>, StatusCode> {
+    change(&state, flag, scope.node_id, None).await
+}
+
+async fn change(
+    state: &AppState,
+    flag: Flag,
+    node_id: Option<String>,
+    enabled: Option<bool>,
+) -> Result<Json<Vec<FlagStatus>>, StatusCode> {
+    if node_id.as_deref().is_some_and(str::is_empty) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let mut redis = state.redis().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+
+    state
+        .flags
+        .set(&mut redis, flag, node_id.as_deref(), enabled)
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, flag = flag.as_str(), "Failed to set feature flag");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    tracing::warn!(
+        flag = flag.as_str(),
+        enabled = ?enabled,
+        node_id = ?node_id,
+        "Feature flag override changed by admin"
+    );
+    state.events.publish(CerberusEvent::FeatureFlagChanged {
+        flag: flag.as_str().to_string(),
+        enabled,
+        node_id,
+    });
+
+    Ok(Json(state.flags.report()))
+}
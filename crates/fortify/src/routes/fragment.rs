@@ -0,0 +1,172 @@
+//! Embeddable gate: just the CAPTCHA form, as an HTML fragment.
+//!
+//! Lets site owners put the gate inside their own branded pages (e.g. an
+//! Nginx SSI include in a custom error page) instead of redirecting to the
+//! Fortify-styled page. The fragment brings no styles of its own; its
+//! elements carry `cerberus-*` classes for the host page to style. The form
+//! posts to `/verify` like the full page does.
+
+use axum::{
+    Json,
+    extract::State,
+    http::{HeaderMap, StatusCode, header},
+    response::{Html, IntoResponse, Response},
+};
+use cerberus_common::{CaptchaChallenge, ChallengeId};
+use serde::Serialize;
+
+use super::{html_escape, rate_limit};
+use crate::state::AppState;
+
+/// `GET /gate/fragment` as JSON (`Accept: application/json`)
+#[derive(Serialize)]
+pub struct FragmentResponse {
+    pub challenge_id: ChallengeId,
+    pub expires_in_secs: u32,
+    /// The form markup
+    pub html: String,
+}
+
+/// Issue a challenge and return its form as an HTML fragment
+///
+/// Same checks as `GET /challenge`: banned circuits get 403 and rate limits
+/// apply. Never cached, since every response carries a new challenge.
+pub async fn serve_fragment(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let circuit_id = super::circuit_id_from_headers(&headers);
+    let format = super::image_format(&state, &headers);
+    let json = super::wants_json(&headers);
+    let difficulty = state.get_threat_level().await.captcha_difficulty();
+
+    let Some(mut redis) = state.redis() else {
+        // Offline: sealed challenge verified without Redis
+        let challenge = state
+            .captcha_generator
+            .generate_stateless(difficulty, format);
+        return respond(&challenge, json).into_response();
+    };
+
+    if let Some(ref circuit_id) = circuit_id {
+        match state
+            .circuit_tracker
+            .is_allowed(&mut redis, circuit_id)
+            .await
+        {
+            Ok((true, _)) => {}
+            Ok((false, reason)) => {
+                let reason = reason.unwrap_or_else(|| "Access denied".to_string());
+                return (StatusCode::FORBIDDEN, no_store(), denied_fragment(&reason))
+                    .into_response();
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "Circuit check failed");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    }
+
+    let limits = match rate_limit::check(&state, &mut redis, circuit_id.as_ref()).await {
+        Ok(limits) => limits,
+        Err(response) => return response.into_response(),
+    };
+    if !limits.allowed() {
+        return limits.too_many_requests();
+    }
+
+    match state
+        .captcha_generator
+        .generate(&mut redis, circuit_id, difficulty, format)
+        .await
+    {
+        Ok(challenge) => (limits, respond(&challenge, json)).into_response(),
+        Err(e) => (
+            limits,
+            super::generation_error(&state, e, "Failed to generate challenge", false),
+        )
+            .into_response(),
+    }
+}
+
+fn respond(challenge: &CaptchaChallenge, json: bool) -> Response {
+    let html = render_fragment(challenge);
+    if json {
+        return (
+            no_store(),
+            Json(FragmentResponse {
+                challenge_id: challenge.challenge_id.clone(),
+                expires_in_secs: super::expires_in_secs(challenge.expires_at),
+                html,
+            }),
+        )
+            .into_response();
+    }
+    (no_store(), Html(html)).into_response()
+}
+
+fn no_store() -> [(header::HeaderName, &'static str); 1] {
+    [(header::CACHE_CONTROL, "no-store")]
+}
+
+/// The CAPTCHA form for `challenge`
+fn render_fragment(challenge: &CaptchaChallenge) -> String {
+    format!(
+        r##"<div class="cerberus-gate">
+    <form method="POST" action="/verify">
+        <input type="hidden" name="challenge_id" value="{challenge_id}">
+        <div class="cerberus-captcha">
+            {image_html}
+        </div>
+        <p class="cerberus-instructions">{instructions}</p>
+        <input type="text"
+               class="cerberus-answer"
+               name="answer"
+               placeholder="Enter code"
+               autocomplete="off"
+               autocapitalize="off"
+               spellcheck="false"
+               maxlength="8"
+               required>
+        <button type="submit" class="cerberus-submit">Verify</button>
+        <button type="submit"
+                class="cerberus-refresh"
+                formaction="/challenge/{challenge_id}/refresh"
+                formnovalidate>↻ New Challenge</button>
+    </form>
+</div>"##,
+        challenge_id = html_escape(challenge.challenge_id.as_str()),
+        image_html = super::captcha_image_html(challenge),
+        instructions = html_escape(&challenge.instructions),
+    )
+}
+
+/// Shown in place of the form to a banned or soft-locked circuit
+fn denied_fragment(reason: &str) -> Html<String> {
+    Html(format!(
+        r#"<div class="cerberus-gate"><p class="cerberus-denied">{}</p></div>"#,
+        html_escape(reason)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_fragment() {
+        let challenge = CaptchaChallenge {
+            challenge_id: "abc123".parse().unwrap(),
+            image_data: "data:image/png;base64,AAAA".to_string(),
+            grid_size: (0, 0),
+            instructions: "Type the <5> characters".to_string(),
+            expected_positions: vec![],
+            expires_at: 0,
+        };
+        let html = render_fragment(&challenge);
+
+        assert!(html.starts_with(r#"<div class="cerberus-gate">"#));
+        assert!(!html.contains("<html") && !html.contains("<style"));
+        assert!(html.contains(r#"name="challenge_id" value="abc123""#));
+        assert!(html.contains(r#"formaction="/challenge/abc123/refresh""#));
+        assert!(html.contains(r#"<img src="data:image/png;base64,AAAA""#));
+        assert!(html.contains("Type the &lt;5&gt; characters"));
+    }
+}
@@ -0,0 +1,113 @@
+//! Admin view of the HAProxy circuit stick table.
+
+use axum::{
+    Json,
+    extract::{Query, State},
+    http::StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::haproxy::{HaproxyApi, HaproxyCircuitStatus, StickTableEntry};
+use crate::state::AppState;
+
+/// Largest page `GET /admin/haproxy/table` returns
+const MAX_PAGE: usize = 1000;
+
+#[derive(Deserialize)]
+pub struct TableQuery {
+    /// Only entries with this status (`normal`, `vip`, `banned`)
+    pub status: Option<HaproxyCircuitStatus>,
+    /// Entries to skip
+    #[serde(default)]
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct TablePage {
+    pub table: String,
+    pub entries: Vec<StickTableEntry>,
+    /// Offset of the next page, if there is one
+    pub next_offset: Option<usize>,
+}
+
+/// List stick table entries, a page at a time
+pub async fn list_table(
+    State(state): State<AppState>,
+    Query(params): Query<TableQuery>,
+) -> Result<Json<TablePage>, StatusCode> {
+    let haproxy = available(&state).await?;
+    let limit = params.limit.unwrap_or(100).min(MAX_PAGE);
+
+    // One more than asked for, to know whether another page follows
+    let mut entries: Vec<_> = haproxy
+        .iter_table(params.status)
+        .await
+        .map_err(internal_error)?
+        .skip(params.offset)
+        .take(limit + 1)
+        .collect();
+    let next_offset = (entries.len() > limit).then(|| params.offset + limit);
+    entries.truncate(limit);
+
+    Ok(Json(TablePage {
+        table: haproxy.stick_table().to_string(),
+        entries,
+        next_offset,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct ClearQuery {
+    /// Only clear entries with this status
+    pub status: Option<HaproxyCircuitStatus>,
+    /// Required to clear the whole table (no `status`)
+    #[serde(default)]
+    pub all: bool,
+}
+
+#[derive(Serialize)]
+pub struct ClearResponse {
+    /// Entries matching the filter just before clearing
+    pub cleared: usize,
+}
+
+/// Bulk-clear stick table entries
+///
+/// Clearing banned entries lifts the bans at HAProxy (Redis still has them).
+pub async fn clear_table(
+    State(state): State<AppState>,
+    Query(params): Query<ClearQuery>,
+) -> Result<Json<ClearResponse>, StatusCode> {
+    if params.status.is_none() && !params.all {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let haproxy = available(&state).await?;
+
+    let cleared = haproxy
+        .iter_table(params.status)
+        .await
+        .map_err(internal_error)?
+        .count();
+    haproxy
+        .clear_table(params.status)
+        .await
+        .map_err(internal_error)?;
+
+    tracing::warn!(status = ?params.status, cleared, "HAProxy stick table cleared by admin");
+    Ok(Json(ClearResponse { cleared }))
+}
+
+/// The runtime API, if enabled and its socket is there
+async fn available(state: &AppState) -> Result<Arc<HaproxyApi>, StatusCode> {
+    match state.haproxy.clone() {
+        Some(haproxy) if haproxy.is_available().await => Ok(haproxy),
+        _ => Err(StatusCode::SERVICE_UNAVAILABLE),
+    }
+}
+
+fn internal_error(e: anyhow::Error) -> StatusCode {
+    tracing::error!(error = %e, "HAProxy runtime API request failed");
+    StatusCode::INTERNAL_SERVER_ERROR
+}
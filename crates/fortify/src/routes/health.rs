@@ -1,8 +1,14 @@
 //! Health check endpoints.
 
-use axum::{Json, extract::State, http::StatusCode};
+use axum::{
+    Json,
+    extract::State,
+    http::{StatusCode, header},
+    response::IntoResponse,
+};
 use serde::Serialize;
 
+use crate::degradation::DegradationLevel;
 use crate::state::AppState;
 
 #[derive(Serialize)]
@@ -23,17 +29,25 @@ pub async fn health_check() -> Json<HealthResponse> {
 pub struct ReadyResponse {
     status: &'static str,
     redis: bool,
+    degradation: DegradationLevel,
+    ammo_fill_percent: u8,
 }
 
 /// Readiness check (are all dependencies healthy?)
+///
+/// Not ready until the Ammo Box has finished its startup warm-up, so the
+/// first burst of traffic isn't served by on-demand generation, nor while
+/// the node drains.
 pub async fn ready_check(State(state): State<AppState>) -> Result<Json<ReadyResponse>, StatusCode> {
     // Check Redis connectivity
     let redis_ok = check_redis(&state).await;
 
-    if redis_ok {
+    if redis_ok && state.ammo_box.is_warm() && !state.drain.is_draining() {
         Ok(Json(ReadyResponse {
             status: "ready",
             redis: true,
+            degradation: state.degradation.level(),
+            ammo_fill_percent: state.ammo_box.fill_percent(),
         }))
     } else {
         // Return 503 if not ready
@@ -42,7 +56,9 @@ pub async fn ready_check(State(state): State<AppState>) -> Result<Json<ReadyResp
 }
 
 async fn check_redis(state: &AppState) -> bool {
-    let mut conn = state.redis.clone();
+    let Some(mut conn) = state.redis() else {
+        return false;
+    };
     let result: Result<String, _> = redis::cmd("PING").query_async(&mut conn).await;
     result.is_ok()
 }
@@ -51,8 +67,16 @@ async fn check_redis(state: &AppState) -> bool {
 pub struct MetricsResponse {
     node_id: String,
     threat_level: u8,
-    // Prometheus-compatible metrics would go here
-    // For now, just basic stats
+    degradation: DegradationLevel,
+    // Latency histograms are at /metrics/prometheus
+}
+
+/// Prometheus scrape endpoint (latency histograms)
+pub async fn prometheus() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        crate::metrics::render(),
+    )
 }
 
 /// Metrics endpoint (for monitoring)
@@ -62,5 +86,6 @@ pub async fn metrics(State(state): State<AppState>) -> Json<MetricsResponse> {
     Json(MetricsResponse {
         node_id: state.node_id.clone(),
         threat_level: level.value(),
+        degradation: state.degradation.level(),
     })
 }
@@ -0,0 +1,145 @@
+//! Maintenance page and its admin switch.
+
+use axum::{
+    Json,
+    extract::{Request, State},
+    http::{StatusCode, header},
+    middleware::Next,
+    response::{Html, IntoResponse, Response},
+};
+use cerberus_common::{CerberusEvent, EventPublisher};
+use serde::{Deserialize, Serialize};
+
+use super::{html_escape, theme::THEME_PATH};
+use crate::state::AppState;
+
+/// Routes that keep answering during maintenance (monitoring, and the
+/// stylesheet the page links)
+const EXEMPT_PATHS: [&str; 5] = [
+    "/health",
+    "/ready",
+    "/metrics",
+    "/metrics/prometheus",
+    THEME_PATH,
+];
+
+/// Serve the maintenance page instead of the route while maintenance is on
+pub async fn guard(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if !state.maintenance.is_active() || EXEMPT_PATHS.contains(&request.uri().path()) {
+        return next.run(request).await;
+    }
+
+    let page = match state.maintenance.custom_page() {
+        Some(page) => page.to_string(),
+        None => render_page(&state.config.maintenance.message),
+    };
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::RETRY_AFTER, state.maintenance.retry_after_secs())],
+        Html(page),
+    )
+        .into_response()
+}
+
+/// The built-in page, in the gate's theme
+fn render_page(message: &str) -> String {
+    format!(
+        r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Sigil - Maintenance</title>
+    <link rel="stylesheet" href="{theme}">
+</head>
+<body>
+    <div class="container">
+        <div class="brand">
+            <span class="brand-logo">🔧</span>
+            <div class="brand-text">
+                <h1>Sigil</h1>
+                <p class="subtitle">Down for maintenance</p>
+            </div>
+        </div>
+        <p class="instructions">{message}</p>
+        <div class="footer">
+            Protected by Cerberus
+        </div>
+    </div>
+</body>
+</html>"##,
+        theme = THEME_PATH,
+        message = html_escape(message),
+    )
+}
+
+#[derive(Serialize)]
+pub struct MaintenanceStatus {
+    pub enabled: bool,
+    /// When maintenance started (Unix seconds)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since: Option<i64>,
+    pub retry_after_secs: u64,
+}
+
+fn status(state: &AppState) -> MaintenanceStatus {
+    MaintenanceStatus {
+        enabled: state.maintenance.is_active(),
+        since: state.maintenance.since(),
+        retry_after_secs: state.maintenance.retry_after_secs(),
+    }
+}
+
+pub async fn get_maintenance(State(state): State<AppState>) -> Json<MaintenanceStatus> {
+    Json(status(&state))
+}
+
+#[derive(Deserialize)]
+pub struct MaintenanceRequest {
+    pub enabled: bool,
+    /// Replaces `maintenance.retry_after_secs` from now on
+    pub retry_after_secs: Option<u64>,
+}
+
+/// Switch maintenance on or off, here and in HAProxy
+///
+/// Returns 502 if HAProxy could not be told; the switch has still been
+/// flipped on this node.
+pub async fn set_maintenance(
+    State(state): State<AppState>,
+    Json(request): Json<MaintenanceRequest>,
+) -> Result<Json<MaintenanceStatus>, StatusCode> {
+    if state
+        .maintenance
+        .set(request.enabled, request.retry_after_secs)
+    {
+        tracing::warn!(
+            enabled = request.enabled,
+            "Maintenance mode changed by admin"
+        );
+        state.events.publish(CerberusEvent::MaintenanceChanged {
+            enabled: request.enabled,
+        });
+    }
+
+    if let Some(ref haproxy) = state.haproxy
+        && let Err(e) = haproxy.set_maintenance(request.enabled).await
+    {
+        tracing::error!(error = %e, "Failed to set the HAProxy maintenance flag");
+        return Err(StatusCode::BAD_GATEWAY);
+    }
+
+    Ok(Json(status(&state)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_escapes_message() {
+        let page = render_page("Back <soon> & better");
+        assert!(page.contains("Back &lt;soon&gt; &amp; better"));
+        assert!(page.contains(THEME_PATH));
+    }
+}
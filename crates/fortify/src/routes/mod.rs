@@ -2,45 +2,210 @@
 
 use axum::{
     Form, Json, Router,
-    extract::State,
-    http::StatusCode,
+    extract::{FromRequest, Query, Request, State},
+    http::{HeaderMap, HeaderValue, StatusCode, header},
     response::{Html, IntoResponse, Redirect, Response},
-    routing::{get, post},
+    routing::{delete, get, post},
 };
 use base64::{Engine as _, engine::general_purpose::STANDARD as BASE64};
+use cerberus_common::{
+    ChallengeId, CircuitId, GateFormNonce, GateSessionId, PassportToken, ThreatLevel,
+};
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tower_http::trace::TraceLayer;
 
+use crate::access_log;
+use crate::admin_access::{self, AdminAccess};
+use crate::captcha::{PowPuzzle, ShadowChallenge};
+use crate::circuits::{CircuitEvent, CircuitMutation, transfer};
+use crate::config::{ImageFormat, PowReward};
+use crate::gate_session::{self, FormSubmission, GateSession};
+use crate::metrics;
+use crate::node_info::NodeInfo;
+use crate::rules;
+use crate::sampling;
+use crate::slo;
 use crate::state::AppState;
+use crate::supervisor;
+use crate::tls;
+use crate::trusted_proxy::{self, TrustedProxies};
+use crate::verification::VerificationRequest;
+use crate::verify_queue;
+use theme::{FormFields, GateTemplate};
 
+mod allowlist;
+mod ammo;
 mod captcha;
+mod cluster;
+mod compression;
+mod drain;
+mod flags;
+mod fragment;
+mod haproxy;
 mod health;
+mod maintenance;
 mod passport;
+mod pow;
+mod rate_limit;
+mod route_metrics;
+mod security;
+mod theme;
+mod throttle;
+
+#[cfg(feature = "grpc")]
+pub use passport::revoke as revoke_passports;
 
 /// Create the main application router
-pub fn create_router(state: AppState) -> Router {
+///
+/// Routes are split into groups (gate pages, JSON API, admin) so each can
+/// carry its own security header / CORS policy.
+pub fn create_router(state: AppState) -> anyhow::Result<Router> {
+    let policies = &state.config.security_headers;
+
+    // A draining node hands out no new challenges
+    let stop_issuing = axum::middleware::from_fn_with_state(state.clone(), drain::guard);
+    // Circuits guessing answers are tarpitted
+    let throttle = axum::middleware::from_fn_with_state(state.clone(), throttle::guard);
+    let mut gate = security::apply(gate_routes(), &policies.gate)?;
+    // Floods of answers queue fairly, the most aggressive circuits shed first
+    if state.config.verify_queue.enabled {
+        gate = gate.layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            verify_queue::guard,
+        ));
+    }
+    let gate = gate.layer(throttle.clone()).layer(stop_issuing.clone());
+    let api = security::apply(api_routes(), &policies.api)?
+        .layer(throttle)
+        .layer(stop_issuing);
+    let mut validate = security::apply(validate_routes(), &policies.api)?;
+
+    // Visitor traffic feeds the attack signature rules
+    let observe = axum::middleware::from_fn_with_state(state.clone(), rules::observe_requests);
+    let gate = gate.layer(observe.clone());
+    validate = validate.layer(observe);
+    if state.config.sampling.enabled {
+        validate = validate.layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            sampling::sample_requests,
+        ));
+    }
+    if state.config.slo.enabled {
+        validate = validate.layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            slo::track_requests,
+        ));
+    }
+    let mut admin = security::apply(admin_routes(), &policies.admin)?;
+
+    // Control surfaces only answer proxies and peers holding a client cert
+    if state.config.tls.client_auth() {
+        validate = validate.layer(axum::middleware::from_fn(tls::require_client_cert));
+        admin = admin.layer(axum::middleware::from_fn(tls::require_client_cert));
+    }
+
+    let mut router = Router::new()
+        .merge(gate)
+        .merge(api)
+        .merge(validate)
+        // Everything but admin gives way to the maintenance page
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            maintenance::guard,
+        ));
+    // One JSON line per visitor request (admin requests aren't logged)
+    if state.config.access_log.enabled {
+        router = router.layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            access_log::log_requests,
+        ));
+    }
+    // Admin endpoints (under a secret path and/or for listed peers only)
+    if let Some(access) = AdminAccess::new(&state.config.admin) {
+        admin = admin.layer(axum::middleware::from_fn_with_state(
+            Arc::new(access),
+            admin_access::restrict,
+        ));
+    }
+    let admin_path = admin_access::mount_path(&state.config.admin)?;
+    if admin_path != admin_access::DEFAULT_PATH {
+        tracing::info!("🔒 Admin routes moved off {}", admin_access::DEFAULT_PATH);
+    }
+    router = router.nest(&admin_path, admin);
+
+    // gzip for slow circuits (gate pages are several KB)
+    if state.config.compression.enabled {
+        router = router.layer(axum::middleware::from_fn_with_state(
+            state.config.compression.clone(),
+            compression::compress,
+        ));
+    }
+
+    let trusted_proxies = axum::middleware::from_fn_with_state(
+        Arc::new(TrustedProxies::new(&state.config.trusted_proxies)),
+        trusted_proxy::strip_untrusted,
+    );
+    let route_labels = route_metrics::RouteLabels::new(&admin_path);
+    Ok(router
+        // Latency, status class and in-flight requests per route
+        .layer(axum::middleware::from_fn_with_state(
+            Arc::new(route_labels.clone()),
+            route_metrics::track,
+        ))
+        // Request spans (continue upstream traces when exporting), the
+        // admin mount path hidden
+        .layer(TraceLayer::new_for_http().make_span_with(route_labels))
+        // Proxy-set headers are only believed from a trusted hop
+        .layer(trusted_proxies)
+        // Add shared state
+        .with_state(state))
+}
+
+/// HTML gate pages (no JavaScript required)
+fn gate_routes() -> Router<AppState> {
     Router::new()
         // Static pages (serve CAPTCHA gate with embedded challenge)
         .route("/", get(serve_captcha_page))
         .route("/captcha.html", get(serve_captcha_page))
+        // Stylesheet, cached by browsers across challenges
+        .route(theme::THEME_PATH, get(theme::serve_theme))
+        // Optional proof-of-work (`captcha.flow`), and where it's answered
+        .route(theme::POW_SCRIPT_PATH, get(theme::serve_pow_script))
+        .route("/gate/pow", post(pow::solve_pow))
+        // Just the form, for embedding in the site's own pages
+        .route("/gate/fragment", get(fragment::serve_fragment))
+        // Verification - supports both JSON and form POST
+        .route("/verify", post(verify))
+        // Challenge image on its own, for Nginx to cache (`serve_images`)
+        .route("/challenge/{challenge_id}/image", get(captcha::get_image))
+        // Replace a challenge (form button or JSON clients)
+        .route(
+            "/challenge/{challenge_id}/refresh",
+            post(captcha::refresh_challenge),
+        )
+        // Protected backend (mock for testing)
+        .route("/app/", get(protected_app))
+        .route("/app/{*path}", get(protected_app))
+}
+
+/// JSON API and health endpoints
+fn api_routes() -> Router<AppState> {
+    Router::new()
         // Health & Status
         .route("/health", get(health::health_check))
         .route("/ready", get(health::ready_check))
         .route("/metrics", get(health::metrics))
+        .route("/metrics/prometheus", get(health::prometheus))
         // CAPTCHA endpoints (JSON API for JS-enabled clients)
         .route("/challenge", get(captcha::get_challenge))
-        // Verification - supports both JSON and form POST
-        .route("/verify", post(verify_form))
-        // Passport validation (for HAProxy/Nginx)
-        .route("/validate", get(passport::validate_passport))
-        // Protected backend (mock for testing)
-        .route("/app/", get(protected_app))
-        .route("/app/{*path}", get(protected_app))
         // Circuit info (for debugging/admin)
         .route("/circuit/{circuit_id}", get(get_circuit_info))
-        // Admin endpoints (protected by randomized path in production)
-        .nest("/admin", admin_routes())
-        // Add shared state
-        .with_state(state)
+}
+
+/// Passport validation (for HAProxy/Nginx; JSON API header policy)
+fn validate_routes() -> Router<AppState> {
+    Router::new().route("/validate", get(passport::validate_passport))
 }
 
 /// Admin routes (threat dial, circuit management, etc.)
@@ -50,23 +215,155 @@ fn admin_routes() -> Router<AppState> {
             "/threat-level",
             get(get_threat_level).post(set_threat_level),
         )
+        .route("/circuits/export", get(export_circuits))
+        .route(
+            "/circuits/import",
+            // Exports of a busy node run well past the default limit
+            post(import_circuits).layer(axum::extract::DefaultBodyLimit::disable()),
+        )
         .route(
             "/circuits/{circuit_id}",
             get(get_circuit_info).delete(ban_circuit),
         )
+        .route("/circuits/{circuit_id}/unban", post(unban_circuit))
+        .route("/circuits/{circuit_id}/honeypot", post(honeypot_hit))
+        .route(
+            "/circuits/{circuit_id}/passports",
+            get(passport::get_circuit_passports),
+        )
         .route("/stats", get(get_stats))
+        .route("/stats/difficulty", get(get_difficulty_stats))
+        .route("/ammo", get(ammo::get_ammo).delete(ammo::flush))
+        .route("/ammo/generate", post(ammo::generate))
+        .route("/ammo/dump", post(ammo::dump))
+        .route("/ammo/load", post(ammo::load))
+        .route("/cluster", get(cluster::get_cluster))
+        .route("/info", get(get_info))
+        .route("/farm/outliers", get(get_farm_outliers))
+        .route("/flags", get(flags::list_flags))
+        .route(
+            "/flags/{flag}",
+            post(flags::set_flag).delete(flags::clear_flag),
+        )
+        .route("/experiments", get(get_experiments))
+        .route("/journeys", get(get_journeys))
+        .route("/passports/inspect", post(passport::get_passport))
+        .route("/passports/revoke", post(passport::revoke_passport))
+        .route("/passports/keys", get(passport::get_keys))
+        .route("/passports/keys/finalize", post(passport::finalize_keys))
+        .route("/passports/journey", post(passport::get_journey))
+        .route("/samples", get(get_samples).delete(clear_samples))
+        .route("/slo", get(get_slo))
+        .route("/tasks", get(get_tasks))
+        .route(
+            "/allowlist",
+            get(allowlist::list_allowlist).post(allowlist::add_to_allowlist),
+        )
+        .route("/allowlist/{name}", delete(allowlist::remove_from_allowlist))
+        .route(
+            "/maintenance",
+            get(maintenance::get_maintenance).post(maintenance::set_maintenance),
+        )
+        .route(
+            "/drain",
+            get(drain::get_drain)
+                .post(drain::start_drain)
+                .delete(drain::cancel_drain),
+        )
+        .route(
+            "/haproxy/table",
+            get(haproxy::list_table).delete(haproxy::clear_table),
+        )
+}
+
+/// Extract the circuit ID set by HAProxy (if any)
+///
+/// A malformed value is treated as absent.
+pub(crate) fn circuit_id_from_headers(headers: &HeaderMap) -> Option<CircuitId> {
+    let value = headers
+        .get(cerberus_common::constants::headers::X_CIRCUIT_ID)?
+        .to_str()
+        .ok()?;
+    match value.parse() {
+        Ok(circuit_id) => Some(circuit_id),
+        Err(e) => {
+            if !value.is_empty() {
+                tracing::debug!(error = %e, "Ignoring malformed X-Circuit-Id");
+            }
+            None
+        }
+    }
+}
+
+/// Map a challenge generation error to a response
+///
+/// Issuance caps surface as 429 and Redis degradation as 503 with
+/// Retry-After (a self-refreshing page for HTML clients); anything else is
+/// a 500.
+fn generation_error(
+    state: &AppState,
+    e: anyhow::Error,
+    context: &'static str,
+    html: bool,
+) -> Response {
+    match e.downcast_ref::<cerberus_common::CerberusError>() {
+        Some(err @ cerberus_common::CerberusError::RateLimited(_)) => {
+            (StatusCode::TOO_MANY_REQUESTS, err.to_string()).into_response()
+        }
+        Some(cerberus_common::CerberusError::Redis(_)) if html => {
+            render_retry_page(state.degradation.retry_after_secs())
+        }
+        Some(cerberus_common::CerberusError::Redis(_)) => (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, state.degradation.retry_after_secs())],
+            "Service busy, please retry shortly",
+        )
+            .into_response(),
+        _ => {
+            tracing::error!(error = %e, "{}", context);
+            (StatusCode::INTERNAL_SERVER_ERROR, context).into_response()
+        }
+    }
 }
 
 // === Circuit Handlers ===
 
+/// Circuit record plus its recent history
+#[derive(Serialize)]
+struct CircuitDetails {
+    #[serde(flatten)]
+    info: cerberus_common::CircuitInfo,
+    /// Challenges left to expire unanswered lately
+    abandoned: u64,
+    /// Newest first
+    events: Vec<CircuitEvent>,
+}
+
 async fn get_circuit_info(
     State(state): State<AppState>,
-    axum::extract::Path(circuit_id): axum::extract::Path<String>,
-) -> Result<Json<cerberus_common::CircuitInfo>, StatusCode> {
-    let mut redis = state.redis.clone();
+    axum::extract::Path(circuit_id): axum::extract::Path<CircuitId>,
+) -> Result<Json<CircuitDetails>, StatusCode> {
+    let mut redis = state.redis().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    let tracker = &state.circuit_tracker;
+
+    let lookup = async {
+        let Some(info) = tracker.get(&mut redis, &circuit_id).await? else {
+            return anyhow::Ok(None);
+        };
+        let events = tracker.events().recent(&mut redis, &circuit_id).await?;
+        let abandoned = state
+            .abandonment
+            .abandoned_by(&mut redis, &circuit_id)
+            .await?;
+        Ok(Some(CircuitDetails {
+            info,
+            abandoned,
+            events,
+        }))
+    };
 
-    match state.circuit_tracker.get(&mut redis, &circuit_id).await {
-        Ok(Some(info)) => Ok(Json(info)),
+    match lookup.await {
+        Ok(Some(details)) => Ok(Json(details)),
         Ok(None) => Err(StatusCode::NOT_FOUND),
         Err(e) => {
             tracing::error!(error = %e, circuit_id = %circuit_id, "Failed to get circuit");
@@ -75,11 +372,75 @@ async fn get_circuit_info(
     }
 }
 
+/// Stream every circuit record as JSON lines (see `circuits::transfer`)
+async fn export_circuits(State(state): State<AppState>) -> Result<Response, StatusCode> {
+    let redis = state.redis().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    let lines = futures::StreamExt::inspect(transfer::export(redis), |chunk| {
+        if let Err(e) = chunk {
+            tracing::error!(error = %e, "Circuit export failed part way");
+        }
+    });
+    tracing::info!("Circuit records exported by admin");
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        axum::body::Body::from_stream(lines),
+    )
+        .into_response())
+}
+
+#[derive(Deserialize)]
+struct ImportQuery {
+    /// Replace records circuits already have
+    #[serde(default)]
+    overwrite: bool,
+}
+
+/// Import circuit records from an export, as the body arrives
+async fn import_circuits(
+    State(state): State<AppState>,
+    Query(query): Query<ImportQuery>,
+    body: axum::body::Body,
+) -> Result<Json<transfer::ImportReport>, StatusCode> {
+    let mut redis = state.redis().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    let mut importer = transfer::Importer::new(&state.circuit_tracker, query.overwrite);
+    let mut chunks = body.into_data_stream();
+
+    let import = async {
+        while let Some(chunk) = futures::StreamExt::next(&mut chunks).await {
+            importer.feed(&mut redis, &chunk?).await?;
+        }
+        importer.finish(&mut redis).await
+    };
+    match import.await {
+        Ok(report) => {
+            tracing::info!(
+                imported = report.imported,
+                existing = report.existing,
+                invalid = report.invalid,
+                overwrite = query.overwrite,
+                "Circuit records imported by admin"
+            );
+            Ok(Json(report))
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to import circuit records");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
 async fn ban_circuit(
     State(state): State<AppState>,
-    axum::extract::Path(circuit_id): axum::extract::Path<String>,
+    axum::extract::Path(circuit_id): axum::extract::Path<CircuitId>,
 ) -> StatusCode {
-    let mut redis = state.redis.clone();
+    let Some(mut redis) = state.redis() else {
+        // Applied when Redis is back
+        state.mutation_queue.push(CircuitMutation::Ban {
+            circuit_id,
+            reason: "Admin ban".to_string(),
+        });
+        return StatusCode::ACCEPTED;
+    };
 
     match state
         .circuit_tracker
@@ -97,11 +458,71 @@ async fn ban_circuit(
     }
 }
 
+/// Lift a ban, soft-lock or gate lockout (404 if the circuit has none)
+async fn unban_circuit(
+    State(state): State<AppState>,
+    axum::extract::Path(circuit_id): axum::extract::Path<CircuitId>,
+) -> StatusCode {
+    let Some(mut redis) = state.redis() else {
+        return StatusCode::SERVICE_UNAVAILABLE;
+    };
+
+    let unlocked = match state.throttle.unlock(&mut redis, &circuit_id).await {
+        Ok(unlocked) => unlocked,
+        Err(e) => {
+            tracing::error!(error = %e, circuit_id = %circuit_id, "Failed to lift gate lockout");
+            return StatusCode::INTERNAL_SERVER_ERROR;
+        }
+    };
+    match state.circuit_tracker.unban(&mut redis, &circuit_id).await {
+        Ok(true) => {
+            tracing::info!(circuit_id = %circuit_id, "Circuit unbanned by admin");
+            StatusCode::OK
+        }
+        Ok(false) if unlocked => {
+            tracing::info!(circuit_id = %circuit_id, "Gate lockout lifted by admin");
+            StatusCode::OK
+        }
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            tracing::error!(error = %e, circuit_id = %circuit_id, "Failed to unban circuit");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Honeypot hit reported by the proxy: soft-locks the circuit
+async fn honeypot_hit(
+    State(state): State<AppState>,
+    axum::extract::Path(circuit_id): axum::extract::Path<CircuitId>,
+) -> StatusCode {
+    let Some(mut redis) = state.redis() else {
+        // Applied when Redis is back
+        state
+            .mutation_queue
+            .push(CircuitMutation::HoneypotHit { circuit_id });
+        return StatusCode::ACCEPTED;
+    };
+
+    match state
+        .circuit_tracker
+        .record_honeypot_hit(&mut redis, &circuit_id)
+        .await
+    {
+        Ok(_) => StatusCode::OK,
+        Err(e) => {
+            tracing::error!(error = %e, circuit_id = %circuit_id, "Failed to record honeypot hit");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
 // === Admin Handlers ===
 
 #[derive(Serialize)]
 struct ThreatLevelResponse {
     level: u8,
+    band: cerberus_common::ThreatBand,
     requires_captcha: bool,
     captcha_count: u8,
 }
@@ -110,6 +531,7 @@ async fn get_threat_level(State(state): State<AppState>) -> Json<ThreatLevelResp
     let level = state.get_threat_level().await;
     Json(ThreatLevelResponse {
         level: level.value(),
+        band: level.band(),
         requires_captcha: level.requires_captcha(),
         captcha_count: level.captcha_count(),
     })
@@ -133,6 +555,7 @@ async fn set_threat_level(
 
     Ok(Json(ThreatLevelResponse {
         level: level.value(),
+        band: level.band(),
         requires_captcha: level.requires_captcha(),
         captcha_count: level.captcha_count(),
     }))
@@ -154,112 +577,734 @@ async fn get_stats(State(state): State<AppState>) -> Json<StatsResponse> {
     })
 }
 
+#[derive(Deserialize)]
+struct OutliersQuery {
+    limit: Option<usize>,
+}
+
+async fn get_farm_outliers(
+    State(state): State<AppState>,
+    axum::extract::Query(params): axum::extract::Query<OutliersQuery>,
+) -> Result<Json<Vec<crate::circuits::FarmOutlier>>, StatusCode> {
+    let mut redis = state.redis().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    let limit = params.limit.unwrap_or(50).min(1000);
+
+    state
+        .solve_time_analyzer
+        .outliers(&mut redis, limit)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to list farm outliers");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Pass and abandonment rates of each experiment variant, cluster-wide
+async fn get_experiments(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<crate::experiments::ExperimentReport>>, StatusCode> {
+    let mut redis = state.redis().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+
+    state
+        .experiments
+        .report(&mut redis)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to read experiment tallies");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Challenge outcomes per difficulty over each stats window, cluster-wide
+async fn get_difficulty_stats(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<crate::challenge_stats::WindowReport>>, StatusCode> {
+    let mut redis = state.redis().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    let now = chrono::Utc::now().timestamp();
+
+    state
+        .challenge_stats
+        .report(&mut redis, now)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to read challenge tallies");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Mean gate-to-first-request stage times per threat level, cluster-wide
+async fn get_journeys(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<crate::journey::LevelReport>>, StatusCode> {
+    let mut redis = state.redis().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+
+    state.journeys.report(&mut redis).await.map(Json).map_err(|e| {
+        tracing::error!(error = %e, "Failed to read journey totals");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
+
+#[derive(Deserialize)]
+struct SamplesQuery {
+    limit: Option<usize>,
+}
+
+/// Captured `/validate` requests, newest first
+async fn get_samples(
+    State(state): State<AppState>,
+    Query(params): Query<SamplesQuery>,
+) -> Json<Vec<sampling::Sample>> {
+    let limit = params
+        .limit
+        .unwrap_or(100)
+        .min(state.config.sampling.capacity);
+    Json(state.sampler.recent(limit))
+}
+
+#[derive(Serialize)]
+struct ClearSamplesResponse {
+    cleared: usize,
+}
+
+async fn clear_samples(State(state): State<AppState>) -> Json<ClearSamplesResponse> {
+    Json(ClearSamplesResponse {
+        cleared: state.sampler.clear(),
+    })
+}
+
+#[derive(Serialize)]
+struct SloResponse {
+    threat_level: u8,
+    objectives: Vec<slo::ObjectiveReport>,
+}
+
+/// Error budgets and burn rates of the `/validate` objectives
+async fn get_slo(State(state): State<AppState>) -> Json<SloResponse> {
+    Json(SloResponse {
+        threat_level: state.get_threat_level().await.value(),
+        objectives: state.slo.report(chrono::Utc::now().timestamp()),
+    })
+}
+
+/// Build, uptime, role, subsystems and redacted config of this node
+async fn get_info(State(state): State<AppState>) -> Json<NodeInfo> {
+    Json(NodeInfo::collect(&state))
+}
+
+/// Background tasks: state, restarts and last panic
+async fn get_tasks(State(state): State<AppState>) -> Json<Vec<supervisor::TaskStatus>> {
+    Json(state.supervisor.status())
+}
+
 // === Static Page Serving ===
 
 /// Form data for CAPTCHA verification (no-JS fallback)
 #[derive(Deserialize)]
 pub struct VerifyForm {
-    pub challenge_id: String,
+    pub challenge_id: ChallengeId,
     pub answer: String,
+    /// Page to return to, with its signature (hidden fields of the gate form)
+    #[serde(default)]
+    pub return_to: Option<String>,
+    #[serde(default)]
+    pub return_sig: Option<String>,
+    /// Shadow trial puzzle shown alongside, and its optional answer
+    #[serde(default)]
+    pub shadow_id: Option<ChallengeId>,
+    #[serde(default)]
+    pub shadow_answer: Option<String>,
+    /// One-time nonce of the rendered form (absent on pages rendered offline,
+    /// which are only accepted while Redis is offline)
+    #[serde(default)]
+    pub form_nonce: Option<GateFormNonce>,
+}
+
+/// Content-negotiated verification endpoint
+///
+/// `application/json` bodies get a `CaptchaResult` (JS clients); anything
+/// else is treated as a form post and gets the redirect flow (no-JS clients).
+async fn verify(State(state): State<AppState>, request: Request) -> Response {
+    let headers = request.headers().clone();
+
+    if is_json_request(&headers) {
+        match Json::<captcha::VerifyRequest>::from_request(request, &state).await {
+            Ok(payload) => captcha::verify_challenge(State(state), headers, payload)
+                .await
+                .into_response(),
+            Err(rejection) => rejection.into_response(),
+        }
+    } else {
+        match Form::<VerifyForm>::from_request(request, &state).await {
+            Ok(form) => verify_form(State(state), headers, form).await,
+            Err(rejection) => rejection.into_response(),
+        }
+    }
+}
+
+/// Does the request body declare itself as JSON?
+fn is_json_request(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|ct| ct.split(';').next())
+        .is_some_and(|mime| {
+            let mime = mime.trim();
+            mime.eq_ignore_ascii_case("application/json")
+                || (mime.starts_with("application/") && mime.ends_with("+json"))
+        })
+}
+
+/// Challenge image format for this request (`captcha.image_format`)
+fn image_format(state: &AppState, headers: &HeaderMap) -> ImageFormat {
+    state
+        .config
+        .captcha
+        .image_format
+        .negotiate(headers.get(header::ACCEPT).and_then(|v| v.to_str().ok()))
+}
+
+/// Does the client prefer a JSON response?
+fn wants_json(headers: &HeaderMap) -> bool {
+    is_json_request(headers)
+        || headers
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|accept| accept.contains("application/json"))
+}
+
+/// Seconds left to answer a challenge expiring at `expires_at`
+fn expires_in_secs(expires_at: i64) -> u32 {
+    let left = expires_at - chrono::Utc::now().timestamp();
+    left.clamp(0, i64::from(u32::MAX)) as u32
 }
 
 /// Handle form POST verification (works without JavaScript)
+///
+/// Wrong answers are counted in the visitor's gate session; a solve sends
+/// them back to the page they came from and ends the session. A form posted
+/// again after it was processed gets the "already processed" page; one
+/// without a nonce issued to the visitor's session, or that can't be looked
+/// up, gets a fresh challenge instead of having its answer checked.
 async fn verify_form(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Form(form): Form<VerifyForm>,
 ) -> Response {
-    let mut redis = state.redis.clone();
+    let circuit_id = circuit_id_from_headers(&headers);
+    let format = image_format(&state, &headers);
+    let session_id = gate_session::from_headers(&headers);
 
-    let result = state
-        .captcha_verifier
-        .verify(&mut redis, &form.challenge_id, &form.answer, None)
-        .await;
+    let request = VerificationRequest {
+        challenge_id: &form.challenge_id,
+        answer: &form.answer,
+        circuit_id: circuit_id.as_ref(),
+        threat_level: state.get_threat_level().await,
+    };
+
+    let mut session = None;
+    let (limits, result) = match state.redis() {
+        Some(mut redis) => {
+            let limits = match rate_limit::check(&state, &mut redis, circuit_id.as_ref()).await {
+                Ok(limits) => limits,
+                Err(status) => return status.into_response(),
+            };
+            if !limits.allowed() {
+                return limits.too_many_requests();
+            }
+
+            if let Some(ref id) = session_id {
+                session = load_gate_session(&state, &mut redis, id).await;
+            }
+
+            let submission = state
+                .gate_sessions
+                .spend_form_nonce(&mut redis, session_id.as_ref(), form.form_nonce.as_ref())
+                .await;
+            let return_to = form_return_to(&state, &form, session.as_ref());
+            let error = match submission {
+                Ok(FormSubmission::New) => None,
+                Ok(FormSubmission::Resubmitted) => {
+                    metrics::GATE_FORM_RESUBMISSIONS.inc();
+                    return (limits, render_processed_page(return_to.as_deref())).into_response();
+                }
+                Ok(FormSubmission::Unknown) => {
+                    Some("This form has expired. Please try again with the new code.")
+                }
+                Err(e) => {
+                    tracing::warn!(error = %e, "Failed to spend gate form nonce");
+                    Some("Verification error. Please try again.")
+                }
+            };
+            if let Some(error) = error {
+                let page = serve_captcha_page_with_error(
+                    state,
+                    circuit_id,
+                    format,
+                    session_id.as_ref().filter(|_| session.is_some()),
+                    return_to.as_deref(),
+                    error,
+                )
+                .await;
+                return (limits, page).into_response();
+            }
 
-    match result {
+            (
+                limits,
+                state.verification.verify(Some(&mut redis), request).await,
+            )
+        }
+        None => (
+            rate_limit::RateLimitHeaders::default(),
+            state.verification.verify(None, request).await,
+        ),
+    };
+    let return_to = form_return_to(&state, &form, session.as_ref());
+    let live_session = session_id.as_ref().filter(|_| session.is_some());
+
+    let solved = matches!(&result, Ok(captcha_result) if captcha_result.success);
+    record_shadow(
+        &state,
+        form.shadow_id.as_ref(),
+        form.shadow_answer.as_deref(),
+        solved,
+    )
+    .await;
+
+    let response = match result {
         Ok(captcha_result) if captcha_result.success => {
             if let Some(token) = captcha_result.passport_token {
-                // Redirect to protected app with passport token
-                Redirect::to(&format!("/app/?passport_token={}", urlencoding::encode(&token)))
-                    .into_response()
+                // The cookie stays, so the form posted again is recognised
+                if let (Some(id), Some(mut redis)) = (session_id.as_ref(), state.redis())
+                    && let Err(e) = state.gate_sessions.end(&mut redis, id).await
+                {
+                    tracing::warn!(error = %e, "Failed to end gate session");
+                }
+                passport_redirect(&token, return_to.as_deref())
             } else {
                 // Success but no token - show error
-                serve_captcha_page_with_error(state, "Verification succeeded but no token generated").await
+                serve_captcha_page_with_error(
+                    state,
+                    circuit_id,
+                    format,
+                    live_session,
+                    return_to.as_deref(),
+                    "Verification succeeded but no token generated",
+                )
+                .await
             }
         }
         Ok(_) => {
             // Wrong answer - show new challenge with error
-            serve_captcha_page_with_error(state, "Incorrect code. Please try again.").await
+            let message = match (session_id.as_ref(), session) {
+                (Some(id), Some(mut session)) => {
+                    session.attempts += 1;
+                    if let Some(mut redis) = state.redis()
+                        && let Err(e) = state.gate_sessions.save(&mut redis, id, &session).await
+                    {
+                        tracing::warn!(error = %e, "Failed to update gate session");
+                    }
+                    incorrect_answer_message(session.attempts)
+                }
+                _ => incorrect_answer_message(1),
+            };
+            serve_captcha_page_with_error(
+                state,
+                circuit_id,
+                format,
+                live_session,
+                return_to.as_deref(),
+                &message,
+            )
+            .await
         }
         Err(e) => {
             tracing::error!(error = %e, "CAPTCHA verification failed");
-            serve_captcha_page_with_error(state, "Verification error. Please try again.").await
+            serve_captcha_page_with_error(
+                state,
+                circuit_id,
+                format,
+                live_session,
+                return_to.as_deref(),
+                "Verification error. Please try again.",
+            )
+            .await
         }
+    };
+
+    (limits, response).into_response()
+}
+
+/// Page to return to after a gate form: the signed form field is specific
+/// to this page; the session covers forms without one
+fn form_return_to(
+    state: &AppState,
+    form: &VerifyForm,
+    session: Option<&GateSession>,
+) -> Option<String> {
+    match (&form.return_to, &form.return_sig) {
+        (Some(path), Some(sig)) => state.gate_sessions.open_return_to(path, sig),
+        _ => None,
     }
+    .or_else(|| session.and_then(|s| s.return_to.clone()))
+}
+
+fn incorrect_answer_message(attempts: u32) -> String {
+    if attempts > 1 {
+        format!(
+            "Incorrect code ({} wrong answers so far). Please try again.",
+            attempts
+        )
+    } else {
+        "Incorrect code. Please try again.".to_string()
+    }
+}
+
+/// Redirect with a passport token to `return_to` (default: the protected app)
+fn passport_redirect(token: &PassportToken, return_to: Option<&str>) -> Response {
+    let target = return_to.unwrap_or("/app/");
+    let separator = if target.contains('?') { '&' } else { '?' };
+    Redirect::to(&format!(
+        "{}{}passport_token={}",
+        target,
+        separator,
+        urlencoding::encode(token.as_str())
+    ))
+    .into_response()
+}
+
+/// Query of the gate page
+#[derive(Deserialize)]
+struct GateQuery {
+    /// Page the visitor was sent from (local path), to return to after solving
+    return_to: Option<String>,
 }
 
 /// Serve the CAPTCHA page with an embedded challenge (no JavaScript required)
-async fn serve_captcha_page(State(state): State<AppState>) -> Response {
-    serve_captcha_page_inner(state, None).await
+///
+/// The page to return to afterwards is `?return_to=`, or else the
+/// `X-Original-URI` Nginx sets when it sends a visitor without a passport
+/// here. Starts a gate session with the first challenge it issues a
+/// visitor without one (see `issue_form_nonce`).
+async fn serve_captcha_page(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<GateQuery>,
+) -> Response {
+    let format = image_format(&state, &headers);
+    let return_to = query
+        .return_to
+        .as_deref()
+        .and_then(gate_session::sanitize_return_to)
+        .or_else(|| gate_session::original_uri(&headers));
+
+    // Visitors keep their session; others only get one with a challenge,
+    // so page loads turned away can't fill Redis with sessions
+    let mut session = None;
+    let mut session_id = None;
+    if let Some(mut redis) = state.redis()
+        && let Some(id) = gate_session::from_headers(&headers)
+        && let Some(mut existing) = load_gate_session(&state, &mut redis, &id).await
+    {
+        // A new gated page replaces the one remembered
+        if return_to.is_some() && existing.return_to != return_to {
+            existing.return_to = return_to.clone();
+            if let Err(e) = state.gate_sessions.save(&mut redis, &id, &existing).await {
+                tracing::warn!(error = %e, "Failed to update gate session");
+            }
+        }
+        session = Some(existing);
+        session_id = Some(id);
+    }
+    let return_to = match session {
+        Some(session) => session.return_to,
+        None => return_to,
+    };
+
+    serve_captcha_page_inner(
+        state,
+        circuit_id_from_headers(&headers),
+        format,
+        session_id.as_ref(),
+        return_to.as_deref(),
+        None,
+    )
+    .await
+}
+
+/// Gate session `id`, treating Redis errors as no session
+async fn load_gate_session(
+    state: &AppState,
+    redis: &mut redis::aio::ConnectionManager,
+    id: &cerberus_common::GateSessionId,
+) -> Option<GateSession> {
+    state
+        .gate_sessions
+        .load(redis, id)
+        .await
+        .inspect_err(|e| tracing::warn!(error = %e, "Failed to load gate session"))
+        .ok()
+        .flatten()
 }
 
 /// Serve CAPTCHA page with an error message
-async fn serve_captcha_page_with_error(state: AppState, error: &str) -> Response {
-    serve_captcha_page_inner(state, Some(error.to_string())).await
+async fn serve_captcha_page_with_error(
+    state: AppState,
+    circuit_id: Option<CircuitId>,
+    format: ImageFormat,
+    session_id: Option<&GateSessionId>,
+    return_to: Option<&str>,
+    error: &str,
+) -> Response {
+    serve_captcha_page_inner(
+        state,
+        circuit_id,
+        format,
+        session_id,
+        return_to,
+        Some(error),
+    )
+    .await
 }
 
-/// Inner function to render CAPTCHA page
-async fn serve_captcha_page_inner(state: AppState, error: Option<String>) -> Response {
-    let mut redis = state.redis.clone();
+/// Inner function to generate a challenge and render the CAPTCHA page
+///
+/// VIPs let straight through are sent to `return_to` (default: the app).
+async fn serve_captcha_page_inner(
+    state: AppState,
+    circuit_id: Option<CircuitId>,
+    format: ImageFormat,
+    session_id: Option<&GateSessionId>,
+    return_to: Option<&str>,
+    error: Option<&str>,
+) -> Response {
+    serve_gate_page(
+        state, circuit_id, format, session_id, return_to, error, false,
+    )
+    .await
+}
+
+/// Generate a challenge and render the gate page
+///
+/// Offers a proof-of-work when `captcha.flow` rewards one at this threat
+/// level; once it's solved (`pow_solved`), none is offered and the
+/// challenge is eased if the flow says so. `session_id` is the visitor's
+/// live gate session, if they have one.
+async fn serve_gate_page(
+    state: AppState,
+    circuit_id: Option<CircuitId>,
+    format: ImageFormat,
+    session_id: Option<&GateSessionId>,
+    return_to: Option<&str>,
+    error: Option<&str>,
+    pow_solved: bool,
+) -> Response {
     let threat_level = state.get_threat_level().await;
-    let difficulty = threat_level.captcha_difficulty();
+    let flow = &state.config.captcha.flow;
+    let reward = flow.reward(threat_level.captcha_difficulty());
+    let difficulty = if pow_solved && reward == PowReward::Easier {
+        threat_level.captcha_difficulty().easier()
+    } else {
+        threat_level.captcha_difficulty()
+    };
+    let signed_return_to = return_to.map(|path| state.gate_sessions.sign_return_to(path));
+
+    let Some(mut redis) = state.redis() else {
+        // Offline: sealed challenge verified without Redis
+        let challenge = state
+            .captcha_generator
+            .generate_stateless(difficulty, format);
+        let form = FormFields {
+            return_to: signed_return_to.as_ref(),
+            nonce: None,
+        };
+        return render_captcha_page(&challenge, threat_level, form, error, None, None);
+    };
+
+    // VIPs may go straight through while the threat level is low
+    if let Some(ref circuit_id) = circuit_id {
+        match state
+            .verification
+            .vip_pass(&mut redis, circuit_id, threat_level)
+            .await
+        {
+            Ok(Some(grant)) => return passport_redirect(&grant.token, return_to),
+            Ok(None) => {}
+            Err(e) => tracing::warn!(error = %e, circuit_id = %circuit_id, "VIP fast path failed"),
+        }
+    }
+
+    // Solutions are spent in Redis, so puzzles are only offered online
+    let pow = (!pow_solved && reward != PowReward::Off).then(|| {
+        PowPuzzle::issue(
+            &state.sealer,
+            flow.pow_bits,
+            flow.pow_ttl_secs.secs(),
+            circuit_id.as_ref(),
+        )
+    });
 
     // Generate a fresh CAPTCHA challenge
     let challenge = match state
         .captcha_generator
-        .generate(&mut redis, None, difficulty)
+        .generate(&mut redis, circuit_id, difficulty, format)
         .await
     {
         Ok(c) => c,
-        Err(e) => {
-            tracing::error!(error = %e, "Failed to generate CAPTCHA");
-            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to generate challenge").into_response();
-        }
+        Err(e) => return generation_error(&state, e, "Failed to generate challenge", true),
+    };
+    let shadow = issue_shadow(&state, &mut redis, difficulty, format).await;
+    let (nonce, cookie) = issue_form_nonce(&state, &mut redis, session_id, return_to).await;
+    let form = FormFields {
+        return_to: signed_return_to.as_ref(),
+        nonce: nonce.as_ref(),
     };
 
-    // Decode the base64 SVG to embed directly
-    let svg_html = if challenge.image_data.starts_with("data:image/svg+xml;base64,") {
-        let b64 = challenge.image_data.strip_prefix("data:image/svg+xml;base64,").unwrap();
-        match BASE64.decode(b64) {
-            Ok(bytes) => String::from_utf8_lossy(&bytes).to_string(),
-            Err(_) => format!(r#"<img src="{}" alt="CAPTCHA">"#, challenge.image_data),
-        }
-    } else {
-        format!(r#"<img src="{}" alt="CAPTCHA">"#, challenge.image_data)
+    let mut response = render_captcha_page(
+        &challenge,
+        threat_level,
+        form,
+        error,
+        pow.as_ref(),
+        shadow.as_ref(),
+    );
+    if let Some(cookie) = cookie {
+        response.headers_mut().insert(header::SET_COOKIE, cookie);
+    }
+    response
+}
+
+/// A nonce for the gate form about to be rendered, kept with the visitor's
+/// gate session `session_id`
+///
+/// A visitor without a live session gets one here, so only a page with a
+/// challenge on it starts a session (not rate limit or error pages, nor VIP
+/// redirects); its `Set-Cookie` is returned. Without a nonce (Redis
+/// failing) the form can't be submitted: its answer would get a fresh
+/// challenge.
+async fn issue_form_nonce(
+    state: &AppState,
+    redis: &mut redis::aio::ConnectionManager,
+    session_id: Option<&GateSessionId>,
+    return_to: Option<&str>,
+) -> (Option<GateFormNonce>, Option<HeaderValue>) {
+    let (id, cookie) = match session_id {
+        Some(id) => (id.clone(), None),
+        None => match state
+            .gate_sessions
+            .start(redis, return_to.map(str::to_string))
+            .await
+        {
+            Ok((id, _)) => {
+                let cookie = state.gate_sessions.cookie(&id);
+                (id, Some(cookie))
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to start gate session");
+                return (None, None);
+            }
+        },
     };
+    let nonce = state
+        .gate_sessions
+        .issue_form_nonce(redis, &id)
+        .await
+        .inspect_err(|e| tracing::warn!(error = %e, "Failed to issue gate form nonce"))
+        .ok();
+    (nonce, cookie)
+}
 
-    // Build error HTML if present
-    let error_html = match error {
-        Some(msg) => format!(
-            r#"<div class="error" style="display:block">{}</div>"#,
-            html_escape(&msg)
-        ),
-        None => String::new(),
+/// A shadow trial puzzle for a sample of visitors (best-effort)
+async fn issue_shadow(
+    state: &AppState,
+    redis: &mut redis::aio::ConnectionManager,
+    difficulty: cerberus_common::CaptchaDifficulty,
+    format: ImageFormat,
+) -> Option<ShadowChallenge> {
+    state
+        .shadow
+        .issue(redis, difficulty, format)
+        .await
+        .inspect_err(|e| tracing::warn!(error = %e, "Failed to issue shadow trial"))
+        .ok()
+        .flatten()
+}
+
+/// Record the answer to a shadow trial puzzle, if one was shown (best-effort)
+async fn record_shadow(
+    state: &AppState,
+    shadow_id: Option<&ChallengeId>,
+    answer: Option<&str>,
+    real_solved: bool,
+) {
+    let (Some(shadow_id), Some(mut redis)) = (shadow_id, state.redis()) else {
+        return;
     };
+    if let Err(e) = state
+        .shadow
+        .record(&mut redis, shadow_id, answer.unwrap_or(""), real_solved)
+        .await
+    {
+        tracing::warn!(error = %e, "Failed to record shadow trial");
+    }
+}
 
-    // Render the complete HTML page with embedded CAPTCHA
+/// Render the CAPTCHA page for a given challenge
+fn render_captcha_page(
+    challenge: &cerberus_common::CaptchaChallenge,
+    threat_level: ThreatLevel,
+    form: FormFields<'_>,
+    error: Option<&str>,
+    pow: Option<&PowPuzzle>,
+    shadow: Option<&ShadowChallenge>,
+) -> Response {
+    let html = GateTemplate::for_level(threat_level).page(
+        challenge,
+        &captcha_image_html(challenge),
+        form,
+        error,
+        pow,
+        shadow,
+    );
+    Html(html).into_response()
+}
+
+/// Challenge image markup: SVGs inline, raster and served images as an `<img>`
+fn captcha_image_html(challenge: &cerberus_common::CaptchaChallenge) -> String {
+    image_html(&challenge.image_data)
+}
+
+/// Markup for an image data URI (or URL)
+fn image_html(image_data: &str) -> String {
+    // Decode the base64 SVG to embed directly
+    if let Some(b64) = image_data.strip_prefix("data:image/svg+xml;base64,")
+        && let Ok(bytes) = BASE64.decode(b64)
+    {
+        return String::from_utf8_lossy(&bytes).to_string();
+    }
+    format!(r#"<img src="{}" alt="CAPTCHA">"#, image_data)
+}
+
+/// Friendly "try again shortly" page served while new challenges are refused
+///
+/// Uses a meta refresh so the retry works without JavaScript.
+fn render_retry_page(retry_after: u64) -> Response {
     let html = format!(
         r##"<!DOCTYPE html>
 <html lang="en">
 <head>
     <meta charset="UTF-8">
     <meta name="viewport" content="width=device-width, initial-scale=1.0">
-    <title>Sigil - Verification Required</title>
+    <meta http-equiv="refresh" content="{retry_after}">
+    <title>Sigil - Please Wait</title>
     <style>
-        * {{ margin: 0; padding: 0; box-sizing: border-box; }}
         body {{
             font-family: -apple-system, BlinkMacSystemFont, 'Segoe UI', Roboto, sans-serif;
             background: linear-gradient(135deg, #1a1a2e 0%, #16213e 100%);
             min-height: 100vh;
+            margin: 0;
             display: flex;
             align-items: center;
             justify-content: center;
@@ -271,140 +1316,73 @@ async fn serve_captcha_page_inner(state: AppState, error: Option<String>) -> Res
             padding: 40px;
             max-width: 420px;
             width: 90%;
-            box-shadow: 0 8px 32px rgba(0, 0, 0, 0.3);
-            border: 1px solid rgba(255, 255, 255, 0.1);
-        }}
-        .brand {{
-            display: flex;
-            align-items: center;
-            gap: 12px;
-            margin-bottom: 24px;
-        }}
-        .brand-logo {{ font-size: 2rem; }}
-        .brand-text h1 {{ font-size: 1.4rem; color: #fff; margin-bottom: 4px; }}
-        .brand-text .subtitle {{ color: #888; font-size: 0.85rem; }}
-        .captcha-box {{
-            background: #0f0f1a;
-            border-radius: 8px;
-            padding: 20px;
-            margin-bottom: 20px;
-            text-align: center;
-        }}
-        .captcha-image {{
-            border-radius: 4px;
-            margin-bottom: 16px;
-            background: #1a1a2e;
-            min-height: 80px;
-            display: flex;
-            align-items: center;
-            justify-content: center;
-            overflow: hidden;
-        }}
-        .captcha-image svg {{ max-width: 100%; height: auto; }}
-        .instructions {{ font-size: 0.85rem; color: #aaa; }}
-        .answer-input {{
-            width: 100%;
-            padding: 14px 16px;
-            background: #2a2a4a;
-            border: 2px solid transparent;
-            border-radius: 8px;
-            color: #fff;
-            font-size: 1.2rem;
-            font-family: monospace;
-            letter-spacing: 4px;
             text-align: center;
-            text-transform: uppercase;
-            margin-bottom: 16px;
-        }}
-        .answer-input:focus {{ outline: none; border-color: #4a9eff; background: #2a3a5a; }}
-        .submit-btn {{
-            width: 100%;
-            padding: 14px;
-            background: linear-gradient(135deg, #4a9eff 0%, #3a7edf 100%);
-            border: none;
-            border-radius: 8px;
-            color: white;
-            font-size: 1rem;
-            font-weight: 600;
-            cursor: pointer;
-        }}
-        .submit-btn:hover {{ box-shadow: 0 4px 12px rgba(74, 158, 255, 0.4); }}
-        .refresh-link {{
-            display: block;
-            text-align: center;
-            margin-top: 16px;
-            color: #888;
-            text-decoration: none;
-            font-size: 0.85rem;
-        }}
-        .refresh-link:hover {{ color: #aaa; }}
-        .footer {{
-            margin-top: 24px;
-            text-align: center;
-            font-size: 0.75rem;
-            color: #666;
-        }}
-        .error {{
-            background: rgba(255, 77, 77, 0.1);
-            border: 1px solid rgba(255, 77, 77, 0.3);
-            color: #ff6b6b;
-            padding: 12px;
-            border-radius: 8px;
-            margin-bottom: 16px;
+            border: 1px solid rgba(255, 255, 255, 0.1);
         }}
+        h1 {{ font-size: 1.4rem; color: #fff; margin-bottom: 12px; }}
+        p {{ color: #aaa; font-size: 0.9rem; }}
     </style>
 </head>
+<body>
+    <div class="container">
+        <h1>⏳ High traffic</h1>
+        <p>We're handling a surge of visitors. This page will retry automatically in {retry_after} seconds.</p>
+    </div>
+</body>
+</html>"##,
+        retry_after = retry_after,
+    );
+
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::RETRY_AFTER, retry_after)],
+        Html(html),
+    )
+        .into_response()
+}
+
+/// "Already processed" page for a gate form posted again
+///
+/// The earlier submission may have let the visitor through or not, so the
+/// page offers both ways on: the page they were going to, or a new challenge.
+fn render_processed_page(return_to: Option<&str>) -> Response {
+    let (continue_to, new_challenge) = match return_to {
+        Some(path) => (
+            path.to_string(),
+            format!("/captcha.html?return_to={}", urlencoding::encode(path)),
+        ),
+        None => ("/app/".to_string(), "/captcha.html".to_string()),
+    };
+    let html = format!(
+        r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <title>Sigil - Already Submitted</title>
+    <link rel="stylesheet" href="{theme}">
+</head>
 <body>
     <div class="container">
         <div class="brand">
             <span class="brand-logo">🔒</span>
             <div class="brand-text">
                 <h1>Sigil</h1>
-                <p class="subtitle">Human verification required</p>
-            </div>
-        </div>
-
-        {error_html}
-
-        <form method="POST" action="/verify">
-            <input type="hidden" name="challenge_id" value="{challenge_id}">
-
-            <div class="captcha-box">
-                <div class="captcha-image">
-                    {svg_html}
-                </div>
-                <p class="instructions">{instructions}</p>
+                <p class="subtitle">Already processed</p>
             </div>
-
-            <input type="text"
-                   class="answer-input"
-                   name="answer"
-                   placeholder="Enter code"
-                   autocomplete="off"
-                   autocapitalize="off"
-                   spellcheck="false"
-                   maxlength="8"
-                   autofocus
-                   required>
-
-            <button type="submit" class="submit-btn">Verify</button>
-
-            <a href="/" class="refresh-link">↻ New Challenge</a>
-        </form>
-
-        <div class="footer">
-            Protected by Cerberus • No JavaScript required
         </div>
+        <p class="instructions">This form was already submitted, so it wasn't sent again. If your answer was accepted, continue to the site; otherwise, take a new challenge.</p>
+        <a class="submit-btn" href="{continue_to}">Continue</a>
+        <a class="refresh-link" href="{new_challenge}">↻ New Challenge</a>
     </div>
 </body>
 </html>"##,
-        error_html = error_html,
-        challenge_id = html_escape(&challenge.challenge_id),
-        svg_html = svg_html,
-        instructions = html_escape(&challenge.instructions),
+        theme = theme::THEME_PATH,
+        continue_to = html_escape(&continue_to),
+        new_challenge = html_escape(&new_challenge),
     );
 
-    Html(html).into_response()
+    (StatusCode::CONFLICT, Html(html)).into_response()
 }
 
 /// Simple HTML escaping for safety
@@ -421,19 +1399,30 @@ fn html_escape(s: &str) -> String {
 /// Protected app endpoint - requires valid passport token
 async fn protected_app(
     State(state): State<AppState>,
+    headers: HeaderMap,
     axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
 ) -> Response {
     // Check for passport token
     let token = params.get("passport_token");
+    let circuit_id = circuit_id_from_headers(&headers);
 
     match token {
         Some(t) => {
-            let mut redis = state.redis.clone();
-            match state
-                .captcha_verifier
-                .validate_passport(&mut redis, t)
-                .await
-            {
+            let valid = match (t.parse::<PassportToken>(), state.redis()) {
+                // Malformed tokens can't be valid
+                (Err(_), _) => Ok(false),
+                (Ok(t), Some(mut redis)) => {
+                    state
+                        .captcha_verifier
+                        .validate_passport(&mut redis, &t, circuit_id.as_ref())
+                        .await
+                }
+                (Ok(t), None) => Ok(state
+                    .captcha_verifier
+                    .validate_signed_passport(&t, circuit_id.as_ref())
+                    .await),
+            };
+            match valid {
                 Ok(true) => {
                     // Valid passport - show protected content
                     Html(format!(r##"<!DOCTYPE html>
@@ -1,11 +1,21 @@
-//! Passport validation endpoint (called by Nginx/HAProxy).
+//! Passport validation (called by Nginx/HAProxy), admin introspection,
+//! revocation, key rotation and journeys.
 
 use axum::{
-    extract::{Query, State},
-    http::StatusCode,
+    Json,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
 };
-use serde::Deserialize;
+use cerberus_common::{CerberusEvent, CircuitId, EventPublisher, PassportToken, redis_keys};
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
 
+use super::{allowlist, rate_limit};
+use crate::captcha::PassportInfo;
+use crate::cluster::RetiredKeyInfo;
+use crate::enforcement::{self, Action};
+use crate::journey::Journey;
 use crate::state::AppState;
 
 #[derive(Deserialize)]
@@ -22,67 +32,427 @@ pub struct ValidateQuery {
 /// - 200: Valid passport
 /// - 401: Invalid or expired passport
 /// - 403: Circuit is banned
-/// - 429: Rate limited
+/// - 429: Rate limited, or too many of the circuit's requests in flight
 ///
 /// This endpoint is designed to be called by Nginx auth_request
-/// or HAProxy's http-request lua action.
+/// or HAProxy's http-request lua action. While Redis is offline only
+/// signed passports can be validated; everything else gets 401.
+/// A malformed token is 401 as well (not 400, which the proxies would
+/// treat as an internal error), and a malformed circuit ID is ignored.
+///
+/// In observe-only mode every answer is 200; refusals are recorded instead.
+/// A live first-party allowlist secret in `allowlist.header` is always 200.
 pub async fn validate_passport(
     State(state): State<AppState>,
+    headers: HeaderMap,
     Query(params): Query<ValidateQuery>,
-) -> StatusCode {
-    let mut redis = state.redis.clone();
+) -> Response {
+    if let Some(entry) = allowlist::allowlisted(&state, &headers).await {
+        tracing::debug!(name = %entry.name, "Allowlisted first-party client");
+        return StatusCode::OK.into_response();
+    }
+
+    let circuit_id = params
+        .circuit_id
+        .and_then(|id| id.parse::<CircuitId>().ok());
+    let response = enforce(&state, &params.token, circuit_id.as_ref()).await;
+    if !state.config.enforcement.observe || response.status() == StatusCode::OK {
+        return response;
+    }
+
+    let action = Action::for_status(response.status());
+    match action {
+        Some(action) => enforcement::record(&state, action, circuit_id.as_ref()),
+        None => tracing::debug!(status = %response.status(), "Observe-only: letting through"),
+    }
+    let mut response = StatusCode::OK.into_response();
+    // Kept for request samples
+    if let Some(action) = action {
+        response.extensions_mut().insert(action);
+    }
+    response
+}
+
+/// The `/validate` answer with enforcement on
+async fn enforce(state: &AppState, token: &str, circuit_id: Option<&CircuitId>) -> Response {
+    let Ok(token) = token.parse::<PassportToken>() else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+
+    let Some(mut redis) = state.redis() else {
+        return if state
+            .captcha_verifier
+            .validate_signed_passport(&token, circuit_id)
+            .await
+        {
+            StatusCode::OK.into_response()
+        } else {
+            StatusCode::UNAUTHORIZED.into_response()
+        };
+    };
 
     // Check if circuit is allowed (if provided)
-    if let Some(ref circuit_id) = params.circuit_id {
+    if let Some(circuit_id) = circuit_id {
         match state
             .circuit_tracker
             .is_allowed(&mut redis, circuit_id)
             .await
         {
-            Ok((false, _)) => return StatusCode::FORBIDDEN,
+            Ok((false, _)) => return StatusCode::FORBIDDEN.into_response(),
             Err(e) => {
                 tracing::error!(error = %e, "Failed to check circuit status");
-                return StatusCode::INTERNAL_SERVER_ERROR;
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
             }
             _ => {}
         }
+    }
 
-        // Check rate limit
-        match state
-            .circuit_tracker
-            .check_rate_limit(
-                &mut redis,
-                circuit_id,
-                state.config.rate_limit.max_requests_per_minute,
-            )
-            .await
-        {
-            Ok((false, _)) => return StatusCode::TOO_MANY_REQUESTS,
-            Err(e) => {
-                tracing::error!(error = %e, "Failed to check rate limit");
-                return StatusCode::INTERNAL_SERVER_ERROR;
-            }
-            _ => {}
-        }
+    // Check rate limit
+    let limits = match rate_limit::check(state, &mut redis, circuit_id).await {
+        Ok(limits) => limits,
+        Err(status) => return status.into_response(),
+    };
+    if !limits.allowed() {
+        return limits.too_many_requests();
     }
 
     // Validate the passport token
-    match state
+    let status = match state
         .captcha_verifier
-        .validate_passport(&mut redis, &params.token)
+        .validate_passport(&mut redis, &token, circuit_id)
         .await
     {
         Ok(true) => {
-            tracing::debug!(token = %params.token, "Passport validated");
-            StatusCode::OK
+            tracing::debug!(token = %token, "Passport validated");
+            match acquire_request_slot(state, &mut redis, circuit_id).await {
+                Ok(true) => {
+                    state.journeys.complete(&mut redis, &token).await;
+                    StatusCode::OK
+                }
+                Ok(false) => return (limits, too_many_in_flight()).into_response(),
+                Err(status) => status,
+            }
         }
         Ok(false) => {
-            tracing::debug!(token = %params.token, "Invalid passport");
+            tracing::debug!(token = %token, "Invalid passport");
             StatusCode::UNAUTHORIZED
         }
         Err(e) => {
             tracing::error!(error = %e, "Passport validation error");
             StatusCode::INTERNAL_SERVER_ERROR
         }
+    };
+
+    (limits, status).into_response()
+}
+
+/// Count a validated request against the circuit's concurrency limit
+///
+/// Fails closed like the rate limit: a Redis error yields 500.
+async fn acquire_request_slot(
+    state: &AppState,
+    redis: &mut redis::aio::ConnectionManager,
+    circuit_id: Option<&CircuitId>,
+) -> Result<bool, StatusCode> {
+    let rate = &state.config.rate_limit;
+    let Some(circuit_id) = circuit_id.filter(|_| rate.max_concurrent_per_circuit > 0) else {
+        return Ok(true);
+    };
+
+    let acquired = state
+        .circuit_tracker
+        .acquire_request_slot(
+            redis,
+            circuit_id,
+            rate.max_concurrent_per_circuit,
+            rate.concurrency_lease_ms,
+        )
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to check request concurrency");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    if !acquired {
+        tracing::debug!(circuit_id = %circuit_id, "Too many requests in flight");
+    }
+    Ok(acquired)
+}
+
+/// 429 for a circuit at its concurrency limit (a slot frees within a lease)
+fn too_many_in_flight() -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(axum::http::header::RETRY_AFTER, 1)],
+        "Too many concurrent requests.",
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub struct InspectRequest {
+    pub token: PassportToken,
+    /// Circuit presenting it, which a signed passport bound to a circuit
+    /// needs to be `valid`
+    pub circuit_id: Option<CircuitId>,
+}
+
+/// What's known of a passport: issue time, expiry, issuing node, circuit,
+/// scopes and use count
+///
+/// Returns:
+/// - 200: The passport (`valid` says whether this node honours it now)
+/// - 404: Neither a signed token nor a stored passport (unknown, expired
+///   or revoked)
+/// - 503: Redis offline, for a token that isn't signed
+///
+/// The token travels in the body so it stays out of access logs.
+pub async fn get_passport(
+    State(state): State<AppState>,
+    Json(request): Json<InspectRequest>,
+) -> Result<Json<PassportInfo>, StatusCode> {
+    let mut redis = state.redis();
+    inspect(
+        &state,
+        redis.as_mut(),
+        &request.token,
+        request.circuit_id.as_ref(),
+    )
+    .await?
+    .map(Json)
+    .ok_or(StatusCode::NOT_FOUND)
+}
+
+#[derive(Serialize)]
+pub struct CircuitPassport {
+    pub token: PassportToken,
+    #[serde(flatten)]
+    pub info: PassportInfo,
+}
+
+/// The passports a circuit holds, newest first
+///
+/// Returns:
+/// - 200: The passports (empty if the circuit holds none)
+/// - 503: Redis offline
+///
+/// That's the circuit's latest passport and, with `passport.max_per_circuit`
+/// set, the older ones it still holds.
+pub async fn get_circuit_passports(
+    State(state): State<AppState>,
+    Path(circuit_id): Path<CircuitId>,
+) -> Result<Json<Vec<CircuitPassport>>, StatusCode> {
+    let mut redis = state.redis().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+
+    let lookup = async {
+        let held: Vec<String> = redis
+            .zrevrange(redis_keys::circuit_passports(&circuit_id), 0, -1)
+            .await?;
+        let latest = state
+            .circuit_tracker
+            .get(&mut redis, &circuit_id)
+            .await?
+            .and_then(|info| info.passport_token);
+        let mut tokens: Vec<PassportToken> = latest.into_iter().collect();
+        for token in held.iter().filter_map(|token| token.parse().ok()) {
+            if !tokens.contains(&token) {
+                tokens.push(token);
+            }
+        }
+        anyhow::Ok(tokens)
+    };
+    let tokens = lookup.await.map_err(|e| {
+        tracing::error!(error = %e, circuit_id = %circuit_id, "Failed to list circuit passports");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let mut passports = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        if let Some(info) = inspect(&state, Some(&mut redis), &token, Some(&circuit_id)).await? {
+            passports.push(CircuitPassport { token, info });
+        }
+    }
+    Ok(Json(passports))
+}
+
+/// A signed passport from its claims (checked for `circuit_id`), or a
+/// stored one from Redis
+async fn inspect(
+    state: &AppState,
+    redis: Option<&mut redis::aio::ConnectionManager>,
+    token: &PassportToken,
+    circuit_id: Option<&CircuitId>,
+) -> Result<Option<PassportInfo>, StatusCode> {
+    let verifier = &state.captcha_verifier;
+    if let Some(info) = verifier.inspect_signed_passport(token, circuit_id).await {
+        return Ok(Some(info));
+    }
+    let redis = redis.ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    verifier
+        .inspect_stored_passport(redis, token)
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to read passport");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+#[derive(Deserialize)]
+pub struct RevokeRequest {
+    /// Passport token to revoke
+    pub token: Option<PassportToken>,
+    /// Revoke whatever passport this circuit holds (and log it out)
+    pub circuit_id: Option<CircuitId>,
+}
+
+#[derive(Serialize)]
+pub struct RevokeResponse {
+    /// Passports revoked
+    pub revoked: usize,
+    /// Peers the revocation was pushed to
+    pub peers_notified: usize,
+}
+
+/// Revoke a passport by token and/or by circuit
+///
+/// Returns:
+/// - 200: Revoked (`revoked` is 0 if the circuit held no passport)
+/// - 400: Neither `token` nor `circuit_id` given
+/// - 503: Revoking by circuit while Redis is offline
+///
+/// The token is refused from now on by this node (signed passports too,
+/// even while offline) and, in cluster mode, by every gossip peer.
+pub async fn revoke_passport(
+    State(state): State<AppState>,
+    Json(request): Json<RevokeRequest>,
+) -> Result<Json<RevokeResponse>, StatusCode> {
+    revoke(&state, request.token, request.circuit_id)
+        .await
+        .map(Json)
+}
+
+/// Revoke `token` and/or `circuit_id`'s passport, here and on gossip peers
+///
+/// Shared by the admin route and the gRPC control plane; errors are the
+/// HTTP statuses `revoke_passport` documents.
+pub async fn revoke(
+    state: &AppState,
+    token: Option<PassportToken>,
+    circuit_id: Option<CircuitId>,
+) -> Result<RevokeResponse, StatusCode> {
+    if token.is_none() && circuit_id.is_none() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let mut redis = state.redis();
+    let mut tokens: Vec<PassportToken> = token.into_iter().collect();
+
+    if let Some(ref circuit_id) = circuit_id {
+        let conn = redis.as_mut().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+        match state
+            .circuit_tracker
+            .revoke_passport(conn, circuit_id, "Admin revoke")
+            .await
+        {
+            Ok(Some(token)) if !tokens.contains(&token) => tokens.push(token),
+            Ok(_) => {}
+            Err(e) => {
+                tracing::error!(error = %e, circuit_id = %circuit_id, "Failed to revoke circuit passport");
+                return Err(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        }
+    }
+
+    let mut peers_notified = 0;
+    for token in &tokens {
+        if let Err(e) = state
+            .captcha_verifier
+            .revoke_passport(redis.as_mut(), token)
+            .await
+        {
+            tracing::error!(error = %e, "Failed to revoke passport");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+
+        if let Some(ref gossip) = state.gossip {
+            match gossip.broadcast_revocation(token.as_str()).await {
+                Ok(sent) => peers_notified = peers_notified.max(sent),
+                Err(e) => tracing::warn!(error = %e, "Failed to push revocation to peers"),
+            }
+        }
+    }
+
+    tracing::info!(
+        revoked = tokens.len(),
+        circuit_id = ?circuit_id,
+        "Passports revoked by admin"
+    );
+    if !tokens.is_empty() {
+        state.events.publish(CerberusEvent::PassportsRevoked {
+            count: tokens.len(),
+            circuit_id,
+        });
+    }
+
+    Ok(RevokeResponse {
+        revoked: tokens.len(),
+        peers_notified,
+    })
+}
+
+#[derive(Serialize)]
+pub struct KeysResponse {
+    /// This node's current public key
+    pub public_key: Option<String>,
+    /// Keys from before a rotation that still validate
+    pub retired: Vec<RetiredKeyInfo>,
+}
+
+/// This node's passport key, and the rotated-out keys it still honours
+pub async fn get_keys(State(state): State<AppState>) -> Json<KeysResponse> {
+    Json(KeysResponse {
+        public_key: state.passports.public_key_b64(),
+        retired: state.passports.retired_keys().await,
+    })
+}
+
+/// Finalize a key rotation on this node: retired keys stop validating now
+///
+/// Check `fortify_passport_retired_key_validations_total` has stopped
+/// growing first. Per node, like the overlap itself.
+pub async fn finalize_keys(State(state): State<AppState>) -> Json<KeysResponse> {
+    let finalized = state.passports.finalize_rotation().await;
+    Json(KeysResponse {
+        public_key: state.passports.public_key_b64(),
+        retired: finalized,
+    })
+}
+
+#[derive(Deserialize)]
+pub struct JourneyRequest {
+    pub token: PassportToken,
+}
+
+/// A passport's way in, from gate render to its first validated request
+///
+/// Returns:
+/// - 200: The journey (`first_request_at_ms` is null until it's complete)
+/// - 404: No journey: unknown or expired passport, or one issued without
+///   a challenge
+/// - 503: Redis offline
+///
+/// The token travels in the body so it stays out of access logs.
+pub async fn get_journey(
+    State(state): State<AppState>,
+    Json(request): Json<JourneyRequest>,
+) -> Result<Json<Journey>, StatusCode> {
+    let mut redis = state.redis().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+
+    match state.journeys.get(&mut redis, &request.token).await {
+        Ok(Some(journey)) => Ok(Json(journey)),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to read passport journey");
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
     }
 }
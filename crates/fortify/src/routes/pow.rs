@@ -0,0 +1,193 @@
+//! Gate proof-of-work answers (progressive challenge flow).
+//!
+//! Browsers running JavaScript solve the puzzle on the gate page and post
+//! the nonce here. A solution is spent in Redis so it's good for one page,
+//! then rewarded as `captcha.flow` says for the current threat level: a
+//! passport straight away, or an easier visual challenge.
+
+use axum::{
+    Form,
+    extract::State,
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use cerberus_common::redis_keys;
+use redis::{AsyncCommands, ExistenceCheck, SetExpiry, SetOptions};
+use serde::Deserialize;
+
+use super::{
+    circuit_id_from_headers, image_format, load_gate_session, passport_redirect, rate_limit,
+    serve_gate_page,
+};
+use crate::captcha::{PowCheck, PowPuzzle};
+use crate::config::PowReward;
+use crate::gate_session;
+use crate::metrics;
+use crate::state::AppState;
+
+/// Hidden fields of the gate page's proof-of-work form
+#[derive(Deserialize)]
+pub struct PowForm {
+    pub seed: String,
+    pub nonce: u64,
+    #[serde(default)]
+    pub return_to: Option<String>,
+    #[serde(default)]
+    pub return_sig: Option<String>,
+}
+
+/// Check a proof-of-work from the gate page
+///
+/// Anything but a fresh solution gets the usual gate page, so a visitor is
+/// never worse off for running the script.
+pub async fn solve_pow(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Form(form): Form<PowForm>,
+) -> Response {
+    let circuit_id = circuit_id_from_headers(&headers);
+    let format = image_format(&state, &headers);
+    let session_id = gate_session::from_headers(&headers);
+
+    // Solutions are spent in Redis; offline, fall back to the usual page
+    let Some(mut redis) = state.redis() else {
+        return serve_gate_page(state, circuit_id, format, None, None, None, false).await;
+    };
+
+    if let Some(ref circuit_id) = circuit_id {
+        match state
+            .circuit_tracker
+            .is_allowed(&mut redis, circuit_id)
+            .await
+        {
+            Ok((true, _)) => {}
+            Ok((false, reason)) => {
+                return (
+                    StatusCode::FORBIDDEN,
+                    reason.unwrap_or_else(|| "Access denied".to_string()),
+                )
+                    .into_response();
+            }
+            Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+        }
+    }
+    let limits = match rate_limit::check(&state, &mut redis, circuit_id.as_ref()).await {
+        Ok(limits) => limits,
+        Err(status) => return status.into_response(),
+    };
+    if !limits.allowed() {
+        return limits.too_many_requests();
+    }
+
+    let session = match session_id {
+        Some(ref id) => load_gate_session(&state, &mut redis, id).await,
+        None => None,
+    };
+    let live_session = session_id.as_ref().filter(|_| session.is_some());
+    let return_to = match (&form.return_to, &form.return_sig) {
+        (Some(path), Some(sig)) => state.gate_sessions.open_return_to(path, sig),
+        _ => None,
+    }
+    .or_else(|| session.and_then(|s| s.return_to));
+
+    let flow = &state.config.captcha.flow;
+    let threat_level = state.get_threat_level().await;
+    let check = PowPuzzle::check(
+        &state.sealer,
+        &form.seed,
+        form.nonce,
+        flow.pow_bits,
+        circuit_id.as_ref(),
+    );
+    let outcome = match check {
+        PowCheck::Solved { expires_at } => match spend(&mut redis, &form.seed, expires_at).await {
+            Ok(true) => "solved",
+            Ok(false) => "replayed",
+            Err(e) => {
+                tracing::warn!(error = %e, "Failed to spend proof-of-work");
+                "replayed"
+            }
+        },
+        PowCheck::Unsolved => "unsolved",
+        PowCheck::Expired => "expired",
+        PowCheck::Invalid => "invalid",
+    };
+    metrics::POW_SOLUTIONS.with_label_values(&[outcome]).inc();
+    let solved = outcome == "solved";
+
+    let response = match flow.reward(threat_level.captcha_difficulty()) {
+        PowReward::Skip if solved => {
+            match state
+                .verification
+                .pow_pass(&mut redis, circuit_id.as_ref(), threat_level)
+                .await
+            {
+                Ok(grant) => {
+                    let mut response = passport_redirect(&grant.token, return_to.as_deref());
+                    if let Some(ref id) = session_id {
+                        if let Err(e) = state.gate_sessions.end(&mut redis, id).await {
+                            tracing::warn!(error = %e, "Failed to end gate session");
+                        }
+                        response
+                            .headers_mut()
+                            .insert(header::SET_COOKIE, state.gate_sessions.expired_cookie());
+                    }
+                    response
+                }
+                Err(e) => {
+                    tracing::error!(error = %e, "Proof-of-work pass failed");
+                    serve_gate_page(
+                        state,
+                        circuit_id,
+                        format,
+                        live_session,
+                        return_to.as_deref(),
+                        None,
+                        false,
+                    )
+                    .await
+                }
+            }
+        }
+        PowReward::Easier if solved => {
+            serve_gate_page(
+                state,
+                circuit_id,
+                format,
+                live_session,
+                return_to.as_deref(),
+                None,
+                true,
+            )
+            .await
+        }
+        _ => {
+            serve_gate_page(
+                state,
+                circuit_id,
+                format,
+                live_session,
+                return_to.as_deref(),
+                None,
+                false,
+            )
+            .await
+        }
+    };
+
+    (limits, response).into_response()
+}
+
+/// Mark a puzzle used until it expires; `false` if it already was
+async fn spend(
+    redis: &mut redis::aio::ConnectionManager,
+    seed: &str,
+    expires_at: i64,
+) -> redis::RedisResult<bool> {
+    let ttl = (expires_at - chrono::Utc::now().timestamp()).max(1) as u64;
+    let options = SetOptions::default()
+        .conditional_set(ExistenceCheck::NX)
+        .with_expiration(SetExpiry::EX(ttl));
+    let set: Option<String> = redis.set_options(redis_keys::pow(seed), 1, options).await?;
+    Ok(set.is_some())
+}
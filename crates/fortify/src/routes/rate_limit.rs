@@ -0,0 +1,97 @@
+//! Rate limit headers for public responses.
+//!
+//! Emits `RateLimit-Limit`, `RateLimit-Remaining`, `RateLimit-Reset` and,
+//! when the limit is exceeded, `Retry-After`, so well-behaved clients can
+//! self-throttle instead of hammering the gate.
+
+use axum::{
+    http::{HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, IntoResponseParts, Response, ResponseParts},
+};
+
+use cerberus_common::CircuitId;
+
+use crate::circuits::RateLimitStatus;
+use crate::state::AppState;
+
+const RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+const RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+const RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+
+/// Response headers describing a circuit's rate limit state
+///
+/// Circuits without an ID are not rate limited and get no headers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RateLimitHeaders(pub Option<RateLimitStatus>);
+
+impl RateLimitHeaders {
+    /// Is the request within its limit (or unlimited)?
+    pub fn allowed(&self) -> bool {
+        self.0.is_none_or(|s| s.allowed)
+    }
+
+    /// A 429 response carrying these headers
+    pub fn too_many_requests(self) -> Response {
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            self,
+            "Too many requests. Please slow down.",
+        )
+            .into_response()
+    }
+}
+
+impl IntoResponseParts for RateLimitHeaders {
+    type Error = std::convert::Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        if let Some(status) = self.0 {
+            let headers = res.headers_mut();
+            headers.insert(RATELIMIT_LIMIT, HeaderValue::from(status.limit));
+            headers.insert(RATELIMIT_REMAINING, HeaderValue::from(status.remaining));
+            headers.insert(RATELIMIT_RESET, HeaderValue::from(status.reset_secs));
+            if !status.allowed {
+                headers.insert(
+                    axum::http::header::RETRY_AFTER,
+                    HeaderValue::from(status.reset_secs),
+                );
+            }
+        }
+        Ok(res)
+    }
+}
+
+/// Count a request against the circuit's per-minute budget
+///
+/// Fails closed: a Redis error yields 500 rather than an unlimited request.
+pub async fn check(
+    state: &AppState,
+    redis: &mut redis::aio::ConnectionManager,
+    circuit_id: Option<&CircuitId>,
+) -> Result<RateLimitHeaders, StatusCode> {
+    let Some(circuit_id) = circuit_id else {
+        return Ok(RateLimitHeaders(None));
+    };
+
+    // Requests the circuit made on peers count too, in cluster mode
+    let now = chrono::Utc::now().timestamp() as u64;
+    let elsewhere = state.rate_share.as_ref().map_or(0, |share| {
+        share.record_local(circuit_id, now);
+        share.elsewhere(circuit_id, now)
+    });
+
+    state
+        .circuit_tracker
+        .check_rate_limit(
+            redis,
+            circuit_id,
+            state.config.rate_limit.max_requests_per_minute,
+            elsewhere,
+        )
+        .await
+        .map(|status| RateLimitHeaders(Some(status)))
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to check rate limit");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
@@ -0,0 +1,245 @@
+//! Per-route request metrics.
+//!
+//! Every request is timed and counted under its route template (e.g.
+//! `/circuits/{circuit_id}`, never the circuit itself) with its method and
+//! status class, and counted in flight while it's being handled. Requests no
+//! route matched share the `unmatched` label, so scans can't blow up the
+//! label set. Admin routes all share `/admin/*`, so the public metrics
+//! don't give away a secret admin mount path; request spans (and so the
+//! logs) show admin paths as `/admin/*` too.
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::Method,
+    middleware::Next,
+    response::Response,
+};
+use prometheus::IntGauge;
+use std::sync::Arc;
+use std::time::Instant;
+use tower_http::trace::MakeSpan;
+use tracing::Span;
+
+use crate::{metrics, telemetry};
+
+/// Label for requests no route matched
+const UNMATCHED: &str = "unmatched";
+
+/// Label for every admin route
+const ADMIN: &str = "/admin/*";
+
+/// Maps matched routes to metric labels
+#[derive(Debug, Clone)]
+pub struct RouteLabels {
+    /// Where the admin router is mounted (see `admin_access::mount_path`)
+    admin_path: String,
+}
+
+impl RouteLabels {
+    pub fn new(admin_path: &str) -> Self {
+        Self {
+            admin_path: admin_path.to_string(),
+        }
+    }
+
+    fn label<'a>(&self, matched: Option<&'a str>) -> &'a str {
+        match matched {
+            None => UNMATCHED,
+            Some(path) if self.is_admin(path) => ADMIN,
+            Some(path) => path,
+        }
+    }
+
+    /// A request path fit to log, admin paths all being `/admin/*`
+    fn path<'a>(&self, path: &'a str) -> &'a str {
+        if self.is_admin(path) { ADMIN } else { path }
+    }
+
+    fn is_admin(&self, path: &str) -> bool {
+        path.strip_prefix(self.admin_path.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    }
+}
+
+impl<B> MakeSpan<B> for RouteLabels {
+    fn make_span(&mut self, request: &axum::http::Request<B>) -> Span {
+        telemetry::request_span(request, self.path(request.uri().path()))
+    }
+}
+
+/// Record latency, status class and in-flight count for the request's route
+pub async fn track(
+    State(labels): State<Arc<RouteLabels>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let route = labels
+        .label(
+            request
+                .extensions()
+                .get::<MatchedPath>()
+                .map(MatchedPath::as_str),
+        )
+        .to_string();
+    let method = method_label(request.method());
+
+    let started = Instant::now();
+    let in_flight = InFlight::enter(&route);
+    let response = next.run(request).await;
+    drop(in_flight);
+
+    metrics::HTTP_REQUEST_SECONDS
+        .with_label_values(&[&route, method])
+        .observe(started.elapsed().as_secs_f64());
+    metrics::HTTP_RESPONSES
+        .with_label_values(&[&route, method, status_class(response.status().as_u16())])
+        .inc();
+    response
+}
+
+/// Counts a request in flight until dropped (also when the client goes away
+/// mid-request and the handler is cancelled)
+struct InFlight(IntGauge);
+
+impl InFlight {
+    fn enter(route: &str) -> Self {
+        let gauge = metrics::HTTP_REQUESTS_IN_FLIGHT.with_label_values(&[route]);
+        gauge.inc();
+        Self(gauge)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// Standard methods by name; anything else is `other`
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::DELETE => "DELETE",
+        Method::HEAD => "HEAD",
+        Method::OPTIONS => "OPTIONS",
+        Method::PATCH => "PATCH",
+        _ => "other",
+    }
+}
+
+fn status_class(status: u16) -> &'static str {
+    match status {
+        100..=199 => "1xx",
+        200..=299 => "2xx",
+        300..=399 => "3xx",
+        400..=499 => "4xx",
+        _ => "5xx",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{Router, body::Body, routing::get};
+    use std::io;
+    use std::sync::Mutex;
+    use tower::ServiceExt;
+    use tower_http::trace::TraceLayer;
+
+    #[tokio::test]
+    async fn test_labels_by_route_template() {
+        let app = Router::new()
+            .route("/metrics-test/{id}", get(|| async { "ok" }))
+            .nest(
+                "/metrics-admin",
+                Router::new().route("/stats", get(|| async { "ok" })),
+            )
+            .nest(
+                "/s3cr3t/admin",
+                Router::new().route("/bans", get(|| async { "ok" })),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                Arc::new(RouteLabels::new("/s3cr3t/admin")),
+                track,
+            ));
+
+        for uri in [
+            "/metrics-test/a",
+            "/metrics-test/b",
+            "/metrics-admin/stats",
+            "/s3cr3t/admin/bans",
+            "/no-such-route",
+        ] {
+            let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            app.clone().oneshot(request).await.unwrap();
+        }
+
+        let ok = metrics::HTTP_RESPONSES.with_label_values(&["/metrics-test/{id}", "GET", "2xx"]);
+        assert_eq!(ok.get(), 2);
+        // Nested routes keep their full path
+        let nested =
+            metrics::HTTP_RESPONSES.with_label_values(&["/metrics-admin/stats", "GET", "2xx"]);
+        assert_eq!(nested.get(), 1);
+        // The admin mount path never shows up in a label
+        let admin = metrics::HTTP_RESPONSES.with_label_values(&[ADMIN, "GET", "2xx"]);
+        assert!(admin.get() >= 1);
+        let leaked =
+            metrics::HTTP_RESPONSES.with_label_values(&["/s3cr3t/admin/bans", "GET", "2xx"]);
+        assert_eq!(leaked.get(), 0);
+        let in_flight = metrics::HTTP_REQUESTS_IN_FLIGHT.with_label_values(&["/metrics-test/{id}"]);
+        assert_eq!(in_flight.get(), 0);
+        assert!(
+            metrics::HTTP_RESPONSES
+                .with_label_values(&[UNMATCHED, "GET", "4xx"])
+                .get()
+                >= 1
+        );
+    }
+
+    /// Log output, kept for inspection
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_admin_path_hidden_from_request_spans() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let handler = || async {
+            tracing::info!("handled");
+            "ok"
+        };
+        let app = Router::new()
+            .route("/span-test", get(handler))
+            .nest("/s3cr3t/admin", Router::new().route("/bans", get(handler)))
+            .layer(TraceLayer::new_for_http().make_span_with(RouteLabels::new("/s3cr3t/admin")));
+
+        for uri in ["/s3cr3t/admin/bans", "/span-test"] {
+            let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+            app.clone().oneshot(request).await.unwrap();
+        }
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("path=/admin/*"), "{output}");
+        assert!(output.contains("path=/span-test"), "{output}");
+        assert!(!output.contains("s3cr3t"), "{output}");
+    }
+}
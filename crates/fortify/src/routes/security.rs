@@ -0,0 +1,155 @@
+//! Security response headers and CORS per route group.
+//!
+//! Every route group (gate pages, JSON API, admin) gets its own
+//! [`HeaderPolicy`]: CSP, X-Frame-Options, Referrer-Policy, nosniff and
+//! optional CORS rules. Header values are parsed once at router build time
+//! so a bad config value fails startup instead of individual requests.
+
+use anyhow::{Context, Result};
+use axum::{
+    Router,
+    http::{HeaderMap, HeaderName, HeaderValue, Method, header},
+    response::Response,
+};
+use std::sync::Arc;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::config::{CorsPolicy, HeaderPolicy};
+use crate::state::AppState;
+
+/// Apply a header policy (and optional CORS layer) to a route group
+pub fn apply(router: Router<AppState>, policy: &HeaderPolicy) -> Result<Router<AppState>> {
+    let headers = Arc::new(build_headers(policy)?);
+
+    let router = router.layer(axum::middleware::map_response_with_state(
+        headers,
+        add_headers,
+    ));
+
+    match policy.cors {
+        Some(ref cors) => Ok(router.layer(build_cors(cors)?)),
+        None => Ok(router),
+    }
+}
+
+async fn add_headers(
+    axum::extract::State(headers): axum::extract::State<Arc<HeaderMap>>,
+    mut response: Response,
+) -> Response {
+    for (name, value) in headers.iter() {
+        // Handlers may set a more specific value (e.g. a relaxed CSP)
+        if !response.headers().contains_key(name) {
+            response.headers_mut().insert(name.clone(), value.clone());
+        }
+    }
+    response
+}
+
+fn build_headers(policy: &HeaderPolicy) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+
+    let mut insert = |name: HeaderName, value: &Option<String>| -> Result<()> {
+        // TOML has no null, so "" is how a config turns a header off
+        if let Some(value) = value.as_deref().filter(|value| !value.is_empty()) {
+            let parsed = HeaderValue::from_str(value)
+                .with_context(|| format!("Invalid value for {}: {:?}", name, value))?;
+            headers.insert(name, parsed);
+        }
+        Ok(())
+    };
+
+    insert(
+        header::CONTENT_SECURITY_POLICY,
+        &policy.content_security_policy,
+    )?;
+    insert(header::X_FRAME_OPTIONS, &policy.frame_options)?;
+    insert(header::REFERRER_POLICY, &policy.referrer_policy)?;
+
+    if policy.nosniff {
+        headers.insert(
+            header::X_CONTENT_TYPE_OPTIONS,
+            HeaderValue::from_static("nosniff"),
+        );
+    }
+
+    Ok(headers)
+}
+
+fn build_cors(cors: &CorsPolicy) -> Result<CorsLayer> {
+    let origins = if cors.allowed_origins.iter().any(|o| o == "*") {
+        AllowOrigin::any()
+    } else {
+        let list = cors
+            .allowed_origins
+            .iter()
+            .map(|o| {
+                HeaderValue::from_str(o).with_context(|| format!("Invalid CORS origin {:?}", o))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        AllowOrigin::list(list)
+    };
+
+    let methods = cors
+        .allowed_methods
+        .iter()
+        .map(|m| {
+            Method::from_bytes(m.as_bytes()).with_context(|| format!("Invalid CORS method {:?}", m))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let allow_headers = cors
+        .allowed_headers
+        .iter()
+        .map(|h| {
+            HeaderName::from_bytes(h.as_bytes())
+                .with_context(|| format!("Invalid CORS header {:?}", h))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(methods)
+        .allow_headers(allow_headers)
+        .max_age(cors.max_age_secs.duration()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_headers_skips_unset() {
+        let policy = HeaderPolicy {
+            content_security_policy: None,
+            frame_options: Some("DENY".to_string()),
+            referrer_policy: None,
+            nosniff: false,
+            cors: None,
+        };
+        let headers = build_headers(&policy).unwrap();
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers[header::X_FRAME_OPTIONS], "DENY");
+    }
+
+    #[test]
+    fn test_build_headers_skips_empty() {
+        let policy: HeaderPolicy =
+            toml::from_str("content_security_policy = \"\"\nframe_options = \"\"").unwrap();
+        let headers = build_headers(&policy).unwrap();
+        assert!(!headers.contains_key(header::CONTENT_SECURITY_POLICY));
+        assert!(!headers.contains_key(header::X_FRAME_OPTIONS));
+        assert_eq!(headers[header::REFERRER_POLICY], "no-referrer");
+    }
+
+    #[test]
+    fn test_invalid_header_value_rejected() {
+        let policy = HeaderPolicy {
+            content_security_policy: Some("default-src\n'none'".to_string()),
+            frame_options: None,
+            referrer_policy: None,
+            nosniff: true,
+            cors: None,
+        };
+        assert!(build_headers(&policy).is_err());
+    }
+}
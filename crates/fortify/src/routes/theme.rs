@@ -0,0 +1,464 @@
+//! Gate page theme: the cached stylesheet and page template.
+//!
+//! The stylesheet is compiled in and served at `/gate/theme.css` with an
+//! ETag and a day of `Cache-Control`, so a Tor Browser session downloads it
+//! once instead of with every challenge; the optional proof-of-work script
+//! (`/gate/pow.js`) is served the same way. The page itself changes with
+//! every challenge, but everything around the challenge depends only on the
+//! threat level; that markup is rendered once per level and reused.
+//!
+//! The page counts down to its challenge's expiry and, without JavaScript,
+//! reloads itself then through a meta refresh to `/captcha.html`, so a
+//! visitor who left the tab open is never handed an expired challenge.
+
+use axum::{
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use cerberus_common::{CaptchaChallenge, GateFormNonce, ThreatLevel};
+use sha2::{Digest, Sha256};
+use std::sync::{LazyLock, OnceLock};
+
+use super::html_escape;
+use crate::captcha::{PowPuzzle, ShadowChallenge};
+use crate::gate_session::SignedReturnTo;
+
+/// Where the gate page links its stylesheet
+pub const THEME_PATH: &str = "/gate/theme.css";
+
+/// Where the expired gate page reloads a fresh challenge
+const RELOAD_PATH: &str = "/captcha.html";
+
+/// Where the gate page loads the proof-of-work script
+pub const POW_SCRIPT_PATH: &str = "/gate/pow.js";
+
+const THEME_CSS: &str = include_str!("../../assets/gate/theme.css");
+
+const POW_JS: &str = include_str!("../../assets/gate/pow.js");
+
+/// Browsers may reuse the stylesheet this long without asking
+const THEME_CACHE_CONTROL: &str = "public, max-age=86400";
+
+/// Threat level from which the page tells visitors protection is raised
+const HEIGHTENED_FROM: u8 = 7;
+
+/// Strong ETag over the stylesheet contents
+static THEME_ETAG: LazyLock<String> = LazyLock::new(|| strong_etag(THEME_CSS));
+
+/// Strong ETag over the script contents
+static POW_ETAG: LazyLock<String> = LazyLock::new(|| strong_etag(POW_JS));
+
+fn strong_etag(contents: &str) -> String {
+    let digest = Sha256::digest(contents.as_bytes());
+    let hex: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("\"{}\"", hex)
+}
+
+/// Serve the stylesheet, or 304 when the client's copy is current
+pub async fn serve_theme(headers: HeaderMap) -> Response {
+    serve_cached(&headers, "text/css; charset=utf-8", &THEME_ETAG, THEME_CSS)
+}
+
+/// Serve the proof-of-work script, or 304 when the client's copy is current
+pub async fn serve_pow_script(headers: HeaderMap) -> Response {
+    serve_cached(
+        &headers,
+        "text/javascript; charset=utf-8",
+        &POW_ETAG,
+        POW_JS,
+    )
+}
+
+fn serve_cached(
+    headers: &HeaderMap,
+    content_type: &'static str,
+    etag_value: &str,
+    body: &'static str,
+) -> Response {
+    let etag = HeaderValue::from_str(etag_value).expect("hex ETag is a valid header value");
+    let cache_control = HeaderValue::from_static(THEME_CACHE_CONTROL);
+
+    if headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| etag_matches(value, etag_value))
+    {
+        return (
+            StatusCode::NOT_MODIFIED,
+            [(header::ETAG, etag), (header::CACHE_CONTROL, cache_control)],
+        )
+            .into_response();
+    }
+
+    (
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static(content_type)),
+            (header::ETAG, etag),
+            (header::CACHE_CONTROL, cache_control),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+/// Weak comparison (RFC 9110), as `If-None-Match` calls for
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+/// Hidden fields the gate form carries back to `/verify`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FormFields<'a> {
+    /// Page to return to after solving
+    pub return_to: Option<&'a SignedReturnTo>,
+    /// Spent when the form is processed (only issued while Redis is up)
+    pub nonce: Option<&'a GateFormNonce>,
+}
+
+/// The gate page split around its per-challenge values
+pub struct GateTemplate {
+    /// Static markup; the values go between consecutive pieces
+    pieces: Vec<String>,
+}
+
+/// Placeholders, in the order they appear in the page
+#[derive(Clone, Copy)]
+enum Slot {
+    Refresh,
+    Error,
+    ChallengeId,
+    Hidden,
+    Image,
+    Instructions,
+    Countdown,
+    Shadow,
+    Pow,
+}
+
+const SLOTS: [Slot; 10] = [
+    Slot::Refresh,
+    Slot::Error,
+    Slot::ChallengeId,
+    Slot::Hidden,
+    Slot::Image,
+    Slot::Instructions,
+    Slot::Countdown,
+    Slot::Shadow,
+    Slot::ChallengeId,
+    Slot::Pow,
+];
+
+/// Marks a slot in the rendered shell (never produced by escaped text)
+const MARKER: &str = "\u{0}slot\u{0}";
+
+impl GateTemplate {
+    /// The template for `threat_level`, rendered on first use
+    pub fn for_level(threat_level: ThreatLevel) -> &'static GateTemplate {
+        // One per threat level, 0-10
+        static TEMPLATES: [OnceLock<GateTemplate>; 11] = [const { OnceLock::new() }; 11];
+        TEMPLATES[threat_level.value() as usize].get_or_init(|| Self::render(threat_level))
+    }
+
+    fn render(threat_level: ThreatLevel) -> Self {
+        let subtitle = if threat_level.value() >= HEIGHTENED_FROM {
+            "Heightened protection is active"
+        } else {
+            "Human verification required"
+        };
+        let html = format!(
+            r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    {slot}
+    <title>Sigil - Verification Required</title>
+    <link rel="stylesheet" href="{theme}">
+</head>
+<body>
+    <div class="container">
+        <div class="brand">
+            <span class="brand-logo">🔒</span>
+            <div class="brand-text">
+                <h1>Sigil</h1>
+                <p class="subtitle">{subtitle}</p>
+            </div>
+        </div>
+
+        {slot}
+
+        <form method="POST" action="/verify">
+            <input type="hidden" name="challenge_id" value="{slot}">
+            {slot}
+
+            <div class="captcha-box">
+                <div class="captcha-image">
+                    {slot}
+                </div>
+                <p class="instructions">{slot}</p>
+                {slot}
+            </div>
+
+            <input type="text"
+                   class="answer-input"
+                   name="answer"
+                   placeholder="Enter code"
+                   autocomplete="off"
+                   autocapitalize="off"
+                   spellcheck="false"
+                   maxlength="8"
+                   autofocus
+                   required>
+
+            {slot}
+
+            <button type="submit" class="submit-btn">Verify</button>
+
+            <button type="submit"
+                    class="refresh-link"
+                    formaction="/challenge/{slot}/refresh"
+                    formnovalidate>↻ New Challenge</button>
+        </form>
+
+        {slot}
+
+        <div class="footer">
+            Protected by Cerberus • No JavaScript required
+        </div>
+    </div>
+</body>
+</html>"##,
+            theme = THEME_PATH,
+            subtitle = subtitle,
+            slot = MARKER,
+        );
+
+        let pieces: Vec<String> = html.split(MARKER).map(str::to_string).collect();
+        debug_assert_eq!(pieces.len(), SLOTS.len() + 1);
+        Self { pieces }
+    }
+
+    /// The page for one challenge, offering `pow` to browsers running
+    /// JavaScript and `shadow` as an optional extra puzzle
+    pub fn page(
+        &self,
+        challenge: &CaptchaChallenge,
+        image_html: &str,
+        form: FormFields<'_>,
+        error: Option<&str>,
+        pow: Option<&PowPuzzle>,
+        shadow: Option<&ShadowChallenge>,
+    ) -> String {
+        let challenge_id = html_escape(challenge.challenge_id.as_str());
+        let instructions = html_escape(&challenge.instructions);
+        // Reload once expired, at least a second out so a page rendered at
+        // the deadline doesn't reload in a loop
+        let expires_in = super::expires_in_secs(challenge.expires_at).max(1);
+        let reload_url = match form.return_to {
+            Some(return_to) => format!(
+                "{}?return_to={}",
+                RELOAD_PATH,
+                urlencoding::encode(&return_to.path)
+            ),
+            None => RELOAD_PATH.to_string(),
+        };
+        let refresh_html = format!(
+            r#"<meta http-equiv="refresh" content="{};url={}">"#,
+            expires_in,
+            html_escape(&reload_url)
+        );
+        let countdown_html = format!(
+            r#"<p class="countdown" style="--expires-in: {secs}s">Expires in {secs}s, then a new challenge loads by itself.</p>"#,
+            secs = expires_in
+        );
+        let return_to = form.return_to;
+        let return_to_html = match return_to {
+            Some(return_to) => format!(
+                r#"<input type="hidden" name="return_to" value="{}">
+            <input type="hidden" name="return_sig" value="{}">"#,
+                html_escape(&return_to.path),
+                html_escape(&return_to.signature)
+            ),
+            None => String::new(),
+        };
+        let hidden_html = match form.nonce {
+            Some(nonce) => format!(
+                r#"{}
+            <input type="hidden" name="form_nonce" value="{}">"#,
+                return_to_html,
+                html_escape(nonce.as_str())
+            ),
+            None => return_to_html.clone(),
+        };
+        let error_html = match error {
+            Some(msg) => format!(
+                r#"<div class="error" style="display:block">{}</div>"#,
+                html_escape(msg)
+            ),
+            None => String::new(),
+        };
+        let shadow_html = match shadow {
+            Some(shadow) => format!(
+                r#"<div class="captcha-box">
+                <p class="instructions">Optional: help us test a new challenge. Your answer here doesn't affect verification.</p>
+                <div class="captcha-image">
+                    {image}
+                </div>
+                <p class="instructions">{instructions}</p>
+                <input type="hidden" name="shadow_id" value="{id}">
+                <input type="text"
+                       class="answer-input"
+                       name="shadow_answer"
+                       placeholder="Optional"
+                       autocomplete="off"
+                       autocapitalize="off"
+                       spellcheck="false"
+                       maxlength="16">
+            </div>"#,
+                image = super::image_html(&shadow.image_data),
+                instructions = html_escape(&shadow.instructions),
+                id = html_escape(shadow.shadow_id.as_str()),
+            ),
+            None => String::new(),
+        };
+        let pow_html = match pow {
+            Some(pow) => format!(
+                r#"<form id="pow" method="POST" action="/gate/pow" data-bits="{bits}" hidden>
+            <input type="hidden" name="seed" value="{seed}">
+            <input type="hidden" name="nonce" value="">
+            {return_to}
+        </form>
+        <p id="pow-status" class="instructions" hidden>Checking your browser…</p>
+        <script src="{script}"></script>"#,
+                bits = pow.bits,
+                seed = html_escape(&pow.seed),
+                return_to = return_to_html,
+                script = POW_SCRIPT_PATH,
+            ),
+            None => String::new(),
+        };
+
+        let mut html = String::with_capacity(
+            self.pieces.iter().map(String::len).sum::<usize>() + image_html.len() + 512,
+        );
+        for (piece, slot) in self.pieces.iter().zip(SLOTS.iter().map(Some).chain([None])) {
+            html.push_str(piece);
+            html.push_str(match slot {
+                Some(Slot::Refresh) => &refresh_html,
+                Some(Slot::Error) => &error_html,
+                Some(Slot::ChallengeId) => &challenge_id,
+                Some(Slot::Hidden) => &hidden_html,
+                Some(Slot::Image) => image_html,
+                Some(Slot::Instructions) => &instructions,
+                Some(Slot::Countdown) => &countdown_html,
+                Some(Slot::Shadow) => &shadow_html,
+                Some(Slot::Pow) => &pow_html,
+                None => "",
+            });
+        }
+        html
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_theme_etag() {
+        assert!(etag_matches(&THEME_ETAG, &THEME_ETAG));
+        assert!(etag_matches(
+            &format!("\"x\", W/{}", *THEME_ETAG),
+            &THEME_ETAG
+        ));
+        assert!(etag_matches("*", &THEME_ETAG));
+        assert!(!etag_matches("\"stale\"", &THEME_ETAG));
+    }
+
+    #[test]
+    fn test_template_per_threat_level() {
+        let challenge = CaptchaChallenge {
+            challenge_id: "abc123".parse().unwrap(),
+            image_data: String::new(),
+            grid_size: (2, 2),
+            instructions: "Type <these> characters".to_string(),
+            expected_positions: Vec::new(),
+            expires_at: 0,
+        };
+
+        let calm = GateTemplate::for_level(ThreatLevel::new(2));
+        assert!(std::ptr::eq(
+            calm,
+            GateTemplate::for_level(ThreatLevel::new(2))
+        ));
+        let page = calm.page(
+            &challenge,
+            "<svg></svg>",
+            FormFields::default(),
+            Some("Wrong answer"),
+            None,
+            None,
+        );
+        assert!(!page.contains(MARKER));
+        assert!(page.contains("Human verification required"));
+        assert!(page.contains(r#"name="challenge_id" value="abc123""#));
+        assert!(page.contains(r#"formaction="/challenge/abc123/refresh""#));
+        assert!(page.contains("Type &lt;these&gt; characters"));
+        assert!(page.contains("<svg></svg>"));
+        assert!(page.contains("Wrong answer"));
+        // Long expired: reloads as soon as it can
+        assert!(page.contains(r#"<meta http-equiv="refresh" content="1;url=/captcha.html">"#));
+        assert!(page.contains("Expires in 1s"));
+        assert!(page.contains(THEME_PATH));
+        assert!(!page.contains(POW_SCRIPT_PATH));
+        assert!(!page.contains("shadow_answer"));
+        assert!(!page.contains("form_nonce"));
+
+        let pow = PowPuzzle {
+            seed: "1.abc.mac".to_string(),
+            bits: 18,
+        };
+        let challenge = CaptchaChallenge {
+            expires_at: chrono::Utc::now().timestamp() + 600,
+            ..challenge
+        };
+        let return_to = SignedReturnTo {
+            path: "/forum/?page=2&sort=new".to_string(),
+            signature: "sig".to_string(),
+        };
+        let nonce: GateFormNonce = "n0nce".parse().unwrap();
+        let form = FormFields {
+            return_to: Some(&return_to),
+            nonce: Some(&nonce),
+        };
+        let page = GateTemplate::for_level(ThreatLevel::new(9)).page(
+            &challenge,
+            "",
+            form,
+            None,
+            Some(&pow),
+            Some(&ShadowChallenge {
+                shadow_id: "def456".parse().unwrap(),
+                image_data: "data:image/png;base64,AAAA".to_string(),
+                instructions: "Solve the sum".to_string(),
+            }),
+        );
+        assert!(page.contains("Heightened protection is active"));
+        assert!(page.contains(r#"name="shadow_id" value="def456""#));
+        assert!(page.contains(r#"<img src="data:image/png;base64,AAAA""#));
+        assert!(page.contains(r#"data-bits="18""#));
+        assert!(page.contains(r#"name="seed" value="1.abc.mac""#));
+        assert!(page.contains(POW_SCRIPT_PATH));
+        assert!(page.contains("url=/captcha.html?return_to=%2Fforum%2F%3Fpage%3D2%26sort%3Dnew"));
+        assert!(!page.contains(r#"content="1;"#));
+        // The nonce goes in the gate form, not the proof-of-work one
+        assert_eq!(
+            page.matches(r#"name="form_nonce" value="n0nce""#).count(),
+            1
+        );
+        assert_eq!(page.matches(r#"name="return_sig" value="sig""#).count(), 2);
+    }
+}
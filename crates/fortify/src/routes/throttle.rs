@@ -0,0 +1,127 @@
+//! Answer throttling in front of the gate and challenge API.
+//!
+//! A circuit over its answer budget, or locked out after a burst of wrong
+//! answers, is tarpitted: the request is held for `tarpit_delay_ms`, then
+//! turned away with 429 and a page that retries once the wait is over.
+
+use axum::{
+    extract::{Request, State},
+    http::{Method, StatusCode, header},
+    middleware::Next,
+    response::{Html, IntoResponse, Response},
+};
+
+use super::{circuit_id_from_headers, theme::THEME_PATH, wants_json};
+use crate::metrics;
+use crate::state::AppState;
+
+/// Routes a locked-out circuit is kept away from (plus challenge refreshes)
+const GATE_PATHS: [&str; 6] = [
+    "/",
+    "/captcha.html",
+    "/gate/fragment",
+    "/gate/pow",
+    "/challenge",
+    "/verify",
+];
+
+/// Tarpit circuits that answer too often or are locked out of the gate
+///
+/// Only counts with Redis; offline, sealed challenges are checked without
+/// any per-circuit state and the general rate limit is all there is.
+pub async fn guard(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let path = request.uri().path();
+    if !GATE_PATHS.contains(&path) && !path.starts_with("/challenge/") {
+        return next.run(request).await;
+    }
+    let (Some(circuit_id), Some(mut redis)) =
+        (circuit_id_from_headers(request.headers()), state.redis())
+    else {
+        return next.run(request).await;
+    };
+    let json = wants_json(request.headers());
+
+    match state.throttle.locked_for(&mut redis, &circuit_id).await {
+        Ok(Some(retry_after)) => return tarpit(&state, "locked", retry_after, json).await,
+        Ok(None) => {}
+        Err(e) => {
+            tracing::warn!(error = %e, circuit_id = %circuit_id, "Failed to check gate lockout")
+        }
+    }
+
+    if path == "/verify" && request.method() == Method::POST {
+        match state.throttle.count_attempt(&mut redis, &circuit_id).await {
+            Ok(Some(retry_after)) => return tarpit(&state, "attempts", retry_after, json).await,
+            Ok(None) => {}
+            Err(e) => {
+                tracing::warn!(error = %e, circuit_id = %circuit_id, "Failed to count answer")
+            }
+        }
+    }
+
+    next.run(request).await
+}
+
+/// Hold the request, then send it away until `retry_after`
+async fn tarpit(state: &AppState, reason: &str, retry_after: u64, json: bool) -> Response {
+    metrics::VERIFY_THROTTLED.with_label_values(&[reason]).inc();
+    tokio::time::sleep(state.throttle.tarpit_delay()).await;
+
+    let retry = [(header::RETRY_AFTER, retry_after)];
+    if json {
+        (StatusCode::TOO_MANY_REQUESTS, retry, "Too many answers").into_response()
+    } else {
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            retry,
+            Html(render_page(retry_after)),
+        )
+            .into_response()
+    }
+}
+
+/// The "slow down" page, in the gate's theme; retries by meta refresh
+fn render_page(retry_after: u64) -> String {
+    format!(
+        r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1.0">
+    <meta http-equiv="refresh" content="{retry_after}">
+    <title>Sigil - Slow Down</title>
+    <link rel="stylesheet" href="{theme}">
+</head>
+<body>
+    <div class="container">
+        <div class="brand">
+            <span class="brand-logo">⏳</span>
+            <div class="brand-text">
+                <h1>Sigil</h1>
+                <p class="subtitle">Too many attempts</p>
+            </div>
+        </div>
+        <p class="instructions">Too many answers in a short time. You can try again in {retry_after} seconds; this page will reload by itself.</p>
+        <div class="footer">
+            Protected by Cerberus
+        </div>
+    </div>
+</body>
+</html>"##,
+        theme = THEME_PATH,
+        retry_after = retry_after,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_retries() {
+        let page = render_page(42);
+        assert!(page.contains(r#"content="42""#));
+        assert!(page.contains("try again in 42 seconds"));
+        assert!(page.contains(THEME_PATH));
+    }
+}
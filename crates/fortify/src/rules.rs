@@ -0,0 +1,711 @@
+//! Attack signature rules: programmable auto-mitigation.
+//!
+//! Requests reaching the gate and passport validation, and challenge
+//! outcomes, are recorded as observations. Every `interval_secs` the
+//! analyzer looks at the last `window_secs` of them and checks each rule
+//! (`[[rules.rule]]`) against per-circuit or node-wide counts: request rate,
+//! hits on a path pattern, requests with header anomalies, and the challenge
+//! failure ratio. A rule whose conditions all hold fires its action (ban the
+//! circuit, raise the threat level, or switch the challenge provider), then
+//! stays quiet for `cooldown_secs` (per circuit for circuit rules). Raising
+//! the threat level is skipped while the `auto_escalation` flag is off.
+
+use anyhow::{Context, Result, bail};
+use axum::{
+    extract::{Request, State},
+    http::{HeaderMap, header},
+    middleware::Next,
+    response::Response,
+};
+use cerberus_common::{BoundedCache, CerberusEvent, CircuitId, EventPublisher};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::cluster::Proposal;
+use crate::enforcement;
+use crate::flags::Flag;
+use crate::metrics;
+use crate::state::AppState;
+
+/// Observations kept at most (oldest dropped first)
+const MAX_OBSERVATIONS: usize = 100_000;
+
+/// Cooldowns kept at most, and the memory they may hold; past either, the
+/// least recently fired are forgotten (that rule may fire early for them)
+const MAX_COOLDOWNS: usize = 50_000;
+const MAX_COOLDOWN_BYTES: usize = 8 << 20;
+
+type CooldownKey = (usize, Option<CircuitId>);
+
+/// User agents of HTTP libraries and command line tools
+const SCRIPTED_AGENTS: &[&str] = &[
+    "curl",
+    "wget",
+    "python",
+    "go-http-client",
+    "libwww",
+    "java/",
+    "okhttp",
+    "scrapy",
+    "httpclient",
+];
+
+/// Rules engine configuration (`[rules]`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RulesConfig {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// How often rules are evaluated
+    #[serde(default = "default_interval")]
+    pub interval_secs: u64,
+
+    /// How far back rules look
+    #[serde(default = "default_window")]
+    pub window_secs: u64,
+
+    /// The rules (`[[rules.rule]]`)
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<Rule>,
+}
+
+impl Default for RulesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: default_interval(),
+            window_secs: default_window(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// An attack signature and the response to it
+///
+/// Every condition given must hold; a rule needs at least one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+    pub name: String,
+
+    /// Counted per circuit or across the node
+    #[serde(default)]
+    pub scope: RuleScope,
+
+    /// At least this many requests in the window
+    pub min_requests: Option<u32>,
+
+    /// Regex on the requested path (`X-Original-URI` when proxied)
+    pub path: Option<String>,
+
+    /// Requests matching `path` needed
+    #[serde(default = "default_one")]
+    pub min_path_hits: u32,
+
+    /// Requests showing any of these anomalies count as anomalous
+    #[serde(default)]
+    pub header_anomalies: Vec<HeaderAnomaly>,
+
+    /// Anomalous requests needed (with `header_anomalies`)
+    #[serde(default = "default_one")]
+    pub min_anomalous: u32,
+
+    /// Share of answered challenges that were wrong (0-1)
+    pub min_failure_ratio: Option<f64>,
+
+    /// Answered challenges needed before the failure ratio counts
+    #[serde(default = "default_min_attempts")]
+    pub min_attempts: u32,
+
+    pub action: RuleAction,
+
+    /// Threat levels to add (`escalate`)
+    #[serde(default = "default_escalate_by")]
+    pub escalate_by: u8,
+
+    /// Challenge provider to switch to (`challenge`)
+    #[serde(default)]
+    pub provider: Option<String>,
+
+    /// Quiet time after firing; also how long a `challenge` switch lasts
+    #[serde(default = "default_cooldown")]
+    pub cooldown_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleScope {
+    /// Each circuit on its own (requests without a circuit ID are skipped)
+    #[default]
+    Circuit,
+    /// All traffic together
+    Global,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    /// Ban the circuit (circuit rules only)
+    Ban,
+    /// Raise the threat level by `escalate_by`
+    Escalate,
+    /// Serve `provider` challenges at every difficulty for `cooldown_secs`
+    Challenge,
+}
+
+impl RuleAction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Ban => "ban",
+            Self::Escalate => "escalate",
+            Self::Challenge => "challenge",
+        }
+    }
+}
+
+/// Request header oddities typical of bots
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HeaderAnomaly {
+    MissingUserAgent,
+    MissingAccept,
+    MissingAcceptLanguage,
+    /// User agent of an HTTP library or command line tool
+    ScriptedUserAgent,
+}
+
+impl HeaderAnomaly {
+    const ALL: [HeaderAnomaly; 4] = [
+        Self::MissingUserAgent,
+        Self::MissingAccept,
+        Self::MissingAcceptLanguage,
+        Self::ScriptedUserAgent,
+    ];
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    fn present(self, headers: &HeaderMap) -> bool {
+        match self {
+            Self::MissingUserAgent => !headers.contains_key(header::USER_AGENT),
+            Self::MissingAccept => !headers.contains_key(header::ACCEPT),
+            Self::MissingAcceptLanguage => !headers.contains_key(header::ACCEPT_LANGUAGE),
+            Self::ScriptedUserAgent => headers
+                .get(header::USER_AGENT)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|agent| {
+                    let agent = agent.to_ascii_lowercase();
+                    SCRIPTED_AGENTS.iter().any(|tool| agent.starts_with(tool))
+                }),
+        }
+    }
+
+    /// Anomalies of a request, as a bit set
+    fn detect(headers: &HeaderMap) -> u8 {
+        Self::ALL
+            .iter()
+            .filter(|anomaly| anomaly.present(headers))
+            .fold(0, |bits, anomaly| bits | anomaly.bit())
+    }
+}
+
+/// Check a rule on its own (config validation)
+pub fn check_rule(rule: &Rule) -> Result<()> {
+    CompiledRule::new(rule.clone()).map(|_| ())
+}
+
+struct CompiledRule {
+    rule: Rule,
+    path: Option<Regex>,
+    anomalies: u8,
+}
+
+impl CompiledRule {
+    fn new(rule: Rule) -> Result<Self> {
+        if rule.name.is_empty() {
+            bail!("rule name must not be empty");
+        }
+        if rule.min_requests.is_none()
+            && rule.path.is_none()
+            && rule.header_anomalies.is_empty()
+            && rule.min_failure_ratio.is_none()
+        {
+            bail!("rule `{}` has no conditions", rule.name);
+        }
+        if let Some(ratio) = rule.min_failure_ratio
+            && !(0.0..=1.0).contains(&ratio)
+        {
+            bail!(
+                "rule `{}`: min_failure_ratio {} is outside [0, 1]",
+                rule.name,
+                ratio
+            );
+        }
+        match rule.action {
+            RuleAction::Ban if rule.scope == RuleScope::Global => {
+                bail!("rule `{}`: only circuit rules can ban", rule.name)
+            }
+            RuleAction::Escalate if rule.escalate_by == 0 => {
+                bail!("rule `{}`: escalate_by must be greater than 0", rule.name)
+            }
+            RuleAction::Challenge => {
+                let providers = crate::captcha::builtin_names();
+                match rule.provider.as_deref() {
+                    Some(name) if providers.contains(&name) => {}
+                    _ => bail!(
+                        "rule `{}`: challenge needs a provider (one of: {})",
+                        rule.name,
+                        providers.join(", ")
+                    ),
+                }
+            }
+            _ => {}
+        }
+
+        let path = rule
+            .path
+            .as_deref()
+            .map(Regex::new)
+            .transpose()
+            .with_context(|| format!("rule `{}`: invalid path regex", rule.name))?;
+        let anomalies = rule
+            .header_anomalies
+            .iter()
+            .fold(0, |bits, anomaly| bits | anomaly.bit());
+        Ok(Self {
+            rule,
+            path,
+            anomalies,
+        })
+    }
+
+    fn matches(&self, counts: &Counts) -> bool {
+        let rule = &self.rule;
+        rule.min_requests.is_none_or(|min| counts.requests >= min)
+            && (self.path.is_none() || counts.path_hits >= rule.min_path_hits)
+            && (self.anomalies == 0 || counts.anomalous >= rule.min_anomalous)
+            && rule.min_failure_ratio.is_none_or(|ratio| {
+                counts.attempts >= rule.min_attempts.max(1)
+                    && counts.failures as f64 / counts.attempts as f64 >= ratio
+            })
+    }
+}
+
+/// What one rule sees of a circuit (or the node) over the window
+#[derive(Debug, Default, Clone, Copy)]
+struct Counts {
+    requests: u32,
+    path_hits: u32,
+    anomalous: u32,
+    attempts: u32,
+    failures: u32,
+}
+
+#[derive(Debug)]
+struct Observation {
+    /// Unix timestamp (seconds)
+    at: i64,
+    circuit_id: Option<CircuitId>,
+    kind: ObservationKind,
+}
+
+#[derive(Debug)]
+enum ObservationKind {
+    Request { path: String, anomalies: u8 },
+    Verification { success: bool },
+}
+
+/// A rule that fired
+#[derive(Debug)]
+pub struct Trigger<'a> {
+    pub rule: &'a Rule,
+    /// The circuit, for circuit rules
+    pub circuit_id: Option<CircuitId>,
+}
+
+/// Records observations and evaluates the rules over them
+pub struct RulesEngine {
+    enabled: bool,
+    window_secs: u64,
+    rules: Vec<CompiledRule>,
+    observations: Mutex<VecDeque<Observation>>,
+    /// (rule index, circuit) -> when it last fired
+    fired: Mutex<BoundedCache<CooldownKey, i64>>,
+}
+
+impl RulesEngine {
+    pub fn new(config: &RulesConfig) -> Result<Self> {
+        let rules = config
+            .rules
+            .iter()
+            .cloned()
+            .map(CompiledRule::new)
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            enabled: config.enabled && !config.rules.is_empty(),
+            window_secs: config.window_secs,
+            rules,
+            observations: Mutex::new(VecDeque::new()),
+            fired: Mutex::new(
+                BoundedCache::new(MAX_COOLDOWNS).with_max_bytes(MAX_COOLDOWN_BYTES, cooldown_bytes),
+            ),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Record a request (`path` as the client asked for it)
+    pub fn record_request(&self, circuit_id: Option<CircuitId>, path: &str, headers: &HeaderMap) {
+        self.record(
+            circuit_id,
+            ObservationKind::Request {
+                path: path.to_string(),
+                anomalies: HeaderAnomaly::detect(headers),
+            },
+        );
+    }
+
+    /// Record an answered challenge
+    pub fn record_verification(&self, circuit_id: Option<&CircuitId>, success: bool) {
+        self.record(
+            circuit_id.cloned(),
+            ObservationKind::Verification { success },
+        );
+    }
+
+    fn record(&self, circuit_id: Option<CircuitId>, kind: ObservationKind) {
+        if !self.enabled {
+            return;
+        }
+        let mut observations = self.observations.lock().unwrap_or_else(|e| e.into_inner());
+        if observations.len() >= MAX_OBSERVATIONS {
+            observations.pop_front();
+        }
+        observations.push_back(Observation {
+            at: chrono::Utc::now().timestamp(),
+            circuit_id,
+            kind,
+        });
+    }
+
+    /// Rules that fire at `now` (cooldowns are updated)
+    pub fn evaluate(&self, now: i64) -> Vec<Trigger<'_>> {
+        let mut observations = self.observations.lock().unwrap_or_else(|e| e.into_inner());
+        let since = now - self.window_secs as i64;
+        while observations.front().is_some_and(|o| o.at < since) {
+            observations.pop_front();
+        }
+
+        let mut fired = self.fired.lock().unwrap_or_else(|e| e.into_inner());
+        let mut triggers = Vec::new();
+        for (index, compiled) in self.rules.iter().enumerate() {
+            let counts = count(compiled, observations.iter());
+            for (circuit_id, counts) in counts {
+                if !compiled.matches(&counts) {
+                    continue;
+                }
+                let key = (index, circuit_id);
+                let cooldown = compiled.rule.cooldown_secs as i64;
+                if fired.peek(&key).is_some_and(|at| now - at < cooldown) {
+                    continue;
+                }
+                fired.insert(key.clone(), now);
+                triggers.push(Trigger {
+                    rule: &compiled.rule,
+                    circuit_id: key.1,
+                });
+            }
+        }
+        // Forget cooldowns that have run out
+        fired.retain(|(index, _), at| now - *at < self.rules[*index].rule.cooldown_secs as i64);
+        metrics::record_cache("rule_cooldowns", fired.stats());
+
+        triggers
+    }
+}
+
+/// Memory held by a cooldown entry
+fn cooldown_bytes(key: &CooldownKey, _: &i64) -> usize {
+    std::mem::size_of::<(CooldownKey, i64)>() + key.1.as_ref().map_or(0, |c| c.as_str().len())
+}
+
+/// Counts per circuit (or a single node-wide entry keyed `None`)
+fn count<'a>(
+    compiled: &CompiledRule,
+    observations: impl Iterator<Item = &'a Observation>,
+) -> HashMap<Option<CircuitId>, Counts> {
+    let mut counts: HashMap<Option<CircuitId>, Counts> = HashMap::new();
+    for observation in observations {
+        let key = match compiled.rule.scope {
+            RuleScope::Global => None,
+            RuleScope::Circuit => match observation.circuit_id {
+                Some(ref circuit_id) => Some(circuit_id.clone()),
+                None => continue,
+            },
+        };
+        let entry = counts.entry(key).or_default();
+        match observation.kind {
+            ObservationKind::Request {
+                ref path,
+                anomalies,
+            } => {
+                entry.requests += 1;
+                if compiled.path.as_ref().is_some_and(|re| re.is_match(path)) {
+                    entry.path_hits += 1;
+                }
+                if anomalies & compiled.anomalies != 0 {
+                    entry.anomalous += 1;
+                }
+            }
+            ObservationKind::Verification { success } => {
+                entry.attempts += 1;
+                if !success {
+                    entry.failures += 1;
+                }
+            }
+        }
+    }
+    counts
+}
+
+/// Middleware recording each request for the rules
+pub async fn observe_requests(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if state.rules.enabled() {
+        let headers = request.headers();
+        let circuit_id = headers
+            .get(cerberus_common::constants::headers::X_CIRCUIT_ID)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok());
+        let path = headers
+            .get(cerberus_common::constants::headers::X_ORIGINAL_URI)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_else(|| request.uri().path());
+        state.rules.record_request(circuit_id, path, headers);
+    }
+    next.run(request).await
+}
+
+/// Background analyzer: evaluates the rules and carries out their actions
+pub async fn rules_worker(state: AppState, mut shutdown: tokio::sync::broadcast::Receiver<()>) {
+    if !state.rules.enabled() {
+        return;
+    }
+    let interval = Duration::from_secs(state.config.rules.interval_secs.max(1));
+    tracing::info!(
+        rules = state.config.rules.rules.len(),
+        "📐 Rules engine started (interval: {:?})",
+        interval
+    );
+
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {
+                let now = chrono::Utc::now().timestamp();
+                for trigger in state.rules.evaluate(now) {
+                    if let Err(e) = apply(&state, &trigger).await {
+                        tracing::error!(rule = %trigger.rule.name, error = %e, "Rule action failed");
+                    }
+                }
+            }
+            _ = shutdown.recv() => {
+                tracing::info!("Rules engine shutting down");
+                break;
+            }
+        }
+    }
+}
+
+async fn apply(state: &AppState, trigger: &Trigger<'_>) -> Result<()> {
+    let rule = trigger.rule;
+    tracing::warn!(
+        rule = %rule.name,
+        action = rule.action.as_str(),
+        circuit_id = ?trigger.circuit_id,
+        "Attack signature rule fired"
+    );
+
+    match rule.action {
+        RuleAction::Ban if state.config.enforcement.observe => {
+            let circuit_id = trigger.circuit_id.as_ref().context("ban needs a circuit")?;
+            enforcement::record(state, enforcement::Action::Ban, Some(circuit_id));
+            // Nothing was done, so nothing triggered
+            return Ok(());
+        }
+        RuleAction::Ban => {
+            let circuit_id = trigger.circuit_id.as_ref().context("ban needs a circuit")?;
+            let reason = format!("rule: {}", rule.name);
+            let mut redis = state.redis().context("Redis offline")?;
+            // In a cluster, the leader decides
+            if let Some(ref election) = state.election {
+                let proposal = Proposal::Ban {
+                    circuit_id: circuit_id.clone(),
+                    reason,
+                };
+                election.propose(&mut redis, &proposal).await?;
+            } else {
+                state
+                    .circuit_tracker
+                    .ban(&mut redis, circuit_id, &reason)
+                    .await?;
+            }
+        }
+        RuleAction::Escalate if !state.flags.is_enabled(Flag::AutoEscalation) => {
+            tracing::info!(rule = %rule.name, "Auto-escalation is off, threat level left alone");
+            // Nothing was done, so nothing triggered
+            return Ok(());
+        }
+        RuleAction::Escalate => {
+            if let Some(ref election) = state.election {
+                let mut redis = state.redis().context("Redis offline")?;
+                let proposal = Proposal::Escalate {
+                    by: rule.escalate_by,
+                    reason: format!("rule: {}", rule.name),
+                };
+                election.propose(&mut redis, &proposal).await?;
+            } else {
+                let current = state.get_threat_level().await;
+                let raised = current.escalate(rule.escalate_by);
+                if raised != current {
+                    state.set_threat_level(raised).await?;
+                }
+            }
+        }
+        RuleAction::Challenge => {
+            let provider = rule.provider.as_deref().context("no provider")?;
+            state
+                .providers
+                .force(provider, Duration::from_secs(rule.cooldown_secs));
+        }
+    }
+
+    state.events.publish(CerberusEvent::RuleTriggered {
+        rule: rule.name.clone(),
+        action: rule.action.as_str().to_string(),
+        circuit_id: trigger.circuit_id.clone(),
+    });
+    Ok(())
+}
+
+fn default_interval() -> u64 {
+    10
+}
+fn default_window() -> u64 {
+    60
+}
+fn default_one() -> u32 {
+    1
+}
+fn default_min_attempts() -> u32 {
+    5
+}
+fn default_escalate_by() -> u8 {
+    1
+}
+fn default_cooldown() -> u64 {
+    300
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn engine(rules: &str) -> RulesEngine {
+        let config: RulesConfig =
+            toml::from_str(&format!("enabled = true\n{}", rules)).expect("valid rules");
+        RulesEngine::new(&config).expect("rules compile")
+    }
+
+    fn browser_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::USER_AGENT,
+            HeaderValue::from_static("Mozilla/5.0 (Windows NT 10.0; rv:115.0)"),
+        );
+        headers.insert(header::ACCEPT, HeaderValue::from_static("text/html"));
+        headers.insert(header::ACCEPT_LANGUAGE, HeaderValue::from_static("en-US"));
+        headers
+    }
+
+    #[test]
+    fn test_circuit_rule_fires_per_circuit_with_cooldown() {
+        let engine = engine(
+            r#"
+            [[rule]]
+            name = "scanner"
+            path = "^/app/(wp-admin|\\.env)"
+            min_path_hits = 2
+            header_anomalies = ["scripted_user_agent"]
+            action = "ban"
+            "#,
+        );
+        let scanner: CircuitId = "fc00::1".parse().unwrap();
+        let visitor: CircuitId = "fc00::2".parse().unwrap();
+
+        let mut curl = HeaderMap::new();
+        curl.insert(header::USER_AGENT, HeaderValue::from_static("curl/8.5.0"));
+        engine.record_request(Some(scanner.clone()), "/app/wp-admin/", &curl);
+        engine.record_request(Some(scanner.clone()), "/app/.env", &curl);
+        // Same paths, but from a browser
+        engine.record_request(Some(visitor.clone()), "/app/wp-admin/", &browser_headers());
+        engine.record_request(Some(visitor), "/app/.env", &browser_headers());
+
+        let now = chrono::Utc::now().timestamp();
+        let triggers = engine.evaluate(now);
+        assert_eq!(triggers.len(), 1);
+        assert_eq!(triggers[0].rule.name, "scanner");
+        assert_eq!(triggers[0].circuit_id, Some(scanner));
+
+        // Cooling down
+        assert!(engine.evaluate(now + 1).is_empty());
+    }
+
+    #[test]
+    fn test_global_failure_ratio() {
+        let engine = engine(
+            r#"
+            [[rule]]
+            name = "solver-farm"
+            scope = "global"
+            min_failure_ratio = 0.75
+            min_attempts = 4
+            action = "challenge"
+            provider = "math"
+            "#,
+        );
+        let now = chrono::Utc::now().timestamp();
+        for success in [false, false, true] {
+            engine.record_verification(None, success);
+        }
+        assert!(engine.evaluate(now).is_empty(), "too few attempts");
+
+        engine.record_verification(None, false);
+        let triggers = engine.evaluate(now);
+        assert_eq!(triggers.len(), 1);
+        assert_eq!(triggers[0].circuit_id, None);
+
+        // Everything has left the window
+        assert!(engine.evaluate(now + 3600).is_empty());
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        let rule = |extra: &str| -> Rule {
+            toml::from_str(&format!("name = \"r\"\naction = \"ban\"\n{}", extra)).unwrap()
+        };
+        assert!(check_rule(&rule("min_requests = 100")).is_ok());
+        assert!(check_rule(&rule("")).is_err(), "no conditions");
+        assert!(check_rule(&rule("path = \"(\"")).is_err(), "bad regex");
+        assert!(check_rule(&rule("min_requests = 1\nscope = \"global\"")).is_err());
+        assert!(check_rule(&rule("min_failure_ratio = 1.5")).is_err());
+    }
+}
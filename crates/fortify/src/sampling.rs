@@ -0,0 +1,263 @@
+//! Request sampling for attack analysis.
+//!
+//! A configurable fraction of `/validate` requests is captured (method,
+//! path, headers, circuit and the decision made) into an in-memory ring
+//! buffer, read back through `/admin/samples`. That's enough to spot what
+//! an attack's requests have in common and write a rule for it, without
+//! logging every request.
+//!
+//! Header order is kept, since it tells clients apart as much as the values
+//! do. Credentials are never captured: the values of `Cookie`,
+//! `Authorization` and the passport header are redacted, and the path is
+//! stored without its query string. Samples stay on the node that took them.
+
+use axum::{
+    extract::{Request, State},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+use cerberus_common::CircuitId;
+use cerberus_common::constants::headers;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use crate::enforcement::Action;
+use crate::state::AppState;
+
+/// Request sampling settings (`[sampling]` in fortify.toml)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SamplingConfig {
+    pub enabled: bool,
+    /// Fraction of requests captured (0.0-1.0)
+    pub rate: f64,
+    /// Samples kept; the oldest are dropped first
+    pub capacity: usize,
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            rate: 0.01,
+            capacity: 1000,
+        }
+    }
+}
+
+/// Headers whose values are replaced by `REDACTED`
+const REDACTED_HEADERS: [&str; 4] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "x-passport-token",
+];
+
+const REDACTED: &str = "<redacted>";
+
+/// Longer header values are cut to this many bytes
+const MAX_HEADER_VALUE_LEN: usize = 256;
+
+/// One captured request
+#[derive(Debug, Clone, Serialize)]
+pub struct Sample {
+    /// Unix timestamp (seconds)
+    pub timestamp: i64,
+    /// Method of the original request (`X-Original-Method` when proxied)
+    pub method: String,
+    /// Path of the original request, without the query string
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub circuit_id: Option<CircuitId>,
+    /// Headers in the order received, names lowercased
+    pub headers: Vec<(String, String)>,
+    /// Status `/validate` answered
+    pub status: u16,
+    /// What would have been done, had observe-only mode not let it through
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub observed: Option<&'static str>,
+}
+
+/// Ring buffer of recent samples
+pub struct RequestSampler {
+    config: SamplingConfig,
+    samples: Mutex<VecDeque<Sample>>,
+}
+
+impl RequestSampler {
+    pub fn new(config: SamplingConfig) -> Self {
+        Self {
+            samples: Mutex::new(VecDeque::with_capacity(config.capacity.min(4096))),
+            config,
+        }
+    }
+
+    /// Should the next request be captured?
+    pub fn should_sample(&self) -> bool {
+        self.config.enabled && self.config.capacity > 0 && rand::random::<f64>() < self.config.rate
+    }
+
+    pub fn push(&self, sample: Sample) {
+        let mut samples = self.samples.lock().unwrap_or_else(|e| e.into_inner());
+        while samples.len() >= self.config.capacity.max(1) {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+
+    /// Up to `limit` samples, newest first
+    pub fn recent(&self, limit: usize) -> Vec<Sample> {
+        let samples = self.samples.lock().unwrap_or_else(|e| e.into_inner());
+        samples.iter().rev().take(limit).cloned().collect()
+    }
+
+    /// Drop every sample, returning how many there were
+    pub fn clear(&self) -> usize {
+        let mut samples = self.samples.lock().unwrap_or_else(|e| e.into_inner());
+        let count = samples.len();
+        samples.clear();
+        count
+    }
+}
+
+/// Middleware capturing a sample of the requests it sees
+pub async fn sample_requests(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    if !state.sampler.should_sample() {
+        return next.run(request).await;
+    }
+
+    let request_headers = request.headers();
+    let method = request_headers
+        .get(headers::X_ORIGINAL_METHOD)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_else(|| request.method().as_str())
+        .to_string();
+    let path = request_headers
+        .get(headers::X_ORIGINAL_URI)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_else(|| request.uri().path());
+    let path = path.split('?').next().unwrap_or_default().to_string();
+    let circuit_id = circuit_id(&request);
+    let captured = normalize_headers(request_headers);
+
+    let response = next.run(request).await;
+    state.sampler.push(Sample {
+        timestamp: chrono::Utc::now().timestamp(),
+        method,
+        path,
+        circuit_id,
+        headers: captured,
+        status: response.status().as_u16(),
+        observed: response.extensions().get::<Action>().map(|a| a.as_str()),
+    });
+    response
+}
+
+/// The circuit from the `circuit_id` query parameter, or `X-Circuit-Id`
+pub(crate) fn circuit_id(request: &Request) -> Option<CircuitId> {
+    request
+        .uri()
+        .query()
+        .and_then(|query| {
+            query
+                .split('&')
+                .find_map(|pair| match pair.split_once('=') {
+                    Some(("circuit_id", value)) => value.parse().ok(),
+                    _ => None,
+                })
+        })
+        .or_else(|| {
+            request
+                .headers()
+                .get(headers::X_CIRCUIT_ID)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse().ok())
+        })
+}
+
+/// Headers as captured: credentials redacted, long values cut short
+fn normalize_headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let name = name.as_str();
+            let value = if REDACTED_HEADERS.contains(&name) {
+                REDACTED.to_string()
+            } else {
+                let value = String::from_utf8_lossy(value.as_bytes());
+                let mut end = value.len().min(MAX_HEADER_VALUE_LEN);
+                while !value.is_char_boundary(end) {
+                    end -= 1;
+                }
+                value[..end].to_string()
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(path: &str) -> Sample {
+        Sample {
+            timestamp: 0,
+            method: "GET".to_string(),
+            path: path.to_string(),
+            circuit_id: None,
+            headers: Vec::new(),
+            status: 200,
+            observed: None,
+        }
+    }
+
+    #[test]
+    fn test_ring_buffer_keeps_newest() {
+        let sampler = RequestSampler::new(SamplingConfig {
+            enabled: true,
+            rate: 1.0,
+            capacity: 2,
+        });
+        assert!(sampler.should_sample());
+        for path in ["/a", "/b", "/c"] {
+            sampler.push(sample(path));
+        }
+
+        let paths: Vec<_> = sampler.recent(10).into_iter().map(|s| s.path).collect();
+        assert_eq!(paths, ["/c", "/b"]);
+        assert_eq!(sampler.recent(1).len(), 1);
+        assert_eq!(sampler.clear(), 2);
+        assert!(sampler.recent(10).is_empty());
+
+        let off = RequestSampler::new(SamplingConfig {
+            rate: 1.0,
+            ..Default::default()
+        });
+        assert!(!off.should_sample());
+    }
+
+    #[test]
+    fn test_headers_normalized() {
+        let mut headers = HeaderMap::new();
+        headers.insert("User-Agent", "curl/8.0".parse().unwrap());
+        headers.insert("Cookie", "session=secret".parse().unwrap());
+        headers.insert("X-Passport-Token", "tok".parse().unwrap());
+        headers.insert("Accept", "x".repeat(1000).parse().unwrap());
+
+        let captured = normalize_headers(&headers);
+        assert_eq!(
+            captured[0],
+            ("user-agent".to_string(), "curl/8.0".to_string())
+        );
+        assert_eq!(captured[1].1, REDACTED);
+        assert_eq!(captured[2].1, REDACTED);
+        assert_eq!(captured[3].1.len(), MAX_HEADER_VALUE_LEN);
+    }
+}
@@ -0,0 +1,400 @@
+//! Service level objectives for `/validate`.
+//!
+//! Every `/validate` answer counts against two objectives: availability
+//! (anything but a 5xx) and latency (answered within `latency_ms`). Answers
+//! are counted in 10-second buckets over `window_secs`, and each objective's
+//! error budget is the share of answers it may get wrong over that window.
+//!
+//! The burn rate is how fast the budget is being spent: 1.0 spends it
+//! exactly over the window, 10.0 in a tenth of it. When both the short and
+//! the long alert windows burn faster than `burn_rate_alert`, `SloBurning`
+//! is published (once, until it recovers). Raised alongside a high threat
+//! level, it's a sign escalation is turning away the visitors it protects.
+//!
+//! Counts are per node and start over on restart. `/admin/slo` shows them.
+
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use cerberus_common::{CerberusEvent, EventPublisher};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::metrics;
+use crate::state::AppState;
+
+/// Width of a counting bucket
+const BUCKET_SECS: i64 = 10;
+
+/// `/validate` objectives (`[slo]` in fortify.toml)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SloConfig {
+    pub enabled: bool,
+    /// Share of answers that must not be a 5xx
+    pub availability_target: f64,
+    /// An answer slower than this misses the latency objective
+    pub latency_ms: u64,
+    /// Share of answers that must be within `latency_ms`
+    pub latency_target: f64,
+    /// Period the error budget is spread over
+    pub window_secs: u64,
+    /// Alert windows: both must burn faster than `burn_rate_alert`
+    pub short_window_secs: u64,
+    pub long_window_secs: u64,
+    pub burn_rate_alert: f64,
+    /// Answers needed in the long window before alerting
+    pub min_requests: u64,
+}
+
+impl Default for SloConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            availability_target: 0.999,
+            latency_ms: 50,
+            latency_target: 0.99,
+            window_secs: 86_400,
+            short_window_secs: 300,
+            long_window_secs: 3600,
+            burn_rate_alert: 14.4,
+            min_requests: 100,
+        }
+    }
+}
+
+/// One of the objectives
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Objective {
+    Availability,
+    Latency,
+}
+
+impl Objective {
+    pub const ALL: [Objective; 2] = [Objective::Availability, Objective::Latency];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Availability => "availability",
+            Self::Latency => "latency",
+        }
+    }
+}
+
+/// Answers counted in one bucket
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Counts {
+    total: u64,
+    /// 5xx answers
+    errors: u64,
+    /// Answers over `latency_ms`
+    slow: u64,
+}
+
+impl Counts {
+    fn add(&mut self, other: &Counts) {
+        self.total += other.total;
+        self.errors += other.errors;
+        self.slow += other.slow;
+    }
+
+    fn bad(&self, objective: Objective) -> u64 {
+        match objective {
+            Objective::Availability => self.errors,
+            Objective::Latency => self.slow,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Bucket {
+    /// Unix seconds / `BUCKET_SECS`
+    index: i64,
+    counts: Counts,
+}
+
+/// Burn rate over one window
+#[derive(Debug, Clone, Serialize)]
+pub struct BurnRate {
+    pub window_secs: u64,
+    pub requests: u64,
+    pub bad: u64,
+    pub burn_rate: f64,
+}
+
+/// One objective's standing
+#[derive(Debug, Clone, Serialize)]
+pub struct ObjectiveReport {
+    pub objective: Objective,
+    pub target: f64,
+    pub window_secs: u64,
+    pub requests: u64,
+    pub bad: u64,
+    /// Share of the window's error budget left (negative once overspent)
+    pub budget_remaining: f64,
+    /// Short alert window, long alert window, whole window
+    pub burn_rates: Vec<BurnRate>,
+    /// Both alert windows over `burn_rate_alert`
+    pub burning: bool,
+}
+
+/// Rolling `/validate` answer counts
+pub struct SloTracker {
+    config: SloConfig,
+    /// Ring of buckets covering `window_secs`
+    buckets: Mutex<Vec<Bucket>>,
+}
+
+impl SloTracker {
+    pub fn new(config: SloConfig) -> Self {
+        let len = (config.window_secs as i64 / BUCKET_SECS).max(1) as usize;
+        Self {
+            config,
+            buckets: Mutex::new(vec![Bucket::default(); len]),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.enabled
+    }
+
+    /// Count an answer given at `now` (Unix seconds)
+    pub fn record(&self, now: i64, status: u16, latency: Duration) {
+        let index = now / BUCKET_SECS;
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let len = buckets.len() as i64;
+        let bucket = &mut buckets[index.rem_euclid(len) as usize];
+        if bucket.index != index {
+            *bucket = Bucket {
+                index,
+                counts: Counts::default(),
+            };
+        }
+        bucket.counts.total += 1;
+        if status >= 500 {
+            bucket.counts.errors += 1;
+        }
+        if latency > Duration::from_millis(self.config.latency_ms) {
+            bucket.counts.slow += 1;
+        }
+    }
+
+    /// Answers over the last `secs` before `now`
+    fn counts(&self, now: i64, secs: u64) -> Counts {
+        let current = now / BUCKET_SECS;
+        let oldest = current - (secs as i64 / BUCKET_SECS).max(1) + 1;
+        let buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let mut counts = Counts::default();
+        for bucket in buckets.iter() {
+            if (oldest..=current).contains(&bucket.index) {
+                counts.add(&bucket.counts);
+            }
+        }
+        counts
+    }
+
+    fn target(&self, objective: Objective) -> f64 {
+        match objective {
+            Objective::Availability => self.config.availability_target,
+            Objective::Latency => self.config.latency_target,
+        }
+    }
+
+    /// Each objective's standing at `now`
+    pub fn report(&self, now: i64) -> Vec<ObjectiveReport> {
+        let config = &self.config;
+        Objective::ALL
+            .into_iter()
+            .map(|objective| {
+                let budget = 1.0 - self.target(objective);
+                let over = |window_secs: u64| {
+                    let counts = self.counts(now, window_secs);
+                    let bad = counts.bad(objective);
+                    BurnRate {
+                        window_secs,
+                        requests: counts.total,
+                        bad,
+                        burn_rate: burn_rate(bad, counts.total, budget),
+                    }
+                };
+                let short = over(config.short_window_secs);
+                let long = over(config.long_window_secs);
+                let whole = over(config.window_secs);
+
+                let burning = long.requests >= config.min_requests
+                    && short.burn_rate > config.burn_rate_alert
+                    && long.burn_rate > config.burn_rate_alert;
+                let allowed = budget * whole.requests as f64;
+                let budget_remaining = if allowed > 0.0 {
+                    1.0 - whole.bad as f64 / allowed
+                } else {
+                    1.0
+                };
+                ObjectiveReport {
+                    objective,
+                    target: self.target(objective),
+                    window_secs: config.window_secs,
+                    requests: whole.requests,
+                    bad: whole.bad,
+                    budget_remaining,
+                    burn_rates: vec![short, long, whole],
+                    burning,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Error rate as a multiple of the rate the budget allows
+fn burn_rate(bad: u64, total: u64, budget: f64) -> f64 {
+    if total == 0 || budget <= 0.0 {
+        return 0.0;
+    }
+    (bad as f64 / total as f64) / budget
+}
+
+/// Middleware counting `/validate` answers
+pub async fn track_requests(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let started = Instant::now();
+    let response = next.run(request).await;
+    state.slo.record(
+        chrono::Utc::now().timestamp(),
+        response.status().as_u16(),
+        started.elapsed(),
+    );
+    response
+}
+
+/// Publish burn rates as metrics every bucket, and `SloBurning` /
+/// `SloRecovered` as objectives start and stop burning
+pub async fn slo_worker(state: AppState, mut shutdown: tokio::sync::broadcast::Receiver<()>) {
+    if !state.slo.enabled() {
+        return;
+    }
+    let interval = Duration::from_secs(BUCKET_SECS as u64);
+    let mut burning = [false; Objective::ALL.len()];
+
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {
+                let reports = state.slo.report(chrono::Utc::now().timestamp());
+                for (report, was_burning) in reports.iter().zip(burning.iter_mut()) {
+                    let objective = report.objective.as_str();
+                    for rate in &report.burn_rates {
+                        metrics::SLO_BURN_RATE
+                            .with_label_values(&[objective, &rate.window_secs.to_string()])
+                            .set(rate.burn_rate);
+                    }
+                    metrics::SLO_BUDGET_REMAINING
+                        .with_label_values(&[objective])
+                        .set(report.budget_remaining);
+
+                    if report.burning == *was_burning {
+                        continue;
+                    }
+                    *was_burning = report.burning;
+                    if report.burning {
+                        let burn_rate = report.burn_rates[1].burn_rate;
+                        let threat_level = state.get_threat_level().await.value();
+                        tracing::warn!(
+                            objective,
+                            burn_rate,
+                            threat_level,
+                            "SLO error budget burning too fast"
+                        );
+                        state.events.publish(CerberusEvent::SloBurning {
+                            objective: objective.to_string(),
+                            burn_rate,
+                            threat_level,
+                        });
+                    } else {
+                        tracing::info!(objective, "SLO burn rate back to normal");
+                        state.events.publish(CerberusEvent::SloRecovered {
+                            objective: objective.to_string(),
+                        });
+                    }
+                }
+            }
+            _ = shutdown.recv() => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker() -> SloTracker {
+        SloTracker::new(SloConfig {
+            window_secs: 3600,
+            short_window_secs: 60,
+            long_window_secs: 600,
+            burn_rate_alert: 10.0,
+            min_requests: 10,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_burn_rates() {
+        let slo = tracker();
+        let now = 1_700_000_000;
+        let fast = Duration::from_millis(5);
+        for i in 0..1000 {
+            slo.record(now - 1800 + i, 200, fast);
+        }
+        // 2% errors in the last minute: 20 times the 0.1% budget
+        for _ in 0..98 {
+            slo.record(now, 200, fast);
+        }
+        slo.record(now, 503, fast);
+        slo.record(now, 502, Duration::from_millis(80));
+
+        let reports = slo.report(now);
+        let availability = &reports[0];
+        assert_eq!(availability.objective, Objective::Availability);
+        assert_eq!(availability.requests, 1100);
+        assert_eq!(availability.bad, 2);
+        let short = &availability.burn_rates[0];
+        assert_eq!((short.requests, short.bad), (100, 2));
+        assert!((short.burn_rate - 20.0).abs() < 1e-6);
+        assert!(availability.burning);
+        // 2 of 1.1 allowed: overspent
+        assert!(availability.budget_remaining < 0.0);
+
+        // 1 slow in 100 is the latency budget, spent exactly
+        let latency = &reports[1];
+        assert_eq!(latency.bad, 1);
+        assert!((latency.burn_rates[0].burn_rate - 1.0).abs() < 1e-6);
+        assert!(!latency.burning);
+    }
+
+    #[test]
+    fn test_old_buckets_fall_out() {
+        let slo = tracker();
+        let now = 1_700_000_000;
+        slo.record(now, 500, Duration::ZERO);
+        // Same ring slot an hour later: the old count is gone
+        slo.record(now + 3600, 200, Duration::ZERO);
+
+        let reports = slo.report(now + 3600);
+        assert_eq!(reports[0].requests, 1);
+        assert_eq!(reports[0].bad, 0);
+        assert_eq!(reports[0].budget_remaining, 1.0);
+
+        // Too few answers to alert on, however bad
+        let slo = tracker();
+        for _ in 0..5 {
+            slo.record(now, 500, Duration::ZERO);
+        }
+        assert!(!slo.report(now)[0].burning);
+    }
+}
@@ -5,10 +5,32 @@ use redis::aio::ConnectionManager;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
-use crate::captcha::{AmmoBox, CaptchaGenerator, CaptchaVerifier};
-use crate::circuits::CircuitTracker;
+use crate::abandonment::AbandonmentTracker;
+use crate::access_log::AccessLog;
+use crate::captcha::{
+    AmmoBox, CaptchaGenerator, CaptchaVerifier, ChallengeSealer, ProviderRegistry, ShadowTrials,
+};
+use crate::challenge_stats::ChallengeStats;
+use crate::circuits::{CircuitTracker, EventLog, MutationQueue, SolveTimeAnalyzer, VerifyThrottle};
+use crate::cluster::{
+    FederationMode, GossipService, LeaderElection, PassportConfig, PassportService, RateShare,
+};
 use crate::config::AppConfig;
-use cerberus_common::ThreatLevel;
+use crate::degradation::{DegradationLevel, DegradationState};
+use crate::drain::Drain;
+use crate::experiments::Experiments;
+use crate::flags::FeatureFlags;
+use crate::journey::Journeys;
+use crate::gate_session::GateSessions;
+use crate::haproxy::HaproxyApi;
+use crate::maintenance::Maintenance;
+use crate::rules::RulesEngine;
+use crate::sampling::RequestSampler;
+use crate::slo::SloTracker;
+use crate::supervisor::Supervisor;
+use crate::verification::VerificationService;
+use crate::verify_queue::VerifyQueue;
+use cerberus_common::{CerberusEvent, EventBus, EventPublisher, ThreatLevel};
 
 /// Shared application state
 #[derive(Clone)]
@@ -16,8 +38,11 @@ pub struct AppState {
     /// Application configuration
     pub config: AppConfig,
 
-    /// Redis connection manager (auto-reconnecting)
-    pub redis: ConnectionManager,
+    /// Redis client (used to reattach after an outage)
+    pub redis_client: redis::Client,
+
+    /// Redis connection manager (auto-reconnecting); `None` until connected
+    redis: Arc<std::sync::RwLock<Option<ConnectionManager>>>,
 
     /// Current threat level (cached locally, synced with Redis)
     pub threat_level: Arc<RwLock<ThreatLevel>>,
@@ -34,46 +59,325 @@ pub struct AppState {
     /// Circuit tracker
     pub circuit_tracker: Arc<CircuitTracker>,
 
+    /// Answer throttling and gate lockout
+    pub throttle: Arc<VerifyThrottle>,
+
+    /// Fair admission of answers under load
+    pub verify_queue: Arc<VerifyQueue>,
+
+    /// CAPTCHA farm detection (solve-time analysis)
+    pub solve_time_analyzer: Arc<SolveTimeAnalyzer>,
+
     /// Pre-generated CAPTCHA pool
     pub ammo_box: Arc<AmmoBox>,
+
+    /// Redis memory guard state
+    pub degradation: Arc<DegradationState>,
+
+    /// Circuit mutations awaiting replay after an outage
+    pub mutation_queue: Arc<MutationQueue>,
+
+    /// Verify-and-record flow
+    pub verification: Arc<VerificationService>,
+
+    /// No-JS gate sessions (attempts, page to return to)
+    pub gate_sessions: Arc<GateSessions>,
+
+    /// Key for sealed challenges, return paths and proof-of-work seeds
+    pub sealer: Arc<ChallengeSealer>,
+
+    /// Challenge providers (rules may force one)
+    pub providers: Arc<ProviderRegistry>,
+
+    /// Candidate provider shown to a sample of visitors, never enforced
+    pub shadow: Arc<ShadowTrials>,
+
+    /// A/B experiments on the challenge flow, for `/admin/experiments`
+    pub experiments: Arc<Experiments>,
+
+    /// Challenges issued but never answered
+    pub abandonment: Arc<AbandonmentTracker>,
+
+    /// Gate-to-first-request timings of passports
+    pub journeys: Arc<Journeys>,
+
+    /// Challenge outcomes per difficulty, for `/admin/stats/difficulty`
+    pub challenge_stats: Arc<ChallengeStats>,
+
+    /// Attack signature rules and their observations
+    pub rules: Arc<RulesEngine>,
+
+    /// Captured `/validate` requests, for `/admin/samples`
+    pub sampler: Arc<RequestSampler>,
+
+    /// `/validate` availability and latency against the objectives
+    pub slo: Arc<SloTracker>,
+
+    /// Access log entries waiting to be written
+    pub access_log: Arc<AccessLog>,
+
+    /// Maintenance mode switch
+    pub maintenance: Arc<Maintenance>,
+
+    /// Drain switch (no new challenges, then exit or standby)
+    pub drain: Arc<Drain>,
+
+    /// Runtime switches for risky behaviors (config + Redis overrides)
+    pub flags: Arc<FeatureFlags>,
+
+    /// Cluster gossip (`None` unless `cluster_enabled`)
+    pub gossip: Option<Arc<GossipService>>,
+
+    /// Request counts shared with peers (`None` unless `cluster_enabled`
+    /// and `rate_limit.cluster.enabled`)
+    pub rate_share: Option<Arc<RateShare>>,
+
+    /// Leader lease (`None` unless `cluster_enabled`)
+    pub election: Option<Arc<LeaderElection>>,
+
+    /// Signs and checks stateless passports; holds the trusted peer keys
+    pub passports: Arc<PassportService>,
+
+    /// Node events (bans, passports, threat level, ...) for subscribers
+    pub events: Arc<EventBus>,
+
+    /// Background tasks and their restarts
+    pub supervisor: Arc<Supervisor>,
+
+    /// HAProxy runtime API (`None` unless `haproxy.enabled`)
+    pub haproxy: Option<Arc<HaproxyApi>>,
 }
 
 impl AppState {
     /// Create new application state, connecting to Redis
-    pub async fn new(config: AppConfig, ammo_box: Arc<AmmoBox>) -> Result<Self> {
+    ///
+    /// An unreachable Redis does not fail startup: Fortify starts in offline
+    /// mode and the Redis guard reattaches once it becomes reachable.
+    pub async fn new(
+        config: AppConfig,
+        ammo_box: Arc<AmmoBox>,
+        events: Arc<EventBus>,
+        supervisor: Arc<Supervisor>,
+    ) -> Result<Self> {
         // Connect to Redis with connection manager (handles reconnection)
-        let client = redis::Client::open(config.redis_url.as_str())
+        let redis_client = redis::Client::open(config.redis_url.as_str())
             .context("Failed to create Redis client")?;
 
-        let redis = ConnectionManager::new(client)
-            .await
-            .context("Failed to connect to Redis")?;
+        let redis = match ConnectionManager::new(redis_client.clone()).await {
+            Ok(conn) => Some(conn),
+            Err(e) => {
+                tracing::warn!(error = %e, "Redis unreachable, starting in offline mode");
+                None
+            }
+        };
 
         let threat_level = Arc::new(RwLock::new(ThreatLevel::new(config.initial_threat_level)));
         let node_id = config.node_id.clone();
 
+        // Stateless passports are minted for this node, or for the whole
+        // cluster when federated
+        let passport_signer = Arc::new(PassportService::new(PassportConfig {
+            token_ttl_secs: config.captcha.passport_ttl_secs.secs(),
+            node_id: node_id.clone(),
+            private_key_path: config.federation.private_key_path.clone(),
+            peer_pubkeys: config.federation.peer_keys.clone(),
+            cluster_wide: config.federation.mode == FederationMode::Signed,
+            previous_private_key_path: config.federation.previous_private_key_path.clone(),
+            previous_peer_pubkeys: config.federation.previous_peer_keys.clone(),
+            key_overlap_secs: config.federation.key_overlap_secs,
+        })?);
+        if let Some(pubkey) = passport_signer.public_key_b64() {
+            passport_signer.add_peer_key(&node_id, &pubkey).await?;
+        }
+
+        let rate_share = (config.cluster_enabled && config.rate_limit.cluster.enabled).then(|| {
+            Arc::new(RateShare::new(
+                config.rate_limit.cluster.clone(),
+                node_id.clone(),
+            ))
+        });
+
+        // Peers push passport revocations and request counts over gossip
+        let gossip = config.cluster_enabled.then(|| {
+            let gossip = GossipService::new(config.gossip.clone(), node_id.clone())
+                .with_passports(passport_signer.clone())
+                .with_publisher(events.clone());
+            Arc::new(match rate_share {
+                Some(ref rate_share) => gossip.with_rate_share(rate_share.clone()),
+                None => gossip,
+            })
+        });
+
+        let election = config.cluster_enabled.then(|| {
+            Arc::new(LeaderElection::new(
+                config.election.clone(),
+                node_id.clone(),
+            ))
+        });
+
+        let haproxy = config
+            .haproxy
+            .enabled
+            .then(|| Arc::new(HaproxyApi::from_config(&config.haproxy)));
+
         // Initialize services
-        let captcha_generator = Arc::new(CaptchaGenerator::new(config.captcha.challenge_ttl_secs));
-        let captcha_verifier = Arc::new(CaptchaVerifier::new(config.captcha.passport_ttl_secs));
-        let circuit_tracker = Arc::new(CircuitTracker::new(
-            cerberus_common::constants::CIRCUIT_TTL_SECS,
-            config.rate_limit.max_failed_attempts,
-            config.rate_limit.soft_lock_duration_secs,
-            config.rate_limit.ban_duration_secs,
+        let degradation = Arc::new(DegradationState::new(config.degradation.clone()));
+        if redis.is_none() {
+            degradation.set_level(DegradationLevel::Offline);
+        }
+        let mutation_queue = Arc::new(MutationQueue::new(
+            config.degradation.offline_queue_capacity,
+        ));
+        let sealer =
+            Arc::new(ChallengeSealer::new().with_max_ttl(config.captcha.challenge_ttl_secs));
+        let providers = Arc::new(ProviderRegistry::new(config.captcha.providers.clone()));
+        // History lives as long as the longest-lived circuit record
+        let event_log = EventLog::new(
+            config.rate_limit.event_history_len,
+            cerberus_common::constants::CIRCUIT_TTL_SECS
+                .max(config.rate_limit.soft_lock_duration_secs)
+                .max(config.rate_limit.ban_duration_secs)
+                .secs(),
+        );
+        let experiments = Arc::new(Experiments::new(&config.experiments));
+        let abandonment = Arc::new(AbandonmentTracker::new(config.abandonment.clone()));
+        let journeys = Arc::new(Journeys::new(&config.journeys));
+        let challenge_stats = Arc::new(ChallengeStats::new(config.challenge_stats.clone()));
+        let captcha_generator = Arc::new(
+            CaptchaGenerator::new(
+                config.captcha.challenge_ttl_secs.secs(),
+                config.captcha.max_outstanding_per_circuit,
+                config.captcha.max_issued_per_second,
+                degradation.clone(),
+                sealer.clone(),
+                event_log.clone(),
+                providers.clone(),
+            )
+            .with_write_batch(config.captcha.write_batch.clone())
+            .with_served_images(config.captcha.serve_images)
+            .with_experiments(experiments.clone())
+            .with_abandonment(abandonment.clone())
+            .with_challenge_stats(challenge_stats.clone()),
+        );
+        let flags = Arc::new(FeatureFlags::new(config.flags.clone(), node_id.clone()));
+        let captcha_verifier = Arc::new(
+            CaptchaVerifier::new(
+                config.captcha.passport_ttl_secs.secs(),
+                config.captcha.passport.clone(),
+                config.vip.clone(),
+                degradation.clone(),
+                passport_signer.clone(),
+                sealer.clone(),
+                providers.clone(),
+            )
+            .with_flags(flags.clone()),
+        );
+        let circuit_tracker = Arc::new(
+            CircuitTracker::new(
+                cerberus_common::constants::CIRCUIT_TTL_SECS.secs(),
+                config.rate_limit.max_failed_attempts,
+                config.rate_limit.soft_lock_duration_secs.secs(),
+                config.rate_limit.ban_duration_secs.secs(),
+                event_log.clone(),
+                config.vip.clone(),
+            )
+            .with_publisher(events.clone()),
+        );
+        let throttle = Arc::new(VerifyThrottle::new(
+            config.rate_limit.verify.clone(),
+            event_log,
+        ));
+        let verify_queue = Arc::new(VerifyQueue::new(config.verify_queue.clone()));
+        let solve_time_analyzer = Arc::new(SolveTimeAnalyzer::new(config.farm_detection.clone()));
+        let rules = Arc::new(RulesEngine::new(&config.rules)?);
+        let shadow = Arc::new(ShadowTrials::new(
+            config.captcha.shadow.clone(),
+            providers.clone(),
+            config.captcha.challenge_ttl_secs.secs(),
+        ));
+        let sampler = Arc::new(RequestSampler::new(config.sampling.clone()));
+        let slo = Arc::new(SloTracker::new(config.slo.clone()));
+        let access_log = Arc::new(AccessLog::new(&config.access_log));
+        let maintenance = Arc::new(Maintenance::new(&config.maintenance)?);
+        let verification = Arc::new(
+            VerificationService::new(
+                captcha_verifier.clone(),
+                circuit_tracker.clone(),
+                solve_time_analyzer.clone(),
+                mutation_queue.clone(),
+                rules.clone(),
+                throttle.clone(),
+                events.clone(),
+            )
+            .with_experiments(experiments.clone())
+            .with_journeys(journeys.clone())
+            .with_challenge_stats(challenge_stats.clone()),
+        );
+
+        let gate_sessions = Arc::new(GateSessions::new(
+            config.captcha.gate_session_ttl_secs.secs(),
+            sealer.clone(),
         ));
 
         Ok(Self {
             config,
-            redis,
+            redis_client,
+            redis: Arc::new(std::sync::RwLock::new(redis)),
             threat_level,
             node_id,
             captcha_generator,
             captcha_verifier,
             circuit_tracker,
+            throttle,
+            verify_queue,
+            solve_time_analyzer,
             ammo_box,
+            degradation,
+            mutation_queue,
+            verification,
+            gate_sessions,
+            sealer,
+            providers,
+            shadow,
+            experiments,
+            abandonment,
+            journeys,
+            challenge_stats,
+            rules,
+            sampler,
+            slo,
+            access_log,
+            maintenance,
+            drain: Arc::new(Drain::default()),
+            flags,
+            gossip,
+            rate_share,
+            election,
+            passports: passport_signer,
+            events,
+            supervisor,
+            haproxy,
         })
     }
 
+    /// Redis connection, or `None` while offline
+    pub fn redis(&self) -> Option<ConnectionManager> {
+        if self.degradation.level() == DegradationLevel::Offline {
+            return None;
+        }
+        self.redis_conn()
+    }
+
+    /// Redis connection regardless of degradation level (for the guard)
+    pub fn redis_conn(&self) -> Option<ConnectionManager> {
+        self.redis.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Install a freshly established connection
+    pub fn attach_redis(&self, conn: ConnectionManager) {
+        *self.redis.write().unwrap_or_else(|e| e.into_inner()) = Some(conn);
+    }
+
     /// Get current threat level
     pub async fn get_threat_level(&self) -> ThreatLevel {
         *self.threat_level.read().await
@@ -81,18 +385,23 @@ impl AppState {
 
     /// Update threat level (local + Redis)
     pub async fn set_threat_level(&self, level: ThreatLevel) -> Result<()> {
-        use redis::AsyncCommands;
-
-        // Update local cache
-        *self.threat_level.write().await = level;
+        self.adopt_threat_level(level).await;
 
         // Sync to Redis for cluster visibility
-        let mut conn = self.redis.clone();
-        let _: () = conn
-            .set(
-                cerberus_common::constants::redis_keys::THREAT_LEVEL,
-                level.value(),
-            )
+        let Some(mut conn) = self.redis() else {
+            tracing::warn!(
+                level = level.value(),
+                "Threat level updated locally (Redis offline)"
+            );
+            return Ok(());
+        };
+        // ...and tell the other nodes straight away (see `threat_sync`)
+        let _: () = redis::pipe()
+            .set(cerberus_common::redis_keys::threat_level(), level.value())
+            .ignore()
+            .publish(cerberus_common::redis_keys::threat_level_channel(), level.value())
+            .ignore()
+            .query_async(&mut conn)
             .await
             .context("Failed to sync threat level to Redis")?;
 
@@ -100,4 +409,15 @@ impl AppState {
 
         Ok(())
     }
+
+    /// Update the local threat level only (following the cluster's)
+    pub async fn adopt_threat_level(&self, level: ThreatLevel) {
+        let previous = std::mem::replace(&mut *self.threat_level.write().await, level);
+        if previous != level {
+            self.events.publish(CerberusEvent::ThreatLevelChanged {
+                from: previous,
+                to: level,
+            });
+        }
+    }
 }
@@ -0,0 +1,297 @@
+//! Panic reporting and supervised background tasks.
+//!
+//! A panic in a spawned task only ends that task: tokio keeps running and,
+//! unless someone awaits the handle, nothing says the Ammo Box stopped
+//! refilling or gossip went quiet. `install_panic_hook` logs every panic
+//! (with the task it happened in) and counts it in
+//! `fortify_panics_total`. Background workers are started through a
+//! `Supervisor`, which restarts a task that panicked after a backoff that
+//! doubles on each panic (1s up to 60s, back to 1s once a run has lasted a
+//! minute) and publishes `TaskPanicked` for the audit log and webhooks.
+//!
+//! A task that returns, normally or with an error, isn't restarted. Each
+//! task's state, restarts and last panic are shown at `/admin/tasks`, and
+//! at shutdown the supervisor waits (for a while) for every task to wind
+//! down, so workers with something to flush get to finish.
+
+use cerberus_common::{CerberusEvent, EventPublisher};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::panic::PanicHookInfo;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+
+use crate::metrics;
+
+/// Delay before the first restart
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest delay between restarts
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A run lasting this long resets the backoff
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+/// Label for panics outside supervised tasks (request handlers, ...)
+const UNSUPERVISED: &str = "unsupervised";
+
+tokio::task_local! {
+    /// Name of the supervised task being polled
+    static TASK: &'static str;
+}
+
+/// Log and count every panic, naming the supervised task it happened in
+///
+/// Replaces the default hook, which writes to stderr past the log format.
+pub fn install_panic_hook() {
+    std::panic::set_hook(Box::new(|info| {
+        let task = TASK.try_with(|task| *task).unwrap_or(UNSUPERVISED);
+        let location = info
+            .location()
+            .map(|l| format!("{}:{}", l.file(), l.line()))
+            .unwrap_or_default();
+        metrics::PANICS.with_label_values(&[task]).inc();
+        tracing::error!(
+            task,
+            location = %location,
+            message = %panic_message(info),
+            "Panic"
+        );
+    }));
+}
+
+/// What a supervised task is doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskState {
+    Running,
+    /// Panicked; waiting out the backoff
+    Restarting,
+    /// Returned (done, disabled, or stopped for shutdown)
+    Finished,
+}
+
+/// A supervised task, as shown at `/admin/tasks`
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskStatus {
+    pub name: &'static str,
+    pub state: TaskState,
+    /// Start of the current (or last) run (Unix seconds)
+    pub started_at: i64,
+    pub restarts: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_panic: Option<String>,
+    /// Unix seconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_panic_at: Option<i64>,
+}
+
+type Statuses = Arc<Mutex<BTreeMap<&'static str, TaskStatus>>>;
+
+/// Owns the background tasks: starts them, restarts them when they panic,
+/// and waits for them at shutdown
+pub struct Supervisor {
+    events: Arc<dyn EventPublisher>,
+    shutdown: broadcast::Sender<()>,
+    statuses: Statuses,
+    /// Supervision loops, each ending with its task
+    loops: Mutex<Vec<(&'static str, JoinHandle<()>)>>,
+}
+
+impl Supervisor {
+    pub fn new(events: Arc<dyn EventPublisher>, shutdown: broadcast::Sender<()>) -> Self {
+        Self {
+            events,
+            shutdown,
+            statuses: Arc::default(),
+            loops: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Run `task` (given a shutdown receiver) on its own tokio task, and
+    /// again after a backoff each time it panics, until shutdown
+    pub fn spawn<F, Fut>(&self, name: &'static str, task: F)
+    where
+        F: Fn(broadcast::Receiver<()>) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let events = self.events.clone();
+        let shutdown = self.shutdown.clone();
+        let statuses = self.statuses.clone();
+        update(&statuses, name, |_| {});
+        let mut stopping = shutdown.subscribe();
+        let handle = tokio::spawn(async move {
+            let mut backoff = INITIAL_BACKOFF;
+            let mut restarts = 0;
+            loop {
+                let started = Instant::now();
+                update(&statuses, name, |status| {
+                    status.state = TaskState::Running;
+                    status.started_at = chrono::Utc::now().timestamp();
+                });
+                let run = tokio::spawn(TASK.scope(name, task(shutdown.subscribe())));
+                let message = match run.await {
+                    Err(e) if e.is_panic() => panic_payload(e.into_panic()),
+                    _ => break,
+                };
+
+                if started.elapsed() >= STABLE_AFTER {
+                    backoff = INITIAL_BACKOFF;
+                }
+                restarts += 1;
+                metrics::TASK_RESTARTS.with_label_values(&[name]).inc();
+                update(&statuses, name, |status| {
+                    status.state = TaskState::Restarting;
+                    status.restarts = restarts;
+                    status.last_panic = Some(message.clone());
+                    status.last_panic_at = Some(chrono::Utc::now().timestamp());
+                });
+                tracing::warn!(
+                    task = name,
+                    restarts,
+                    "Task panicked; restarting in {:?}",
+                    backoff
+                );
+                events.publish(CerberusEvent::TaskPanicked {
+                    task: name.to_string(),
+                    message,
+                    restarts,
+                    restart_in_secs: backoff.as_secs(),
+                });
+
+                tokio::select! {
+                    _ = tokio::time::sleep(backoff) => {}
+                    _ = stopping.recv() => break,
+                }
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            update(&statuses, name, |status| status.state = TaskState::Finished);
+        });
+        lock(&self.loops).push((name, handle));
+    }
+
+    /// Every task, by name
+    pub fn status(&self) -> Vec<TaskStatus> {
+        lock(&self.statuses).values().cloned().collect()
+    }
+
+    /// Wait up to `timeout` for every task to finish (once shutdown has been
+    /// signalled); returns the names of those still running
+    pub async fn wait(&self, timeout: Duration) -> Vec<&'static str> {
+        let loops = std::mem::take(&mut *lock(&self.loops));
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut running = Vec::new();
+        for (name, handle) in loops {
+            if tokio::time::timeout_at(deadline, handle).await.is_err() {
+                running.push(name);
+            }
+        }
+        running
+    }
+}
+
+/// Change `name`'s status, adding it if it's new
+fn update(statuses: &Statuses, name: &'static str, change: impl FnOnce(&mut TaskStatus)) {
+    let mut statuses = lock(statuses);
+    let status = statuses.entry(name).or_insert_with(|| TaskStatus {
+        name,
+        state: TaskState::Running,
+        started_at: 0,
+        restarts: 0,
+        last_panic: None,
+        last_panic_at: None,
+    });
+    change(status);
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn panic_message(info: &PanicHookInfo<'_>) -> String {
+    if let Some(message) = info.payload().downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = info.payload().downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "(no message)".to_string()
+    }
+}
+
+fn panic_payload(payload: Box<dyn std::any::Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload
+            .downcast_ref::<&str>()
+            .map_or_else(|| "(no message)".to_string(), |m| m.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[derive(Default)]
+    struct Collect(Mutex<Vec<CerberusEvent>>);
+
+    impl EventPublisher for Collect {
+        fn publish(&self, event: CerberusEvent) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_restarts_after_panic() {
+        let events = Arc::new(Collect::default());
+        let (shutdown, _) = broadcast::channel(1);
+        let supervisor = Supervisor::new(events.clone(), shutdown.clone());
+
+        // Panics twice, then runs until shutdown
+        let runs = Arc::new(AtomicU32::new(0));
+        let counter = runs.clone();
+        supervisor.spawn("flaky", move |mut shutdown| {
+            let run = counter.fetch_add(1, Ordering::SeqCst);
+            async move {
+                if run < 2 {
+                    panic!("run {} failed", run);
+                }
+                let _ = shutdown.recv().await;
+            }
+        });
+
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        let published = events.0.lock().unwrap().clone();
+        assert_eq!(
+            published,
+            [
+                CerberusEvent::TaskPanicked {
+                    task: "flaky".to_string(),
+                    message: "run 0 failed".to_string(),
+                    restarts: 1,
+                    restart_in_secs: 1,
+                },
+                CerberusEvent::TaskPanicked {
+                    task: "flaky".to_string(),
+                    message: "run 1 failed".to_string(),
+                    restarts: 2,
+                    restart_in_secs: 2,
+                },
+            ]
+        );
+
+        let status = &supervisor.status()[0];
+        assert_eq!((status.state, status.restarts), (TaskState::Running, 2));
+        assert_eq!(status.last_panic.as_deref(), Some("run 1 failed"));
+
+        // Stopped for good by shutdown
+        shutdown.send(()).unwrap();
+        assert!(supervisor.wait(Duration::from_secs(5)).await.is_empty());
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        assert_eq!(supervisor.status()[0].state, TaskState::Finished);
+    }
+}
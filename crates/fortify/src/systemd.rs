@@ -0,0 +1,113 @@
+//! Systemd integration: socket activation, readiness and watchdog.
+//!
+//! All of it is inert unless Fortify is started by systemd: without
+//! `LISTEN_FDS` the listener is bound from config as usual, and without
+//! `NOTIFY_SOCKET` / `WATCHDOG_USEC` notifications are skipped. That lets a
+//! hardened unit (`Type=notify`, `WatchdogSec=`, no network bind rights,
+//! socket owned by a `.socket` unit) run the same binary as development.
+
+use anyhow::Result;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+use crate::listener::Listener;
+
+/// Take the listening socket passed by systemd (`fortify.socket`), if any
+///
+/// Only the first inherited descriptor is used; it may be a TCP or a unix
+/// stream socket.
+#[cfg(unix)]
+pub fn inherited_listener() -> Result<Option<Listener>> {
+    use anyhow::Context;
+    use std::os::fd::{FromRawFd, IntoRawFd};
+
+    let Some(fd) = sd_notify::listen_fds()
+        .context("Invalid LISTEN_FDS from systemd")?
+        .next()
+    else {
+        return Ok(None);
+    };
+
+    // SAFETY: systemd hands us ownership of descriptors from 3 up to
+    // 3 + LISTEN_FDS, and nothing else in the process has touched them.
+    let unix = unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) };
+    if unix.local_addr().is_ok() {
+        unix.set_nonblocking(true)?;
+        return Ok(Some(Listener::Unix(tokio::net::UnixListener::from_std(
+            unix,
+        )?)));
+    }
+
+    // Not AF_UNIX, so it should be TCP
+    // SAFETY: ownership moves straight from the unix wrapper
+    let tcp = unsafe { std::net::TcpListener::from_raw_fd(unix.into_raw_fd()) };
+    tcp.set_nonblocking(true)
+        .context("Inherited socket is not a stream listener")?;
+    Ok(Some(Listener::Tcp(tokio::net::TcpListener::from_std(tcp)?)))
+}
+
+#[cfg(not(unix))]
+pub fn inherited_listener() -> Result<Option<Listener>> {
+    Ok(None)
+}
+
+/// Tell systemd startup is complete (`READY=1`)
+pub fn notify_ready(status: &str) {
+    #[cfg(unix)]
+    notify(&[
+        sd_notify::NotifyState::Ready,
+        sd_notify::NotifyState::Status(status),
+    ]);
+    #[cfg(not(unix))]
+    let _ = status;
+}
+
+/// Tell systemd shutdown has begun (`STOPPING=1`)
+pub fn notify_stopping() {
+    #[cfg(unix)]
+    notify(&[sd_notify::NotifyState::Stopping]);
+}
+
+#[cfg(unix)]
+fn notify(states: &[sd_notify::NotifyState]) {
+    if let Err(e) = sd_notify::notify(false, states) {
+        tracing::warn!(error = %e, "Failed to notify systemd");
+    }
+}
+
+/// Watchdog interval requested by the unit (`WatchdogSec=`), if any
+fn watchdog_interval() -> Option<Duration> {
+    #[cfg(unix)]
+    {
+        let mut usec = 0;
+        if sd_notify::watchdog_enabled(false, &mut usec) && usec > 0 {
+            return Some(Duration::from_micros(usec));
+        }
+    }
+    None
+}
+
+/// Send `WATCHDOG=1` at half the configured interval until shutdown
+///
+/// Keepalives come from the async runtime, so a wedged runtime stops them
+/// and systemd restarts the service.
+pub async fn watchdog_worker(mut shutdown: broadcast::Receiver<()>) {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+    tracing::info!(
+        interval_ms = interval.as_millis() as u64,
+        "🐕 Systemd watchdog enabled"
+    );
+
+    let mut ticker = tokio::time::interval(interval / 2);
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                #[cfg(unix)]
+                notify(&[sd_notify::NotifyState::Watchdog]);
+            }
+            _ = shutdown.recv() => break,
+        }
+    }
+}
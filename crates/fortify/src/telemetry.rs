@@ -0,0 +1,181 @@
+//! Logging and optional OpenTelemetry trace export.
+//!
+//! With the `otel` feature and `[telemetry] enabled = true`, spans are
+//! exported over OTLP/HTTP to a collector. Incoming W3C `traceparent`
+//! headers (e.g. from HAProxy or Nginx) become the parent of the request
+//! span, so a trace continues across the proxy chain.
+//!
+//! Log fields pass through the `[privacy]` policy (see `redact`) in both
+//! text and JSON output.
+
+use anyhow::Result;
+use axum::http::Request;
+use tracing::Span;
+use tracing_subscriber::fmt::format::DefaultFields;
+use tracing_subscriber::{EnvFilter, fmt, prelude::*};
+
+use crate::config::TelemetryConfig;
+use crate::redact::{self, JsonEvents, PrivacyConfig, RedactFields, RedactJsonFields};
+
+/// Flushes pending spans on drop; keep alive for the life of the process
+pub struct TelemetryGuard {
+    #[cfg(feature = "otel")]
+    provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.provider.take()
+            && let Err(e) = provider.shutdown()
+        {
+            eprintln!("Failed to flush traces: {}", e);
+        }
+    }
+}
+
+/// Initialize structured logging (and trace export, if configured)
+pub fn init(
+    level: &str,
+    json: bool,
+    config: &TelemetryConfig,
+    privacy: &PrivacyConfig,
+) -> Result<TelemetryGuard> {
+    redact::install(privacy);
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level));
+
+    #[cfg(feature = "otel")]
+    let (guard, otel) = otel::init(config)?;
+    #[cfg(not(feature = "otel"))]
+    let (guard, otel) = (
+        TelemetryGuard {},
+        None::<tracing_subscriber::layer::Identity>,
+    );
+
+    if json {
+        tracing_subscriber::registry()
+            .with(otel)
+            .with(filter)
+            .with(
+                fmt::layer()
+                    .event_format(JsonEvents)
+                    .fmt_fields(RedactJsonFields),
+            )
+            .init();
+    } else {
+        tracing_subscriber::registry()
+            .with(otel)
+            .with(filter)
+            .with(
+                fmt::layer()
+                    .with_target(true)
+                    .with_thread_ids(true)
+                    .fmt_fields(RedactFields::new(DefaultFields::new())),
+            )
+            .init();
+    }
+
+    #[cfg(not(feature = "otel"))]
+    if config.enabled {
+        tracing::warn!(
+            "Telemetry enabled in config, but Fortify was built without the `otel` feature"
+        );
+    }
+
+    Ok(guard)
+}
+
+/// Span for an incoming HTTP request (parented to `traceparent`, if any)
+///
+/// `path` is the request path as it may be logged (see
+/// `routes::route_metrics::RouteLabels`, which hides the admin mount path).
+pub fn request_span<B>(request: &Request<B>, path: &str) -> Span {
+    let span = tracing::info_span!(
+        "request",
+        method = %request.method(),
+        path = %path,
+    );
+
+    #[cfg(feature = "otel")]
+    otel::set_parent(&span, request.headers());
+
+    span
+}
+
+#[cfg(feature = "otel")]
+mod otel {
+    use anyhow::{Context, Result};
+    use axum::http::HeaderMap;
+    use opentelemetry::propagation::Extractor;
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::Resource;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use opentelemetry_sdk::trace::{Sampler, SdkTracer, SdkTracerProvider};
+    use tracing::Span;
+    use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+    use tracing_subscriber::registry::LookupSpan;
+
+    use super::TelemetryGuard;
+    use crate::config::TelemetryConfig;
+
+    pub fn init<S>(
+        config: &TelemetryConfig,
+    ) -> Result<(TelemetryGuard, Option<OpenTelemetryLayer<S, SdkTracer>>)>
+    where
+        S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    {
+        if !config.enabled {
+            return Ok((TelemetryGuard { provider: None }, None));
+        }
+
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(&config.otlp_endpoint)
+            .build()
+            .context("Failed to build OTLP exporter")?;
+
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                config.sample_ratio,
+            ))))
+            .with_resource(
+                Resource::builder()
+                    .with_service_name(config.service_name.clone())
+                    .build(),
+            )
+            .build();
+
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        let tracer = provider.tracer("fortify");
+        let layer = tracing_opentelemetry::layer().with_tracer(tracer);
+
+        Ok((
+            TelemetryGuard {
+                provider: Some(provider),
+            },
+            Some(layer),
+        ))
+    }
+
+    /// Continue a trace started upstream
+    pub fn set_parent(span: &Span, headers: &HeaderMap) {
+        let parent = opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(headers))
+        });
+        let _ = span.set_parent(parent);
+    }
+
+    struct HeaderExtractor<'a>(&'a HeaderMap);
+
+    impl Extractor for HeaderExtractor<'_> {
+        fn get(&self, key: &str) -> Option<&str> {
+            self.0.get(key).and_then(|v| v.to_str().ok())
+        }
+
+        fn keys(&self) -> Vec<&str> {
+            self.0.keys().map(|k| k.as_str()).collect()
+        }
+    }
+}
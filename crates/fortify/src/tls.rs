@@ -0,0 +1,281 @@
+//! TLS on the Fortify listener, with optional client certificates (mTLS).
+//!
+//! With `[tls] enabled`, every connection is TLS. When `client_ca_path` is
+//! also set, clients may present a certificate issued by that CA. The
+//! handshake still succeeds without one (so the gate pages stay reachable),
+//! but the control surfaces (`/validate` and `/admin`) refuse requests
+//! that lack a verified client certificate: only the local proxies and
+//! cluster peers are issued one.
+
+use anyhow::{Context, Result};
+use axum::extract::Request;
+use axum::extract::connect_info::{ConnectInfo, Connected};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::serve::{IncomingStream, Listener};
+use rustls::RootCertStore;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ServerConfig, WebPkiClientVerifier};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::server::TlsStream;
+
+use crate::config::TlsConfig;
+use crate::trusted_proxy::Peer;
+
+/// Handshakes that take longer than this are dropped
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Completed handshakes waiting for the server to pick them up
+const ACCEPT_BACKLOG: usize = 256;
+
+/// Build the rustls server config from `[tls]`
+pub fn server_config(config: &TlsConfig) -> Result<Arc<ServerConfig>> {
+    let certs = CertificateDer::pem_file_iter(&config.cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read certificate {}", config.cert_path))?;
+    let key = PrivateKeyDer::from_pem_file(&config.key_path)
+        .with_context(|| format!("Failed to read private key {}", config.key_path))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+
+    let builder = match &config.client_ca_path {
+        Some(ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in CertificateDer::pem_file_iter(ca_path)
+                .with_context(|| format!("Failed to read client CA {}", ca_path))?
+            {
+                roots.add(cert.with_context(|| format!("Bad certificate in {}", ca_path))?)?;
+            }
+
+            // Optional at the handshake; enforced per route group
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .allow_unauthenticated()
+                .build()
+                .context("Failed to build client certificate verifier")?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut server_config = builder
+        .with_single_cert(certs, key)
+        .context("Certificate does not match private key")?;
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(Arc::new(server_config))
+}
+
+/// Wraps a listener, yielding connections once their TLS handshake is done
+///
+/// Handshakes run in their own tasks so one slow client cannot hold up
+/// everyone else's accept.
+pub struct TlsListener<L: Listener> {
+    incoming: mpsc::Receiver<(TlsStream<L::Io>, L::Addr)>,
+    local_addr: L::Addr,
+}
+
+impl<L> TlsListener<L>
+where
+    L: Listener,
+    L::Addr: Clone + 'static,
+{
+    pub fn new(mut inner: L, config: Arc<ServerConfig>) -> std::io::Result<Self> {
+        let local_addr = inner.local_addr()?;
+        let acceptor = TlsAcceptor::from(config);
+        let (tx, incoming) = mpsc::channel(ACCEPT_BACKLOG);
+
+        tokio::spawn(async move {
+            loop {
+                let (io, addr) = tokio::select! {
+                    conn = inner.accept() => conn,
+                    _ = tx.closed() => break,
+                };
+
+                let acceptor = acceptor.clone();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(io)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = tx.send((stream, addr)).await;
+                        }
+                        Ok(Err(e)) => tracing::debug!(error = %e, "TLS handshake failed"),
+                        Err(_) => tracing::debug!("TLS handshake timed out"),
+                    }
+                });
+            }
+        });
+
+        Ok(Self {
+            incoming,
+            local_addr,
+        })
+    }
+}
+
+impl<L> Listener for TlsListener<L>
+where
+    L: Listener,
+    L::Addr: Clone + Sync + 'static,
+{
+    type Io = TlsStream<L::Io>;
+    type Addr = L::Addr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.incoming.recv().await {
+            Some(conn) => conn,
+            // The accept task only stops once we are dropped
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Ok(self.local_addr.clone())
+    }
+}
+
+/// Client certificate presented on this connection (already verified
+/// against `client_ca_path` by the handshake)
+#[derive(Debug, Clone)]
+pub struct ClientCert {
+    /// SHA-256 of the leaf certificate, hex (for audit logs)
+    pub fingerprint: Option<String>,
+    /// Where the connection came from (see `trusted_proxy`)
+    pub peer: Peer,
+}
+
+impl<L> Connected<IncomingStream<'_, TlsListener<L>>> for ClientCert
+where
+    L: Listener,
+    L::Addr: Clone + Sync + Into<Peer> + 'static,
+{
+    fn connect_info(stream: IncomingStream<'_, TlsListener<L>>) -> Self {
+        let (_, session) = stream.io().get_ref();
+        Self {
+            peer: stream.remote_addr().clone().into(),
+            fingerprint: session
+                .peer_certificates()
+                .and_then(|certs| certs.first())
+                .map(|leaf| hex_digest(leaf.as_ref())),
+        }
+    }
+}
+
+fn hex_digest(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Reject requests without a verified client certificate
+pub async fn require_client_cert(request: Request, next: Next) -> Response {
+    let fingerprint = request
+        .extensions()
+        .get::<ConnectInfo<ClientCert>>()
+        .and_then(|ConnectInfo(cert)| cert.fingerprint.clone());
+
+    match fingerprint {
+        Some(fingerprint) => {
+            tracing::debug!(client_cert = %fingerprint, path = %request.uri().path(), "mTLS client");
+            next.run(request).await
+        }
+        None => (StatusCode::FORBIDDEN, "Client certificate required").into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::routing::get;
+    use rustls::ClientConfig;
+    use rustls::pki_types::ServerName;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::TlsConnector;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tls");
+
+    fn fixture(name: &str) -> String {
+        format!("{}/{}", FIXTURES, name)
+    }
+
+    async fn get_status(addr: std::net::SocketAddr, path: &str, with_cert: bool) -> String {
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(CertificateDer::from_pem_file(fixture("ca.crt")).unwrap())
+            .unwrap();
+        let builder =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots);
+        let config = if with_cert {
+            let certs = CertificateDer::pem_file_iter(fixture("client.crt"))
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let key = PrivateKeyDer::from_pem_file(fixture("client.key")).unwrap();
+            builder.with_client_auth_cert(certs, key).unwrap()
+        } else {
+            builder.with_no_client_auth()
+        };
+
+        let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut tls = TlsConnector::from(Arc::new(config))
+            .connect(ServerName::try_from("localhost").unwrap(), tcp)
+            .await
+            .unwrap();
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            path
+        );
+        tls.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        tls.read_to_string(&mut response).await.unwrap();
+        response.lines().next().unwrap_or_default().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_mtls_enforced_on_control_routes() {
+        let config = server_config(&TlsConfig {
+            enabled: true,
+            cert_path: fixture("server.crt"),
+            key_path: fixture("server.key"),
+            client_ca_path: Some(fixture("ca.crt")),
+        })
+        .unwrap();
+
+        let app = Router::new()
+            .route("/validate", get(|| async { "ok" }))
+            .layer(axum::middleware::from_fn(require_client_cert))
+            .route("/health", get(|| async { "ok" }));
+
+        let tcp = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = tcp.local_addr().unwrap();
+        let listener = TlsListener::new(tcp, config).unwrap();
+        tokio::spawn(async move {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<ClientCert>(),
+            )
+            .await
+            .unwrap();
+        });
+
+        assert_eq!(get_status(addr, "/health", false).await, "HTTP/1.1 200 OK");
+        assert_eq!(
+            get_status(addr, "/validate", false).await,
+            "HTTP/1.1 403 Forbidden"
+        );
+        assert_eq!(get_status(addr, "/validate", true).await, "HTTP/1.1 200 OK");
+    }
+}
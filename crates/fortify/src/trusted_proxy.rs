@@ -0,0 +1,250 @@
+//! Trust boundary for proxy-set headers.
+//!
+//! HAProxy and Nginx tell Fortify about the request through headers:
+//! `X-Circuit-Id` names the Tor circuit, `X-Original-URI` and
+//! `X-Original-Method` describe the request behind an auth subrequest, and
+//! so on. A client able to set those itself could pose as another circuit
+//! or dodge rules matched on the path. So they are only honoured on
+//! connections from a trusted hop (`[trusted_proxies]`: a list of source
+//! addresses, and/or the unix socket listener); on any other connection
+//! they are stripped before the request reaches a handler.
+
+use axum::extract::connect_info::{ConnectInfo, Connected};
+use axum::extract::{Request, State};
+use axum::http::HeaderName;
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::serve::IncomingStream;
+use cerberus_common::constants::headers;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+use crate::metrics;
+use crate::tls::ClientCert;
+
+/// Trusted hops (`[trusted_proxies]` in fortify.toml)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrustedProxyConfig {
+    /// Strip internal headers from untrusted connections (off = trust everyone)
+    pub enabled: bool,
+    /// Source addresses of trusted proxies: IPs or CIDR ranges
+    pub addresses: Vec<String>,
+    /// Trust connections on the unix socket listener
+    pub unix_socket: bool,
+    /// Headers only a trusted hop may set
+    pub headers: Vec<String>,
+}
+
+impl Default for TrustedProxyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            addresses: vec!["127.0.0.1/32".to_string(), "::1/128".to_string()],
+            unix_socket: true,
+            headers: [
+                headers::X_CIRCUIT_ID,
+                headers::X_ORIGINAL_URI,
+                headers::X_ORIGINAL_METHOD,
+                headers::X_THREAT_LEVEL,
+                headers::X_NODE_ID,
+                "X-Forwarded-For",
+                "X-Forwarded-Host",
+                "X-Forwarded-Proto",
+                "X-Real-IP",
+                "Forwarded",
+            ]
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        }
+    }
+}
+
+/// Parse an entry of `addresses`: a CIDR range, or a single IP
+pub fn parse_address(s: &str) -> Option<IpNet> {
+    s.parse()
+        .ok()
+        .or_else(|| s.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+/// Where a connection came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Peer {
+    Tcp(IpAddr),
+    /// The unix socket listener (the peer has no address)
+    Unix,
+}
+
+impl From<SocketAddr> for Peer {
+    fn from(addr: SocketAddr) -> Self {
+        Self::Tcp(addr.ip().to_canonical())
+    }
+}
+
+#[cfg(unix)]
+impl From<tokio::net::unix::SocketAddr> for Peer {
+    fn from(_: tokio::net::unix::SocketAddr) -> Self {
+        Self::Unix
+    }
+}
+
+impl Connected<IncomingStream<'_, tokio::net::TcpListener>> for Peer {
+    fn connect_info(stream: IncomingStream<'_, tokio::net::TcpListener>) -> Self {
+        (*stream.remote_addr()).into()
+    }
+}
+
+#[cfg(unix)]
+impl Connected<IncomingStream<'_, tokio::net::UnixListener>> for Peer {
+    fn connect_info(_: IncomingStream<'_, tokio::net::UnixListener>) -> Self {
+        Self::Unix
+    }
+}
+
+/// Parsed `[trusted_proxies]`
+#[derive(Debug)]
+pub struct TrustedProxies {
+    enabled: bool,
+    networks: Vec<IpNet>,
+    unix_socket: bool,
+    headers: Vec<HeaderName>,
+}
+
+impl TrustedProxies {
+    /// Entries that don't parse are skipped (config validation reports them)
+    pub fn new(config: &TrustedProxyConfig) -> Self {
+        Self {
+            enabled: config.enabled,
+            networks: config
+                .addresses
+                .iter()
+                .filter_map(|s| parse_address(s))
+                .collect(),
+            unix_socket: config.unix_socket,
+            headers: config
+                .headers
+                .iter()
+                .filter_map(|name| HeaderName::try_from(name.as_str()).ok())
+                .collect(),
+        }
+    }
+
+    /// Whether `peer` may set the internal headers (unknown peers may not)
+    pub fn trusts(&self, peer: Option<Peer>) -> bool {
+        if !self.enabled {
+            return true;
+        }
+        match peer {
+            Some(Peer::Tcp(ip)) => self.networks.iter().any(|net| net.contains(&ip)),
+            Some(Peer::Unix) => self.unix_socket,
+            None => false,
+        }
+    }
+}
+
+/// The peer recorded by the listener (plain or TLS)
+pub fn peer_of(request: &Request) -> Option<Peer> {
+    let extensions = request.extensions();
+    extensions
+        .get::<ConnectInfo<Peer>>()
+        .map(|ConnectInfo(peer)| *peer)
+        .or_else(|| {
+            extensions
+                .get::<ConnectInfo<ClientCert>>()
+                .map(|ConnectInfo(cert)| cert.peer)
+        })
+}
+
+/// Remove internal headers from requests that didn't come through a trusted hop
+pub async fn strip_untrusted(
+    State(proxies): State<Arc<TrustedProxies>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let peer = peer_of(&request);
+    if !proxies.trusts(peer) {
+        let headers = request.headers_mut();
+        for name in &proxies.headers {
+            if headers.remove(name).is_some() {
+                metrics::UNTRUSTED_HEADERS_STRIPPED
+                    .with_label_values(&[name.as_str()])
+                    .inc();
+                tracing::debug!(header = %name, ?peer, "Stripped header from untrusted peer");
+            }
+        }
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::body::Body;
+    use axum::routing::get;
+    use tower::ServiceExt;
+
+    #[test]
+    fn test_trusted_peers() {
+        let proxies = TrustedProxies::new(&TrustedProxyConfig {
+            addresses: vec!["10.0.0.0/8".into(), "192.168.1.5".into(), "bogus".into()],
+            unix_socket: false,
+            ..Default::default()
+        });
+        let tcp = |s: &str| Some(Peer::from(s.parse::<SocketAddr>().unwrap()));
+
+        assert!(proxies.trusts(tcp("10.20.30.40:5000")));
+        assert!(proxies.trusts(tcp("192.168.1.5:80")));
+        assert!(!proxies.trusts(tcp("192.168.1.6:80")));
+        // IPv4-mapped addresses from a dual-stack socket count as IPv4
+        assert!(proxies.trusts(tcp("[::ffff:10.0.0.1]:80")));
+        assert!(!proxies.trusts(Some(Peer::Unix)));
+        assert!(!proxies.trusts(None));
+
+        assert!(TrustedProxies::new(&TrustedProxyConfig::default()).trusts(Some(Peer::Unix)));
+    }
+
+    #[tokio::test]
+    async fn test_internal_headers_stripped_from_untrusted_peer() {
+        let proxies = Arc::new(TrustedProxies::new(&TrustedProxyConfig::default()));
+        let app = Router::new()
+            .route(
+                "/",
+                get(|request: Request| async move {
+                    let has = |name: &str| request.headers().contains_key(name);
+                    format!("{} {}", has(headers::X_CIRCUIT_ID), has("user-agent"))
+                }),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                proxies,
+                strip_untrusted,
+            ));
+
+        let call = |peer: SocketAddr| {
+            let mut request = Request::builder()
+                .uri("/")
+                .header(headers::X_CIRCUIT_ID, "abc")
+                .header("User-Agent", "test")
+                .body(Body::empty())
+                .unwrap();
+            request
+                .extensions_mut()
+                .insert(ConnectInfo(Peer::from(peer)));
+            let app = app.clone();
+            async move {
+                let body = app.oneshot(request).await.unwrap().into_body();
+                let bytes = axum::body::to_bytes(body, 1024).await.unwrap();
+                String::from_utf8(bytes.to_vec()).unwrap()
+            }
+        };
+
+        assert_eq!(call("127.0.0.1:4000".parse().unwrap()).await, "true true");
+        assert_eq!(
+            call("203.0.113.9:4000".parse().unwrap()).await,
+            "false true"
+        );
+    }
+}
@@ -0,0 +1,677 @@
+//! CAPTCHA verification service.
+//!
+//! Owns the whole verify flow so routes only deal with HTTP concerns:
+//! 1. Consume the challenge and compare the answer (`CaptchaVerifier::check`)
+//! 2. Plan the passport and the circuit state change in memory
+//! 3. Commit both in one script that only writes if the circuit record is
+//!    unchanged since it was loaded, so a passport is never stored without
+//!    its circuit record (or vice versa) and concurrent verifies for one
+//!    circuit don't overwrite each other; on a conflict, go back to 2
+//! 4. Append the circuit's history events
+//! 5. Feed the solve time into farm detection (best-effort)
+//!
+//! A passport issued on a solved challenge starts its journey (see
+//! `journey`), committed along with the history events.
+//!
+//! With no Redis connection the flow runs statelessly: sealed challenge,
+//! signed passport, and the circuit change queued for replay.
+
+use anyhow::{Result, bail};
+use cerberus_common::{
+    CaptchaResult, CerberusEvent, ChallengeId, CircuitId, CircuitInfo, EventPublisher, ThreatLevel,
+};
+use redis::AsyncCommands;
+use redis::aio::ConnectionManager;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::captcha::{CaptchaVerifier, ChallengeCheck, PassportGrant};
+use crate::challenge_stats::{ChallengeStats, ChallengeStatsConfig};
+use crate::circuits::{
+    CircuitEvent, CircuitEventKind, CircuitMutation, CircuitTracker, MutationQueue, SolveSample,
+    SolveTimeAnalyzer, VerifyThrottle,
+};
+use crate::experiments::Experiments;
+use crate::journey::{JourneyConfig, Journeys};
+use crate::metrics;
+use crate::rules::RulesEngine;
+
+/// Times a verify reloads a circuit that keeps changing under it
+const MAX_COMMIT_ATTEMPTS: u32 = 5;
+
+/// A verification attempt
+#[derive(Debug, Clone, Copy)]
+pub struct VerificationRequest<'a> {
+    pub challenge_id: &'a ChallengeId,
+    pub answer: &'a str,
+    pub circuit_id: Option<&'a CircuitId>,
+    /// Current threat level (picks the passport TTL)
+    pub threat_level: ThreatLevel,
+}
+
+/// Verification flow service
+pub struct VerificationService {
+    verifier: Arc<CaptchaVerifier>,
+    tracker: Arc<CircuitTracker>,
+    analyzer: Arc<SolveTimeAnalyzer>,
+    queue: Arc<MutationQueue>,
+    /// Answers feed the attack signature rules
+    rules: Arc<RulesEngine>,
+    /// Bursts of wrong answers lock the gate
+    throttle: Arc<VerifyThrottle>,
+    publisher: Arc<dyn EventPublisher>,
+    /// Answers are tallied per experiment variant
+    experiments: Arc<Experiments>,
+    /// Passports issued on a solved challenge start a journey
+    journeys: Arc<Journeys>,
+    /// Answers are tallied per difficulty
+    stats: Arc<ChallengeStats>,
+}
+
+impl VerificationService {
+    pub fn new(
+        verifier: Arc<CaptchaVerifier>,
+        tracker: Arc<CircuitTracker>,
+        analyzer: Arc<SolveTimeAnalyzer>,
+        queue: Arc<MutationQueue>,
+        rules: Arc<RulesEngine>,
+        throttle: Arc<VerifyThrottle>,
+        publisher: Arc<dyn EventPublisher>,
+    ) -> Self {
+        Self {
+            verifier,
+            tracker,
+            analyzer,
+            queue,
+            rules,
+            throttle,
+            publisher,
+            experiments: Arc::default(),
+            journeys: Arc::new(Journeys::new(&JourneyConfig { enabled: false })),
+            stats: Arc::new(ChallengeStats::new(ChallengeStatsConfig {
+                enabled: false,
+                ..Default::default()
+            })),
+        }
+    }
+
+    /// Tally answers for the experiments circuits are enrolled in
+    pub fn with_experiments(mut self, experiments: Arc<Experiments>) -> Self {
+        self.experiments = experiments;
+        self
+    }
+
+    /// Record the journeys of passports issued on a solved challenge
+    pub fn with_journeys(mut self, journeys: Arc<Journeys>) -> Self {
+        self.journeys = journeys;
+        self
+    }
+
+    /// Tally answers per difficulty into `stats`
+    pub fn with_challenge_stats(mut self, stats: Arc<ChallengeStats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Verify an answer and record the outcome
+    ///
+    /// `redis` is `None` while offline.
+    #[tracing::instrument(
+        name = "captcha.verify",
+        skip_all,
+        fields(challenge_id = %request.challenge_id, circuit_id = ?request.circuit_id)
+    )]
+    pub async fn verify(
+        &self,
+        redis: Option<&mut ConnectionManager>,
+        request: VerificationRequest<'_>,
+    ) -> Result<CaptchaResult> {
+        let started = Instant::now();
+        let Some(redis) = redis else {
+            return self.verify_offline(request, started);
+        };
+        let received_at_ms = chrono::Utc::now().timestamp_millis();
+
+        let (check, difficulty) = self
+            .verifier
+            .check(
+                redis,
+                request.challenge_id,
+                request.answer,
+                request.circuit_id,
+            )
+            .await?;
+
+        // Compare-and-set on the circuit record: a concurrent verify for the
+        // same circuit makes us reload and plan again instead of losing its
+        // update
+        let mut attempts = 0;
+        let (passport, circuit, vip) = loop {
+            attempts += 1;
+            let loaded = match request.circuit_id {
+                Some(circuit_id) => Some(self.tracker.load_for_update(redis, circuit_id).await?),
+                None => None,
+            };
+
+            let vip = match (check, &loaded) {
+                (ChallengeCheck::Correct { .. }, Some((info, _))) => {
+                    self.tracker.qualifies_for_vip(redis, info).await?
+                }
+                _ => false,
+            };
+            let passport = match check {
+                ChallengeCheck::Correct { .. } => Some(self.verifier.grant_passport(
+                    request.circuit_id,
+                    false,
+                    vip,
+                    request.threat_level,
+                )?),
+                _ => None,
+            };
+            let record = passport.as_ref().and_then(|p| p.record.as_ref());
+
+            let Some((mut info, stored)) = loaded else {
+                if let Some(entry) = record {
+                    redis
+                        .set_ex::<_, _, ()>(&entry.key, &entry.value, entry.ttl)
+                        .await?;
+                }
+                break (passport, None, vip);
+            };
+            let events = self.plan_circuit(&mut info, passport.as_ref(), vip);
+            if self
+                .tracker
+                .save_if_unchanged(redis, &info, stored.as_deref(), record)
+                .await?
+            {
+                break (passport, Some((info, events)), vip);
+            }
+            if attempts >= MAX_COMMIT_ATTEMPTS {
+                bail!(
+                    "circuit {} changed on every one of {} attempts",
+                    info.circuit_id,
+                    attempts
+                );
+            }
+        };
+
+        let journey = match (check, &passport) {
+            (ChallengeCheck::Correct { solve_time_ms: Some(solve_ms) }, Some(grant)) => self
+                .journeys
+                .start(received_at_ms, solve_ms, request.threat_level)
+                .map(|journey| (grant, journey)),
+            _ => None,
+        };
+
+        if circuit.is_some() || journey.is_some() {
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            if let Some((grant, journey)) = journey {
+                self.journeys
+                    .queue_start(&mut pipe, &grant.token, grant.expires_at, &journey);
+            }
+            if let Some((ref info, ref events)) = circuit {
+                self.tracker.queue_related(&mut pipe, info, events);
+            }
+            pipe.query_async::<()>(redis).await?;
+
+            if let Some((ref info, ref events)) = circuit {
+                self.tracker.announce(&info.circuit_id, events);
+            }
+        }
+        if let Some(ref grant) = passport {
+            if let Some(circuit_id) = request.circuit_id {
+                self.limit_passports(redis, circuit_id, grant).await;
+            }
+            self.announce_passport(request.circuit_id, grant, vip);
+        }
+
+        observe(check, started);
+        self.record_answer(request.circuit_id, check);
+        self.experiments
+            .record(redis, request.circuit_id, check)
+            .await;
+        self.stats.record(redis, difficulty, check).await;
+        if let (ChallengeCheck::Incorrect, Some(circuit_id)) = (check, request.circuit_id) {
+            self.record_wrong_answer(redis, circuit_id).await;
+        }
+        if let ChallengeCheck::Correct { solve_time_ms } = check {
+            tracing::info!(
+                challenge_id = %request.challenge_id,
+                circuit_id = ?request.circuit_id,
+                "CAPTCHA verified successfully"
+            );
+            if let (Some(solve_ms), Some(circuit_id)) = (solve_time_ms, request.circuit_id) {
+                self.record_solve_time(redis, circuit_id, solve_ms, request.answer)
+                    .await;
+            }
+        }
+
+        Ok(build_result(check, passport))
+    }
+
+    /// Stateless flow: sealed challenge, signed passport, queued mutation
+    fn verify_offline(
+        &self,
+        request: VerificationRequest<'_>,
+        started: Instant,
+    ) -> Result<CaptchaResult> {
+        let check = self
+            .verifier
+            .check_sealed(request.challenge_id, request.answer);
+
+        let passport = match check {
+            ChallengeCheck::Correct { .. } => Some(self.verifier.grant_passport(
+                request.circuit_id,
+                true,
+                false,
+                request.threat_level,
+            )?),
+            _ => None,
+        };
+
+        if let Some(ref grant) = passport {
+            self.announce_passport(request.circuit_id, grant, false);
+        }
+        if let Some(circuit_id) = request.circuit_id {
+            let circuit_id = circuit_id.clone();
+            self.queue.push(match passport {
+                Some(ref grant) => CircuitMutation::Success {
+                    circuit_id,
+                    passport_token: grant.token.clone(),
+                    passport_expires: grant.expires_at,
+                },
+                None => CircuitMutation::Failure { circuit_id },
+            });
+        }
+        observe(check, started);
+        self.record_answer(request.circuit_id, check);
+
+        Ok(build_result(check, passport))
+    }
+
+    /// Feed an answer to the attack signature rules (expired or unknown
+    /// challenges don't count)
+    fn record_answer(&self, circuit_id: Option<&CircuitId>, check: ChallengeCheck) {
+        match check {
+            ChallengeCheck::Correct { .. } => self.rules.record_verification(circuit_id, true),
+            ChallengeCheck::Incorrect => self.rules.record_verification(circuit_id, false),
+            ChallengeCheck::Expired | ChallengeCheck::Missing => {}
+        }
+    }
+
+    fn announce_passport(&self, circuit_id: Option<&CircuitId>, grant: &PassportGrant, vip: bool) {
+        self.publisher.publish(CerberusEvent::PassportIssued {
+            circuit_id: circuit_id.cloned(),
+            expires_at: grant.expires_at,
+            vip,
+        });
+    }
+
+    /// Apply the outcome to circuit state (no I/O), returning history events
+    fn plan_circuit(
+        &self,
+        info: &mut CircuitInfo,
+        passport: Option<&PassportGrant>,
+        vip: bool,
+    ) -> Vec<CircuitEvent> {
+        match passport {
+            Some(grant) => self
+                .tracker
+                .apply_success(info, &grant.token, grant.expires_at, vip),
+            None => self.tracker.apply_failure(info),
+        }
+    }
+
+    /// VIP fast path: a passport without a challenge, if the circuit is a
+    /// VIP and the threat level is below `vip.skip_challenge_below`
+    #[tracing::instrument(name = "captcha.vip_pass", skip(self, redis))]
+    pub async fn vip_pass(
+        &self,
+        redis: &mut ConnectionManager,
+        circuit_id: &CircuitId,
+        threat_level: ThreatLevel,
+    ) -> Result<Option<PassportGrant>> {
+        let Some(mut info) = self.tracker.get(redis, circuit_id).await? else {
+            return Ok(None);
+        };
+        if !self.tracker.skips_challenge(&info, threat_level) {
+            return Ok(None);
+        }
+
+        let grant = self
+            .verifier
+            .grant_passport(Some(circuit_id), false, true, threat_level)?;
+        let events = self.tracker.apply_fast_pass(
+            &mut info,
+            &grant.token,
+            grant.expires_at,
+            CircuitEventKind::VipFastPass,
+        );
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        if let Some(ref entry) = grant.record {
+            pipe.set_ex(&entry.key, &entry.value, entry.ttl).ignore();
+        }
+        self.tracker.queue_save(&mut pipe, &info, &events)?;
+        pipe.query_async::<()>(redis).await?;
+        self.limit_passports(redis, circuit_id, &grant).await;
+        self.announce_passport(Some(circuit_id), &grant, true);
+
+        tracing::debug!(circuit_id = %circuit_id, "VIP passed without a challenge");
+        Ok(Some(grant))
+    }
+
+    /// Proof-of-work pass: a passport without a visual challenge, for a
+    /// solved gate proof-of-work where `captcha.flow` allows skipping
+    ///
+    /// The caller checks the puzzle and that the circuit isn't locked out.
+    #[tracing::instrument(name = "captcha.pow_pass", skip(self, redis))]
+    pub async fn pow_pass(
+        &self,
+        redis: &mut ConnectionManager,
+        circuit_id: Option<&CircuitId>,
+        threat_level: ThreatLevel,
+    ) -> Result<PassportGrant> {
+        let grant = self
+            .verifier
+            .grant_passport(circuit_id, false, false, threat_level)?;
+        let circuit = match circuit_id {
+            Some(circuit_id) => {
+                let mut info = self.tracker.load(redis, circuit_id).await?;
+                let events = self.tracker.apply_fast_pass(
+                    &mut info,
+                    &grant.token,
+                    grant.expires_at,
+                    CircuitEventKind::PowPass,
+                );
+                Some((info, events))
+            }
+            None => None,
+        };
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        if let Some(ref entry) = grant.record {
+            pipe.set_ex(&entry.key, &entry.value, entry.ttl).ignore();
+        }
+        if let Some((ref info, ref events)) = circuit {
+            self.tracker.queue_save(&mut pipe, info, events)?;
+        }
+        pipe.query_async::<()>(redis).await?;
+        if let Some((ref info, ref events)) = circuit {
+            self.tracker.announce(&info.circuit_id, events);
+        }
+        if let Some(circuit_id) = circuit_id {
+            self.limit_passports(redis, circuit_id, &grant).await;
+        }
+        self.announce_passport(circuit_id, &grant, false);
+
+        tracing::debug!(circuit_id = ?circuit_id, "Passed on proof-of-work");
+        Ok(grant)
+    }
+
+    /// Enforce the per-circuit passport cap; errors are logged, never fatal
+    async fn limit_passports(
+        &self,
+        redis: &mut ConnectionManager,
+        circuit_id: &CircuitId,
+        grant: &PassportGrant,
+    ) {
+        let Some(ref record) = grant.record else {
+            return;
+        };
+        if let Err(e) = self
+            .verifier
+            .limit_circuit_passports(redis, circuit_id, &grant.token, record.ttl)
+            .await
+        {
+            tracing::warn!(error = %e, circuit_id = %circuit_id, "Failed to apply passport cap");
+        }
+    }
+
+    /// Gate lockout on a burst of wrong answers; errors are logged, never fatal
+    async fn record_wrong_answer(&self, redis: &mut ConnectionManager, circuit_id: &CircuitId) {
+        if let Err(e) = self.throttle.record_wrong(redis, circuit_id).await {
+            tracing::warn!(error = %e, circuit_id = %circuit_id, "Failed to count wrong answer");
+        }
+    }
+
+    /// Farm detection; errors are logged, never fatal
+    async fn record_solve_time(
+        &self,
+        redis: &mut ConnectionManager,
+        circuit_id: &CircuitId,
+        solve_ms: u64,
+        answer: &str,
+    ) {
+        if !self.analyzer.enabled() {
+            return;
+        }
+
+        let sample = SolveSample::new(solve_ms, answer);
+        match self.analyzer.record(redis, circuit_id, sample).await {
+            Ok(verdict) if verdict.penalize => {
+                let penalty = self.analyzer.reputation_penalty();
+                if let Err(e) = self
+                    .tracker
+                    .adjust_reputation(redis, circuit_id, -penalty, "farm_detection")
+                    .await
+                {
+                    tracing::warn!(error = %e, circuit_id = %circuit_id, "Failed to penalize circuit");
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                tracing::warn!(error = %e, circuit_id = %circuit_id, "Failed to record solve time");
+            }
+        }
+    }
+}
+
+/// Record verify latency (and solve time) for a completed check
+fn observe(check: ChallengeCheck, started: Instant) {
+    let outcome = match check {
+        ChallengeCheck::Correct { solve_time_ms } => {
+            if let Some(ms) = solve_time_ms {
+                metrics::CAPTCHA_SOLVE_SECONDS.observe(ms as f64 / 1000.0);
+            }
+            "correct"
+        }
+        ChallengeCheck::Incorrect => "incorrect",
+        ChallengeCheck::Expired => "expired",
+        ChallengeCheck::Missing => "missing",
+    };
+    metrics::CAPTCHA_VERIFY_SECONDS
+        .with_label_values(&[outcome])
+        .observe(started.elapsed().as_secs_f64());
+}
+
+/// Map a check outcome (and passport) to the API result
+fn build_result(check: ChallengeCheck, passport: Option<PassportGrant>) -> CaptchaResult {
+    let (remaining_challenges, error) = match check {
+        ChallengeCheck::Correct { solve_time_ms } => {
+            return CaptchaResult {
+                success: true,
+                remaining_challenges: 0,
+                passport_token: passport.map(|p| p.token),
+                error_message: None,
+                solve_time_ms,
+            };
+        }
+        // They need to try again
+        ChallengeCheck::Incorrect => (1, "Incorrect answer"),
+        ChallengeCheck::Expired => (0, "Challenge expired"),
+        ChallengeCheck::Missing => (0, "Challenge expired or invalid"),
+    };
+
+    CaptchaResult {
+        success: false,
+        remaining_challenges,
+        passport_token: None,
+        error_message: Some(error.to_string()),
+        solve_time_ms: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuits::EventLog;
+    use crate::config::VipConfig;
+    use cerberus_common::{CircuitStatus, PassportToken};
+
+    fn grant() -> PassportGrant {
+        PassportGrant {
+            token: "tok".parse().unwrap(),
+            expires_at: 1_000,
+            record: None,
+        }
+    }
+
+    #[test]
+    fn test_build_result_success_carries_passport() {
+        let result = build_result(
+            ChallengeCheck::Correct {
+                solve_time_ms: Some(4200),
+            },
+            Some(grant()),
+        );
+        assert!(result.success);
+        assert_eq!(
+            result.passport_token.as_ref().map(PassportToken::as_str),
+            Some("tok")
+        );
+        assert_eq!(result.solve_time_ms, Some(4200));
+    }
+
+    #[test]
+    fn test_build_result_failures() {
+        let result = build_result(ChallengeCheck::Incorrect, None);
+        assert!(!result.success);
+        assert_eq!(result.remaining_challenges, 1);
+        assert_eq!(result.error_message.as_deref(), Some("Incorrect answer"));
+
+        let result = build_result(ChallengeCheck::Missing, None);
+        assert_eq!(result.remaining_challenges, 0);
+        assert!(result.passport_token.is_none());
+    }
+
+    #[test]
+    fn test_circuit_transitions() {
+        let tracker = CircuitTracker::new(
+            3600,
+            2,
+            1800,
+            3600,
+            EventLog::new(10, 3600),
+            VipConfig::default(),
+        );
+        let mut info = CircuitInfo::new("c1".parse().unwrap());
+
+        let events = tracker.apply_failure(&mut info);
+        assert_eq!(info.failed_attempts, 1);
+        assert_eq!(events.len(), 1);
+        let events = tracker.apply_failure(&mut info);
+        assert_eq!(info.status, cerberus_common::CircuitStatus::SoftLocked);
+        assert_eq!(events.last().unwrap().kind, CircuitEventKind::SoftLocked);
+
+        let grant = grant();
+        let events = tracker.apply_success(&mut info, &grant.token, grant.expires_at, false);
+        assert_eq!(events[0].kind, CircuitEventKind::Solved);
+        assert_eq!(info.failed_attempts, 0);
+        assert_eq!(
+            info.passport_token.as_ref().map(PassportToken::as_str),
+            Some("tok")
+        );
+        assert_eq!(tracker.entry(&info).unwrap().key, "circuit:c1");
+    }
+
+    #[test]
+    fn test_vip_promotion_and_fast_pass() {
+        let vip = VipConfig {
+            skip_challenge_below: 4,
+            ..Default::default()
+        };
+        let tracker = CircuitTracker::new(3600, 2, 1800, 3600, EventLog::new(10, 3600), vip);
+        let mut info = CircuitInfo::new("c1".parse().unwrap());
+        let grant = grant();
+
+        tracker.apply_success(&mut info, &grant.token, grant.expires_at, false);
+        assert_eq!(info.status, CircuitStatus::Verified);
+        assert!(!tracker.skips_challenge(&info, ThreatLevel::new(1)));
+
+        let events = tracker.apply_success(&mut info, &grant.token, grant.expires_at, true);
+        assert_eq!(info.status, CircuitStatus::Vip);
+        assert_eq!(events[1].kind, CircuitEventKind::VipPromoted);
+
+        assert!(tracker.skips_challenge(&info, ThreatLevel::new(3)));
+        assert!(!tracker.skips_challenge(&info, ThreatLevel::new(4)));
+        let events = tracker.apply_fast_pass(
+            &mut info,
+            &"tok2".parse().unwrap(),
+            2_000,
+            CircuitEventKind::VipFastPass,
+        );
+        assert_eq!(events[0].kind, CircuitEventKind::VipFastPass);
+        assert_eq!(
+            info.passport_token.as_ref().map(PassportToken::as_str),
+            Some("tok2")
+        );
+        assert_eq!(info.successful_solves, 2);
+    }
+
+    #[test]
+    fn test_banned_circuit_keeps_its_ban() {
+        let tracker = CircuitTracker::new(
+            3600,
+            1,
+            1800,
+            3600,
+            EventLog::new(10, 3600),
+            VipConfig::default(),
+        );
+        let mut info = CircuitInfo::new("c1".parse().unwrap());
+        info.status = CircuitStatus::Banned;
+
+        // Neither a solve nor more failures change a ban
+        let grant = grant();
+        tracker.apply_success(&mut info, &grant.token, grant.expires_at, true);
+        assert_eq!(info.status, CircuitStatus::Banned);
+        let events = tracker.apply_failure(&mut info);
+        assert_eq!(info.status, CircuitStatus::Banned);
+        assert!(
+            events
+                .iter()
+                .all(|e| e.kind != CircuitEventKind::SoftLocked)
+        );
+    }
+
+    #[test]
+    fn test_vip_demoted_on_failure() {
+        let tracker = CircuitTracker::new(
+            3600,
+            3,
+            1800,
+            3600,
+            EventLog::new(10, 3600),
+            VipConfig::default(),
+        );
+        let mut info = CircuitInfo::new("c1".parse().unwrap());
+        tracker.apply_success(&mut info, &"tok".parse().unwrap(), 1_000, true);
+        assert_eq!(info.status, CircuitStatus::Vip);
+
+        let events = tracker.apply_failure(&mut info);
+        assert_eq!(info.status, CircuitStatus::Verified);
+        assert_eq!(events[1].kind, CircuitEventKind::VipDemoted);
+
+        // Demotion only applies to VIPs
+        assert!(
+            tracker
+                .demote(&mut info, CircuitStatus::SoftLocked, "honeypot")
+                .is_none()
+        );
+        assert_eq!(info.status, CircuitStatus::Verified);
+    }
+}
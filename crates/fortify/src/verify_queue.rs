@@ -0,0 +1,277 @@
+//! Fair admission to `/verify` under load.
+//!
+//! Verifications run `max_concurrent` at a time; the rest wait in a queue
+//! of at most `queue_capacity`, for up to `max_wait_ms`. Each circuit has
+//! a token bucket (`per_circuit_rate` answers a second, bursts of
+//! `per_circuit_burst`). A circuit within its rate may fill the whole
+//! queue; one over it is only queued while the queue is less than
+//! `excess_share` full. So when a handful of circuits flood the verify
+//! path, theirs are the answers shed first, and circuits answering at a
+//! human pace still get through.
+//!
+//! Requests without a circuit ID share one bucket. State is per node and
+//! in memory; the Redis-backed answer throttle (`circuits::VerifyThrottle`)
+//! still applies on top.
+
+use axum::{
+    extract::{Request, State},
+    http::{Method, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use cerberus_common::CircuitId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::metrics;
+use crate::routes::circuit_id_from_headers;
+use crate::state::AppState;
+
+/// Idle buckets are forgotten this often (they'd be full again anyway)
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Verify queue settings (`[verify_queue]` in fortify.toml)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VerifyQueueConfig {
+    pub enabled: bool,
+    /// Verifications handled at once
+    pub max_concurrent: usize,
+    /// Answers waiting for a slot, at most
+    pub queue_capacity: usize,
+    /// Longest an answer waits before being shed
+    pub max_wait_ms: u64,
+    /// Sustained answers per second a circuit may send
+    pub per_circuit_rate: f64,
+    /// Answers a circuit may send at once before its rate applies
+    pub per_circuit_burst: u32,
+    /// Fraction of the queue open to circuits over their rate (0.0-1.0)
+    pub excess_share: f64,
+    /// Retry-After sent with shed answers, in seconds
+    pub retry_after_secs: u64,
+}
+
+impl Default for VerifyQueueConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_concurrent: 64,
+            queue_capacity: 512,
+            max_wait_ms: 2000,
+            per_circuit_rate: 1.0,
+            per_circuit_burst: 5,
+            excess_share: 0.25,
+            retry_after_secs: 2,
+        }
+    }
+}
+
+/// Why an answer was turned away
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shed {
+    /// The circuit is over its rate and the queue is past `excess_share`
+    OverRate,
+    /// The queue is full
+    QueueFull,
+    /// No slot came free within `max_wait_ms`
+    Timeout,
+}
+
+impl Shed {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::OverRate => "over_rate",
+            Self::QueueFull => "queue_full",
+            Self::Timeout => "timeout",
+        }
+    }
+}
+
+/// Token bucket of one circuit
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+/// Per-circuit buckets feeding a bounded global queue
+pub struct VerifyQueue {
+    config: VerifyQueueConfig,
+    slots: Semaphore,
+    waiting: AtomicUsize,
+    buckets: Mutex<Buckets>,
+}
+
+struct Buckets {
+    by_circuit: HashMap<Option<CircuitId>, Bucket>,
+    swept: Instant,
+}
+
+impl VerifyQueue {
+    pub fn new(config: VerifyQueueConfig) -> Self {
+        Self {
+            slots: Semaphore::new(config.max_concurrent.max(1)),
+            waiting: AtomicUsize::new(0),
+            buckets: Mutex::new(Buckets {
+                by_circuit: HashMap::new(),
+                swept: Instant::now(),
+            }),
+            config,
+        }
+    }
+
+    /// Answers waiting for a slot
+    pub fn depth(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+
+    /// Wait for a verification slot, or be shed
+    pub async fn admit(&self, circuit_id: Option<&CircuitId>) -> Result<SemaphorePermit<'_>, Shed> {
+        let within_rate = self.take_token(circuit_id, Instant::now());
+        if let Ok(permit) = self.slots.try_acquire() {
+            return Ok(permit);
+        }
+
+        let depth = self.waiting.fetch_add(1, Ordering::Relaxed);
+        let _waiting = Waiting(&self.waiting);
+        let excess_limit = (self.config.queue_capacity as f64 * self.config.excess_share) as usize;
+        if !within_rate && depth >= excess_limit {
+            return Err(Shed::OverRate);
+        }
+        if depth >= self.config.queue_capacity {
+            return Err(Shed::QueueFull);
+        }
+        metrics::VERIFY_QUEUE_DEPTH.set(self.depth() as i64);
+
+        let started = Instant::now();
+        let wait = Duration::from_millis(self.config.max_wait_ms);
+        let permit = tokio::time::timeout(wait, self.slots.acquire())
+            .await
+            .map_err(|_| Shed::Timeout)?
+            .expect("verify slots are never closed");
+        metrics::VERIFY_QUEUE_WAIT_SECONDS.observe(started.elapsed().as_secs_f64());
+        Ok(permit)
+    }
+
+    /// Take a token from the circuit's bucket; false if it has none left
+    fn take_token(&self, circuit_id: Option<&CircuitId>, now: Instant) -> bool {
+        let burst = self.config.per_circuit_burst.max(1) as f64;
+        let rate = self.config.per_circuit_rate.max(0.0);
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+
+        if now.duration_since(buckets.swept) >= SWEEP_INTERVAL {
+            buckets.by_circuit.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.refilled).as_secs_f64() * rate < burst
+            });
+            buckets.swept = now;
+        }
+
+        let bucket = buckets
+            .by_circuit
+            .entry(circuit_id.cloned())
+            .or_insert(Bucket {
+                tokens: burst,
+                refilled: now,
+            });
+        let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+        bucket.refilled = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Counts an answer out of the queue however it leaves
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        let depth = self.0.fetch_sub(1, Ordering::Relaxed).saturating_sub(1);
+        metrics::VERIFY_QUEUE_DEPTH.set(depth as i64);
+    }
+}
+
+/// Middleware queueing `POST /verify` fairly, shedding with 503
+pub async fn guard(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if request.uri().path() != "/verify" || request.method() != Method::POST {
+        return next.run(request).await;
+    }
+    let circuit_id = circuit_id_from_headers(request.headers());
+    let _permit = match state.verify_queue.admit(circuit_id.as_ref()).await {
+        Ok(permit) => permit,
+        Err(shed) => {
+            metrics::VERIFY_QUEUE_SHED
+                .with_label_values(&[shed.as_str()])
+                .inc();
+            tracing::debug!(circuit_id = ?circuit_id, reason = shed.as_str(), "Shed verify request");
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(header::RETRY_AFTER, state.config.verify_queue.retry_after_secs)],
+                "Too many answers being checked, try again shortly",
+            )
+                .into_response();
+        }
+    };
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(max_concurrent: usize, queue_capacity: usize) -> VerifyQueue {
+        VerifyQueue::new(VerifyQueueConfig {
+            max_concurrent,
+            queue_capacity,
+            max_wait_ms: 50,
+            per_circuit_rate: 1.0,
+            per_circuit_burst: 2,
+            excess_share: 0.5,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_token_bucket_refills() {
+        let queue = queue(1, 4);
+        let circuit: CircuitId = "fc00::1".parse().unwrap();
+        let now = Instant::now();
+
+        assert!(queue.take_token(Some(&circuit), now));
+        assert!(queue.take_token(Some(&circuit), now));
+        assert!(!queue.take_token(Some(&circuit), now));
+        // Another circuit has its own bucket
+        assert!(queue.take_token(None, now));
+        assert!(queue.take_token(Some(&circuit), now + Duration::from_secs(1)));
+    }
+
+    #[tokio::test]
+    async fn test_aggressive_circuit_shed_first() {
+        let queue = queue(1, 4);
+        let flooder: CircuitId = "fc00::2".parse().unwrap();
+        let human: CircuitId = "fc00::3".parse().unwrap();
+
+        // The only slot is busy, and two answers are waiting
+        let _busy = queue.admit(None).await.unwrap();
+        queue.waiting.store(2, Ordering::Relaxed);
+
+        // The flooder spent its burst; the queue is past its excess share
+        assert!(queue.take_token(Some(&flooder), Instant::now()));
+        assert!(queue.take_token(Some(&flooder), Instant::now()));
+        assert_eq!(queue.admit(Some(&flooder)).await.unwrap_err(), Shed::OverRate);
+
+        // A circuit within its rate still queues (and times out here)
+        assert_eq!(queue.admit(Some(&human)).await.unwrap_err(), Shed::Timeout);
+
+        queue.waiting.store(4, Ordering::Relaxed);
+        assert_eq!(queue.admit(Some(&human)).await.unwrap_err(), Shed::QueueFull);
+    }
+}
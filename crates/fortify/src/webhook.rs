@@ -0,0 +1,352 @@
+//! Webhook notifications for critical events.
+//!
+//! Operators get alerted on their own infrastructure instead of polling:
+//! when the threat level reaches `min_threat_level`, the node is cut off
+//! from the cluster, the Ammo Box runs critically low, or circuits are
+//! banned en masse, a JSON alert is POSTed to every configured URL.
+//!
+//! With a `secret`, each request carries
+//! `X-Cerberus-Signature: sha256=<hex>`, an HMAC-SHA256 over
+//! `{X-Cerberus-Timestamp}.{body}`, so receivers can reject forged or
+//! replayed alerts. Onion endpoints are reached through `socks_proxy`.
+
+use anyhow::{Context, Result};
+use cerberus_common::outbound::{CircuitIsolation, OutboundClientBuilder};
+use cerberus_common::{CerberusEvent, EventSubscriber};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Signature header (`sha256=<hex>`)
+pub const SIGNATURE_HEADER: &str = "X-Cerberus-Signature";
+/// Unix timestamp the signature covers
+pub const TIMESTAMP_HEADER: &str = "X-Cerberus-Timestamp";
+
+/// Attempts per alert and URL
+const DELIVERY_ATTEMPTS: u32 = 3;
+/// First delay between attempts (doubles each retry)
+const DELIVERY_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Webhook settings (`[webhooks]` in fortify.toml)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhookConfig {
+    pub enabled: bool,
+    /// Where alerts are POSTed
+    pub urls: Vec<String>,
+    /// HMAC-SHA256 key for `X-Cerberus-Signature` (empty = unsigned)
+    pub secret: String,
+    /// SOCKS5 proxy for outbound requests, e.g. `socks5h://127.0.0.1:9050`
+    pub socks_proxy: Option<String>,
+    /// Which webhook requests may share a Tor circuit
+    pub isolation: CircuitIsolation,
+    pub timeout_secs: u64,
+    /// Alerts to send
+    pub alerts: Vec<AlertKind>,
+    /// Threat level at which `threat_level` alerts fire
+    pub min_threat_level: u8,
+    /// Bans within `mass_ban_window_secs` that make a `mass_ban` alert
+    pub mass_ban_count: usize,
+    pub mass_ban_window_secs: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            urls: Vec::new(),
+            secret: String::new(),
+            socks_proxy: None,
+            isolation: CircuitIsolation::Destination,
+            timeout_secs: 10,
+            alerts: vec![
+                AlertKind::ThreatLevel,
+                AlertKind::Isolation,
+                AlertKind::AmmoLow,
+                AlertKind::AmmoStarved,
+                AlertKind::MassBan,
+            ],
+            min_threat_level: 8,
+            mass_ban_count: 20,
+            mass_ban_window_secs: 60,
+        }
+    }
+}
+
+/// What an alert is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    /// Threat level rose to `min_threat_level` or above
+    ThreatLevel,
+    /// The node lost touch with the cluster
+    Isolation,
+    /// The pre-generated CAPTCHA pool is critically low
+    AmmoLow,
+    /// The CAPTCHA pool stayed low long enough to be an emergency
+    AmmoStarved,
+    /// `mass_ban_count` bans within `mass_ban_window_secs`
+    MassBan,
+}
+
+/// Body of a webhook request
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub alert: AlertKind,
+    pub node_id: String,
+    /// Unix timestamp (seconds)
+    pub at: i64,
+    /// One line for humans
+    pub message: String,
+    /// The event that raised the alert
+    pub event: CerberusEvent,
+}
+
+/// Turns node events into alerts and delivers them
+pub struct Webhooks {
+    config: WebhookConfig,
+    node_id: String,
+    client: reqwest::Client,
+    /// Recent ban times (Unix seconds), for `mass_ban`
+    bans: Mutex<VecDeque<i64>>,
+}
+
+impl Webhooks {
+    pub fn new(config: WebhookConfig, node_id: String) -> Result<Self> {
+        let mut client = OutboundClientBuilder::new()
+            .timeout(Duration::from_secs(config.timeout_secs))
+            .isolation(config.isolation);
+        if let Some(ref proxy) = config.socks_proxy {
+            client = client.proxy(proxy);
+        }
+        Ok(Self {
+            client: client.build().context("Failed to build webhook client")?,
+            config,
+            node_id,
+            bans: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    /// The alert `event` raises at `now`, if any
+    fn alert(&self, event: &CerberusEvent, now: i64) -> Option<Alert> {
+        let (kind, message) = match event {
+            CerberusEvent::ThreatLevelChanged { from, to }
+                if to.value() >= self.config.min_threat_level
+                    && from.value() < self.config.min_threat_level =>
+            {
+                (
+                    AlertKind::ThreatLevel,
+                    format!(
+                        "Threat level raised from {} to {}",
+                        from.value(),
+                        to.value()
+                    ),
+                )
+            }
+            CerberusEvent::NodeIsolated { unhealthy, peers } => (
+                AlertKind::Isolation,
+                format!(
+                    "Node isolated: {} of {} peers unreachable",
+                    unhealthy, peers
+                ),
+            ),
+            CerberusEvent::AmmoLow {
+                available,
+                capacity,
+            } => (
+                AlertKind::AmmoLow,
+                format!("CAPTCHA pool critically low: {} of {}", available, capacity),
+            ),
+            CerberusEvent::AmmoStarved {
+                available,
+                capacity,
+                starved_secs,
+            } => (
+                AlertKind::AmmoStarved,
+                format!(
+                    "CAPTCHA pool starving for {}s: {} of {}",
+                    starved_secs, available, capacity
+                ),
+            ),
+            CerberusEvent::CircuitBanned { .. } => {
+                let count = self.count_ban(now)?;
+                (
+                    AlertKind::MassBan,
+                    format!(
+                        "{} circuits banned in the last {}s",
+                        count, self.config.mass_ban_window_secs
+                    ),
+                )
+            }
+            _ => return None,
+        };
+
+        self.config.alerts.contains(&kind).then(|| Alert {
+            alert: kind,
+            node_id: self.node_id.clone(),
+            at: now,
+            message,
+            event: event.clone(),
+        })
+    }
+
+    /// Record a ban; returns the count once it reaches `mass_ban_count`
+    ///
+    /// The window starts over after an alert, so a ban wave alerts once per
+    /// `mass_ban_count` bans rather than on every ban that follows.
+    fn count_ban(&self, now: i64) -> Option<usize> {
+        let mut bans = self.bans.lock().unwrap_or_else(|e| e.into_inner());
+        let since = now - self.config.mass_ban_window_secs as i64;
+        while bans.front().is_some_and(|&at| at <= since) {
+            bans.pop_front();
+        }
+        bans.push_back(now);
+        if bans.len() < self.config.mass_ban_count.max(1) {
+            return None;
+        }
+        let count = bans.len();
+        bans.clear();
+        Some(count)
+    }
+
+    /// POST `alert` to every URL, retrying failures
+    async fn deliver(client: reqwest::Client, config: WebhookConfig, alert: Alert) {
+        let body = match serde_json::to_vec(&alert) {
+            Ok(body) => body,
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to serialize webhook alert");
+                return;
+            }
+        };
+        let timestamp = alert.at.to_string();
+        let signature =
+            (!config.secret.is_empty()).then(|| sign(config.secret.as_bytes(), &timestamp, &body));
+
+        for url in &config.urls {
+            let mut delay = DELIVERY_RETRY_DELAY;
+            for attempt in 1..=DELIVERY_ATTEMPTS {
+                let mut request = client
+                    .post(url)
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .header(TIMESTAMP_HEADER, &timestamp)
+                    .body(body.clone());
+                if let Some(ref signature) = signature {
+                    request = request.header(SIGNATURE_HEADER, signature);
+                }
+                let result = match request.send().await {
+                    Ok(response) => response.error_for_status().map(|_| ()),
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(()) => break,
+                    Err(e) if attempt < DELIVERY_ATTEMPTS => {
+                        tracing::debug!(error = %e, attempt, "Retrying webhook delivery");
+                        tokio::time::sleep(delay).await;
+                        delay *= 2;
+                    }
+                    Err(e) => tracing::warn!(
+                        error = %e,
+                        alert = ?alert.alert,
+                        "Webhook delivery failed"
+                    ),
+                }
+            }
+        }
+    }
+}
+
+impl EventSubscriber for Webhooks {
+    fn on_event(&self, event: &CerberusEvent) {
+        let Some(alert) = self.alert(event, chrono::Utc::now().timestamp()) else {
+            return;
+        };
+        tracing::info!(alert = ?alert.alert, message = %alert.message, "Sending webhook alert");
+        tokio::spawn(Self::deliver(
+            self.client.clone(),
+            self.config.clone(),
+            alert,
+        ));
+    }
+}
+
+/// `sha256=<hex>` HMAC over `{timestamp}.{body}`
+pub fn sign(secret: &[u8], timestamp: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC takes any key length");
+    mac.update(timestamp.as_bytes());
+    mac.update(b".");
+    mac.update(body);
+    let hex: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("sha256={}", hex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cerberus_common::ThreatLevel;
+
+    fn webhooks(config: WebhookConfig) -> Webhooks {
+        Webhooks::new(config, "node-1".to_string()).unwrap()
+    }
+
+    #[test]
+    fn test_alerts_for_critical_events() {
+        let hooks = webhooks(WebhookConfig {
+            mass_ban_count: 3,
+            ..Default::default()
+        });
+        let raised = |from, to| CerberusEvent::ThreatLevelChanged {
+            from: ThreatLevel::new(from),
+            to: ThreatLevel::new(to),
+        };
+
+        let alert = hooks.alert(&raised(5, 9), 0).unwrap();
+        assert_eq!(alert.alert, AlertKind::ThreatLevel);
+        assert_eq!(alert.node_id, "node-1");
+        // Already above, or not high enough
+        assert!(hooks.alert(&raised(8, 9), 0).is_none());
+        assert!(hooks.alert(&raised(3, 7), 0).is_none());
+
+        let ban = CerberusEvent::CircuitBanned {
+            circuit_id: "fc00::1".parse().unwrap(),
+            reason: None,
+        };
+        assert!(hooks.alert(&ban, 0).is_none());
+        assert!(hooks.alert(&ban, 1).is_none());
+        assert_eq!(hooks.alert(&ban, 2).unwrap().alert, AlertKind::MassBan);
+        // Window starts over
+        assert!(hooks.alert(&ban, 3).is_none());
+
+        let starved = CerberusEvent::AmmoStarved {
+            available: 40,
+            capacity: 10_000,
+            starved_secs: 30,
+        };
+        assert_eq!(
+            hooks.alert(&starved, 0).unwrap().alert,
+            AlertKind::AmmoStarved
+        );
+
+        let quiet = webhooks(WebhookConfig {
+            alerts: vec![AlertKind::Isolation],
+            ..Default::default()
+        });
+        assert!(quiet.alert(&raised(5, 9), 0).is_none());
+    }
+
+    #[test]
+    fn test_signature() {
+        // echo -n '1700000000.{}' | openssl dgst -sha256 -hmac secret
+        assert_eq!(
+            sign(b"secret", "1700000000", b"{}"),
+            "sha256=b8569b78799ff9e3cbff0fc2d63a33a2b57f3282abd07c37ae5e8e7d79a5f163"
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! Secret key encryption at rest (`--encrypt` and `decrypt`).
+//!
+//! With `--encrypt`, `hs_ed25519_secret_key` is not written; instead
+//! `hs_ed25519_secret_key.enc` holds the same Tor key file sealed with
+//! AES-256-GCM, under a key derived from a passphrase with Argon2id. The key
+//! never touches the generating machine's disk in the clear, and
+//! `vanity-onion decrypt` turns it back into the file Tor loads on the
+//! machine that runs the service.
+//!
+//! ## File layout
+//! ```text
+//! magic "VOENC" || version (1) || m_cost (4) || t_cost (4) || p_cost (4)
+//!     || salt (16) || nonce (12) || ciphertext || tag (16)
+//! ```
+//! Integers are little-endian. Everything before the ciphertext is
+//! authenticated along with it, so the KDF parameters can't be swapped.
+
+use std::path::Path;
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use zeroize::Zeroizing;
+
+/// Name of the encrypted secret key file
+pub const ENCRYPTED_SECRET_FILE: &str = "hs_ed25519_secret_key.enc";
+
+const MAGIC: &[u8; 5] = b"VOENC";
+const FORMAT_VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+/// magic + version + three u32 parameters + salt + nonce
+const HEADER_LEN: usize = MAGIC.len() + 1 + 12 + SALT_LEN + NONCE_LEN;
+
+/// Argon2id cost parameters, stored in the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    /// Memory in KiB
+    pub m_cost: u32,
+    /// Passes
+    pub t_cost: u32,
+    /// Lanes
+    pub p_cost: u32,
+}
+
+impl Default for KdfParams {
+    /// 64 MiB, 3 passes (RFC 9106's second recommended option)
+    fn default() -> Self {
+        Self {
+            m_cost: 64 * 1024,
+            t_cost: 3,
+            p_cost: 1,
+        }
+    }
+}
+
+/// Seal `plaintext` under `passphrase`
+pub fn seal(plaintext: &[u8], passphrase: &str, params: KdfParams) -> Result<Vec<u8>, String> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut nonce);
+
+    let mut sealed = Vec::with_capacity(HEADER_LEN + plaintext.len() + TAG_LEN);
+    sealed.extend_from_slice(MAGIC);
+    sealed.push(FORMAT_VERSION);
+    sealed.extend_from_slice(&params.m_cost.to_le_bytes());
+    sealed.extend_from_slice(&params.t_cost.to_le_bytes());
+    sealed.extend_from_slice(&params.p_cost.to_le_bytes());
+    sealed.extend_from_slice(&salt);
+    sealed.extend_from_slice(&nonce);
+
+    let key = derive_key(passphrase, &salt, params)?;
+    let cipher = Aes256Gcm::new_from_slice(key.as_slice()).map_err(|e| e.to_string())?;
+    let ciphertext = cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad: &sealed,
+            },
+        )
+        .map_err(|_| "Encryption failed".to_string())?;
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+/// Open a file written by `seal`
+pub fn open(sealed: &[u8], passphrase: &str) -> Result<Zeroizing<Vec<u8>>, String> {
+    if sealed.len() < HEADER_LEN + TAG_LEN || &sealed[..MAGIC.len()] != MAGIC {
+        return Err("Not an encrypted vanity-onion key file".to_string());
+    }
+    let version = sealed[MAGIC.len()];
+    if version != FORMAT_VERSION {
+        return Err(format!("Unsupported key file version {}", version));
+    }
+
+    let (header, ciphertext) = sealed.split_at(HEADER_LEN);
+    let u32_at = |offset: usize| {
+        let mut bytes = [0u8; 4];
+        bytes.copy_from_slice(&header[offset..offset + 4]);
+        u32::from_le_bytes(bytes)
+    };
+    let params_at = MAGIC.len() + 1;
+    let params = KdfParams {
+        m_cost: u32_at(params_at),
+        t_cost: u32_at(params_at + 4),
+        p_cost: u32_at(params_at + 8),
+    };
+    let salt = &header[params_at + 12..params_at + 12 + SALT_LEN];
+    let nonce = &header[HEADER_LEN - NONCE_LEN..];
+
+    let key = derive_key(passphrase, salt, params)?;
+    let cipher = Aes256Gcm::new_from_slice(key.as_slice()).map_err(|e| e.to_string())?;
+    cipher
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: header,
+            },
+        )
+        .map(Zeroizing::new)
+        .map_err(|_| "Wrong passphrase or corrupted key file".to_string())
+}
+
+fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    params: KdfParams,
+) -> Result<Zeroizing<[u8; 32]>, String> {
+    let params = Params::new(params.m_cost, params.t_cost, params.p_cost, Some(32))
+        .map_err(|e| format!("Invalid Argon2 parameters: {}", e))?;
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut_slice())
+        .map_err(|e| format!("Key derivation failed: {}", e))?;
+    Ok(key)
+}
+
+/// Read the passphrase from `file` (first line), or prompt for it
+///
+/// When prompting for a new passphrase (`confirm`), it is asked for twice.
+pub fn read_passphrase(file: Option<&Path>, confirm: bool) -> Result<Zeroizing<String>, String> {
+    let passphrase = match file {
+        Some(path) => {
+            let contents = Zeroizing::new(
+                std::fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?,
+            );
+            Zeroizing::new(contents.lines().next().unwrap_or_default().to_string())
+        }
+        None => {
+            let passphrase = Zeroizing::new(
+                rpassword::prompt_password("🔒 Passphrase: ").map_err(|e| e.to_string())?,
+            );
+            if confirm {
+                let again = Zeroizing::new(
+                    rpassword::prompt_password("🔒 Confirm passphrase: ")
+                        .map_err(|e| e.to_string())?,
+                );
+                if *again != *passphrase {
+                    return Err("Passphrases do not match".to_string());
+                }
+            }
+            passphrase
+        }
+    };
+
+    if passphrase.is_empty() {
+        return Err("Passphrase is empty".to_string());
+    }
+    Ok(passphrase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cheap parameters; the defaults take a while in debug builds
+    const TEST_PARAMS: KdfParams = KdfParams {
+        m_cost: 64,
+        t_cost: 1,
+        p_cost: 1,
+    };
+
+    #[test]
+    fn test_seal_and_open() {
+        let secret = b"== ed25519v1-secret: type0 ==\x00\x00\x00 key bytes";
+        let sealed = seal(secret, "correct horse", TEST_PARAMS).unwrap();
+        assert_eq!(sealed.len(), HEADER_LEN + secret.len() + TAG_LEN);
+        assert!(!sealed.windows(9).any(|w| w == b"key bytes"));
+
+        assert_eq!(open(&sealed, "correct horse").unwrap().as_slice(), secret);
+        assert!(open(&sealed, "wrong horse").is_err());
+    }
+
+    #[test]
+    fn test_header_is_authenticated() {
+        let sealed = seal(b"secret", "passphrase", TEST_PARAMS).unwrap();
+
+        // Raising the cost parameters must not go unnoticed
+        let mut tampered = sealed.clone();
+        tampered[MAGIC.len() + 5] ^= 2;
+        assert!(open(&tampered, "passphrase").is_err());
+
+        let mut wrong_version = sealed;
+        wrong_version[MAGIC.len()] = 2;
+        assert!(open(&wrong_version, "passphrase")
+            .unwrap_err()
+            .contains("version"));
+        assert!(open(b"plaintext", "passphrase").is_err());
+    }
+}
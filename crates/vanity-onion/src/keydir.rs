@@ -0,0 +1,287 @@
+//! Onion service key directories (`verify` and `inspect`).
+//!
+//! Tor refuses a key directory with little more than "bad key", so these
+//! checks take one apart: each file's size and header, the secret scalar's
+//! clamping, the public key derived from the secret key, and the hostname's
+//! checksum and version bytes against the public key.
+
+use std::path::Path;
+
+use ed25519_dalek::hazmat::ExpandedSecretKey;
+use ed25519_dalek::VerifyingKey;
+use sha2::{Digest, Sha256};
+
+use crate::encrypt::ENCRYPTED_SECRET_FILE;
+use crate::{onion_checksum, ONION_V3_VERSION};
+
+pub const SECRET_KEY_FILE: &str = "hs_ed25519_secret_key";
+pub const PUBLIC_KEY_FILE: &str = "hs_ed25519_public_key";
+pub const HOSTNAME_FILE: &str = "hostname";
+
+/// Header of `hs_ed25519_secret_key`, followed by the 64-byte expanded key
+pub const SECRET_KEY_HEADER: &[u8; 32] = b"== ed25519v1-secret: type0 ==\x00\x00\x00";
+/// Header of `hs_ed25519_public_key`, followed by the 32-byte public key
+pub const PUBLIC_KEY_HEADER: &[u8; 32] = b"== ed25519v1-public: type0 ==\x00\x00\x00";
+
+/// Outcome of one check: what was found, or what is wrong
+pub struct Finding {
+    pub check: &'static str,
+    pub result: Result<String, String>,
+}
+
+impl Finding {
+    fn new(check: &'static str, result: Result<String, String>) -> Self {
+        Self { check, result }
+    }
+}
+
+/// What a key directory holds, as far as it could be read
+#[derive(Default)]
+pub struct KeyDir {
+    pub public_key: Option<VerifyingKey>,
+    /// Public key derived from the secret key
+    pub derived_key: Option<VerifyingKey>,
+    /// Public key encoded in the hostname
+    pub hostname_key: Option<[u8; 32]>,
+    pub findings: Vec<Finding>,
+}
+
+impl KeyDir {
+    /// Load and check every key file in `dir`
+    pub fn load(dir: &Path) -> Self {
+        let mut keydir = KeyDir::default();
+
+        match read(dir, PUBLIC_KEY_FILE) {
+            Some(data) => {
+                let result = parse_public_key(&data).map(|key| {
+                    keydir.public_key = Some(key);
+                    format!("public key {}", hex(key.as_bytes()))
+                });
+                keydir.findings.push(Finding::new(PUBLIC_KEY_FILE, result));
+            }
+            None => keydir
+                .findings
+                .push(Finding::new(PUBLIC_KEY_FILE, Err("missing".to_string()))),
+        }
+
+        match read(dir, SECRET_KEY_FILE) {
+            Some(data) => {
+                let result = parse_secret_key(&data).map(|key| {
+                    keydir.derived_key = Some(key);
+                    format!("derives public key {}", hex(key.as_bytes()))
+                });
+                keydir.findings.push(Finding::new(SECRET_KEY_FILE, result));
+            }
+            None if dir.join(ENCRYPTED_SECRET_FILE).exists() => keydir.findings.push(Finding::new(
+                SECRET_KEY_FILE,
+                Ok(format!(
+                    "encrypted ({}), not checked; run `vanity-onion decrypt` first",
+                    ENCRYPTED_SECRET_FILE
+                )),
+            )),
+            None => keydir
+                .findings
+                .push(Finding::new(SECRET_KEY_FILE, Err("missing".to_string()))),
+        }
+
+        match read(dir, HOSTNAME_FILE) {
+            Some(data) => {
+                let hostname = String::from_utf8_lossy(&data);
+                let result = parse_hostname(&hostname).map(|key| {
+                    keydir.hostname_key = Some(key);
+                    format!("{} (checksum and version ok)", hostname.trim())
+                });
+                keydir.findings.push(Finding::new(HOSTNAME_FILE, result));
+            }
+            None => keydir
+                .findings
+                .push(Finding::new(HOSTNAME_FILE, Err("missing".to_string()))),
+        }
+
+        if let (Some(public), Some(derived)) = (keydir.public_key, keydir.derived_key) {
+            keydir.findings.push(Finding::new(
+                "secret key matches public key",
+                if public == derived {
+                    Ok("yes".to_string())
+                } else {
+                    Err("the secret key belongs to a different public key".to_string())
+                },
+            ));
+        }
+        if let (Some(public), Some(hostname)) = (keydir.public_key, keydir.hostname_key) {
+            keydir.findings.push(Finding::new(
+                "hostname matches public key",
+                if public.as_bytes() == &hostname {
+                    Ok("yes".to_string())
+                } else {
+                    Err("the hostname is for a different key".to_string())
+                },
+            ));
+        }
+
+        keydir
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.findings.iter().all(|f| f.result.is_ok())
+    }
+
+    /// The address, from the public key or failing that the secret key
+    pub fn onion_address(&self) -> Option<String> {
+        self.public_key
+            .or(self.derived_key)
+            .map(|key| crate::compute_onion_address(&key))
+    }
+}
+
+fn read(dir: &Path, name: &str) -> Option<Vec<u8>> {
+    std::fs::read(dir.join(name)).ok()
+}
+
+/// Check a key file's size and header, returning the key bytes
+fn key_bytes<'a>(data: &'a [u8], header: &[u8; 32], key_len: usize) -> Result<&'a [u8], String> {
+    if !data.starts_with(header) {
+        let found = &data[..data.len().min(29)];
+        return Err(format!(
+            "header is {:?}, expected {:?}",
+            String::from_utf8_lossy(found),
+            String::from_utf8_lossy(&header[..29])
+        ));
+    }
+    if data.len() != header.len() + key_len {
+        return Err(format!(
+            "{} bytes, expected {}",
+            data.len(),
+            header.len() + key_len
+        ));
+    }
+    Ok(&data[header.len()..])
+}
+
+/// Parse `hs_ed25519_public_key`
+pub fn parse_public_key(data: &[u8]) -> Result<VerifyingKey, String> {
+    let bytes = key_bytes(data, PUBLIC_KEY_HEADER, 32)?;
+    let mut key = [0u8; 32];
+    key.copy_from_slice(bytes);
+    VerifyingKey::from_bytes(&key).map_err(|_| "not a valid Ed25519 point".to_string())
+}
+
+/// Parse `hs_ed25519_secret_key`, returning the public key it derives
+pub fn parse_secret_key(data: &[u8]) -> Result<VerifyingKey, String> {
+    let bytes = key_bytes(data, SECRET_KEY_HEADER, 64)?;
+    let mut expanded = zeroize::Zeroizing::new([0u8; 64]);
+    expanded.copy_from_slice(bytes);
+
+    // Tor stores the scalar clamped; an unclamped one usually means the
+    // 32-byte seed was written where the expanded key belongs
+    if expanded[0] & 7 != 0 || expanded[31] & 0x80 != 0 || expanded[31] & 0x40 == 0 {
+        return Err(
+            "secret scalar is not clamped (seed saved instead of expanded key?)".to_string(),
+        );
+    }
+    Ok(VerifyingKey::from(&ExpandedSecretKey::from_bytes(
+        &expanded,
+    )))
+}
+
+/// Parse a `hostname` file, checking its checksum and version bytes
+///
+/// Returns the public key the address encodes.
+pub fn parse_hostname(hostname: &str) -> Result<[u8; 32], String> {
+    let hostname = hostname.trim();
+    let label = hostname
+        .strip_suffix(".onion")
+        .ok_or_else(|| format!("'{}' does not end in .onion", hostname))?;
+    if label.len() != 56 {
+        return Err(format!(
+            "address is {} characters, expected 56",
+            label.len()
+        ));
+    }
+    let bytes = base32::decode(base32::Alphabet::Rfc4648Lower { padding: false }, label)
+        .filter(|bytes| bytes.len() == 35)
+        .ok_or_else(|| "address is not valid base32".to_string())?;
+
+    let mut public_key = [0u8; 32];
+    public_key.copy_from_slice(&bytes[..32]);
+    if bytes[34] != ONION_V3_VERSION {
+        return Err(format!(
+            "version byte is {:#04x}, expected {:#04x}",
+            bytes[34], ONION_V3_VERSION
+        ));
+    }
+    if bytes[32..34] != onion_checksum(&public_key) {
+        return Err("checksum does not match the address's public key".to_string());
+    }
+    Ok(public_key)
+}
+
+/// SHA-256 of a public key, for comparing keys at a glance
+pub fn fingerprint(key: &VerifyingKey) -> String {
+    hex(&Sha256::digest(key.as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    crate::hex_encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+    use rand::rngs::OsRng;
+
+    fn key_dir(name: &str) -> (std::path::PathBuf, String) {
+        let dir =
+            std::env::temp_dir().join(format!("vanity-onion-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let signing_key = SigningKey::generate(&mut OsRng);
+        let onion = crate::compute_onion_address(&signing_key.verifying_key());
+        crate::save_keys(&dir, &signing_key, &onion, None).unwrap();
+        (dir, onion)
+    }
+
+    #[test]
+    fn test_saved_keys_verify() {
+        let (dir, onion) = key_dir("verify");
+        let keydir = KeyDir::load(&dir);
+        assert!(keydir.is_ok());
+        assert_eq!(keydir.findings.len(), 5);
+        assert_eq!(keydir.onion_address(), Some(onion));
+
+        // A hostname from another key is caught
+        let (other, other_onion) = key_dir("verify-other");
+        std::fs::copy(other.join(HOSTNAME_FILE), dir.join(HOSTNAME_FILE)).unwrap();
+        let keydir = KeyDir::load(&dir);
+        assert!(!keydir.is_ok());
+        assert_ne!(keydir.onion_address(), Some(other_onion));
+
+        std::fs::remove_dir_all(dir).unwrap();
+        std::fs::remove_dir_all(other).unwrap();
+    }
+
+    #[test]
+    fn test_format_errors() {
+        let signing_key = SigningKey::generate(&mut OsRng);
+        let onion = crate::compute_onion_address(&signing_key.verifying_key());
+        assert!(parse_hostname(&format!("{}.onion\n", onion)).is_ok());
+
+        // Flip a character in the checksum, then the version
+        let mut bad = onion.clone().into_bytes();
+        bad[53] = if bad[53] == b'a' { b'b' } else { b'a' };
+        let bad = String::from_utf8(bad).unwrap();
+        assert!(parse_hostname(&format!("{}.onion", bad)).is_err());
+        assert!(parse_hostname(&onion).unwrap_err().contains(".onion"));
+
+        // The 32-byte seed where the expanded key belongs
+        let mut seed_file = SECRET_KEY_HEADER.to_vec();
+        seed_file.extend_from_slice(&signing_key.to_bytes());
+        assert!(parse_secret_key(&seed_file).unwrap_err().contains("bytes"));
+        let mut unclamped = SECRET_KEY_HEADER.to_vec();
+        unclamped.extend_from_slice(&[0xff; 64]);
+        assert!(parse_secret_key(&unclamped)
+            .unwrap_err()
+            .contains("clamped"));
+        assert!(parse_public_key(&seed_file).unwrap_err().contains("header"));
+    }
+}
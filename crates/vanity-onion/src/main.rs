@@ -19,14 +19,33 @@
 //!
 //! # Use all CPU cores and save to file
 //! vanity-onion --prefix sigil --threads 0 --output keys/
+//!
+//! # Accept "e" or "3" in the second place, anything in the fourth
+//! vanity-onion --prefix 's[e3]c?re'
+//!
+//! # Too long to find? Get feasible alternatives for this machine
+//! vanity-onion --prefix cerberusproject --suggest
+//!
+//! # Keep the secret key encrypted, and decrypt it where Tor runs
+//! vanity-onion --prefix sigil --output keys/ --encrypt
+//! vanity-onion decrypt keys/
+//!
+//! # Check a key directory Tor won't load
+//! vanity-onion verify /var/lib/tor/hidden_service
+//! vanity-onion inspect /var/lib/tor/hidden_service
 //! ```
 
-use std::path::PathBuf;
+mod encrypt;
+mod keydir;
+mod pattern;
+mod suggest;
+
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use ed25519_dalek::{SigningKey, VerifyingKey};
 use indicatif::{ProgressBar, ProgressStyle};
 use rand::rngs::OsRng;
@@ -34,14 +53,21 @@ use rayon::prelude::*;
 use sha2::{Digest as Sha2Digest, Sha512};
 use sha3::{Digest, Sha3_256};
 
+use pattern::Pattern;
+
 /// Cerberus Vanity Onion Address Generator
 #[derive(Parser, Debug)]
 #[command(name = "vanity-onion")]
 #[command(author, version, about = "Generate branded .onion addresses", long_about = None)]
+#[command(subcommand_negates_reqs = true)]
 struct Args {
-    /// Prefix to search for (case-insensitive, base32 chars only: a-z, 2-7)
-    #[arg(short, long)]
-    prefix: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Prefix to search for (case-insensitive, base32 chars only: a-z, 2-7;
+    /// `?` matches any character, `[e3]` any of those listed)
+    #[arg(short, long, required = true)]
+    prefix: Option<String>,
 
     /// Number of threads (0 = auto-detect)
     #[arg(short, long, default_value = "0")]
@@ -70,6 +96,55 @@ struct Args {
     /// Test mode: if prefix too long, auto-shorten for faster testing
     #[arg(long)]
     test_mode: bool,
+
+    /// Suggest alternatives to the prefix that can be found in time
+    #[arg(long)]
+    suggest: bool,
+
+    /// Time budget for --suggest in seconds
+    #[arg(long, default_value = "86400")]
+    suggest_within: u64,
+
+    /// Save the secret key encrypted with a passphrase
+    #[arg(long, requires = "output")]
+    encrypt: bool,
+
+    /// Read the passphrase from this file instead of prompting
+    #[arg(long)]
+    passphrase_file: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Decrypt a key saved with --encrypt into Tor's hs_ed25519_secret_key
+    Decrypt {
+        /// Key directory, or the hs_ed25519_secret_key.enc file itself
+        input: PathBuf,
+
+        /// Directory to write hs_ed25519_secret_key to (default: next to the input)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// Read the passphrase from this file instead of prompting
+        #[arg(long)]
+        passphrase_file: Option<PathBuf>,
+
+        /// Overwrite an existing hs_ed25519_secret_key
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Check that a key directory's secret key, public key and hostname agree
+    Verify {
+        /// Onion service key directory
+        dir: PathBuf,
+    },
+
+    /// Print a key directory's address, fingerprints and file formats
+    Inspect {
+        /// Onion service key directory
+        dir: PathBuf,
+    },
 }
 
 /// Tor v3 onion address version byte
@@ -81,24 +156,77 @@ const CHECKSUM_PREFIX: &[u8] = b".onion checksum";
 fn main() {
     let args = Args::parse();
 
-    // Validate prefix (base32 only: a-z, 2-7)
-    let mut prefix = args.prefix.to_lowercase();
-    if !prefix.chars().all(|c| c.is_ascii_lowercase() || ('2'..='7').contains(&c)) {
-        eprintln!("Error: Prefix must contain only base32 characters (a-z, 2-7)");
-        eprintln!("       Invalid characters will never match");
-        std::process::exit(1);
+    match &args.command {
+        Some(Command::Decrypt {
+            input,
+            output,
+            passphrase_file,
+            force,
+        }) => {
+            match decrypt_keys(input, output.as_deref(), passphrase_file.as_deref(), *force) {
+                Ok(secret_file) => {
+                    println!("🔓 Secret key written to: {}", secret_file.display());
+                    println!("   Keep hs_ed25519_public_key and hostname next to it,");
+                    println!("   and the directory readable by Tor only (chmod 700).");
+                }
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+        Some(Command::Verify { dir }) => verify_keys(dir),
+        Some(Command::Inspect { dir }) => inspect_keys(dir),
+        None => {}
     }
+    let requested = args.prefix.clone().unwrap_or_default();
+
+    // Set thread count
+    let threads = if args.threads == 0 {
+        num_cpus()
+    } else {
+        args.threads
+    };
+
+    if args.suggest {
+        print_suggestions(&requested, args.suggest_within, threads);
+        return;
+    }
+
+    // Validate prefix (base32 only: a-z, 2-7, plus `?` and `[...]`)
+    let mut prefix = match Pattern::parse(&requested) {
+        Ok(prefix) => prefix,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            eprintln!("       Invalid characters will never match");
+            std::process::exit(1);
+        }
+    };
 
     // Test mode: shorten prefix if too long for fast testing
     if args.test_mode && prefix.len() > 3 {
-        let original = prefix.clone();
-        prefix = prefix[..3].to_string();
+        let original = prefix.to_string();
+        prefix = prefix.truncated(3);
         println!("⚡ TEST MODE: Shortened prefix '{}' → '{}' for faster generation", original, prefix);
         println!();
     }
 
-    // Calculate difficulty
-    let difficulty = 32u64.pow(prefix.len() as u32);
+    // Ask for the passphrase now rather than after a long search
+    let passphrase = if args.encrypt {
+        match encrypt::read_passphrase(args.passphrase_file.as_deref(), true) {
+            Ok(passphrase) => Some(passphrase),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    // Calculate difficulty (saturates for absurdly long prefixes)
+    let difficulty = prefix.expected_attempts() as u64;
     let expected_attempts = difficulty; // ~50% chance after this many
 
     println!("🔍 Vanity Onion Generator");
@@ -123,12 +251,6 @@ fn main() {
         return;
     }
 
-    // Set thread count
-    let threads = if args.threads == 0 {
-        num_cpus()
-    } else {
-        args.threads
-    };
     println!("Threads: {}", threads);
     println!();
 
@@ -220,7 +342,7 @@ fn main() {
             let signing_key = SigningKey::generate(&mut OsRng);
             let onion = compute_onion_address(&signing_key.verifying_key());
 
-            if onion.starts_with(&prefix) {
+            if prefix.matches(&onion) {
                 found.store(true, Ordering::Relaxed);
                 Some((signing_key, onion))
             } else {
@@ -250,12 +372,16 @@ fn main() {
 
             // Save keys if output specified
             if let Some(output_dir) = args.output {
-                if let Err(e) = save_keys(&output_dir, &secret_key, &onion_address) {
+                let passphrase = passphrase.as_ref().map(|p| p.as_str());
+                if let Err(e) = save_keys(&output_dir, &secret_key, &onion_address, passphrase) {
                     eprintln!("Error saving keys: {}", e);
                     std::process::exit(1);
                 }
                 println!();
                 println!("📁 Keys saved to: {}/", output_dir.display());
+                if passphrase.is_some() {
+                    println!("🔒 Secret key encrypted; run `vanity-onion decrypt` where Tor runs");
+                }
             } else {
                 println!();
                 println!("⚠️  Keys not saved! Use --output <dir> to save keys.");
@@ -276,6 +402,7 @@ fn main() {
                 println!("💡 Tips:");
                 println!("   - Use a shorter prefix (3-4 chars) for faster results");
                 println!("   - Use --test-mode to auto-shorten long prefixes");
+                println!("   - Use --suggest to find alternatives that fit your hardware");
                 println!("   - Increase --timeout or --max-attempts");
                 println!();
                 std::process::exit(2); // Exit code 2 = hit limit
@@ -287,33 +414,90 @@ fn main() {
     }
 }
 
+/// Benchmark, then print alternatives to `word` that fit in `within_secs`
+fn print_suggestions(requested: &str, within_secs: u64, threads: usize) {
+    let word = match suggest::normalize(requested) {
+        Ok(word) => word,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    println!("💡 Vanity Prefix Suggestions");
+    println!("===========================");
+    println!("Prefix: {}", word);
+    if word != requested.to_lowercase() {
+        println!("        (from '{}': base32 has no 0, 1, 8 or 9)", requested);
+    }
+
+    let rate = benchmark_rate().saturating_mul(threads as u64).max(1);
+    let eta = |pattern: &Pattern| format_duration((pattern.expected_attempts() / rate as f64) as u64);
+    println!("Estimated rate: ~{}/sec ({} threads)", format_number(rate), threads);
+    println!("Budget: {}", format_duration(within_secs));
+
+    let Ok(exact) = Pattern::parse(&word) else {
+        return;
+    };
+    println!("Estimated time as requested: {}", eta(&exact));
+    println!();
+
+    let suggestions = suggest::suggest(&word, rate as f64 * within_secs as f64);
+    if suggestions.is_empty() {
+        if exact.expected_attempts() <= rate as f64 * within_secs as f64 {
+            println!("✅ The prefix fits the budget as it is.");
+        } else {
+            println!("❌ Nothing close fits the budget; raise --suggest-within.");
+        }
+        return;
+    }
+
+    println!("Alternatives:");
+    for suggestion in &suggestions {
+        println!(
+            "   {:<10} {:<28} ~{}",
+            suggestion.kind,
+            suggestion.pattern.to_string(),
+            eta(&suggestion.pattern)
+        );
+    }
+    println!();
+    println!("Run one with: vanity-onion --prefix '<pattern>'");
+}
+
 /// Compute the full onion address from a public key
 fn compute_onion_address(pubkey: &VerifyingKey) -> String {
     let pubkey_bytes = pubkey.as_bytes();
-
-    // Compute checksum: SHA3-256(".onion checksum" || pubkey || version)
-    let mut hasher = Sha3_256::new();
-    hasher.update(CHECKSUM_PREFIX);
-    hasher.update(pubkey_bytes);
-    hasher.update([ONION_V3_VERSION]);
-    let hash = hasher.finalize();
-    let checksum = &hash[..2];
+    let checksum = onion_checksum(pubkey_bytes);
 
     // Concatenate: pubkey (32) + checksum (2) + version (1) = 35 bytes
     let mut address_bytes = [0u8; 35];
     address_bytes[..32].copy_from_slice(pubkey_bytes);
-    address_bytes[32..34].copy_from_slice(checksum);
+    address_bytes[32..34].copy_from_slice(&checksum);
     address_bytes[34] = ONION_V3_VERSION;
 
     // Base32 encode (lowercase, no padding)
     base32::encode(base32::Alphabet::Rfc4648Lower { padding: false }, &address_bytes)
 }
 
+/// Checksum bytes: SHA3-256(".onion checksum" || pubkey || version)[..2]
+fn onion_checksum(pubkey_bytes: &[u8; 32]) -> [u8; 2] {
+    let mut hasher = Sha3_256::new();
+    hasher.update(CHECKSUM_PREFIX);
+    hasher.update(pubkey_bytes);
+    hasher.update([ONION_V3_VERSION]);
+    let hash = hasher.finalize();
+    [hash[0], hash[1]]
+}
+
 /// Save the key files in Tor's expected format
+///
+/// With a passphrase, the secret key is written encrypted (see `encrypt`).
 fn save_keys(
     output_dir: &PathBuf,
     secret_key: &SigningKey,
     onion_address: &str,
+    passphrase: Option<&str>,
 ) -> std::io::Result<()> {
     std::fs::create_dir_all(output_dir)?;
 
@@ -323,7 +507,7 @@ fn save_keys(
     let mut secret_file = output_dir.clone();
     secret_file.push("hs_ed25519_secret_key");
 
-    let header = b"== ed25519v1-secret: type0 ==\x00\x00\x00";
+    let header = keydir::SECRET_KEY_HEADER;
     
     // Compute the expanded secret key the same way Ed25519 does:
     // h = SHA512(seed), then clamp h[0..32] for the scalar, h[32..64] for nonce
@@ -340,16 +524,24 @@ fn save_keys(
     expanded_bytes[31] &= 127;
     expanded_bytes[31] |= 64;
     
-    let mut secret_data = Vec::with_capacity(32 + 64);
+    let mut secret_data = zeroize::Zeroizing::new(Vec::with_capacity(32 + 64));
     secret_data.extend_from_slice(header);
     secret_data.extend_from_slice(&expanded_bytes);
-    std::fs::write(&secret_file, &secret_data)?;
+    match passphrase {
+        Some(passphrase) => {
+            let sealed = encrypt::seal(&secret_data, passphrase, encrypt::KdfParams::default())
+                .map_err(std::io::Error::other)?;
+            secret_file.set_file_name(encrypt::ENCRYPTED_SECRET_FILE);
+            std::fs::write(&secret_file, sealed)?;
+        }
+        None => std::fs::write(&secret_file, &*secret_data)?,
+    }
 
     // hs_ed25519_public_key (Tor format: header + 32-byte public key)
     let mut public_file = output_dir.clone();
     public_file.push("hs_ed25519_public_key");
 
-    let pub_header = b"== ed25519v1-public: type0 ==\x00\x00\x00";
+    let pub_header = keydir::PUBLIC_KEY_HEADER;
     let mut pub_data = Vec::with_capacity(32 + 32);
     pub_data.extend_from_slice(pub_header);
     pub_data.extend_from_slice(secret_key.verifying_key().as_bytes());
@@ -367,12 +559,152 @@ fn save_keys(
         "onion_address": format!("{}.onion", onion_address),
         "prefix": onion_address.chars().take(6).collect::<String>(),
         "generated_at": chrono_now_iso(),
+        "encrypted": passphrase.is_some(),
     });
     std::fs::write(&json_file, serde_json::to_string_pretty(&json).unwrap_or_default())?;
 
     Ok(())
 }
 
+/// Decrypt an `--encrypt`ed secret key back into Tor's format
+///
+/// Returns the path of the `hs_ed25519_secret_key` written.
+fn decrypt_keys(
+    input: &Path,
+    output_dir: Option<&Path>,
+    passphrase_file: Option<&Path>,
+    force: bool,
+) -> Result<PathBuf, String> {
+    let encrypted_file = if input.is_dir() {
+        input.join(encrypt::ENCRYPTED_SECRET_FILE)
+    } else {
+        input.to_path_buf()
+    };
+    let sealed = std::fs::read(&encrypted_file)
+        .map_err(|e| format!("Failed to read {}: {}", encrypted_file.display(), e))?;
+
+    let output_dir = match output_dir {
+        Some(dir) => dir.to_path_buf(),
+        None => encrypted_file
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default(),
+    };
+    let secret_file = output_dir.join(keydir::SECRET_KEY_FILE);
+    if secret_file.exists() && !force {
+        return Err(format!(
+            "{} already exists (use --force to overwrite)",
+            secret_file.display()
+        ));
+    }
+
+    let passphrase = encrypt::read_passphrase(passphrase_file, false)?;
+    let secret_data = encrypt::open(&sealed, &passphrase)?;
+    if !secret_data.starts_with(keydir::SECRET_KEY_HEADER) {
+        return Err("Decrypted data is not a Tor secret key".to_string());
+    }
+
+    std::fs::create_dir_all(&output_dir)
+        .map_err(|e| format!("Failed to create {}: {}", output_dir.display(), e))?;
+    write_private(&secret_file, &secret_data)
+        .map_err(|e| format!("Failed to write {}: {}", secret_file.display(), e))?;
+    Ok(secret_file)
+}
+
+/// Check a key directory and exit non-zero if anything is wrong
+fn verify_keys(dir: &Path) -> ! {
+    let keydir = keydir::KeyDir::load(dir);
+    println!("🔎 Verifying {}", dir.display());
+    print_findings(&keydir);
+    println!();
+
+    if keydir.is_ok() {
+        if let Some(onion_address) = keydir.onion_address() {
+            println!("✅ Keys are consistent: {}.onion", onion_address);
+        }
+        std::process::exit(0);
+    }
+    println!("❌ Key directory has problems");
+    std::process::exit(1);
+}
+
+/// Print everything known about a key directory, for debugging
+fn inspect_keys(dir: &Path) -> ! {
+    let keydir = keydir::KeyDir::load(dir);
+    println!("🔎 Inspecting {}", dir.display());
+    println!();
+
+    match keydir.onion_address() {
+        Some(onion_address) => println!("🧅 Onion Address: {}.onion", onion_address),
+        None => println!("🧅 Onion Address: unknown (no readable key)"),
+    }
+    if let Some(key) = keydir.public_key {
+        println!("   Public key:         {}", hex_encode(key.as_bytes()));
+        println!("   SHA-256:            {}", keydir::fingerprint(&key));
+    }
+    if let Some(key) = keydir.derived_key.filter(|k| Some(*k) != keydir.public_key) {
+        println!("   From secret key:    {}", hex_encode(key.as_bytes()));
+        println!("   SHA-256:            {}", keydir::fingerprint(&key));
+    }
+    if let Some(key) = keydir
+        .hostname_key
+        .filter(|k| keydir.public_key.map(|p| p.to_bytes()) != Some(*k))
+    {
+        println!("   From hostname:      {}", hex_encode(&key));
+    }
+
+    println!();
+    println!("📁 Files:");
+    for name in [
+        keydir::SECRET_KEY_FILE,
+        encrypt::ENCRYPTED_SECRET_FILE,
+        keydir::PUBLIC_KEY_FILE,
+        keydir::HOSTNAME_FILE,
+    ] {
+        match std::fs::metadata(dir.join(name)) {
+            Ok(meta) => {
+                #[cfg(unix)]
+                let mode = {
+                    use std::os::unix::fs::PermissionsExt;
+                    format!(" mode {:o}", meta.permissions().mode() & 0o777)
+                };
+                #[cfg(not(unix))]
+                let mode = String::new();
+                println!("   {:<26} {} bytes{}", name, meta.len(), mode);
+            }
+            Err(_) => println!("   {:<26} -", name),
+        }
+    }
+
+    println!();
+    println!("🩺 Checks:");
+    print_findings(&keydir);
+    std::process::exit(if keydir.is_ok() { 0 } else { 1 });
+}
+
+fn print_findings(keydir: &keydir::KeyDir) {
+    for finding in &keydir.findings {
+        match &finding.result {
+            Ok(detail) => println!("   ✅ {}: {}", finding.check, detail),
+            Err(problem) => println!("   ❌ {}: {}", finding.check, problem),
+        }
+    }
+}
+
+/// Write a file only its owner can read
+fn write_private(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(data)
+}
+
 /// Benchmark key generation rate
 fn benchmark_rate() -> u64 {
     let start = Instant::now();
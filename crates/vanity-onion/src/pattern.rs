@@ -0,0 +1,142 @@
+//! Prefix patterns.
+//!
+//! A prefix is matched position by position against the start of the
+//! address. Besides base32 characters (a-z, 2-7), a position can be `?`
+//! (any character) or a class such as `[e3]` (any of the characters
+//! listed), so `c[e3]rb?rus` matches both `cerberus...` and `c3rbxrus...`.
+
+use std::fmt;
+
+/// Characters that can appear in a v3 onion address
+pub const BASE32_CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    /// Characters accepted at each position
+    positions: Vec<Vec<u8>>,
+}
+
+impl Pattern {
+    /// Parse a prefix pattern (case-insensitive)
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut positions = Vec::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            let accepted = match c {
+                '?' => BASE32_CHARS.to_vec(),
+                '[' => {
+                    let mut class = Vec::new();
+                    loop {
+                        match chars.next() {
+                            Some(']') => break,
+                            Some(c) => {
+                                let c = base32_char(c)?;
+                                if !class.contains(&c) {
+                                    class.push(c);
+                                }
+                            }
+                            None => return Err(format!("Unclosed '[' in '{}'", s)),
+                        }
+                    }
+                    if class.is_empty() {
+                        return Err(format!("Empty '[]' in '{}'", s));
+                    }
+                    class
+                }
+                c => vec![base32_char(c)?],
+            };
+            positions.push(accepted);
+        }
+
+        if positions.is_empty() {
+            return Err("Prefix is empty".to_string());
+        }
+        Ok(Self { positions })
+    }
+
+    /// Number of address characters the pattern covers
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Does `address` start with something this pattern accepts?
+    pub fn matches(&self, address: &str) -> bool {
+        let address = address.as_bytes();
+        address.len() >= self.positions.len()
+            && self
+                .positions
+                .iter()
+                .zip(address)
+                .all(|(accepted, c)| accepted.contains(c))
+    }
+
+    /// Average number of keys to generate before one matches
+    pub fn expected_attempts(&self) -> f64 {
+        self.positions
+            .iter()
+            .map(|accepted| BASE32_CHARS.len() as f64 / accepted.len() as f64)
+            .product()
+    }
+
+    /// The first `len` positions
+    pub fn truncated(&self, len: usize) -> Self {
+        Self {
+            positions: self.positions[..len.min(self.positions.len())].to_vec(),
+        }
+    }
+
+    /// Does any position accept more than one character?
+    pub fn has_alternatives(&self) -> bool {
+        self.positions.iter().any(|accepted| accepted.len() > 1)
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for accepted in &self.positions {
+            match accepted.as_slice() {
+                [c] => write!(f, "{}", *c as char)?,
+                all if all.len() == BASE32_CHARS.len() => f.write_str("?")?,
+                class => write!(f, "[{}]", String::from_utf8_lossy(class))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn base32_char(c: char) -> Result<u8, String> {
+    let c = c.to_ascii_lowercase();
+    if c.is_ascii_lowercase() || ('2'..='7').contains(&c) {
+        Ok(c as u8)
+    } else {
+        Err(format!("'{}' is not a base32 character (a-z, 2-7)", c))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern_matching() {
+        let pattern = Pattern::parse("C[E3]rb?rus").unwrap();
+        assert_eq!(pattern.len(), 8);
+        assert_eq!(pattern.to_string(), "c[e3]rb?rus");
+        assert!(pattern.matches("cerberusxyz"));
+        assert!(pattern.matches("c3rbxrus"));
+        assert!(!pattern.matches("cirberus"));
+        assert!(!pattern.matches("c3rb"));
+
+        // Each fixed character is 32x, the class 16x, the wildcard 1x
+        assert_eq!(pattern.expected_attempts(), 32f64.powi(6) * 16.0);
+        assert_eq!(pattern.truncated(2).to_string(), "c[e3]");
+    }
+
+    #[test]
+    fn test_pattern_rejects_invalid() {
+        assert!(Pattern::parse("sigil0").is_err());
+        assert!(Pattern::parse("si[gl").is_err());
+        assert!(Pattern::parse("si[]").is_err());
+        assert!(Pattern::parse("").is_err());
+    }
+}
@@ -0,0 +1,150 @@
+//! Prefix suggestions (`--suggest`).
+//!
+//! Every character makes a prefix 32 times harder to find, so a full brand
+//! name is usually out of reach. Given a word and how many keys can be
+//! tried, this proposes the nearest patterns expected to be found in time:
+//! the word cut short, the word with leetspeak lookalikes accepted
+//! (`[e3]`), and the word with some of its vowels left open (`?`).
+
+use crate::pattern::Pattern;
+
+/// Letters and the base32 digits that pass for them
+const LEETSPEAK: [(char, char); 6] = [
+    ('a', '4'),
+    ('e', '3'),
+    ('g', '6'),
+    ('s', '5'),
+    ('t', '7'),
+    ('z', '2'),
+];
+
+/// Digits base32 lacks, and the letters that stand in for them
+const DIGIT_LETTERS: [(char, char); 4] = [('0', 'o'), ('1', 'i'), ('8', 'b'), ('9', 'g')];
+
+const VOWELS: &str = "aeiouy";
+
+/// A feasible alternative to the requested prefix
+#[derive(Debug)]
+pub struct Suggestion {
+    pub kind: &'static str,
+    pub pattern: Pattern,
+}
+
+/// Lowercase `word` and replace the digits base32 lacks with lookalikes
+pub fn normalize(word: &str) -> Result<String, String> {
+    let word: String = word
+        .chars()
+        .map(|c| {
+            let c = c.to_ascii_lowercase();
+            DIGIT_LETTERS
+                .iter()
+                .find(|(digit, _)| *digit == c)
+                .map_or(c, |(_, letter)| *letter)
+        })
+        .collect();
+    // Validates what's left
+    Pattern::parse(&word)?;
+    Ok(word)
+}
+
+/// Alternatives to `word` (normalized) expected within `max_attempts`
+pub fn suggest(word: &str, max_attempts: f64) -> Vec<Suggestion> {
+    let Ok(exact) = Pattern::parse(word) else {
+        return Vec::new();
+    };
+    let mut suggestions = Vec::new();
+
+    // Cut short
+    let shorter = longest_feasible(&exact, max_attempts);
+    let reach = shorter.as_ref().map_or(0, Pattern::len);
+    if let Some(pattern) = shorter.filter(|p| p.len() < exact.len()) {
+        suggestions.push(Suggestion {
+            kind: "shorter",
+            pattern,
+        });
+    }
+
+    // Lookalikes accepted: each one halves the work
+    let leet: String = word
+        .chars()
+        .map(
+            |c| match LEETSPEAK.iter().find(|(l, d)| *l == c || *d == c) {
+                Some((letter, digit)) => format!("[{}{}]", letter, digit),
+                None => c.to_string(),
+            },
+        )
+        .collect();
+    if let Some(pattern) = Pattern::parse(&leet)
+        .ok()
+        .and_then(|p| longest_feasible(&p, max_attempts))
+        .filter(|p| p.has_alternatives() && p.len() >= reach)
+    {
+        suggestions.push(Suggestion {
+            kind: "leetspeak",
+            pattern,
+        });
+    }
+
+    // Vowels left open, last first, keeping the first character
+    if exact.expected_attempts() > max_attempts {
+        let mut open: Vec<char> = word.chars().collect();
+        for i in (1..open.len()).rev() {
+            if !VOWELS.contains(open[i]) {
+                continue;
+            }
+            open[i] = '?';
+            let Ok(pattern) = Pattern::parse(&open.iter().collect::<String>()) else {
+                break;
+            };
+            if pattern.expected_attempts() <= max_attempts {
+                suggestions.push(Suggestion {
+                    kind: "wildcard",
+                    pattern,
+                });
+                break;
+            }
+        }
+    }
+
+    suggestions
+}
+
+/// The longest leading part of `pattern` expected within `max_attempts`
+fn longest_feasible(pattern: &Pattern, max_attempts: f64) -> Option<Pattern> {
+    (1..=pattern.len())
+        .rev()
+        .map(|len| pattern.truncated(len))
+        .find(|p| p.expected_attempts() <= max_attempts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suggestions() {
+        // Six fixed characters fit, eight don't
+        let suggestions = suggest("cerberus", 32f64.powi(6));
+        let found: Vec<_> = suggestions
+            .iter()
+            .map(|s| (s.kind, s.pattern.to_string()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                ("shorter", "cerber".to_string()),
+                ("leetspeak", "c[e3]rb[e3]r".to_string()),
+                ("wildcard", "cerb?r?s".to_string()),
+            ]
+        );
+
+        // Nothing to suggest when the word itself fits and has no lookalikes
+        assert!(suggest("crumb", 32f64.powi(6)).is_empty());
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("B00kSt0re").unwrap(), "bookstore");
+        assert!(normalize("shop!").is_err());
+    }
+}
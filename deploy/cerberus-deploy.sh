@@ -497,10 +497,6 @@ redis_url = "redis://127.0.0.1:6379"
 node_id = "$(hostname)"
 initial_threat_level = 1
 
-[backend]
-target = "$BACKEND"
-vanity = "$(cat $TOR_HS_DIR/hostname | cut -c1-5)"
-
 [captcha]
 challenge_ttl_secs = 300
 passport_ttl_secs = 300
@@ -519,10 +515,12 @@ After=network.target redis.service
 Wants=redis.service
 
 [Service]
-Type=simple
+Type=notify
 ExecStart=$FORTIFY_BIN --config $CERBERUS_ROOT/fortify/config.toml
 Restart=always
 RestartSec=5
+TimeoutStartSec=90
+WatchdogSec=30
 User=www-data
 Group=www-data
 
@@ -693,10 +693,6 @@ redis_url = "redis://127.0.0.1:6379"
 node_id = "$(hostname)"
 initial_threat_level = 1
 
-[backend]
-target = "$BACKEND_ONION"
-vanity = "$VANITY_PREFIX"
-
 [captcha]
 challenge_ttl_secs = 300
 passport_ttl_secs = 300
@@ -705,11 +701,6 @@ passport_ttl_secs = 300
 max_failed_attempts = 5
 soft_lock_duration_secs = 300
 ban_duration_secs = 3600
-
-[ammo_box]
-pool_size = 1000
-disk_cache_path = "/var/lib/cerberus/ammo"
-refill_threshold = 0.5
 EOF
 
     # Create systemd service
@@ -720,10 +711,12 @@ After=network.target redis.service
 Wants=redis.service
 
 [Service]
-Type=simple
+Type=notify
 ExecStart=$FORTIFY_BIN --config $CERBERUS_ROOT/fortify/config.toml
 Restart=always
 RestartSec=5
+TimeoutStartSec=90
+WatchdogSec=30
 User=www-data
 Group=www-data
 Environment="RUST_LOG=info"